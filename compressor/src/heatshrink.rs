@@ -0,0 +1,136 @@
+//! Heatshrink coding of 16-bit words, an LZSS variant whose bit-packed
+//! stream is the densest of the codecs on small data, and the slowest to
+//! decode.
+//!
+//! A stream starts with the decompressed length in words, like a run-length
+//! stream, followed by the bits of the bytes of the input, little-endian,
+//! most significant bit first in the bytes of the following words. A 1
+//! bit is followed by a literal byte, and a 0 bit by a back-reference: its
+//! distance less one in `WINDOW_BITS` bits and its length less one in
+//! `LOOKAHEAD_BITS` bits. Decoders stop once they have written the
+//! decompressed length, so the zero bits padding the stream are ignored.
+
+use crate::lz::{byte, set_byte, ByteWriter, Matcher};
+
+/// Back-references reach up to 1024 bytes back.
+pub const WINDOW_BITS: u32 = 10;
+/// Back-references are up to 32 bytes long.
+pub const LOOKAHEAD_BITS: u32 = 5;
+
+const WINDOW: usize = 1 << WINDOW_BITS;
+const MAX_MATCH: usize = 1 << LOOKAHEAD_BITS;
+// A back-reference takes 16 bits and a literal 9, so two bytes are worth
+// one.
+const MIN_MATCH: usize = 2;
+
+/// The longest stream `compress` produces for `len` input words: all
+/// literals and the length word.
+pub const fn max_compressed_len(len: usize) -> usize {
+    1 + (2 * len * 9 / 8 + 2) / 2
+}
+
+struct BitWriter<'a> {
+    bytes: ByteWriter<'a>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter<'_> {
+    fn push(&mut self, value: usize, count: u32) {
+        for i in (0..count).rev() {
+            self.bits = (self.bits << 1) | ((value >> i) & 1) as u32;
+            self.count += 1;
+            if self.count == 8 {
+                self.bytes.push(self.bits as u8);
+                self.bits = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> usize {
+        if self.count > 0 {
+            self.push(0, 8 - self.count);
+        }
+        self.bytes.finish()
+    }
+}
+
+/// Compresses `input` into `output` and returns the length of the stream.
+/// `output` needs room for `max_compressed_len(input.len())` words, and
+/// `input` can't be longer than 65535 words.
+pub fn compress(input: &[u16], output: &mut [u16]) -> usize {
+    assert!(input.len() <= u16::MAX as usize, "input too long");
+    output[0] = input.len() as u16;
+    let len = 2 * input.len();
+    let mut writer = BitWriter {
+        bytes: ByteWriter::new(&mut output[1..]),
+        bits: 0,
+        count: 0,
+    };
+    let mut matcher = Matcher::<WINDOW>::new();
+    let mut pos = 0;
+    while pos < len {
+        let (distance, length) = matcher.find(input, len, pos, MAX_MATCH.min(len - pos));
+        if length >= MIN_MATCH {
+            writer.push(0, 1);
+            writer.push(distance - 1, WINDOW_BITS);
+            writer.push(length - 1, LOOKAHEAD_BITS);
+            for p in pos..pos + length {
+                matcher.insert(input, len, p);
+            }
+            pos += length;
+        } else {
+            writer.push(1, 1);
+            writer.push(byte(input, pos) as usize, 8);
+            matcher.insert(input, len, pos);
+            pos += 1;
+        }
+    }
+    1 + writer.finish()
+}
+
+struct BitReader<'a> {
+    input: &'a [u16],
+    // In bits.
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, count: u32) -> usize {
+        let mut value = 0;
+        for _ in 0..count {
+            let b = byte(self.input, self.pos / 8);
+            value = (value << 1) | ((b >> (7 - self.pos % 8)) & 1) as usize;
+            self.pos += 1;
+        }
+        value
+    }
+}
+
+/// Decompresses `input` into `output` on the CPU. Panics if `output` is too
+/// short.
+pub fn decompress(input: &[u16], output: &mut [u16]) {
+    let len = 2 * crate::decompressed_size(input) as usize;
+    if len == 0 {
+        return;
+    }
+    let mut reader = BitReader {
+        input: &input[1..],
+        pos: 0,
+    };
+    let mut out = 0;
+    while out < len {
+        if reader.read(1) == 1 {
+            set_byte(output, out, reader.read(8) as u8);
+            out += 1;
+        } else {
+            let distance = reader.read(WINDOW_BITS) + 1;
+            let length = reader.read(LOOKAHEAD_BITS) + 1;
+            for _ in 0..length {
+                set_byte(output, out, byte(output, out - distance));
+                out += 1;
+            }
+        }
+    }
+}
@@ -1,12 +1,90 @@
-// File format:
-// decompressed size: u16
-// Repeated:
-//   data length: u8
-//   run length (repetitions of last value of data): u8
-//   data: [u16]
+//! Run-length coding of 16-bit words, used for tile and sprite data, and
+//! the `lz4` and `heatshrink` codecs, which compress better and take longer
+//! to decode. `Codec` picks one of them.
+//!
+//! The proc macros compress on the host and the device decompresses, either
+//! with `decompress` or with the decompressors in `picosystem::compression`.
+//!
+//! # Stream format
+//!
+//! A stream is a sequence of `u16` words:
+//!
+//! ```text
+//! decompressed length   1 word
+//! blocks, until the end of the stream:
+//!   control             1 word: data length in the low byte,
+//!                               run length in the high byte
+//!   data                data length words, copied to the output
+//! ```
+//!
+//! After its data a block repeats the last data word run length more
+//! times. A block without data skips run length output words, leaving them
+//! unchanged, which lets transparent areas be drawn over existing pixels.
+//! `compress` never produces skips.
+//!
+//! The decompressed length is informational; decoders stop at the end of
+//! the stream.
 
 #![no_std]
 
+pub mod heatshrink;
+mod lz;
+pub mod lz4;
+
+/// The coding of a compressed stream. All of them start with the
+/// decompressed length in words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// Run-length coding, the fastest to decode and the only one the DMA
+    /// decompressor handles.
+    Rle,
+    /// LZ4, which also finds repeated patterns.
+    Lz4,
+    /// Heatshrink, bit-packed and the densest on short data.
+    Heatshrink,
+}
+
+impl Codec {
+    /// From the fastest to decode to the slowest.
+    pub const ALL: [Codec; 3] = [Codec::Rle, Codec::Lz4, Codec::Heatshrink];
+
+    /// The longest stream `compress` produces for `len` input words.
+    pub const fn max_compressed_len(self, len: usize) -> usize {
+        match self {
+            Codec::Rle => max_compressed_len(len),
+            Codec::Lz4 => lz4::max_compressed_len(len),
+            Codec::Heatshrink => heatshrink::max_compressed_len(len),
+        }
+    }
+
+    /// Compresses `input` into `output` and returns the length of the
+    /// stream. The LZ coders need about 32K of stack, so they are meant for
+    /// the host.
+    pub fn compress(self, input: &[u16], output: &mut [u16]) -> usize {
+        match self {
+            Codec::Rle => compress(input, output),
+            Codec::Lz4 => lz4::compress(input, output),
+            Codec::Heatshrink => heatshrink::compress(input, output),
+        }
+    }
+
+    /// Decompresses `input` into `output` on the CPU.
+    pub fn decompress(self, input: &[u16], output: &mut [u16]) {
+        match self {
+            Codec::Rle => decompress(input, output),
+            Codec::Lz4 => lz4::decompress(input, output),
+            Codec::Heatshrink => heatshrink::decompress(input, output),
+        }
+    }
+}
+
+/// The longest stream `compress` produces for `len` input words: the
+/// length word and a control word for each block of one word.
+pub const fn max_compressed_len(len: usize) -> usize {
+    2 * len + 1
+}
+
+/// The decompressed length recorded in the stream.
 pub fn decompressed_size(input: &[u16]) -> u16 {
     if !input.is_empty() {
         input[0]
@@ -15,10 +93,13 @@ pub fn decompressed_size(input: &[u16]) -> u16 {
     }
 }
 
-fn ctrl_word(data_length: u8, run_length: u8) -> u16 {
+/// The control word of a block.
+pub fn ctrl_word(data_length: u8, run_length: u8) -> u16 {
     ((run_length as u16) << 8) | data_length as u16
 }
 
+/// Decompresses `input` into `output` on the CPU. Panics if `output` is too
+/// short.
 pub fn decompress(input: &[u16], output: &mut [u16]) {
     let mut input_index: usize = 0;
     let mut output_index: usize = 0;
@@ -48,7 +129,114 @@ pub fn decompress(input: &[u16], output: &mut [u16]) {
     }
 }
 
+/// Decodes a run-length stream a piece at a time, for drawing images a row
+/// at a time straight into the framebuffer rather than decompressing all
+/// of them into a buffer first.
+///
+/// `read` and `skip_words` move through the output, and iterating gives each
+/// word, or `None` for words in skips.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    input: &'a [u16],
+    // Index of the next data word, or the next control word.
+    index: usize,
+    // Data words left in the current block.
+    data: usize,
+    // Repeats of the last data word, or skipped words, left after them.
+    run: usize,
+    skip: bool,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(input: &'a [u16]) -> Self {
+        Reader {
+            input,
+            index: input.len().min(1),
+            data: 0,
+            run: 0,
+            skip: false,
+        }
+    }
+
+    // Starts the next block if the current one is done. Returns false at
+    // the end of the stream.
+    fn refill(&mut self) -> bool {
+        while self.data == 0 && self.run == 0 {
+            if self.index >= self.input.len() {
+                return false;
+            }
+            let ctrl = self.input[self.index];
+            self.index += 1;
+            self.data = (ctrl & 0xff) as usize;
+            self.run = (ctrl >> 8) as usize;
+            self.skip = self.data == 0;
+        }
+        true
+    }
+
+    // Moves `count` words on, writing them to `output` if there is one.
+    fn advance(&mut self, count: usize, mut output: Option<&mut [u16]>) -> usize {
+        let mut done = 0;
+        while done < count && self.refill() {
+            if self.data > 0 {
+                let n = self.data.min(count - done);
+                if let Some(output) = output.as_deref_mut() {
+                    output[done..done + n].copy_from_slice(&self.input[self.index..self.index + n]);
+                }
+                self.index += n;
+                self.data -= n;
+                done += n;
+            } else {
+                let n = self.run.min(count - done);
+                if let Some(output) = output.as_deref_mut() {
+                    if !self.skip {
+                        output[done..done + n].fill(self.input[self.index - 1]);
+                    }
+                }
+                self.run -= n;
+                done += n;
+            }
+        }
+        done
+    }
+
+    /// Decodes the next `output.len()` words into `output`, leaving the
+    /// words of skips unchanged. Returns how many there were, fewer at the
+    /// end of the stream.
+    pub fn read(&mut self, output: &mut [u16]) -> usize {
+        self.advance(output.len(), Some(output))
+    }
+
+    /// Moves past the next `count` words without decoding them. Returns
+    /// how many there were, fewer at the end of the stream.
+    pub fn skip_words(&mut self, count: usize) -> usize {
+        self.advance(count, None)
+    }
+}
+
+impl Iterator for Reader<'_> {
+    type Item = Option<u16>;
+
+    fn next(&mut self) -> Option<Option<u16>> {
+        if !self.refill() {
+            return None;
+        }
+        if self.data > 0 {
+            self.data -= 1;
+            self.index += 1;
+            Some(Some(self.input[self.index - 1]))
+        } else {
+            self.run -= 1;
+            Some((!self.skip).then(|| self.input[self.index - 1]))
+        }
+    }
+}
+
+/// Compresses `input` into `output` and returns the length of the stream.
+/// `output` needs room for `max_compressed_len(input.len())` words, and
+/// `input` can't be longer than 65535 words.
 pub fn compress(input: &[u16], output: &mut [u16]) -> usize {
+    assert!(input.len() <= u16::MAX as usize, "input too long");
     let mut input_index: usize = 1;
     let mut output_index: usize = 0;
     let input_length = input.len();
@@ -75,7 +263,8 @@ pub fn compress(input: &[u16], output: &mut [u16]) -> usize {
         if value == last_value && run_length < 255 {
             run_length += 1;
         } else {
-            if run_length >= 3 || data_length == 255 {
+            // Short runs join the data unless that makes the block too long.
+            if run_length >= 3 || data_length as usize + run_length as usize >= 255 {
                 write(ctrl_word(data_length, run_length));
                 for i in 0..(data_length as usize) {
                     write(input[data_start_index + i]);
@@ -102,6 +291,20 @@ pub fn compress(input: &[u16], output: &mut [u16]) -> usize {
     output_index
 }
 
+/// The CRC-32 of `data`, as used by zlib and Ethernet, which the DMA
+/// sniffer in `picosystem::dma` computes in hardware. The proc macros use
+/// it to record checksums of compressed data that the device checks.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
 #[cfg(test)]
 #[macro_use]
 extern crate std;
@@ -205,6 +408,188 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_short_run_at_data_limit() {
+        // Joining the run of two 7s to the 254 words of data before it would
+        // make a block of 256, past what the control word's u8 can hold, so
+        // the block is written before the 8 starts the next one.
+        let mut input: std::vec::Vec<u16> = (0..253).collect();
+        input.extend([7, 7, 8]);
+        let mut output = [0; 1000];
+        let output_length = compress(&input, &mut output);
+        let mut expected = vec![256, ctrl_word(254, 1)];
+        expected.extend(0..253);
+        expected.extend([7, ctrl_word(1, 0), 8]);
+        assert_eq!(&output[0..output_length], &expected[..]);
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(&[]), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    fn round_trip(input: &[u16]) {
+        let mut compressed = vec![0; max_compressed_len(input.len())];
+        let compressed_length = compress(input, &mut compressed);
+        assert_eq!(decompressed_size(&compressed) as usize, input.len());
+        let mut output = vec![0; input.len()];
+        decompress(&compressed[0..compressed_length], &mut output);
+        assert_eq!(input, &output[..]);
+    }
+
+    #[test]
+    fn test_round_trip_edges() {
+        round_trip(&[]);
+        round_trip(&[7]);
+        round_trip(&[7, 7]);
+        round_trip(&[1, 2, 2, 2, 3]);
+        round_trip(&[1, 2, 2, 2, 2, 3]);
+        round_trip(&[5; 255]);
+        round_trip(&[5; 256]);
+        round_trip(&[5; 511]);
+        round_trip(&[5; 1024]);
+    }
+
+    #[test]
+    fn test_round_trip_block_boundaries() {
+        // Data blocks fill up at 255 words, with runs before and after.
+        for len in 250..260 {
+            let mut input = vec![9; 4];
+            input.extend((0..len).map(|i| i as u16));
+            input.extend([3; 300]);
+            round_trip(&input);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_short_run_ending_data() {
+        // A short run that would take a data block past 255 words.
+        for len in 250..256 {
+            let mut input: std::vec::Vec<u16> = (0..len).map(|i| i as u16).collect();
+            input.extend([7, 7, 8]);
+            round_trip(&input);
+        }
+    }
+
+    #[test]
+    fn test_max_compressed_len() {
+        let input: std::vec::Vec<u16> = (0..1024).map(|i| (i % 2) as u16).collect();
+        let mut compressed = vec![0; max_compressed_len(input.len())];
+        assert!(compress(&input, &mut compressed) <= max_compressed_len(input.len()));
+    }
+
+    #[test]
+    fn test_decompress_skip_keeps_output() {
+        let input = [
+            5,
+            ctrl_word(1, 1),
+            0xaa,
+            ctrl_word(0, 2),
+            ctrl_word(1, 0),
+            0xbb,
+        ];
+        let mut output = [0x55; 5];
+        decompress(&input, &mut output);
+        assert_eq!(output, [0xaa, 0xaa, 0x55, 0x55, 0xbb]);
+    }
+
+    #[test]
+    fn test_reader_matches_decompress() {
+        let input: std::vec::Vec<u16> = (0..1000).map(|i| (i / 7 % 5) as u16).collect();
+        let mut compressed = vec![0; max_compressed_len(input.len())];
+        let compressed_length = compress(&input, &mut compressed);
+        let compressed = &compressed[0..compressed_length];
+        for chunk in [1, 3, 40, 240, 1000] {
+            let mut reader = Reader::new(compressed);
+            let mut output = vec![0; input.len()];
+            for piece in output.chunks_mut(chunk) {
+                assert_eq!(reader.read(piece), piece.len());
+            }
+            assert_eq!(reader.read(&mut [0; 4]), 0);
+            assert_eq!(input, output);
+        }
+        let words: std::vec::Vec<u16> = Reader::new(compressed).map(Option::unwrap).collect();
+        assert_eq!(input, words);
+    }
+
+    #[test]
+    fn test_reader_skips() {
+        let input = [
+            6,
+            ctrl_word(2, 1),
+            0xaa,
+            0xbb,
+            ctrl_word(0, 2),
+            ctrl_word(1, 0),
+            0xcc,
+        ];
+        let mut reader = Reader::new(&input);
+        assert_eq!(reader.skip_words(1), 1);
+        let mut output = [0x55; 4];
+        assert_eq!(reader.read(&mut output), 4);
+        assert_eq!(output, [0xbb, 0xbb, 0x55, 0x55]);
+        assert_eq!(reader.skip_words(5), 1);
+        let words: std::vec::Vec<Option<u16>> = Reader::new(&input).collect();
+        assert_eq!(
+            words,
+            [Some(0xaa), Some(0xbb), Some(0xbb), None, None, Some(0xcc)]
+        );
+        assert_eq!(Reader::new(&[]).next(), None);
+    }
+
+    fn codec_round_trip(codec: Codec, input: &[u16]) -> usize {
+        let mut compressed = vec![0; codec.max_compressed_len(input.len())];
+        let compressed_length = codec.compress(input, &mut compressed);
+        assert!(compressed_length <= compressed.len());
+        assert_eq!(decompressed_size(&compressed) as usize, input.len());
+        let mut output = vec![0; input.len()];
+        codec.decompress(&compressed[0..compressed_length], &mut output);
+        assert_eq!(input, &output[..], "{:?}", codec);
+        compressed_length
+    }
+
+    #[test]
+    fn test_codecs_round_trip_edges() {
+        for codec in Codec::ALL {
+            codec_round_trip(codec, &[]);
+            codec_round_trip(codec, &[7]);
+            codec_round_trip(codec, &[7, 7]);
+            codec_round_trip(codec, &[1, 2, 3, 1, 2, 3, 1, 2]);
+            codec_round_trip(codec, &[5; 1024]);
+            // Long literals and matches, and matches reaching past the window.
+            let input: std::vec::Vec<u16> = (0..3000).map(|i| (i * 7919 % 5003) as u16).collect();
+            codec_round_trip(codec, &input);
+            let input: std::vec::Vec<u16> = (0..4000).map(|i| (i % 1500) as u16).collect();
+            codec_round_trip(codec, &input);
+        }
+    }
+
+    #[test]
+    fn test_lz_codecs_find_patterns() {
+        // Alternating words have no runs but repeat every two words.
+        let input: std::vec::Vec<u16> = (0..256).map(|i| (i % 2) as u16 * 0x1234).collect();
+        let rle = codec_round_trip(Codec::Rle, &input);
+        assert!(codec_round_trip(Codec::Lz4, &input) < rle / 10);
+        assert!(codec_round_trip(Codec::Heatshrink, &input) < rle / 10);
+    }
+
+    #[test]
+    fn test_codecs_random() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let input: std::vec::Vec<u16> = (0..2048)
+                .map(|_| match rng.gen::<u32>() % 3 {
+                    0 => rng.gen(),
+                    _ => 100 + rng.gen::<u16>() % 4,
+                })
+                .collect();
+            for codec in Codec::ALL {
+                codec_round_trip(codec, &input);
+            }
+        }
+    }
+
     #[test]
     fn test_random() {
         let mut total_compressed_size = 0;
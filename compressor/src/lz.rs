@@ -0,0 +1,116 @@
+// Pieces shared by the LZ4 and heatshrink coders, which work on the bytes of
+// the words, little-endian like the device.
+
+/// Byte `i` of `words`.
+pub(crate) fn byte(words: &[u16], i: usize) -> u8 {
+    (words[i / 2] >> (8 * (i % 2))) as u8
+}
+
+pub(crate) fn set_byte(words: &mut [u16], i: usize, value: u8) {
+    let shift = 8 * (i % 2);
+    let word = &mut words[i / 2];
+    *word = (*word & !(0xff << shift)) | ((value as u16) << shift);
+}
+
+/// Appends bytes to words.
+pub(crate) struct ByteWriter<'a> {
+    words: &'a mut [u16],
+    len: usize,
+}
+
+impl<'a> ByteWriter<'a> {
+    pub(crate) fn new(words: &'a mut [u16]) -> Self {
+        ByteWriter { words, len: 0 }
+    }
+
+    pub(crate) fn push(&mut self, value: u8) {
+        set_byte(self.words, self.len, value);
+        self.len += 1;
+    }
+
+    /// Pads the last word with a zero byte and returns the words written.
+    pub(crate) fn finish(mut self) -> usize {
+        if !self.len.is_multiple_of(2) {
+            self.push(0);
+        }
+        self.len / 2
+    }
+}
+
+const HASH_BITS: u32 = 12;
+// Candidates tried for each position.
+const MAX_CHAIN: usize = 64;
+
+fn hash(input: &[u16], pos: usize) -> usize {
+    let pair = byte(input, pos) as u32 | (byte(input, pos + 1) as u32) << 8;
+    (pair.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Finds earlier repeats of the bytes of an input, up to `WINDOW` bytes
+/// back, by chaining the positions starting with the same two bytes. Its
+/// tables take 4 * (4096 + `WINDOW`) bytes, which is fine on the host.
+pub(crate) struct Matcher<const WINDOW: usize> {
+    // One more than the last position of each hash, 0 for none.
+    head: [u32; 1 << HASH_BITS],
+    // One more than the position before each one with the same hash.
+    prev: [u32; WINDOW],
+}
+
+impl<const WINDOW: usize> Matcher<WINDOW> {
+    pub(crate) fn new() -> Self {
+        Matcher {
+            head: [0; 1 << HASH_BITS],
+            prev: [0; WINDOW],
+        }
+    }
+
+    /// Records position `pos` of `input`, which is `len` bytes long.
+    /// Positions are recorded in order.
+    pub(crate) fn insert(&mut self, input: &[u16], len: usize, pos: usize) {
+        if pos + 2 > len {
+            return;
+        }
+        let hash = hash(input, pos);
+        self.prev[pos % WINDOW] = self.head[hash];
+        self.head[hash] = pos as u32 + 1;
+    }
+
+    /// The distance and length of the longest repeat of the bytes at `pos`
+    /// before it, at most `max_len` long, which can't go past the end.
+    /// The length is 0 without one.
+    pub(crate) fn find(
+        &self,
+        input: &[u16],
+        len: usize,
+        pos: usize,
+        max_len: usize,
+    ) -> (usize, usize) {
+        let mut best = (0, 0);
+        if pos + 2 > len {
+            return best;
+        }
+        let mut candidate = self.head[hash(input, pos)] as usize;
+        for _ in 0..MAX_CHAIN {
+            if candidate == 0 {
+                break;
+            }
+            let start = candidate - 1;
+            // Further back the slot was reused by a later position.
+            if start >= pos || pos - start > WINDOW {
+                break;
+            }
+            let mut length = 0;
+            while length < max_len && byte(input, start + length) == byte(input, pos + length) {
+                length += 1;
+            }
+            if length > best.1 {
+                best = (pos - start, length);
+                if length == max_len {
+                    break;
+                }
+            }
+            candidate = self.prev[start % WINDOW] as usize;
+        }
+        best
+    }
+}
@@ -0,0 +1,137 @@
+//! LZ4 coding of 16-bit words, which finds repeats of any bytes rather than
+//! only runs of one word, for data with repeating patterns.
+//!
+//! A stream starts with the decompressed length in words, like a run-length
+//! stream, followed by an LZ4 block of the bytes of the input,
+//! little-endian, in the bytes of the following words. Decoders stop once
+//! they have written the decompressed length, so the block may end with a
+//! match, and a zero byte pads it to whole words.
+
+use crate::lz::{byte, set_byte, ByteWriter, Matcher};
+
+const MIN_MATCH: usize = 4;
+// Matches are searched this far back, further than the largest tiles.
+const WINDOW: usize = 4096;
+
+/// The longest stream `compress` produces for `len` input words: a single
+/// sequence of literals and the length word.
+pub const fn max_compressed_len(len: usize) -> usize {
+    let bytes = 2 * len;
+    1 + (bytes + bytes / 255 + 3) / 2
+}
+
+// The rest of a length whose nibble in the token is 15.
+fn write_length(output: &mut ByteWriter, mut length: usize) {
+    while length >= 255 {
+        output.push(255);
+        length -= 255;
+    }
+    output.push(length as u8);
+}
+
+fn write_sequence(
+    output: &mut ByteWriter,
+    input: &[u16],
+    literals: core::ops::Range<usize>,
+    distance: usize,
+    match_length: usize,
+) {
+    let literal_length = literals.len();
+    // A sequence without a match only ends the block.
+    let match_code = match_length.saturating_sub(MIN_MATCH);
+    output.push(((literal_length.min(15) as u8) << 4) | match_code.min(15) as u8);
+    if literal_length >= 15 {
+        write_length(output, literal_length - 15);
+    }
+    for i in literals {
+        output.push(byte(input, i));
+    }
+    if match_length > 0 {
+        output.push(distance as u8);
+        output.push((distance >> 8) as u8);
+        if match_code >= 15 {
+            write_length(output, match_code - 15);
+        }
+    }
+}
+
+/// Compresses `input` into `output` and returns the length of the stream.
+/// `output` needs room for `max_compressed_len(input.len())` words, and
+/// `input` can't be longer than 65535 words.
+pub fn compress(input: &[u16], output: &mut [u16]) -> usize {
+    assert!(input.len() <= u16::MAX as usize, "input too long");
+    output[0] = input.len() as u16;
+    let len = 2 * input.len();
+    let mut writer = ByteWriter::new(&mut output[1..]);
+    let mut matcher = Matcher::<WINDOW>::new();
+    let mut anchor = 0;
+    let mut pos = 0;
+    while pos < len {
+        let (distance, length) = matcher.find(input, len, pos, len - pos);
+        if length >= MIN_MATCH {
+            write_sequence(&mut writer, input, anchor..pos, distance, length);
+            for p in pos..pos + length {
+                matcher.insert(input, len, p);
+            }
+            pos += length;
+            anchor = pos;
+        } else {
+            matcher.insert(input, len, pos);
+            pos += 1;
+        }
+    }
+    if anchor < len {
+        write_sequence(&mut writer, input, anchor..len, 0, 0);
+    }
+    1 + writer.finish()
+}
+
+fn read_length(input: &[u16], index: &mut usize) -> usize {
+    let mut length = 0;
+    loop {
+        let b = byte(input, *index);
+        *index += 1;
+        length += b as usize;
+        if b != 255 {
+            return length;
+        }
+    }
+}
+
+/// Decompresses `input` into `output` on the CPU. Panics if `output` is too
+/// short.
+pub fn decompress(input: &[u16], output: &mut [u16]) {
+    let len = 2 * crate::decompressed_size(input) as usize;
+    if len == 0 {
+        return;
+    }
+    let input = &input[1..];
+    let mut index = 0;
+    let mut out = 0;
+    while out < len {
+        let token = byte(input, index);
+        index += 1;
+        let mut literal_length = (token >> 4) as usize;
+        if literal_length == 15 {
+            literal_length += read_length(input, &mut index);
+        }
+        for _ in 0..literal_length {
+            set_byte(output, out, byte(input, index));
+            index += 1;
+            out += 1;
+        }
+        if out >= len {
+            break;
+        }
+        let distance = byte(input, index) as usize | (byte(input, index + 1) as usize) << 8;
+        index += 2;
+        let mut match_length = (token & 15) as usize;
+        if match_length == 15 {
+            match_length += read_length(input, &mut index);
+        }
+        for _ in 0..match_length + MIN_MATCH {
+            set_byte(output, out, byte(output, out - distance));
+            out += 1;
+        }
+    }
+}
@@ -0,0 +1,9 @@
+use std::env;
+
+fn main() {
+    // defmt keeps its format strings in a section of their own, which its
+    // linker script lays out.
+    if env::var_os("CARGO_FEATURE_DEFMT").is_some() {
+        println!("cargo:rustc-link-arg=-Tdefmt.x");
+    }
+}
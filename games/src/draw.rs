@@ -1,6 +1,7 @@
 use display::{HEIGHT, WIDTH};
 use log::info;
-use picosystem::{display, hardware, time};
+use picosystem::time::{self, FrameClock};
+use picosystem::{display, hardware};
 
 use embedded_graphics::pixelcolor::raw::RawU16;
 use embedded_graphics::pixelcolor::Rgb565;
@@ -27,8 +28,7 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
     let mut prev_cursor: Option<Styled<_, _>> = None;
 
     let mut frame = 0;
-    let mut prev_time_us = time::time_us();
-    let mut prev_frame = 0;
+    let mut clock = FrameClock::new();
 
     loop {
         if hw.input.dpad_left.is_held() && cursorx > 0 {
@@ -100,12 +100,13 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
                 .unwrap();
         });
 
-        let now = time::time_us();
-        if now - prev_time_us > 1_000_000 {
-            let frame_time = (now - prev_time_us) / (frame - prev_frame) as u32;
-            info!("Frame time: {} us", frame_time);
-            prev_frame = frame;
-            prev_time_us = now;
+        clock.tick();
+        if clock.frame().is_multiple_of(64) {
+            let stats = clock.stats();
+            info!(
+                "Frame time: {} us, jitter {} us",
+                stats.mean_us, stats.jitter_us
+            );
         }
         frame += 1;
     }
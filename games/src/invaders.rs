@@ -2,19 +2,26 @@ use embedded_graphics::image::Image;
 use embedded_graphics::mono_font::{ascii::FONT_10X20, MonoTextStyle};
 use embedded_graphics::pixelcolor::Rgb565;
 use embedded_graphics::prelude::*;
-use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::primitives::Rectangle;
 use embedded_graphics::text::{Alignment, Text};
 use heapless::Vec;
-use micromath::vector::I16x2;
-use picosystem::display::{Display, HEIGHT, WIDTH};
+use picosystem::display::{HEIGHT, WIDTH};
 use picosystem::fps_monitor::FpsMonitor;
 use picosystem::hardware;
+use picosystem::meminfo;
+use picosystem::particles::{Emitter, ParticleSystem};
+use picosystem::splash::Splash;
 use picosystem::time;
-use picosystem_macros::sprite;
-
-sprite!(sprite_ship, "games/assets/playerShip2_red.png", 56);
-sprite!(sprite_laser, "games/assets/laserGreen04.png", 6);
-sprite!(sprite_enemy, "games/assets/enemyGreen1.png", 31);
+use picosystem_macros::assets;
+
+assets! {
+    struct Assets;
+    flash_budget = 32768;
+    sprite!(ship, "games/assets/playerShip2_red.png", 56);
+    sprite!(laser, "games/assets/laserGreen04.png", 6);
+    sprite!(enemy, "games/assets/enemyGreen1.png", 31);
+    image!(title, "games/assets/playerShip2_red.png", 120);
+}
 
 #[derive(Debug, Clone)]
 struct Entity {
@@ -44,9 +51,16 @@ impl Entity {
 pub fn main(hw: &mut hardware::Hardware) -> ! {
     let background_color = Rgb565::CSS_DARK_SLATE_BLUE;
 
-    let player_img = Image::new(sprite_ship(), Point::zero());
-    let laser_img = Image::new(sprite_laser(), Point::zero());
-    let enemy_img = Image::new(sprite_enemy(), Point::zero());
+    let assets = Assets;
+    Splash {
+        background: background_color,
+        ..Splash::default()
+    }
+    .show(hw, assets.title());
+
+    let player_img = Image::new(assets.ship(), Point::zero());
+    let laser_img = Image::new(assets.laser(), Point::zero());
+    let enemy_img = Image::new(assets.enemy(), Point::zero());
 
     let mut rng = oorandom::Rand32::new(time::time_us() as u64);
     let speed = 2;
@@ -64,7 +78,7 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
     let screen_bounding_box =
         Rectangle::new(Point::new(0, 0), Size::new(WIDTH as u32, HEIGHT as u32));
     let mut fps_monitor = FpsMonitor::new();
-    let mut particles = Particles::new();
+    let mut particles: ParticleSystem<100> = ParticleSystem::new();
 
     loop {
         if hw.input.dpad_left.is_held() && player.p.x > 0 {
@@ -131,10 +145,7 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
                     l.dead = true;
                     score += 1;
                     sound = Sound::EnemyDestroyed { start_tick: tick };
-                    particles.add_explosion(I16x2 {
-                        x: e.p.x as i16 * FRACTION as i16,
-                        y: e.p.y as i16 * FRACTION as i16,
-                    });
+                    particles.emit(&Emitter::explosion(), e.p);
                 }
             }
         }
@@ -142,6 +153,7 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
         lasers = lasers.iter().filter(|l| !l.dead).cloned().collect();
         enemies = enemies.iter().filter(|e| !e.dead).cloned().collect();
         particles.update();
+        meminfo::report_pool("particles", particles.len(), particles.capacity());
 
         hw.draw(|display| {
             display.clear(background_color).unwrap();
@@ -219,99 +231,3 @@ impl Sound {
         }
     }
 }
-
-const FRACTION: i32 = 64;
-
-fn transform(p: I16x2) -> Point {
-    Point::new(p.x as i32 / FRACTION, p.y as i32 / FRACTION)
-}
-
-#[derive(Debug, Clone)]
-struct Particle {
-    p: I16x2,
-    v: I16x2,
-    color: Rgb565,
-    life: i16,
-}
-
-struct Particles {
-    particles: Vec<Particle, 100>,
-    rng: oorandom::Rand32,
-}
-
-impl Particles {
-    const MAX_WIDTH: i16 = WIDTH as i16 * FRACTION as i16;
-    const MAX_HEIGHT: i16 = HEIGHT as i16 * FRACTION as i16;
-
-    fn new() -> Self {
-        Self {
-            particles: Vec::new(),
-            rng: oorandom::Rand32::new(time::time_us() as u64),
-        }
-    }
-
-    fn add_particle(&mut self, p: I16x2, v: I16x2, color: Rgb565, life: i16) {
-        self.particles.push(Particle { p, v, color, life }).unwrap();
-    }
-
-    fn rand16(&mut self, min: i16, max: i16) -> i16 {
-        if min >= 0 && max >= 0 {
-            self.rng.rand_range((min as u32)..(max as u32)) as i16
-        } else {
-            let maxadjust = if max < 0 { -max } else { 0 };
-            let minadjust = if min < 0 { -min } else { 0 };
-            let result = self.rng.rand_range(((min + minadjust + maxadjust) as u32)
-            ..((max + minadjust + maxadjust) as u32));
-            result as i16 - maxadjust - minadjust
-        }
-    }
-
-    fn add_explosion(&mut self, p: I16x2) {
-        let minv = -3 * FRACTION;
-        let maxv = 3 * FRACTION;
-        for _ in 0..10 {
-            let v = I16x2 {
-                x: self.rand16(minv as i16, maxv as i16),
-                y: self.rand16(minv as i16, maxv as i16),
-            };
-            let color = Rgb565::new(
-                self.rand16(100, 255) as u8,
-                self.rand16(0, 255) as u8,
-                self.rand16(0, 255) as u8,
-            );
-            let life = self.rand16(5, 30);
-            self.add_particle(p, v, color, life);
-        }
-    }
-
-    #[allow(clippy::assign_op_pattern)]
-    fn update(&mut self) {
-        for i in 0..self.particles.len() {
-            self.particles[i].p = self.particles[i].p + self.particles[i].v;
-            self.particles[i].life -= 1;
-            if self.particles[i].p.x >= Self::MAX_WIDTH
-                || self.particles[i].p.x < 0
-                || self.particles[i].p.y >= Self::MAX_HEIGHT
-                || self.particles[i].p.y < 0
-            {
-                self.particles[i].life = 0;
-            }
-        }
-        self.particles = self
-            .particles
-            .iter()
-            .filter(|l| l.life > 0)
-            .cloned()
-            .collect();
-    }
-
-    fn draw(&self, display: &mut Display) {
-        for particle in self.particles.iter() {
-            let sz = particle.life.min(10) as u32;
-            Rectangle::with_center(transform(particle.p), Size::new(sz, sz))
-                .into_styled(PrimitiveStyle::with_fill(particle.color))
-                .draw(display)
-                .unwrap();
-        }
-    }
-}
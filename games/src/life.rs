@@ -1,5 +1,6 @@
 use log::info;
-use picosystem::{hardware, time};
+use picosystem::hardware;
+use picosystem::time::{self, FrameClock};
 
 use embedded_graphics::pixelcolor::Rgb565;
 use embedded_graphics::prelude::*;
@@ -19,9 +20,7 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
     let mut cursory = 60;
     let mut paused = false;
 
-    let mut frame = 0;
-    let mut prev_time_us = time::time_us();
-    let mut prev_frame = 0;
+    let mut clock = FrameClock::new();
     loop {
         if !paused {
             const LIMIT: usize = BOARD_SIZE - 1;
@@ -92,14 +91,14 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
             paused = !paused;
         }
 
-        let now = time::time_us();
-        if now - prev_time_us > 1_000_000 {
-            let frame_time = (now - prev_time_us) / (frame - prev_frame) as u32;
-            info!("Frame time: {} us", frame_time);
-            prev_frame = frame;
-            prev_time_us = now;
+        clock.tick();
+        if clock.frame().is_multiple_of(64) {
+            let stats = clock.stats();
+            info!(
+                "Frame time: {} us, jitter {} us",
+                stats.mean_us, stats.jitter_us
+            );
         }
-        frame += 1;
     }
 }
 
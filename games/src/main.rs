@@ -22,6 +22,7 @@ use log::info;
 use picosystem::display::{Display, HEIGHT, WIDTH};
 use picosystem::fps_monitor::FpsMonitor;
 use picosystem::hardware;
+use picosystem::input::{ButtonEventKind, ButtonId};
 use picosystem::time;
 
 use embedded_graphics::{
@@ -33,6 +34,12 @@ use embedded_graphics::{
 use heapless::Vec;
 use micromath::vector::I16x2;
 
+picosystem_macros::game_meta!(
+    title = "PicoSystem games",
+    version = "0.1.0",
+    icon = "games/assets/playerShip2_red.png"
+);
+
 struct MenuItem {
     name: &'static str,
     main: fn(&mut hardware::Hardware) -> !,
@@ -97,14 +104,20 @@ fn main() -> ! {
 
     let mut fps_monitor = FpsMonitor::new();
 
-    loop {
-        if hw.input.dpad_up.is_pressed() && selected_index > 0 {
-            selected_index -= 1;
-        } else if hw.input.dpad_down.is_pressed() && selected_index < items.len() - 1 {
-            selected_index += 1;
-        } else if hw.input.button_a.is_pressed() {
-            break;
-        } else if hw.input.button_x.is_held() && hw.input.button_y.is_held() {
+    hw.input.enable_events();
+    'menu: loop {
+        while let Some(event) = hw.input.poll_event() {
+            if event.kind == ButtonEventKind::Release {
+                continue;
+            }
+            match event.button {
+                ButtonId::DpadUp if selected_index > 0 => selected_index -= 1,
+                ButtonId::DpadDown if selected_index < items.len() - 1 => selected_index += 1,
+                ButtonId::A if event.kind == ButtonEventKind::Press => break 'menu,
+                _ => {}
+            }
+        }
+        if hw.input.button_x.is_held() && hw.input.button_y.is_held() {
             system::main(&mut hw);
         }
 
@@ -135,6 +148,7 @@ fn main() -> ! {
         stars.update();
         fps_monitor.update();
     }
+    hw.input.disable_events();
 
     hw.draw(|display| {
         display.clear(Rgb565::BLACK).unwrap();
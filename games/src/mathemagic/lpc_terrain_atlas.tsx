@@ -1,4 +1,19 @@
 <?xml version="1.0" encoding="UTF-8"?>
 <tileset version="1.5" tiledversion="1.8.0" name="lpc_terrain_atlas" tilewidth="32" tileheight="32" tilecount="1024" columns="32">
  <image source="terrain_atlas.png" width="1032" height="1032"/>
+ <tile id="126">
+  <objectgroup draworder="index" id="2">
+   <object id="1" x="24" y="20" width="8" height="12"/>
+  </objectgroup>
+ </tile>
+ <tile id="127">
+  <objectgroup draworder="index" id="2">
+   <object id="1" x="0" y="20" width="8" height="12"/>
+  </objectgroup>
+ </tile>
+ <tile id="462">
+  <objectgroup draworder="index" id="2">
+   <object id="1" x="0" y="8" width="32" height="24"/>
+  </objectgroup>
+ </tile>
 </tileset>
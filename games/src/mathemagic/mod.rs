@@ -2,12 +2,15 @@ use embedded_graphics::image::Image;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::Rectangle;
 use log::info;
+use picosystem::anim::{self, Animation, AnimationPlayer, Frame, PlayMode};
+use picosystem::camera::Camera;
 use picosystem::display::{Display, HEIGHT, WIDTH};
 use picosystem::fps_monitor::FpsMonitor;
 use picosystem::hardware;
-use picosystem::map::{Map, MapTile, INVALID_TILE};
-use picosystem::tile::{self, GenMapTile, TILE_SIZE};
-use picosystem::time;
+use picosystem::map::{Map, MapTile, TileRenderer};
+use picosystem::math::I16F16;
+use picosystem::tile::{GenMapTile, TILE_SIZE};
+use picosystem::time::{self, FrameClock};
 use picosystem_macros::{atlas, map, sprite};
 
 atlas!(atlas, "games/src/mathemagic/terrain_atlas.png", 32);
@@ -32,6 +35,17 @@ enum Direction {
 
 const SLIME_FRAME_LENGTH: i32 = 30;
 
+const PLAYER_SIZE: u32 = 64;
+
+// Each row of the player's sheet is one direction: a standing frame followed
+// by eight walking frames.
+static WALK_FRAMES: [Frame; 8] = anim::strip(
+    Point::new(PLAYER_SIZE as i32, 0),
+    Size::new(PLAYER_SIZE, PLAYER_SIZE),
+    50,
+);
+static WALK: Animation = Animation::new(&WALK_FRAMES, PlayMode::Loop);
+
 fn generate_map(position: Point) -> GenMapTile {
     let map = worldmap();
 
@@ -54,29 +68,16 @@ fn generate_map(position: Point) -> GenMapTile {
         atlas456(),
     ];
 
-    use hash32::{Hash, Hasher};
-    let mut hasher = hash32::Murmur3Hasher::default();
-    position.x.hash(&mut hasher);
-    position.y.hash(&mut hasher);
-    let hash = hasher.finish();
-    let map_x = position.x / TILE_SIZE;
-    let map_y = position.y / TILE_SIZE;
-    let mut layers = heapless::Vec::new();
-
-    if (0..(map.width as i32)).contains(&map_x) && (0..(map.height as i32)).contains(&map_y) {
-        let index = (map_x + map_y * map.width as i32) as usize;
-        for tile_index in map.tiles[index].layers {
-            if tile_index != INVALID_TILE {
-                let _ = layers.push(map.tile_functions[tile_index as usize]());
-            }
-        }
-    }
-
-    if layers.is_empty() {
+    map.gen_map_tile(position).unwrap_or_else(|| {
+        use hash32::{Hash, Hasher};
+        let mut hasher = hash32::Murmur3Hasher::default();
+        position.x.hash(&mut hasher);
+        position.y.hash(&mut hasher);
+        let hash = hasher.finish();
+        let mut layers = heapless::Vec::new();
         let _ = layers.push(ocean_tiles[hash as usize % ocean_tiles.len()]);
-    }
-
-    GenMapTile { layers }
+        GenMapTile { layers }
+    })
 }
 
 #[derive(Debug)]
@@ -119,7 +120,7 @@ fn move_slime(slime: &mut Monster, rng: &mut oorandom::Rand32) {
     slime.move_frames_remaining -= 1;
 }
 
-fn draw_slime(slime: &Monster, display: &mut Display, player_position: Point) {
+fn draw_slime(slime: &Monster, display: &mut Display, camera: &Camera) {
     let s: u32 = 24;
     let mut anim_frame = slime.move_frames_remaining / SLIME_FRAME_LENGTH % 4;
     if anim_frame == 3 {
@@ -134,7 +135,7 @@ fn draw_slime(slime: &Monster, display: &mut Display, player_position: Point) {
     let atlas_coord = Point::new(anim_frame * 24, atlas_y);
     let slime_sprite = slime_atlas().sub_image(&Rectangle::new(atlas_coord, Size::new(s, s)));
     Image::new(&slime_sprite, Point::new(0, 0))
-        .translate(slime.position - player_position - Point::new(s as i32, s as i32) / 2)
+        .translate(camera.to_screen(slime.position) - Point::new(s as i32, s as i32) / 2)
         .draw(display)
         .unwrap();
 }
@@ -148,82 +149,116 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
         info!("Flash clock divider: {}", regs.baudr.read().bits());
     }
 
-    let mut position = Point::new((100 * 32 - 240) / 2, (100 * 32 - 240) / 2);
+    let map = worldmap();
+    let spawn = map
+        .object("player")
+        .map_or(Point::new(1600, 1600), |o| o.position);
+    // The player's position is the center of their sprite.
+    let mut position = spawn;
+    let mut camera = Camera::new(Size::new(WIDTH as u32, HEIGHT as u32));
+    camera.set_bounds(Some(map.bounds()));
+    camera.set_dead_zone(Size::new(48, 32));
+    camera.set_smoothing(I16F16::from_f32(0.2));
+    camera.jump_to(position);
     let mut frame = 0;
-    let mut walk_frame = 0;
+    let mut walk = AnimationPlayer::new(&WALK);
+    let mut clock = FrameClock::new();
+    // Prefetches up to 2K of compressed tiles coming into view.
+    let mut tile_renderer: TileRenderer<_, TILE_SIZE, 1024> = TileRenderer::new(generate_map);
+    tile_renderer.set_animations(map.animations);
     let mut player_direction = Direction::North;
 
     let mut slimes: heapless::Vec<Monster, 8> = heapless::Vec::new();
-    for _ in 0..8 {
-        slimes
-            .push(Monster {
-                position: position
-                    + Point::new(240 / 2, 240 / 2)
-                    + Point::new(
-                        rng.rand_range(0..400) as i32 - 200,
-                        rng.rand_range(0..400) as i32 - 200,
-                    ),
+    for area in map.objects_of_kind("slime_spawn") {
+        let count = area.int_property("count").unwrap_or(1);
+        for _ in 0..count {
+            let offset = Point::new(
+                rng.rand_range(0..area.size.width.max(1)) as i32,
+                rng.rand_range(0..area.size.height.max(1)) as i32,
+            );
+            let _ = slimes.push(Monster {
+                position: area.position + offset,
                 direction: Direction::South,
                 velocity: Point::new(0, 0),
                 move_frames_remaining: rng.rand_range(0..120) as i32,
-            })
-            .unwrap();
+            });
+        }
     }
 
     loop {
         let speed = 2;
+        let previous_position = position;
+        let walking;
         if hw.input.dpad_left.is_held() {
             position.x -= speed;
             player_direction = Direction::West;
-            walk_frame += 1;
+            walking = true;
         } else if hw.input.dpad_right.is_held() {
             position.x += speed;
             player_direction = Direction::East;
-            walk_frame += 1;
+            walking = true;
         } else if hw.input.dpad_up.is_held() {
             position.y -= speed;
             player_direction = Direction::North;
-            walk_frame += 1;
+            walking = true;
         } else if hw.input.dpad_down.is_held() {
             position.y += speed;
             player_direction = Direction::South;
-            walk_frame += 1;
+            walking = true;
         } else {
-            walk_frame = 0;
+            walking = false;
+        }
+
+        clock.tick();
+        if walking {
+            walk.update(clock.delta_ms());
+        } else {
+            walk.restart();
+        }
+
+        // Only the player's feet collide so they can walk behind tall tiles.
+        let feet = Rectangle::new(position + Point::new(-8, 20), Size::new(16, 10));
+        if map.is_blocked(&feet) {
+            position = previous_position;
         }
 
         for slime in slimes.iter_mut() {
             move_slime(slime, &mut rng);
         }
 
-        tile::draw(&mut hw.display, position, &generate_map, frame % 60 == 0);
+        if hw.input.button_a.is_pressed() {
+            camera.shake(6, 20);
+        }
+        camera.follow(position);
+        tile_renderer.set_position(camera.position());
+        tile_renderer.draw(&mut hw.display);
+        if frame % 60 == 0 {
+            info!("position: {:?}", position);
+            tile_renderer.stats().log();
+        }
 
         hw.draw(|display| {
-            let s: u32 = 64;
+            let s = PLAYER_SIZE;
             let player_atlas = protagonist();
-            let walk_anim = if walk_frame == 0 {
-                0
+            let row = match player_direction {
+                Direction::North => 0,
+                Direction::East => 3 * s as i32,
+                Direction::South => 2 * s as i32,
+                Direction::West => s as i32,
+            };
+            let source = if walking {
+                walk.source_rect()
             } else {
-                1 + (walk_frame / 3) % 8
+                Rectangle::new(Point::zero(), Size::new(s, s))
             };
-            let atlas_coord = match player_direction {
-                Direction::North => Point::new(0, 0),
-                Direction::East => Point::new(0, 3 * s as i32),
-                Direction::South => Point::new(0, 2 * s as i32),
-                Direction::West => Point::new(0, s as i32),
-            } + Point::new(walk_anim * s as i32, 0);
-            let player_sprite =
-                player_atlas.sub_image(&Rectangle::new(atlas_coord, Size::new(s, s)));
+            let player_sprite = player_atlas.sub_image(&source.translate(Point::new(0, row)));
             Image::new(&player_sprite, Point::new(0, 0))
-                .translate(Point::new(
-                    (WIDTH as i32 - s as i32) / 2,
-                    (HEIGHT as i32 - s as i32) / 2,
-                ))
+                .translate(camera.to_screen(position) - Point::new(s as i32, s as i32) / 2)
                 .draw(display)
                 .unwrap();
 
             for slime in slimes.iter() {
-                draw_slime(slime, display, position);
+                draw_slime(slime, display, &camera);
             }
         });
 
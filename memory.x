@@ -2,10 +2,15 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
     FLASH : ORIGIN = 0x10000100, LENGTH = 4096K - 0x100
-    STATIC_FLASH : ORIGIN = 0x10400000, LENGTH = 16384K - 4096K
+    STATIC_FLASH : ORIGIN = 0x10400000, LENGTH = 16384K - 4096K - 1024K
+    /* picosystem::launcher runs games from slots here instead, see slot.x. */
+    /* The last 1M is used by storage.rs. */
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
+/* Leaves room for the game header after the vector table. */
+_stext = ORIGIN(FLASH) + 0xf00;
+
 SECTIONS {
     /* ### Boot loader */
     .boot2 ORIGIN(BOOT2) :
@@ -13,6 +18,13 @@ SECTIONS {
         KEEP(*(.boot2));
     } > BOOT2
 
+    /* Where picosystem_macros::game_meta! puts the name and icon, between
+       the vector table and the code. */
+    .game_header ORIGIN(FLASH) + 0x100 :
+    {
+        KEEP(*(.game_header));
+    } > FLASH
+
     .static_rodata ORIGIN(STATIC_FLASH):
     {
         *(.static_rodata)
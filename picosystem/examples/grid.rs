@@ -1,6 +1,7 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(feature = "simulator"), no_std)]
+#![cfg_attr(not(feature = "simulator"), no_main)]
 
+#[cfg(not(feature = "simulator"))]
 use cortex_m_rt::entry;
 use log::info;
 use picosystem::hardware;
@@ -9,7 +10,7 @@ use embedded_graphics::pixelcolor::Rgb565;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::*;
 
-#[entry]
+#[cfg_attr(not(feature = "simulator"), entry)]
 fn main() -> ! {
     let mut hw = hardware::Hardware::new();
 
@@ -0,0 +1,27 @@
+#![cfg_attr(not(feature = "simulator"), no_std)]
+#![cfg_attr(not(feature = "simulator"), no_main)]
+
+// The launcher runs games installed in flash, which the simulator doesn't
+// have.
+#[cfg(feature = "simulator")]
+fn main() {
+    eprintln!("The launcher example only runs on the device");
+}
+
+#[cfg(not(feature = "simulator"))]
+mod device {
+    use cortex_m_rt::entry;
+    use log::info;
+    use picosystem::{hardware, launcher};
+
+    #[entry]
+    fn main() -> ! {
+        let mut hw = hardware::Hardware::new();
+        info!("Finished initialization");
+
+        for game in launcher::scan() {
+            info!("Slot {}: {}", game.slot, game.header.name());
+        }
+        launcher::run(&mut hw)
+    }
+}
@@ -1,31 +1,43 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(feature = "simulator"), no_std)]
+#![cfg_attr(not(feature = "simulator"), no_main)]
 
+#[cfg(not(feature = "simulator"))]
 use cortex_m_rt::entry;
-use embedded_hal::digital::v2::OutputPin;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::*;
 use log::info;
 use picosystem::hardware;
 
-#[entry]
+#[cfg_attr(not(feature = "simulator"), entry)]
 fn main() -> ! {
     let mut hw = hardware::Hardware::new();
     info!("Finished initialization");
 
-    hw.red_led_pin.set_low().unwrap();
-    hw.green_led_pin.set_low().unwrap();
-    hw.blue_led_pin.set_low().unwrap();
-
+    // Nothing is drawn, so the watchdog is fed here rather than by
+    // `Hardware::draw`, at least every `DEFAULT_TIMEOUT_MS`.
     loop {
-        hw.red_led_pin.set_high().unwrap();
-        hw.delay.delay_ms(500);
-        hw.red_led_pin.set_low().unwrap();
+        hw.watchdog.feed();
+        for color in [Rgb888::RED, Rgb888::GREEN, Rgb888::BLUE] {
+            hw.led.set_color(color);
+            hw.delay.delay_ms(500);
+        }
+
+        hw.watchdog.feed();
+        hw.led.blink(Rgb888::YELLOW, 250);
+        hw.delay.delay_ms(2000);
 
-        hw.green_led_pin.set_high().unwrap();
-        hw.delay.delay_ms(500);
-        hw.green_led_pin.set_low().unwrap();
+        hw.watchdog.feed();
+        hw.led.breathe(Rgb888::CYAN, 2000);
+        hw.delay.delay_ms(4000);
 
-        hw.blue_led_pin.set_high().unwrap();
-        hw.delay.delay_ms(500);
-        hw.blue_led_pin.set_low().unwrap();
+        hw.watchdog.feed();
+        hw.led.set_effect(picosystem::led::Effect::Solid);
+        hw.led.set_color(Rgb888::WHITE);
+        for brightness in (0..=255).rev().step_by(5) {
+            hw.led.set_brightness(brightness);
+            hw.delay.delay_ms(20);
+        }
+        hw.led.set_brightness(255);
+        hw.led.off();
     }
 }
@@ -0,0 +1,69 @@
+#![cfg_attr(not(feature = "simulator"), no_std)]
+#![cfg_attr(not(feature = "simulator"), no_main)]
+
+// Music and samples are only played on the device, the simulator's audio
+// keeps games building but has no synthesizer.
+#[cfg(feature = "simulator")]
+fn main() {
+    eprintln!("The music example only runs on the device");
+}
+
+#[cfg(not(feature = "simulator"))]
+mod device {
+    use cortex_m_rt::entry;
+    use embedded_graphics::mono_font::{ascii::FONT_10X20, MonoTextStyle};
+    use embedded_graphics::pixelcolor::Rgb565;
+    use embedded_graphics::prelude::*;
+    use embedded_graphics::text::{Alignment, Text};
+    use log::info;
+    use picosystem::hardware;
+    use picosystem_macros::{music, sound};
+
+    music!(song, "picosystem/examples/music/assets/song.txt");
+    sound!(coin, "picosystem/examples/music/assets/coin.wav");
+
+    #[entry]
+    fn main() -> ! {
+        let mut hw = hardware::Hardware::new();
+        info!("Finished initialization");
+
+        let mut tempo = song().tempo;
+        hw.audio.play_music(song(), true);
+
+        loop {
+            if hw.input.dpad_up.is_pressed() && tempo < 240 {
+                tempo += 10;
+                hw.audio.set_music_tempo(tempo);
+            }
+            if hw.input.dpad_down.is_pressed() && tempo > 40 {
+                tempo -= 10;
+                hw.audio.set_music_tempo(tempo);
+            }
+            if hw.input.button_a.is_pressed() {
+                if hw.audio.is_music_playing() {
+                    hw.audio.stop_music();
+                } else {
+                    hw.audio.play_music(song(), true);
+                    hw.audio.set_music_tempo(tempo);
+                }
+            }
+            if hw.input.button_b.is_pressed() {
+                hw.audio.play_sample(coin(), 255, false);
+            }
+
+            hw.draw(|display| {
+                display.clear(Rgb565::BLACK).unwrap();
+                let mut s = heapless::String::<32>::new();
+                core::fmt::write(&mut s, format_args!("tempo {}", tempo)).unwrap();
+                Text::with_alignment(
+                    &s,
+                    Point::new(120, 120),
+                    MonoTextStyle::new(&FONT_10X20, Rgb565::WHITE),
+                    Alignment::Center,
+                )
+                .draw(display)
+                .unwrap();
+            });
+        }
+    }
+}
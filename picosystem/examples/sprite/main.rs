@@ -1,6 +1,7 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(feature = "simulator"), no_std)]
+#![cfg_attr(not(feature = "simulator"), no_main)]
 
+#[cfg(not(feature = "simulator"))]
 use cortex_m_rt::entry;
 use embedded_graphics::image::Image;
 use embedded_graphics::pixelcolor::Rgb565;
@@ -9,12 +10,17 @@ use heapless::Vec;
 use log::info;
 use picosystem::display::{HEIGHT, WIDTH};
 use picosystem::hardware;
+use picosystem::input::VirtualStick;
+use picosystem::math::{Vector2, I16F16};
+use picosystem::sprite::draw_indexed;
 use picosystem_macros::sprite;
 
+// Quantized to 16 colors, a quarter of the flash of the RGB565 version.
 sprite!(
     sprite_ship,
     "picosystem/examples/sprite/assets/playerShip2_red.png",
-    56
+    56,
+    16
 );
 
 sprite!(
@@ -23,33 +29,31 @@ sprite!(
     6
 );
 
-#[entry]
+#[cfg_attr(not(feature = "simulator"), entry)]
 fn main() -> ! {
     let mut hw = hardware::Hardware::new();
     info!("Finished initialization");
 
     let background_color = Rgb565::CSS_DARK_SLATE_BLUE;
 
-    let player_img = Image::new(sprite_ship(), Point::zero());
+    let ship = sprite_ship();
     let laser_img = Image::new(sprite_laser(), Point::zero());
 
-    let mut p = Point::new(120, 120);
+    let mut position = Vector2::new(I16F16::from_int(120), I16F16::from_int(120));
+    let mut stick = VirtualStick::new();
+    let bottom_right = Vector2::new(
+        I16F16::from_int(WIDTH as i32 - 1),
+        I16F16::from_int(HEIGHT as i32 - 1),
+    );
     let speed = 2;
     let mut lasers: Vec<Point, 32> = Vec::new();
 
     loop {
-        if hw.input.dpad_left.is_held() && p.x > 0 {
-            p.x -= speed;
-        }
-        if hw.input.dpad_right.is_held() && p.x < WIDTH as i32 - speed {
-            p.x += speed;
-        }
-        if hw.input.dpad_up.is_held() && p.y > 0 {
-            p.y -= speed;
-        }
-        if hw.input.dpad_down.is_held() && p.y < HEIGHT as i32 - speed {
-            p.y += speed;
-        }
+        stick.update(&hw.input);
+        position += stick.value() * I16F16::from_int(speed);
+        position.x = position.x.clamp(I16F16::ZERO, bottom_right.x);
+        position.y = position.y.clamp(I16F16::ZERO, bottom_right.y);
+        let p = position.to_point();
         if hw.input.button_a.is_pressed() {
             let _ = lasers.push(p);
         }
@@ -71,10 +75,7 @@ fn main() -> ! {
                 .cloned()
                 .collect();
 
-            player_img
-                .translate(p - Point::new(player_img.bounding_box().size.width as i32 / 2, 0))
-                .draw(display)
-                .unwrap();
+            draw_indexed(display, ship, p - Point::new(ship.size.width as i32 / 2, 0));
         });
     }
 }
@@ -0,0 +1,93 @@
+#![cfg_attr(not(feature = "simulator"), no_std)]
+#![cfg_attr(not(feature = "simulator"), no_main)]
+
+// The executor drives the device's timers and interrupts, so async games
+// only run on the device.
+#[cfg(feature = "simulator")]
+fn main() {
+    eprintln!("The tasks example only runs on the device");
+}
+
+#[cfg(not(feature = "simulator"))]
+mod device {
+    use core::cell::Cell;
+    use core::pin::pin;
+    use cortex_m_rt::entry;
+    use embedded_graphics::pixelcolor::{Rgb565, Rgb888};
+    use embedded_graphics::prelude::*;
+    use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+    use log::info;
+    use picosystem::display::{Display, HEIGHT, WIDTH};
+    use picosystem::executor::Executor;
+    use picosystem::hardware;
+    use picosystem::input::{ButtonEventKind, ButtonId, Input};
+    use picosystem::led::Led;
+    use picosystem::time::{self, Duration};
+
+    // Bounces a square around the screen, in the color picked with the buttons.
+    async fn render(display: &mut Display, color: &Cell<Rgb565>) {
+        let size = 40;
+        let mut position = Point::new(0, 0);
+        let mut velocity = Point::new(3, 2);
+        loop {
+            position += velocity;
+            if position.x <= 0 || position.x + size >= WIDTH as i32 {
+                velocity.x = -velocity.x;
+            }
+            if position.y <= 0 || position.y + size >= HEIGHT as i32 {
+                velocity.y = -velocity.y;
+            }
+            display
+                .draw_async(|display| {
+                    display.clear(Rgb565::BLACK).unwrap();
+                    Rectangle::new(position, Size::new(size as u32, size as u32))
+                        .into_styled(PrimitiveStyle::with_fill(color.get()))
+                        .draw(display)
+                        .unwrap();
+                })
+                .await;
+        }
+    }
+
+    async fn buttons(input: &mut Input, color: &Cell<Rgb565>) {
+        loop {
+            let event = input.next_event().await;
+            if event.kind != ButtonEventKind::Press {
+                continue;
+            }
+            info!("{:?} pressed", event.button);
+            match event.button {
+                ButtonId::A => color.set(Rgb565::RED),
+                ButtonId::B => color.set(Rgb565::GREEN),
+                ButtonId::X => color.set(Rgb565::BLUE),
+                ButtonId::Y => color.set(Rgb565::YELLOW),
+                _ => {}
+            }
+        }
+    }
+
+    async fn blink(led: &mut Led) {
+        loop {
+            led.set_color(Rgb888::WHITE);
+            time::sleep(Duration::millis(100)).await;
+            led.off();
+            time::sleep(Duration::millis(900)).await;
+        }
+    }
+
+    #[entry]
+    fn main() -> ! {
+        let mut hw = hardware::Hardware::new();
+        info!("Finished initialization");
+
+        hw.input.enable_events();
+        let color = Cell::new(Rgb565::RED);
+        let mut executor = Executor::new();
+        executor.run(&mut [
+            pin!(render(&mut hw.display, &color)),
+            pin!(buttons(&mut hw.input, &color)),
+            pin!(blink(&mut hw.led)),
+        ]);
+        unreachable!()
+    }
+}
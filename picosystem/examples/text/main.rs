@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+use cortex_m_rt::entry;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use log::info;
+use picosystem::font::Spacing;
+use picosystem::hardware;
+use picosystem_macros::font;
+
+font!(font_6x13, "picosystem/examples/text/assets/6x13.bdf", 13);
+
+#[entry]
+fn main() -> ! {
+    let mut hw = hardware::Hardware::new();
+    info!("Finished initialization");
+
+    let font = font_6x13();
+    let proportional = font.with_spacing(Spacing::Proportional);
+    let mut x = 0;
+
+    loop {
+        hw.draw(|display| {
+            display.clear(Rgb565::BLACK).unwrap();
+            font.draw_text(
+                display,
+                Point::new(8, 8),
+                Rgb565::WHITE,
+                "Monospace text\nwith two lines.",
+            );
+            proportional.draw_text(
+                display,
+                Point::new(8, 48),
+                Rgb565::YELLOW,
+                "Proportional text, fits more in.",
+            );
+            font.draw_text(
+                display,
+                Point::new(8, 80),
+                Rgb565::CYAN,
+                "UTF-8: déjà vu, ½ × ¾ = ⅜?",
+            );
+            // Clipped at the screen edges.
+            let end = font.draw_text(
+                display,
+                Point::new(x - 200, 200),
+                Rgb565::GREEN,
+                "Scrolling off the edge of the screen",
+            );
+            if end.x < 0 {
+                x = 440;
+            }
+        });
+        x -= 1;
+    }
+}
@@ -1,6 +1,7 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(feature = "simulator"), no_std)]
+#![cfg_attr(not(feature = "simulator"), no_main)]
 
+#[cfg(not(feature = "simulator"))]
 use cortex_m_rt::entry;
 use embedded_graphics::pixelcolor::Rgb565;
 use embedded_graphics::prelude::*;
@@ -8,48 +9,57 @@ use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
 use heapless::Vec;
 use log::info;
 use picosystem::display::WIDTH;
-use picosystem::fps_monitor::FpsMonitor;
 use picosystem::hardware;
+use picosystem::scheduler::FrameRate;
 
-#[entry]
+struct Tunnel {
+    sizes: Vec<f32, 32>,
+    countdown: i32,
+}
+
+#[cfg_attr(not(feature = "simulator"), entry)]
 fn main() -> ! {
     let mut hw = hardware::Hardware::new();
     info!("Finished initialization");
 
     let center = Point::new(119, 119);
-    let mut sizes: Vec<f32, 32> = Vec::new();
     let multiplier = 1.02;
     let initial_size = 3.0;
-    let mut fps_monitor = FpsMonitor::new();
     let interval = 25;
-    let mut countdown = 0;
-
-    loop {
-        sizes = sizes
-            .iter()
-            .cloned()
-            .map(|size| size * multiplier)
-            .filter(|size| *size < WIDTH as f32)
-            .collect();
+    let tunnel = Tunnel {
+        sizes: Vec::new(),
+        countdown: 0,
+    };
 
-        if countdown == 0 {
-            let _ = sizes.push(initial_size);
-            countdown = interval;
-        } else {
-            countdown -= 1;
-        }
+    hardware::run(
+        &mut hw,
+        FrameRate::Fps50,
+        tunnel,
+        |tunnel, _hw, _frame| {
+            tunnel.sizes = tunnel
+                .sizes
+                .iter()
+                .cloned()
+                .map(|size| size * multiplier)
+                .filter(|size| *size < WIDTH as f32)
+                .collect();
 
-        hw.draw(|display| {
+            if tunnel.countdown == 0 {
+                let _ = tunnel.sizes.push(initial_size);
+                tunnel.countdown = interval;
+            } else {
+                tunnel.countdown -= 1;
+            }
+        },
+        |tunnel, display, _frame| {
             display.clear(Rgb565::CSS_DARK_SLATE_BLUE).unwrap();
-            for &size in sizes.iter() {
+            for &size in tunnel.sizes.iter() {
                 let size = size as u32 | 1;
-                Rectangle::with_center(center, Size::new(size as u32, size as u32))
+                Rectangle::with_center(center, Size::new(size, size))
                     .into_styled(PrimitiveStyle::with_stroke(Rgb565::GREEN, 1))
                     .draw(display)
                     .unwrap();
             }
-        });
-
-        fps_monitor.update();
-    }
+        },
+    );
 }
@@ -0,0 +1,188 @@
+//! Achievements a game awards the player, such as finishing a level without
+//! a hit.
+//!
+//! A game lists its achievements in a static slice and keeps them in an
+//! `Achievements` under its own name, which namespaces the file they are
+//! saved to, so games on the same console don't share unlocks. Each
+//! achievement has an id that stays the same between versions of the game,
+//! since the unlocked ones are saved by id rather than by their place in
+//! the list.
+//!
+//! On the device, `Achievements::load` reads the unlocked ones from flash,
+//! and `Achievements::unlock` saves the new one, shows its name as a toast
+//! and flashes the LED. The simulator starts with none unlocked.
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::led::Led;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::logging;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::storage::{self, FsError};
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::toast::{self, Toast};
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use embedded_graphics::pixelcolor::Rgb888;
+
+/// Ids go from 0 to one less than this.
+pub const MAX_ACHIEVEMENTS: usize = 64;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+const FILE_PREFIX: &str = "achievements_";
+const VERSION: u8 = 1;
+// The version and the unlocked ids as bits.
+const LEN: usize = 9;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+const TOAST_DURATION_MS: u32 = 3000;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+const FLASH_COLOR: Rgb888 = Rgb888::new(255, 192, 0);
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+const FLASH_TIMES: u32 = 3;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+const FLASH_PERIOD_MS: u32 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Achievement {
+    /// Below `MAX_ACHIEVEMENTS` and unique within the game.
+    pub id: u8,
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+/// The achievements of one game and which of them are unlocked.
+#[derive(Debug, Clone)]
+pub struct Achievements {
+    game: &'static str,
+    list: &'static [Achievement],
+    unlocked: u64,
+}
+
+impl Achievements {
+    /// The achievements in `list` of the game called `game`, all locked.
+    pub fn new(game: &'static str, list: &'static [Achievement]) -> Self {
+        assert!(list
+            .iter()
+            .all(|achievement| (achievement.id as usize) < MAX_ACHIEVEMENTS));
+        Achievements {
+            game,
+            list,
+            unlocked: 0,
+        }
+    }
+
+    pub fn game(&self) -> &'static str {
+        self.game
+    }
+
+    pub fn list(&self) -> &'static [Achievement] {
+        self.list
+    }
+
+    pub fn get(&self, id: u8) -> Option<&'static Achievement> {
+        self.list.iter().find(|achievement| achievement.id == id)
+    }
+
+    pub fn is_unlocked(&self, id: u8) -> bool {
+        self.get(id).is_some() && self.unlocked & 1 << id != 0
+    }
+
+    pub fn unlocked_count(&self) -> usize {
+        self.iter().filter(|&(_, unlocked)| unlocked).count()
+    }
+
+    /// The achievements in the order listed, each with whether it is
+    /// unlocked, for a screen showing them all.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static Achievement, bool)> + '_ {
+        self.list
+            .iter()
+            .map(|achievement| (achievement, self.unlocked & 1 << achievement.id != 0))
+    }
+
+    /// Marks `id` unlocked without saving or announcing it. Returns the
+    /// achievement if it was locked, or `None` if it was already unlocked
+    /// or isn't in the list.
+    pub fn set_unlocked(&mut self, id: u8) -> Option<&'static Achievement> {
+        let achievement = self.get(id)?;
+        if self.is_unlocked(id) {
+            return None;
+        }
+        self.unlocked |= 1 << id;
+        Some(achievement)
+    }
+
+    /// Locks every achievement again.
+    pub fn reset(&mut self) {
+        self.unlocked = 0;
+    }
+
+    pub fn to_bytes(&self) -> [u8; LEN] {
+        let mut bytes = [VERSION; LEN];
+        bytes[1..].copy_from_slice(&self.unlocked.to_le_bytes());
+        bytes
+    }
+
+    /// Takes the unlocked achievements from `bytes` made by `to_bytes`.
+    /// Returns false, leaving them as they were, if `bytes` weren't.
+    pub fn set_bytes(&mut self, bytes: &[u8]) -> bool {
+        match *bytes {
+            [VERSION, ref unlocked @ ..] if unlocked.len() == LEN - 1 => {
+                let mut bits = [0; LEN - 1];
+                bits.copy_from_slice(unlocked);
+                self.unlocked = u64::from_le_bytes(bits);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+impl Achievements {
+    /// The achievements in `list` of the game called `game`, with the ones
+    /// unlocked before read from flash. Names longer than 27 bytes don't
+    /// fit in a file name, so nothing is read or saved for them.
+    pub fn load(game: &'static str, list: &'static [Achievement]) -> Self {
+        let mut achievements = Self::new(game, list);
+        let mut bytes = [0; LEN];
+        let read = achievements
+            .file_name()
+            .and_then(|name| storage::fs().read(&name, &mut bytes));
+        match read {
+            Ok(len) => {
+                if !achievements.set_bytes(&bytes[..len]) {
+                    logging::warn!("Ignoring unreadable achievements of {}", game);
+                }
+            }
+            Err(FsError::NotFound) => {}
+            Err(e) => logging::warn!("Failed to read achievements of {}: {:?}", game, e),
+        }
+        achievements
+    }
+
+    pub fn save(&self) -> Result<(), FsError> {
+        storage::fs().write(&self.file_name()?, &self.to_bytes())
+    }
+
+    /// Unlocks `id`, saves it and announces it with a toast of its name and
+    /// a few flashes of `led`. Returns false and does nothing if it was
+    /// already unlocked or isn't in the list.
+    pub fn unlock(&mut self, id: u8, led: &mut Led) -> bool {
+        let Some(achievement) = self.set_unlocked(id) else {
+            return false;
+        };
+        if let Err(e) = self.save() {
+            logging::warn!("Failed to save achievement {}: {:?}", achievement.name, e);
+        }
+        toast::notify_toast(Toast::new(achievement.name).with_duration_ms(TOAST_DURATION_MS));
+        led.flash(FLASH_COLOR, FLASH_TIMES, FLASH_PERIOD_MS);
+        true
+    }
+
+    fn file_name(&self) -> Result<heapless::String<{ storage::MAX_NAME_LEN }>, FsError> {
+        let mut name = heapless::String::new();
+        name.push_str(FILE_PREFIX)
+            .and_then(|_| name.push_str(self.game))
+            .map_err(|_| FsError::NameTooLong)?;
+        Ok(name)
+    }
+}
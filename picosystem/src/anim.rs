@@ -0,0 +1,173 @@
+//! Sprite sheet animations.
+//!
+//! An `Animation` lists frames as rectangles of an atlas, each with its own
+//! duration. An `AnimationPlayer` advances through them by elapsed time and
+//! gives the rectangle to draw, usually with `sub_image`.
+
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Source rectangle in the atlas.
+    pub rect: Rectangle,
+    pub duration_ms: u16,
+}
+
+impl Frame {
+    pub const fn new(rect: Rectangle, duration_ms: u16) -> Self {
+        Frame { rect, duration_ms }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayMode {
+    /// Starts over after the last frame.
+    Loop,
+    /// Stops on the last frame.
+    Once,
+    /// Plays forwards then backwards, without repeating the end frames.
+    PingPong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Animation<'a> {
+    pub frames: &'a [Frame],
+    pub mode: PlayMode,
+}
+
+impl<'a> Animation<'a> {
+    pub const fn new(frames: &'a [Frame], mode: PlayMode) -> Self {
+        assert!(!frames.is_empty(), "an animation needs at least one frame");
+        Animation { frames, mode }
+    }
+
+    /// Total length of one pass through the frames.
+    pub fn duration_ms(&self) -> u32 {
+        self.frames.iter().map(|f| f.duration_ms as u32).sum()
+    }
+}
+
+/// `N` frames of `size` laid out left to right from `origin`, all lasting
+/// `duration_ms`, as in a row of a sprite sheet.
+pub const fn strip<const N: usize>(origin: Point, size: Size, duration_ms: u16) -> [Frame; N] {
+    let mut frames = [Frame::new(Rectangle::new(origin, size), duration_ms); N];
+    let mut i = 0;
+    while i < N {
+        frames[i].rect.top_left.x = origin.x + (i as u32 * size.width) as i32;
+        i += 1;
+    }
+    frames
+}
+
+/// Playback state of an animation.
+#[derive(Debug, Clone)]
+pub struct AnimationPlayer<'a> {
+    animation: &'a Animation<'a>,
+    frame: usize,
+    elapsed_ms: u32,
+    forward: bool,
+    finished: bool,
+}
+
+impl<'a> AnimationPlayer<'a> {
+    pub fn new(animation: &'a Animation<'a>) -> Self {
+        AnimationPlayer {
+            animation,
+            frame: 0,
+            elapsed_ms: 0,
+            forward: true,
+            finished: false,
+        }
+    }
+
+    pub fn animation(&self) -> &'a Animation<'a> {
+        self.animation
+    }
+
+    /// Switches to `animation` from its first frame, unless it is already
+    /// playing, so this can be called every frame with the animation wanted.
+    pub fn set_animation(&mut self, animation: &'a Animation<'a>) {
+        if !core::ptr::eq(self.animation, animation) {
+            self.animation = animation;
+            self.restart();
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.frame = 0;
+        self.elapsed_ms = 0;
+        self.forward = true;
+        self.finished = false;
+    }
+
+    /// Advances playback by `dt_ms`, skipping frames if more than one has
+    /// passed.
+    pub fn update(&mut self, dt_ms: u32) {
+        if self.finished {
+            return;
+        }
+        self.elapsed_ms += dt_ms;
+        loop {
+            // Zero length frames still show for a millisecond so a
+            // misconfigured animation can't spin here forever.
+            let duration = (self.animation.frames[self.frame].duration_ms as u32).max(1);
+            if self.elapsed_ms < duration {
+                break;
+            }
+            self.elapsed_ms -= duration;
+            self.advance();
+            if self.finished {
+                self.elapsed_ms = 0;
+                break;
+            }
+        }
+    }
+
+    fn advance(&mut self) {
+        let len = self.animation.frames.len();
+        match self.animation.mode {
+            PlayMode::Loop => self.frame = (self.frame + 1) % len,
+            PlayMode::Once => {
+                if self.frame + 1 < len {
+                    self.frame += 1;
+                } else {
+                    self.finished = true;
+                }
+            }
+            PlayMode::PingPong => {
+                if len == 1 {
+                    return;
+                }
+                if self.forward && self.frame + 1 == len {
+                    self.forward = false;
+                } else if !self.forward && self.frame == 0 {
+                    self.forward = true;
+                }
+                if self.forward {
+                    self.frame += 1;
+                } else {
+                    self.frame -= 1;
+                }
+            }
+        }
+    }
+
+    pub fn frame_index(&self) -> usize {
+        self.frame
+    }
+
+    pub fn frame(&self) -> &'a Frame {
+        &self.animation.frames[self.frame]
+    }
+
+    /// The atlas rectangle to draw now.
+    pub fn source_rect(&self) -> Rectangle {
+        self.frame().rect
+    }
+
+    /// Whether a `PlayMode::Once` animation has reached its end.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
@@ -1,70 +1,828 @@
-use embedded_hal::digital::v2::OutputPin;
-use embedded_hal::digital::v2::ToggleableOutputPin;
-use rp2040_hal::gpio::dynpin::DynPin;
-use rp_pico::hal;
+use crate::dma::{self, DmaChannel, DmaManager};
+use crate::music::{MusicPlayer, Song};
+use crate::time::{self, Alarm};
+use core::cell::UnsafeCell;
+use rp2040_hal::gpio::dynpin::{DynFunction, DynPin, DynPinMode};
 use rp_pico::hal::pac;
 use rp_pico::hal::pac::interrupt;
 
-pub struct Audio;
+pub const NUM_CHANNELS: usize = 4;
 
-pub struct StaticAudio {
-    pin: DynPin,
-    period_us: u32,
+const SAMPLE_PERIOD_US: u32 = 45;
+pub const SAMPLE_RATE: u32 = 1_000_000 / SAMPLE_PERIOD_US;
+
+// GPIO11 is PWM slice 5, output B.
+const PWM_SLICE: usize = 5;
+const PWM_TOP: u16 = 255;
+
+// The mixer fills one half of the staging buffer with PWM levels while DMA
+// sends the other to the PWM, a level each time it wraps. The halves are
+// refilled by the audio alarm, twice in the time one takes to play.
+const HALF_LEN: usize = 64;
+const STAGING_BITS: u8 = 9;
+const REFILL_PERIOD_US: u32 = SAMPLE_PERIOD_US * HALF_LEN as u32 / 2;
+
+// Aligned to its size, for the DMA to read it as a ring.
+#[repr(align(512))]
+struct Staging([[u32; HALF_LEN]; 2]);
+
+static mut STAGING: Staging = Staging([[0; HALF_LEN]; 2]);
+
+// Full gain of the music ducking in 16.16 fixed point, and how much it
+// changes per sample: about 20 ms to duck and 250 ms to come back.
+const DUCK_GAIN_MAX: u32 = 1 << 16;
+const DUCK_ATTACK_STEP: u32 = DUCK_GAIN_MAX / (SAMPLE_RATE / 50);
+const DUCK_RELEASE_STEP: u32 = DUCK_GAIN_MAX / (SAMPLE_RATE / 4);
+
+/// Volume controls, each from 0 for silence to 255 for full volume. Music
+/// is played on the channels its song uses, everything else, including
+/// samples, is a sound effect. Master scales both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Master,
+    Sfx,
+    Music,
 }
 
-static mut STATIC_AUDIO: Option<StaticAudio> = None;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Noise,
+}
 
-impl Audio {
-    pub fn new(mut pin: DynPin) -> Self {
-        pin.into_push_pull_output();
-        pin.set_low().unwrap();
-        unsafe {
-            assert!(STATIC_AUDIO.is_none());
-            STATIC_AUDIO = Some(StaticAudio { pin, period_us: 0 });
+/// A tone whose frequency and volume slide linearly from start to end over its duration.
+#[derive(Debug, Clone, Copy)]
+pub struct Sfx {
+    pub waveform: Waveform,
+    pub start_freq: u32,
+    pub end_freq: u32,
+    pub start_volume: u8,
+    pub end_volume: u8,
+    pub duration_ms: u32,
+}
+
+/// Attack, decay, sustain and release of a note's volume. Attack rises to
+/// full volume, decay falls to the sustain level, which holds until the note
+/// is released and fades out over the release time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Envelope {
+    pub attack_ms: u16,
+    pub decay_ms: u16,
+    /// Fraction of the volume in 256ths. Zero ends the note after the decay.
+    pub sustain: u8,
+    pub release_ms: u16,
+}
+
+impl Envelope {
+    /// Full volume from start to release, then silence.
+    pub const NONE: Envelope = Envelope {
+        attack_ms: 0,
+        decay_ms: 0,
+        sustain: 255,
+        release_ms: 0,
+    };
+}
+
+/// Periodic pitch wobble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vibrato {
+    /// Pitch deviation to either side in sixteenths of a semitone.
+    pub depth: u8,
+    pub rate_hz: u8,
+}
+
+impl Vibrato {
+    pub const NONE: Vibrato = Vibrato {
+        depth: 0,
+        rate_hz: 0,
+    };
+}
+
+/// A sound described by its waveform, volume and effects, for `play_note`
+/// and songs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instrument {
+    pub waveform: Waveform,
+    pub volume: u8,
+    pub envelope: Envelope,
+    pub vibrato: Vibrato,
+    /// Pitch slide in Hz per second, applied from the start of each note.
+    pub slide: i16,
+}
+
+impl Instrument {
+    pub const fn new(waveform: Waveform, volume: u8) -> Self {
+        Instrument {
+            waveform,
+            volume,
+            envelope: Envelope::NONE,
+            vibrato: Vibrato::NONE,
+            slide: 0,
+        }
+    }
+
+    pub const fn with_envelope(self, envelope: Envelope) -> Self {
+        Instrument { envelope, ..self }
+    }
+
+    pub const fn with_vibrato(self, depth: u8, rate_hz: u8) -> Self {
+        Instrument {
+            vibrato: Vibrato { depth, rate_hz },
+            ..self
+        }
+    }
+
+    pub const fn with_slide(self, slide: i16) -> Self {
+        Instrument { slide, ..self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+pub(crate) struct Channel {
+    active: bool,
+    waveform: Waveform,
+    phase: u32,
+    phase_step: u32,
+    phase_step_delta: i32,
+    // 8.16 fixed point.
+    volume: u32,
+    volume_delta: i32,
+    // None plays until stopped.
+    remaining_samples: Option<u32>,
+    noise: u16,
+    envelope: Envelope,
+    stage: Stage,
+    // Envelope level in 8.16 fixed point, its change per sample and the
+    // samples left in the stage.
+    level: u32,
+    level_delta: i32,
+    stage_samples: u32,
+    vibrato_phase: u32,
+    vibrato_phase_step: u32,
+    // Largest change to the phase step at the peak of the vibrato.
+    vibrato_amount: u32,
+}
+
+impl Channel {
+    const fn new() -> Self {
+        Channel {
+            active: false,
+            waveform: Waveform::Square,
+            phase: 0,
+            phase_step: 0,
+            phase_step_delta: 0,
+            volume: 0,
+            volume_delta: 0,
+            remaining_samples: None,
+            noise: 1,
+            envelope: Envelope::NONE,
+            stage: Stage::Sustain,
+            level: 255 << 16,
+            level_delta: 0,
+            stage_samples: 0,
+            vibrato_phase: 0,
+            vibrato_phase_step: 0,
+            vibrato_amount: 0,
+        }
+    }
+
+    pub(crate) fn start(&mut self, waveform: Waveform, freq: u32, volume: u8) {
+        self.active = true;
+        self.waveform = waveform;
+        self.phase = 0;
+        self.phase_step = phase_step(freq);
+        self.phase_step_delta = 0;
+        self.volume = (volume as u32) << 16;
+        self.volume_delta = 0;
+        self.remaining_samples = None;
+        self.envelope = Envelope::NONE;
+        self.stage = Stage::Sustain;
+        self.level = 255 << 16;
+        self.level_delta = 0;
+        self.vibrato_amount = 0;
+    }
+
+    pub(crate) fn start_instrument(&mut self, instrument: &Instrument, freq: u32) {
+        self.start(instrument.waveform, freq, instrument.volume);
+        self.envelope = instrument.envelope;
+        self.enter_stage(Stage::Attack);
+        self.phase_step_delta = slide_step(instrument.slide);
+        let vibrato = instrument.vibrato;
+        self.vibrato_phase = 0;
+        self.vibrato_phase_step = phase_step(vibrato.rate_hz as u32);
+        // A sixteenth of a semitone is close to 0.37% of the frequency.
+        self.vibrato_amount = ((self.phase_step as u64 * vibrato.depth as u64 * 3897) >> 20) as u32;
+    }
+
+    pub(crate) fn stop(&mut self) {
+        self.active = false;
+    }
+
+    /// Moves to the release stage, or stops if there is no release.
+    pub(crate) fn release(&mut self) {
+        if self.active && self.stage != Stage::Release {
+            self.enter_stage(Stage::Release);
+        }
+    }
+
+    fn enter_stage(&mut self, stage: Stage) {
+        let (ms, target) = match stage {
+            Stage::Attack => (self.envelope.attack_ms, 255),
+            Stage::Decay => (self.envelope.decay_ms, self.envelope.sustain),
+            Stage::Sustain => {
+                self.stage = stage;
+                self.level_delta = 0;
+                if self.envelope.sustain == 0 {
+                    self.active = false;
+                }
+                return;
+            }
+            Stage::Release => (self.envelope.release_ms, 0),
         };
-        Audio
+        if stage == Stage::Attack {
+            self.level = 0;
+        }
+        let samples = ms as u32 * SAMPLE_RATE / 1000;
+        if samples == 0 {
+            self.level = (target as u32) << 16;
+            self.next_stage(stage);
+            return;
+        }
+        self.stage = stage;
+        self.stage_samples = samples;
+        self.level_delta = (((target as i32) << 16) - self.level as i32) / samples as i32;
     }
 
-    pub fn start_tone(&mut self, freq: u32) {
-        let period_us = 1_000_000 / freq;
-        unsafe {
-            if STATIC_AUDIO.as_mut().unwrap().period_us == 0 {
-                start_timer(period_us);
+    fn next_stage(&mut self, stage: Stage) {
+        match stage {
+            Stage::Attack => self.enter_stage(Stage::Decay),
+            Stage::Decay => self.enter_stage(Stage::Sustain),
+            Stage::Sustain => {}
+            Stage::Release => self.active = false,
+        }
+    }
+
+    fn update_envelope(&mut self) {
+        if self.stage == Stage::Sustain {
+            return;
+        }
+        self.level = (self.level as i32 + self.level_delta).max(0) as u32;
+        self.stage_samples -= 1;
+        if self.stage_samples == 0 {
+            self.next_stage(self.stage);
+        }
+    }
+
+    pub(crate) fn set_volume(&mut self, volume: u8) {
+        self.volume = (volume as u32) << 16;
+        self.volume_delta = 0;
+    }
+
+    fn start_sfx(&mut self, sfx: &Sfx) {
+        let samples = (sfx.duration_ms * SAMPLE_RATE / 1000).max(1);
+        self.start(sfx.waveform, sfx.start_freq, sfx.start_volume);
+        self.phase_step_delta =
+            (phase_step(sfx.end_freq) as i32 - self.phase_step as i32) / samples as i32;
+        self.volume_delta = (((sfx.end_volume as i32) << 16) - self.volume as i32) / samples as i32;
+        self.remaining_samples = Some(samples);
+    }
+
+    fn next_sample(&mut self) -> i32 {
+        if !self.active {
+            return 0;
+        }
+        let previous_phase = self.phase;
+        let mut step = self.phase_step;
+        if self.vibrato_amount != 0 {
+            self.vibrato_phase = self.vibrato_phase.wrapping_add(self.vibrato_phase_step);
+            // Triangle from -32768 to 32767.
+            let lfo = if self.vibrato_phase < 0x8000_0000 {
+                (self.vibrato_phase >> 15) as i32 - 32768
+            } else {
+                32767 - ((self.vibrato_phase - 0x8000_0000) >> 15) as i32
+            };
+            let offset = (self.vibrato_amount as i64 * lfo as i64) >> 15;
+            step = (step as i64 + offset) as u32;
+        }
+        self.phase = self.phase.wrapping_add(step);
+        let value = match self.waveform {
+            Waveform::Square => {
+                if self.phase < 0x8000_0000 {
+                    127
+                } else {
+                    -128
+                }
+            }
+            Waveform::Triangle => {
+                let p = (self.phase >> 23) as i32;
+                if p < 256 {
+                    p - 128
+                } else {
+                    383 - p
+                }
+            }
+            Waveform::Sawtooth => (self.phase >> 24) as i32 - 128,
+            Waveform::Noise => {
+                // Pick a new random level once per period.
+                if self.phase < previous_phase {
+                    let bit =
+                        (self.noise ^ (self.noise >> 2) ^ (self.noise >> 3) ^ (self.noise >> 5))
+                            & 1;
+                    self.noise = (self.noise >> 1) | (bit << 15);
+                }
+                if self.noise & 1 != 0 {
+                    127
+                } else {
+                    -128
+                }
+            }
+        };
+        let volume = (self.volume >> 16) * (self.level >> 16) / 255;
+        let sample = value * volume as i32 / 256;
+        self.update_envelope();
+
+        self.phase_step = (self.phase_step as i32 + self.phase_step_delta) as u32;
+        self.volume = (self.volume as i32 + self.volume_delta) as u32;
+        if let Some(remaining_samples) = self.remaining_samples.as_mut() {
+            *remaining_samples -= 1;
+            if *remaining_samples == 0 {
+                self.active = false;
+            }
+        }
+        sample
+    }
+}
+
+/// Unsigned 8-bit mono PCM, as generated by the `sound!` macro.
+#[derive(Debug)]
+pub struct Sample {
+    pub data: &'static [u8],
+    /// Samples per second. Played back resampled to `SAMPLE_RATE`.
+    pub rate: u32,
+}
+
+/// Plays a `Sample` from flash, resampled into the mix that DMA streams to
+/// the PWM.
+struct PcmVoice {
+    sample: Option<&'static Sample>,
+    looping: bool,
+    volume: u8,
+    // The byte being played, the 16.16 fraction of the way to the next
+    // one, and how far that moves per output sample.
+    index: usize,
+    position: u32,
+    step: u32,
+}
+
+impl PcmVoice {
+    const fn new() -> Self {
+        PcmVoice {
+            sample: None,
+            looping: false,
+            volume: 0,
+            index: 0,
+            position: 0,
+            step: 0,
+        }
+    }
+
+    fn start(&mut self, sample: &'static Sample, volume: u8, looping: bool) {
+        self.sample = (!sample.data.is_empty()).then_some(sample);
+        self.looping = looping;
+        self.volume = volume;
+        self.index = 0;
+        self.position = 0;
+        self.step = ((sample.rate as u64) << 16)
+            .checked_div(SAMPLE_RATE as u64)
+            .unwrap_or(0) as u32;
+    }
+
+    fn next_sample(&mut self) -> i32 {
+        let Some(sample) = self.sample else {
+            return 0;
+        };
+        let value = sample.data[self.index] as i32 - 128;
+
+        self.position += self.step;
+        self.index += (self.position >> 16) as usize;
+        self.position &= 0xffff;
+        if self.index >= sample.data.len() {
+            if self.looping {
+                self.index %= sample.data.len();
+            } else {
+                self.sample = None;
             }
-            STATIC_AUDIO.as_mut().unwrap().period_us = period_us;
-            pac::NVIC::unmask(hal::pac::Interrupt::TIMER_IRQ_0);
         }
+        value * self.volume as i32 / 256
     }
+}
 
-    pub fn stop(&mut self) {
-        pac::NVIC::mask(hal::pac::Interrupt::TIMER_IRQ_0);
-        unsafe {
-            STATIC_AUDIO.as_mut().unwrap().period_us = 0;
+// The channel is claimed by `Audio`, which keeps it for the mixer.
+fn audio_dma_channel() -> DmaChannel {
+    unsafe { DmaChannel::new(dma::CHANNEL_AUDIO) }
+}
+
+// Starts sending `STAGING` to the PWM from its first half.
+fn start_stream() {
+    unsafe {
+        dma::start_ring_to_peripheral(
+            &mut audio_dma_channel(),
+            core::ptr::addr_of!(STAGING) as u32,
+            STAGING_BITS,
+            (*pac::PWM::PTR).ch[PWM_SLICE].cc.as_ptr() as u32,
+            dma::DREQ_PWM_WRAP0 + PWM_SLICE as u8,
+        );
+    }
+}
+
+// The half of `STAGING` the DMA is sending.
+fn playing_half() -> usize {
+    let offset = audio_dma_channel().get_src() - core::ptr::addr_of!(STAGING) as u32;
+    (offset as usize / core::mem::size_of::<[u32; HALF_LEN]>()) & 1
+}
+
+// Change of the phase step per sample for a slide of `hz_per_s`.
+fn slide_step(hz_per_s: i16) -> i32 {
+    let step = (phase_step(hz_per_s.unsigned_abs() as u32) / SAMPLE_RATE) as i32;
+    if hz_per_s < 0 {
+        -step
+    } else {
+        step
+    }
+}
+
+fn phase_step(freq: u32) -> u32 {
+    ((freq as u64) << 32)
+        .checked_div(SAMPLE_RATE as u64)
+        .unwrap_or(0) as u32
+}
+
+struct Mixer {
+    channels: [Channel; NUM_CHANNELS],
+    pcm: PcmVoice,
+    music: Option<MusicPlayer>,
+    // Channels on the music bus. They stay there after the song ends
+    // until their notes have faded out.
+    music_channels: usize,
+    master_volume: u8,
+    sfx_volume: u8,
+    music_volume: u8,
+    // Music volume while sound effects play, and the gain applied to music
+    // now, moving towards it.
+    duck_volume: u8,
+    duck_gain: u32,
+    running: bool,
+    // The half of `STAGING` to fill once the DMA has moved on from it.
+    free_half: usize,
+    // When the audio alarm next goes off.
+    next_refill_us: u32,
+}
+
+impl Mixer {
+    const fn new() -> Self {
+        Mixer {
+            channels: [
+                Channel::new(),
+                Channel::new(),
+                Channel::new(),
+                Channel::new(),
+            ],
+            pcm: PcmVoice::new(),
+            music: None,
+            music_channels: 0,
+            master_volume: 255,
+            sfx_volume: 255,
+            music_volume: 255,
+            duck_volume: 255,
+            duck_gain: DUCK_GAIN_MAX,
+            running: false,
+            free_half: 0,
+            next_refill_us: 0,
+        }
+    }
+
+    fn next_sample(&mut self) -> u16 {
+        if let Some(music) = self.music.as_mut() {
+            if !music.tick(&mut self.channels) {
+                self.music = None;
+            }
+        }
+        if self.music.is_none()
+            && !self.channels[..self.music_channels]
+                .iter()
+                .any(|c| c.active)
+        {
+            self.music_channels = 0;
+        }
+        let (music_channels, sfx_channels) = self.channels.split_at_mut(self.music_channels);
+        let sfx_active = self.pcm.sample.is_some() || sfx_channels.iter().any(|c| c.active);
+        self.duck_gain = if sfx_active {
+            let target = (self.duck_volume as u32 + 1) << 8;
+            self.duck_gain.saturating_sub(DUCK_ATTACK_STEP).max(target)
+        } else {
+            (self.duck_gain + DUCK_RELEASE_STEP).min(DUCK_GAIN_MAX)
+        };
+
+        // The sum is divided among the voices playing, so that chords and
+        // effects over music don't clip.
+        let voices = music_channels.iter().filter(|c| c.active).count()
+            + sfx_channels.iter().filter(|c| c.active).count()
+            + self.pcm.sample.is_some() as usize;
+        let music: i32 = music_channels.iter_mut().map(|c| c.next_sample()).sum();
+        let sfx: i32 = sfx_channels
+            .iter_mut()
+            .map(|c| c.next_sample())
+            .sum::<i32>()
+            + self.pcm.next_sample();
+        let music = ((music * self.duck_gain as i32) >> 16) * (self.music_volume as i32 + 1) / 256;
+        let sfx = sfx * (self.sfx_volume as i32 + 1) / 256;
+        let sum = (music + sfx) * (self.master_volume as i32 + 1) / 256 / voices.max(1) as i32;
+        (sum.clamp(-128, 127) + 128) as u16
+    }
+
+    fn is_active(&self) -> bool {
+        self.music.is_some() || self.pcm.sample.is_some() || self.channels.iter().any(|c| c.active)
+    }
+
+    // Fills `half` of `STAGING` with the next samples, as PWM levels of
+    // output B.
+    fn fill(&mut self, half: usize) {
+        let staging = unsafe { &mut (*core::ptr::addr_of_mut!(STAGING)).0[half] };
+        for level in staging.iter_mut() {
+            *level = (self.next_sample() as u32) << 16;
+        }
+    }
+
+    // Starts streaming to the PWM if it is not already running.
+    fn start(&mut self) {
+        if !self.running {
+            self.running = true;
+            self.fill(0);
+            self.fill(1);
+            self.free_half = 0;
+            start_stream();
+            self.next_refill_us = time::time_us().wrapping_add(REFILL_PERIOD_US);
+            unsafe { Alarm::new(time::ALARM_AUDIO) }.arm_at(self.next_refill_us);
         }
     }
 }
 
-unsafe fn start_timer(period_us: u32) {
-    let timer_regs = pac::TIMER::PTR;
-    (*timer_regs).inte.write(|w| {
-        w.alarm_0().set_bit();
-        w
-    });
-    let now = (*timer_regs).timerawl.read().bits();
-    (*timer_regs).alarm0.write(|w| w.bits(now + period_us));
-    (*timer_regs).intr.write(|w| {
-        w.alarm_0().set_bit();
-        w
-    });
+// Only used on core 0: by `Audio`, with interrupts disabled, and by the
+// audio alarm's interrupt, which `Mixer::start` enables on core 0. So the
+// interrupt gets the mixer without taking the spinlock of a critical
+// section every refill.
+struct MixerCell(UnsafeCell<Mixer>);
+
+unsafe impl Sync for MixerCell {}
+
+static MIXER: MixerCell = MixerCell(UnsafeCell::new(Mixer::new()));
+
+// Runs `f` with the mixer, out of the reach of `mix`.
+fn with_mixer<R>(f: impl FnOnce(&mut Mixer) -> R) -> R {
+    debug_assert_eq!(current_core(), 0, "audio is only played from core 0");
+    cortex_m::interrupt::free(|_| f(unsafe { &mut *MIXER.0.get() }))
+}
+
+fn current_core() -> usize {
+    unsafe { (*pac::SIO::PTR).cpuid.read().bits() as usize }
+}
+
+/// Software synthesizer with `NUM_CHANNELS` independent voices and a PCM
+/// sample voice, mixed a block at a time in a timer interrupt and sent by
+/// DMA to the PWM driving the piezo. Only used from core 0, which takes the
+/// interrupt.
+pub struct Audio {
+    _pwm: pac::PWM,
+    _dma_channel: DmaChannel,
+    _alarm: Alarm,
 }
 
+impl Audio {
+    pub fn new(
+        mut pin: DynPin,
+        pwm: pac::PWM,
+        resets: &mut pac::RESETS,
+        system_clock_hz: u32,
+    ) -> Self {
+        resets.reset.modify(|_, w| w.pwm().clear_bit());
+        while resets.reset_done.read().pwm().bit_is_clear() {}
+
+        // The counter wraps once per sample, which paces the DMA. The
+        // divider is in sixteenths.
+        let div =
+            (system_clock_hz as u64 * 16 / ((PWM_TOP as u64 + 1) * SAMPLE_RATE as u64)) as u32;
+        let slice = &pwm.ch[PWM_SLICE];
+        slice.top.write(|w| unsafe { w.top().bits(PWM_TOP) });
+        slice
+            .div
+            .write(|w| unsafe { w.int().bits((div / 16) as u8).frac().bits((div % 16) as u8) });
+        slice.cc.write(|w| unsafe { w.b().bits(0) });
+        slice.csr.write(|w| w.en().set_bit());
+        pin.try_into_mode(DynPinMode::Function(DynFunction::Pwm))
+            .unwrap();
+
+        Audio {
+            _pwm: pwm,
+            _dma_channel: DmaManager::claim(dma::CHANNEL_AUDIO).unwrap(),
+            _alarm: Alarm::claim(time::ALARM_AUDIO).unwrap(),
+        }
+    }
+
+    /// Plays a tone on `channel` until it is stopped.
+    pub fn play_tone(&mut self, channel: usize, waveform: Waveform, freq: u32, volume: u8) {
+        with_mixer(|mixer| {
+            mixer.channels[channel].start(waveform, freq, volume);
+            mixer.start();
+        });
+    }
+
+    /// Plays a full volume square wave on channel 0 until it is stopped.
+    pub fn start_tone(&mut self, freq: u32) {
+        self.play_tone(0, Waveform::Square, freq, 255);
+    }
+
+    /// Plays `freq` with `instrument` on `channel` until it is released.
+    pub fn play_note(&mut self, channel: usize, instrument: &Instrument, freq: u32) {
+        with_mixer(|mixer| {
+            mixer.channels[channel].start_instrument(instrument, freq);
+            mixer.start();
+        });
+    }
+
+    /// Lets the note on `channel` fade out over its instrument's release.
+    pub fn release_note(&mut self, channel: usize) {
+        with_mixer(|mixer| {
+            mixer.channels[channel].release();
+        });
+    }
+
+    /// Plays `sfx` on `channel`, replacing whatever the channel was playing.
+    pub fn play_sfx(&mut self, channel: usize, sfx: &Sfx) {
+        with_mixer(|mixer| {
+            mixer.channels[channel].start_sfx(sfx);
+            mixer.start();
+        });
+    }
+
+    pub fn is_playing(&self, channel: usize) -> bool {
+        with_mixer(|mixer| mixer.channels[channel].active)
+    }
+
+    pub fn stop_channel(&mut self, channel: usize) {
+        with_mixer(|mixer| {
+            mixer.channels[channel].active = false;
+        });
+    }
+
+    /// Plays `sample` alongside the synthesizer channels, replacing any
+    /// sample already playing. With `looping` it repeats until stopped.
+    pub fn play_sample(&mut self, sample: &'static Sample, volume: u8, looping: bool) {
+        with_mixer(|mixer| {
+            mixer.pcm.start(sample, volume, looping);
+            mixer.start();
+        });
+    }
+
+    pub fn is_sample_playing(&self) -> bool {
+        with_mixer(|mixer| mixer.pcm.sample.is_some())
+    }
+
+    pub fn stop_sample(&mut self) {
+        with_mixer(|mixer| {
+            mixer.pcm.sample = None;
+        });
+    }
+
+    /// Plays `song` using its first `Song::channels` channels, from the start
+    /// and in the background. With `looping` the song repeats until stopped.
+    pub fn play_music(&mut self, song: &'static Song, looping: bool) {
+        with_mixer(|mixer| {
+            let music = MusicPlayer::new(song, looping);
+            mixer.music_channels = music.channels();
+            mixer.music = Some(music);
+            mixer.start();
+        });
+    }
+
+    /// Overrides the tempo of the current song, in beats per minute.
+    pub fn set_music_tempo(&mut self, tempo: u8) {
+        with_mixer(|mixer| {
+            if let Some(music) = mixer.music.as_mut() {
+                music.set_tempo(tempo);
+            }
+        });
+    }
+
+    pub fn is_music_playing(&self) -> bool {
+        with_mixer(|mixer| mixer.music.is_some())
+    }
+
+    pub fn stop_music(&mut self) {
+        with_mixer(|mixer| {
+            if let Some(music) = mixer.music.take() {
+                for channel in mixer.channels.iter_mut().take(music.channels()) {
+                    channel.stop();
+                }
+            }
+            mixer.music_channels = 0;
+        });
+    }
+
+    pub fn set_volume(&mut self, bus: Bus, volume: u8) {
+        with_mixer(|mixer| match bus {
+            Bus::Master => mixer.master_volume = volume,
+            Bus::Sfx => mixer.sfx_volume = volume,
+            Bus::Music => mixer.music_volume = volume,
+        });
+    }
+
+    pub fn volume(&self, bus: Bus) -> u8 {
+        with_mixer(|mixer| match bus {
+            Bus::Master => mixer.master_volume,
+            Bus::Sfx => mixer.sfx_volume,
+            Bus::Music => mixer.music_volume,
+        })
+    }
+
+    /// Scales everything played, from 0 for silence to 255 for full volume.
+    pub fn set_master_volume(&mut self, volume: u8) {
+        self.set_volume(Bus::Master, volume);
+    }
+
+    pub fn master_volume(&self) -> u8 {
+        self.volume(Bus::Master)
+    }
+
+    /// Lowers the music to `volume` while sound effects play, fading it
+    /// down and back up. 255, the default, leaves the music alone.
+    pub fn set_ducking(&mut self, volume: u8) {
+        with_mixer(|mixer| mixer.duck_volume = volume);
+    }
+
+    /// Stops all channels and music.
+    pub fn stop(&mut self) {
+        with_mixer(|mixer| {
+            mixer.music = None;
+            mixer.music_channels = 0;
+            mixer.pcm.sample = None;
+            for channel in mixer.channels.iter_mut() {
+                channel.stop();
+            }
+        });
+    }
+}
+
+unsafe fn set_pwm_level(level: u16) {
+    (*pac::PWM::PTR).ch[PWM_SLICE]
+        .cc
+        .modify(|_, w| w.b().bits(level));
+}
+
+// Refills the half of `STAGING` the DMA has finished sending, and returns
+// false once there is nothing left to play.
+fn mix(mixer: &mut Mixer) -> bool {
+    if !mixer.is_active() {
+        mixer.running = false;
+        audio_dma_channel().abort();
+        unsafe { set_pwm_level(0) };
+        return false;
+    }
+    if audio_dma_channel().get_count() == 0 {
+        start_stream();
+    }
+    if playing_half() != mixer.free_half {
+        mixer.fill(mixer.free_half);
+        mixer.free_half ^= 1;
+    }
+    true
+}
+
+// The audio alarm's interrupt. It is handled here rather than by `time`, so
+// that refills don't take the critical sections of its shared alarm table.
 #[allow(non_snake_case)]
 #[interrupt]
 fn TIMER_IRQ_0() {
-    unsafe {
-        if let Some(s) = STATIC_AUDIO.as_mut() {
-            s.pin.toggle().unwrap();
-            start_timer(s.period_us);
-        }
+    let mut alarm = unsafe { Alarm::new(time::ALARM_AUDIO) };
+    alarm.disarm();
+    let mixer = unsafe { &mut *MIXER.0.get() };
+    if !mix(mixer) {
+        return;
+    }
+    // After a late refill the next one is a period later, rather than the
+    // missed ones being made up.
+    mixer.next_refill_us = mixer.next_refill_us.wrapping_add(REFILL_PERIOD_US);
+    let now = time::time_us();
+    if (mixer.next_refill_us.wrapping_sub(now) as i32) <= 0 {
+        mixer.next_refill_us = now.wrapping_add(REFILL_PERIOD_US);
     }
+    alarm.arm_at(mixer.next_refill_us);
 }
@@ -0,0 +1,758 @@
+use crate::display::{framebuffer, screen, Display, WIDTH};
+use crate::dma::{self, DmaChannel};
+use crate::interp::InterpManager;
+use crate::rom_math;
+use crate::sprite::Sprite;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use picosystem_macros::ram_code;
+
+// Shorter runs of visible pixels are cheaper to copy with the CPU.
+const MIN_DMA_RUN: u32 = 3;
+
+/// Mirroring of the source area of a blit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flip {
+    pub horizontal: bool,
+    pub vertical: bool,
+}
+
+impl Flip {
+    pub const NONE: Flip = Flip {
+        horizontal: false,
+        vertical: false,
+    };
+    pub const HORIZONTAL: Flip = Flip {
+        horizontal: true,
+        vertical: false,
+    };
+    pub const VERTICAL: Flip = Flip {
+        horizontal: false,
+        vertical: true,
+    };
+    pub const BOTH: Flip = Flip {
+        horizontal: true,
+        vertical: true,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rotation {
+    Deg90,
+    Deg180,
+    Deg270,
+    /// Radians, sampled with fixed point math.
+    Angle(f32),
+}
+
+impl Rotation {
+    // Cosine and sine in 16.16 fixed point, exact for quarter turns.
+    fn cos_sin(self) -> (i32, i32) {
+        const ONE: i32 = 1 << 16;
+        match self {
+            Rotation::Deg90 => (0, ONE),
+            Rotation::Deg180 => (-ONE, 0),
+            Rotation::Deg270 => (0, -ONE),
+            Rotation::Angle(angle) => {
+                let (sin, cos) = rom_math::sin_cos(angle);
+                ((cos * ONE as f32) as i32, (sin * ONE as f32) as i32)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Transparency<'a> {
+    Opaque,
+    /// Pixels of this RGB565 color are not drawn.
+    ColorKey(u16),
+    /// One bit per pixel, set for visible pixels. Each row starts on a new
+    /// word with the first pixel in the low bit, like tile masks.
+    Mask(&'a [u32]),
+    /// Four bits of opacity per pixel, from 0 for invisible to 15 for
+    /// opaque, blended with the screen. Each row starts on a new byte with
+    /// the first pixel in the low bits.
+    Alpha(&'a [u8]),
+}
+
+/// How `blit_blended` mixes an image with what is already on the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Blend {
+    /// The average of the two, for shadows and ghosts.
+    Half,
+    /// The image over the screen with an opacity from 0 to 15, times the
+    /// opacity of each pixel if the image has an alpha channel.
+    Alpha(u8),
+    /// The sum of the two, saturating at white, for glows and lasers.
+    Add,
+    /// The product of the two, darkening the screen where the image is
+    /// dark, for lighting and shadows.
+    Multiply,
+}
+
+/// RGB565 pixels in rows of `width`.
+#[derive(Debug, Clone, Copy)]
+pub struct Image<'a> {
+    pub data: &'a [u16],
+    pub width: u32,
+    /// Set when `data` is stored big-endian like the framebuffer and tiles.
+    pub big_endian: bool,
+    pub transparency: Transparency<'a>,
+}
+
+impl<'a> Image<'a> {
+    pub fn height(&self) -> u32 {
+        self.data.len() as u32 / self.width
+    }
+
+    pub fn bounding_box(&self) -> Rectangle {
+        Rectangle::new(Point::zero(), Size::new(self.width, self.height()))
+    }
+
+    // Pixel at `index` in framebuffer byte order.
+    fn framebuffer_color(&self, index: usize) -> u16 {
+        if self.big_endian {
+            self.data[index]
+        } else {
+            self.data[index].to_be()
+        }
+    }
+
+    // Pixel at `index` in native byte order, for blending.
+    fn color(&self, index: usize) -> u16 {
+        if self.big_endian {
+            u16::from_be(self.data[index])
+        } else {
+            self.data[index]
+        }
+    }
+
+    // Opacity of pixel (x, y) from 0 to 15.
+    fn alpha(&self, x: u32, y: u32) -> u8 {
+        match self.transparency {
+            Transparency::Alpha(alpha) => {
+                let byte = alpha[(y * self.width.div_ceil(2) + x / 2) as usize];
+                (byte >> ((x & 1) * 4)) & 0xf
+            }
+            _ if self.run(x, y, 1).0 => 15,
+            _ => 0,
+        }
+    }
+
+    // Draws pixel (x, y), which is at `index` in `data`, over the
+    // framebuffer pixel `dst`.
+    pub(crate) fn draw_pixel(&self, x: u32, y: u32, index: usize, dst: &mut u16) {
+        match self.alpha(x, y) {
+            0 => {}
+            15 => *dst = self.framebuffer_color(index),
+            alpha => *dst = mix(self.color(index), u16::from_be(*dst), alpha5(alpha)).to_be(),
+        }
+    }
+
+    /// Length of the run of pixels from `x` in row `y` that are all visible
+    /// or all transparent, at most `max`. With an alpha channel only opaque
+    /// pixels count as visible.
+    fn run(&self, x: u32, y: u32, max: u32) -> (bool, u32) {
+        match self.transparency {
+            Transparency::Opaque => (true, max),
+            Transparency::ColorKey(key) => {
+                let row = (y * self.width) as usize;
+                let color = |x: u32| {
+                    let color = self.data[row + x as usize];
+                    if self.big_endian {
+                        u16::from_be(color)
+                    } else {
+                        color
+                    }
+                };
+                let visible = color(x) != key;
+                let n = (x..x + max)
+                    .take_while(|&x| (color(x) != key) == visible)
+                    .count();
+                (visible, n as u32)
+            }
+            Transparency::Mask(mask) => {
+                let words_per_row = self.width.div_ceil(32);
+                let bits = mask[(y * words_per_row + x / 32) as usize] >> (x % 32);
+                let visible = bits & 1 != 0;
+                let n = if visible {
+                    bits.trailing_ones()
+                } else {
+                    bits.trailing_zeros()
+                };
+                (visible, n.min(32 - x % 32).min(max))
+            }
+            Transparency::Alpha(_) => {
+                let visible = self.alpha(x, y) == 15;
+                let n = (x..x + max)
+                    .take_while(|&x| (self.alpha(x, y) == 15) == visible)
+                    .count();
+                (visible, n as u32)
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a Sprite<'a>> for Image<'a> {
+    fn from(sprite: &'a Sprite<'a>) -> Self {
+        Image {
+            data: sprite.data,
+            width: sprite.size.width,
+            big_endian: false,
+            transparency: match (sprite.alpha, sprite.transparent_color) {
+                (Some(alpha), _) => Transparency::Alpha(alpha),
+                (None, Some(color)) => Transparency::ColorKey(color),
+                (None, None) => Transparency::Opaque,
+            },
+        }
+    }
+}
+
+/// Draws the `src` area of `image` with its top left corner at `dst`,
+/// clipped to the screen. Returns true if nothing was clipped.
+pub fn blit(display: &mut Display, image: &Image, src: &Rectangle, dst: Point, flip: Flip) -> bool {
+    match blit_dma(display.draw_dma_channel(), image, src, dst, flip) {
+        Some(area) => {
+            display.mark_dirty(area);
+            area.size == src.intersection(&image.bounding_box()).size
+        }
+        None => false,
+    }
+}
+
+/// Like `blit` but uses `dma_channel` and leaves marking the returned
+/// screen area dirty to the caller, for renderers drawing many images.
+#[ram_code]
+pub fn blit_dma(
+    dma_channel: &mut DmaChannel,
+    image: &Image,
+    src: &Rectangle,
+    dst: Point,
+    flip: Flip,
+) -> Option<Rectangle> {
+    let src = src.intersection(&image.bounding_box());
+    let clipped = Rectangle::new(dst, src.size).intersection(&screen());
+    if clipped.is_zero_sized() {
+        return None;
+    }
+
+    let fb = framebuffer();
+    let offset = clipped.top_left - dst;
+    let width = clipped.size.width;
+    for y in 0..clipped.size.height as i32 {
+        let src_y = if flip.vertical {
+            src.top_left.y + src.size.height as i32 - 1 - (offset.y + y)
+        } else {
+            src.top_left.y + offset.y + y
+        } as u32;
+        let src_row = (src_y * image.width) as usize;
+        let dst_row = (clipped.top_left.y + y) as usize * WIDTH + clipped.top_left.x as usize;
+
+        if flip.horizontal {
+            // DMA can only copy forwards.
+            let src_end = src.top_left.x + src.size.width as i32 - 1 - offset.x;
+            for x in 0..width as usize {
+                let src_x = (src_end - x as i32) as u32;
+                image.draw_pixel(src_x, src_y, src_row + src_x as usize, &mut fb[dst_row + x]);
+            }
+            continue;
+        }
+
+        let src_x = (src.top_left.x + offset.x) as u32;
+        let mut x = 0;
+        while x < width {
+            let (visible, n) = image.run(src_x + x, src_y, width - x);
+            if visible {
+                let src_index = src_row + (src_x + x) as usize;
+                let dst_index = dst_row + x as usize;
+                if n >= MIN_DMA_RUN {
+                    unsafe {
+                        copy_run(
+                            dma_channel,
+                            image,
+                            image.data.as_ptr().add(src_index),
+                            fb.as_mut_ptr().add(dst_index),
+                            n,
+                        );
+                    }
+                } else {
+                    for i in 0..n as usize {
+                        fb[dst_index + i] = image.framebuffer_color(src_index + i);
+                    }
+                }
+            } else if let Transparency::Alpha(_) = image.transparency {
+                for i in 0..n {
+                    let src_x = src_x + x + i;
+                    let dst = &mut fb[dst_row + (x + i) as usize];
+                    image.draw_pixel(src_x, src_y, src_row + src_x as usize, dst);
+                }
+            }
+            x += n;
+        }
+    }
+    dma_channel.wait();
+    Some(clipped)
+}
+
+#[ram_code]
+unsafe fn copy_run(
+    dma_channel: &mut DmaChannel,
+    image: &Image,
+    src: *const u16,
+    dst: *mut u16,
+    count: u32,
+) {
+    dma_channel.wait();
+    if !image.big_endian {
+        dma::start_copy_mem_bswap(dma_channel, src as u32, dst as u32, 2, count);
+    } else if count.is_multiple_of(2) && (src as u32 | dst as u32).is_multiple_of(4) {
+        dma::start_copy_mem(dma_channel, src as u32, dst as u32, 4, count / 2);
+    } else {
+        dma::start_copy_mem(dma_channel, src as u32, dst as u32, 2, count);
+    }
+}
+
+/// Draws the `src` area of `image` enlarged `scale` times, with its top left
+/// corner at `dst`. Rows repeated by the scaling are copied by DMA for opaque
+/// images, and source columns are stepped through by the interpolator.
+#[ram_code]
+pub fn blit_scaled(
+    display: &mut Display,
+    image: &Image,
+    src: &Rectangle,
+    dst: Point,
+    scale: u32,
+    flip: Flip,
+) {
+    let src = src.intersection(&image.bounding_box());
+    let clipped = Rectangle::new(dst, src.size * scale).intersection(&screen());
+    if clipped.is_zero_sized() {
+        return;
+    }
+    display.mark_dirty(clipped);
+
+    let mut dma_channel = match image.transparency {
+        Transparency::Opaque => Some(display.draw_dma_channel()),
+        _ => None,
+    };
+    let fb = framebuffer();
+    let offset = clipped.top_left - dst;
+
+    // The interpolator steps through source columns in 16.16 fixed point,
+    // saving a division per pixel, unless code this interrupted holds it.
+    // Rounding the step up keeps the integer part exact for any row that
+    // fits on the screen.
+    let step = 0x10000_u32.div_ceil(scale);
+    let skipped = offset.x as u32 * step;
+    let (start, step) = if flip.horizontal {
+        // Counting down from the last column's last fraction floors the
+        // same way as counting up.
+        (
+            ((src.size.width - 1) << 16 | 0xffff) - skipped,
+            -(step as i32),
+        )
+    } else {
+        (skipped, step as i32)
+    };
+    let mut interp = InterpManager::claim(0).ok();
+    if let Some(interp) = interp.as_mut() {
+        interp.configure_stepper(16, start, step, src.top_left.x as u32);
+    }
+
+    for y in 0..clipped.size.height as i32 {
+        let dst_row = (clipped.top_left.y + y) as usize * WIDTH + clipped.top_left.x as usize;
+        if let Some(dma_channel) = dma_channel.as_deref_mut() {
+            if y > 0 && !((offset.y + y) as u32).is_multiple_of(scale) {
+                unsafe {
+                    dma_channel.wait();
+                    dma::start_copy_mem(
+                        dma_channel,
+                        fb.as_ptr().add(dst_row - WIDTH) as u32,
+                        fb.as_mut_ptr().add(dst_row) as u32,
+                        2,
+                        clipped.size.width,
+                    );
+                }
+                continue;
+            }
+            // The previous row must be complete before it is overwritten.
+            dma_channel.wait();
+        }
+
+        let src_y = flip_coord(
+            src.top_left.y,
+            src.size.height,
+            (offset.y + y) as u32 / scale,
+            flip.vertical,
+        );
+        let src_row = (src_y * image.width) as usize;
+        if let Some(interp) = interp.as_mut() {
+            interp.restart_stepper(start, src.top_left.x as u32);
+            for x in 0..clipped.size.width {
+                let src_x = interp.pop_full();
+                let dst = &mut fb[dst_row + x as usize];
+                image.draw_pixel(src_x, src_y, src_row + src_x as usize, dst);
+            }
+            continue;
+        }
+        for x in 0..clipped.size.width {
+            let src_x = flip_coord(
+                src.top_left.x,
+                src.size.width,
+                (offset.x as u32 + x) / scale,
+                flip.horizontal,
+            );
+            let dst = &mut fb[dst_row + x as usize];
+            image.draw_pixel(src_x, src_y, src_row + src_x as usize, dst);
+        }
+    }
+    if let Some(dma_channel) = dma_channel {
+        dma_channel.wait();
+    }
+}
+
+/// Draws the `src` area of `image` with its top left corner at `dst`,
+/// mixed with the screen by `blend`.
+#[ram_code]
+pub fn blit_blended(
+    display: &mut Display,
+    image: &Image,
+    src: &Rectangle,
+    dst: Point,
+    flip: Flip,
+    blend: Blend,
+) {
+    let src = src.intersection(&image.bounding_box());
+    let clipped = Rectangle::new(dst, src.size).intersection(&screen());
+    if clipped.is_zero_sized() {
+        return;
+    }
+    display.mark_dirty(clipped);
+
+    let fb = framebuffer();
+    let offset = clipped.top_left - dst;
+    for y in 0..clipped.size.height {
+        let src_y = flip_coord(
+            src.top_left.y,
+            src.size.height,
+            offset.y as u32 + y,
+            flip.vertical,
+        );
+        let src_row = (src_y * image.width) as usize;
+        let dst_row = (clipped.top_left.y as u32 + y) as usize * WIDTH;
+        for x in 0..clipped.size.width {
+            let src_x = flip_coord(
+                src.top_left.x,
+                src.size.width,
+                offset.x as u32 + x,
+                flip.horizontal,
+            );
+            let alpha = image.alpha(src_x, src_y);
+            if alpha == 0 {
+                continue;
+            }
+            let pixel = &mut fb[dst_row + (clipped.top_left.x as u32 + x) as usize];
+            let color = image.color(src_row + src_x as usize);
+            let background = u16::from_be(*pixel);
+            let blended = match blend {
+                Blend::Half if alpha == 15 => half(color, background),
+                Blend::Half => mix(color, background, alpha5(alpha) / 2),
+                Blend::Alpha(opacity) => mix(
+                    color,
+                    background,
+                    (alpha5(alpha) * alpha5(opacity.min(15))) >> 5,
+                ),
+                Blend::Add => fade(add(color, background), background, alpha),
+                Blend::Multiply => fade(multiply(color, background), background, alpha),
+            };
+            *pixel = blended.to_be();
+        }
+    }
+}
+
+/// Widths of the borders of a nine-patch image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Insets {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl Insets {
+    pub const fn uniform(width: u32) -> Self {
+        Insets {
+            left: width,
+            top: width,
+            right: width,
+            bottom: width,
+        }
+    }
+}
+
+/// Fills `dst` with copies of the `src` area of `image`, starting from its
+/// top left corner. For opaque images only the first copy in each row is
+/// drawn, the rest of the row and the rows below are copied by DMA.
+pub fn tiled_fill(display: &mut Display, image: &Image, src: &Rectangle, dst: &Rectangle) {
+    let dma_channel = match image.transparency {
+        Transparency::Opaque => Some(display.draw_dma_channel()),
+        _ => None,
+    };
+    if let Some(area) = tile(dma_channel, image, src, dst) {
+        display.mark_dirty(area);
+    }
+}
+
+/// Draws `image` as a frame filling `dst`, for dialog boxes, buttons and
+/// bars of any size. The corners inside `insets` are drawn as they are,
+/// the edges between them are repeated along their length and the center
+/// is repeated both ways, like `tiled_fill`.
+pub fn nine_patch(display: &mut Display, image: &Image, insets: Insets, dst: &Rectangle) {
+    let size = image.bounding_box().size;
+    // Offsets and lengths of the three columns and rows, in the image and
+    // on the screen. If `dst` is smaller than the corners they overlap.
+    let columns = [
+        (0, insets.left, 0, insets.left),
+        (
+            insets.left,
+            size.width.saturating_sub(insets.left + insets.right),
+            insets.left,
+            dst.size.width.saturating_sub(insets.left + insets.right),
+        ),
+        (
+            size.width.saturating_sub(insets.right),
+            insets.right,
+            dst.size.width.saturating_sub(insets.right),
+            insets.right,
+        ),
+    ];
+    let rows = [
+        (0, insets.top, 0, insets.top),
+        (
+            insets.top,
+            size.height.saturating_sub(insets.top + insets.bottom),
+            insets.top,
+            dst.size.height.saturating_sub(insets.top + insets.bottom),
+        ),
+        (
+            size.height.saturating_sub(insets.bottom),
+            insets.bottom,
+            dst.size.height.saturating_sub(insets.bottom),
+            insets.bottom,
+        ),
+    ];
+
+    let mut dma_channel = match image.transparency {
+        Transparency::Opaque => Some(display.draw_dma_channel()),
+        _ => None,
+    };
+    for &(src_y, src_height, dst_y, dst_height) in &rows {
+        for &(src_x, src_width, dst_x, dst_width) in &columns {
+            let src = Rectangle::new(
+                Point::new(src_x as i32, src_y as i32),
+                Size::new(src_width, src_height),
+            );
+            let part = Rectangle::new(
+                dst.top_left + Point::new(dst_x as i32, dst_y as i32),
+                Size::new(dst_width, dst_height),
+            );
+            tile(dma_channel.as_deref_mut(), image, &src, &part);
+        }
+    }
+    let clipped = dst.intersection(&screen());
+    if !clipped.is_zero_sized() {
+        display.mark_dirty(clipped);
+    }
+}
+
+// Fills `dst` with copies of `src` and returns the screen area drawn. With
+// a DMA channel the image must be opaque, as drawn pixels are copied.
+fn tile(
+    mut dma_channel: Option<&mut DmaChannel>,
+    image: &Image,
+    src: &Rectangle,
+    dst: &Rectangle,
+) -> Option<Rectangle> {
+    let src = src.intersection(&image.bounding_box());
+    let clipped = dst.intersection(&screen());
+    if src.is_zero_sized() || clipped.is_zero_sized() {
+        return None;
+    }
+
+    let fb = framebuffer();
+    let offset = clipped.top_left - dst.top_left;
+    let (tile_width, tile_height) = (src.size.width, src.size.height);
+    let width = clipped.size.width;
+    let first_x = offset.x as u32 % tile_width;
+    for y in 0..clipped.size.height {
+        let dst_row =
+            (clipped.top_left.y as u32 + y) as usize * WIDTH + clipped.top_left.x as usize;
+        if let Some(dma_channel) = dma_channel.as_deref_mut() {
+            if y >= tile_height {
+                // Same as the row a tile higher up.
+                unsafe {
+                    dma_channel.wait();
+                    dma::start_copy_mem(
+                        dma_channel,
+                        fb.as_ptr().add(dst_row - tile_height as usize * WIDTH) as u32,
+                        fb.as_mut_ptr().add(dst_row) as u32,
+                        2,
+                        width,
+                    );
+                }
+                continue;
+            }
+        }
+
+        let src_y = src.top_left.y as u32 + (offset.y as u32 + y) % tile_height;
+        let src_row = (src_y * image.width) as usize;
+        let drawn = if dma_channel.is_some() {
+            width.min(tile_width)
+        } else {
+            width
+        };
+        let mut tile_x = first_x;
+        for x in 0..drawn as usize {
+            let src_x = src.top_left.x as u32 + tile_x;
+            image.draw_pixel(src_x, src_y, src_row + src_x as usize, &mut fb[dst_row + x]);
+            tile_x += 1;
+            if tile_x == tile_width {
+                tile_x = 0;
+            }
+        }
+
+        if let Some(dma_channel) = dma_channel.as_deref_mut() {
+            // Doubles the part of the row drawn until the row is full.
+            // Copies never overlap, as DMA may read ahead of its writes.
+            let mut done = drawn;
+            while done < width {
+                let count = done.min(width - done);
+                unsafe {
+                    dma_channel.wait();
+                    dma::start_copy_mem(
+                        dma_channel,
+                        fb.as_ptr().add(dst_row) as u32,
+                        fb.as_mut_ptr().add(dst_row + done as usize) as u32,
+                        2,
+                        count,
+                    );
+                }
+                done += count;
+            }
+            dma_channel.wait();
+        }
+    }
+    if let Some(dma_channel) = dma_channel {
+        dma_channel.wait();
+    }
+    Some(clipped)
+}
+
+/// Draws the `src` area of `image` rotated clockwise around its center,
+/// which is placed at `center`.
+#[ram_code]
+pub fn blit_rotated(
+    display: &mut Display,
+    image: &Image,
+    src: &Rectangle,
+    center: Point,
+    rotation: Rotation,
+) {
+    let src = src.intersection(&image.bounding_box());
+    let (cos, sin) = rotation.cos_sin();
+    let (width, height) = (src.size.width as i32, src.size.height as i32);
+
+    // Bounding box of the rotated area, with a pixel of margin for rounding.
+    let extent_x = ((cos.abs() * width + sin.abs() * height) >> 16) / 2 + 1;
+    let extent_y = ((sin.abs() * width + cos.abs() * height) >> 16) / 2 + 1;
+    let area = Rectangle::new(
+        center - Point::new(extent_x, extent_y),
+        Size::new(2 * extent_x as u32, 2 * extent_y as u32),
+    );
+    let clipped = area.intersection(&screen());
+    if clipped.is_zero_sized() {
+        return;
+    }
+    display.mark_dirty(clipped);
+
+    // Source coordinates of destination pixel centers in 16.16 fixed point,
+    // relative to the top left of `src`.
+    let fb = framebuffer();
+    let dx = (((clipped.top_left.x - center.x) as i64) << 16) + 0x8000;
+    for y in 0..clipped.size.height as i32 {
+        let dy = (((clipped.top_left.y + y - center.y) as i64) << 16) + 0x8000;
+        let mut u = ((width << 16) / 2) as i64 + ((dx * cos as i64 + dy * sin as i64) >> 16);
+        let mut v = ((height << 16) / 2) as i64 + ((dy * cos as i64 - dx * sin as i64) >> 16);
+        let dst_row = (clipped.top_left.y + y) as usize * WIDTH + clipped.top_left.x as usize;
+        for x in 0..clipped.size.width as usize {
+            let (src_x, src_y) = ((u >> 16) as i32, (v >> 16) as i32);
+            if (0..width).contains(&src_x) && (0..height).contains(&src_y) {
+                let src_x = (src.top_left.x + src_x) as u32;
+                let src_y = (src.top_left.y + src_y) as u32;
+                let index = (src_y * image.width + src_x) as usize;
+                image.draw_pixel(src_x, src_y, index, &mut fb[dst_row + x]);
+            }
+            u += cos as i64;
+            v -= sin as i64;
+        }
+    }
+}
+
+// The average of two RGB565 colors, without the low bit of each channel
+// carrying into the next.
+fn half(a: u16, b: u16) -> u16 {
+    (a & b) + (((a ^ b) & 0xf7de) >> 1)
+}
+
+// `fg` over `bg` with `alpha` from 0 to 32. The channels are spread over a
+// word with gaps wide enough for their products, so all three are blended
+// with one multiplication.
+fn mix(fg: u16, bg: u16, alpha: u32) -> u16 {
+    const MASK: u32 = 0x07e0_f81f;
+    let fg = (fg as u32 | (fg as u32) << 16) & MASK;
+    let bg = (bg as u32 | (bg as u32) << 16) & MASK;
+    let mixed = (bg.wrapping_add(fg.wrapping_sub(bg).wrapping_mul(alpha) >> 5)) & MASK;
+    (mixed | mixed >> 16) as u16
+}
+
+// Each channel of `a` plus `b`, saturating.
+fn add(a: u16, b: u16) -> u16 {
+    let red = ((a >> 11) + (b >> 11)).min(0x1f);
+    let green = (((a >> 5) & 0x3f) + ((b >> 5) & 0x3f)).min(0x3f);
+    let blue = ((a & 0x1f) + (b & 0x1f)).min(0x1f);
+    red << 11 | green << 5 | blue
+}
+
+// Each channel of `a` times `b`, as fractions of full brightness. Adding one
+// to `b` keeps full brightness exact without dividing.
+fn multiply(a: u16, b: u16) -> u16 {
+    let red = ((a >> 11) * ((b >> 11) + 1)) >> 5;
+    let green = (((a >> 5) & 0x3f) * (((b >> 5) & 0x3f) + 1)) >> 6;
+    let blue = ((a & 0x1f) * ((b & 0x1f) + 1)) >> 5;
+    red << 11 | green << 5 | blue
+}
+
+// `effect` over `bg` with the opacity of the image pixel it came from.
+fn fade(effect: u16, bg: u16, alpha: u8) -> u16 {
+    if alpha == 15 {
+        effect
+    } else {
+        mix(effect, bg, alpha5(alpha))
+    }
+}
+
+// Four bit opacity scaled to 0 to 32.
+fn alpha5(alpha: u8) -> u32 {
+    let alpha = alpha as u32;
+    (alpha << 1) + ((alpha + 7) >> 3)
+}
+
+// Coordinate in the image of the pixel `offset` pixels into an area of
+// `size` pixels starting at `start`.
+fn flip_coord(start: i32, size: u32, offset: u32, flip: bool) -> u32 {
+    if flip {
+        start as u32 + size - 1 - offset
+    } else {
+        start as u32 + offset
+    }
+}
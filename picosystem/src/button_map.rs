@@ -0,0 +1,168 @@
+//! Which button does what, so that players can choose their own buttons
+//! and hold the console the other way up.
+//!
+//! Games read the buttons through an `Action`, such as `Action::Jump`, with
+//! `Input::is_action_held` or `Input::action_button`, rather than through a
+//! `ButtonId`. The `ButtonMap` of `Input` decides which button each action
+//! is. `Hardware::new` sets it from the settings, where a settings screen
+//! keeps the player's choice with `ButtonMap::bind`.
+//!
+//! Turning the console turns the d-pad and the face buttons with it, so a
+//! game shown sideways sets `ButtonMap::rotated` with the quarter turns of
+//! its orientation, and up on the d-pad stays up on the screen.
+
+use crate::input::ButtonId;
+
+pub const NUM_ACTIONS: usize = 8;
+
+/// What a game uses a button for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Left,
+    Right,
+    Up,
+    Down,
+    Jump,
+    Fire,
+    Special,
+    Menu,
+}
+
+pub const ACTIONS: [Action; NUM_ACTIONS] = [
+    Action::Left,
+    Action::Right,
+    Action::Up,
+    Action::Down,
+    Action::Jump,
+    Action::Fire,
+    Action::Special,
+    Action::Menu,
+];
+
+// In the order of their bits in a `ButtonMask`.
+const BUTTONS: [ButtonId; 8] = [
+    ButtonId::DpadLeft,
+    ButtonId::DpadRight,
+    ButtonId::DpadUp,
+    ButtonId::DpadDown,
+    ButtonId::X,
+    ButtonId::Y,
+    ButtonId::A,
+    ButtonId::B,
+];
+
+// The d-pad and the face buttons, clockwise from the top.
+const DPAD: [ButtonId; 4] = [
+    ButtonId::DpadUp,
+    ButtonId::DpadRight,
+    ButtonId::DpadDown,
+    ButtonId::DpadLeft,
+];
+const FACE: [ButtonId; 4] = [ButtonId::X, ButtonId::A, ButtonId::B, ButtonId::Y];
+
+/// Which hand holds the d-pad. Left-handed players hold the console upside
+/// down, with the d-pad on the right, and the picture and the buttons are
+/// turned to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Handedness {
+    #[default]
+    Right,
+    Left,
+}
+
+impl Handedness {
+    /// How far the console is turned clockwise, for `ButtonMap::rotated`.
+    pub fn quarter_turns(self) -> u8 {
+        match self {
+            Handedness::Right => 0,
+            Handedness::Left => 2,
+        }
+    }
+}
+
+/// The button of each action. Each button does one action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonMap {
+    buttons: [ButtonId; NUM_ACTIONS],
+}
+
+impl Default for ButtonMap {
+    fn default() -> Self {
+        ButtonMap::new()
+    }
+}
+
+impl ButtonMap {
+    /// The d-pad for the directions, A to jump, B to fire, X for the
+    /// special move and Y for the menu.
+    pub const fn new() -> Self {
+        ButtonMap {
+            buttons: [
+                ButtonId::DpadLeft,
+                ButtonId::DpadRight,
+                ButtonId::DpadUp,
+                ButtonId::DpadDown,
+                ButtonId::A,
+                ButtonId::B,
+                ButtonId::X,
+                ButtonId::Y,
+            ],
+        }
+    }
+
+    pub fn button(&self, action: Action) -> ButtonId {
+        self.buttons[action as usize]
+    }
+
+    pub fn action(&self, button: ButtonId) -> Option<Action> {
+        ACTIONS
+            .into_iter()
+            .find(|&action| self.button(action) == button)
+    }
+
+    /// Makes `button` do `action`. The action that `button` did takes the
+    /// button `action` had, so no button is left doing two.
+    pub fn bind(&mut self, action: Action, button: ButtonId) {
+        if let Some(other) = self.action(button) {
+            self.buttons[other as usize] = self.button(action);
+        }
+        self.buttons[action as usize] = button;
+    }
+
+    /// The map for holding the console turned `quarter_turns` clockwise
+    /// from upright, with each action on the button now where its button
+    /// was. `display::Orientation::quarter_turns` gives the turns of an
+    /// orientation.
+    pub fn rotated(&self, quarter_turns: u8) -> Self {
+        let turn = |button: ButtonId| {
+            for ring in [&DPAD, &FACE] {
+                if let Some(at) = ring.iter().position(|&b| b == button) {
+                    return ring[(at + 4 - quarter_turns as usize % 4) % 4];
+                }
+            }
+            button
+        };
+        ButtonMap {
+            buttons: self.buttons.map(turn),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; NUM_ACTIONS] {
+        self.buttons.map(|button| button as u8)
+    }
+
+    /// Returns `None` unless `bytes` give each action a different button.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; NUM_ACTIONS] = bytes.try_into().ok()?;
+        let mut seen = 0u8;
+        for &byte in bytes {
+            if byte as usize >= BUTTONS.len() || seen & 1 << byte != 0 {
+                return None;
+            }
+            seen |= 1 << byte;
+        }
+        Some(ButtonMap {
+            buttons: bytes.map(|byte| BUTTONS[byte as usize]),
+        })
+    }
+}
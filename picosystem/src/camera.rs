@@ -0,0 +1,190 @@
+//! A camera owning the view position.
+//!
+//! The camera follows a target with optional smoothing and a dead zone the
+//! target can move in without the view moving, keeps the view inside the
+//! world bounds and adds screen shake. Games pass `position()` to the tile
+//! renderer and draw sprites at `to_screen(world_position)`.
+
+use crate::math::{Vector2, I16F16};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+pub struct Camera {
+    // Center of the view before shaking, in fixed point so that smoothing
+    // can move it by less than a pixel a frame.
+    center: Vector2,
+    view_size: Size,
+    smoothing: I16F16,
+    dead_zone: Size,
+    bounds: Option<Rectangle>,
+    shake_amplitude: i32,
+    shake_frames: u32,
+    shake_frames_left: u32,
+    shake_offset: Point,
+    rng: oorandom::Rand32,
+}
+
+impl Camera {
+    /// A camera with a view of `view_size`, usually the screen size, that
+    /// snaps to its target.
+    pub fn new(view_size: Size) -> Self {
+        Camera {
+            center: Vector2::new(I16F16::ZERO, I16F16::ZERO),
+            view_size,
+            smoothing: I16F16::ONE,
+            dead_zone: Size::zero(),
+            bounds: None,
+            shake_amplitude: 0,
+            shake_frames: 0,
+            shake_frames_left: 0,
+            shake_offset: Point::zero(),
+            rng: oorandom::Rand32::new(0x5eed),
+        }
+    }
+
+    /// Fraction of the distance to the target covered each frame. One snaps
+    /// to the target, smaller values lag behind it.
+    pub fn set_smoothing(&mut self, smoothing: I16F16) {
+        self.smoothing = smoothing;
+    }
+
+    /// Size of the area around the center the target can move in without
+    /// the camera following.
+    pub fn set_dead_zone(&mut self, dead_zone: Size) {
+        self.dead_zone = dead_zone;
+    }
+
+    /// Keeps the view inside `bounds`, usually `Map::bounds`. Bounds smaller
+    /// than the view are centered.
+    pub fn set_bounds(&mut self, bounds: Option<Rectangle>) {
+        self.bounds = bounds;
+        self.center = self.clamp(self.center);
+    }
+
+    /// Centers the view on `center` at once.
+    pub fn jump_to(&mut self, center: Point) {
+        self.center = self.clamp(center.into());
+    }
+
+    /// Moves the view towards `target`. Call once a frame.
+    pub fn follow(&mut self, target: Point) {
+        let target = Vector2::from(target);
+        let half_zone_x = I16F16::from_int(self.dead_zone.width as i32 / 2);
+        let half_zone_y = I16F16::from_int(self.dead_zone.height as i32 / 2);
+        let goal = Vector2::new(
+            approach(self.center.x, target.x, half_zone_x),
+            approach(self.center.y, target.y, half_zone_y),
+        );
+        let center = self.center + (goal - self.center) * self.smoothing;
+        self.center = self.clamp(center);
+        self.update_shake();
+    }
+
+    /// Shakes the view by up to `amplitude` pixels, fading out over
+    /// `frames` calls to `follow`. A stronger shake replaces a weaker one.
+    pub fn shake(&mut self, amplitude: u32, frames: u32) {
+        let remaining = self.current_amplitude();
+        if amplitude as i32 >= remaining {
+            self.shake_amplitude = amplitude as i32;
+            self.shake_frames = frames;
+            self.shake_frames_left = frames;
+        }
+    }
+
+    fn current_amplitude(&self) -> i32 {
+        if self.shake_frames == 0 {
+            return 0;
+        }
+        self.shake_amplitude * self.shake_frames_left as i32 / self.shake_frames as i32
+    }
+
+    fn update_shake(&mut self) {
+        let amplitude = self.current_amplitude();
+        self.shake_offset = if amplitude > 0 {
+            let range = (2 * amplitude + 1) as u32;
+            Point::new(
+                self.rng.rand_range(0..range) as i32 - amplitude,
+                self.rng.rand_range(0..range) as i32 - amplitude,
+            )
+        } else {
+            Point::zero()
+        };
+        self.shake_frames_left = self.shake_frames_left.saturating_sub(1);
+    }
+
+    fn clamp(&self, center: Vector2) -> Vector2 {
+        let Some(bounds) = self.bounds else {
+            return center;
+        };
+        let clamp_axis = |c: I16F16, start: i32, len: u32, view: u32| {
+            let half = view as i32 / 2;
+            if len <= view {
+                I16F16::from_int(start + len as i32 / 2)
+            } else {
+                let min = I16F16::from_int(start + half);
+                let max = I16F16::from_int(start + len as i32 - (view as i32 - half));
+                if c < min {
+                    min
+                } else if c > max {
+                    max
+                } else {
+                    c
+                }
+            }
+        };
+        Vector2::new(
+            clamp_axis(
+                center.x,
+                bounds.top_left.x,
+                bounds.size.width,
+                self.view_size.width,
+            ),
+            clamp_axis(
+                center.y,
+                bounds.top_left.y,
+                bounds.size.height,
+                self.view_size.height,
+            ),
+        )
+    }
+
+    /// World position of the view's center, without shake.
+    pub fn center(&self) -> Point {
+        self.center.floor()
+    }
+
+    /// World position of the top left corner of the view, including shake.
+    /// This is the position for `TileRenderer::set_position`.
+    pub fn position(&self) -> Point {
+        self.center.floor() - self.view_size / 2 + self.shake_offset
+    }
+
+    /// The visible area in world coordinates.
+    pub fn view(&self) -> Rectangle {
+        Rectangle::new(self.position(), self.view_size)
+    }
+
+    pub fn to_screen(&self, world: Point) -> Point {
+        world - self.position()
+    }
+
+    pub fn to_world(&self, screen: Point) -> Point {
+        screen + self.position()
+    }
+
+    /// Returns true if any of `area`, in world coordinates, is on screen.
+    pub fn is_visible(&self, area: &Rectangle) -> bool {
+        !self.view().intersection(area).is_zero_sized()
+    }
+}
+
+// Moves `center` just far enough for `target` to be within `half_zone` of it.
+fn approach(center: I16F16, target: I16F16, half_zone: I16F16) -> I16F16 {
+    if target > center + half_zone {
+        target - half_zone
+    } else if target < center - half_zone {
+        target + half_zone
+    } else {
+        center
+    }
+}
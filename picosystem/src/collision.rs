@@ -0,0 +1,349 @@
+//! Collision tests between moving objects and against the map.
+//!
+//! Shapes are `Aabb`s, axis-aligned boxes, and `Circle`s in 16.16 fixed
+//! point, so objects can move by fractions of a pixel per frame.
+//!
+//! `sweep` moves a box by a velocity, stopping it flush against anything
+//! blocked, one axis at a time so that it slides along walls and floors.
+//! It takes the test for blocked areas as a function, usually
+//! `Map::is_blocked`, and checks every pixel the box passes, so fast
+//! objects don't tunnel through thin walls.
+//!
+//! A `SpatialHash` finds the objects near an area without testing all of
+//! them. It is filled with the boxes of the objects each frame and then
+//! queried, or asked for all the pairs that overlap.
+
+use crate::math::{Fixed, Vector2, I16F16};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+/// An axis-aligned box from `min` up to but not including `max`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Aabb {
+    pub min: Vector2,
+    pub max: Vector2,
+}
+
+impl Aabb {
+    pub const fn new(min: Vector2, max: Vector2) -> Self {
+        Aabb { min, max }
+    }
+
+    /// A box with its top left corner at `position`.
+    pub fn from_size(position: Vector2, size: Vector2) -> Self {
+        Aabb::new(position, position + size)
+    }
+
+    pub fn from_rectangle(rectangle: &Rectangle) -> Self {
+        Aabb::from_size(
+            rectangle.top_left.into(),
+            Vector2::new(
+                Fixed::from_int(rectangle.size.width as i32),
+                Fixed::from_int(rectangle.size.height as i32),
+            ),
+        )
+    }
+
+    /// The pixels the box covers, even partly.
+    pub fn to_rectangle(&self) -> Rectangle {
+        let top_left = self.min.floor();
+        let bottom_right = Point::new(self.max.x.ceil(), self.max.y.ceil());
+        Rectangle::new(
+            top_left,
+            Size::new(
+                (bottom_right.x - top_left.x).max(0) as u32,
+                (bottom_right.y - top_left.y).max(0) as u32,
+            ),
+        )
+    }
+
+    pub fn size(&self) -> Vector2 {
+        self.max - self.min
+    }
+
+    pub fn center(&self) -> Vector2 {
+        Vector2::new(
+            (self.min.x + self.max.x).div_int(2),
+            (self.min.y + self.max.y).div_int(2),
+        )
+    }
+
+    pub fn translate(&self, offset: Vector2) -> Self {
+        Aabb::new(self.min + offset, self.max + offset)
+    }
+
+    pub fn contains(&self, point: Vector2) -> bool {
+        (self.min.x..self.max.x).contains(&point.x) && (self.min.y..self.max.y).contains(&point.y)
+    }
+
+    /// Returns true if the boxes share some area. Boxes that only touch
+    /// don't overlap.
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x < other.max.x
+            && other.min.x < self.max.x
+            && self.min.y < other.max.y
+            && other.min.y < self.max.y
+    }
+
+    /// The shortest move that takes this box out of `other`, or `None` if
+    /// they don't overlap.
+    pub fn penetration(&self, other: &Aabb) -> Option<Vector2> {
+        if !self.overlaps(other) {
+            return None;
+        }
+        let axis = |min: I16F16, max: I16F16, other_min: I16F16, other_max: I16F16| {
+            let (left, right) = (other_min - max, other_max - min);
+            if right < -left {
+                right
+            } else {
+                left
+            }
+        };
+        let x = axis(self.min.x, self.max.x, other.min.x, other.max.x);
+        let y = axis(self.min.y, self.max.y, other.min.y, other.max.y);
+        Some(if x.abs() < y.abs() {
+            Vector2::new(x, I16F16::ZERO)
+        } else {
+            Vector2::new(I16F16::ZERO, y)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Circle {
+    pub center: Vector2,
+    pub radius: I16F16,
+}
+
+impl Circle {
+    pub const fn new(center: Vector2, radius: I16F16) -> Self {
+        Circle { center, radius }
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let radius = Vector2::new(self.radius, self.radius);
+        Aabb::new(self.center - radius, self.center + radius)
+    }
+
+    pub fn contains(&self, point: Vector2) -> bool {
+        within(point - self.center, self.radius)
+    }
+
+    pub fn overlaps(&self, other: &Circle) -> bool {
+        within(other.center - self.center, self.radius + other.radius)
+    }
+
+    pub fn overlaps_aabb(&self, aabb: &Aabb) -> bool {
+        // The point of the box closest to the center.
+        let closest = Vector2::new(
+            self.center.x.clamp(aabb.min.x, aabb.max.x),
+            self.center.y.clamp(aabb.min.y, aabb.max.y),
+        );
+        within(closest - self.center, self.radius)
+    }
+}
+
+// Returns true if `offset` is shorter than `distance`. Squared in 64 bits,
+// as 16.16 squares overflow past 181 pixels.
+fn within(offset: Vector2, distance: I16F16) -> bool {
+    let square = |value: I16F16| value.to_bits() as i64 * value.to_bits() as i64;
+    square(offset.x) + square(offset.y) < square(distance)
+}
+
+/// Where `sweep` left a box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sweep {
+    pub aabb: Aabb,
+    /// The box was stopped short horizontally, by a wall.
+    pub hit_x: bool,
+    /// The box was stopped short vertically, by the floor when moving
+    /// down or the ceiling when moving up.
+    pub hit_y: bool,
+}
+
+/// Moves `aabb` by `velocity`, horizontally and then vertically, stopping
+/// each move where the pixels the box covers would be `blocked`. A box that
+/// starts out blocked moves freely, so objects stuck in walls can get out.
+pub fn sweep(aabb: Aabb, velocity: Vector2, blocked: impl Fn(&Rectangle) -> bool) -> Sweep {
+    let fits = |aabb: &Aabb| !blocked(&aabb.to_rectangle());
+    let dx = sweep_axis(aabb.min.x, aabb.max.x, velocity.x, |offset| {
+        fits(&aabb.translate(Vector2::new(offset, I16F16::ZERO)))
+    });
+    let aabb = aabb.translate(Vector2::new(dx, I16F16::ZERO));
+    let dy = sweep_axis(aabb.min.y, aabb.max.y, velocity.y, |offset| {
+        fits(&aabb.translate(Vector2::new(I16F16::ZERO, offset)))
+    });
+    Sweep {
+        aabb: aabb.translate(Vector2::new(I16F16::ZERO, dy)),
+        hit_x: dx != velocity.x,
+        hit_y: dy != velocity.y,
+    }
+}
+
+// The farthest offset towards `delta` at which the box from `min` to `max`
+// on one axis `fits`, stepping a pixel at a time from the start.
+fn sweep_axis(min: I16F16, max: I16F16, delta: I16F16, fits: impl Fn(I16F16) -> bool) -> I16F16 {
+    if delta == I16F16::ZERO || !fits(I16F16::ZERO) {
+        return delta;
+    }
+    if delta > I16F16::ZERO {
+        // Moving the leading edge past `boundary` covers the pixel after it.
+        let mut boundary = I16F16::from_int(max.ceil());
+        loop {
+            let next = boundary + I16F16::ONE;
+            let offset = (next - max).min(delta);
+            if !fits(offset) {
+                return (boundary - max).max(I16F16::ZERO);
+            }
+            if offset == delta {
+                return delta;
+            }
+            boundary = next;
+        }
+    } else {
+        let mut boundary = I16F16::from_int(min.floor());
+        loop {
+            let next = boundary - I16F16::ONE;
+            let offset = (next - min).max(delta);
+            if !fits(offset) {
+                return (boundary - min).min(I16F16::ZERO);
+            }
+            if offset == delta {
+                return delta;
+            }
+            boundary = next;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry<Id> {
+    id: Id,
+    aabb: Aabb,
+    // The cell the entry is listed under.
+    cell: (i32, i32),
+    // The next entry in the same bucket, or `END`.
+    next: u16,
+}
+
+const END: u16 = u16::MAX;
+
+/// Objects sorted into square cells of the world, with the cells hashed
+/// into `BUCKETS` lists, a power of two. An object is listed once for every
+/// cell its box covers, up to `N` listings in all.
+pub struct SpatialHash<Id: Copy, const BUCKETS: usize, const N: usize> {
+    cell_shift: u32,
+    heads: [u16; BUCKETS],
+    entries: heapless::Vec<Entry<Id>, N>,
+}
+
+impl<Id: Copy, const BUCKETS: usize, const N: usize> SpatialHash<Id, BUCKETS, N> {
+    /// A hash with cells of `cell_size` pixels, rounded up to a power of
+    /// two. Cells about the size of the larger objects work well.
+    pub fn new(cell_size: u32) -> Self {
+        assert!(BUCKETS.is_power_of_two() && N < END as usize);
+        SpatialHash {
+            cell_shift: cell_size.max(1).next_power_of_two().trailing_zeros(),
+            heads: [END; BUCKETS],
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    /// Removes every object, to fill the hash again for the next frame.
+    pub fn clear(&mut self) {
+        self.heads = [END; BUCKETS];
+        self.entries.clear();
+    }
+
+    /// Adds the object `id` with its box. Returns false if the hash ran out
+    /// of room, in which case the object may be found only in some cells.
+    pub fn insert(&mut self, id: Id, aabb: Aabb) -> bool {
+        let (first, last) = self.cells(&aabb);
+        for y in first.1..=last.1 {
+            for x in first.0..=last.0 {
+                let bucket = bucket::<BUCKETS>((x, y));
+                let entry = Entry {
+                    id,
+                    aabb,
+                    cell: (x, y),
+                    next: self.heads[bucket],
+                };
+                if self.entries.push(entry).is_err() {
+                    return false;
+                }
+                self.heads[bucket] = (self.entries.len() - 1) as u16;
+            }
+        }
+        true
+    }
+
+    /// Calls `f` once with every object whose box overlaps `area`.
+    pub fn query(&self, area: &Aabb, mut f: impl FnMut(Id)) {
+        let (first, last) = self.cells(area);
+        for y in first.1..=last.1 {
+            for x in first.0..=last.0 {
+                for entry in self.bucket((x, y)) {
+                    if self.first_shared_cell(&entry.aabb, area) == Some(entry.cell) {
+                        f(entry.id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls `f` once with every pair of objects whose boxes overlap.
+    pub fn pairs(&self, mut f: impl FnMut(Id, Id)) {
+        for entry in &self.entries {
+            let mut index = entry.next;
+            while let Some(other) = self.entries.get(index as usize) {
+                index = other.next;
+                if other.cell == entry.cell
+                    && self.first_shared_cell(&entry.aabb, &other.aabb) == Some(entry.cell)
+                {
+                    f(entry.id, other.id);
+                }
+            }
+        }
+    }
+
+    fn cell(&self, point: Vector2) -> (i32, i32) {
+        (
+            point.x.floor() >> self.cell_shift,
+            point.y.floor() >> self.cell_shift,
+        )
+    }
+
+    // The first and last cells covered by `aabb`, by rows and columns.
+    fn cells(&self, aabb: &Aabb) -> ((i32, i32), (i32, i32)) {
+        let last = Vector2::new(
+            aabb.max.x - Fixed::from_bits(1),
+            aabb.max.y - Fixed::from_bits(1),
+        );
+        (self.cell(aabb.min), self.cell(last))
+    }
+
+    // The top left cell of the area both boxes cover, where the boxes are
+    // reported so that objects listed under several cells are reported once.
+    fn first_shared_cell(&self, a: &Aabb, b: &Aabb) -> Option<(i32, i32)> {
+        if !a.overlaps(b) {
+            return None;
+        }
+        Some(self.cell(Vector2::new(a.min.x.max(b.min.x), a.min.y.max(b.min.y))))
+    }
+
+    // The entries listed under `cell`.
+    fn bucket(&self, cell: (i32, i32)) -> impl Iterator<Item = &Entry<Id>> {
+        let mut index = self.heads[bucket::<BUCKETS>(cell)];
+        core::iter::from_fn(move || {
+            let entry = self.entries.get(index as usize)?;
+            index = entry.next;
+            Some(entry)
+        })
+        .filter(move |entry| entry.cell == cell)
+    }
+}
+
+fn bucket<const BUCKETS: usize>((x, y): (i32, i32)) -> usize {
+    let hash = (x as u32).wrapping_mul(0x9e37_79b1) ^ (y as u32).wrapping_mul(0x85eb_ca77);
+    (hash >> 16) as usize & (BUCKETS - 1)
+}
@@ -0,0 +1,117 @@
+//! Whole-screen color effects.
+//!
+//! A `ColorFilter` maps the red, green and blue channels of a pixel through
+//! separate lookup tables, which covers fades to black or white, flashes and
+//! tints. The display applies it to pixels as they are sent to the LCD with
+//! `Display::set_color_filter`, so the framebuffer keeps the unfiltered
+//! picture.
+
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::{Rgb565, RgbColor};
+use embedded_graphics::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+}
+
+impl Channel {
+    /// The largest value of the channel: 31 for red and blue, 63 for green.
+    pub fn max(self) -> u8 {
+        match self {
+            Channel::Red => Rgb565::MAX_R,
+            Channel::Green => Rgb565::MAX_G,
+            Channel::Blue => Rgb565::MAX_B,
+        }
+    }
+
+    fn of(self, color: Rgb565) -> u8 {
+        match self {
+            Channel::Red => color.r(),
+            Channel::Green => color.g(),
+            Channel::Blue => color.b(),
+        }
+    }
+
+    fn shift(self) -> u16 {
+        match self {
+            Channel::Red => 11,
+            Channel::Green => 5,
+            Channel::Blue => 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ColorFilter {
+    // Filtered channels, already shifted into place and byte swapped like
+    // the pixels in the framebuffer, so that a pixel is filtered with three
+    // lookups.
+    red: [u16; 32],
+    green: [u16; 64],
+    blue: [u16; 32],
+}
+
+impl ColorFilter {
+    /// Builds a filter from `f(channel, value)`, which maps a channel value
+    /// in `0..=channel.max()` to a new value in the same range.
+    pub fn from_fn(f: impl Fn(Channel, u8) -> u8) -> Self {
+        let table = |channel: Channel, value: usize| {
+            let value = f(channel, value as u8).min(channel.max()) as u16;
+            (value << channel.shift()).swap_bytes()
+        };
+        ColorFilter {
+            red: core::array::from_fn(|value| table(Channel::Red, value)),
+            green: core::array::from_fn(|value| table(Channel::Green, value)),
+            blue: core::array::from_fn(|value| table(Channel::Blue, value)),
+        }
+    }
+
+    /// Leaves colors unchanged.
+    pub fn identity() -> Self {
+        Self::from_fn(|_, value| value)
+    }
+
+    /// Blends every color towards `color` by `amount` out of 255. Fading to
+    /// black or white, or flashing red when the player is hit, is a fade
+    /// with the amount changing over a few frames.
+    pub fn fade(color: Rgb565, amount: u8) -> Self {
+        let amount = amount as u32;
+        Self::from_fn(|channel, value| {
+            let target = channel.of(color) as u32;
+            ((value as u32 * (255 - amount) + target * amount + 127) / 255) as u8
+        })
+    }
+
+    /// Scales every channel by the matching channel of `color`, so white
+    /// leaves colors unchanged and a dark blue gives a night look.
+    pub fn tint(color: Rgb565) -> Self {
+        Self::from_fn(|channel, value| {
+            let max = channel.max() as u32;
+            ((value as u32 * channel.of(color) as u32 + max / 2) / max) as u8
+        })
+    }
+
+    pub fn apply(&self, color: Rgb565) -> Rgb565 {
+        let raw = self.apply_raw(RawU16::from(color).into_inner().to_be());
+        Rgb565::from(RawU16::new(u16::from_be(raw)))
+    }
+
+    /// Filters a pixel stored in the framebuffer's byte order.
+    #[inline(always)]
+    pub fn apply_raw(&self, pixel: u16) -> u16 {
+        let pixel = pixel.swap_bytes();
+        self.red[(pixel >> 11) as usize]
+            | self.green[((pixel >> 5) & 0x3f) as usize]
+            | self.blue[(pixel & 0x1f) as usize]
+    }
+
+    /// Filters framebuffer pixels from `src` into `dst`.
+    pub fn apply_slice(&self, src: &[u16], dst: &mut [u16]) {
+        for (dst, &src) in dst.iter_mut().zip(src) {
+            *dst = self.apply_raw(src);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Recognizing button sequences and chords, for cheat codes, entering a
+//! debug menu or resetting.
+//!
+//! A `ComboDetector` holds the combos a game listens for, each with an ID
+//! of the game's choosing, for example `Combo::Sequence` with the masks of
+//! B, A, B and A within 500 ms. Call `Input::update_combos` once per frame
+//! and it returns the IDs of the combos completed on that frame.
+//!
+//! Combos follow the buttons as `Input` sees them, so a recording played
+//! back enters the same cheat codes again.
+
+use crate::replay::ButtonMask;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combo {
+    /// The buttons pressed one after the other, all within `within_ms` of
+    /// the first. Pressing any other button starts again.
+    Sequence {
+        buttons: &'static [ButtonMask],
+        within_ms: u32,
+    },
+    /// Exactly these buttons held together for `hold_ms`. Fires once until
+    /// they are let go.
+    Chord { buttons: ButtonMask, hold_ms: u32 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tracker<Id> {
+    id: Id,
+    combo: Combo,
+    // Steps of a sequence matched so far.
+    progress: usize,
+    // When the sequence was started or the chord was first held.
+    start_us: u64,
+    // Set while a chord that fired is still held.
+    fired: bool,
+}
+
+pub struct ComboDetector<Id: Copy, const N: usize> {
+    trackers: heapless::Vec<Tracker<Id>, N>,
+    previous: ButtonMask,
+}
+
+impl<Id: Copy, const N: usize> ComboDetector<Id, N> {
+    pub const fn new() -> Self {
+        ComboDetector {
+            trackers: heapless::Vec::new(),
+            previous: 0,
+        }
+    }
+
+    /// Listens for `combo`. Returns false if there are already `N` combos.
+    pub fn add(&mut self, id: Id, combo: Combo) -> bool {
+        self.trackers
+            .push(Tracker {
+                id,
+                combo,
+                progress: 0,
+                start_us: 0,
+                fired: false,
+            })
+            .is_ok()
+    }
+
+    /// Forgets any combos half entered.
+    pub fn reset(&mut self) {
+        for tracker in self.trackers.iter_mut() {
+            tracker.progress = 0;
+            tracker.fired = false;
+        }
+    }
+
+    /// Takes the buttons `held` at `now_us` and returns the combos
+    /// completed.
+    pub fn update(&mut self, held: ButtonMask, now_us: u64) -> heapless::Vec<Id, N> {
+        let pressed = held & !self.previous;
+        self.previous = held;
+        let mut completed = heapless::Vec::new();
+        for tracker in self.trackers.iter_mut() {
+            if tracker.update(held, pressed, now_us) {
+                let _ = completed.push(tracker.id);
+            }
+        }
+        completed
+    }
+}
+
+impl<Id: Copy, const N: usize> Default for ComboDetector<Id, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Id> Tracker<Id> {
+    fn update(&mut self, held: ButtonMask, pressed: ButtonMask, now_us: u64) -> bool {
+        match self.combo {
+            Combo::Sequence { buttons, within_ms } => {
+                if self.progress > 0 && now_us - self.start_us > within_ms as u64 * 1000 {
+                    self.progress = 0;
+                }
+                if pressed == 0 || buttons.is_empty() {
+                    return false;
+                }
+                if pressed == buttons[self.progress] {
+                    self.progress += 1;
+                } else {
+                    self.progress = 0;
+                    // The wrong press may start the sequence again.
+                    if pressed == buttons[0] {
+                        self.progress = 1;
+                    }
+                }
+                if self.progress == 1 {
+                    self.start_us = now_us;
+                }
+                if self.progress == buttons.len() {
+                    self.progress = 0;
+                    return true;
+                }
+                false
+            }
+            Combo::Chord { buttons, hold_ms } => {
+                if held != buttons {
+                    self.progress = 0;
+                    self.fired = false;
+                    return false;
+                }
+                if self.progress == 0 {
+                    self.progress = 1;
+                    self.start_us = now_us;
+                }
+                if !self.fired && now_us - self.start_us >= hold_ms as u64 * 1000 {
+                    self.fired = true;
+                    return true;
+                }
+                false
+            }
+        }
+    }
+}
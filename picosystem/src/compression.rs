@@ -0,0 +1,243 @@
+//! Compressed tile and sprite data.
+//!
+//! The codecs and their stream formats live in `picosystem_compressor`,
+//! shared with the proc macros that compress assets on the host. This
+//! module adds a run-length decompressor that does the copying with two DMA
+//! channels, one that falls back to the CPU when no channels are free, and
+//! LZ4 and heatshrink decompressors that run from RAM. `decompress_with`
+//! picks the one for a `Codec`. `Reader` decodes run-length streams a piece
+//! at a time, which `sprite::CompressedSprite` uses to draw straight into
+//! the framebuffer.
+
+pub use picosystem_compressor::{
+    compress, ctrl_word, decompress, decompressed_size, heatshrink, lz4, max_compressed_len, Codec,
+    Reader,
+};
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+mod device {
+    use super::{heatshrink, Codec};
+    use crate::dma::{self, DmaChannel, DmaManager};
+    use picosystem_macros::ram_code;
+
+    /// Decompresses `input` into `output`, copying data with `channel0` and
+    /// filling runs with `channel1` so both overlap with parsing. Reading
+    /// `input` from RAM rather than flash is much faster.
+    #[ram_code]
+    pub fn decompress_dma(
+        channel0: &mut DmaChannel,
+        channel1: &mut DmaChannel,
+        input: &[u16],
+        output: &mut [u16],
+    ) {
+        assert!(
+            super::decompressed_size(input) as usize <= output.len(),
+            "output too short"
+        );
+        unsafe {
+            let mut src_ptr: *const u16 = input.as_ptr().add(1);
+            let end_ptr = input.as_ptr().add(input.len());
+            let mut dst_ptr: *mut u16 = output.as_mut_ptr();
+
+            while src_ptr < end_ptr {
+                let ctrl = *src_ptr;
+                src_ptr = src_ptr.add(1);
+                let data_length = ctrl & 0xff;
+                let run_length = ctrl >> 8;
+
+                if data_length == 0 {
+                    dst_ptr = dst_ptr.add(run_length as usize);
+                    continue;
+                }
+
+                channel0.wait();
+                dma::start_copy_mem(
+                    channel0,
+                    src_ptr as u32,
+                    dst_ptr as u32,
+                    2,
+                    data_length as u32,
+                );
+                src_ptr = src_ptr.add(data_length as usize);
+                dst_ptr = dst_ptr.add(data_length as usize);
+
+                if run_length > 0 {
+                    channel1.wait();
+                    dma::start_set_mem(
+                        channel1,
+                        src_ptr.offset(-1) as u32,
+                        dst_ptr as u32,
+                        2,
+                        run_length as u32,
+                    );
+                    dst_ptr = dst_ptr.add(run_length as usize);
+                }
+            }
+
+            channel0.wait();
+            channel1.wait();
+        }
+    }
+
+    /// Decompresses with two free DMA channels if there are any, and on the
+    /// CPU otherwise.
+    pub fn decompress_any(input: &[u16], output: &mut [u16]) {
+        match (DmaManager::claim_any(), DmaManager::claim_any()) {
+            (Ok(mut channel0), Ok(mut channel1)) => {
+                decompress_dma(&mut channel0, &mut channel1, input, output)
+            }
+            _ => super::decompress(input, output),
+        }
+    }
+
+    /// Decompresses `input`, compressed with `codec`. Run-length streams
+    /// use the channels, and the others the CPU.
+    pub fn decompress_with(
+        codec: Codec,
+        channel0: &mut DmaChannel,
+        channel1: &mut DmaChannel,
+        input: &[u16],
+        output: &mut [u16],
+    ) {
+        match codec {
+            Codec::Rle => decompress_dma(channel0, channel1, input, output),
+            Codec::Lz4 => decompress_lz4(input, output),
+            Codec::Heatshrink => decompress_heatshrink(input, output),
+        }
+    }
+
+    // The rest of an LZ4 length whose nibble is 15.
+    #[inline(always)]
+    unsafe fn read_length(src: &mut *const u8) -> usize {
+        let mut length = 0;
+        loop {
+            let b = **src;
+            *src = src.add(1);
+            length += b as usize;
+            if b != 255 {
+                return length;
+            }
+        }
+    }
+
+    /// Decompresses an LZ4 stream on the CPU, like `lz4::decompress` but
+    /// running from RAM and reading bytes through pointers. Copies stop at
+    /// the end of the output, and a stream referring back before its start
+    /// stops decoding.
+    #[ram_code]
+    pub fn decompress_lz4(input: &[u16], output: &mut [u16]) {
+        let len = 2 * super::decompressed_size(input) as usize;
+        assert!(len <= 2 * output.len(), "output too short");
+        unsafe {
+            let mut src = (input.as_ptr() as *const u8).add(2);
+            let src_end = (input.as_ptr() as *const u8).add(2 * input.len());
+            let start = output.as_mut_ptr() as *mut u8;
+            let end = start.add(len);
+            let mut dst = start;
+            while dst < end && src < src_end {
+                let token = *src;
+                src = src.add(1);
+                let mut literals = (token >> 4) as usize;
+                if literals == 15 {
+                    literals += read_length(&mut src);
+                }
+                for _ in 0..literals.min(end.offset_from(dst) as usize) {
+                    *dst = *src;
+                    dst = dst.add(1);
+                    src = src.add(1);
+                }
+                if dst >= end {
+                    break;
+                }
+                let distance = *src as usize | (*src.add(1) as usize) << 8;
+                src = src.add(2);
+                let mut length = (token & 15) as usize;
+                if length == 15 {
+                    length += read_length(&mut src);
+                }
+                if distance == 0 || distance > dst.offset_from(start) as usize {
+                    break;
+                }
+                let mut from = dst.sub(distance);
+                for _ in 0..(length + 4).min(end.offset_from(dst) as usize) {
+                    *dst = *from;
+                    dst = dst.add(1);
+                    from = from.add(1);
+                }
+            }
+        }
+    }
+
+    // Reads a heatshrink stream most significant bit first.
+    struct BitReader {
+        src: *const u8,
+        end: *const u8,
+        bits: u32,
+        count: u32,
+    }
+
+    impl BitReader {
+        #[inline(always)]
+        fn read(&mut self, count: u32) -> usize {
+            while self.count < count {
+                let b = if self.src < self.end {
+                    unsafe {
+                        let b = *self.src;
+                        self.src = self.src.add(1);
+                        b
+                    }
+                } else {
+                    0
+                };
+                self.bits = (self.bits << 8) | b as u32;
+                self.count += 8;
+            }
+            self.count -= count;
+            ((self.bits >> self.count) & ((1 << count) - 1)) as usize
+        }
+    }
+
+    /// Decompresses a heatshrink stream on the CPU, like
+    /// `heatshrink::decompress` but running from RAM. Copies stop at the
+    /// end of the output, and a stream referring back before its start
+    /// stops decoding.
+    #[ram_code]
+    pub fn decompress_heatshrink(input: &[u16], output: &mut [u16]) {
+        let len = 2 * super::decompressed_size(input) as usize;
+        assert!(len <= 2 * output.len(), "output too short");
+        unsafe {
+            let mut reader = BitReader {
+                src: (input.as_ptr() as *const u8).add(2),
+                end: (input.as_ptr() as *const u8).add(2 * input.len()),
+                bits: 0,
+                count: 0,
+            };
+            let start = output.as_mut_ptr() as *mut u8;
+            let end = start.add(len);
+            let mut dst = start;
+            while dst < end {
+                if reader.read(1) == 1 {
+                    *dst = reader.read(8) as u8;
+                    dst = dst.add(1);
+                    continue;
+                }
+                let distance = reader.read(heatshrink::WINDOW_BITS) + 1;
+                let length = reader.read(heatshrink::LOOKAHEAD_BITS) + 1;
+                if distance > dst.offset_from(start) as usize {
+                    break;
+                }
+                let mut from = dst.sub(distance);
+                for _ in 0..length.min(end.offset_from(dst) as usize) {
+                    *dst = *from;
+                    dst = dst.add(1);
+                    from = from.add(1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub use device::{
+    decompress_any, decompress_dma, decompress_heatshrink, decompress_lz4, decompress_with,
+};
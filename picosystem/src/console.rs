@@ -0,0 +1,356 @@
+//! A text console drawn over the screen.
+//!
+//! `Console` keeps a grid of characters written with `core::fmt::Write`,
+//! wrapping long lines and scrolling up when the last line is full. Colors
+//! are set with `set_colors` or with ANSI escapes, `\x1b[31m` for red
+//! text and `\x1b[0m` to go back to the defaults.
+//!
+//! On the device there is also a global console, which log messages are
+//! copied to with `set_log_enabled`, handy during bring-up before anything
+//! else works. `Hardware::draw` draws it over the frame while it is shown,
+//! which holding X and Y and pressing B toggles. It is left out with the
+//! `double-buffer` feature, as the second framebuffer leaves no RAM for it.
+
+use core::fmt;
+use embedded_graphics::mono_font::{ascii::FONT_6X10, MonoTextStyleBuilder};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Baseline, Text};
+
+pub const CHAR_WIDTH: u32 = 6;
+pub const CHAR_HEIGHT: u32 = 10;
+pub const COLUMNS: usize = 40;
+pub const ROWS: usize = 24;
+
+const DEFAULT_FG: Rgb565 = Rgb565::new(24, 48, 24);
+const MAX_ESCAPE_PARAMS: usize = 4;
+
+// The ANSI colors 0 to 7, then their bright versions.
+const PALETTE: [Rgb565; 16] = [
+    Rgb565::new(0, 0, 0),
+    Rgb565::new(24, 0, 0),
+    Rgb565::new(0, 48, 0),
+    Rgb565::new(24, 48, 0),
+    Rgb565::new(0, 0, 24),
+    Rgb565::new(24, 0, 24),
+    Rgb565::new(0, 48, 24),
+    DEFAULT_FG,
+    Rgb565::new(12, 24, 12),
+    Rgb565::new(31, 12, 12),
+    Rgb565::new(12, 63, 12),
+    Rgb565::new(31, 63, 12),
+    Rgb565::new(12, 24, 31),
+    Rgb565::new(31, 12, 31),
+    Rgb565::new(12, 63, 31),
+    Rgb565::new(31, 63, 31),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    c: u8,
+    fg: Rgb565,
+    bg: Option<Rgb565>,
+}
+
+const BLANK: Cell = Cell {
+    c: b' ',
+    fg: DEFAULT_FG,
+    bg: None,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `\x1b`.
+    Start,
+    /// After `\x1b[`, reading numeric parameters separated by `;`.
+    Csi,
+}
+
+pub struct Console {
+    // A ring of rows, with `top` the row shown first.
+    cells: [[Cell; COLUMNS]; ROWS],
+    top: usize,
+    column: usize,
+    // Rows below `top`.
+    row: usize,
+    fg: Rgb565,
+    bg: Option<Rgb565>,
+    escape: Escape,
+    params: [u16; MAX_ESCAPE_PARAMS],
+    num_params: usize,
+}
+
+#[allow(clippy::new_without_default)]
+impl Console {
+    pub const fn new() -> Self {
+        Console {
+            cells: [[BLANK; COLUMNS]; ROWS],
+            top: 0,
+            column: 0,
+            row: 0,
+            fg: DEFAULT_FG,
+            bg: None,
+            escape: Escape::None,
+            params: [0; MAX_ESCAPE_PARAMS],
+            num_params: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells = [[BLANK; COLUMNS]; ROWS];
+        self.top = 0;
+        self.column = 0;
+        self.row = 0;
+    }
+
+    /// Sets the colors of the text written next. Without a background, the
+    /// picture underneath shows between the letters.
+    pub fn set_colors(&mut self, fg: Rgb565, bg: Option<Rgb565>) {
+        self.fg = fg;
+        self.bg = bg;
+    }
+
+    pub fn reset_colors(&mut self) {
+        self.set_colors(DEFAULT_FG, None);
+    }
+
+    /// Draws the console over `target`, from its top left corner.
+    pub fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        for row in 0..ROWS {
+            let cells = &self.cells[(self.top + row) % ROWS];
+            let y = (row as u32 * CHAR_HEIGHT) as i32;
+            // Draws runs of cells with the same colors, skipping blank
+            // cells without a background.
+            let mut start = 0;
+            while start < COLUMNS {
+                let first = cells[start];
+                let len = cells[start..]
+                    .iter()
+                    .take_while(|cell| cell.fg == first.fg && cell.bg == first.bg)
+                    .count();
+                let run = &cells[start..start + len];
+                if first.bg.is_some() || run.iter().any(|cell| cell.c != b' ') {
+                    let mut text = [b' '; COLUMNS];
+                    for (dst, cell) in text.iter_mut().zip(run) {
+                        *dst = cell.c;
+                    }
+                    let mut style = MonoTextStyleBuilder::new()
+                        .font(&FONT_6X10)
+                        .text_color(first.fg);
+                    if let Some(bg) = first.bg {
+                        style = style.background_color(bg);
+                    }
+                    let position = Point::new((start as u32 * CHAR_WIDTH) as i32, y);
+                    let text = core::str::from_utf8(&text[..len]).unwrap_or("");
+                    Text::with_baseline(text, position, style.build(), Baseline::Top)
+                        .draw(target)?;
+                }
+                start += len;
+            }
+        }
+        Ok(())
+    }
+
+    /// The area covered by `draw`.
+    pub fn bounding_box(&self) -> Rectangle {
+        Rectangle::new(
+            Point::zero(),
+            Size::new(COLUMNS as u32 * CHAR_WIDTH, ROWS as u32 * CHAR_HEIGHT),
+        )
+    }
+
+    fn put(&mut self, c: u8) {
+        match c {
+            b'\n' => self.new_line(),
+            b'\r' => self.column = 0,
+            b'\t' => {
+                for _ in 0..4 - self.column % 4 {
+                    self.put(b' ');
+                }
+            }
+            0x1b => {
+                self.escape = Escape::Start;
+            }
+            _ => {
+                if self.column == COLUMNS {
+                    self.new_line();
+                }
+                let c = if c.is_ascii_graphic() || c == b' ' {
+                    c
+                } else {
+                    b'?'
+                };
+                let row = (self.top + self.row) % ROWS;
+                self.cells[row][self.column] = Cell {
+                    c,
+                    fg: self.fg,
+                    bg: self.bg,
+                };
+                self.column += 1;
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < ROWS {
+            self.row += 1;
+        } else {
+            // The top row becomes the new bottom row.
+            self.cells[self.top] = [BLANK; COLUMNS];
+            self.top = (self.top + 1) % ROWS;
+        }
+    }
+
+    fn put_escaped(&mut self, c: u8) {
+        match (self.escape, c) {
+            (Escape::Start, b'[') => {
+                self.escape = Escape::Csi;
+                self.params = [0; MAX_ESCAPE_PARAMS];
+                self.num_params = 1;
+            }
+            (Escape::Csi, b'0'..=b'9') => {
+                if let Some(param) = self.params.get_mut(self.num_params - 1) {
+                    *param = param.saturating_mul(10).saturating_add((c - b'0') as u16);
+                }
+            }
+            (Escape::Csi, b';') => self.num_params += 1,
+            (Escape::Csi, b'm') => {
+                let params = self.params;
+                for &code in &params[..self.num_params.min(MAX_ESCAPE_PARAMS)] {
+                    self.select_graphic_rendition(code);
+                }
+                self.escape = Escape::None;
+            }
+            (Escape::Csi, b'J') if self.params[0] == 2 => {
+                self.clear();
+                self.escape = Escape::None;
+            }
+            // Other sequences are ignored.
+            (Escape::Csi, b'\x20'..=b'\x3f') => {}
+            _ => self.escape = Escape::None,
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, code: u16) {
+        match code {
+            0 => self.reset_colors(),
+            30..=37 => self.fg = PALETTE[code as usize - 30],
+            39 => self.fg = DEFAULT_FG,
+            40..=47 => self.bg = Some(PALETTE[code as usize - 40]),
+            49 => self.bg = None,
+            90..=97 => self.fg = PALETTE[code as usize - 90 + 8],
+            100..=107 => self.bg = Some(PALETTE[code as usize - 100 + 8]),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.bytes() {
+            if self.escape == Escape::None {
+                self.put(c);
+            } else {
+                self.put_escaped(c);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(
+    target_arch = "arm",
+    target_os = "none",
+    not(feature = "double-buffer")
+))]
+pub use global::*;
+
+// Without the global console, log messages only go to USB serial.
+#[cfg(all(target_arch = "arm", target_os = "none", feature = "double-buffer"))]
+pub(crate) fn log(_level: log::Level, _message: &str) {}
+
+#[cfg(all(
+    target_arch = "arm",
+    target_os = "none",
+    not(feature = "double-buffer")
+))]
+mod global {
+    use super::*;
+    use core::cell::Cell;
+    use core::fmt::Write;
+    use core::ptr::addr_of_mut;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use critical_section::Mutex;
+    use log::Level;
+
+    // Only used while `IN_USE` is set, which is taken in a critical section
+    // so that drawing the console doesn't hold one.
+    static mut CONSOLE: Console = Console::new();
+    static IN_USE: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+    static LOG_ENABLED: AtomicBool = AtomicBool::new(false);
+    static VISIBLE: AtomicBool = AtomicBool::new(false);
+
+    fn try_with<R>(f: impl FnOnce(&mut Console) -> R) -> Option<R> {
+        let taken = critical_section::with(|cs| !IN_USE.borrow(cs).replace(true));
+        if !taken {
+            return None;
+        }
+        let result = f(unsafe { &mut *addr_of_mut!(CONSOLE) });
+        critical_section::with(|cs| IN_USE.borrow(cs).set(false));
+        Some(result)
+    }
+
+    /// Runs `f` with the global console. Panics if called from inside `f`.
+    pub fn with<R>(f: impl FnOnce(&mut Console) -> R) -> R {
+        try_with(f).expect("console already in use")
+    }
+
+    /// Copies log messages to the global console.
+    pub fn set_log_enabled(enabled: bool) {
+        LOG_ENABLED.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn set_visible(visible: bool) {
+        VISIBLE.store(visible, Ordering::Relaxed);
+    }
+
+    pub fn is_visible() -> bool {
+        VISIBLE.load(Ordering::Relaxed)
+    }
+
+    /// Called by the logger with each message. Messages logged while the
+    /// console is in use, for example from an interrupt, are dropped.
+    pub(crate) fn log(level: Level, message: &str) {
+        if !LOG_ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        let fg = match level {
+            Level::Error => PALETTE[9],
+            Level::Warn => PALETTE[11],
+            Level::Info => DEFAULT_FG,
+            Level::Debug | Level::Trace => PALETTE[8],
+        };
+        try_with(|console| {
+            let (old_fg, old_bg) = (console.fg, console.bg);
+            console.set_colors(fg, None);
+            let _ = writeln!(console, "{}", message);
+            console.set_colors(old_fg, old_bg);
+        });
+    }
+
+    /// Draws the global console over `target` if it is visible.
+    pub fn draw_overlay<D>(target: &mut D)
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        if is_visible() {
+            let _ = try_with(|console| console.draw(target));
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Retro screen effects: scanlines, a vignette and a pixel grid.
+//!
+//! A `CrtFilter` darkens each pixel by a brightness for its row times one
+//! for its column, which is cheap enough to apply to a whole row while the
+//! previous one is being sent. The display applies it to pixels as they are
+//! sent to the LCD with `Display::set_crt_filter`, after any color filter,
+//! so the framebuffer keeps the plain picture.
+
+use crate::display::{HEIGHT, WIDTH};
+
+// Full brightness in the row and column tables.
+const FULL: u16 = 256;
+
+#[derive(Clone)]
+pub struct CrtFilter {
+    rows: [u16; HEIGHT],
+    columns: [u16; WIDTH],
+    // Whether every column is at full brightness, so rows at full
+    // brightness are left alone.
+    plain_columns: bool,
+}
+
+#[allow(clippy::new_without_default)]
+impl CrtFilter {
+    /// A filter that leaves the picture unchanged, to add effects to.
+    pub fn new() -> Self {
+        CrtFilter {
+            rows: [FULL; HEIGHT],
+            columns: [FULL; WIDTH],
+            plain_columns: true,
+        }
+    }
+
+    /// Darkens every other row by `amount` out of 255, like the gaps
+    /// between the lines of a CRT.
+    pub fn with_scanlines(mut self, amount: u8) -> Self {
+        for row in self.rows.iter_mut().skip(1).step_by(2) {
+            *row = darken(*row, amount);
+        }
+        self
+    }
+
+    /// Darkens the corners by `amount` out of 255, fading towards the
+    /// middle of the screen.
+    pub fn with_vignette(mut self, amount: u8) -> Self {
+        // Each axis darkens by half the amount at the edges, so their
+        // product darkens the corners by about the whole amount.
+        let falloff = |i: usize, size: usize| {
+            let distance = (2 * i + 1).abs_diff(size) as u32;
+            let amount = amount as u32 * distance * distance / (2 * (size * size) as u32);
+            FULL - ((FULL as u32 * amount) / 255) as u16
+        };
+        for (y, row) in self.rows.iter_mut().enumerate() {
+            *row = scale(*row, falloff(y, HEIGHT));
+        }
+        for (x, column) in self.columns.iter_mut().enumerate() {
+            *column = scale(*column, falloff(x, WIDTH));
+        }
+        self.plain_columns = amount == 0 && self.plain_columns;
+        self
+    }
+
+    /// Darkens the last row and column of every `size` by `size` pixel
+    /// cell by `amount` out of 255, outlining the pixels of a game drawn
+    /// `size` times larger.
+    pub fn with_pixel_grid(mut self, size: usize, amount: u8) -> Self {
+        if size < 2 {
+            return self;
+        }
+        for row in self.rows.iter_mut().skip(size - 1).step_by(size) {
+            *row = darken(*row, amount);
+        }
+        for column in self.columns.iter_mut().skip(size - 1).step_by(size) {
+            *column = darken(*column, amount);
+        }
+        self.plain_columns = amount == 0 && self.plain_columns;
+        self
+    }
+
+    /// Filters framebuffer pixels in place, starting at column `x` of row
+    /// `y`.
+    pub fn apply_row(&self, x: usize, y: usize, pixels: &mut [u16]) {
+        let row = self.rows[y] as u32;
+        if row == FULL as u32 && self.plain_columns {
+            return;
+        }
+        for (pixel, &column) in pixels.iter_mut().zip(&self.columns[x..]) {
+            let brightness = (row * column as u32) >> 11;
+            *pixel = dim(u16::from_be(*pixel), brightness).to_be();
+        }
+    }
+}
+
+fn scale(brightness: u16, factor: u16) -> u16 {
+    ((brightness as u32 * factor as u32) / FULL as u32) as u16
+}
+
+fn darken(brightness: u16, amount: u8) -> u16 {
+    scale(
+        brightness,
+        FULL - ((FULL as u32 * amount as u32) / 255) as u16,
+    )
+}
+
+// `color` with every channel scaled by `brightness` from 0 to 32. The
+// channels are spread over a word with gaps wide enough for their products,
+// so all three are scaled with one multiplication.
+fn dim(color: u16, brightness: u32) -> u16 {
+    const MASK: u32 = 0x07e0_f81f;
+    let spread = (color as u32 | (color as u32) << 16) & MASK;
+    let dimmed = ((spread * brightness) >> 5) & MASK;
+    (dimmed | dimmed >> 16) as u16
+}
@@ -0,0 +1,242 @@
+//! A panel of performance figures drawn over the bottom of the screen.
+//!
+//! It shows the frame rate with a graph of recent frame times, the time
+//! spent in each phase of the last frame, how much RAM and flash is used,
+//! the XIP cache hit rate and misses per frame while `xip` samples them,
+//! and the hit rate of the tile cache, followed by the pools reported to
+//! `meminfo` and the smallest, average and largest time per frame of each
+//! `profile` label. `Hardware::draw` measures frames and draws the panel
+//! while it is shown, which holding X and Y and pressing A toggles. The
+//! scheduler reports update times, and `TileRenderer` its cache statistics.
+
+use crate::display::{framebuffer, screen_height, Display, WIDTH};
+use crate::map::TileRendererStats;
+use crate::meminfo::{self, PoolStats};
+use crate::{profile, storage, time, xip};
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use critical_section::Mutex;
+use embedded_graphics::mono_font::{ascii::FONT_6X10, MonoTextStyle};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Baseline, Text};
+
+const HISTORY: usize = 120;
+const LINE_HEIGHT: i32 = 10;
+const LINES: usize = 5;
+// Pools from `meminfo` shown below the fixed lines, then labels from
+// `profile`.
+const MAX_POOL_LINES: usize = 3;
+const MAX_PROFILE_LINES: usize = 6;
+const GRAPH_HEIGHT: i32 = 32;
+// Frame time at the top of the graph.
+const GRAPH_FULL_US: u32 = 50_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Game logic, reported by the scheduler.
+    Update,
+    /// Drawing into the framebuffer.
+    Draw,
+    /// Waiting for the previous flush and for vsync.
+    Flush,
+}
+
+#[derive(Clone, Copy)]
+struct Stats {
+    frame_times_us: [u16; HISTORY],
+    next: usize,
+    last_frame_end_us: u32,
+    phases_us: [u32; 3],
+    tiles: Option<TileRendererStats>,
+}
+
+static STATS: Mutex<RefCell<Stats>> = Mutex::new(RefCell::new(Stats {
+    frame_times_us: [0; HISTORY],
+    next: 0,
+    last_frame_end_us: 0,
+    phases_us: [0; 3],
+    tiles: None,
+}));
+
+static VISIBLE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_visible(visible: bool) {
+    VISIBLE.store(visible, Ordering::Relaxed);
+}
+
+pub fn is_visible() -> bool {
+    VISIBLE.load(Ordering::Relaxed)
+}
+
+/// Records the time spent in `phase` during the current frame.
+pub fn record_phase(phase: Phase, time_us: u32) {
+    let index = match phase {
+        Phase::Update => 0,
+        Phase::Draw => 1,
+        Phase::Flush => 2,
+    };
+    critical_section::with(|cs| STATS.borrow_ref_mut(cs).phases_us[index] = time_us);
+}
+
+pub fn set_tile_stats(stats: &TileRendererStats) {
+    critical_section::with(|cs| STATS.borrow_ref_mut(cs).tiles = Some(*stats));
+}
+
+/// Records the time since the previous frame ended.
+pub(crate) fn end_frame() {
+    let now = time::time_us();
+    critical_section::with(|cs| {
+        let mut stats = STATS.borrow_ref_mut(cs);
+        let frame_time_us = now.wrapping_sub(stats.last_frame_end_us);
+        let next = stats.next;
+        stats.frame_times_us[next] = frame_time_us.min(u16::MAX as u32) as u16;
+        stats.next = (next + 1) % HISTORY;
+        stats.last_frame_end_us = now;
+    });
+}
+
+/// Draws the panel over `display` if it is visible.
+pub fn draw_overlay(display: &mut Display) {
+    if !is_visible() {
+        return;
+    }
+    let stats = critical_section::with(|cs| *STATS.borrow_ref(cs));
+    let pools = meminfo::pools();
+    let num_pool_lines = pools.len().min(MAX_POOL_LINES);
+    let reports = profile::reports();
+    let num_lines = LINES + num_pool_lines + reports.len().min(MAX_PROFILE_LINES);
+    let panel_height = num_lines as i32 * LINE_HEIGHT + GRAPH_HEIGHT + 6;
+    let top = screen_height() as i32 - panel_height;
+    let panel = Rectangle::new(
+        Point::new(0, top),
+        Size::new(WIDTH as u32, panel_height as u32),
+    );
+    darken(display, &panel);
+
+    let mut lines: [heapless::String<48>; LINES + MAX_POOL_LINES + MAX_PROFILE_LINES] =
+        Default::default();
+    let (total_us, max_us, frames) = stats
+        .frame_times_us
+        .iter()
+        .filter(|&&t| t > 0)
+        .fold((0u32, 0u32, 0u32), |(total, max, n), &t| {
+            (total + t as u32, max.max(t as u32), n + 1)
+        });
+    let average_us = total_us.checked_div(frames).unwrap_or(0);
+    let fps = 1_000_000u32.checked_div(average_us).unwrap_or(0);
+    let _ = write!(
+        lines[0],
+        "{} fps  frame {}  max {} ms",
+        fps,
+        Ms(average_us),
+        Ms(max_us)
+    );
+    let [update, draw, flush] = stats.phases_us;
+    let _ = write!(
+        lines[1],
+        "update {}  draw {}  flush {}",
+        Ms(update),
+        Ms(draw),
+        Ms(flush)
+    );
+    let _ = write!(
+        lines[2],
+        "RAM {}K  stack {}K  free {}K",
+        meminfo::static_ram() / 1024,
+        meminfo::stack_high_water() / 1024,
+        meminfo::free_ram() / 1024
+    );
+    let _ = write!(
+        lines[3],
+        "flash {}K  files {}K",
+        meminfo::program_flash() / 1024,
+        storage::fs().free_space() / 1024
+    );
+    if let Some(xip) = xip::last_frame() {
+        let _ = write!(lines[3], "  XIP {}%/{}", xip.hit_rate(), xip.misses());
+    }
+    match stats.tiles {
+        Some(tiles) => {
+            let _ = write!(
+                lines[4],
+                "tile hits {}%  evicted {}",
+                hit_rate(tiles.cache_misses, tiles.cache_lookups),
+                tiles.cache_evictions
+            );
+        }
+        None => {
+            let _ = write!(lines[4], "no tile renderer");
+        }
+    }
+    for (line, pool) in lines[LINES..].iter_mut().zip(&pools[..num_pool_lines]) {
+        let PoolStats {
+            name,
+            used,
+            peak,
+            capacity,
+        } = pool;
+        let _ = write!(line, "{} {}/{}  peak {}", name, used, capacity, peak);
+    }
+    let profile_lines = &mut lines[LINES + num_pool_lines..];
+    for (line, report) in profile_lines.iter_mut().zip(&reports) {
+        let _ = write!(
+            line,
+            "{} {}/{}/{} us",
+            report.label, report.min_us, report.avg_us, report.max_us
+        );
+    }
+    let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+    for (i, line) in lines[..num_lines].iter().enumerate() {
+        let position = Point::new(2, top + 2 + i as i32 * LINE_HEIGHT);
+        let _ = Text::with_baseline(line, position, style, Baseline::Top).draw(display);
+    }
+
+    // Oldest frame on the left, two pixels per frame.
+    let bottom = screen_height() as i32 - 2;
+    for i in 0..HISTORY {
+        let frame_time_us = stats.frame_times_us[(stats.next + i) % HISTORY] as u32;
+        let height = (frame_time_us * GRAPH_HEIGHT as u32 / GRAPH_FULL_US).min(GRAPH_HEIGHT as u32);
+        let color = match frame_time_us {
+            0..=25_000 => Rgb565::GREEN,
+            25_001..=33_333 => Rgb565::YELLOW,
+            _ => Rgb565::RED,
+        };
+        let bar = Rectangle::new(
+            Point::new(i as i32 * 2, bottom - height as i32),
+            Size::new(2, height),
+        );
+        let _ = display.fill_solid(&bar, color);
+    }
+}
+
+// Halves the brightness of `area` so that text over it stands out while the
+// game still shows through.
+fn darken(display: &mut Display, area: &Rectangle) {
+    let fb = framebuffer();
+    let Some(bottom_right) = area.bottom_right() else {
+        return;
+    };
+    for y in area.top_left.y..=bottom_right.y {
+        let row = y as usize * WIDTH;
+        for pixel in &mut fb[row + area.top_left.x as usize..=row + bottom_right.x as usize] {
+            *pixel = ((u16::from_be(*pixel) >> 1) & 0x7bef).to_be();
+        }
+    }
+    display.mark_dirty(*area);
+}
+
+fn hit_rate(misses: u32, lookups: u32) -> u32 {
+    (lookups - misses.min(lookups)) * 100 / lookups.max(1)
+}
+
+/// Microseconds shown as milliseconds with one decimal.
+struct Ms(u32);
+
+impl core::fmt::Display for Ms {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}.{}", self.0 / 1000, self.0 % 1000 / 100)
+    }
+}
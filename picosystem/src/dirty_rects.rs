@@ -0,0 +1,88 @@
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+const MAX_RECTS: usize = 8;
+
+/// Set of screen regions modified since the last flush.
+///
+/// Overlapping or touching rectangles are merged as they are added. When the
+/// set is full everything collapses into a single bounding rectangle.
+pub struct DirtyRects {
+    rects: heapless::Vec<Rectangle, MAX_RECTS>,
+}
+
+impl DirtyRects {
+    pub const fn new() -> Self {
+        DirtyRects {
+            rects: heapless::Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, rect: Rectangle) {
+        if rect.is_zero_sized() {
+            return;
+        }
+        let mut rect = rect;
+        // Merging may make the result touch other rectangles, so repeat until stable.
+        while let Some(i) = self.rects.iter().position(|r| touches(r, &rect)) {
+            rect = union(&self.rects.swap_remove(i), &rect);
+        }
+        if let Err(rect) = self.rects.push(rect) {
+            let merged = self.rects.iter().fold(rect, |acc, r| union(&acc, r));
+            self.rects.clear();
+            let _ = self.rects.push(merged);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.rects.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rectangle> {
+        self.rects.iter()
+    }
+
+    /// Sorts the rectangles top to bottom, the order they should be sent to the LCD in.
+    pub fn sort(&mut self) {
+        self.rects
+            .sort_unstable_by_key(|r| (r.top_left.y, r.top_left.x));
+    }
+
+    /// Total number of pixels covered by the rectangles.
+    pub fn area(&self) -> u32 {
+        self.rects
+            .iter()
+            .map(|r| r.size.width * r.size.height)
+            .sum()
+    }
+}
+
+impl Default for DirtyRects {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn touches(a: &Rectangle, b: &Rectangle) -> bool {
+    a.top_left.x <= b.top_left.x + b.size.width as i32
+        && b.top_left.x <= a.top_left.x + a.size.width as i32
+        && a.top_left.y <= b.top_left.y + b.size.height as i32
+        && b.top_left.y <= a.top_left.y + a.size.height as i32
+}
+
+fn union(a: &Rectangle, b: &Rectangle) -> Rectangle {
+    let top_left = Point::new(
+        a.top_left.x.min(b.top_left.x),
+        a.top_left.y.min(b.top_left.y),
+    );
+    let bottom_right = Point::new(
+        (a.top_left.x + a.size.width as i32).max(b.top_left.x + b.size.width as i32),
+        (a.top_left.y + a.size.height as i32).max(b.top_left.y + b.size.height as i32),
+    );
+    let size = bottom_right - top_left;
+    Rectangle::new(top_left, Size::new(size.x as u32, size.y as u32))
+}
@@ -1,6 +1,17 @@
+use crate::color_filter::ColorFilter;
+use crate::crt_filter::CrtFilter;
+use crate::dirty_rects::DirtyRects;
 use crate::dma::{self, DmaChannel};
+#[cfg(feature = "async")]
+use crate::executor::{self, WakerSlot};
+use crate::interrupts::{self, GpioEvent};
+use crate::logging::info;
 use crate::time;
+use core::cell::Cell;
 use core::convert::TryInto;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use critical_section::Mutex;
 use display_interface_spi::SPIInterfaceNoCS;
 use embedded_graphics::draw_target::DrawTarget;
 use embedded_graphics::{
@@ -12,31 +23,404 @@ use embedded_hal::blocking::delay::DelayUs;
 use embedded_hal::digital::v2::{InputPin, OutputPin};
 use embedded_hal::spi::MODE_3;
 use hal::pac;
+use hal::pio::{
+    PIOBuilder, PIOExt, PinDir, PinState, Running, ShiftDirection, StateMachine, Tx, PIO, SM0,
+};
 use hal::spi::Spi;
-use log::info;
+use picosystem_macros::ram_code;
 use rp2040_hal as hal;
 use rp2040_hal::gpio::dynpin::DynFunction;
 use rp2040_hal::gpio::dynpin::DynPin;
 use rp2040_hal::gpio::dynpin::DynPinMode;
+use rp_pico::hal::pac::interrupt;
 use st7789::{TearingEffect, ST7789};
 use fugit::RateExtU32;
 
 pub const WIDTH: usize = 240;
 pub const HEIGHT: usize = 240;
 
+// The rows of the logical screen, which are the first rows of the
+// framebuffer. Fewer than `HEIGHT` with a letterbox.
+static SCREEN_HEIGHT: AtomicUsize = AtomicUsize::new(HEIGHT);
+
+/// How many rows of the framebuffer are drawn and shown: `HEIGHT`, or the
+/// height of the letterbox set with `Display::set_letterbox`.
+pub fn screen_height() -> usize {
+    SCREEN_HEIGHT.load(Ordering::Relaxed)
+}
+
+/// The area of the framebuffer that is shown, for code that draws into it
+/// directly.
+pub fn screen() -> Rectangle {
+    Rectangle::new(
+        Point::zero(),
+        Size::new(WIDTH as u32, screen_height() as u32),
+    )
+}
+
+// Completion of a flush is reported by DMA_IRQ_0. Transfers of a partial
+// flush before the last one also raise it, so the handler only signals once
+// the flush is armed, which happens after its last transfer has started.
+/// Called from the DMA interrupt when a flush completes.
+pub type FlushCallback = fn();
+
+static FLUSH_CHANNEL: AtomicUsize = AtomicUsize::new(dma::CHANNEL_FRAMEBUFFER);
+static FLUSH_ARMED: AtomicBool = AtomicBool::new(false);
+static FLUSH_DONE: AtomicBool = AtomicBool::new(true);
+static FLUSH_CALLBACK: Mutex<Cell<Option<FlushCallback>>> = Mutex::new(Cell::new(None));
+// The core that ran `Display::new`, which is the only one taking DMA_IRQ_0.
+static IRQ_CORE: AtomicUsize = AtomicUsize::new(0);
+// The task waiting in `Display::flush_done`.
+#[cfg(feature = "async")]
+static FLUSH_WAKER: WakerSlot = WakerSlot::new();
+
+// With TE sync, the GPIO interrupt of the TE pin starts a full flush at
+// the next vsync, from where it waits here, and counts the vsyncs for
+// `wait_for_vsync`. The vsync pin is the TE output of the LCD.
+const NO_TE_SYNC: usize = usize::MAX;
+static TE_SYNC_GPIO: AtomicUsize = AtomicUsize::new(NO_TE_SYNC);
+static QUEUED_FLUSH: Mutex<Cell<Option<QueuedFlush>>> = Mutex::new(Cell::new(None));
+static VSYNC_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy)]
+struct QueuedFlush {
+    target: PixelTarget,
+    src: usize,
+    pixels: usize,
+}
+
+// With a color or CRT filter, pixels are filtered into one line buffer while
+// the other is being sent, and DMA_IRQ_0 starts sending each line when the
+// previous one is done. Lines never cross rows of the framebuffer. The
+// filters are only replaced while nothing is sent.
+static mut COLOR_FILTER: Option<ColorFilter> = None;
+static mut CRT_FILTER: Option<CrtFilter> = None;
+static mut FILTER_LINES: [[u16; WIDTH]; 2] = [[0; WIDTH]; 2];
+static FILTER_JOB: Mutex<Cell<FilterJob>> = Mutex::new(Cell::new(FilterJob::IDLE));
+
+// Where pixel data is written: the SPI0 data register or the PIO TX FIFO.
+#[derive(Clone, Copy)]
+struct PixelTarget {
+    dst: u32,
+    treq: u8,
+    pio: bool,
+}
+
+impl PixelTarget {
+    unsafe fn start(self, dma_channel: &mut DmaChannel, src: *const u16, pixels: usize) {
+        if self.pio {
+            // Pixels are stored in the byte order the LCD expects, while the state
+            // machine sends the most significant bit of a halfword first.
+            dma::start_copy_to_peripheral(
+                dma_channel,
+                src as u32,
+                self.dst,
+                self.treq,
+                true,
+                2,
+                pixels as u32,
+            );
+        } else {
+            dma::start_copy_to_peripheral(
+                dma_channel,
+                src as u32,
+                self.dst,
+                self.treq,
+                false,
+                1,
+                (pixels * 2) as u32,
+            );
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct FilterJob {
+    target: PixelTarget,
+    // Address of the next framebuffer pixel to filter, and its index.
+    src: usize,
+    index: usize,
+    remaining: usize,
+    line: usize,
+    // Number of filtered pixels in `line` waiting to be sent.
+    ready: usize,
+}
+
+impl FilterJob {
+    const IDLE: FilterJob = FilterJob {
+        target: PixelTarget {
+            dst: 0,
+            treq: 0,
+            pio: false,
+        },
+        src: 0,
+        index: 0,
+        remaining: 0,
+        line: 0,
+        ready: 0,
+    };
+
+    fn prepare(&mut self) {
+        let (x, y) = (self.index % WIDTH, self.index / WIDTH);
+        let pixels = self.remaining.min(WIDTH - x);
+        unsafe {
+            let src = core::slice::from_raw_parts(self.src as *const u16, pixels);
+            let line = &mut (*addr_of_mut!(FILTER_LINES))[self.line];
+            let line = &mut line[..pixels];
+            match &*addr_of!(COLOR_FILTER) {
+                Some(filter) => filter.apply_slice(src, line),
+                None => line.copy_from_slice(src),
+            }
+            if let Some(filter) = &*addr_of!(CRT_FILTER) {
+                filter.apply_row(x, y, line);
+            }
+        }
+        self.src += pixels * 2;
+        self.index += pixels;
+        self.remaining -= pixels;
+        self.ready = pixels;
+    }
+
+    // Sends the prepared line and filters the next one into the other buffer.
+    fn send(&mut self) {
+        unsafe {
+            let mut dma_channel = DmaChannel::new(FLUSH_CHANNEL.load(Ordering::Relaxed));
+            let line = (*addr_of!(FILTER_LINES))[self.line].as_ptr();
+            self.target.start(&mut dma_channel, line, self.ready);
+        }
+        self.line ^= 1;
+        self.prepare();
+    }
+}
+
+#[cfg(not(feature = "double-buffer"))]
 static mut FRAMEBUFFER: [u16; WIDTH * HEIGHT] = [0; WIDTH * HEIGHT];
 
+#[cfg(not(feature = "double-buffer"))]
 pub fn framebuffer() -> &'static mut [u16; WIDTH * HEIGHT] {
     unsafe { &mut FRAMEBUFFER }
 }
 
+// Two framebuffers take 225 KiB of the 256 KiB of RAM, hence the feature gate.
+#[cfg(feature = "double-buffer")]
+static mut FRAMEBUFFERS: [[u16; WIDTH * HEIGHT]; 2] = [[0; WIDTH * HEIGHT]; 2];
+
+/// Index of the framebuffer being drawn into. The other one may be in flight to the LCD.
+#[cfg(feature = "double-buffer")]
+static DRAW_BUFFER: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the framebuffer that drawing should go to.
+#[cfg(feature = "double-buffer")]
+pub fn framebuffer() -> &'static mut [u16; WIDTH * HEIGHT] {
+    let index = DRAW_BUFFER.load(Ordering::Relaxed);
+    unsafe { &mut (*core::ptr::addr_of_mut!(FRAMEBUFFERS))[index] }
+}
+
+/// How pixel data reaches the LCD. Commands always go through SPI0.
+pub enum DisplayBus {
+    /// The SPI0 peripheral, clocked at half of `clk_peri`.
+    Spi,
+    /// A PIO0 state machine shifting pixels out on the SPI pins. It is
+    /// clocked from `clk_sys` rather than `clk_peri` and sends 16 bit
+    /// pixels with one idle cycle between them, so at the default clocks it
+    /// is on par with SPI and gains on it when `clk_peri` runs slower than
+    /// `clk_sys`.
+    Pio(pac::PIO0),
+}
+
 pub type RealDisplay = st7789::ST7789<SPIInterfaceNoCS<Spi<hal::spi::Enabled, pac::SPI0, 8>, DynPin>, DynPin, DynPin>;
 
 pub struct Display {
     st7789: RealDisplay,
     lcd_vsync_pin: DynPin,
+    lcd_vsync_gpio: usize,
     dma_channel: DmaChannel,
+    // Fills and blits get their own channel, as the flush channel may still
+    // be sending the framebuffer.
+    fill_dma_channel: DmaChannel,
     last_vsync_time: u32,
+    dirty_rects: DirtyRects,
+    partial_flush: bool,
+    full_window: bool,
+    pio_bus: Option<PioBus>,
+    orientation: Orientation,
+    color_order: ColorOrder,
+    gamma: Gamma,
+    lcd_dc_gpio: usize,
+    backlight_gpio: usize,
+    // Restored by `enable_backlight`.
+    backlight_level: u8,
+}
+
+/// Which way up the picture is shown. The LCD is square, so rotating it
+/// keeps the framebuffer size and layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    /// The top of the framebuffer is at the top of the screen, above the
+    /// buttons.
+    Portrait,
+    /// Rotated a quarter turn clockwise, for holding the device with the
+    /// buttons on the left.
+    Landscape,
+    /// Rotated half a turn.
+    Flipped,
+    /// Rotated a quarter turn anticlockwise, for holding the device with the
+    /// buttons on the right.
+    LandscapeFlipped,
+}
+
+impl Orientation {
+    /// How far the console is turned clockwise to hold it this way up, for
+    /// `ButtonMap::rotated`.
+    pub fn quarter_turns(self) -> u8 {
+        match self {
+            Orientation::Portrait => 0,
+            Orientation::Landscape => 1,
+            Orientation::Flipped => 2,
+            Orientation::LandscapeFlipped => 3,
+        }
+    }
+
+    fn madctl(self) -> st7789::Orientation {
+        match self {
+            Orientation::Portrait => st7789::Orientation::Portrait,
+            Orientation::Landscape => st7789::Orientation::Landscape,
+            Orientation::Flipped => st7789::Orientation::PortraitSwapped,
+            Orientation::LandscapeFlipped => st7789::Orientation::LandscapeSwapped,
+        }
+    }
+
+    // The controller has 320 rows and the panel shows the first 240 of them,
+    // so when the row order is reversed the window starts 80 rows in, on
+    // whichever axis addresses rows.
+    fn window_offset(self) -> Point {
+        const UNUSED_ROWS: i32 = 320 - HEIGHT as i32;
+        match self {
+            Orientation::Portrait | Orientation::Landscape => Point::zero(),
+            Orientation::Flipped => Point::new(0, UNUSED_ROWS),
+            Orientation::LandscapeFlipped => Point::new(UNUSED_ROWS, 0),
+        }
+    }
+}
+
+/// The order of red and blue in the pixels the LCD controller is sent.
+/// Panels are wired either way, so a unit that shows red as blue needs the
+/// other order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorOrder {
+    #[default]
+    Rgb,
+    Bgr,
+}
+
+impl ColorOrder {
+    // The BGR bit of MADCTL.
+    fn madctl(self) -> u8 {
+        match self {
+            ColorOrder::Rgb => 0,
+            ColorOrder::Bgr => 0x08,
+        }
+    }
+}
+
+/// The gamma curves built into the LCD controller. Panels vary, and a
+/// picture that looks washed out or too dark on one unit may look right
+/// with another curve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Gamma {
+    #[default]
+    G2_2,
+    G1_8,
+    G2_5,
+    G1_0,
+}
+
+impl Gamma {
+    pub const ALL: [Gamma; 4] = [Gamma::G2_2, Gamma::G1_8, Gamma::G2_5, Gamma::G1_0];
+
+    // The parameter of GAMSET.
+    fn gamset(self) -> u8 {
+        match self {
+            Gamma::G2_2 => 0x01,
+            Gamma::G1_8 => 0x02,
+            Gamma::G2_5 => 0x04,
+            Gamma::G1_0 => 0x08,
+        }
+    }
+}
+
+// The LCD samples data on the rising edge of the clock, which idles high
+// (SPI mode 3). The state machine waits for a pixel with the clock high and
+// sends its bits most significant first, changing the data pin on the
+// falling edge.
+struct PioBus {
+    _pio: PIO<pac::PIO0>,
+    _sm: StateMachine<(pac::PIO0, SM0), Running>,
+    tx: Tx<(pac::PIO0, SM0)>,
+    sck_pin: usize,
+    mosi_pin: usize,
+}
+
+impl PioBus {
+    fn new(pio0: pac::PIO0, resets: &mut pac::RESETS, sck_pin: u8, mosi_pin: u8) -> PioBus {
+        let side_set = pio::SideSet::new(false, 1, false);
+        let mut a = pio::Assembler::<{ pio::RP2040_MAX_PROGRAM_SIZE }>::new_with_side_set(side_set);
+        let mut wrap_target = a.label();
+        let mut wrap_source = a.label();
+        let mut bit_loop = a.label();
+        a.bind(&mut wrap_target);
+        a.pull_with_side_set(false, true, 1);
+        a.bind(&mut bit_loop);
+        a.out_with_side_set(pio::OutDestination::PINS, 1, 0);
+        a.jmp_with_side_set(
+            pio::JmpCondition::OutputShiftRegisterNotEmpty,
+            &mut bit_loop,
+            1,
+        );
+        a.bind(&mut wrap_source);
+        let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+        let (mut pio, sm0, _, _, _) = pio0.split(resets);
+        let installed = pio.install(&program).unwrap();
+        let (mut sm, _, tx) = PIOBuilder::from_program(installed)
+            .out_pins(mosi_pin, 1)
+            .side_set_pin_base(sck_pin)
+            .out_shift_direction(ShiftDirection::Left)
+            .pull_threshold(16)
+            .clock_divisor_fixed_point(1, 0)
+            .build(sm0);
+        sm.set_pins([(sck_pin, PinState::High)]);
+        sm.set_pindirs([(sck_pin, PinDir::Output), (mosi_pin, PinDir::Output)]);
+        PioBus {
+            _pio: pio,
+            _sm: sm.start(),
+            tx,
+            sck_pin: sck_pin as usize,
+            mosi_pin: mosi_pin as usize,
+        }
+    }
+
+    /// Hands the clock and data pins to the state machine, or back to SPI0
+    /// for sending commands.
+    fn select(&mut self, pio: bool) {
+        let io = unsafe { &*pac::IO_BANK0::PTR };
+        for pin in [self.sck_pin, self.mosi_pin] {
+            io.gpio[pin].gpio_ctrl.modify(|_, w| {
+                if pio {
+                    w.funcsel().pio0()
+                } else {
+                    w.funcsel().spi()
+                }
+            });
+        }
+    }
+
+    /// Waits until the last pixel has been shifted out.
+    fn wait_idle(&mut self) {
+        while !self.tx.is_empty() {}
+        self.tx.clear_stalled_flag();
+        while !self.tx.has_stalled() {}
+    }
 }
 
 
@@ -65,9 +449,15 @@ impl Display {
         spi_device: pac::SPI0,
         resets: &mut pac::RESETS,
         delay_source: &mut impl DelayUs<u32>,
-        dma_channel: DmaChannel,
+        mut dma_channel: DmaChannel,
+        bus: DisplayBus,
     ) -> Display {
         info!("Initializing display");
+        let sck_pin = lcd_sck_pin.id().num;
+        let mosi_pin = lcd_mosi_pin.id().num;
+        let lcd_dc_gpio = lcd_dc_pin.id().num as usize;
+        let lcd_vsync_gpio = lcd_vsync_pin.id().num as usize;
+        let backlight_gpio = backlight_pin.id().num as usize;
         backlight_pin.into_push_pull_output();
         lcd_dc_pin.into_push_pull_output();
         lcd_cs_pin.into_push_pull_output();
@@ -90,11 +480,29 @@ impl Display {
         let mut st7789 = ST7789::new(di, Some(lcd_reset_pin), Some(backlight_pin), WIDTH as u16, HEIGHT as u16);
         st7789.init(delay_source).unwrap();
         st7789.set_tearing_effect(TearingEffect::Vertical).unwrap();
+        FLUSH_CHANNEL.store(dma_channel.channel, Ordering::Relaxed);
+        dma_channel.set_irq0_enabled(true);
+        IRQ_CORE.store(current_core(), Ordering::Relaxed);
+        unsafe {
+            pac::NVIC::unmask(pac::Interrupt::DMA_IRQ_0);
+        }
         let mut display = Display {
             st7789,
             dma_channel,
+            fill_dma_channel: dma::DmaManager::claim(dma::CHANNEL_FRAMEBUFFER_FILL).unwrap(),
             lcd_vsync_pin,
+            lcd_vsync_gpio,
             last_vsync_time: 0,
+            dirty_rects: DirtyRects::new(),
+            partial_flush: false,
+            full_window: true,
+            pio_bus: None,
+            orientation: Orientation::Portrait,
+            color_order: ColorOrder::Rgb,
+            gamma: Gamma::G2_2,
+            lcd_dc_gpio,
+            backlight_gpio,
+            backlight_level: 255,
         };
         // A single clear occasionally fails to clear the screen.
         for _ in 0..2 {
@@ -106,58 +514,585 @@ impl Display {
                 .set_pixels(0, 0, (WIDTH - 1) as u16, (HEIGHT - 1) as u16, colors)
                 .unwrap();
         }
+        if let DisplayBus::Pio(pio0) = bus {
+            info!("Sending pixels with PIO");
+            let mut pio_bus = PioBus::new(pio0, resets, sck_pin, mosi_pin);
+            pio_bus.select(true);
+            display.pio_bus = Some(pio_bus);
+        }
         display.enable_backlight(delay_source);
         display
     }
 
     fn start_flush(&mut self) {
-        unsafe {
-            dma::start_copy_to_spi(
-                &mut self.dma_channel,
-                framebuffer().as_ptr() as u32,
-                (*pac::SPI0::PTR).sspdr.as_ptr() as u32,
-                1,
-                (WIDTH * HEIGHT * 2) as u32,
-            );
+        if self.partial_flush {
+            self.start_partial_flush();
+        } else {
+            self.start_flush_buffer(framebuffer());
+        }
+    }
+
+    #[ram_code]
+    fn start_flush_buffer(&mut self, buffer: &[u16; WIDTH * HEIGHT]) {
+        FLUSH_DONE.store(false, Ordering::Relaxed);
+        self.dirty_rects.clear();
+        if !self.full_window {
+            self.set_window(&self.bounding_box());
+        }
+        if self.te_sync() {
+            let flush = QueuedFlush {
+                target: self.pixel_target(),
+                src: buffer.as_ptr() as usize,
+                pixels: WIDTH * screen_height(),
+            };
+            critical_section::with(|cs| QUEUED_FLUSH.borrow(cs).set(Some(flush)));
+            return;
+        }
+        self.start_transfer(buffer, 0, WIDTH * screen_height());
+        arm_flush_irq();
+    }
+
+    /// Sends only the regions recorded in `dirty_rects` to the LCD. The last
+    /// transfer is left running, like a full flush.
+    #[ram_code]
+    fn start_partial_flush(&mut self) {
+        FLUSH_DONE.store(false, Ordering::Relaxed);
+        let mut dirty_rects = core::mem::take(&mut self.dirty_rects);
+        dirty_rects.sort();
+        let fb = framebuffer();
+        for rect in dirty_rects.iter() {
+            let rect = rect.intersection(&self.bounding_box());
+            if rect.is_zero_sized() {
+                continue;
+            }
+            self.set_window(&rect);
+            let start = rect.top_left.x as usize + rect.top_left.y as usize * WIDTH;
+            // Full-width regions are contiguous in the framebuffer.
+            let (rows, row_length) = if rect.size.width as usize == WIDTH {
+                (1, WIDTH * rect.size.height as usize)
+            } else {
+                (rect.size.height as usize, rect.size.width as usize)
+            };
+            for row in 0..rows {
+                self.wait_for_transfer();
+                self.start_transfer(fb, start + row * WIDTH, row_length);
+            }
+        }
+        arm_flush_irq();
+    }
+
+    fn pixel_target(&self) -> PixelTarget {
+        match &self.pio_bus {
+            Some(pio_bus) => PixelTarget {
+                dst: pio_bus.tx.fifo_address() as u32,
+                treq: pio_bus.tx.dreq_value(),
+                pio: true,
+            },
+            None => PixelTarget {
+                dst: unsafe { (*pac::SPI0::PTR).sspdr.as_ptr() as u32 },
+                treq: dma::DREQ_SPI0_TX,
+                pio: false,
+            },
+        }
+    }
+
+    /// Starts sending `pixels` pixels of `buffer` from index `start` over
+    /// the selected bus, through the filters if there are any.
+    fn start_transfer(&mut self, buffer: &[u16; WIDTH * HEIGHT], start: usize, pixels: usize) {
+        let target = self.pixel_target();
+        start_pixels(
+            &mut self.dma_channel,
+            target,
+            buffer[start..].as_ptr(),
+            start,
+            pixels,
+        );
+    }
+
+    // Waits for the current transfer, including the rest of a filtered one.
+    #[ram_code]
+    fn wait_for_transfer(&self) {
+        loop {
+            self.dma_channel.wait();
+            if critical_section::with(|cs| FILTER_JOB.borrow(cs).get().ready == 0) {
+                break;
+            }
+        }
+    }
+
+    /// Applies `filter` to every pixel sent to the LCD from the next flush
+    /// on. The framebuffer is left as drawn, and the whole screen is sent on
+    /// the next flush.
+    pub fn set_color_filter(&mut self, filter: Option<ColorFilter>) {
+        self.wait_for_flush();
+        self.wait_for_transfer();
+        unsafe { *addr_of_mut!(COLOR_FILTER) = filter };
+        self.mark_dirty(self.bounding_box());
+    }
+
+    /// Applies `filter` to every pixel sent to the LCD from the next flush
+    /// on, after the color filter. The framebuffer is left as drawn, and
+    /// the whole screen is sent on the next flush.
+    pub fn set_crt_filter(&mut self, filter: Option<CrtFilter>) {
+        self.wait_for_flush();
+        self.wait_for_transfer();
+        unsafe { *addr_of_mut!(CRT_FILTER) = filter };
+        self.mark_dirty(self.bounding_box());
+    }
+
+    /// Points the LCD RAM write window at `rect`. Subsequent pixel data fills it row by row.
+    fn set_window(&mut self, rect: &Rectangle) {
+        let offset = self.orientation.window_offset() + Point::new(0, self.letterbox_top());
+        let top_left = rect.top_left + offset;
+        let bottom_right = rect.bottom_right().unwrap() + offset;
+        self.begin_commands();
+        self.st7789
+            .set_pixels(
+                top_left.x as u16,
+                top_left.y as u16,
+                bottom_right.x as u16,
+                bottom_right.y as u16,
+                core::iter::empty(),
+            )
+            .unwrap();
+        self.end_commands();
+        self.full_window = *rect == self.bounding_box();
+    }
+
+    /// Waits for pixel data to be sent and hands the LCD pins to SPI0, so
+    /// that the driver can send commands.
+    fn begin_commands(&mut self) {
+        self.wait_for_spi_idle();
+        if let Some(pio_bus) = &mut self.pio_bus {
+            pio_bus.wait_idle();
+            pio_bus.select(false);
+        }
+    }
+
+    fn end_commands(&mut self) {
+        if let Some(pio_bus) = &mut self.pio_bus {
+            pio_bus.select(true);
+        }
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Rotates the picture on the LCD. The framebuffer keeps its layout, so
+    /// drawing code is unaffected. The whole screen is sent on the next flush.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+        self.send_madctl();
+        self.set_window(&self.bounding_box());
+        self.mark_dirty(self.bounding_box());
+    }
+
+    /// The height of the letterbox, if there is one.
+    pub fn letterbox(&self) -> Option<usize> {
+        Some(screen_height()).filter(|&height| height < HEIGHT)
+    }
+
+    /// Shrinks the screen to `height` rows in the middle of the LCD, with
+    /// black bars above and below, or gives it back all of it with `None`.
+    /// The screen keeps its width, and its top left corner stays the
+    /// origin for drawing, so a game that wants more frames for less
+    /// screen draws and flushes fewer pixels: 240x160 has two thirds of
+    /// them. Drawing through `Display` is clipped to the screen, and code
+    /// writing to `framebuffer()` keeps to `screen()`. The framebuffer is
+    /// cleared and the whole LCD is flushed to draw the bars.
+    pub fn set_letterbox(&mut self, height: Option<usize>) {
+        let height = height.unwrap_or(HEIGHT).clamp(1, HEIGHT);
+        self.wait_for_flush();
+        SCREEN_HEIGHT.store(HEIGHT, Ordering::Relaxed);
+        self.set_window(&self.bounding_box());
+        self.clear_fast(Rgb565::BLACK);
+        self.flush_blocking();
+        SCREEN_HEIGHT.store(height, Ordering::Relaxed);
+        self.set_window(&self.bounding_box());
+        self.mark_dirty(self.bounding_box());
+    }
+
+    // Rows of the LCD above the screen.
+    fn letterbox_top(&self) -> i32 {
+        (HEIGHT - screen_height()) as i32 / 2
+    }
+
+    pub fn color_order(&self) -> ColorOrder {
+        self.color_order
+    }
+
+    /// Swaps red and blue on the LCD, for panels wired the other way. The
+    /// framebuffer is unaffected, and the change shows straight away.
+    pub fn set_color_order(&mut self, color_order: ColorOrder) {
+        self.color_order = color_order;
+        self.send_madctl();
+    }
+
+    pub fn gamma(&self) -> Gamma {
+        self.gamma
+    }
+
+    /// Selects one of the controller's gamma curves. The change shows
+    /// straight away.
+    pub fn set_gamma(&mut self, gamma: Gamma) {
+        self.send_command(0x26, &[gamma.gamset()]); // GAMSET
+        self.gamma = gamma;
+    }
+
+    // The driver's `set_orientation` clears the color order bit, so MADCTL
+    // is sent here with both.
+    fn send_madctl(&mut self) {
+        let madctl = self.orientation.madctl() as u8 | self.color_order.madctl();
+        self.send_command(0x36, &[madctl]); // MADCTL
+    }
+
+    // Also called between the transfers of a partial flush, before it is
+    // armed, so this polls the channel rather than waiting for the IRQ.
+    fn wait_for_spi_idle(&mut self) {
+        self.wait_for_transfer();
+        let spi = unsafe { &*pac::SPI0::PTR };
+        while spi.sspsr.read().bsy().bit_is_set() {}
+        // The DMA transfer leaves received bytes behind, which would confuse the blocking driver.
+        while spi.sspsr.read().rne().bit_is_set() {
+            let _ = spi.sspdr.read();
         }
     }
 
+    /// Sleeps until the flush in progress, if any, has completed.
     fn wait_for_flush(&mut self) {
-        self.dma_channel.wait();
+        // The other core, such as the render server on core 1, is never
+        // woken by the IRQ, so it polls instead.
+        if current_core() != IRQ_CORE.load(Ordering::Relaxed) {
+            while !FLUSH_DONE.load(Ordering::Acquire) {
+                core::hint::spin_loop();
+            }
+            return;
+        }
+        while !FLUSH_DONE.load(Ordering::Acquire) {
+            // With interrupts masked the IRQ can't slip in between the check
+            // and the wfi, and a pending interrupt still wakes the core.
+            cortex_m::interrupt::free(|_| {
+                if !FLUSH_DONE.load(Ordering::Acquire) {
+                    cortex_m::asm::wfi();
+                }
+            });
+        }
+    }
+
+    /// When enabled, `flush` and `draw` only send the regions modified since
+    /// the previous flush. Has no effect on `swap_buffers`.
+    pub fn set_partial_flush(&mut self, enabled: bool) {
+        self.partial_flush = enabled;
+    }
+
+    /// Records a region as modified. Needed after writing to `framebuffer()` directly.
+    pub fn mark_dirty(&mut self, area: Rectangle) {
+        self.dirty_rects.add(area);
+    }
+
+    pub fn dirty_rects(&self) -> &DirtyRects {
+        &self.dirty_rects
     }
 
     pub fn flush(&mut self) {
-        self.wait_for_vsync();
+        self.flush_blocking();
+    }
+
+    /// Starts a flush at the next vsync and returns without waiting for it
+    /// to complete. Completion sets `is_flush_done` and calls the flush
+    /// callback. The framebuffer must not be drawn to until then.
+    pub fn flush_async(&mut self) {
+        self.wait_for_flush();
+        self.start_flush_at_vsync();
+    }
+
+    // With TE sync the interrupt starts a full flush, and this returns
+    // straight away. A partial flush sends commands between its transfers,
+    // so it is still started here.
+    fn start_flush_at_vsync(&mut self) {
+        if !self.te_sync() || self.partial_flush {
+            self.wait_for_vsync();
+        }
         self.start_flush();
+    }
+
+    pub fn te_sync(&self) -> bool {
+        TE_SYNC_GPIO.load(Ordering::Relaxed) != NO_TE_SYNC
+    }
+
+    /// Has the interrupt of the TE pin, which the LCD raises as it starts
+    /// a refresh, start full flushes, instead of polling the pin before
+    /// starting them. `flush_async` and `draw` then return at once, rather
+    /// than spinning until vsync, and the flush still starts as the panel
+    /// starts reading, so it doesn't tear. Until it starts,
+    /// `flush_progress` is 0, so the tile renderer waits for it. Partial
+    /// flushes still poll the pin. Must be called on core 0, which takes
+    /// the interrupt.
+    pub fn set_te_sync(&mut self, enabled: bool) {
+        if enabled == self.te_sync() {
+            return;
+        }
         self.wait_for_flush();
+        let gpio = self.lcd_vsync_gpio;
+        unsafe {
+            if enabled {
+                TE_SYNC_GPIO.store(gpio, Ordering::Relaxed);
+                interrupts::acknowledge_gpio_interrupt();
+                interrupts::enable_gpio_interrupt(gpio, GpioEvent::EdgeHigh);
+                interrupts::unmask_gpio_interrupt();
+            } else {
+                interrupts::disable_gpio_interrupt(gpio, GpioEvent::EdgeHigh);
+                TE_SYNC_GPIO.store(NO_TE_SYNC, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Flushes and sleeps until the flush has completed, instead of polling
+    /// the DMA channel.
+    pub fn flush_blocking(&mut self) {
+        self.flush_async();
+        self.wait_for_flush();
+    }
+
+    pub fn is_flush_done(&self) -> bool {
+        FLUSH_DONE.load(Ordering::Acquire)
+    }
+
+    /// Sets a function called when a flush completes. It runs in interrupt
+    /// context, or with interrupts disabled, so it should be short.
+    pub fn set_flush_callback(&mut self, callback: Option<FlushCallback>) {
+        critical_section::with(|cs| FLUSH_CALLBACK.borrow(cs).set(callback));
     }
 
+    #[cfg(not(feature = "double-buffer"))]
     pub fn draw(&mut self, func: impl FnOnce(&mut Self)) {
         self.wait_for_flush();
         func(self);
-        self.wait_for_vsync();
+        self.start_flush_at_vsync();
+    }
+
+    #[cfg(feature = "double-buffer")]
+    pub fn draw(&mut self, func: impl FnOnce(&mut Self)) {
+        func(self);
+        self.swap_buffers();
+    }
+
+    /// Starts flushing the framebuffer that was just drawn and switches
+    /// drawing to the other one, so the next frame can be drawn while
+    /// this one is being sent to the LCD.
+    #[cfg(feature = "double-buffer")]
+    pub fn swap_buffers(&mut self) {
+        let drawn = framebuffer();
+        self.wait_for_flush();
+        if !self.te_sync() {
+            self.wait_for_vsync();
+        }
+        DRAW_BUFFER.store(1 - DRAW_BUFFER.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.start_flush_buffer(drawn);
+    }
+
+    /// Waits for the flush in progress, if any, in an async task.
+    #[cfg(feature = "async")]
+    pub async fn flush_done(&mut self) {
+        core::future::poll_fn(|cx| {
+            FLUSH_WAKER.register(cx.waker());
+            if FLUSH_DONE.load(Ordering::Acquire) {
+                core::task::Poll::Ready(())
+            } else {
+                core::task::Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Waits for the start of the next vsync in an async task. The pin
+    /// doesn't wake the task, so other tasks run between checks.
+    #[cfg(feature = "async")]
+    pub async fn vsync(&mut self) {
+        if self.te_sync() {
+            let count = VSYNC_COUNT.load(Ordering::Acquire);
+            while VSYNC_COUNT.load(Ordering::Acquire) == count {
+                executor::yield_now().await;
+            }
+            self.last_vsync_time = time::time_us();
+            return;
+        }
+        while self.lcd_vsync_pin.is_high().unwrap() {
+            executor::yield_now().await;
+        }
+        while self.lcd_vsync_pin.is_low().unwrap() {
+            executor::yield_now().await;
+        }
+        self.last_vsync_time = time::time_us();
+    }
+
+    /// Like `draw`, letting other tasks run while waiting for the flush
+    /// and vsync.
+    #[cfg(all(feature = "async", not(feature = "double-buffer")))]
+    pub async fn draw_async(&mut self, func: impl FnOnce(&mut Self)) {
+        self.flush_done().await;
+        func(self);
+        if !self.te_sync() || self.partial_flush {
+            self.vsync().await;
+        }
         self.start_flush();
     }
 
+    #[cfg(all(feature = "async", feature = "double-buffer"))]
+    pub async fn draw_async(&mut self, func: impl FnOnce(&mut Self)) {
+        func(self);
+        let drawn = framebuffer();
+        self.flush_done().await;
+        if !self.te_sync() {
+            self.vsync().await;
+        }
+        DRAW_BUFFER.store(1 - DRAW_BUFFER.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.start_flush_buffer(drawn);
+    }
+
+    /// Turns the backlight on at the level last set with `set_backlight_level`.
     pub fn enable_backlight(&mut self, delay_source: &mut impl DelayUs<u32>) {
+        self.select_backlight_pwm(false);
         self.st7789.set_backlight(st7789::BacklightState::On, delay_source).unwrap();
+        if self.backlight_level < 255 {
+            self.set_backlight_level(self.backlight_level);
+        }
     }
     
     pub fn disable_backlight(&mut self, delay_source: &mut impl DelayUs<u32>) {
+        self.select_backlight_pwm(false);
         self.st7789.set_backlight(st7789::BacklightState::Off, delay_source).unwrap();
     }
 
+    /// Dims the backlight with PWM, from 0 for off to 255 for full
+    /// brightness. `disable_backlight` turns PWM off until `enable_backlight`.
+    /// The PWM slice is shared with the green LED, and is only set up here
+    /// if the LED hasn't done it.
+    pub fn set_backlight_level(&mut self, level: u8) {
+        self.backlight_level = level;
+        let slice = (self.backlight_gpio / 2) % 8;
+        let pwm = unsafe { &(*pac::PWM::PTR).ch[slice] };
+        if pwm.csr.read().en().bit_is_clear() {
+            pwm.top.write(|w| unsafe { w.top().bits(u16::MAX - 1) });
+            pwm.div.write(|w| unsafe { w.int().bits(1).frac().bits(0) });
+            pwm.csr.modify(|_, w| w.en().set_bit());
+        }
+        // Squared so that steps look even.
+        let top = pwm.top.read().top().bits() as u32 + 1;
+        let level = level as u32;
+        let compare = (top * level * level / (255 * 255)) as u16;
+        if self.backlight_gpio & 1 == 0 {
+            pwm.cc.modify(|_, w| unsafe { w.a().bits(compare) });
+        } else {
+            pwm.cc.modify(|_, w| unsafe { w.b().bits(compare) });
+        }
+        self.select_backlight_pwm(true);
+    }
+
+    pub fn backlight_level(&self) -> u8 {
+        self.backlight_level
+    }
+
+    fn select_backlight_pwm(&mut self, pwm: bool) {
+        let io = unsafe { &*pac::IO_BANK0::PTR };
+        io.gpio[self.backlight_gpio].gpio_ctrl.modify(|_, w| {
+            if pwm {
+                w.funcsel().pwm()
+            } else {
+                w.funcsel().sio()
+            }
+        });
+    }
+
+    /// Puts the LCD controller to sleep. The picture is lost, and the
+    /// backlight should be turned off first.
+    pub fn sleep(&mut self, delay_source: &mut impl DelayUs<u32>) {
+        self.wait_for_flush();
+        self.send_command(0x10, &[]); // SLPIN
+        delay_source.delay_us(5_000);
+    }
+
+    /// Wakes the LCD controller from `sleep`. The whole screen is sent on
+    /// the next flush.
+    pub fn wake(&mut self, delay_source: &mut impl DelayUs<u32>) {
+        self.send_command(0x11, &[]); // SLPOUT
+        delay_source.delay_us(120_000);
+        self.set_window(&self.bounding_box());
+        self.mark_dirty(self.bounding_box());
+    }
+
+    // Sends a command and its parameters. The driver has no method for
+    // the sleep and gamma commands.
+    fn send_command(&mut self, command: u8, params: &[u8]) {
+        self.begin_commands();
+        let sio = unsafe { &*pac::SIO::PTR };
+        let spi = unsafe { &*pac::SPI0::PTR };
+        let send = |bytes: &[u8]| {
+            for &byte in bytes {
+                while spi.sspsr.read().tnf().bit_is_clear() {}
+                spi.sspdr.write(|w| unsafe { w.data().bits(byte as u16) });
+            }
+            while spi.sspsr.read().bsy().bit_is_set() {}
+            while spi.sspsr.read().rne().bit_is_set() {
+                let _ = spi.sspdr.read();
+            }
+        };
+        sio.gpio_out_clr
+            .write(|w| unsafe { w.bits(1 << self.lcd_dc_gpio) });
+        send(&[command]);
+        sio.gpio_out_set
+            .write(|w| unsafe { w.bits(1 << self.lcd_dc_gpio) });
+        send(params);
+        self.end_commands();
+    }
+
     pub fn wait_for_vsync(&mut self) {
 /*         if self.last_vsync_time != 0 && time::time_us() - self.last_vsync_time > 16_000 {
             log::info!("Missed vsync");
         } */
         // log::info!("frametime {0}",time::time_us() - self.last_vsync_time);
-        while self.lcd_vsync_pin.is_high().unwrap() {}
-        while self.lcd_vsync_pin.is_low().unwrap() {}
+        if self.te_sync() {
+            let count = VSYNC_COUNT.load(Ordering::Acquire);
+            while VSYNC_COUNT.load(Ordering::Acquire) == count {
+                // Core 0 takes the TE interrupt, so core 1 polls. On core 0,
+                // as in `wait_for_flush`, the interrupt can't slip in
+                // between the check and the wfi.
+                if current_core() != 0 {
+                    core::hint::spin_loop();
+                    continue;
+                }
+                cortex_m::interrupt::free(|_| {
+                    if VSYNC_COUNT.load(Ordering::Acquire) == count {
+                        cortex_m::asm::wfi();
+                    }
+                });
+            }
+        } else {
+            while self.lcd_vsync_pin.is_high().unwrap() {}
+            while self.lcd_vsync_pin.is_low().unwrap() {}
+        }
         self.last_vsync_time = time::time_us();
     }
 
+    /// Returns how many pixels of the draw framebuffer have already been
+    /// sent to the LCD. Pixels before this index are safe to overwrite.
+    /// None are while the flush waits for the TE interrupt to start it.
     pub fn flush_progress(&self) -> usize {
+        if cfg!(feature = "double-buffer") {
+            return WIDTH * HEIGHT;
+        }
+        if critical_section::with(|cs| QUEUED_FLUSH.borrow(cs).get().is_some()) {
+            return 0;
+        }
+        if filtering() {
+            // Pixels are safe to overwrite once they have been filtered.
+            let job = critical_section::with(|cs| FILTER_JOB.borrow(cs).get());
+            if job.ready == 0 {
+                return WIDTH * HEIGHT;
+            }
+            return job.index;
+        }
         if self.dma_channel.get_count() == 0 {
             return WIDTH * HEIGHT;
         }
@@ -165,6 +1100,184 @@ impl Display {
     }
 }
 
+fn current_core() -> usize {
+    unsafe { (*pac::SIO::PTR).cpuid.read().bits() as usize }
+}
+
+fn filtering() -> bool {
+    unsafe { (*addr_of!(COLOR_FILTER)).is_some() || (*addr_of!(CRT_FILTER)).is_some() }
+}
+
+// Signals completion of an armed flush if its last transfer has finished.
+// Called from the IRQ and right after arming, in case the transfer finished
+// before the flush was armed.
+#[ram_code]
+fn complete_flush() {
+    critical_section::with(|cs| {
+        let channel = FLUSH_CHANNEL.load(Ordering::Relaxed);
+        let idle = unsafe { (*pac::DMA::PTR).ch[channel].ch_trans_count.read().bits() == 0 }
+            && FILTER_JOB.borrow(cs).get().ready == 0;
+        if !FLUSH_ARMED.load(Ordering::Relaxed) || !idle {
+            return;
+        }
+        FLUSH_ARMED.store(false, Ordering::Relaxed);
+        FLUSH_DONE.store(true, Ordering::Release);
+        if let Some(callback) = FLUSH_CALLBACK.borrow(cs).get() {
+            callback();
+        }
+        #[cfg(feature = "async")]
+        FLUSH_WAKER.wake();
+    });
+}
+
+fn arm_flush_irq() {
+    FLUSH_ARMED.store(true, Ordering::Relaxed);
+    complete_flush();
+}
+
+// Starts sending `pixels` pixels from `src`, which is the pixel at `index`
+// of a framebuffer, through the filters if there are any.
+#[ram_code]
+fn start_pixels(
+    dma_channel: &mut DmaChannel,
+    target: PixelTarget,
+    src: *const u16,
+    index: usize,
+    pixels: usize,
+) {
+    if !filtering() {
+        unsafe { target.start(dma_channel, src, pixels) };
+        return;
+    }
+    critical_section::with(|cs| {
+        let mut job = FilterJob {
+            target,
+            src: src as usize,
+            index,
+            remaining: pixels,
+            line: 0,
+            ready: 0,
+        };
+        job.prepare();
+        job.send();
+        FILTER_JOB.borrow(cs).set(job);
+    });
+}
+
+/// Called from IO_IRQ_BANK0. On the TE pin's rising edge, with TE sync,
+/// counts a vsync and starts the flush waiting for it.
+#[ram_code]
+pub(crate) fn handle_te_interrupt() {
+    let gpio = TE_SYNC_GPIO.load(Ordering::Relaxed);
+    if gpio == NO_TE_SYNC || !interrupts::gpio_interrupt_pending(gpio, GpioEvent::EdgeHigh) {
+        return;
+    }
+    VSYNC_COUNT.store(
+        VSYNC_COUNT.load(Ordering::Relaxed).wrapping_add(1),
+        Ordering::Release,
+    );
+    if let Some(flush) = critical_section::with(|cs| QUEUED_FLUSH.borrow(cs).take()) {
+        let mut dma_channel = unsafe { DmaChannel::new(FLUSH_CHANNEL.load(Ordering::Relaxed)) };
+        start_pixels(
+            &mut dma_channel,
+            flush.target,
+            flush.src as *const u16,
+            0,
+            flush.pixels,
+        );
+        arm_flush_irq();
+    }
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA_IRQ_0() {
+    let channel = FLUSH_CHANNEL.load(Ordering::Relaxed);
+    if dma::acknowledge_irq0(channel) {
+        critical_section::with(|cs| {
+            let job = FILTER_JOB.borrow(cs);
+            let mut next = job.get();
+            if next.ready > 0
+                && unsafe { (*pac::DMA::PTR).ch[channel].ch_trans_count.read().bits() == 0 }
+            {
+                next.send();
+                job.set(next);
+            }
+        });
+        complete_flush();
+    }
+}
+
+impl Display {
+    /// The channel fills use, lent to blits and other drawing into the
+    /// framebuffer so they don't claim one each time. Transfers started on
+    /// it must be waited for before it is given back.
+    pub(crate) fn draw_dma_channel(&mut self) -> &mut DmaChannel {
+        &mut self.fill_dma_channel
+    }
+
+    /// Fills the framebuffer with `color` by DMA, two pixels per transfer.
+    /// `clear` does the same.
+    pub fn clear_fast(&mut self, color: Rgb565) {
+        self.fill_rect_fast(self.bounding_box(), color);
+    }
+
+    /// Fills `area`, clipped to the screen, with `color` by DMA, a transfer
+    /// per row, or one for all of them if they are as wide as the screen.
+    /// `fill_solid`, and so filled rectangles, do the same.
+    pub fn fill_rect_fast(&mut self, area: Rectangle, color: Rgb565) {
+        let area = area.intersection(&self.bounding_box());
+        if area.bottom_right().is_none() {
+            return;
+        }
+        self.mark_dirty(area);
+        let pixel = RawU16::from(color).into_inner().to_be();
+        let fb = framebuffer();
+        let (x, y) = (area.top_left.x as usize, area.top_left.y as usize);
+        let (width, height) = (area.size.width as usize, area.size.height as usize);
+        let dma_channel = &mut self.fill_dma_channel;
+        if width == WIDTH {
+            fill_span(dma_channel, &mut fb[y * WIDTH..(y + height) * WIDTH], pixel);
+        } else {
+            for row in y..y + height {
+                let start = row * WIDTH + x;
+                fill_span(dma_channel, &mut fb[start..start + width], pixel);
+            }
+        }
+    }
+}
+
+// Spans shorter than this are quicker to fill than to set up a transfer for.
+const MIN_DMA_FILL: usize = 16;
+
+// Fills `span` with `pixel`, by DMA in 32-bit words where they are aligned.
+fn fill_span(dma_channel: &mut DmaChannel, span: &mut [u16], pixel: u16) {
+    if span.len() < MIN_DMA_FILL {
+        span.fill(pixel);
+        return;
+    }
+    let span = if !(span.as_ptr() as usize).is_multiple_of(4) {
+        span[0] = pixel;
+        &mut span[1..]
+    } else {
+        span
+    };
+    let words = span.len() / 2;
+    if !span.len().is_multiple_of(2) {
+        span[span.len() - 1] = pixel;
+    }
+    let word = (pixel as u32) << 16 | pixel as u32;
+    unsafe {
+        dma::set_mem(
+            dma_channel,
+            &word as *const u32 as u32,
+            span.as_mut_ptr() as u32,
+            4,
+            words as u32,
+        );
+    }
+}
+
 impl DrawTarget for Display {
     type Color = Rgb565;
     type Error = core::convert::Infallible;
@@ -174,15 +1287,21 @@ impl DrawTarget for Display {
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
         const M: u32 = WIDTH as u32 - 1;
-        const N: u32 = HEIGHT as u32 - 1;
+        let n = screen_height() as u32 - 1;
         let fb = framebuffer();
+        let mut bounds = PixelBounds::new();
         for Pixel(coord, color) in pixels.into_iter() {
-            if let Ok((x @ 0..=M, y @ 0..=N)) = coord.try_into() {
+            if let Ok((x @ 0..=M, y)) = coord.try_into() {
+                if y > n {
+                    continue;
+                }
                 let index: u32 = x + y * WIDTH as u32;
                 let color = RawU16::from(color).into_inner();
                 fb[index as usize] = color.to_be();
+                bounds.add(x, y);
             }
         }
+        self.mark_dirty(bounds.rectangle());
         Ok(())
     }
 
@@ -198,6 +1317,7 @@ impl DrawTarget for Display {
         let skip_top_left = clipped_area.top_left - area.top_left;
         let skip_bottom_right = area.bottom_right().unwrap() - clipped_area.bottom_right().unwrap();
 
+        self.mark_dirty(clipped_area);
         let fb = framebuffer();
         let mut colors = colors.into_iter();
 
@@ -229,34 +1349,19 @@ impl DrawTarget for Display {
     }
 
     fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
-        let color = RawU16::from(color).into_inner().to_be();
-        unsafe {
-            dma::set_mem(
-                &mut self.dma_channel,
-                &color as *const u16 as u32,
-                framebuffer().as_ptr() as u32,
-                2,
-                (WIDTH * HEIGHT) as u32,
-            );
-        }
-        if framebuffer()[0] != color {
-            log::info!(
-                "incorrect framebuffer[0], expected {} got {}",
-                color,
-                framebuffer()[0]
-            );
-        }
+        self.clear_fast(color);
         Ok(())
     }
 
     fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
-        self.fill_contiguous(area, core::iter::repeat(color))
+        self.fill_rect_fast(*area, color);
+        Ok(())
     }
 }
 
 impl OriginDimensions for Display {
     fn size(&self) -> Size {
-        Size::new(WIDTH as u32, HEIGHT as u32)
+        screen().size
     }
 }
 
@@ -279,14 +1384,21 @@ impl<'a> DrawTarget for XorDisplay<'a> {
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
         const M: u32 = WIDTH as u32 - 1;
+        let n = screen_height() as u32 - 1;
         let fb = framebuffer();
+        let mut bounds = PixelBounds::new();
         for Pixel(coord, color) in pixels.into_iter() {
-            if let Ok((x @ 0..=M, y @ 0..=M)) = coord.try_into() {
+            if let Ok((x @ 0..=M, y)) = coord.try_into() {
+                if y > n {
+                    continue;
+                }
                 let index: u32 = x + y * WIDTH as u32;
                 let color = RawU16::from(color).into_inner();
                 fb[index as usize] ^= color.to_be();
+                bounds.add(x, y);
             }
         }
+        self.display.mark_dirty(bounds.rectangle());
 
         Ok(())
     }
@@ -297,3 +1409,33 @@ impl<'a> OriginDimensions for XorDisplay<'a> {
         self.display.size()
     }
 }
+
+/// Bounding box of the pixels written by a `draw_iter` call.
+struct PixelBounds {
+    min: (u32, u32),
+    max: (u32, u32),
+}
+
+impl PixelBounds {
+    fn new() -> Self {
+        PixelBounds {
+            min: (u32::MAX, u32::MAX),
+            max: (0, 0),
+        }
+    }
+
+    fn add(&mut self, x: u32, y: u32) {
+        self.min = (self.min.0.min(x), self.min.1.min(y));
+        self.max = (self.max.0.max(x), self.max.1.max(y));
+    }
+
+    fn rectangle(&self) -> Rectangle {
+        if self.min.0 > self.max.0 {
+            return Rectangle::zero();
+        }
+        Rectangle::with_corners(
+            Point::new(self.min.0 as i32, self.min.1 as i32),
+            Point::new(self.max.0 as i32, self.max.1 as i32),
+        )
+    }
+}
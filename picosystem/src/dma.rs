@@ -1,24 +1,108 @@
 #![allow(clippy::missing_safety_doc)]
 
+#[cfg(feature = "async")]
+use crate::executor::WakerSlot;
+use crate::logging;
+use core::cell::Cell;
+use critical_section::Mutex;
 use rp2040_pac::dma::ch::ch_ctrl_trig::CH_CTRL_TRIG_SPEC as CtrlReg;
 use rp2040_pac::dma::ch::ch_ctrl_trig::W as CtrlWriter;
 use rp2040_pac::dma::CH;
 use rp2040_pac::generic::W;
+use rp_pico::hal::pac::interrupt;
 
 pub const CHANNEL_FRAMEBUFFER: usize = 0;
 pub const CHANNEL_TILE0: usize = 1;
 pub const CHANNEL_TILE1: usize = 2;
+pub const CHANNEL_FRAMEBUFFER_FILL: usize = 3;
+pub const CHANNEL_AUDIO: usize = 4;
+
+pub const NUM_CHANNELS: usize = 12;
+
+/// Channels with a fixed role in the library, skipped by `DmaManager::claim_any`.
+const RESERVED_CHANNELS: u16 = (1 << CHANNEL_FRAMEBUFFER)
+    | (1 << CHANNEL_TILE0)
+    | (1 << CHANNEL_TILE1)
+    | (1 << CHANNEL_FRAMEBUFFER_FILL)
+    | (1 << CHANNEL_AUDIO);
+
+/// Bitmap of channels currently handed out by `DmaManager`.
+static CLAIMED_CHANNELS: Mutex<Cell<u16>> = Mutex::new(Cell::new(0));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaError {
+    InvalidChannel(usize),
+    AlreadyClaimed(usize),
+    NoFreeChannel,
+}
+
+/// Hands out owned DMA channels. A claimed channel is released when its
+/// `DmaChannel` is dropped, so two users can never drive the same channel.
+pub struct DmaManager;
+
+impl DmaManager {
+    pub fn claim(channel: usize) -> Result<DmaChannel, DmaError> {
+        if channel >= NUM_CHANNELS {
+            return Err(DmaError::InvalidChannel(channel));
+        }
+        critical_section::with(|cs| {
+            let claimed = CLAIMED_CHANNELS.borrow(cs);
+            if claimed.get() & (1 << channel) != 0 {
+                return Err(DmaError::AlreadyClaimed(channel));
+            }
+            claimed.set(claimed.get() | (1 << channel));
+            Ok(())
+        })?;
+        let mut dma_channel = unsafe { DmaChannel::new(channel) };
+        dma_channel.claimed = true;
+        Ok(dma_channel)
+    }
+
+    pub fn claim_any() -> Result<DmaChannel, DmaError> {
+        let channel = critical_section::with(|cs| {
+            let claimed = CLAIMED_CHANNELS.borrow(cs);
+            let channel = (!(claimed.get() | RESERVED_CHANNELS)).trailing_zeros() as usize;
+            if channel >= NUM_CHANNELS {
+                return Err(DmaError::NoFreeChannel);
+            }
+            claimed.set(claimed.get() | (1 << channel));
+            Ok(channel)
+        })?;
+        let mut dma_channel = unsafe { DmaChannel::new(channel) };
+        dma_channel.claimed = true;
+        Ok(dma_channel)
+    }
+
+    pub fn is_claimed(channel: usize) -> bool {
+        critical_section::with(|cs| CLAIMED_CHANNELS.borrow(cs).get() & (1 << channel) != 0)
+    }
+
+    fn release(channel: usize) {
+        critical_section::with(|cs| {
+            let claimed = CLAIMED_CHANNELS.borrow(cs);
+            claimed.set(claimed.get() & !(1 << channel));
+        });
+    }
+}
 
 pub struct DmaChannel {
     pub channel: usize,
     pub ch: &'static CH,
+    claimed: bool,
 }
 
+// A handle is the only one to its channel, so it can be handed to the other
+// core, like the render server's.
+unsafe impl Send for DmaChannel {}
+
 impl DmaChannel {
+    /// Creates an untracked handle to `channel`. Prefer `DmaManager::claim`,
+    /// which guarantees exclusive access.
     pub unsafe fn new(channel: usize) -> Self {
         DmaChannel {
             channel,
             ch: &(*rp2040_pac::DMA::PTR).ch[channel],
+            claimed: false,
         }
     }
 
@@ -56,6 +140,50 @@ impl DmaChannel {
     pub fn get_count(&self) -> u32 {
         self.ch.ch_trans_count.read().bits()
     }
+
+    /// Stops the transfer in progress, waiting until the channel is idle.
+    pub fn abort(&mut self) {
+        let mask = 1 << self.channel;
+        unsafe {
+            let dma = &*rp2040_pac::DMA::PTR;
+            dma.chan_abort.write(|w| w.bits(mask));
+            while dma.chan_abort.read().bits() & mask != 0 {}
+        }
+    }
+
+    /// Raises DMA_IRQ_0 whenever a transfer on this channel completes.
+    pub fn set_irq0_enabled(&mut self, enabled: bool) {
+        let mask = 1 << self.channel;
+        unsafe {
+            (*rp2040_pac::DMA::PTR).inte0.modify(|r, w| {
+                if enabled {
+                    w.bits(r.bits() | mask)
+                } else {
+                    w.bits(r.bits() & !mask)
+                }
+            });
+        }
+    }
+}
+
+/// Clears a pending DMA_IRQ_0 for `channel`, returning whether it was set.
+pub fn acknowledge_irq0(channel: usize) -> bool {
+    let mask = 1 << channel;
+    let dma = unsafe { &*rp2040_pac::DMA::PTR };
+    if dma.ints0.read().bits() & mask == 0 {
+        return false;
+    }
+    dma.ints0.write(|w| unsafe { w.bits(mask) });
+    true
+}
+
+impl Drop for DmaChannel {
+    fn drop(&mut self) {
+        if self.claimed {
+            self.wait();
+            DmaManager::release(self.channel);
+        }
+    }
 }
 
 fn wordsize(elem_size: u32) -> u32 {
@@ -167,10 +295,28 @@ pub unsafe fn copy_mem_bswap(
 }
 
 pub unsafe fn copy_flash_to_mem(dma_channel: &mut DmaChannel, src: u32, dst: u32, count: u32) {
+    copy_flash(dma_channel, src, dst, count, false);
+}
+
+/// Copies `count` words from flash like `copy_flash_to_mem` while `sniffer`
+/// checksums them, and returns their CRC-32.
+pub unsafe fn copy_flash_to_mem_crc32(
+    dma_channel: &mut DmaChannel,
+    sniffer: &mut Sniffer,
+    src: u32,
+    dst: u32,
+    count: u32,
+) -> u32 {
+    sniffer.start_crc32(dma_channel);
+    copy_flash(dma_channel, src, dst, count, true);
+    sniffer.crc32()
+}
+
+unsafe fn copy_flash(dma_channel: &mut DmaChannel, src: u32, dst: u32, count: u32, sniff: bool) {
     // Flush XIP FIFO.
     let xip_ctrl = &*rp_pico::pac::XIP_CTRL::PTR;
     while xip_ctrl.stat.read().fifo_empty().bit_is_clear() {
-        log::info!("XIP FIFO not empty");
+        logging::info!("XIP FIFO not empty");
         cortex_m::asm::nop();
     }
     xip_ctrl.stream_addr.write(|w| w.bits(src));
@@ -181,6 +327,7 @@ pub unsafe fn copy_flash_to_mem(dma_channel: &mut DmaChannel, src: u32, dst: u32
     dma_channel.set_dst(dst);
     dma_channel.set_count(count);
     dma_channel.set_ctrl_and_trigger(|w| {
+        w.sniff_en().bit(sniff);
         w.treq_sel().bits(37); // DREQ_XIP_STREAM
         w.chain_to().bits(channel as u8);
         w.incr_write().set_bit();
@@ -191,15 +338,23 @@ pub unsafe fn copy_flash_to_mem(dma_channel: &mut DmaChannel, src: u32, dst: u32
     dma_channel.wait();
 
     while xip_ctrl.stat.read().fifo_empty().bit_is_clear() {
-        log::info!("XIP FIFO not empty");
+        logging::info!("XIP FIFO not empty");
         cortex_m::asm::nop();
     }
 }
 
-pub(crate) unsafe fn start_copy_to_spi(
+pub(crate) const DREQ_SPI0_TX: u8 = 16;
+pub(crate) const DREQ_PWM_WRAP0: u8 = 24;
+
+/// Starts copying `count` elements from `src` to the peripheral register
+/// `dst`, paced by the DREQ `treq`. With `bswap` the bytes of each element
+/// are reversed.
+pub(crate) unsafe fn start_copy_to_peripheral(
     dma_channel: &mut DmaChannel,
     src: u32,
     dst: u32,
+    treq: u8,
+    bswap: bool,
     elem_size: u32,
     count: u32,
 ) {
@@ -208,7 +363,8 @@ pub(crate) unsafe fn start_copy_to_spi(
     dma_channel.set_dst(dst);
     dma_channel.set_count(count);
     dma_channel.set_ctrl_and_trigger(|w| {
-        w.treq_sel().bits(16); // SPI0 TX
+        w.bswap().bit(bswap);
+        w.treq_sel().bits(treq);
         w.chain_to().bits(channel as u8);
         w.incr_read().set_bit();
         w.data_size().bits(wordsize(elem_size) as u8);
@@ -216,3 +372,396 @@ pub(crate) unsafe fn start_copy_to_spi(
         w
     });
 }
+
+/// Starts sending the words of the ring buffer at `src`, `1 << ring_bits`
+/// bytes aligned to its size, to the peripheral register `dst` over and
+/// over, paced by the DREQ `treq`. It runs for `u32::MAX` words or until
+/// the channel is aborted.
+pub(crate) unsafe fn start_ring_to_peripheral(
+    dma_channel: &mut DmaChannel,
+    src: u32,
+    ring_bits: u8,
+    dst: u32,
+    treq: u8,
+) {
+    let channel = dma_channel.channel;
+    dma_channel.set_src(src);
+    dma_channel.set_dst(dst);
+    dma_channel.set_count(u32::MAX);
+    dma_channel.set_ctrl_and_trigger(|w| {
+        w.treq_sel().bits(treq);
+        w.chain_to().bits(channel as u8);
+        w.ring_size().bits(ring_bits);
+        w.incr_read().set_bit();
+        w.data_size().bits(wordsize(4) as u8);
+        w.en().set_bit();
+        w
+    });
+}
+
+// Fields of a channel's CTRL register, for building control blocks.
+const CTRL_EN: u32 = 1 << 0;
+const CTRL_DATA_SIZE_SHIFT: u32 = 2;
+const CTRL_INCR_READ: u32 = 1 << 4;
+const CTRL_INCR_WRITE: u32 = 1 << 5;
+const CTRL_CHAIN_TO_SHIFT: u32 = 11;
+const CTRL_CHAIN_TO_MASK: u32 = 0xf << CTRL_CHAIN_TO_SHIFT;
+const CTRL_TREQ_SHIFT: u32 = 15;
+const TREQ_PERMANENT: u32 = 0x3f;
+const CTRL_IRQ_QUIET: u32 = 1 << 21;
+const CTRL_BSWAP: u32 = 1 << 22;
+
+/// One transfer of a list run by a `ChainedDma`, laid out like the first
+/// alias of a channel's registers, which the control channel writes it to.
+///
+/// Blocks don't raise interrupts themselves. A list ends with `END`, whose
+/// zero count doesn't start a transfer but signals that the list is done.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlBlock {
+    ctrl: u32,
+    read_addr: u32,
+    write_addr: u32,
+    count: u32,
+}
+
+impl ControlBlock {
+    pub const END: ControlBlock = ControlBlock {
+        ctrl: CTRL_EN | CTRL_IRQ_QUIET,
+        read_addr: 0,
+        write_addr: 0,
+        count: 0,
+    };
+
+    const fn new(ctrl: u32, src: u32, dst: u32, elem_size: u32, count: u32) -> Self {
+        let data_size = match elem_size {
+            1 => 0,
+            2 => 1,
+            4 => 2,
+            _ => panic!("invalid DMA element size"),
+        };
+        ControlBlock {
+            ctrl: ctrl | CTRL_EN | CTRL_IRQ_QUIET | data_size << CTRL_DATA_SIZE_SHIFT,
+            read_addr: src,
+            write_addr: dst,
+            count,
+        }
+    }
+
+    /// Like `set_mem`, fills `count` elements at `dst` with the one at `src`.
+    pub const fn set_mem(src: u32, dst: u32, elem_size: u32, count: u32) -> Self {
+        let ctrl = TREQ_PERMANENT << CTRL_TREQ_SHIFT | CTRL_INCR_WRITE;
+        Self::new(ctrl, src, dst, elem_size, count)
+    }
+
+    /// Like `copy_mem`, copies `count` elements from `src` to `dst`.
+    pub const fn copy_mem(src: u32, dst: u32, elem_size: u32, count: u32) -> Self {
+        let ctrl = TREQ_PERMANENT << CTRL_TREQ_SHIFT | CTRL_INCR_READ | CTRL_INCR_WRITE;
+        Self::new(ctrl, src, dst, elem_size, count)
+    }
+
+    /// Copies `count` elements from `src` to the peripheral register `dst`,
+    /// paced by the DREQ `treq`. With `bswap` the bytes of each element are
+    /// reversed.
+    pub const fn copy_to_peripheral(
+        src: u32,
+        dst: u32,
+        treq: u8,
+        bswap: bool,
+        elem_size: u32,
+        count: u32,
+    ) -> Self {
+        let bswap = if bswap { CTRL_BSWAP } else { 0 };
+        let ctrl = (treq as u32) << CTRL_TREQ_SHIFT | CTRL_INCR_READ | bswap;
+        Self::new(ctrl, src, dst, elem_size, count)
+    }
+}
+
+pub type ChainCallback = fn();
+
+/// Per channel, set by DMA_IRQ_1 when a list finished on it.
+static CHAINS_DONE: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+/// Per channel, called by DMA_IRQ_1 when a list finished on it.
+static CHAIN_CALLBACKS: Mutex<Cell<[Option<ChainCallback>; NUM_CHANNELS]>> =
+    Mutex::new(Cell::new([None; NUM_CHANNELS]));
+/// Per channel, the task waiting in `ChainedDma::done`.
+#[cfg(feature = "async")]
+static CHAIN_WAKERS: [WakerSlot; NUM_CHANNELS] = [const { WakerSlot::new() }; NUM_CHANNELS];
+
+/// Runs lists of `ControlBlock`s one after another without the CPU.
+///
+/// The control channel copies each block into the data channel's
+/// registers, which starts its transfer, and the data channel chains back
+/// to the control channel when it is done, which loads the next block.
+/// Completion can be polled with `is_done` or reported by DMA_IRQ_1 with
+/// `set_callback`.
+pub struct ChainedDma {
+    data: DmaChannel,
+    control: DmaChannel,
+}
+
+impl ChainedDma {
+    pub fn new(data: DmaChannel, control: DmaChannel) -> Self {
+        ChainedDma { data, control }
+    }
+
+    /// Claims two free channels.
+    pub fn claim_any() -> Result<Self, DmaError> {
+        let data = DmaManager::claim_any()?;
+        let control = DmaManager::claim_any()?;
+        Ok(Self::new(data, control))
+    }
+
+    /// Starts running `blocks`, which must end with `ControlBlock::END`.
+    /// Waits for the list before if it is still running.
+    ///
+    /// # Safety
+    ///
+    /// `blocks` and the memory their transfers read and write must stay
+    /// valid until the list is done.
+    pub unsafe fn start(&mut self, blocks: &mut [ControlBlock]) {
+        assert_eq!(blocks.last(), Some(&ControlBlock::END));
+        self.wait();
+        let chain_to = (self.control.channel as u32) << CTRL_CHAIN_TO_SHIFT;
+        for block in blocks.iter_mut() {
+            block.ctrl = block.ctrl & !CTRL_CHAIN_TO_MASK | chain_to;
+        }
+
+        let mask = 1 << self.data.channel;
+        critical_section::with(|cs| {
+            let done = CHAINS_DONE.borrow(cs);
+            done.set(done.get() & !mask);
+        });
+        // Clears the raw status as well.
+        (*rp2040_pac::DMA::PTR).ints1.write(|w| w.bits(mask));
+
+        let channel = self.control.channel;
+        self.control.set_src(blocks.as_ptr() as u32);
+        self.control
+            .set_dst(self.data.ch.ch_al1_ctrl.as_ptr() as u32);
+        self.control.set_count(4);
+        self.control.set_ctrl_and_trigger(|w| {
+            w.treq_sel().permanent();
+            w.chain_to().bits(channel as u8);
+            // Writes wrap around the four registers of the alias.
+            w.ring_sel().set_bit();
+            w.ring_size().bits(4);
+            w.incr_read().set_bit();
+            w.incr_write().set_bit();
+            w.data_size().bits(2);
+            w.en().set_bit();
+            w
+        });
+    }
+
+    /// Whether the last list started has finished.
+    pub fn is_done(&self) -> bool {
+        let mask = 1 << self.data.channel;
+        let raw = unsafe { (*rp2040_pac::DMA::PTR).intr.read().bits() };
+        raw & mask != 0 || critical_section::with(|cs| CHAINS_DONE.borrow(cs).get() & mask != 0)
+    }
+
+    /// Waits for the last list started to finish, if any is running.
+    pub fn wait(&self) {
+        let busy = |channel: &DmaChannel| channel.ch.ch_ctrl_trig.read().busy().bit_is_set();
+        while busy(&self.control) || busy(&self.data) {}
+    }
+
+    /// Waits for the last list started to finish in an async task. Leaves
+    /// DMA_IRQ_1 enabled for the data channel.
+    #[cfg(feature = "async")]
+    pub async fn done(&mut self) {
+        let channel = self.data.channel;
+        unsafe {
+            (*rp2040_pac::DMA::PTR)
+                .inte1
+                .modify(|r, w| w.bits(r.bits() | 1 << channel));
+            rp_pico::pac::NVIC::unmask(rp_pico::pac::Interrupt::DMA_IRQ_1);
+        }
+        core::future::poll_fn(|cx| {
+            CHAIN_WAKERS[channel].register(cx.waker());
+            if self.is_done() {
+                core::task::Poll::Ready(())
+            } else {
+                core::task::Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Calls `callback` from DMA_IRQ_1 whenever a list finishes, or stops
+    /// the interrupt with `None`.
+    pub fn set_callback(&mut self, callback: Option<ChainCallback>) {
+        let channel = self.data.channel;
+        critical_section::with(|cs| {
+            let callbacks = CHAIN_CALLBACKS.borrow(cs);
+            let mut all = callbacks.get();
+            all[channel] = callback;
+            callbacks.set(all);
+        });
+        let mask = 1 << channel;
+        unsafe {
+            (*rp2040_pac::DMA::PTR).inte1.modify(|r, w| {
+                if callback.is_some() {
+                    w.bits(r.bits() | mask)
+                } else {
+                    w.bits(r.bits() & !mask)
+                }
+            });
+            if callback.is_some() {
+                rp_pico::pac::NVIC::unmask(rp_pico::pac::Interrupt::DMA_IRQ_1);
+            }
+        }
+    }
+
+    /// Returns the channels, once the list running is done.
+    pub fn free(mut self) -> (DmaChannel, DmaChannel) {
+        self.wait();
+        self.set_callback(None);
+        (self.data, self.control)
+    }
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA_IRQ_1() {
+    let dma = unsafe { &*rp2040_pac::DMA::PTR };
+    let pending = dma.ints1.read().bits();
+    dma.ints1.write(|w| unsafe { w.bits(pending) });
+    let callbacks = critical_section::with(|cs| {
+        let done = CHAINS_DONE.borrow(cs);
+        done.set(done.get() | pending);
+        CHAIN_CALLBACKS.borrow(cs).get()
+    });
+    for (channel, callback) in callbacks.iter().enumerate() {
+        if let (true, Some(callback)) = (pending & 1 << channel != 0, callback) {
+            callback();
+        }
+        #[cfg(feature = "async")]
+        if pending & 1 << channel != 0 {
+            CHAIN_WAKERS[channel].wake();
+        }
+    }
+}
+
+static SNIFFER_CLAIMED: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// The DMA sniffer, which checksums the data one channel transfers as it
+/// goes, at no cost to the CPU. There is only one, shared by all channels,
+/// and only transfers started with sniffing enabled, such as by
+/// `copy_mem_crc32`, are checksummed.
+pub struct Sniffer {
+    _private: (),
+}
+
+impl Sniffer {
+    /// Returns `None` if the sniffer is in use.
+    pub fn claim() -> Option<Self> {
+        critical_section::with(|cs| {
+            let claimed = SNIFFER_CLAIMED.borrow(cs);
+            if claimed.get() {
+                return None;
+            }
+            claimed.set(true);
+            Some(Sniffer { _private: () })
+        })
+    }
+
+    /// Starts a CRC-32 of the data `dma_channel` transfers next. The result
+    /// matches `picosystem_compressor::crc32` of the bytes in memory order.
+    pub fn start_crc32(&mut self, dma_channel: &DmaChannel) {
+        let dma = unsafe { &*rp2040_pac::DMA::PTR };
+        dma.sniff_data.write(|w| unsafe { w.bits(!0) });
+        dma.sniff_ctrl.write(|w| {
+            unsafe { w.dmach().bits(dma_channel.channel as u8) };
+            w.calc().crc32r();
+            w.out_rev().set_bit();
+            w.out_inv().set_bit();
+            w.en().set_bit()
+        });
+    }
+
+    /// The CRC-32 of the data sniffed since `start_crc32`, once its
+    /// transfers are done.
+    pub fn crc32(&self) -> u32 {
+        unsafe { (*rp2040_pac::DMA::PTR).sniff_data.read().bits() }
+    }
+}
+
+impl Drop for Sniffer {
+    fn drop(&mut self) {
+        unsafe { (*rp2040_pac::DMA::PTR).sniff_ctrl.write(|w| w.bits(0)) };
+        critical_section::with(|cs| SNIFFER_CLAIMED.borrow(cs).set(false));
+    }
+}
+
+/// Copies `count` elements from `src` to `dst` while `sniffer` checksums
+/// them, and returns their CRC-32.
+pub unsafe fn copy_mem_crc32(
+    dma_channel: &mut DmaChannel,
+    sniffer: &mut Sniffer,
+    src: u32,
+    dst: u32,
+    elem_size: u32,
+    count: u32,
+) -> u32 {
+    sniffer.start_crc32(dma_channel);
+    start_sniffed(dma_channel, src, dst, true, elem_size, count);
+    dma_channel.wait();
+    sniffer.crc32()
+}
+
+unsafe fn start_sniffed(
+    dma_channel: &mut DmaChannel,
+    src: u32,
+    dst: u32,
+    incr_write: bool,
+    elem_size: u32,
+    count: u32,
+) {
+    let channel = dma_channel.channel;
+    dma_channel.set_src(src);
+    dma_channel.set_dst(dst);
+    dma_channel.set_count(count);
+    dma_channel.set_ctrl_and_trigger(|w| {
+        w.sniff_en().set_bit();
+        w.treq_sel().permanent();
+        w.chain_to().bits(channel as u8);
+        w.incr_write().bit(incr_write);
+        w.incr_read().set_bit();
+        w.data_size().bits(wordsize(elem_size) as u8);
+        w.en().set_bit();
+        w
+    });
+}
+
+/// The CRC-32 of `region`, in RAM or flash, computed by the sniffer as a
+/// DMA channel reads it. Falls back to the CPU when the sniffer or every
+/// channel is in use.
+pub fn crc32(region: &[u8]) -> u32 {
+    let (Some(mut sniffer), Ok(mut dma_channel)) = (Sniffer::claim(), DmaManager::claim_any())
+    else {
+        return picosystem_compressor::crc32(region);
+    };
+    let address = region.as_ptr() as u32;
+    let elem_size = if address.is_multiple_of(4) && region.len().is_multiple_of(4) {
+        4
+    } else {
+        1
+    };
+    // The data is only read, so every element lands in the same word.
+    let mut sink = 0u32;
+    sniffer.start_crc32(&dma_channel);
+    unsafe {
+        start_sniffed(
+            &mut dma_channel,
+            address,
+            &mut sink as *mut u32 as u32,
+            false,
+            elem_size,
+            region.len() as u32 / elem_size,
+        );
+    }
+    dma_channel.wait();
+    sniffer.crc32()
+}
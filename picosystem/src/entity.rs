@@ -0,0 +1,247 @@
+//! Game objects in fixed-capacity pools.
+//!
+//! An `EntityPool<T, N>` holds up to `N` objects of one type, such as the
+//! enemies or the bullets of a game. `spawn` returns a `Handle` to the new
+//! object, which stays valid until the object is despawned. A slot that is
+//! reused gets a new generation, so an old handle to it finds nothing
+//! instead of the new object.
+//!
+//! Types implementing `Update` and `Draw` can be stepped and drawn a pool
+//! at a time. `Update` takes a context of the game's choosing, usually a
+//! struct with the input, the player and the pools of other types, so an
+//! enemy can fire by spawning into the bullet pool. Objects whose update
+//! returns false are despawned. An object can't spawn into its own pool
+//! while the pool is being updated, so such spawns are collected in the
+//! context and made afterwards.
+
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+
+/// Refers to an object in an `EntityPool<T, N>`.
+pub struct Handle<T> {
+    index: u16,
+    generation: u16,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    /// Slot of the object in its pool, less than the pool's capacity.
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+}
+
+// Written out as deriving would require `T` to implement them too.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+/// Steps an object by a frame.
+pub trait Update<Context: ?Sized> {
+    /// Returns false to despawn the object.
+    fn update(&mut self, context: &mut Context) -> bool;
+}
+
+pub trait Draw {
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>;
+}
+
+struct Slot<T> {
+    // Bumped every time the slot's object is despawned.
+    generation: u16,
+    object: Option<T>,
+}
+
+/// A pool of up to `N` objects, at most 65536. Objects spawned while it is
+/// full are dropped.
+pub struct EntityPool<T, const N: usize> {
+    // Grown as objects are spawned, and never shrunk so that the slots
+    // keep their generations.
+    slots: heapless::Vec<Slot<T>, N>,
+    // Indices of the empty slots.
+    free: heapless::Vec<u16, N>,
+    len: usize,
+}
+
+impl<T, const N: usize> EntityPool<T, N> {
+    pub const fn new() -> Self {
+        EntityPool {
+            slots: heapless::Vec::new(),
+            free: heapless::Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Adds `object`, returning `None` if the pool is full.
+    pub fn spawn(&mut self, object: T) -> Option<Handle<T>> {
+        let index = match self.free.pop() {
+            Some(index) => index as usize,
+            None => {
+                self.slots
+                    .push(Slot {
+                        generation: 0,
+                        object: None,
+                    })
+                    .ok()?;
+                self.slots.len() - 1
+            }
+        };
+        let slot = &mut self.slots[index];
+        slot.object = Some(object);
+        self.len += 1;
+        Some(handle(index, slot.generation))
+    }
+
+    /// Removes the object `handle` refers to and returns it, or `None` if
+    /// it was already despawned.
+    pub fn despawn(&mut self, handle: Handle<T>) -> Option<T> {
+        let slot = self.slots.get_mut(handle.index())?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        let object = slot.object.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        // There is room, as every slot is in `free` at most once.
+        let _ = self.free.push(handle.index);
+        self.len -= 1;
+        Some(object)
+    }
+
+    /// Despawns every object, so handles to them find nothing.
+    pub fn clear(&mut self) {
+        for index in 0..self.slots.len() {
+            self.despawn(handle(index, self.slots[index].generation));
+        }
+    }
+
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        let slot = self.slots.get(handle.index())?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.object.as_ref()
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        let slot = self.slots.get_mut(handle.index())?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.object.as_mut()
+    }
+
+    /// The objects with their handles, in slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let object = slot.object.as_ref()?;
+            Some((handle(index, slot.generation), object))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<T>, &mut T)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let object = slot.object.as_mut()?;
+                Some((handle(index, slot.generation), object))
+            })
+    }
+
+    /// Keeps only the objects for which `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(Handle<T>, &mut T) -> bool) {
+        for index in 0..self.slots.len() {
+            let slot = &mut self.slots[index];
+            let handle = handle(index, slot.generation);
+            if let Some(object) = &mut slot.object {
+                if !keep(handle, object) {
+                    self.despawn(handle);
+                }
+            }
+        }
+    }
+
+    /// Updates every object, despawning those whose update returns false.
+    pub fn update_all<C: ?Sized>(&mut self, context: &mut C)
+    where
+        T: Update<C>,
+    {
+        self.retain(|_, object| object.update(context));
+    }
+
+    /// Draws every object in slot order.
+    pub fn draw_all<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        T: Draw,
+        D: DrawTarget<Color = Rgb565>,
+    {
+        for (_, object) in self.iter() {
+            object.draw(target)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T, const N: usize> Default for EntityPool<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn handle<T>(index: usize, generation: u16) -> Handle<T> {
+    Handle {
+        index: index as u16,
+        generation,
+        _type: PhantomData,
+    }
+}
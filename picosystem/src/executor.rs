@@ -0,0 +1,207 @@
+//! Async tasks, for writing a game as a few tasks, such as one rendering,
+//! one playing music and one talking over the link, each awaiting what it
+//! waits for instead of everything being polled from one loop.
+//!
+//! Tasks are futures pinned by the caller and polled on the core calling
+//! `Executor::run`, which sleeps while none of them can go on. Flushes,
+//! DMA lists, button events, the link and `time::sleep` wake the tasks
+//! waiting for them from their interrupts:
+//!
+//! ```ignore
+//! let mut executor = Executor::new();
+//! executor.run(&mut [
+//!     pin!(render(&mut hw.display)),
+//!     pin!(play(&mut hw.audio)),
+//! ]);
+//! ```
+
+use crate::time::{self, Alarm};
+use core::cell::{Cell, RefCell};
+use core::future::{poll_fn, Future};
+use core::pin::Pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use critical_section::Mutex;
+
+pub const MAX_TASKS: usize = 32;
+
+// Sleeps pending at once, across all tasks.
+const MAX_TIMERS: usize = 16;
+
+pub type Task<'a> = Pin<&'a mut dyn Future<Output = ()>>;
+
+// Bit per task, set when it is woken.
+static READY: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+// The alarm waking sleeping tasks, claimed by `Executor::new`.
+static TIMER_ALARM: Mutex<Cell<Option<usize>>> = Mutex::new(Cell::new(None));
+
+// When a sleeping task wakes, in microseconds since startup.
+type Timer = Option<(u64, Waker)>;
+
+static TIMERS: Mutex<RefCell<[Timer; MAX_TIMERS]>> =
+    Mutex::new(RefCell::new([const { None }; MAX_TIMERS]));
+
+// The waker of a task is its index.
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+unsafe fn clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+unsafe fn wake(data: *const ()) {
+    critical_section::with(|cs| {
+        let ready = READY.borrow(cs);
+        ready.set(ready.get() | 1 << data as usize);
+    });
+    // Ends the wfe of `run`, even if it hasn't started yet.
+    cortex_m::asm::sev();
+}
+
+unsafe fn drop(_: *const ()) {}
+
+fn task_waker(index: usize) -> Waker {
+    unsafe { Waker::from_raw(RawWaker::new(index as *const (), &VTABLE)) }
+}
+
+/// Runs tasks until they have all finished. There is one at a time.
+pub struct Executor {
+    _alarm: Alarm,
+}
+
+impl Executor {
+    /// Claims a free timer alarm, for `time::sleep`.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let alarm = Alarm::claim_any().expect("no free alarm for the executor");
+        critical_section::with(|cs| TIMER_ALARM.borrow(cs).set(Some(alarm.index())));
+        Executor { _alarm: alarm }
+    }
+
+    /// Polls each task once, then again whenever it is woken, and returns
+    /// once all have finished.
+    pub fn run(&mut self, tasks: &mut [Task]) {
+        assert!(tasks.len() <= MAX_TASKS, "too many tasks");
+        let mut pending = ((1u64 << tasks.len()) - 1) as u32;
+        critical_section::with(|cs| READY.borrow(cs).set(pending));
+        while pending != 0 {
+            let ready = critical_section::with(|cs| READY.borrow(cs).replace(0)) & pending;
+            if ready == 0 {
+                // A wake since the check has set the event, so this
+                // returns straight away rather than miss it.
+                cortex_m::asm::wfe();
+                continue;
+            }
+            for (index, task) in tasks.iter_mut().enumerate() {
+                if ready & 1 << index == 0 {
+                    continue;
+                }
+                let waker = task_waker(index);
+                if task
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_ready()
+                {
+                    pending &= !(1 << index);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        critical_section::with(|cs| {
+            TIMER_ALARM.borrow(cs).set(None);
+            for timer in TIMERS.borrow_ref_mut(cs).iter_mut() {
+                *timer = None;
+            }
+        });
+    }
+}
+
+/// Lets the other tasks run before going on.
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+/// Where a task waiting for an interrupt leaves its waker, for the
+/// interrupt to wake it. One task waits on each.
+pub struct WakerSlot(Mutex<RefCell<Option<Waker>>>);
+
+impl WakerSlot {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        WakerSlot(Mutex::new(RefCell::new(None)))
+    }
+
+    pub fn register(&self, waker: &Waker) {
+        critical_section::with(|cs| {
+            let mut slot = self.0.borrow_ref_mut(cs);
+            if !slot.as_ref().is_some_and(|w| w.will_wake(waker)) {
+                *slot = Some(waker.clone());
+            }
+        });
+    }
+
+    pub fn wake(&self) {
+        if let Some(waker) = critical_section::with(|cs| self.0.borrow_ref_mut(cs).take()) {
+            waker.wake();
+        }
+    }
+}
+
+/// Wakes `waker` at `deadline_us` since startup. Sleeps given up before
+/// then still wake their task, once, which polls it for nothing.
+pub(crate) fn wake_at(deadline_us: u64, waker: &Waker) {
+    critical_section::with(|cs| {
+        let mut timers = TIMERS.borrow_ref_mut(cs);
+        let queued = timers
+            .iter()
+            .flatten()
+            .any(|(deadline, w)| *deadline == deadline_us && w.will_wake(waker));
+        if !queued {
+            let free = timers
+                .iter_mut()
+                .find(|timer| timer.is_none())
+                .expect("too many sleeping tasks");
+            *free = Some((deadline_us, waker.clone()));
+            schedule_timers(&timers[..]);
+        }
+    });
+}
+
+// Sets the alarm for the first sleep to end.
+fn schedule_timers(timers: &[Timer]) {
+    let Some(next) = timers.iter().flatten().map(|(deadline, _)| *deadline).min() else {
+        return;
+    };
+    let index = critical_section::with(|cs| TIMER_ALARM.borrow(cs).get())
+        .expect("sleeping needs an Executor");
+    // Further away than the alarm reaches it wakes early and is set again.
+    let delay_us = next.saturating_sub(time::time_us64()).min(i32::MAX as u64) as u32;
+    unsafe { Alarm::new(index) }.schedule_once(delay_us, wake_timers);
+}
+
+// Wakes the tasks whose sleep has ended, on the executor's alarm.
+fn wake_timers() -> bool {
+    let now = time::time_us64();
+    critical_section::with(|cs| {
+        let mut timers = TIMERS.borrow_ref_mut(cs);
+        for timer in timers.iter_mut() {
+            if timer.as_ref().is_some_and(|(deadline, _)| *deadline <= now) {
+                timer.take().unwrap().1.wake();
+            }
+        }
+        schedule_timers(&timers[..]);
+    });
+    false
+}
@@ -0,0 +1,133 @@
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spacing {
+    /// Every glyph advances by the font's advance.
+    Monospace,
+    /// Glyphs advance by their own width plus one pixel.
+    Proportional,
+}
+
+/// A glyph's bitmap, trimmed to the pixels that are set.
+#[derive(Debug)]
+pub struct Glyph {
+    pub code: u32,
+    /// Offset of the bitmap from the glyph's top left corner, whose top is
+    /// the top of the line.
+    pub x_offset: i8,
+    pub y_offset: i8,
+    pub width: u8,
+    pub height: u8,
+    pub advance: u8,
+    /// Offset in bits of the glyph's bitmap in `Font::bitmap`. Rows are
+    /// packed one bit per pixel, most significant bit first.
+    pub bit_offset: u32,
+}
+
+impl Glyph {
+    fn advance(&self, font: &Font) -> i32 {
+        match font.spacing {
+            Spacing::Monospace => font.advance as i32,
+            Spacing::Proportional if self.width == 0 => self.advance as i32,
+            Spacing::Proportional => self.x_offset as i32 + self.width as i32 + 1,
+        }
+    }
+
+    fn bounding_box(&self, position: Point) -> Rectangle {
+        Rectangle::new(
+            position + Point::new(self.x_offset as i32, self.y_offset as i32),
+            Size::new(self.width as u32, self.height as u32),
+        )
+    }
+}
+
+/// A bitmap font compiled by the `font!` macro.
+#[derive(Debug, Clone, Copy)]
+pub struct Font {
+    pub line_height: u32,
+    /// Distance from the top of the line to the baseline.
+    pub ascent: u32,
+    /// The widest glyph's advance, used for monospace spacing.
+    pub advance: u32,
+    pub spacing: Spacing,
+    /// Sorted by code.
+    pub glyphs: &'static [Glyph],
+    pub bitmap: &'static [u8],
+}
+
+impl Font {
+    pub fn with_spacing(self, spacing: Spacing) -> Font {
+        Font { spacing, ..self }
+    }
+
+    /// Returns the glyph for `c`, or for '?' if the font doesn't have it.
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        let find = |code: u32| {
+            self.glyphs
+                .binary_search_by_key(&code, |g| g.code)
+                .ok()
+                .map(|i| &self.glyphs[i])
+        };
+        find(c as u32).or_else(|| find('?' as u32))
+    }
+
+    /// Size of `text` when drawn, with lines separated by '\n'.
+    pub fn measure(&self, text: &str) -> Size {
+        let mut width = 0;
+        let mut lines = 0;
+        for line in text.split('\n') {
+            let line_width: i32 = line
+                .chars()
+                .filter_map(|c| self.glyph(c))
+                .map(|g| g.advance(self))
+                .sum();
+            width = width.max(line_width as u32);
+            lines += 1;
+        }
+        Size::new(width, lines * self.line_height)
+    }
+
+    /// Draws `text` with the top left corner of its first line at
+    /// `position`, clipped to the target. Returns the position after the last
+    /// glyph.
+    pub fn draw_text<D>(&self, target: &mut D, position: Point, color: Rgb565, text: &str) -> Point
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let bounds = target.bounding_box();
+        let mut cursor = position;
+        for c in text.chars() {
+            if c == '\n' {
+                cursor = Point::new(position.x, cursor.y + self.line_height as i32);
+                continue;
+            }
+            let Some(glyph) = self.glyph(c) else {
+                continue;
+            };
+            let area = glyph.bounding_box(cursor);
+            if !area.intersection(&bounds).is_zero_sized() {
+                let _ = target.draw_iter(
+                    self.glyph_points(glyph, area.top_left)
+                        .map(|point| Pixel(point, color)),
+                );
+            }
+            cursor.x += glyph.advance(self);
+        }
+        cursor
+    }
+
+    fn glyph_points<'a>(
+        &'a self,
+        glyph: &'a Glyph,
+        top_left: Point,
+    ) -> impl Iterator<Item = Point> + 'a {
+        let width = glyph.width as u32;
+        (0..width * glyph.height as u32).filter_map(move |i| {
+            let bit = glyph.bit_offset + i;
+            let set = self.bitmap[(bit / 8) as usize] & (0x80 >> (bit % 8)) != 0;
+            set.then(|| top_left + Point::new((i % width) as i32, (i / width) as i32))
+        })
+    }
+}
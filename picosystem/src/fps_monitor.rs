@@ -1,30 +1,38 @@
-use crate::time;
-use log::info;
+use crate::logging::info;
+use crate::time::{self, Duration, Instant};
 
 pub struct FpsMonitor {
-    last_time_us: u32,
+    last_time: Instant,
     frames: u32,
+    fps: u32,
 }
 
 impl FpsMonitor {
-    const FPS_INTERVAL_US: u32 = 1_000_000;
+    const FPS_INTERVAL: Duration = Duration::secs(1);
 
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {
-            last_time_us: time::time_us(),
+            last_time: time::now(),
             frames: 0,
+            fps: 0,
         }
     }
 
     pub fn update(&mut self) {
-        let now = time::time_us();
-        if now - self.last_time_us >= Self::FPS_INTERVAL_US {
+        let now = time::now();
+        if now - self.last_time >= Self::FPS_INTERVAL {
             info!("FPS: {}", self.frames);
-            self.last_time_us = now;
+            self.fps = self.frames;
+            self.last_time = now;
             self.frames = 0;
         } else {
             self.frames += 1;
         }
     }
+
+    /// Frames counted over the last full second.
+    pub fn fps(&self) -> u32 {
+        self.fps
+    }
 }
@@ -0,0 +1,153 @@
+//! Scratch memory for the current frame, so that large temporary buffers
+//! don't have to live on the stack.
+//!
+//! Each core has its own arena of `FRAME_ARENA_SIZE` bytes. Inside
+//! `with_frame_arena` buffers are allocated by bumping a pointer, and they
+//! are released together when the closure returns, so they can't outlive
+//! it. Calls may nest, such as a tile load inside the tile renderer: the
+//! inner call allocates after the outer one's buffers and releases only
+//! its own, and the outer arena can't allocate until it returns.
+//!
+//! At the end of each frame the arena is reset, and the most it held
+//! during the frame is reported to `meminfo`.
+//!
+//! With the `double-buffer` feature the arenas are smaller, to fit next to
+//! the second framebuffer, and only hold what loading the largest tile
+//! takes: its worst-case compressed data, or the indices and data of a
+//! paletted one.
+
+use crate::meminfo;
+#[cfg(feature = "double-buffer")]
+use crate::tile::MAX_TILE_LOAD_SIZE;
+use core::alloc::Layout;
+use core::cell::{Cell, UnsafeCell};
+use rp_pico::hal::pac;
+
+#[cfg(not(feature = "double-buffer"))]
+pub const FRAME_ARENA_SIZE: usize = 8 * 1024;
+#[cfg(feature = "double-buffer")]
+pub const FRAME_ARENA_SIZE: usize = MAX_TILE_LOAD_SIZE;
+
+const POOL_NAMES: [&str; 2] = ["arena core 0", "arena core 1"];
+
+#[repr(align(8))]
+struct Buffer(UnsafeCell<[u8; FRAME_ARENA_SIZE]>);
+
+struct CoreArena {
+    buffer: Buffer,
+    // Offset of the first free byte.
+    used: Cell<usize>,
+    // The most used since the frame started.
+    peak: Cell<usize>,
+    // Calls of `with_frame_arena` running, the innermost of which may
+    // allocate.
+    depth: Cell<usize>,
+}
+
+// Each core only touches its own arena.
+unsafe impl Sync for CoreArena {}
+
+impl CoreArena {
+    const fn new() -> Self {
+        CoreArena {
+            buffer: Buffer(UnsafeCell::new([0; FRAME_ARENA_SIZE])),
+            used: Cell::new(0),
+            peak: Cell::new(0),
+            depth: Cell::new(0),
+        }
+    }
+}
+
+static ARENAS: [CoreArena; 2] = [CoreArena::new(), CoreArena::new()];
+
+fn current_core() -> usize {
+    unsafe { (*pac::SIO::PTR).cpuid.read().bits() as usize }
+}
+
+/// The part of the arena of the current core lent to one call of
+/// `with_frame_arena`. Allocations fail with `None` when it is full, or
+/// while a nested call has the arena. Values allocated in it are never
+/// dropped.
+pub struct FrameArena {
+    arena: &'static CoreArena,
+    depth: usize,
+}
+
+// Each allocation is a different part of the arena, so handing out mutable
+// references from a shared one is sound.
+#[allow(clippy::mut_from_ref)]
+impl FrameArena {
+    pub fn alloc<T>(&self, value: T) -> Option<&mut T> {
+        let ptr = self.alloc_layout(Layout::new::<T>())? as *mut T;
+        unsafe {
+            ptr.write(value);
+            Some(&mut *ptr)
+        }
+    }
+
+    /// A slice of `len` copies of `value`.
+    pub fn alloc_slice<T: Copy>(&self, len: usize, value: T) -> Option<&mut [T]> {
+        let ptr = self.alloc_layout(Layout::array::<T>(len).ok()?)? as *mut T;
+        unsafe {
+            for i in 0..len {
+                ptr.add(i).write(value);
+            }
+            Some(core::slice::from_raw_parts_mut(ptr, len))
+        }
+    }
+
+    /// A copy of `src`.
+    pub fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> Option<&mut [T]> {
+        let ptr = self.alloc_layout(Layout::for_value(src))? as *mut T;
+        unsafe {
+            ptr.copy_from_nonoverlapping(src.as_ptr(), src.len());
+            Some(core::slice::from_raw_parts_mut(ptr, src.len()))
+        }
+    }
+
+    /// Bytes left, not counting padding for alignment.
+    pub fn remaining(&self) -> usize {
+        FRAME_ARENA_SIZE - self.arena.used.get()
+    }
+
+    fn alloc_layout(&self, layout: Layout) -> Option<*mut u8> {
+        let arena = self.arena;
+        if arena.depth.get() != self.depth {
+            return None;
+        }
+        let base = arena.buffer.0.get() as usize;
+        let start = (base + arena.used.get()).next_multiple_of(layout.align()) - base;
+        let end = start.checked_add(layout.size())?;
+        if end > FRAME_ARENA_SIZE {
+            return None;
+        }
+        arena.used.set(end);
+        arena.peak.set(arena.peak.get().max(end));
+        Some((base + start) as *mut u8)
+    }
+}
+
+/// Runs `f` with the arena of the current core, releasing everything it
+/// allocated when it returns.
+pub fn with_frame_arena<R>(f: impl FnOnce(&FrameArena) -> R) -> R {
+    let arena = &ARENAS[current_core()];
+    let used = arena.used.get();
+    let depth = arena.depth.get() + 1;
+    arena.depth.set(depth);
+    let result = f(&FrameArena { arena, depth });
+    arena.depth.set(depth - 1);
+    arena.used.set(used);
+    result
+}
+
+/// Resets the arena of the current core and reports how much of it the
+/// frame used. Called at the end of each frame, by `Hardware::draw` on
+/// core 0 and by the render server on core 1.
+pub(crate) fn end_frame() {
+    let core = current_core();
+    let arena = &ARENAS[core];
+    assert_eq!(arena.depth.get(), 0, "frame arena still borrowed");
+    arena.used.set(0);
+    meminfo::report_pool(POOL_NAMES[core], arena.peak.get(), FRAME_ARENA_SIZE);
+    arena.peak.set(0);
+}
@@ -0,0 +1,109 @@
+//! State machines for the flow of a game and the behavior of its
+//! characters.
+//!
+//! States are usually a `Copy` enum implementing `State`, such as title,
+//! play, pause and game over, or an enemy's patrol, chase and stunned. A
+//! `StateMachine` holds the current one, calls `exit` and `enter` when it
+//! changes, and `update` with the time spent in it. A state's `timeout`
+//! switches to another state after a while, which covers splash screens,
+//! invulnerability after a hit and other timed states without keeping
+//! timers by hand.
+//!
+//! Like `ComboDetector`, the machine is given the time on every call, so
+//! recorded games play back the same.
+
+/// A state of a `StateMachine`, with `Context` being whatever the states
+/// need to act on, such as the game or an entity.
+pub trait State<Context: ?Sized>: Copy + PartialEq {
+    /// Called when the machine switches to this state.
+    fn enter(&self, _context: &mut Context) {}
+
+    /// Called when the machine leaves this state.
+    fn exit(&self, _context: &mut Context) {}
+
+    /// Called on every `StateMachine::update` with the time spent in this
+    /// state. Returns the state to switch to, if any.
+    fn update(&self, _context: &mut Context, _elapsed_ms: u32) -> Option<Self> {
+        None
+    }
+
+    /// How long to stay in this state, in milliseconds, and the state to
+    /// switch to after that.
+    fn timeout(&self) -> Option<(u32, Self)> {
+        None
+    }
+}
+
+pub struct StateMachine<S> {
+    state: S,
+    previous: Option<S>,
+    entered_us: u64,
+    started: bool,
+}
+
+impl<S: Copy + PartialEq> StateMachine<S> {
+    /// A machine starting in `initial`, which is entered on the first
+    /// `update`.
+    pub const fn new(initial: S) -> Self {
+        StateMachine {
+            state: initial,
+            previous: None,
+            entered_us: 0,
+            started: false,
+        }
+    }
+
+    pub fn state(&self) -> S {
+        self.state
+    }
+
+    /// The state before the current one, for going back to the game from a
+    /// pause menu.
+    pub fn previous(&self) -> Option<S> {
+        self.previous
+    }
+
+    pub fn is_in(&self, state: S) -> bool {
+        self.state == state
+    }
+
+    /// Milliseconds spent in the current state at `now_us`.
+    pub fn elapsed_ms(&self, now_us: u64) -> u32 {
+        (now_us.saturating_sub(self.entered_us) / 1000).min(u32::MAX as u64) as u32
+    }
+
+    /// Leaves the current state for `next`, even if they are the same,
+    /// which starts the state over.
+    pub fn switch<C: ?Sized>(&mut self, context: &mut C, next: S, now_us: u64)
+    where
+        S: State<C>,
+    {
+        if self.started {
+            self.state.exit(context);
+            self.previous = Some(self.state);
+        }
+        self.state = next;
+        self.entered_us = now_us;
+        self.started = true;
+        next.enter(context);
+    }
+
+    /// Updates the current state, or switches to the next one if it timed
+    /// out. Returns the state switched to, if any. There is at most one
+    /// switch per update.
+    pub fn update<C: ?Sized>(&mut self, context: &mut C, now_us: u64) -> Option<S>
+    where
+        S: State<C>,
+    {
+        if !self.started {
+            self.switch(context, self.state, now_us);
+        }
+        let elapsed_ms = self.elapsed_ms(now_us);
+        let next = match self.state.timeout() {
+            Some((duration_ms, next)) if elapsed_ms >= duration_ms => Some(next),
+            _ => self.state.update(context, elapsed_ms),
+        }?;
+        self.switch(context, next, now_us);
+        Some(next)
+    }
+}
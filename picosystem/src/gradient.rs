@@ -0,0 +1,153 @@
+//! Dithered gradients for skies, menus and backgrounds.
+//!
+//! RGB565 has only 32 levels of red and blue, so a smooth gradient drawn
+//! with it shows wide bands. The colors here are given in `Rgb888` and
+//! dithered down with a 4x4 Bayer matrix, mixing the two nearest levels in
+//! a fixed pattern so that the bands blend into each other.
+//!
+//! `VerticalGradient` and `RadialGradient` are embedded-graphics
+//! `Drawable`s that fill their area with `fill_contiguous`, and `dither`
+//! gives single pixels in between the levels. The pattern depends on the
+//! position on the screen, so neighbouring areas line up.
+
+use embedded_graphics::pixelcolor::{Rgb565, Rgb888, RgbColor};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+// Thresholds of the ordered dither, in sixteenths of a level.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// `color` reduced to RGB565 for the pixel at `position`.
+pub fn dither(color: Rgb888, position: Point) -> Rgb565 {
+    Levels::new(color).dither(position)
+}
+
+/// Fills `area` from `top` on the first row to `bottom` on the last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerticalGradient {
+    pub area: Rectangle,
+    pub top: Rgb888,
+    pub bottom: Rgb888,
+}
+
+impl VerticalGradient {
+    pub fn new(area: Rectangle, top: Rgb888, bottom: Rgb888) -> Self {
+        VerticalGradient { area, top, bottom }
+    }
+}
+
+impl Dimensions for VerticalGradient {
+    fn bounding_box(&self) -> Rectangle {
+        self.area
+    }
+}
+
+impl Drawable for VerticalGradient {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let (top, bottom) = (Levels::new(self.top), Levels::new(self.bottom));
+        let first = self.area.top_left.y;
+        let last = (self.area.size.height as i32 - 1).max(1);
+        let columns = self.area.columns();
+        let colors = self.area.rows().flat_map(|y| {
+            let levels = top.lerp(&bottom, y - first, last);
+            columns
+                .clone()
+                .map(move |x| levels.dither(Point::new(x, y)))
+        });
+        target.fill_contiguous(&self.area, colors)
+    }
+}
+
+/// Fills `area` with `inner` at `center`, fading to `outer` at `radius`
+/// pixels away and beyond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RadialGradient {
+    pub area: Rectangle,
+    pub center: Point,
+    pub radius: u32,
+    pub inner: Rgb888,
+    pub outer: Rgb888,
+}
+
+impl RadialGradient {
+    pub fn new(area: Rectangle, center: Point, radius: u32, inner: Rgb888, outer: Rgb888) -> Self {
+        RadialGradient {
+            area,
+            center,
+            radius,
+            inner,
+            outer,
+        }
+    }
+}
+
+impl Dimensions for RadialGradient {
+    fn bounding_box(&self) -> Rectangle {
+        self.area
+    }
+}
+
+impl Drawable for RadialGradient {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let (inner, outer) = (Levels::new(self.inner), Levels::new(self.outer));
+        // Distances are measured in sixteenths of a pixel, which keeps
+        // their squares within 32 bits up to this radius.
+        let radius = self.radius.clamp(1, 4095) as i32;
+        let end = radius * 16;
+        let colors = self.area.points().map(|point| {
+            let offset = point - self.center;
+            let (dx, dy) = (offset.x.abs(), offset.y.abs());
+            if dx >= radius || dy >= radius {
+                return outer.dither(point);
+            }
+            let distance = ((dx * dx + dy * dy) as u32 * 256).isqrt() as i32;
+            inner.lerp(&outer, distance.min(end), end).dither(point)
+        });
+        target.fill_contiguous(&self.area, colors)
+    }
+}
+
+// Red, green and blue in sixteenths of an RGB565 level, so that adding a
+// threshold from `BAYER` and dropping four bits dithers them.
+#[derive(Clone, Copy)]
+struct Levels([i32; 3]);
+
+impl Levels {
+    fn new(color: Rgb888) -> Self {
+        let level = |value: u8, max: u8| (value as i32 * max as i32 * 16 + 127) / 255;
+        Levels([
+            level(color.r(), Rgb565::MAX_R),
+            level(color.g(), Rgb565::MAX_G),
+            level(color.b(), Rgb565::MAX_B),
+        ])
+    }
+
+    // `t` of the way from `self` to `other` out of `scale`.
+    fn lerp(&self, other: &Levels, t: i32, scale: i32) -> Levels {
+        Levels(core::array::from_fn(|c| {
+            self.0[c] + (other.0[c] - self.0[c]) * t / scale
+        }))
+    }
+
+    fn dither(&self, position: Point) -> Rgb565 {
+        let threshold = BAYER[(position.y & 3) as usize][(position.x & 3) as usize] as i32;
+        let channel = |c: usize, max: u8| ((self.0[c] + threshold) >> 4).min(max as i32) as u8;
+        Rgb565::new(
+            channel(0, Rgb565::MAX_R),
+            channel(1, Rgb565::MAX_G),
+            channel(2, Rgb565::MAX_B),
+        )
+    }
+}
@@ -1,8 +1,18 @@
-use crate::display::Display;
-use crate::{audio, dma, idle, input, usb_logger};
+#[cfg(not(feature = "double-buffer"))]
+use crate::console;
+use crate::display::{Display, DisplayBus};
+use crate::input::{ButtonId, Combo, ComboDetector};
+use crate::replay::ButtonMask;
+use crate::settings::Settings;
+use crate::{
+    audio, debug_overlay, dma, frame_arena, idle, input, launcher, led, link, logging, meminfo,
+    peripherals, profile, render, scheduler, storage, time, toast, usb_logger, usb_storage,
+    watchdog, xip,
+};
+use core::cell::Cell;
+use critical_section::Mutex;
 use embedded_hal::adc::OneShot;
-use embedded_hal::digital::v2::OutputPin;
-use rp2040_hal::gpio::dynpin::DynPin;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 use rp2040_hal::gpio::pin::bank0::Gpio26;
 use rp2040_hal::gpio::pin::{FloatingInput, Pin};
 use rp2040_hal::gpio::Pins;
@@ -17,22 +27,109 @@ use rp2040_hal::{
     xosc::setup_xosc_blocking,
 };
 
+// Draws a frame with `func` and the overlays, returning how long `func`
+// took.
+fn draw_frame(display: &mut Display, func: impl FnOnce(&mut Display)) -> u32 {
+    let draw_start = time::time_us();
+    func(display);
+    let draw_time_us = time::time_us().wrapping_sub(draw_start);
+    toast::draw_overlay(display);
+    #[cfg(not(feature = "double-buffer"))]
+    console::draw_overlay(display);
+    debug_overlay::draw_overlay(display);
+    draw_time_us
+}
+
+/// Waits on the microsecond timer, as SysTick is taken by `profile`.
+pub struct Delay;
+
+impl Delay {
+    pub fn delay_ms(&mut self, ms: u32) {
+        self.delay_us_64(ms as u64 * 1000);
+    }
+
+    pub fn delay_us(&mut self, us: u32) {
+        self.delay_us_64(us as u64);
+    }
+
+    fn delay_us_64(&mut self, us: u64) {
+        let start = time::time_us64();
+        while time::time_us64() - start < us {
+            usb_logger::drain();
+        }
+    }
+}
+
+impl DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        Delay::delay_ms(self, ms);
+    }
+}
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        Delay::delay_us(self, us);
+    }
+}
+
 pub struct Hardware {
     pub display: Display,
-    pub red_led_pin: DynPin,
-    pub green_led_pin: DynPin,
-    pub blue_led_pin: DynPin,
+    pub led: led::Led,
+    pub link: link::Link,
+    pub expansion: peripherals::Expansion,
     pub battery_pin: Pin<Gpio26, FloatingInput>,
-    pub delay: cortex_m::delay::Delay,
+    pub delay: Delay,
     pub adc: hal::adc::Adc,
     pub input: input::Input,
     pub audio: audio::Audio,
     pub idle: idle::Idle,
+    pub render: render::RenderServer,
+    pub watchdog: watchdog::Watchdog,
+    pub settings: Settings,
+    overlay_combos: ComboDetector<Overlay, 2>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overlay {
+    #[cfg(not(feature = "double-buffer"))]
+    Console,
+    Debug,
+}
+
+const OVERLAY_MODIFIER: ButtonMask = ButtonId::X.mask() | ButtonId::Y.mask();
+// Long enough to pass through X, Y and A or B on the way to the reset chord
+// of all four without toggling an overlay.
+const OVERLAY_HOLD_MS: u32 = 300;
+
+static DEVICE_ID: Mutex<Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+/// The unique ID of the flash chip, which is different on every console.
+pub fn device_id() -> u64 {
+    if let Some(id) = critical_section::with(|cs| DEVICE_ID.borrow(cs).get()) {
+        return id;
+    }
+    let id = storage::flash_unique_id();
+    critical_section::with(|cs| DEVICE_ID.borrow(cs).set(Some(id)));
+    id
+}
+
+/// Reboots into the boot ROM's UF2 mass storage mode, as if BOOTSEL was
+/// held, so new firmware can be copied over USB. Bound to a button chord
+/// with `Input::set_bootsel_chord`.
+pub fn reboot_to_bootsel() -> ! {
+    logging::info!("Rebooting to the bootloader");
+    // Both the mass storage and the PICOBOOT interfaces, no activity LED.
+    hal::rom_data::reset_to_usb_boot(0, 0);
+    loop {
+        cortex_m::asm::wfi();
+    }
 }
 
 impl Hardware {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
+        launcher::chain_if_requested();
+        meminfo::paint_stack();
         let mut pac = pac::Peripherals::take().unwrap();
         let core = pac::CorePeripherals::take().unwrap();
         let mut watchdog = hal::watchdog::Watchdog::new(pac.WATCHDOG);
@@ -50,15 +147,16 @@ impl Hardware {
         .ok()
         .unwrap();
 
-        let mut delay =
-            cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
+        profile::init(core.SYST, clocks.system_clock.freq().to_Hz());
+        let mut delay = Delay;
 
-        usb_logger::init(
-            pac.USBCTRL_REGS,
-            pac.USBCTRL_DPRAM,
-            &mut pac.RESETS,
-            clocks.usb_clock,
-        );
+        // USB is left to mass storage mode when it was requested.
+        let mut usb = Some((pac.USBCTRL_REGS, pac.USBCTRL_DPRAM, clocks.usb_clock));
+        let usb_storage_mode = usb_storage::requested();
+        if !usb_storage_mode {
+            let (regs, dpram, usb_clock) = usb.take().unwrap();
+            usb_logger::init(regs, dpram, &mut pac.RESETS, usb_clock);
+        }
 
         #[cfg(feature = "wait-for-serial")]
         {
@@ -70,9 +168,9 @@ impl Hardware {
             }
         }
 
-        log::info!("Logging initialized");
+        logging::info!("Logging initialized");
 
-        log::info!("System clock: {}", clocks.system_clock.freq());
+        logging::info!("System clock: {} Hz", clocks.system_clock.freq().to_Hz());
 
         let sio = hal::sio::Sio::new(pac.SIO);
         let pins = Pins::new(
@@ -82,18 +180,34 @@ impl Hardware {
             &mut pac.RESETS,
         );
 
-        let mut red_led_pin = pins.gpio14.into_push_pull_output();
-        let mut green_led_pin = pins.gpio13.into_push_pull_output();
-        let mut blue_led_pin = pins.gpio15.into_push_pull_output();
+        let led = led::Led::new(
+            pins.gpio14.into(),
+            pins.gpio13.into(),
+            pins.gpio15.into(),
+            &pac.PWM,
+            &mut pac.RESETS,
+        );
 
-        red_led_pin.set_low().unwrap();
-        green_led_pin.set_low().unwrap();
-        blue_led_pin.set_low().unwrap();
+        let link = link::Link::new(
+            pac.UART0,
+            pins.gpio0,
+            pins.gpio1,
+            &mut pac.RESETS,
+            clocks.peripheral_clock.freq(),
+        );
 
         let battery_pin = pins.gpio26.into_floating_input();
         let adc = hal::adc::Adc::new(pac.ADC, &mut pac.RESETS);
 
-        let display = Display::new(
+        // The display enables its DMA interrupt, so DMA must be out of reset.
+        pac.RESETS.reset.modify(|_, w| w.dma().clear_bit());
+        while pac.RESETS.reset_done.read().dma().bit_is_clear() {}
+
+        #[cfg(feature = "pio-display")]
+        let display_bus = DisplayBus::Pio(pac.PIO0);
+        #[cfg(not(feature = "pio-display"))]
+        let display_bus = DisplayBus::Spi;
+        let mut display = Display::new(
             /*backlight_pin=*/ pins.gpio12.into(),
             /*lcd_dc_pin=*/ pins.gpio9.into(),
             /*lcd_cs_pin=*/ pins.gpio5.into(),
@@ -104,13 +218,11 @@ impl Hardware {
             /*spi_device=*/ pac.SPI0,
             /*resets=*/ &mut pac.RESETS,
             /*delay_source=*/ &mut delay,
-            /*dma_channel=*/ unsafe { dma::DmaChannel::new(dma::CHANNEL_FRAMEBUFFER) },
+            /*dma_channel=*/ dma::DmaManager::claim(dma::CHANNEL_FRAMEBUFFER).unwrap(),
+            /*bus=*/ display_bus,
         );
 
-        pac.RESETS.reset.modify(|_, w| w.dma().clear_bit());
-        while pac.RESETS.reset_done.read().dma().bit_is_clear() {}
-
-        let input = input::Input::new(
+        let mut input = input::Input::new(
             pins.gpio22.into(),
             pins.gpio21.into(),
             pins.gpio23.into(),
@@ -121,19 +233,67 @@ impl Hardware {
             pins.gpio19.into(),
         );
 
-        let audio = audio::Audio::new(pins.gpio11.into());
+        let mut audio = audio::Audio::new(
+            pins.gpio11.into(),
+            pac.PWM,
+            &mut pac.RESETS,
+            clocks.system_clock.freq().to_Hz(),
+        );
+
+        if let Some((regs, dpram, usb_clock)) = usb.take() {
+            usb_storage::run(
+                regs,
+                dpram,
+                &mut pac.RESETS,
+                usb_clock,
+                &mut display,
+                &input,
+            );
+        }
+        if input.button_x.is_held() && input.button_y.is_held() {
+            usb_storage::enter();
+        }
+
+        if let Some(crash) = watchdog::take_crash() {
+            watchdog::show_crash(&crash, &mut display, &input);
+        }
+        let mut watchdog = watchdog::Watchdog::new(watchdog);
+        watchdog.start(watchdog::DEFAULT_TIMEOUT_MS);
+
+        let settings = Settings::load();
+        settings.apply(&mut display, &mut audio, &mut input);
+        logging::info!("Device ID: {:016x}", device_id());
+
+        let render = render::RenderServer::start(&mut pac.PSM, &mut pac.PPB, sio.fifo);
+
+        let expansion = peripherals::Expansion::new(
+            pins.gpio2,
+            pins.gpio3,
+            pins.gpio10,
+            pins.gpio27,
+            pins.gpio28,
+            pac.I2C1,
+            pac.SPI1,
+            pac.RESETS,
+            clocks.system_clock.freq(),
+            clocks.peripheral_clock.freq(),
+        );
 
         Hardware {
             display,
-            red_led_pin: red_led_pin.into(),
-            green_led_pin: green_led_pin.into(),
-            blue_led_pin: blue_led_pin.into(),
+            led,
+            link,
+            expansion,
             battery_pin,
             adc,
             delay,
             input,
             audio,
             idle: idle::Idle::new(),
+            render,
+            watchdog,
+            settings,
+            overlay_combos: Self::overlay_combos(),
         }
     }
 
@@ -186,10 +346,76 @@ impl Hardware {
     }
 
     pub fn draw(&mut self, func: impl FnOnce(&mut Display)) {
-        if self.idle.check_idle(&mut self.input) {
+        self.begin_frame();
+        let start = time::time_us();
+        let mut draw_time_us = 0;
+        self.display
+            .draw(|display| draw_time_us = draw_frame(display, func));
+        Self::end_frame(time::time_us().wrapping_sub(start), draw_time_us);
+    }
+
+    /// Like `draw`, letting other async tasks run while waiting for the
+    /// flush and vsync.
+    #[cfg(feature = "async")]
+    pub async fn draw_async(&mut self, func: impl FnOnce(&mut Display)) {
+        self.begin_frame();
+        let start = time::time_us();
+        let mut draw_time_us = 0;
+        self.display
+            .draw_async(|display| draw_time_us = draw_frame(display, func))
+            .await;
+        Self::end_frame(time::time_us().wrapping_sub(start), draw_time_us);
+    }
+
+    fn begin_frame(&mut self) {
+        self.watchdog.feed();
+        usb_logger::drain();
+        if self.idle.check_idle(&mut self.input, &mut self.display) {
+            self.watchdog.pause();
             self.idle.enter_idle(&mut self.display, &mut self.delay);
+            self.watchdog.resume();
+        }
+        self.input.check_reset();
+        self.check_overlay_combos();
+    }
+
+    fn end_frame(total_time_us: u32, draw_time_us: u32) {
+        debug_overlay::record_phase(debug_overlay::Phase::Draw, draw_time_us);
+        debug_overlay::record_phase(debug_overlay::Phase::Flush, total_time_us - draw_time_us);
+        debug_overlay::end_frame();
+        frame_arena::end_frame();
+        xip::end_frame();
+        profile::end_frame();
+    }
+
+    // Holding X and Y and holding B for a moment shows or hides the console,
+    // A the debug overlay.
+    fn overlay_combos() -> ComboDetector<Overlay, 2> {
+        let mut combos = ComboDetector::new();
+        let mut add = |overlay, button: ButtonId| {
+            let buttons = OVERLAY_MODIFIER | button.mask();
+            combos.add(
+                overlay,
+                Combo::Chord {
+                    buttons,
+                    hold_ms: OVERLAY_HOLD_MS,
+                },
+            );
+        };
+        #[cfg(not(feature = "double-buffer"))]
+        add(Overlay::Console, ButtonId::B);
+        add(Overlay::Debug, ButtonId::A);
+        combos
+    }
+
+    fn check_overlay_combos(&mut self) {
+        for overlay in self.input.update_combos(&mut self.overlay_combos) {
+            match overlay {
+                #[cfg(not(feature = "double-buffer"))]
+                Overlay::Console => console::set_visible(!console::is_visible()),
+                Overlay::Debug => debug_overlay::set_visible(!debug_overlay::is_visible()),
+            }
         }
-        self.display.draw(func);
     }
 
     pub fn read_battery_raw(&mut self) -> u16 {
@@ -212,3 +438,18 @@ impl Hardware {
         ((raw - low) / (high - low)).clamp(0.0, 1.0)
     }
 }
+
+/// Runs the game loop forever. `update` runs at the fixed `rate` and may
+/// change `state`, `render` draws it once per frame.
+pub fn run<S>(
+    hw: &mut Hardware,
+    rate: scheduler::FrameRate,
+    mut state: S,
+    mut update: impl FnMut(&mut S, &mut Hardware, &scheduler::FrameContext),
+    mut render: impl FnMut(&S, &mut Display, &scheduler::FrameContext),
+) -> ! {
+    let mut scheduler = scheduler::Scheduler::new(rate);
+    loop {
+        scheduler.run_frame(hw, &mut state, &mut update, &mut render);
+    }
+}
@@ -0,0 +1,288 @@
+//! A table of the best scores of a game, kept in flash.
+//!
+//! `HighScores` holds the best `N` scores, highest first, each with the
+//! player's name. `insert` puts a score in its place and returns its rank,
+//! and `draw` shows the table with the new entry picked out.
+//!
+//! On the device, `HighScores::load` reads the table saved by the game
+//! from flash, `submit` asks the player for a name with the on-screen
+//! keyboard when a score makes the table and saves it, and `show` shows the
+//! table until a button is pressed. Like achievements, each game saves to
+//! its own file named after it. The simulator starts with an empty table.
+
+use core::fmt::Write;
+use embedded_graphics::mono_font::{ascii::FONT_10X20, MonoTextStyle};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::hardware::Hardware;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::logging;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::storage::{self, FsError};
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::ui::{TextInput, ALPHANUMERIC};
+
+/// The most scores a table can hold.
+pub const MAX_ENTRIES: usize = 10;
+pub const MAX_NAME_LEN: usize = 8;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+const FILE_PREFIX: &str = "scores_";
+const VERSION: u8 = 1;
+// The version and the number of entries.
+const HEADER_LEN: usize = 2;
+// The score and the length of the name, followed by the name.
+const ENTRY_HEADER_LEN: usize = 5;
+const MAX_LEN: usize = HEADER_LEN + MAX_ENTRIES * (ENTRY_HEADER_LEN + MAX_NAME_LEN);
+
+const TITLE: &str = "HIGH SCORES";
+const TITLE_Y: i32 = 16;
+const FIRST_ROW_Y: i32 = 46;
+const ROW_HEIGHT: i32 = 19;
+const MARGIN: i32 = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: heapless::String<MAX_NAME_LEN>,
+    pub score: u32,
+}
+
+/// The best `N` scores of a game, at most `MAX_ENTRIES`.
+#[derive(Debug, Clone)]
+pub struct HighScores<const N: usize> {
+    game: &'static str,
+    entries: heapless::Vec<Entry, N>,
+}
+
+impl<const N: usize> HighScores<N> {
+    /// An empty table for the game called `game`.
+    pub fn new(game: &'static str) -> Self {
+        assert!(N <= MAX_ENTRIES);
+        HighScores {
+            game,
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    pub fn game(&self) -> &'static str {
+        self.game
+    }
+
+    /// The scores, highest first.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The lowest score needed to get into the table, 0 while it has room.
+    pub fn min_score(&self) -> u32 {
+        match self.entries.last() {
+            Some(last) if self.entries.is_full() => last.score.saturating_add(1),
+            _ => 0,
+        }
+    }
+
+    /// The place, from 0, that `score` would take, or `None` if it doesn't
+    /// make the table. A score equal to others goes below them.
+    pub fn rank(&self, score: u32) -> Option<usize> {
+        let rank = self
+            .entries
+            .iter()
+            .position(|entry| entry.score < score)
+            .unwrap_or(self.entries.len());
+        (rank < N).then_some(rank)
+    }
+
+    /// Puts `score` in its place, dropping the lowest one if the table is
+    /// full, and returns its rank. `name` is cut to `MAX_NAME_LEN` bytes.
+    pub fn insert(&mut self, name: &str, score: u32) -> Option<usize> {
+        let rank = self.rank(score)?;
+        if self.entries.is_full() {
+            self.entries.pop();
+        }
+        let mut end = name.len().min(MAX_NAME_LEN);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        let _ = self.entries.push(Entry {
+            name: name[..end].into(),
+            score,
+        });
+        self.entries[rank..].rotate_right(1);
+        Some(rank)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn to_bytes(&self) -> heapless::Vec<u8, MAX_LEN> {
+        let mut bytes = heapless::Vec::new();
+        let _ = bytes.extend_from_slice(&[VERSION, self.entries.len() as u8]);
+        for entry in &self.entries {
+            let _ = bytes.extend_from_slice(&entry.score.to_le_bytes());
+            let _ = bytes.push(entry.name.len() as u8);
+            let _ = bytes.extend_from_slice(entry.name.as_bytes());
+        }
+        bytes
+    }
+
+    /// Takes the scores from `bytes` made by `to_bytes`, keeping the best
+    /// `N`. Returns false, leaving the table as it was, if `bytes` weren't.
+    pub fn set_bytes(&mut self, mut bytes: &[u8]) -> bool {
+        let [VERSION, count, ref rest @ ..] = *bytes else {
+            return false;
+        };
+        bytes = rest;
+        let mut entries = heapless::Vec::<Entry, N>::new();
+        for _ in 0..count {
+            let [a, b, c, d, name_len, ref rest @ ..] = *bytes else {
+                return false;
+            };
+            let Some(name) = rest.get(..name_len as usize) else {
+                return false;
+            };
+            let Ok(name) = core::str::from_utf8(name) else {
+                return false;
+            };
+            if name.len() > MAX_NAME_LEN {
+                return false;
+            }
+            let _ = entries.push(Entry {
+                name: name.into(),
+                score: u32::from_le_bytes([a, b, c, d]),
+            });
+            bytes = &rest[name_len as usize..];
+        }
+        self.entries = entries;
+        true
+    }
+
+    /// Draws the table over the whole of `target`, with the entry at rank
+    /// `highlight` in another color.
+    pub fn draw<D>(&self, target: &mut D, highlight: Option<usize>) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        target.clear(Rgb565::BLACK)?;
+        let width = target.bounding_box().size.width as i32;
+        let style = |alignment| {
+            TextStyleBuilder::new()
+                .alignment(alignment)
+                .baseline(Baseline::Middle)
+                .build()
+        };
+        let title = MonoTextStyle::new(&FONT_10X20, Rgb565::CSS_GOLD);
+        Text::with_text_style(
+            TITLE,
+            Point::new(width / 2, TITLE_Y),
+            title,
+            style(Alignment::Center),
+        )
+        .draw(target)?;
+
+        for (rank, entry) in self.entries.iter().enumerate() {
+            let color = if highlight == Some(rank) {
+                Rgb565::CSS_LIME
+            } else {
+                Rgb565::WHITE
+            };
+            let text_style = MonoTextStyle::new(&FONT_10X20, color);
+            let y = FIRST_ROW_Y + rank as i32 * ROW_HEIGHT;
+            let mut line = heapless::String::<{ MAX_NAME_LEN + 4 }>::new();
+            let _ = write!(line, "{:>2}. {}", rank + 1, entry.name);
+            Text::with_text_style(
+                &line,
+                Point::new(MARGIN, y),
+                text_style,
+                style(Alignment::Left),
+            )
+            .draw(target)?;
+            let mut score = heapless::String::<10>::new();
+            let _ = write!(score, "{}", entry.score);
+            Text::with_text_style(
+                &score,
+                Point::new(width - MARGIN, y),
+                text_style,
+                style(Alignment::Right),
+            )
+            .draw(target)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+impl<const N: usize> HighScores<N> {
+    /// The table of the game called `game` saved in flash, or an empty one.
+    /// Names longer than 33 bytes don't fit in a file name, so nothing is
+    /// read or saved for them.
+    pub fn load(game: &'static str) -> Self {
+        let mut scores = Self::new(game);
+        let mut bytes = [0; MAX_LEN];
+        let read = scores
+            .file_name()
+            .and_then(|name| storage::fs().read(&name, &mut bytes));
+        match read {
+            Ok(len) => {
+                if !scores.set_bytes(&bytes[..len]) {
+                    logging::warn!("Ignoring unreadable high scores of {}", game);
+                }
+            }
+            Err(FsError::NotFound) => {}
+            Err(e) => logging::warn!("Failed to read high scores of {}: {:?}", game, e),
+        }
+        scores
+    }
+
+    pub fn save(&self) -> Result<(), FsError> {
+        storage::fs().write(&self.file_name()?, &self.to_bytes())
+    }
+
+    /// If `score` makes the table, asks for the player's name, starting
+    /// with the one in the settings, then puts the score in the table and
+    /// saves it. Returns the rank of the score, or `None` if it didn't make
+    /// the table or the name was cancelled.
+    pub fn submit(&mut self, hw: &mut Hardware, score: u32) -> Option<usize> {
+        self.rank(score)?;
+        let mut input = TextInput::<MAX_NAME_LEN>::new("NEW HIGH SCORE", ALPHANUMERIC);
+        input.set_text(&hw.settings.player_name);
+        let name = input.run(hw)?;
+        let rank = self.insert(&name, score)?;
+        if let Err(e) = self.save() {
+            logging::warn!("Failed to save high scores of {}: {:?}", self.game, e);
+        }
+        Some(rank)
+    }
+
+    /// Shows the table, with the entry at rank `highlight` in another
+    /// color, until A or B is pressed.
+    pub fn show(&self, hw: &mut Hardware, highlight: Option<usize>) {
+        loop {
+            if hw.input.button_a.is_pressed() || hw.input.button_b.is_pressed() {
+                return;
+            }
+            hw.draw(|display| {
+                let _ = self.draw(display, highlight);
+            });
+        }
+    }
+
+    fn file_name(&self) -> Result<heapless::String<{ storage::MAX_NAME_LEN }>, FsError> {
+        let mut name = heapless::String::new();
+        name.push_str(FILE_PREFIX)
+            .and_then(|_| name.push_str(self.game))
+            .map_err(|_| FsError::NameTooLong)?;
+        Ok(name)
+    }
+}
@@ -1,11 +1,42 @@
-use cortex_m::delay::Delay;
+//! Dimming the screen and then sleeping while nobody plays.
+//!
+//! `Hardware::draw` checks every frame how long it has been since a button
+//! was last held. After 30 seconds the backlight dims, after two minutes
+//! the console sleeps with `power::sleep_until_button`, and pressing a
+//! button brings both back. A game can change the times, be told of each
+//! step with `set_handler`, or hold them off with `reset`, for example
+//! while a cutscene plays.
 
-use crate::{display, input, interrupts, time};
+use crate::hardware::Delay;
+use crate::power::{self, SleepDepth};
+use crate::time::{self, Duration};
+use crate::{display, input};
 
-const IDLE_TIME_US: u64 = 300_000_000;
+const DIM_TIME_US: u64 = 30_000_000;
+const IDLE_TIME_US: u64 = 120_000_000;
+
+/// Told to the idle handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleEvent {
+    /// The backlight was dimmed.
+    Dim,
+    /// The console is about to sleep.
+    Sleep,
+    /// A button was pressed while dimmed or asleep, and the backlight is
+    /// back to its level.
+    Wake,
+}
+
+pub type IdleHandler = fn(IdleEvent);
 
 pub struct Idle {
     last_active_time: u64,
+    dim_after_us: Option<u64>,
+    timeout_us: Option<u64>,
+    depth: SleepDepth,
+    // The backlight level from before dimming, while dimmed.
+    undimmed_level: Option<u8>,
+    handler: Option<IdleHandler>,
 }
 
 #[allow(clippy::new_without_default)]
@@ -13,35 +44,93 @@ impl Idle {
     pub fn new() -> Idle {
         Idle {
             last_active_time: 0,
+            dim_after_us: Some(DIM_TIME_US),
+            timeout_us: Some(IDLE_TIME_US),
+            depth: SleepDepth::Light,
+            undimmed_level: None,
+            handler: None,
         }
     }
 
-    pub fn check_idle(&mut self, input: &mut input::Input) -> bool {
+    /// Sets how long without input before dimming the backlight to a
+    /// quarter, or `None` to never dim.
+    pub fn set_dim_after_ms(&mut self, dim_after_ms: Option<u32>) {
+        self.dim_after_us = dim_after_ms.map(|ms| ms as u64 * 1000);
+    }
+
+    /// Sets how long without input before sleeping, or `None` to never
+    /// sleep, for example while a cutscene plays.
+    pub fn set_timeout_ms(&mut self, timeout_ms: Option<u32>) {
+        self.timeout_us = timeout_ms.map(|ms| ms as u64 * 1000);
+    }
+
+    pub fn set_depth(&mut self, depth: SleepDepth) {
+        self.depth = depth;
+    }
+
+    /// Calls `handler` when dimming, before sleeping and when woken, for
+    /// example to pause the game.
+    pub fn set_handler(&mut self, handler: Option<IdleHandler>) {
+        self.handler = handler;
+    }
+
+    /// How long since a button was last held.
+    pub fn idle_time(&self) -> Duration {
+        Duration::micros(time::time_us64() - self.last_active_time)
+    }
+
+    /// Starts the wait for dimming and sleeping again, as if a button had
+    /// been pressed. The backlight comes back on the next check.
+    pub fn reset(&mut self) {
+        self.last_active_time = time::time_us64();
+    }
+
+    /// Dims the backlight once it is time to, and returns whether it is
+    /// time to sleep. A button held undims it.
+    pub fn check_idle(&mut self, input: &mut input::Input, display: &mut display::Display) -> bool {
         let now = time::time_us64();
         if input.is_active() {
             self.last_active_time = now;
-        } else if now - self.last_active_time > IDLE_TIME_US {
-            return true;
         }
-        false
+        let idle_us = now - self.last_active_time;
+        if self.undimmed_level.is_some() {
+            if idle_us < self.dim_after_us.unwrap_or(u64::MAX) {
+                self.undim(display);
+            }
+        } else if self
+            .dim_after_us
+            .is_some_and(|dim_after_us| idle_us > dim_after_us)
+        {
+            let level = display.backlight_level();
+            self.undimmed_level = Some(level);
+            display.set_backlight_level(level / 4);
+            self.notify(IdleEvent::Dim);
+        }
+        self.timeout_us
+            .is_some_and(|timeout_us| idle_us > timeout_us)
     }
 
     pub fn enter_idle(&mut self, display: &mut display::Display, delay: &mut Delay) {
-        display.disable_backlight(delay);
-        unsafe {
-            let inputs = 16..24;
-            for gpio in inputs.clone() {
-                interrupts::enable_gpio_interrupt(gpio, interrupts::GpioEvent::EdgeLow);
-            }
-            interrupts::acknowledge_gpio_interrupt();
-            interrupts::unmask_gpio_interrupt();
-            cortex_m::asm::wfi();
-            interrupts::mask_gpio_interrupt();
-            for gpio in inputs {
-                interrupts::disable_gpio_interrupt(gpio, interrupts::GpioEvent::EdgeLow);
-            }
-        }
-        display.enable_backlight(delay);
+        self.notify(IdleEvent::Sleep);
+        power::sleep_until_button(display, delay, self.depth);
         self.last_active_time = time::time_us64();
+        if self.undimmed_level.is_some() {
+            self.undim(display);
+        } else {
+            self.notify(IdleEvent::Wake);
+        }
     }
-}
\ No newline at end of file
+
+    fn undim(&mut self, display: &mut display::Display) {
+        if let Some(level) = self.undimmed_level.take() {
+            display.set_backlight_level(level);
+            self.notify(IdleEvent::Wake);
+        }
+    }
+
+    fn notify(&self, event: IdleEvent) {
+        if let Some(handler) = self.handler {
+            handler(event);
+        }
+    }
+}
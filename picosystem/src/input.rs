@@ -1,15 +1,205 @@
-use crate::time;
+pub use crate::button_map::{Action, ButtonMap};
+pub use crate::combo::{Combo, ComboDetector};
+#[cfg(feature = "async")]
+use crate::executor::WakerSlot;
+use crate::logging;
+use crate::press::PressTracker;
+use crate::replay::{ButtonMask, Player, Recording};
+use crate::time::{self, Alarm, Duration};
+pub use crate::virtual_stick::VirtualStick;
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicU16, Ordering};
+use critical_section::Mutex;
 use embedded_hal::digital::v2::InputPin;
 use rp2040_hal::gpio::dynpin::DynPin;
+use rp_pico::hal::pac;
 
 const DEBOUNCE_US: u64 = 30_000;
 const REPEAT_US: u64 = 200_000;
 
+const NUM_BUTTONS: usize = 8;
+const EVENT_QUEUE_SIZE: usize = 32;
+const POLL_PERIOD_US: u32 = 1_000;
+const EVENT_DEBOUNCE_US: u64 = 5_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonId {
+    DpadLeft,
+    DpadRight,
+    DpadUp,
+    DpadDown,
+    X,
+    Y,
+    A,
+    B,
+}
+
+impl ButtonId {
+    /// The bit of the button in a `ButtonMask`.
+    pub const fn mask(self) -> ButtonMask {
+        1 << self as u8
+    }
+}
+
+const BUTTON_IDS: [ButtonId; NUM_BUTTONS] = [
+    ButtonId::DpadLeft,
+    ButtonId::DpadRight,
+    ButtonId::DpadUp,
+    ButtonId::DpadDown,
+    ButtonId::X,
+    ButtonId::Y,
+    ButtonId::A,
+    ButtonId::B,
+];
+
+// Holding X, Y, A and B together for this long resets.
+const RESET_BUTTONS: ButtonMask =
+    ButtonId::X.mask() | ButtonId::Y.mask() | ButtonId::A.mask() | ButtonId::B.mask();
+const RESET_HOLD_MS: u32 = 1000;
+const BOOTSEL_HOLD_MS: u32 = 2000;
+
+/// What to do when the reset chord is held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetAction {
+    /// Reboot back to the launcher menu.
+    Launcher,
+    /// Reboot into the USB bootloader, to load new firmware.
+    Bootloader,
+    /// Carry on. `Input::take_reset_request` then returns true, so the
+    /// game can ask first or save before calling `reset`.
+    Ignore,
+}
+
+/// Called when the reset chord is held, and decides what happens.
+pub type ResetHandler = fn() -> ResetAction;
+
+static RESET_HANDLER: Mutex<Cell<Option<ResetHandler>>> = Mutex::new(Cell::new(None));
+
+/// Reboots now. `ResetAction::Ignore` does nothing.
+pub fn reset(action: ResetAction) {
+    match action {
+        ResetAction::Launcher => {
+            logging::info!("Rebooting to the launcher");
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+        ResetAction::Bootloader => crate::hardware::reboot_to_bootsel(),
+        ResetAction::Ignore => {}
+    }
+}
+
+// Buttons replayed in place of the real ones, with `OVERRIDE_ACTIVE` set.
+static OVERRIDE: AtomicU16 = AtomicU16::new(0);
+const OVERRIDE_ACTIVE: u16 = 0x100;
+
+fn overridden_buttons() -> Option<ButtonMask> {
+    let value = OVERRIDE.load(Ordering::Relaxed);
+    (value & OVERRIDE_ACTIVE != 0).then_some(value as ButtonMask)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEventKind {
+    Press,
+    Release,
+    /// Sent while a button is held, after the repeat delay.
+    Repeat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonEvent {
+    pub button: ButtonId,
+    pub kind: ButtonEventKind,
+    /// `time::time_us64` when the event happened.
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Copy)]
+struct ButtonState {
+    gpio: u8,
+    held: bool,
+    last_change_us: u64,
+    next_repeat_us: u64,
+}
+
+struct EventState {
+    buttons: [ButtonState; NUM_BUTTONS],
+    events: heapless::Deque<ButtonEvent, EVENT_QUEUE_SIZE>,
+    // Zero disables repeat.
+    repeat_delay_us: u64,
+    repeat_interval_us: u64,
+    enabled: bool,
+    dropped_events: u32,
+}
+
+impl EventState {
+    fn push(&mut self, button: ButtonId, kind: ButtonEventKind, timestamp: u64) {
+        if self
+            .events
+            .push_back(ButtonEvent {
+                button,
+                kind,
+                timestamp,
+            })
+            .is_err()
+        {
+            self.dropped_events += 1;
+        }
+    }
+
+    fn poll(&mut self) {
+        let now = time::time_us64();
+        let gpio_in = unsafe { (*pac::SIO::PTR).gpio_in.read().bits() };
+        let overridden = overridden_buttons();
+        for (i, &id) in BUTTON_IDS.iter().enumerate() {
+            let button = self.buttons[i];
+            // Buttons pull their pins low.
+            let held = match overridden {
+                Some(buttons) => buttons & (1 << i) != 0,
+                None => gpio_in & (1 << button.gpio) == 0,
+            };
+            if held != button.held {
+                if now - button.last_change_us < EVENT_DEBOUNCE_US {
+                    continue;
+                }
+                let kind = if held {
+                    ButtonEventKind::Press
+                } else {
+                    ButtonEventKind::Release
+                };
+                self.push(id, kind, now);
+                let button = &mut self.buttons[i];
+                button.held = held;
+                button.last_change_us = now;
+                button.next_repeat_us = now + self.repeat_delay_us;
+            } else if held && self.repeat_delay_us > 0 && now >= button.next_repeat_us {
+                self.push(id, ButtonEventKind::Repeat, now);
+                self.buttons[i].next_repeat_us = now + self.repeat_interval_us.max(1);
+            }
+        }
+    }
+}
+
+static EVENTS: Mutex<RefCell<EventState>> = Mutex::new(RefCell::new(EventState {
+    buttons: [ButtonState {
+        gpio: 0,
+        held: false,
+        last_change_us: 0,
+        next_repeat_us: 0,
+    }; NUM_BUTTONS],
+    events: heapless::Deque::new(),
+    repeat_delay_us: 400_000,
+    repeat_interval_us: 100_000,
+    enabled: false,
+    dropped_events: 0,
+}));
+
 pub struct Button {
     pin: DynPin,
+    // Bit of the button in a `ButtonMask`, or 0 if it isn't part of `Input`.
+    mask: ButtonMask,
     press_inhibit: bool,
     last_held_time: u64,
     last_repeat_time: u64,
+    presses: PressTracker,
 }
 
 impl Button {
@@ -17,14 +207,24 @@ impl Button {
         pin.into_pull_down_input();
         Button {
             pin,
+            mask: 0,
             press_inhibit: false,
             last_held_time: 0,
             last_repeat_time: 0,
+            presses: PressTracker::new(),
         }
     }
 
     pub fn is_held(&self) -> bool {
-        self.pin.is_low().unwrap()
+        match overridden_buttons() {
+            Some(buttons) if self.mask != 0 => buttons & self.mask != 0,
+            _ => self.pin.is_low().unwrap(),
+        }
+    }
+
+    fn with_mask(mut self, mask: ButtonMask) -> Self {
+        self.mask = mask;
+        self
     }
 
     pub fn is_pressed(&mut self) -> bool {
@@ -50,6 +250,39 @@ impl Button {
             false
         }
     }
+
+    /// Whether the button has been held for at least `duration`. Like
+    /// `is_pressed`, this and the long and double presses only see the
+    /// button when asked, so games ask every frame.
+    pub fn held_for(&mut self, duration: Duration) -> bool {
+        let now = time::time_us64();
+        self.presses.update(self.is_held(), now);
+        self.presses.held_for(duration, now)
+    }
+
+    /// Returns true once per press, when the button has been held for the
+    /// long press time, half a second unless set otherwise.
+    pub fn is_long_pressed(&mut self) -> bool {
+        let now = time::time_us64();
+        self.presses.update(self.is_held(), now);
+        self.presses.take_long_press(now)
+    }
+
+    /// Returns true once when the button is pressed a second time within
+    /// the double press window, 300 ms unless set otherwise. A third press
+    /// starts again.
+    pub fn is_double_pressed(&mut self) -> bool {
+        self.presses.update(self.is_held(), time::time_us64());
+        self.presses.take_double_press()
+    }
+
+    pub fn set_long_press(&mut self, duration: Duration) {
+        self.presses.set_long_press(duration);
+    }
+
+    pub fn set_double_press_window(&mut self, window: Duration) {
+        self.presses.set_double_press_window(window);
+    }
 }
 
 pub struct Input {
@@ -61,6 +294,10 @@ pub struct Input {
     pub button_y: Button,
     pub button_a: Button,
     pub button_b: Button,
+    reset_combos: ComboDetector<ResetAction, 2>,
+    reset_requested: bool,
+    button_map: ButtonMap,
+    _alarm: Alarm,
 }
 
 impl Input {
@@ -75,33 +312,265 @@ impl Input {
         button_a_pin: DynPin,
         button_b_pin: DynPin,
     ) -> Self {
+        let pins = [
+            &dpad_left_pin,
+            &dpad_right_pin,
+            &dpad_up_pin,
+            &dpad_down_pin,
+            &button_x_pin,
+            &button_y_pin,
+            &button_a_pin,
+            &button_b_pin,
+        ];
+        critical_section::with(|cs| {
+            let mut events = EVENTS.borrow_ref_mut(cs);
+            for (button, pin) in events.buttons.iter_mut().zip(pins) {
+                button.gpio = pin.id().num;
+            }
+        });
         Input {
-            dpad_left: Button::new(dpad_left_pin),
-            dpad_right: Button::new(dpad_right_pin),
-            dpad_up: Button::new(dpad_up_pin),
-            dpad_down: Button::new(dpad_down_pin),
-            button_x: Button::new(button_x_pin),
-            button_y: Button::new(button_y_pin),
-            button_a: Button::new(button_a_pin),
-            button_b: Button::new(button_b_pin),
+            dpad_left: Button::new(dpad_left_pin).with_mask(1 << 0),
+            dpad_right: Button::new(dpad_right_pin).with_mask(1 << 1),
+            dpad_up: Button::new(dpad_up_pin).with_mask(1 << 2),
+            dpad_down: Button::new(dpad_down_pin).with_mask(1 << 3),
+            button_x: Button::new(button_x_pin).with_mask(1 << 4),
+            button_y: Button::new(button_y_pin).with_mask(1 << 5),
+            button_a: Button::new(button_a_pin).with_mask(1 << 6),
+            button_b: Button::new(button_b_pin).with_mask(1 << 7),
+            reset_combos: Self::reset_combos(None),
+            reset_requested: false,
+            button_map: ButtonMap::new(),
+            _alarm: Alarm::claim(time::ALARM_INPUT).unwrap(),
         }
     }
 
-    pub fn is_active(&self) -> bool {
-        for button in [
+    fn reset_combos(bootsel_buttons: Option<ButtonMask>) -> ComboDetector<ResetAction, 2> {
+        let mut combos = ComboDetector::new();
+        combos.add(
+            ResetAction::Launcher,
+            Combo::Chord {
+                buttons: RESET_BUTTONS,
+                hold_ms: RESET_HOLD_MS,
+            },
+        );
+        if let Some(buttons) = bootsel_buttons {
+            combos.add(
+                ResetAction::Bootloader,
+                Combo::Chord {
+                    buttons,
+                    hold_ms: BOOTSEL_HOLD_MS,
+                },
+            );
+        }
+        combos
+    }
+
+    /// Replaces what holding X, Y, A and B does, which is to go back to
+    /// the launcher. `None` restores that.
+    pub fn set_reset_handler(&mut self, handler: Option<ResetHandler>) {
+        critical_section::with(|cs| RESET_HANDLER.borrow(cs).set(handler));
+    }
+
+    /// Makes holding `buttons` for two seconds reboot into the USB
+    /// bootloader, without asking the reset handler. Handy while developing
+    /// on a console that is closed up. `None`, the default, turns it off.
+    pub fn set_bootsel_chord(&mut self, buttons: Option<ButtonMask>) {
+        self.reset_combos = Self::reset_combos(buttons);
+    }
+
+    /// Resets if X, Y, A and B have been held for a second and the reset
+    /// handler agrees. Called by `Hardware::draw` every frame. Only the
+    /// real buttons count, so a recording can't reset.
+    pub fn check_reset(&mut self) {
+        let held = self.physical_buttons();
+        let completed = self.reset_combos.update(held, time::time_us64());
+        if completed.contains(&ResetAction::Bootloader) {
+            reset(ResetAction::Bootloader);
+        }
+        if completed.is_empty() {
+            return;
+        }
+        let handler = critical_section::with(|cs| RESET_HANDLER.borrow(cs).get());
+        let action = handler.map_or(ResetAction::Launcher, |handler| handler());
+        self.reset_requested = action == ResetAction::Ignore;
+        reset(action);
+    }
+
+    /// Whether the reset chord was held and the handler ignored it, since
+    /// the last call.
+    pub fn take_reset_request(&mut self) -> bool {
+        core::mem::take(&mut self.reset_requested)
+    }
+
+    fn buttons(&self) -> [&Button; NUM_BUTTONS] {
+        [
             &self.dpad_left,
             &self.dpad_right,
-            &self.dpad_down,
             &self.dpad_up,
+            &self.dpad_down,
             &self.button_x,
             &self.button_y,
             &self.button_a,
             &self.button_b,
-        ] {
-            if button.is_held() {
-                return true;
-            }
+        ]
+    }
+
+    /// Whether a button is actually held, even while a recording is played
+    /// back.
+    pub fn is_active(&self) -> bool {
+        self.physical_buttons() != 0
+    }
+
+    /// The buttons held, or the ones played back by `replay_frame`.
+    pub fn held_buttons(&self) -> ButtonMask {
+        self.buttons()
+            .iter()
+            .fold(0, |mask, button| match button.is_held() {
+                true => mask | button.mask,
+                false => mask,
+            })
+    }
+
+    /// The buttons actually held, for example to end an attract mode demo.
+    pub fn physical_buttons(&self) -> ButtonMask {
+        self.buttons()
+            .iter()
+            .fold(0, |mask, button| match button.pin.is_low().unwrap() {
+                true => mask | button.mask,
+                false => mask,
+            })
+    }
+
+    /// Sets which button does each action.
+    pub fn set_button_map(&mut self, button_map: ButtonMap) {
+        self.button_map = button_map;
+    }
+
+    pub fn button_map(&self) -> &ButtonMap {
+        &self.button_map
+    }
+
+    /// The button of `action`, to check whether it is held or pressed.
+    pub fn action_button(&mut self, action: Action) -> &mut Button {
+        match self.button_map.button(action) {
+            ButtonId::DpadLeft => &mut self.dpad_left,
+            ButtonId::DpadRight => &mut self.dpad_right,
+            ButtonId::DpadUp => &mut self.dpad_up,
+            ButtonId::DpadDown => &mut self.dpad_down,
+            ButtonId::X => &mut self.button_x,
+            ButtonId::Y => &mut self.button_y,
+            ButtonId::A => &mut self.button_a,
+            ButtonId::B => &mut self.button_b,
         }
-        false
     }
+
+    pub fn is_action_held(&self, action: Action) -> bool {
+        self.held_buttons() & self.button_map.button(action).mask() != 0
+    }
+
+    /// Feeds the buttons held on this frame to `combos` and returns the
+    /// combos completed.
+    pub fn update_combos<Id: Copy, const N: usize>(
+        &self,
+        combos: &mut ComboDetector<Id, N>,
+    ) -> heapless::Vec<Id, N> {
+        combos.update(self.held_buttons(), time::time_us64())
+    }
+
+    /// Adds the buttons held on this frame to `recording`. Returns false
+    /// once the recording is full.
+    pub fn record_frame<const N: usize>(&self, recording: &mut Recording<N>) -> bool {
+        recording.push(self.held_buttons())
+    }
+
+    /// Makes the buttons, and their events, follow the next frame of
+    /// `player` instead of the real buttons. Returns false, and gives the
+    /// real buttons back, once the recording has been played.
+    pub fn replay_frame(&mut self, player: &mut Player) -> bool {
+        let buttons = player.next_frame();
+        self.set_override(buttons);
+        buttons.is_some()
+    }
+
+    /// Replaces the real buttons with `buttons`, or gives them back with
+    /// `None`.
+    pub fn set_override(&mut self, buttons: Option<ButtonMask>) {
+        let value = buttons.map_or(0, |buttons| OVERRIDE_ACTIVE | buttons as u16);
+        OVERRIDE.store(value, Ordering::Relaxed);
+    }
+    /// Starts sampling the buttons from a timer interrupt into an event
+    /// queue read with `poll_event`, so presses between frames aren't missed.
+    pub fn enable_events(&mut self) {
+        critical_section::with(|cs| {
+            let mut events = EVENTS.borrow_ref_mut(cs);
+            if events.enabled {
+                return;
+            }
+            events.enabled = true;
+            events.events.clear();
+            let now = time::time_us64();
+            let gpio_in = unsafe { (*pac::SIO::PTR).gpio_in.read().bits() };
+            for button in events.buttons.iter_mut() {
+                button.held = gpio_in & (1 << button.gpio) == 0;
+                button.last_change_us = now;
+            }
+            unsafe { Alarm::new(time::ALARM_INPUT) }.schedule_periodic(POLL_PERIOD_US, sample);
+        });
+    }
+
+    pub fn disable_events(&mut self) {
+        critical_section::with(|cs| EVENTS.borrow_ref_mut(cs).enabled = false);
+    }
+
+    /// Held buttons send `Repeat` events after `delay_ms` and then every
+    /// `interval_ms`. A delay of 0 disables repeat.
+    pub fn set_repeat(&mut self, delay_ms: u32, interval_ms: u32) {
+        critical_section::with(|cs| {
+            let mut events = EVENTS.borrow_ref_mut(cs);
+            events.repeat_delay_us = delay_ms as u64 * 1000;
+            events.repeat_interval_us = interval_ms as u64 * 1000;
+        });
+    }
+
+    /// Returns the oldest queued event.
+    pub fn poll_event(&mut self) -> Option<ButtonEvent> {
+        critical_section::with(|cs| EVENTS.borrow_ref_mut(cs).events.pop_front())
+    }
+
+    /// Waits for the next event in an async task. Needs `enable_events`.
+    #[cfg(feature = "async")]
+    pub async fn next_event(&mut self) -> ButtonEvent {
+        core::future::poll_fn(|cx| {
+            EVENT_WAKER.register(cx.waker());
+            match self.poll_event() {
+                Some(event) => core::task::Poll::Ready(event),
+                None => core::task::Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Number of events lost because the queue was full.
+    pub fn dropped_events(&self) -> u32 {
+        critical_section::with(|cs| EVENTS.borrow_ref(cs).dropped_events)
+    }
+}
+
+// The task waiting in `Input::next_event`.
+#[cfg(feature = "async")]
+static EVENT_WAKER: WakerSlot = WakerSlot::new();
+
+// Samples the buttons into the event queue, on the input alarm.
+fn sample() -> bool {
+    critical_section::with(|cs| {
+        let mut events = EVENTS.borrow_ref_mut(cs);
+        if events.enabled {
+            events.poll();
+            #[cfg(feature = "async")]
+            if !events.events.is_empty() {
+                EVENT_WAKER.wake();
+            }
+        }
+        events.enabled
+    })
 }
@@ -0,0 +1,261 @@
+//! The SIO interpolators, two per core.
+//!
+//! Each interpolator has two lanes that shift, mask and add an accumulator to
+//! a base in a single cycle, which makes them good at generating addresses
+//! for texture lookups, clamping values and blending between two values.
+//! Interpolators are handed out like DMA channels: claimed per core and
+//! released when dropped, so an interrupt handler can't clobber the state of
+//! the code it interrupted.
+
+use core::cell::Cell;
+use critical_section::Mutex;
+use rp_pico::hal::pac;
+
+pub const NUM_INTERPS: usize = 2;
+
+/// Interpolator with the blend mode.
+pub const INTERP_BLEND: usize = 0;
+/// Interpolator with the clamp mode.
+pub const INTERP_CLAMP: usize = 1;
+
+// Offsets of the registers of an interpolator from its base.
+const ACCUM0: usize = 0x00;
+const BASE0: usize = 0x08;
+const POP_LANE0: usize = 0x14;
+const POP_FULL: usize = 0x1c;
+const PEEK_LANE0: usize = 0x20;
+const PEEK_FULL: usize = 0x28;
+const CTRL_LANE0: usize = 0x2c;
+const ACCUM0_ADD: usize = 0x34;
+
+// Offset of the first interpolator from the SIO base, and between the two.
+const INTERP0_OFFSET: usize = 0x80;
+const INTERP_STRIDE: usize = 0x40;
+
+/// Bitmap of interpolators handed out, two bits per core.
+static CLAIMED_INTERPS: Mutex<Cell<u8>> = Mutex::new(Cell::new(0));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterpError {
+    InvalidInterp(usize),
+    AlreadyClaimed(usize),
+}
+
+/// Hands out the current core's interpolators.
+pub struct InterpManager;
+
+impl InterpManager {
+    pub fn claim(index: usize) -> Result<Interp, InterpError> {
+        if index >= NUM_INTERPS {
+            return Err(InterpError::InvalidInterp(index));
+        }
+        let bit = 1 << (current_core() * NUM_INTERPS + index);
+        critical_section::with(|cs| {
+            let claimed = CLAIMED_INTERPS.borrow(cs);
+            if claimed.get() & bit != 0 {
+                return Err(InterpError::AlreadyClaimed(index));
+            }
+            claimed.set(claimed.get() | bit);
+            Ok(())
+        })?;
+        let base =
+            unsafe { (pac::SIO::PTR as *mut u8).add(INTERP0_OFFSET + index * INTERP_STRIDE) };
+        Ok(Interp {
+            index,
+            bit,
+            base: base as *mut u32,
+        })
+    }
+
+    fn release(bit: u8) {
+        critical_section::with(|cs| {
+            let claimed = CLAIMED_INTERPS.borrow(cs);
+            claimed.set(claimed.get() & !bit);
+        });
+    }
+}
+
+fn current_core() -> usize {
+    unsafe { (*pac::SIO::PTR).cpuid.read().bits() as usize }
+}
+
+/// Configuration of one lane, written to its control register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LaneConfig(u32);
+
+impl LaneConfig {
+    /// Passes the accumulator through unchanged.
+    pub const fn new() -> Self {
+        LaneConfig(31 << 10)
+    }
+
+    /// Shifts the accumulator right by `shift` bits.
+    pub const fn shift(self, shift: u32) -> Self {
+        LaneConfig(self.0 & !0x1f | (shift & 0x1f))
+    }
+
+    /// Keeps bits `lsb` to `msb` inclusive of the shifted accumulator.
+    pub const fn mask(self, lsb: u32, msb: u32) -> Self {
+        LaneConfig(self.0 & !(0x3ff << 5) | (lsb & 0x1f) << 5 | (msb & 0x1f) << 10)
+    }
+
+    /// Sign extends the masked value, and compares signed when clamping.
+    pub const fn signed(self, signed: bool) -> Self {
+        self.flag(15, signed)
+    }
+
+    /// Adds the raw accumulator to the base for the lane result, so popping
+    /// steps the accumulator. The full result still uses the shifted and
+    /// masked value.
+    pub const fn add_raw(self, add_raw: bool) -> Self {
+        self.flag(18, add_raw)
+    }
+
+    /// Lane 0 of interpolator 0 only: lane 1 blends between the two bases.
+    pub const fn blend(self, blend: bool) -> Self {
+        self.flag(21, blend)
+    }
+
+    /// Lane 0 of interpolator 1 only: clamps the lane result between the
+    /// two bases.
+    pub const fn clamp(self, clamp: bool) -> Self {
+        self.flag(22, clamp)
+    }
+
+    const fn flag(self, bit: u32, set: bool) -> Self {
+        if set {
+            LaneConfig(self.0 | 1 << bit)
+        } else {
+            LaneConfig(self.0 & !(1 << bit))
+        }
+    }
+}
+
+impl Default for LaneConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Interp {
+    index: usize,
+    bit: u8,
+    base: *mut u32,
+}
+
+impl Interp {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    fn write(&mut self, offset: usize, value: u32) {
+        unsafe { self.base.add(offset / 4).write_volatile(value) }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { self.base.add(offset / 4).read_volatile() }
+    }
+
+    pub fn set_config(&mut self, lane: usize, config: LaneConfig) {
+        self.write(CTRL_LANE0 + lane * 4, config.0);
+    }
+
+    pub fn set_accum(&mut self, lane: usize, value: u32) {
+        self.write(ACCUM0 + lane * 4, value);
+    }
+
+    pub fn accum(&self, lane: usize) -> u32 {
+        self.read(ACCUM0 + lane * 4)
+    }
+
+    /// Adds `value` to the accumulator in a single write.
+    pub fn add_accum(&mut self, lane: usize, value: u32) {
+        self.write(ACCUM0_ADD + lane * 4, value);
+    }
+
+    /// Sets the base of a lane, or with `lane` 2 the base of the full result.
+    pub fn set_base(&mut self, lane: usize, value: u32) {
+        self.write(BASE0 + lane * 4, value);
+    }
+
+    pub fn peek(&self, lane: usize) -> u32 {
+        self.read(PEEK_LANE0 + lane * 4)
+    }
+
+    /// Returns a lane result and writes both lane results back to their
+    /// accumulators.
+    pub fn pop(&mut self, lane: usize) -> u32 {
+        self.read(POP_LANE0 + lane * 4)
+    }
+
+    pub fn peek_full(&self) -> u32 {
+        self.read(PEEK_FULL)
+    }
+
+    /// Returns the full result, the base 2 plus both shifted and masked
+    /// accumulators, and writes the lane results back.
+    pub fn pop_full(&mut self) -> u32 {
+        self.read(POP_FULL)
+    }
+
+    /// Sets up lane 0 to step through `base + (start + n * step) >> frac_bits`
+    /// on successive `pop_full`s, for walking texture coordinates in fixed
+    /// point. Negative steps are fine as long as the coordinate stays
+    /// positive. Lane 1 is zeroed.
+    pub fn configure_stepper(&mut self, frac_bits: u32, start: u32, step: i32, base: u32) {
+        self.set_config(
+            0,
+            LaneConfig::new()
+                .shift(frac_bits)
+                .mask(0, 31 - frac_bits)
+                .add_raw(true),
+        );
+        self.set_config(1, LaneConfig::new());
+        self.set_accum(0, start);
+        self.set_base(0, step as u32);
+        self.set_accum(1, 0);
+        self.set_base(1, 0);
+        self.set_base(2, base);
+    }
+
+    /// Moves a stepper set up by `configure_stepper` to a new start and base,
+    /// keeping its step.
+    pub fn restart_stepper(&mut self, start: u32, base: u32) {
+        self.set_accum(0, start);
+        self.set_base(2, base);
+    }
+
+    /// Sets up interpolator 1 for `clamp`.
+    pub fn configure_clamp(&mut self, min: i32, max: i32) {
+        assert_eq!(self.index, INTERP_CLAMP, "only interpolator 1 clamps");
+        self.set_config(0, LaneConfig::new().signed(true).clamp(true));
+        self.set_base(0, min as u32);
+        self.set_base(1, max as u32);
+    }
+
+    pub fn clamp(&mut self, value: i32) -> i32 {
+        self.set_accum(0, value as u32);
+        self.peek(0) as i32
+    }
+
+    /// Sets up interpolator 0 for `blend`.
+    pub fn configure_blend(&mut self) {
+        assert_eq!(self.index, INTERP_BLEND, "only interpolator 0 blends");
+        self.set_config(0, LaneConfig::new().blend(true));
+        self.set_config(1, LaneConfig::new());
+    }
+
+    /// Blends from `a` to `b` by `alpha` / 256.
+    pub fn blend(&mut self, a: u32, b: u32, alpha: u8) -> u32 {
+        self.set_base(0, a);
+        self.set_base(1, b);
+        self.set_accum(1, alpha as u32);
+        self.peek(1)
+    }
+}
+
+impl Drop for Interp {
+    fn drop(&mut self) {
+        InterpManager::release(self.bit);
+    }
+}
@@ -21,6 +21,27 @@ pub unsafe fn disable_gpio_interrupt(gpio: usize, event: GpioEvent) {
         .modify(|r, w| w.bits(r.bits() & !((event as u32) << (4 * (gpio % 8)))));
 }
 
+/// Makes `event` on `gpio` wake the chip from DORMANT.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn enable_dormant_wake(gpio: usize, event: GpioEvent) {
+    let regs = &*pac::IO_BANK0::PTR;
+    regs.dormant_wake_inte[gpio / 8]
+        .modify(|r, w| w.bits(r.bits() | (event as u32) << (4 * (gpio % 8))));
+}
+
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn disable_dormant_wake(gpio: usize, event: GpioEvent) {
+    let regs = &*pac::IO_BANK0::PTR;
+    regs.dormant_wake_inte[gpio / 8]
+        .modify(|r, w| w.bits(r.bits() & !((event as u32) << (4 * (gpio % 8)))));
+}
+
+/// Whether `event` on `gpio` is raising the interrupt of core 0.
+pub fn gpio_interrupt_pending(gpio: usize, event: GpioEvent) -> bool {
+    let regs = unsafe { &*pac::IO_BANK0::PTR };
+    regs.proc0_ints[gpio / 8].read().bits() & (event as u32) << (4 * (gpio % 8)) != 0
+}
+
 #[allow(clippy::missing_safety_doc)]
 pub unsafe fn unmask_gpio_interrupt() {
     pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
@@ -43,5 +64,6 @@ pub fn acknowledge_gpio_interrupt() {
 #[allow(non_snake_case)]
 #[interrupt]
 unsafe fn IO_IRQ_BANK0() {
+    crate::display::handle_te_interrupt();
     acknowledge_gpio_interrupt();
 }
@@ -0,0 +1,368 @@
+//! Running games kept side by side in flash, like cartridges.
+//!
+//! Flash past the first 4 MiB, up to the file system, is split into
+//! `SLOT_COUNT` slots of `SLOT_SIZE` bytes, each holding a game linked to
+//! run from it with `slot.x` as its `memory.x`, with that slot's addresses.
+//! The game declares a `GameHeader`, usually with
+//! `picosystem_macros::game_meta!`, which gives its name and icon for the
+//! menu. Games are put in their slots by
+//! flashing their UF2 files like any other, which leaves the launcher and
+//! the other slots alone.
+//!
+//! `run` shows a menu of the games found. The chosen one is booted by
+//! recording its slot in a watchdog scratch register and resetting the
+//! device. `Hardware::new` checks the register before setting anything up,
+//! and then moves the vector table to the slot and jumps to the game's
+//! reset handler, so the game starts from a clean reset like it would on
+//! its own. Resetting the device from the game goes back to the launcher.
+
+use crate::hardware::Hardware;
+use crate::input::Input;
+use crate::logging;
+use crate::sprite::Sprite;
+use embedded_graphics::image::Image;
+use embedded_graphics::mono_font::{ascii::FONT_10X20, MonoTextStyle};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+use rp_pico::hal::pac;
+
+const XIP_BASE: u32 = 0x1000_0000;
+/// Offset of the first slot from the start of flash, where `memory.x` ends
+/// `FLASH`.
+pub const SLOTS_OFFSET: u32 = 4 * 1024 * 1024;
+pub const SLOT_SIZE: u32 = 1024 * 1024;
+pub const SLOT_COUNT: usize =
+    ((crate::storage::STORAGE_OFFSET - SLOTS_OFFSET) / SLOT_SIZE) as usize;
+/// Offset of the vector table in an image, after the copy of boot2 every
+/// image starts with.
+pub const VECTORS_OFFSET: u32 = 0x100;
+/// Offset of the `GameHeader` in an image, between the vector table and the
+/// code.
+pub const HEADER_OFFSET: u32 = 0x200;
+
+pub const MAX_NAME_LEN: usize = 24;
+pub const MAX_VERSION_LEN: usize = 16;
+pub const MAX_AUTHOR_LEN: usize = 24;
+pub const ICON_SIZE: u32 = 32;
+pub const ICON_PIXELS: usize = (ICON_SIZE * ICON_SIZE) as usize;
+
+// "PSGM"
+const MAGIC: u32 = 0x4d47_5350;
+const VERSION: u32 = 2;
+
+// Kept in watchdog scratch3 across the reset, with the slot in the low
+// bits.
+const BOOT_MAGIC: u32 = 0xb007_0000;
+const BOOT_SLOT_MASK: u32 = 0xffff;
+
+const RAM_START: u32 = 0x2000_0000;
+const RAM_END: u32 = 0x2004_2000;
+
+/// What the launcher knows about a game, placed at `HEADER_OFFSET` in its
+/// image by `memory.x` or `slot.x` when declared in the `.game_header`
+/// section, usually with `picosystem_macros::game_meta!`.
+///
+/// Host tools can find it in a UF2 file at that offset from the start of
+/// the image. It is laid out as declared, in little-endian 32-bit words
+/// except for the strings, which are UTF-8 padded with zeros after their
+/// length, and the icon pixels.
+#[repr(C)]
+pub struct GameHeader {
+    magic: u32,
+    version: u32,
+    name_len: u32,
+    name: [u8; MAX_NAME_LEN],
+    game_version_len: u32,
+    game_version: [u8; MAX_VERSION_LEN],
+    author_len: u32,
+    author: [u8; MAX_AUTHOR_LEN],
+    // The icon is drawn only if this is set.
+    has_icon: u32,
+    // `u32::MAX` if the icon has no transparent color.
+    transparent_color: u32,
+    icon: [u16; ICON_PIXELS],
+}
+
+impl GameHeader {
+    /// A header for the game called `name`, cut to `MAX_NAME_LEN` bytes,
+    /// without an icon.
+    pub const fn new(name: &str) -> Self {
+        let (name_len, name) = copy_str(name);
+        GameHeader {
+            magic: MAGIC,
+            version: VERSION,
+            name_len,
+            name,
+            game_version_len: 0,
+            game_version: [0; MAX_VERSION_LEN],
+            author_len: 0,
+            author: [0; MAX_AUTHOR_LEN],
+            has_icon: 0,
+            transparent_color: u32::MAX,
+            icon: [0; ICON_PIXELS],
+        }
+    }
+
+    /// Sets the version of the game, such as "1.2", cut to `MAX_VERSION_LEN`
+    /// bytes.
+    pub const fn with_version(self, version: &str) -> Self {
+        let (game_version_len, game_version) = copy_str(version);
+        GameHeader {
+            game_version_len,
+            game_version,
+            ..self
+        }
+    }
+
+    /// Sets who made the game, cut to `MAX_AUTHOR_LEN` bytes.
+    pub const fn with_author(self, author: &str) -> Self {
+        let (author_len, author) = copy_str(author);
+        GameHeader {
+            author_len,
+            author,
+            ..self
+        }
+    }
+
+    /// Shows `pixels`, `ICON_SIZE` pixels square and laid out like sprite
+    /// data, next to the name.
+    pub const fn with_icon(self, pixels: &[u16; ICON_PIXELS]) -> Self {
+        GameHeader {
+            has_icon: 1,
+            icon: *pixels,
+            ..self
+        }
+    }
+
+    pub const fn with_transparent_color(self, color: u16) -> Self {
+        GameHeader {
+            transparent_color: color as u32,
+            ..self
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        as_str(&self.name, self.name_len)
+    }
+
+    /// The version of the game, empty if it wasn't given.
+    pub fn game_version(&self) -> &str {
+        as_str(&self.game_version, self.game_version_len)
+    }
+
+    /// Who made the game, empty if it wasn't given.
+    pub fn author(&self) -> &str {
+        as_str(&self.author, self.author_len)
+    }
+
+    pub fn icon(&self) -> Option<Sprite<'_>> {
+        (self.has_icon != 0).then_some(Sprite {
+            size: Size::new_equal(ICON_SIZE),
+            transparent_color: u16::try_from(self.transparent_color).ok(),
+            data: &self.icon,
+            alpha: None,
+        })
+    }
+}
+
+// Copies as much of `s` as fits without cutting a character in two,
+// returning the length copied.
+const fn copy_str<const N: usize>(s: &str) -> (u32, [u8; N]) {
+    let bytes = s.as_bytes();
+    let mut len = if bytes.len() < N { bytes.len() } else { N };
+    // Back to the first byte of the character at the cut.
+    while len < bytes.len() && len > 0 && bytes[len] & 0xc0 == 0x80 {
+        len -= 1;
+    }
+    let mut copy = [0; N];
+    let mut i = 0;
+    while i < len {
+        copy[i] = bytes[i];
+        i += 1;
+    }
+    (len as u32, copy)
+}
+
+fn as_str(bytes: &[u8], len: u32) -> &str {
+    core::str::from_utf8(&bytes[..(len as usize).min(bytes.len())]).unwrap_or("?")
+}
+
+/// A game found in a slot.
+#[derive(Clone, Copy)]
+pub struct Game {
+    pub slot: usize,
+    pub header: &'static GameHeader,
+}
+
+fn slot_address(slot: usize) -> u32 {
+    XIP_BASE + SLOTS_OFFSET + slot as u32 * SLOT_SIZE
+}
+
+/// The game in `slot`, if it has a valid header and vector table.
+pub fn game(slot: usize) -> Option<Game> {
+    if slot >= SLOT_COUNT {
+        return None;
+    }
+    let address = slot_address(slot);
+    let header = unsafe { &*((address + HEADER_OFFSET) as *const GameHeader) };
+    if header.magic != MAGIC || header.version != VERSION {
+        return None;
+    }
+    let vectors = (address + VECTORS_OFFSET) as *const u32;
+    let (stack, reset) = unsafe { (vectors.read(), vectors.add(1).read()) };
+    let slot_range = address + VECTORS_OFFSET..address + SLOT_SIZE;
+    if !(RAM_START..=RAM_END).contains(&stack) || !slot_range.contains(&(reset & !1)) {
+        logging::warn!("Ignoring slot {} with a bad vector table", slot);
+        return None;
+    }
+    Some(Game { slot, header })
+}
+
+/// The games in every slot, in slot order.
+pub fn scan() -> heapless::Vec<Game, SLOT_COUNT> {
+    (0..SLOT_COUNT).filter_map(game).collect()
+}
+
+/// Resets the device into the game in `game.slot`.
+pub fn boot(game: &Game) -> ! {
+    let watchdog = unsafe { &*pac::WATCHDOG::PTR };
+    watchdog
+        .scratch3
+        .write(|w| unsafe { w.bits(BOOT_MAGIC | game.slot as u32) });
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+/// Jumps to the game `boot` reset into, if any, called by `Hardware::new`
+/// before it sets anything up.
+pub(crate) fn chain_if_requested() {
+    let watchdog = unsafe { &*pac::WATCHDOG::PTR };
+    let request = watchdog.scratch3.read().bits();
+    if request & !BOOT_SLOT_MASK != BOOT_MAGIC {
+        return;
+    }
+    watchdog.scratch3.write(|w| unsafe { w.bits(0) });
+    let Some(game) = game((request & BOOT_SLOT_MASK) as usize) else {
+        return;
+    };
+    let vectors = slot_address(game.slot) + VECTORS_OFFSET;
+    unsafe {
+        (*cortex_m::peripheral::SCB::PTR).vtor.write(vectors);
+        cortex_m::asm::bootload(vectors as *const u32)
+    }
+}
+
+const TITLE_Y: i32 = 16;
+const FIRST_ROW_Y: i32 = 34;
+const ROW_HEIGHT: i32 = ICON_SIZE as i32 + 2;
+const VISIBLE_ROWS: usize = 6;
+const MARGIN: i32 = 8;
+
+/// The menu of games shown by `run`.
+pub struct Menu {
+    games: heapless::Vec<Game, SLOT_COUNT>,
+    selected: usize,
+}
+
+impl Menu {
+    pub fn new(games: heapless::Vec<Game, SLOT_COUNT>) -> Self {
+        Menu { games, selected: 0 }
+    }
+
+    pub fn selected(&self) -> Option<&Game> {
+        self.games.get(self.selected)
+    }
+
+    /// Handles button presses, call once per frame. Returns the game to
+    /// boot once A is pressed.
+    pub fn update(&mut self, input: &mut Input) -> Option<Game> {
+        if input.dpad_up.is_pressed() && self.selected > 0 {
+            self.selected -= 1;
+        }
+        if input.dpad_down.is_pressed() && self.selected + 1 < self.games.len() {
+            self.selected += 1;
+        }
+        if input.button_a.is_pressed() {
+            return self.selected().copied();
+        }
+        None
+    }
+
+    pub fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        target.clear(Rgb565::BLACK)?;
+        let width = target.bounding_box().size.width as i32;
+        let style = |alignment| {
+            TextStyleBuilder::new()
+                .alignment(alignment)
+                .baseline(Baseline::Middle)
+                .build()
+        };
+        let title = MonoTextStyle::new(&FONT_10X20, Rgb565::CSS_GOLD);
+        Text::with_text_style(
+            "GAMES",
+            Point::new(width / 2, TITLE_Y),
+            title,
+            style(Alignment::Center),
+        )
+        .draw(target)?;
+        if self.games.is_empty() {
+            let text = MonoTextStyle::new(&FONT_10X20, Rgb565::CSS_LIGHT_GRAY);
+            Text::with_text_style(
+                "No games found",
+                target.bounding_box().center(),
+                text,
+                style(Alignment::Center),
+            )
+            .draw(target)?;
+            return Ok(());
+        }
+
+        // Scrolled so the selected game is on screen.
+        let first = self.selected.saturating_sub(VISIBLE_ROWS - 1);
+        for (row, game) in self.games.iter().enumerate().skip(first).take(VISIBLE_ROWS) {
+            let top = FIRST_ROW_Y + (row - first) as i32 * ROW_HEIGHT;
+            let selected = row == self.selected;
+            if selected {
+                Rectangle::new(
+                    Point::new(0, top),
+                    Size::new(width as u32, ROW_HEIGHT as u32),
+                )
+                .into_styled(PrimitiveStyle::with_fill(Rgb565::CSS_DARK_GREEN))
+                .draw(target)?;
+            }
+            if let Some(icon) = game.header.icon() {
+                Image::new(&icon, Point::new(MARGIN, top + 1)).draw(target)?;
+            }
+            let color = if selected {
+                Rgb565::WHITE
+            } else {
+                Rgb565::CSS_LIGHT_GRAY
+            };
+            Text::with_text_style(
+                game.header.name(),
+                Point::new(2 * MARGIN + ICON_SIZE as i32, top + ROW_HEIGHT / 2),
+                MonoTextStyle::new(&FONT_10X20, color),
+                style(Alignment::Left),
+            )
+            .draw(target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows the games in the slots and boots the one chosen.
+pub fn run(hw: &mut Hardware) -> ! {
+    let mut menu = Menu::new(scan());
+    loop {
+        if let Some(game) = menu.update(&mut hw.input) {
+            boot(&game);
+        }
+        hw.draw(|display| {
+            let _ = menu.draw(display);
+        });
+    }
+}
@@ -0,0 +1,246 @@
+use crate::time::{self, Alarm};
+use core::cell::RefCell;
+use critical_section::Mutex;
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+use rp2040_hal::gpio::dynpin::{DynFunction, DynPin, DynPinMode};
+use rp_pico::hal::pac;
+
+// Levels are squared brightness for a rough gamma correction.
+const PWM_TOP: u16 = 255 * 255;
+const EFFECT_PERIOD_US: u32 = 20_000;
+
+// PWM slice and channel (0 for A, 1 for B) of each LED pin.
+const RED: (usize, usize) = (7, 0); // GPIO14
+const GREEN: (usize, usize) = (6, 1); // GPIO13
+const BLUE: (usize, usize) = (7, 1); // GPIO15
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Solid,
+    /// On for the first half of each period and off for the second.
+    Blink {
+        period_ms: u32,
+    },
+    /// Fades in and out once per period.
+    Breathe {
+        period_ms: u32,
+    },
+}
+
+// A few blinks of another color over the effect, then back to it.
+#[derive(Clone, Copy)]
+struct Flash {
+    color: Rgb888,
+    period_ms: u32,
+    start_us: u32,
+    duration_us: u32,
+}
+
+struct LedState {
+    color: Rgb888,
+    brightness: u8,
+    effect: Effect,
+    effect_start_us: u32,
+    flash: Option<Flash>,
+    running: bool,
+}
+
+impl LedState {
+    // Brightness of the effect at `now`, 0 to 255.
+    fn effect_level(&self, now: u32) -> u32 {
+        let elapsed_ms = now.wrapping_sub(self.effect_start_us) / 1000;
+        match self.effect {
+            Effect::Solid => 255,
+            Effect::Blink { period_ms } => {
+                if elapsed_ms % period_ms.max(1) < period_ms / 2 {
+                    255
+                } else {
+                    0
+                }
+            }
+            Effect::Breathe { period_ms } => {
+                let period_ms = period_ms.max(2);
+                let phase = elapsed_ms % period_ms;
+                let half = period_ms / 2;
+                if phase < half {
+                    phase * 255 / half
+                } else {
+                    (period_ms - phase) * 255 / (period_ms - half)
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, now: u32) {
+        let mut color = self.color;
+        let mut level = self.effect_level(now);
+        if let Some(flash) = self.flash {
+            let elapsed_us = now.wrapping_sub(flash.start_us);
+            if elapsed_us < flash.duration_us {
+                let phase_ms = elapsed_us / 1000 % flash.period_ms;
+                color = flash.color;
+                level = if phase_ms < flash.period_ms / 2 {
+                    255
+                } else {
+                    0
+                };
+            } else {
+                self.flash = None;
+            }
+        }
+        let level = level * self.brightness as u32 / 255;
+        for (pwm, value) in [(RED, color.r()), (GREEN, color.g()), (BLUE, color.b())] {
+            let value = value as u32 * level / 255;
+            unsafe { set_pwm_level(pwm, (value * value) as u16) };
+        }
+    }
+
+    // Runs the effect timer while an effect is active.
+    fn start(&mut self) {
+        let now = timer_now();
+        self.update(now);
+        if self.is_animated() && !self.running {
+            self.running = true;
+            unsafe { Alarm::new(time::ALARM_LED) }.schedule_periodic(EFFECT_PERIOD_US, animate);
+        }
+    }
+
+    fn is_animated(&self) -> bool {
+        self.effect != Effect::Solid || self.flash.is_some()
+    }
+}
+
+static LED: Mutex<RefCell<LedState>> = Mutex::new(RefCell::new(LedState {
+    color: Rgb888::BLACK,
+    brightness: 255,
+    effect: Effect::Solid,
+    effect_start_us: 0,
+    flash: None,
+    running: false,
+}));
+
+/// The RGB LED, dimmed with PWM. Effects are animated from a timer interrupt.
+pub struct Led {
+    _pins: [DynPin; 3],
+    _alarm: Alarm,
+}
+
+impl Led {
+    pub fn new(
+        red_pin: DynPin,
+        green_pin: DynPin,
+        blue_pin: DynPin,
+        pwm: &pac::PWM,
+        resets: &mut pac::RESETS,
+    ) -> Self {
+        resets.reset.modify(|_, w| w.pwm().clear_bit());
+        while resets.reset_done.read().pwm().bit_is_clear() {}
+
+        for slice in [GREEN.0, RED.0] {
+            let slice = &pwm.ch[slice];
+            slice.top.write(|w| unsafe { w.top().bits(PWM_TOP) });
+            slice
+                .div
+                .write(|w| unsafe { w.int().bits(1).frac().bits(0) });
+            slice.csr.modify(|_, w| w.en().set_bit());
+        }
+        let mut pins = [red_pin, green_pin, blue_pin];
+        for pin in pins.iter_mut() {
+            pin.try_into_mode(DynPinMode::Function(DynFunction::Pwm))
+                .unwrap();
+        }
+        for channel in [RED, GREEN, BLUE] {
+            unsafe { set_pwm_level(channel, 0) };
+        }
+
+        Led {
+            _pins: pins,
+            _alarm: Alarm::claim(time::ALARM_LED).unwrap(),
+        }
+    }
+
+    pub fn set_color(&mut self, color: Rgb888) {
+        critical_section::with(|cs| {
+            let mut led = LED.borrow_ref_mut(cs);
+            led.color = color;
+            led.start();
+        });
+    }
+
+    pub fn color(&self) -> Rgb888 {
+        critical_section::with(|cs| LED.borrow_ref(cs).color)
+    }
+
+    /// Scales the color, from 0 (off) to 255 (full brightness).
+    pub fn set_brightness(&mut self, brightness: u8) {
+        critical_section::with(|cs| {
+            let mut led = LED.borrow_ref_mut(cs);
+            led.brightness = brightness;
+            led.start();
+        });
+    }
+
+    pub fn set_effect(&mut self, effect: Effect) {
+        critical_section::with(|cs| {
+            let mut led = LED.borrow_ref_mut(cs);
+            led.effect = effect;
+            led.effect_start_us = timer_now();
+            led.start();
+        });
+    }
+
+    pub fn blink(&mut self, color: Rgb888, period_ms: u32) {
+        self.set_color(color);
+        self.set_effect(Effect::Blink { period_ms });
+    }
+
+    pub fn breathe(&mut self, color: Rgb888, period_ms: u32) {
+        self.set_color(color);
+        self.set_effect(Effect::Breathe { period_ms });
+    }
+
+    /// Blinks `color` `times` times over the color and effect set, which
+    /// carry on afterwards.
+    pub fn flash(&mut self, color: Rgb888, times: u32, period_ms: u32) {
+        let period_ms = period_ms.max(2);
+        critical_section::with(|cs| {
+            let mut led = LED.borrow_ref_mut(cs);
+            led.flash = Some(Flash {
+                color,
+                period_ms,
+                start_us: timer_now(),
+                duration_us: times * period_ms * 1000,
+            });
+            led.start();
+        });
+    }
+
+    /// Turns the LED off and stops any effect.
+    pub fn off(&mut self) {
+        self.set_effect(Effect::Solid);
+        self.set_color(Rgb888::BLACK);
+    }
+}
+
+fn timer_now() -> u32 {
+    unsafe { (*pac::TIMER::PTR).timerawl.read().bits() }
+}
+
+unsafe fn set_pwm_level((slice, channel): (usize, usize), level: u16) {
+    let cc = &(*pac::PWM::PTR).ch[slice].cc;
+    if channel == 0 {
+        cc.modify(|_, w| w.a().bits(level));
+    } else {
+        cc.modify(|_, w| w.b().bits(level));
+    }
+}
+
+// Moves the effect on, on the LED alarm.
+fn animate() -> bool {
+    critical_section::with(|cs| {
+        let mut led = LED.borrow_ref_mut(cs);
+        led.update(timer_now());
+        led.running = led.is_animated();
+        led.running
+    })
+}
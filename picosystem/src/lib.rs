@@ -1,21 +1,67 @@
 #![no_std]
 
+#[cfg(feature = "simulator")]
+extern crate std;
+
+pub mod achievements;
+pub mod anim;
+pub mod button_map;
+pub mod camera;
+pub mod collision;
+pub mod color_filter;
+pub mod combo;
+pub mod compression;
+pub mod console;
+pub mod dirty_rects;
+pub mod entity;
+pub mod font;
+pub mod fsm;
+pub mod gradient;
+pub mod high_scores;
+mod logging;
 pub mod map;
+pub mod math;
+pub mod pathfinding;
+mod press;
+pub mod replay;
+pub mod rng;
+pub mod settings;
 pub mod sprite;
+pub mod test_pattern;
 pub mod tile;
+pub mod toast;
+pub mod virtual_stick;
 
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod audio;
 
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod blit;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod crt_filter;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod debug_overlay;
+
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod display;
 
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod dma;
 
-#[cfg(all(target_arch = "arm", target_os = "none"))]
+#[cfg(all(target_arch = "arm", target_os = "none", feature = "async"))]
+pub mod executor;
+
+#[cfg(any(
+    all(target_arch = "arm", target_os = "none"),
+    all(feature = "simulator", not(target_os = "none"))
+))]
 pub mod fps_monitor;
 
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod frame_arena;
+
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod hardware;
 
@@ -25,6 +71,69 @@ pub mod idle;
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod input;
 
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod interp;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod launcher;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod led;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod lighting;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod link;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod meminfo;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod music;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod particles;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod peripherals;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod power;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod profile;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod raycast;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod render;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod rom_math;
+
+#[cfg(all(target_arch = "arm", target_os = "none", feature = "defmt"))]
+mod rtt_logger;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod scene;
+
+#[cfg(any(
+    all(target_arch = "arm", target_os = "none"),
+    all(feature = "simulator", not(target_os = "none"))
+))]
+pub mod scheduler;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod splash;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod storage;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod three_d;
+
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod time;
 
@@ -34,8 +143,26 @@ pub mod time_tracker;
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod interrupts;
 
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod ui;
+
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod usb_logger;
 
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod usb_storage;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod watchdog;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod xip;
+
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod panic;
+
+#[cfg(all(feature = "simulator", not(target_os = "none")))]
+pub mod simulator;
+
+#[cfg(all(feature = "simulator", not(target_os = "none")))]
+pub use simulator::{audio, display, hardware, input, led, time, watchdog};
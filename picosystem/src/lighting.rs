@@ -0,0 +1,178 @@
+//! Dynamic lights for caves and night scenes.
+//!
+//! A `LightMap` holds how brightly lit the screen is every `CELL` pixels.
+//! Each frame, `clear` fills it with the ambient light, `add_point` and
+//! `add_cone` add torches and flashlights on top, and once the scene is
+//! drawn `apply` multiplies the framebuffer by it, like `Blend::Multiply`
+//! with a light image. Between samples the light is interpolated, so the
+//! coarse map still gives smooth edges.
+//!
+//! Lighting the whole screen takes several milliseconds, as every pixel is
+//! multiplied.
+
+use crate::display::{framebuffer, screen_height, Display, HEIGHT, WIDTH};
+use crate::math::{atan2, Angle, Fixed};
+use embedded_graphics::pixelcolor::{Rgb565, RgbColor};
+use embedded_graphics::prelude::*;
+
+/// Pixels between samples of the light map, a power of two.
+pub const CELL: usize = 8;
+const CELL_SHIFT: u32 = CELL.trailing_zeros();
+const MAP_WIDTH: usize = WIDTH / CELL + 1;
+const MAP_HEIGHT: usize = HEIGHT / CELL + 1;
+
+pub struct LightMap {
+    // Red, green and blue of each sample, 255 for full brightness.
+    samples: [[u8; 3]; MAP_WIDTH * MAP_HEIGHT],
+}
+
+impl LightMap {
+    /// A map lit fully, which leaves the screen unchanged.
+    pub const fn new() -> Self {
+        LightMap {
+            samples: [[255; 3]; MAP_WIDTH * MAP_HEIGHT],
+        }
+    }
+
+    /// Starts a frame with `ambient` light everywhere, black for total
+    /// darkness.
+    pub fn clear(&mut self, ambient: Rgb565) {
+        self.samples = [channels(ambient); MAP_WIDTH * MAP_HEIGHT];
+    }
+
+    /// Adds a light at `center` that fades out towards `radius`.
+    pub fn add_point(&mut self, center: Point, radius: u32, color: Rgb565) {
+        self.add_light(center, radius, color, |_, _| 256);
+    }
+
+    /// Adds a light at `center` shining towards `direction`, spreading
+    /// `spread` to either side, like a flashlight.
+    pub fn add_cone(
+        &mut self,
+        center: Point,
+        radius: u32,
+        direction: Angle,
+        spread: Angle,
+        color: Rgb565,
+    ) {
+        let spread = spread.0.min(0x7fff) as i32;
+        if spread == 0 {
+            return;
+        }
+        self.add_light(center, radius, color, |dx, dy| {
+            if dx == 0 && dy == 0 {
+                return 256;
+            }
+            let angle = atan2(Fixed::from_int(dy), Fixed::from_int(dx));
+            let off_axis = ((angle - direction).0 as i16 as i32).abs();
+            // Full strength in the middle, fading over the outer quarter.
+            ((spread - off_axis) * 1024 / spread).clamp(0, 256) as u32
+        });
+    }
+
+    // Adds `color` to the samples within `radius` of `center`, scaled by a
+    // quadratic falloff and by `shape(dx, dy)` out of 256.
+    fn add_light(
+        &mut self,
+        center: Point,
+        radius: u32,
+        color: Rgb565,
+        shape: impl Fn(i32, i32) -> u32,
+    ) {
+        let radius = radius as i32;
+        if radius == 0 {
+            return;
+        }
+        let color = channels(color);
+        let radius_squared = (radius * radius) as u32;
+        // 1 / radius squared in 8.24, so the falloff needs no division per
+        // sample.
+        let inverse = (1 << 24) / radius_squared;
+        let first = |c: i32| ((c - radius).max(0) as usize).div_ceil(CELL);
+        let last =
+            |c: i32, samples: usize| (((c + radius).max(0) as usize) / CELL).min(samples - 1);
+        for sy in first(center.y)..=last(center.y, MAP_HEIGHT) {
+            let dy = (sy * CELL) as i32 - center.y;
+            for sx in first(center.x)..=last(center.x, MAP_WIDTH) {
+                let dx = (sx * CELL) as i32 - center.x;
+                let distance_squared = (dx * dx + dy * dy) as u32;
+                if distance_squared >= radius_squared {
+                    continue;
+                }
+                let falloff = ((radius_squared - distance_squared) * inverse) >> 16;
+                let strength = (falloff * shape(dx, dy)) >> 8;
+                if strength == 0 {
+                    continue;
+                }
+                let sample = &mut self.samples[sy * MAP_WIDTH + sx];
+                for (channel, light) in sample.iter_mut().zip(color) {
+                    *channel = (*channel as u32 + ((light as u32 * strength) >> 8)).min(255) as u8;
+                }
+            }
+        }
+    }
+
+    /// Multiplies the framebuffer by the light map.
+    pub fn apply(&self, display: &mut Display) {
+        let fb = framebuffer();
+        for y in 0..screen_height() {
+            let (sy, fy) = (y >> CELL_SHIFT, (y & (CELL - 1)) as i32);
+            let top = &self.samples[sy * MAP_WIDTH..(sy + 1) * MAP_WIDTH];
+            let bottom = &self.samples[(sy + 1) * MAP_WIDTH..(sy + 2) * MAP_WIDTH];
+            // Light down the row in 8.8 fixed point, interpolated between
+            // the samples above and below and then along the row.
+            let row_light = |sx: usize| -> [i32; 3] {
+                core::array::from_fn(|c| {
+                    let (top, bottom) = (top[sx][c] as i32, bottom[sx][c] as i32);
+                    (top << 8) + (((bottom - top) * fy) << (8 - CELL_SHIFT))
+                })
+            };
+            let row = &mut fb[y * WIDTH..(y + 1) * WIDTH];
+            let mut left = row_light(0);
+            for (sx, pixels) in row.chunks_exact_mut(CELL).enumerate() {
+                let right = row_light(sx + 1);
+                if left == [255 << 8; 3] && right == [255 << 8; 3] {
+                    left = right;
+                    continue;
+                }
+                // Dividing rounds towards zero, so the light never steps
+                // past the next sample.
+                let step: [i32; 3] = core::array::from_fn(|c| (right[c] - left[c]) / CELL as i32);
+                let mut light = left;
+                for pixel in pixels {
+                    *pixel = light_pixel(u16::from_be(*pixel), &light).to_be();
+                    for c in 0..3 {
+                        light[c] += step[c];
+                    }
+                }
+                left = right;
+            }
+        }
+        display.mark_dirty(display.bounding_box());
+    }
+}
+
+impl Default for LightMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The channels of `color` scaled to 0-255.
+fn channels(color: Rgb565) -> [u8; 3] {
+    [
+        color.r() << 3 | color.r() >> 2,
+        color.g() << 2 | color.g() >> 4,
+        color.b() << 3 | color.b() >> 2,
+    ]
+}
+
+// `color` times `light`, whose channels are 8.8 fixed point with 255.0 for
+// full brightness.
+fn light_pixel(color: u16, light: &[i32; 3]) -> u16 {
+    let scale = |value: u16, light: i32| ((value as u32 * (light as u32 + 256)) >> 16) as u16;
+    let red = scale(color >> 11, light[0]);
+    let green = scale((color >> 5) & 0x3f, light[1]);
+    let blue = scale(color & 0x1f, light[2]);
+    red << 11 | green << 5 | blue
+}
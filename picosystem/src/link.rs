@@ -0,0 +1,469 @@
+//! Two consoles playing together over a serial cable, like the link cables
+//! of old handhelds.
+//!
+//! The link is UART0 on GPIO0 (TX) and GPIO1 (RX). Cross TX and RX between
+//! the two consoles and connect their grounds. `Link::connect` waits for
+//! the other console, then `send`, `poll` and `recv` exchange small packets
+//! that are checksummed, acknowledged and sent again until they arrive, in
+//! order and once each. Games that run in lockstep call `exchange` once per
+//! frame with their input and get the other console's input for the same
+//! frame back.
+//!
+//! A frame is `SYNC`, its kind, a sequence number, the payload length, the
+//! payload and a Fletcher-16 checksum of everything after `SYNC`.
+
+#[cfg(feature = "async")]
+use crate::executor::{self, WakerSlot};
+use crate::{hardware, logging, time};
+use core::cell::RefCell;
+use critical_section::Mutex;
+use fugit::{HertzU32, RateExtU32};
+use rp2040_hal::gpio::pin::bank0::{Gpio0, Gpio1};
+use rp2040_hal::gpio::pin::{FunctionUart, Pin, PinId, PinMode, ValidPinMode};
+use rp2040_hal::uart::{DataBits, Enabled, StopBits, UartConfig, UartPeripheral};
+use rp_pico::hal::pac;
+use rp_pico::hal::pac::interrupt;
+
+pub const BAUD_RATE: u32 = 500_000;
+pub const MAX_PAYLOAD: usize = 32;
+
+const SYNC: u8 = 0x7e;
+// Sync, kind, sequence number and length.
+const HEADER_LEN: usize = 4;
+const MAX_FRAME: usize = HEADER_LEN + MAX_PAYLOAD + 2;
+const PROTOCOL_VERSION: u8 = 1;
+const HELLO_INTERVAL_US: u32 = 50_000;
+const RETRY_US: u32 = 20_000;
+// Half a second without an acknowledgement ends the connection.
+const MAX_RETRIES: u32 = 25;
+const RX_BUFFER_SIZE: usize = 256;
+const RX_QUEUE_SIZE: usize = 4;
+
+pub type Packet = heapless::Vec<u8, MAX_PAYLOAD>;
+
+type Frame = heapless::Vec<u8, MAX_FRAME>;
+type LinkPins = (Pin<Gpio0, FunctionUart>, Pin<Gpio1, FunctionUart>);
+type LinkUart = UartPeripheral<Enabled, pac::UART0, LinkPins>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkError {
+    NotConnected,
+    /// The previous packet hasn't been acknowledged yet.
+    Busy,
+    TooLong,
+    Timeout,
+    /// The other console stopped answering.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Kind {
+    /// The protocol version, the sender's device ID and a number picked by
+    /// each `connect`.
+    Hello = 1,
+    HelloAck = 2,
+    Data = 3,
+    Ack = 4,
+}
+
+impl Kind {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Kind::Hello),
+            2 => Some(Kind::HelloAck),
+            3 => Some(Kind::Data),
+            4 => Some(Kind::Ack),
+            _ => None,
+        }
+    }
+}
+
+// Bytes received by the interrupt, waiting for `Link::poll`.
+struct RxState {
+    bytes: heapless::Deque<u8, RX_BUFFER_SIZE>,
+    dropped: u32,
+}
+
+static RX: Mutex<RefCell<RxState>> = Mutex::new(RefCell::new(RxState {
+    bytes: heapless::Deque::new(),
+    dropped: 0,
+}));
+
+// The task waiting for bytes in `Link::recv_async` or `Link::send_async`.
+#[cfg(feature = "async")]
+static RX_WAKER: WakerSlot = WakerSlot::new();
+
+fn checksum(bytes: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for &byte in bytes {
+        a = (a + byte as u16) % 255;
+        b = (b + a) % 255;
+    }
+    b << 8 | a
+}
+
+fn encode(kind: Kind, seq: u8, payload: &[u8]) -> Frame {
+    let mut frame = Frame::new();
+    let _ = frame.extend_from_slice(&[SYNC, kind as u8, seq, payload.len() as u8]);
+    let _ = frame.extend_from_slice(payload);
+    let sum = checksum(&frame[1..]);
+    let _ = frame.extend_from_slice(&sum.to_le_bytes());
+    frame
+}
+
+/// Collects bytes into frames, skipping anything that doesn't check out
+/// until the next `SYNC`.
+struct Parser {
+    frame: Frame,
+}
+
+impl Parser {
+    fn push(&mut self, byte: u8) -> Option<(Kind, u8, Packet)> {
+        if self.frame.is_empty() && byte != SYNC {
+            return None;
+        }
+        let _ = self.frame.push(byte);
+        if self.frame.len() < HEADER_LEN {
+            return None;
+        }
+        let len = self.frame[3] as usize;
+        if len > MAX_PAYLOAD {
+            self.frame.clear();
+            return None;
+        }
+        if self.frame.len() < HEADER_LEN + len + 2 {
+            return None;
+        }
+        let body = &self.frame[1..HEADER_LEN + len];
+        let sum = u16::from_le_bytes([
+            self.frame[HEADER_LEN + len],
+            self.frame[HEADER_LEN + len + 1],
+        ]);
+        let result = match Kind::from_u8(self.frame[1]) {
+            Some(kind) if sum == checksum(body) => Some((
+                kind,
+                self.frame[2],
+                Packet::from_slice(&self.frame[HEADER_LEN..HEADER_LEN + len]).unwrap(),
+            )),
+            _ => None,
+        };
+        self.frame.clear();
+        result
+    }
+}
+
+pub struct Link {
+    uart: LinkUart,
+    parser: Parser,
+    peer_id: Option<u64>,
+    session: u32,
+    peer_session: u32,
+    next_seq: u8,
+    // The last data frame sent, until it is acknowledged.
+    unacked: Option<(u8, Frame)>,
+    retries: u32,
+    last_send_us: u32,
+    // Resent copies of this one are acknowledged again but not delivered.
+    last_received_seq: Option<u8>,
+    received: heapless::Deque<Packet, RX_QUEUE_SIZE>,
+}
+
+impl Link {
+    pub fn new<TxMode, RxMode>(
+        uart: pac::UART0,
+        tx_pin: Pin<Gpio0, TxMode>,
+        rx_pin: Pin<Gpio1, RxMode>,
+        resets: &mut pac::RESETS,
+        peripheral_clock: HertzU32,
+    ) -> Self
+    where
+        TxMode: PinMode + ValidPinMode<Gpio0>,
+        RxMode: PinMode + ValidPinMode<Gpio1>,
+    {
+        let pins = (tx_pin.into_mode(), rx_pin.into_mode());
+        // Keeps the line idle while nothing is plugged in.
+        unsafe {
+            (*pac::PADS_BANK0::PTR).gpio[Gpio1::DYN.num as usize]
+                .modify(|_, w| w.pue().set_bit().pde().clear_bit());
+        }
+        let config = UartConfig::new(BAUD_RATE.Hz(), DataBits::Eight, None, StopBits::One);
+        let mut uart = UartPeripheral::new(uart, pins, resets)
+            .enable(config, peripheral_clock)
+            .unwrap();
+        uart.enable_rx_interrupt();
+        unsafe { pac::NVIC::unmask(pac::Interrupt::UART0_IRQ) };
+        Link {
+            uart,
+            parser: Parser {
+                frame: Frame::new(),
+            },
+            peer_id: None,
+            session: 0,
+            peer_session: 0,
+            next_seq: 0,
+            unacked: None,
+            retries: 0,
+            last_send_us: 0,
+            last_received_seq: None,
+            received: heapless::Deque::new(),
+        }
+    }
+
+    /// Waits up to `timeout_ms` for the other console, which must be
+    /// calling `connect` too.
+    pub fn connect(&mut self, timeout_ms: u32) -> Result<(), LinkError> {
+        let start = time::time_us();
+        self.session = start;
+        let mut last_hello_us = start.wrapping_sub(HELLO_INTERVAL_US);
+        while !self.is_connected() {
+            let now = time::time_us();
+            if now.wrapping_sub(start) >= timeout_ms.saturating_mul(1000) {
+                return Err(LinkError::Timeout);
+            }
+            if now.wrapping_sub(last_hello_us) >= HELLO_INTERVAL_US {
+                self.send_hello(Kind::Hello);
+                last_hello_us = now;
+            }
+            self.receive();
+        }
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) {
+        self.peer_id = None;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.peer_id.is_some()
+    }
+
+    /// The device ID of the other console.
+    pub fn peer_id(&self) -> Option<u64> {
+        self.peer_id
+    }
+
+    /// True on exactly one of two connected consoles, for example to pick
+    /// who is player one.
+    pub fn is_host(&self) -> bool {
+        self.peer_id
+            .is_some_and(|peer_id| hardware::device_id() < peer_id)
+    }
+
+    /// Whether `send` can take another packet.
+    pub fn can_send(&self) -> bool {
+        self.is_connected() && self.unacked.is_none()
+    }
+
+    /// Sends `data`. Only one packet is in flight at a time, call `poll`
+    /// until `can_send` before sending the next.
+    pub fn send(&mut self, data: &[u8]) -> Result<(), LinkError> {
+        if !self.is_connected() {
+            return Err(LinkError::NotConnected);
+        }
+        if data.len() > MAX_PAYLOAD {
+            return Err(LinkError::TooLong);
+        }
+        if self.unacked.is_some() {
+            return Err(LinkError::Busy);
+        }
+        let seq = self.next_seq;
+        self.next_seq = seq.wrapping_add(1);
+        let frame = encode(Kind::Data, seq, data);
+        self.uart.write_full_blocking(&frame);
+        self.unacked = Some((seq, frame));
+        self.retries = 0;
+        self.last_send_us = time::time_us();
+        Ok(())
+    }
+
+    /// Handles what was received and sends the last packet again if it
+    /// wasn't acknowledged in time. Call it at least once per frame.
+    pub fn poll(&mut self) -> Result<(), LinkError> {
+        if !self.is_connected() {
+            return Err(LinkError::NotConnected);
+        }
+        self.receive();
+        if let Some((_, frame)) = &self.unacked {
+            let now = time::time_us();
+            if now.wrapping_sub(self.last_send_us) >= RETRY_US {
+                if self.retries == MAX_RETRIES {
+                    logging::warn!("Link lost");
+                    self.peer_id = None;
+                    self.unacked = None;
+                    return Err(LinkError::Disconnected);
+                }
+                self.uart.write_full_blocking(frame);
+                self.retries += 1;
+                self.last_send_us = now;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the oldest packet received.
+    pub fn recv(&mut self) -> Option<Packet> {
+        self.received.pop_front()
+    }
+
+    /// Waits for the next packet in an async task, polling whenever bytes
+    /// arrive or the last packet is due to be sent again.
+    #[cfg(feature = "async")]
+    pub async fn recv_async(&mut self) -> Result<Packet, LinkError> {
+        loop {
+            self.poll()?;
+            if let Some(packet) = self.recv() {
+                return Ok(packet);
+            }
+            received_or_retry().await;
+        }
+    }
+
+    /// Waits until `send` can take another packet in an async task, then
+    /// sends `data`.
+    #[cfg(feature = "async")]
+    pub async fn send_async(&mut self, data: &[u8]) -> Result<(), LinkError> {
+        while !self.can_send() {
+            self.poll()?;
+            if self.can_send() {
+                break;
+            }
+            received_or_retry().await;
+        }
+        self.send(data)
+    }
+
+    /// Sends `data` and waits up to `timeout_ms` for the other console's
+    /// packet. With both consoles calling this once per frame, each gets
+    /// the other's data for the same frame.
+    pub fn exchange(&mut self, data: &[u8], timeout_ms: u32) -> Result<Packet, LinkError> {
+        let start = time::time_us();
+        let timeout_us = timeout_ms.saturating_mul(1000);
+        while !self.can_send() {
+            self.poll()?;
+            if time::time_us().wrapping_sub(start) >= timeout_us {
+                return Err(LinkError::Timeout);
+            }
+        }
+        self.send(data)?;
+        loop {
+            self.poll()?;
+            if let Some(packet) = self.recv() {
+                return Ok(packet);
+            }
+            if time::time_us().wrapping_sub(start) >= timeout_us {
+                return Err(LinkError::Timeout);
+            }
+        }
+    }
+
+    /// Bytes lost because they weren't polled in time.
+    pub fn dropped_bytes(&self) -> u32 {
+        critical_section::with(|cs| RX.borrow_ref(cs).dropped)
+    }
+
+    fn send_hello(&mut self, kind: Kind) {
+        let mut payload = [0; 13];
+        payload[0] = PROTOCOL_VERSION;
+        payload[1..9].copy_from_slice(&hardware::device_id().to_le_bytes());
+        payload[9..].copy_from_slice(&self.session.to_le_bytes());
+        self.uart.write_full_blocking(&encode(kind, 0, &payload));
+    }
+
+    fn receive(&mut self) {
+        loop {
+            let byte = critical_section::with(|cs| RX.borrow_ref_mut(cs).bytes.pop_front());
+            let Some(byte) = byte else {
+                return;
+            };
+            if let Some((kind, seq, payload)) = self.parser.push(byte) {
+                self.handle(kind, seq, &payload);
+            }
+        }
+    }
+
+    fn handle(&mut self, kind: Kind, seq: u8, payload: &[u8]) {
+        match kind {
+            Kind::Hello | Kind::HelloAck => {
+                let &[PROTOCOL_VERSION, ref rest @ ..] = payload else {
+                    logging::warn!("Link peer speaks another protocol");
+                    return;
+                };
+                let Ok(rest) = <[u8; 12]>::try_from(rest) else {
+                    return;
+                };
+                if kind == Kind::Hello {
+                    self.send_hello(Kind::HelloAck);
+                }
+                let peer_id = u64::from_le_bytes(rest[..8].try_into().unwrap());
+                let peer_session = u32::from_le_bytes(rest[8..].try_into().unwrap());
+                // Hellos repeated while the peer waits for an answer change
+                // nothing, a new connection starts again.
+                if self.peer_id != Some(peer_id) || self.peer_session != peer_session {
+                    logging::info!("Linked to {:016x}", peer_id);
+                    self.peer_id = Some(peer_id);
+                    self.peer_session = peer_session;
+                    self.next_seq = 0;
+                    self.unacked = None;
+                    self.last_received_seq = None;
+                    self.received.clear();
+                }
+            }
+            Kind::Data => {
+                if self.last_received_seq != Some(seq) {
+                    if self.received.is_full() {
+                        // Not acknowledged, so it is sent again later.
+                        return;
+                    }
+                    let _ = self
+                        .received
+                        .push_back(Packet::from_slice(payload).unwrap());
+                    self.last_received_seq = Some(seq);
+                }
+                self.uart.write_full_blocking(&encode(Kind::Ack, seq, &[]));
+            }
+            Kind::Ack => {
+                if matches!(self.unacked, Some((unacked_seq, _)) if unacked_seq == seq) {
+                    self.unacked = None;
+                }
+            }
+        }
+    }
+}
+
+// Waits for bytes, or at most until a packet sent now would be due again.
+#[cfg(feature = "async")]
+async fn received_or_retry() {
+    let deadline_us = time::time_us64() + RETRY_US as u64;
+    core::future::poll_fn(|cx| {
+        RX_WAKER.register(cx.waker());
+        let received = critical_section::with(|cs| !RX.borrow_ref(cs).bytes.is_empty());
+        if received || time::time_us64() >= deadline_us {
+            return core::task::Poll::Ready(());
+        }
+        executor::wake_at(deadline_us, cx.waker());
+        core::task::Poll::Pending
+    })
+    .await
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn UART0_IRQ() {
+    let uart = unsafe { &*pac::UART0::PTR };
+    critical_section::with(|cs| {
+        let mut rx = RX.borrow_ref_mut(cs);
+        while uart.uartfr.read().rxfe().bit_is_clear() {
+            let data = uart.uartdr.read();
+            // Framing, parity, break and overrun errors.
+            if data.bits() & 0xf00 != 0 {
+                continue;
+            }
+            if rx.bytes.push_back(data.data().bits()).is_err() {
+                rx.dropped += 1;
+            }
+        }
+    });
+    #[cfg(feature = "async")]
+    RX_WAKER.wake();
+}
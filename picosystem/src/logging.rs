@@ -0,0 +1,78 @@
+//! Logging macros for the crate's own messages. With the `defmt` feature
+//! they go to defmt, which only sends an index for the format string and
+//! the raw arguments, over RTT; otherwise to the `log` crate and USB serial.
+//!
+//! Arguments need `defmt::Format` as well as `Display` or `Debug`. Types
+//! from other crates that lack it are wrapped in `Display2Format` or
+//! `Debug2Format`, and format strings keep to what both understand: no
+//! precision, for one.
+
+// The simulator only logs a few of the crate's messages.
+#![cfg_attr(not(all(target_arch = "arm", target_os = "none")), allow(unused))]
+
+use core::fmt;
+
+#[cfg(feature = "defmt")]
+macro_rules! error {
+    ($($arg:tt)*) => { defmt::error!($($arg)*) };
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! error {
+    ($($arg:tt)*) => { log::error!($($arg)*) };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! warning {
+    ($($arg:tt)*) => { defmt::warn!($($arg)*) };
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! warning {
+    ($($arg:tt)*) => { log::warn!($($arg)*) };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! info {
+    ($($arg:tt)*) => { defmt::info!($($arg)*) };
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! info {
+    ($($arg:tt)*) => { log::info!($($arg)*) };
+}
+
+// `warn` alone would be taken for the lint attribute.
+pub(crate) use {error, info, warning as warn};
+
+/// Logs a value with its `Display` implementation.
+pub(crate) struct Display2Format<'a, T: ?Sized>(pub &'a T);
+
+impl<T: fmt::Display + ?Sized> fmt::Display for Display2Format<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T: fmt::Display + ?Sized> defmt::Format for Display2Format<'_, T> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", defmt::Display2Format(self.0))
+    }
+}
+
+/// Logs a value with its `Debug` implementation.
+pub(crate) struct Debug2Format<'a, T: ?Sized>(pub &'a T);
+
+impl<T: fmt::Debug + ?Sized> fmt::Debug for Debug2Format<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T: fmt::Debug + ?Sized> defmt::Format for Debug2Format<'_, T> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{:?}", defmt::Debug2Format(self.0))
+    }
+}
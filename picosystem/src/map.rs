@@ -1,16 +1,820 @@
-use crate::tile::Tile;
+use crate::logging;
+use crate::tile::{GenMapTile, Tile};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
 
 pub const INVALID_TILE: u16 = !0;
 pub const NUM_LAYERS: usize = 4;
+/// Width and height of a map chunk in tiles.
+pub const CHUNK_TILES: usize = 16;
 
 pub struct Map {
+    /// Size in tiles.
     pub width: usize,
     pub height: usize,
-    pub tiles: &'static [MapTile],
+    /// Width and height of a tile in pixels.
+    pub tile_size: i32,
+    /// Row major, `width_chunks()` per row. Chunks without any tiles are
+    /// `None`, and identical chunks are stored once.
+    pub chunks: &'static [Option<&'static MapChunk>],
+    /// Tiles of all the map's tilesets, each tileset numbered after the
+    /// ones before it.
     pub tile_functions: [fn() -> &'static Tile; 2048],
+    pub animations: &'static [TileAnimation],
+    /// Collision shapes of tileset tiles, sorted by tile.
+    pub tile_colliders: &'static [TileCollider],
+    /// Blocked world areas from object layers named "collision".
+    pub colliders: &'static [Rectangle],
+    /// Objects from the other object layers, in layer order.
+    pub objects: &'static [MapObject],
+    /// Sky and parallax layers, back to front.
+    pub parallax_layers: &'static [MapLayer],
+}
+
+impl Map {
+    pub fn width_chunks(&self) -> usize {
+        self.width.div_ceil(CHUNK_TILES)
+    }
+
+    /// The map's area in world coordinates.
+    pub fn bounds(&self) -> Rectangle {
+        Rectangle::new(
+            Point::zero(),
+            Size::new(
+                self.width as u32 * self.tile_size as u32,
+                self.height as u32 * self.tile_size as u32,
+            ),
+        )
+    }
+
+    /// The tile at column `x` and row `y`, or `None` outside the map and in
+    /// empty chunks.
+    pub fn tile(&self, x: i32, y: i32) -> Option<&'static MapTile> {
+        if !(0..self.width as i32).contains(&x) || !(0..self.height as i32).contains(&y) {
+            return None;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let chunk = self.chunks[x / CHUNK_TILES + y / CHUNK_TILES * self.width_chunks()]?;
+        Some(&chunk.tiles[x % CHUNK_TILES + y % CHUNK_TILES * CHUNK_TILES])
+    }
+
+    /// The tile covering world position `point`.
+    pub fn tile_at(&self, point: Point) -> Option<&'static MapTile> {
+        self.tile(
+            point.x.div_euclid(self.tile_size),
+            point.y.div_euclid(self.tile_size),
+        )
+    }
+
+    /// The layers of the tile at world position `point`, for a
+    /// `TileRenderer` map generator. `None` where no tile is placed.
+    pub fn gen_map_tile(&self, point: Point) -> Option<GenMapTile> {
+        let map_tile = self.tile_at(point)?;
+        let mut layers = heapless::Vec::new();
+        for &tile in map_tile.layers.iter().filter(|&&t| t != INVALID_TILE) {
+            let _ = layers.push(self.tile_functions[tile as usize]());
+        }
+        if layers.is_empty() {
+            None
+        } else {
+            Some(GenMapTile { layers })
+        }
+    }
+
+    pub fn is_point_blocked(&self, point: Point) -> bool {
+        self.is_blocked(&Rectangle::new(point, Size::new(1, 1)))
+    }
+
+    /// Returns true if `area`, in world coordinates, overlaps a collision
+    /// shape of a placed tile or a collider. Areas outside the map are free.
+    pub fn is_blocked(&self, area: &Rectangle) -> bool {
+        if self.colliders.iter().any(|c| overlaps(c, area)) {
+            return true;
+        }
+        let bottom_right = match area.bottom_right() {
+            Some(bottom_right) => bottom_right,
+            None => return false,
+        };
+        let start_x = area.top_left.x.div_euclid(self.tile_size).max(0);
+        let start_y = area.top_left.y.div_euclid(self.tile_size).max(0);
+        let end_x = bottom_right
+            .x
+            .div_euclid(self.tile_size)
+            .min(self.width as i32 - 1);
+        let end_y = bottom_right
+            .y
+            .div_euclid(self.tile_size)
+            .min(self.height as i32 - 1);
+        for map_y in start_y..=end_y {
+            for map_x in start_x..=end_x {
+                let origin = Point::new(map_x * self.tile_size, map_y * self.tile_size);
+                let map_tile = match self.tile(map_x, map_y) {
+                    Some(map_tile) => map_tile,
+                    None => continue,
+                };
+                for &tile in map_tile.layers.iter().filter(|&&t| t != INVALID_TILE) {
+                    if self
+                        .tile_colliders(tile)
+                        .any(|c| overlaps(&c.area.translate(origin), area))
+                    {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// The first object called `name`.
+    pub fn object(&self, name: &str) -> Option<&'static MapObject> {
+        self.objects.iter().find(|o| o.name == name)
+    }
+
+    /// Objects whose class is `kind`.
+    pub fn objects_of_kind<'a>(
+        &self,
+        kind: &'a str,
+    ) -> impl Iterator<Item = &'static MapObject> + 'a {
+        self.objects.iter().filter(move |o| o.kind == kind)
+    }
+
+    fn tile_colliders(&self, tile: u16) -> impl Iterator<Item = &TileCollider> {
+        let start = self.tile_colliders.partition_point(|c| c.tile < tile);
+        self.tile_colliders[start..]
+            .iter()
+            .take_while(move |c| c.tile == tile)
+    }
+}
+
+fn overlaps(a: &Rectangle, b: &Rectangle) -> bool {
+    !a.intersection(b).is_zero_sized()
 }
 
 #[derive(Debug)]
 pub struct MapTile {
     pub layers: [u16; NUM_LAYERS],
 }
+
+/// A square of `CHUNK_TILES` tiles on each side. Each chunk is a separate
+/// static, so only the chunks around the camera are read from flash.
+#[derive(Debug)]
+pub struct MapChunk {
+    pub tiles: [MapTile; CHUNK_TILES * CHUNK_TILES],
+}
+
+/// A layer scrolling at its own speed, or a sky filling the screen, drawn
+/// by a `TileRenderer` set up with `set_parallax`.
+#[derive(Debug)]
+pub struct MapLayer {
+    pub name: &'static str,
+    /// Scroll speed relative to the camera in 256ths, so 128 moves half as
+    /// fast as the map.
+    pub parallax_x: i32,
+    pub parallax_y: i32,
+    /// Drawn in front of the map's own layers rather than behind them.
+    pub foreground: bool,
+    pub kind: LayerKind,
+}
+
+#[derive(Debug)]
+pub enum LayerKind {
+    /// Vertical gradient from the top of the screen to the bottom.
+    Sky { top: Rgb565, bottom: Rgb565 },
+    /// Tiles indexing `Map::tile_functions`, `INVALID_TILE` where empty.
+    Tiles {
+        width: usize,
+        height: usize,
+        /// Wraps around horizontally instead of ending at the layer's edges.
+        repeat: bool,
+        chunks: &'static [Option<&'static LayerChunk>],
+    },
+}
+
+/// Tiles of a parallax layer, `CHUNK_TILES` on each side.
+#[derive(Debug)]
+pub struct LayerChunk {
+    pub tiles: [u16; CHUNK_TILES * CHUNK_TILES],
+}
+
+impl MapLayer {
+    /// Top left corner of the screen in layer coordinates when the camera is
+    /// at `position`.
+    pub fn scroll(&self, position: Point) -> Point {
+        Point::new(
+            (position.x * self.parallax_x) >> 8,
+            (position.y * self.parallax_y) >> 8,
+        )
+    }
+
+    /// The tile at column `x` and row `y`, if any.
+    pub fn tile(&self, x: i32, y: i32) -> Option<u16> {
+        let LayerKind::Tiles {
+            width,
+            height,
+            repeat,
+            chunks,
+        } = &self.kind
+        else {
+            return None;
+        };
+        let x = if *repeat {
+            x.rem_euclid(*width as i32)
+        } else {
+            x
+        };
+        if !(0..*width as i32).contains(&x) || !(0..*height as i32).contains(&y) {
+            return None;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let chunk = chunks[x / CHUNK_TILES + y / CHUNK_TILES * width.div_ceil(CHUNK_TILES)]?;
+        let tile = chunk.tiles[x % CHUNK_TILES + y % CHUNK_TILES * CHUNK_TILES];
+        (tile != INVALID_TILE).then_some(tile)
+    }
+
+    /// Color of screen row `y` of a sky layer.
+    pub fn sky_color(&self, y: i32, screen_height: i32) -> Option<Rgb565> {
+        let LayerKind::Sky { top, bottom } = self.kind else {
+            return None;
+        };
+        let lerp = |a: u8, b: u8| {
+            (a as i32 + (b as i32 - a as i32) * y / (screen_height - 1).max(1)) as u8
+        };
+        Some(Rgb565::new(
+            lerp(top.r(), bottom.r()),
+            lerp(top.g(), bottom.g()),
+            lerp(top.b(), bottom.b()),
+        ))
+    }
+}
+
+/// A map placed in a `World`.
+pub struct WorldMap {
+    /// World position of the map's top left corner, a multiple of the tile
+    /// size.
+    pub origin: Point,
+    pub map: &'static Map,
+}
+
+/// Several maps laid out side by side as one seamless world. Colliders and
+/// objects of each map stay relative to its origin.
+pub struct World {
+    pub maps: &'static [WorldMap],
+}
+
+impl World {
+    /// The map covering world position `point`. Where maps overlap the first
+    /// one wins.
+    pub fn map_at(&self, point: Point) -> Option<&'static WorldMap> {
+        self.maps
+            .iter()
+            .find(|m| m.map.bounds().translate(m.origin).contains(point))
+    }
+
+    /// The layers at world position `point`, for a `TileRenderer` map
+    /// generator.
+    pub fn gen_map_tile(&self, point: Point) -> Option<GenMapTile> {
+        let world_map = self.map_at(point)?;
+        world_map.map.gen_map_tile(point - world_map.origin)
+    }
+
+    /// Checks `area` against every map it overlaps.
+    pub fn is_blocked(&self, area: &Rectangle) -> bool {
+        self.maps.iter().any(|m| {
+            overlaps(&m.map.bounds().translate(m.origin), area)
+                && m.map.is_blocked(&area.translate(-m.origin))
+        })
+    }
+}
+
+/// Collision shape of a tileset tile, relative to the tile's top left corner.
+#[derive(Debug)]
+pub struct TileCollider {
+    pub tile: u16,
+    pub area: Rectangle,
+}
+
+/// An object placed in the map editor, such as a spawn point, chest or
+/// trigger area.
+#[derive(Debug)]
+pub struct MapObject {
+    pub id: u32,
+    pub name: &'static str,
+    /// The object's class (type in older versions of Tiled).
+    pub kind: &'static str,
+    /// World position of the top left corner.
+    pub position: Point,
+    /// Zero for point objects. Polygons get their bounding box.
+    pub size: Size,
+    pub properties: &'static [Property],
+}
+
+impl MapObject {
+    pub fn area(&self) -> Rectangle {
+        Rectangle::new(self.position, self.size)
+    }
+
+    pub fn center(&self) -> Point {
+        self.position + self.size / 2
+    }
+
+    pub fn property(&self, name: &str) -> Option<PropertyValue> {
+        self.properties
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| p.value)
+    }
+
+    pub fn int_property(&self, name: &str) -> Option<i32> {
+        match self.property(name)? {
+            PropertyValue::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn bool_property(&self, name: &str) -> Option<bool> {
+        match self.property(name)? {
+            PropertyValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn str_property(&self, name: &str) -> Option<&'static str> {
+        match self.property(name)? {
+            PropertyValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// A custom property of a map object, sorted by name.
+#[derive(Debug)]
+pub struct Property {
+    pub name: &'static str,
+    pub value: PropertyValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    /// Strings and file paths.
+    String(&'static str),
+    Color(Rgb565),
+    /// Id of another object, or 0 if unset.
+    Object(u32),
+}
+
+pub struct AnimationFrame {
+    pub tile: fn() -> &'static Tile,
+    pub duration_ms: u32,
+}
+
+/// A looping animation shown wherever `tile` is placed in the map.
+pub struct TileAnimation {
+    pub tile: fn() -> &'static Tile,
+    pub frames: &'static [AnimationFrame],
+    /// Sum of the frame durations.
+    pub duration_ms: u32,
+}
+
+impl TileAnimation {
+    pub fn frame_at(&self, time_ms: u32) -> &'static Tile {
+        let mut time_ms = time_ms % self.duration_ms.max(1);
+        for frame in self.frames {
+            if time_ms < frame.duration_ms {
+                return (frame.tile)();
+            }
+            time_ms -= frame.duration_ms;
+        }
+        (self.tile)()
+    }
+}
+
+/// Replaces `tile` with the current frame if it is animated.
+pub fn animated_tile(
+    animations: &[TileAnimation],
+    tile: &'static Tile,
+    time_ms: u32,
+) -> &'static Tile {
+    animations
+        .iter()
+        .find(|animation| core::ptr::eq((animation.tile)(), tile))
+        .map_or(tile, |animation| animation.frame_at(time_ms))
+}
+
+/// Cache hit statistics and timings of a `TileRenderer` frame.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TileRendererStats {
+    pub draw_time_us: u32,
+    pub load_time_us: u32,
+    pub cache_misses: u32,
+    pub cache_lookups: u32,
+    /// Misses that pushed another tile out of the full cache.
+    pub cache_evictions: u32,
+    /// Tiles loaded from data prefetched during the frame before.
+    pub prefetch_hits: u32,
+    /// Tiles prefetched for the next frame.
+    pub prefetched: u32,
+    /// Set when drawing fell more than two tile rows behind the display flush.
+    pub slow_draw: bool,
+}
+
+impl TileRendererStats {
+    pub fn log(&self) {
+        // In hundredths of a percent.
+        let miss_rate = self.cache_misses as u64 * 10000 / self.cache_lookups.max(1) as u64;
+        logging::info!(
+            "draw_time={}us load_time={}us",
+            self.draw_time_us,
+            self.load_time_us
+        );
+        logging::info!(
+            "Tile cache: misses={} lookups={} evictions={} miss_rate={}.{:02}%",
+            self.cache_misses,
+            self.cache_lookups,
+            self.cache_evictions,
+            miss_rate / 100,
+            miss_rate % 100
+        );
+        logging::info!(
+            "Prefetch: hits={} prefetched={}",
+            self.prefetch_hits,
+            self.prefetched
+        );
+        if self.slow_draw {
+            logging::info!("Slow draw detected");
+        }
+    }
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+mod device {
+    use crate::debug_overlay;
+    use crate::display::{framebuffer, screen_height, Display, HEIGHT, WIDTH};
+    use crate::map::{animated_tile, Map, MapLayer, TileAnimation, TileRendererStats};
+    use crate::tile::*;
+    use crate::time;
+    use crate::{meminfo, profile};
+    use embedded_graphics::pixelcolor::raw::RawU16;
+    use embedded_graphics::prelude::*;
+    use embedded_graphics::primitives::Rectangle;
+
+    /// Scrolling renderer for maps built from `SIZE` pixel tiles, which may be
+    /// 8, 16 or 32.
+    ///
+    /// Tiles are drawn row by row into the framebuffer right behind the
+    /// display flush. They are decompressed into a `TileCache`, which keeps
+    /// them from one frame to the next, so while the screen moves slowly
+    /// only the tiles coming into view are decompressed. The cache takes all
+    /// of the `TILE_CACHE_SIZE` bytes set aside for it unless
+    /// `set_cache_budget` gives it less, and only one renderer can exist at
+    /// a time.
+    ///
+    /// With parallax layers every tile is drawn with its mask over the layers
+    /// behind it.
+    ///
+    /// With `PREFETCH` above 0, the tiles that the screen will reach next
+    /// frame if it keeps moving at the same speed are copied from flash to
+    /// RAM by DMA while the frame is drawn, into two buffers of `PREFETCH`
+    /// words that take turns, so loading them next frame doesn't wait for
+    /// flash. Only tiles from the map generator are prefetched, and only
+    /// without parallax layers.
+    pub struct TileRenderer<F, const SIZE: i32 = TILE_SIZE, const PREFETCH: usize = 0>
+    where
+        F: Fn(Point) -> GenMapTile,
+    {
+        map_generator: F,
+        position: Point,
+        // Where the last frame was drawn, for the speed of the screen.
+        last_position: Point,
+        animations: &'static [TileAnimation],
+        parallax: Option<&'static Map>,
+        cache: TileCache,
+        prefetcher: TilePrefetcher<PREFETCH>,
+        stats: TileRendererStats,
+    }
+
+    impl<F, const SIZE: i32, const PREFETCH: usize> TileRenderer<F, SIZE, PREFETCH>
+    where
+        F: Fn(Point) -> GenMapTile,
+    {
+        /// `map_generator` returns the tile layers at a world position, which
+        /// is always a multiple of `SIZE`.
+        pub fn new(map_generator: F) -> Self {
+            const { assert!(is_valid_tile_size(SIZE), "tiles must be 8, 16 or 32 pixels") };
+            TileRenderer {
+                map_generator,
+                position: Point::zero(),
+                last_position: Point::zero(),
+                animations: &[],
+                parallax: None,
+                cache: TileCache::claim(TILE_CACHE_SIZE).expect("tile cache in use"),
+                prefetcher: TilePrefetcher::new(),
+                stats: TileRendererStats::default(),
+            }
+        }
+
+        /// World position of the top left corner of the screen.
+        pub fn position(&self) -> Point {
+            self.position
+        }
+
+        pub fn set_position(&mut self, position: Point) {
+            self.position = position;
+        }
+
+        /// Animates tiles returned by the map generator, usually with
+        /// `Map::animations`. Frames advance with `time::time_us`.
+        pub fn set_animations(&mut self, animations: &'static [TileAnimation]) {
+            self.animations = animations;
+        }
+
+        /// Draws the sky and parallax layers of `map` behind and in front of
+        /// the generated tiles. The generator may then leave positions
+        /// without tiles for the layers behind to show through. Put a sky
+        /// at the back unless the layers behind cover the whole screen.
+        pub fn set_parallax(&mut self, map: &'static Map) {
+            self.parallax = Some(map).filter(|map| !map.parallax_layers.is_empty());
+        }
+
+        /// Limits the tile cache to `budget` bytes of RAM, which it then
+        /// holds as many tiles of as fit.
+        pub fn set_cache_budget(&mut self, budget: usize) {
+            self.cache.set_budget(budget);
+        }
+
+        /// Statistics of the last call to `draw`.
+        pub fn stats(&self) -> &TileRendererStats {
+            &self.stats
+        }
+
+        /// Draws the whole screen. Call it right after starting a flush so
+        /// that rows are only overwritten once they have been sent.
+        pub fn draw(&mut self, display: &mut Display) {
+            match self.parallax {
+                Some(map) => self.draw_layered(display, map),
+                None => self.draw_tiles(display),
+            }
+            meminfo::report_pool("tile cache", self.cache.len(), self.cache.capacity());
+            self.last_position = self.position;
+            debug_overlay::set_tile_stats(&self.stats);
+        }
+
+        fn draw_tiles(&mut self, display: &mut Display) {
+            let position = self.position;
+            let map_generator = &self.map_generator;
+            let animations = self.animations;
+            let time_ms = (time::time_us64() / 1000) as u32;
+            let cache = &mut self.cache;
+            let stats = &mut self.stats;
+            *stats = TileRendererStats::default();
+
+            let velocity = position - self.last_position;
+            let next_tiles = entering_tiles::<SIZE>(position, position + velocity)
+                .flat_map(|map_coord| map_generator(map_coord).layers.into_iter())
+                .map(|tile| animated_tile(animations, tile, time_ms));
+            stats.prefetched = self.prefetcher.start(next_tiles) as u32;
+            let prefetcher = &self.prefetcher;
+
+            let subtile_mask = SIZE - 1;
+            let tile_size = Size::new(SIZE as u32, SIZE as u32);
+
+            let mut drawn_y: i32 = 0;
+            let mut world_y = position.y;
+            let subtile_y = position.y & subtile_mask;
+
+            let mut tile_dma = TileDma::claim();
+            // Tiles are written straight into the framebuffer.
+            display.mark_dirty(display.bounding_box());
+
+            loop {
+                wait_for_flush::<SIZE>(display, drawn_y, stats);
+                let draw_scope = profile::scope("tiles.draw");
+
+                let screen_y = drawn_y - subtile_y;
+                let subtile_x = position.x & subtile_mask;
+
+                for screen_x in (-subtile_x..(WIDTH as i32)).step_by(SIZE as usize) {
+                    let world_x = position.x + screen_x;
+                    let map_coord = Point::new(world_x & !subtile_mask, world_y & !subtile_mask);
+                    let screen_coord = Point::new(screen_x, screen_y);
+                    let map_tile = map_generator(map_coord);
+                    for (layer, tile) in map_tile.layers.iter().enumerate() {
+                        let tile = animated_tile(animations, tile, time_ms);
+                        let prefetched = prefetcher.get(tile);
+                        let loaded = cached_tile(cache, &mut tile_dma, stats, tile, prefetched);
+                        // The base layer covers the whole tile, the rest go over it.
+                        if layer == 0 {
+                            draw_opaque_tile(
+                                &mut tile_dma.channel0,
+                                loaded,
+                                screen_coord,
+                                tile_size,
+                            );
+                        } else {
+                            draw_transparent_tile(
+                                &mut tile_dma.channel0,
+                                loaded,
+                                screen_coord,
+                                tile_size,
+                            );
+                        }
+                    }
+                }
+
+                stats.draw_time_us += draw_scope.finish();
+
+                drawn_y += SIZE;
+                world_y += SIZE;
+                if screen_y + SIZE >= screen_height() as i32 {
+                    break;
+                }
+            }
+            self.prefetcher.finish();
+        }
+
+        // Draws strips of tile rows behind the flush like `draw`, each strip
+        // back to front: background layers, the generated tiles, foreground
+        // layers.
+        fn draw_layered(&mut self, display: &mut Display, map: &'static Map) {
+            let position = self.position;
+            let map_generator = &self.map_generator;
+            let stats = &mut self.stats;
+            *stats = TileRendererStats::default();
+            let mut painter = LayerPainter::<SIZE> {
+                cache: &mut self.cache,
+                tile_dma: TileDma::claim(),
+                animations: self.animations,
+                time_ms: (time::time_us64() / 1000) as u32,
+            };
+            display.mark_dirty(display.bounding_box());
+
+            let subtile_x = position.x & (SIZE - 1);
+            let subtile_y = position.y & (SIZE - 1);
+            let mut drawn_y: i32 = 0;
+            loop {
+                wait_for_flush::<SIZE>(display, drawn_y, stats);
+                let draw_scope = profile::scope("tiles.draw");
+
+                let screen_y = drawn_y - subtile_y;
+                let top = screen_y.max(0);
+                let bottom = (screen_y + SIZE).min(screen_height() as i32);
+                let clip = Rectangle::new(
+                    Point::new(0, top),
+                    Size::new(WIDTH as u32, (bottom - top) as u32),
+                );
+
+                for layer in map.parallax_layers.iter().filter(|l| !l.foreground) {
+                    painter.draw_layer(stats, map, layer, position, &clip);
+                }
+                for screen_x in (-subtile_x..(WIDTH as i32)).step_by(SIZE as usize) {
+                    let screen_coord = Point::new(screen_x, screen_y);
+                    let map_tile = map_generator(position + screen_coord);
+                    for &tile in map_tile.layers.iter() {
+                        painter.draw_tile(stats, tile, screen_coord, &clip);
+                    }
+                }
+                for layer in map.parallax_layers.iter().filter(|l| l.foreground) {
+                    painter.draw_layer(stats, map, layer, position, &clip);
+                }
+
+                stats.draw_time_us += draw_scope.finish();
+                drawn_y += SIZE;
+                if bottom >= screen_height() as i32 {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The positions of the tiles on screen at `next` that aren't at
+    /// `position`, tile aligned.
+    fn entering_tiles<const SIZE: i32>(
+        position: Point,
+        next: Point,
+    ) -> impl Iterator<Item = Point> {
+        let tiles = |position: Point| {
+            let first = Point::new(position.x.div_euclid(SIZE), position.y.div_euclid(SIZE));
+            let last = Point::new(
+                (position.x + WIDTH as i32 - 1).div_euclid(SIZE),
+                (position.y + screen_height() as i32 - 1).div_euclid(SIZE),
+            );
+            (first, last)
+        };
+        let (first, last) = tiles(position);
+        let (next_first, next_last) = tiles(next);
+        (next_first.y..=next_last.y)
+            .flat_map(move |y| (next_first.x..=next_last.x).map(move |x| Point::new(x, y)))
+            .filter(move |tile| {
+                !(first.x..=last.x).contains(&tile.x) || !(first.y..=last.y).contains(&tile.y)
+            })
+            .map(|tile| tile * SIZE)
+    }
+
+    /// `tile` from `cache`, which loads it first unless it is already there,
+    /// from `prefetched` if that holds its data.
+    fn cached_tile<'a>(
+        cache: &'a mut TileCache,
+        tile_dma: &mut TileDma,
+        stats: &mut TileRendererStats,
+        tile: &Tile,
+        prefetched: Option<&[u16]>,
+    ) -> &'a LoadedTile {
+        stats.cache_lookups += 1;
+        let (loaded, lookup) = cache.get_or_load(tile, |dst| {
+            stats.prefetch_hits += prefetched.is_some() as u32;
+            let load_scope = profile::scope("tiles.load");
+            load_tile_prefetched(tile_dma, tile, prefetched, dst, true);
+            stats.load_time_us += load_scope.finish();
+        });
+        match lookup {
+            CacheLookup::Hit => {}
+            CacheLookup::Loaded => stats.cache_misses += 1,
+            CacheLookup::Replaced => {
+                stats.cache_misses += 1;
+                stats.cache_evictions += 1;
+            }
+        }
+        loaded
+    }
+
+    /// Waits until the flush is at least a tile row past `drawn_y`.
+    fn wait_for_flush<const SIZE: i32>(
+        display: &Display,
+        drawn_y: i32,
+        stats: &mut TileRendererStats,
+    ) {
+        loop {
+            let progress = display.flush_progress();
+            let safe_y = (progress as i32 - WIDTH as i32 + 1) / WIDTH as i32;
+            if safe_y - drawn_y < SIZE && progress < (WIDTH * HEIGHT) {
+                continue;
+            } else if safe_y - drawn_y > 2 * SIZE {
+                stats.slow_draw = true;
+            }
+            return;
+        }
+    }
+
+    /// Draws masked tiles through the tile cache.
+    struct LayerPainter<'a, const SIZE: i32> {
+        cache: &'a mut TileCache,
+        tile_dma: TileDma,
+        animations: &'static [TileAnimation],
+        time_ms: u32,
+    }
+
+    impl<const SIZE: i32> LayerPainter<'_, SIZE> {
+        fn draw_tile(
+            &mut self,
+            stats: &mut TileRendererStats,
+            tile: &'static Tile,
+            dst: Point,
+            clip: &Rectangle,
+        ) {
+            let tile = animated_tile(self.animations, tile, self.time_ms);
+            let loaded = cached_tile(self.cache, &mut self.tile_dma, stats, tile, None);
+            draw_tile_clipped(&mut self.tile_dma.channel0, loaded, dst, clip, true);
+        }
+
+        fn draw_layer(
+            &mut self,
+            stats: &mut TileRendererStats,
+            map: &Map,
+            layer: &MapLayer,
+            position: Point,
+            clip: &Rectangle,
+        ) {
+            let height = screen_height() as i32;
+            if layer.sky_color(0, height).is_some() {
+                let fb = framebuffer();
+                for y in clip.rows() {
+                    let color = layer.sky_color(y, height).unwrap();
+                    let color = RawU16::from(color).into_inner().to_be();
+                    fb[y as usize * WIDTH..(y as usize + 1) * WIDTH].fill(color);
+                }
+                return;
+            }
+            let scroll = layer.scroll(position);
+            let bottom = clip.top_left.y + clip.size.height as i32;
+            let first_x = scroll.x.div_euclid(SIZE);
+            let last_x = (scroll.x + WIDTH as i32 - 1).div_euclid(SIZE);
+            let first_y = (scroll.y + clip.top_left.y).div_euclid(SIZE);
+            let last_y = (scroll.y + bottom - 1).div_euclid(SIZE);
+            for y in first_y..=last_y {
+                for x in first_x..=last_x {
+                    if let Some(index) = layer.tile(x, y) {
+                        let tile = map.tile_functions[index as usize]();
+                        let dst = Point::new(x * SIZE, y * SIZE) - scroll;
+                        self.draw_tile(stats, tile, dst, clip);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub use device::TileRenderer;
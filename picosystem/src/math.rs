@@ -0,0 +1,707 @@
+//! Fixed-point math for the FPU-less Cortex-M0+.
+//!
+//! `Fixed<FRAC>` is an `i32` with `FRAC` fractional bits. Trigonometry works
+//! on binary angles, where a full turn is 65536, and uses quarter-wave and
+//! arctangent tables computed at compile time, interpolating between entries.
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use embedded_graphics::prelude::*;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed<const FRAC: u32>(i32);
+
+/// 16 integer and 16 fractional bits, the general purpose format.
+pub type I16F16 = Fixed<16>;
+/// 8 integer and 24 fractional bits, for values that stay small such as
+/// unit vectors and blend factors.
+pub type I8F24 = Fixed<24>;
+
+impl<const FRAC: u32> Fixed<FRAC> {
+    pub const ZERO: Self = Fixed(0);
+    pub const ONE: Self = Fixed(1 << FRAC);
+    pub const HALF: Self = Fixed(1 << (FRAC - 1));
+    pub const MIN: Self = Fixed(i32::MIN);
+    pub const MAX: Self = Fixed(i32::MAX);
+
+    pub const fn from_bits(bits: i32) -> Self {
+        Fixed(bits)
+    }
+
+    pub const fn to_bits(self) -> i32 {
+        self.0
+    }
+
+    pub const fn from_int(n: i32) -> Self {
+        Fixed(n << FRAC)
+    }
+
+    /// Converts from a float, which is slow on the target. Fine in constants.
+    pub const fn from_f32(f: f32) -> Self {
+        Fixed((f * (1u32 << FRAC) as f32) as i32)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / (1u32 << FRAC) as f32
+    }
+
+    /// Rounds towards negative infinity.
+    pub const fn floor(self) -> i32 {
+        self.0 >> FRAC
+    }
+
+    pub const fn ceil(self) -> i32 {
+        (self.0 + (1 << FRAC) - 1) >> FRAC
+    }
+
+    pub const fn round(self) -> i32 {
+        (self.0 + (1 << (FRAC - 1))) >> FRAC
+    }
+
+    pub const fn frac(self) -> Self {
+        Fixed(self.0 & ((1 << FRAC) - 1))
+    }
+
+    pub const fn abs(self) -> Self {
+        Fixed(self.0.abs())
+    }
+
+    pub const fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// Converts to another number of fractional bits, losing precision or
+    /// range as needed.
+    pub const fn convert<const TO: u32>(self) -> Fixed<TO> {
+        if TO >= FRAC {
+            Fixed(self.0 << (TO - FRAC))
+        } else {
+            Fixed(self.0 >> (FRAC - TO))
+        }
+    }
+
+    pub fn mul_int(self, n: i32) -> Self {
+        Fixed(self.0 * n)
+    }
+
+    pub fn div_int(self, n: i32) -> Self {
+        Fixed(self.0 / n)
+    }
+
+    /// Linear interpolation from `self` at `t = 0` to `other` at `t = 1`.
+    pub fn lerp(self, other: Self, t: Self) -> Self {
+        self + (other - self) * t
+    }
+
+    pub fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Self::ZERO;
+        }
+        // sqrt(x * 2^FRAC) * 2^FRAC = sqrt(x * 2^(2 * FRAC)).
+        Fixed(isqrt((self.0 as u64) << FRAC) as i32)
+    }
+}
+
+impl<const FRAC: u32> Add for Fixed<FRAC> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Fixed(self.0 + other.0)
+    }
+}
+
+impl<const FRAC: u32> Sub for Fixed<FRAC> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Fixed(self.0 - other.0)
+    }
+}
+
+impl<const FRAC: u32> Mul for Fixed<FRAC> {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Fixed(((self.0 as i64 * other.0 as i64) >> FRAC) as i32)
+    }
+}
+
+impl<const FRAC: u32> Div for Fixed<FRAC> {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        // Small dividends stay in 32 bits, where division is done by the
+        // hardware divider on the RP2040 rather than in software.
+        if self.0.unsigned_abs() >> (31 - FRAC) == 0 {
+            Fixed((self.0 << FRAC) / other.0)
+        } else {
+            Fixed((((self.0 as i64) << FRAC) / other.0 as i64) as i32)
+        }
+    }
+}
+
+impl<const FRAC: u32> Neg for Fixed<FRAC> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Fixed(-self.0)
+    }
+}
+
+impl<const FRAC: u32> AddAssign for Fixed<FRAC> {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl<const FRAC: u32> SubAssign for Fixed<FRAC> {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+impl<const FRAC: u32> MulAssign for Fixed<FRAC> {
+    fn mul_assign(&mut self, other: Self) {
+        *self = *self * other;
+    }
+}
+
+impl<const FRAC: u32> DivAssign for Fixed<FRAC> {
+    fn div_assign(&mut self, other: Self) {
+        *self = *self / other;
+    }
+}
+
+impl<const FRAC: u32> From<i32> for Fixed<FRAC> {
+    fn from(n: i32) -> Self {
+        Self::from_int(n)
+    }
+}
+
+impl<const FRAC: u32> fmt::Debug for Fixed<FRAC> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl<const FRAC: u32> fmt::Display for Fixed<FRAC> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_f32())
+    }
+}
+
+fn isqrt(n: u64) -> u64 {
+    if n == 0 {
+        return 0;
+    }
+    let mut result = 0;
+    let mut bit = 1u64 << ((63 - n.leading_zeros()) & !1);
+    let mut n = n;
+    while bit != 0 {
+        if n >= result + bit {
+            n -= result + bit;
+            result = (result >> 1) + bit;
+        } else {
+            result >>= 1;
+        }
+        bit >>= 2;
+    }
+    result
+}
+
+/// An angle where a full turn is 65536, so it wraps around for free.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Angle(pub u16);
+
+impl Angle {
+    pub const ZERO: Angle = Angle(0);
+    pub const QUARTER: Angle = Angle(0x4000);
+    pub const HALF: Angle = Angle(0x8000);
+
+    pub const fn from_degrees(degrees: i32) -> Angle {
+        Angle((degrees * 65536 / 360) as u16)
+    }
+
+    pub fn from_radians(radians: I16F16) -> Angle {
+        // 65536 / 2pi in 16.16.
+        const TURNS_PER_RADIAN: i64 = 683_565_276;
+        Angle(((radians.to_bits() as i64 * TURNS_PER_RADIAN) >> 32) as u16)
+    }
+
+    pub fn to_radians(self) -> I16F16 {
+        // 2pi in 16.16.
+        const TAU: i64 = 411_775;
+        Fixed(((self.0 as i64 * TAU) >> 16) as i32)
+    }
+
+    pub fn sin(self) -> I16F16 {
+        sin(self)
+    }
+
+    pub fn cos(self) -> I16F16 {
+        cos(self)
+    }
+}
+
+impl Add for Angle {
+    type Output = Angle;
+
+    fn add(self, other: Angle) -> Angle {
+        Angle(self.0.wrapping_add(other.0))
+    }
+}
+
+impl Sub for Angle {
+    type Output = Angle;
+
+    fn sub(self, other: Angle) -> Angle {
+        Angle(self.0.wrapping_sub(other.0))
+    }
+}
+
+impl Neg for Angle {
+    type Output = Angle;
+
+    fn neg(self) -> Angle {
+        Angle(self.0.wrapping_neg())
+    }
+}
+
+const TABLE_BITS: u32 = 8;
+const TABLE_SIZE: usize = 1 << TABLE_BITS;
+
+// The tables are computed with 2.30 fixed point series.
+const SERIES_ONE: i64 = 1 << 30;
+
+/// sin over a quarter turn in 16.16, with an extra entry for the end.
+static SIN_TABLE: [i32; TABLE_SIZE + 1] = sin_table();
+
+const fn sin_table() -> [i32; TABLE_SIZE + 1] {
+    // pi / 2 in 2.30.
+    const QUARTER_TURN: i64 = 1_686_629_713;
+    let mut table = [0; TABLE_SIZE + 1];
+    let mut i = 0;
+    while i <= TABLE_SIZE {
+        let x = QUARTER_TURN * i as i64 / TABLE_SIZE as i64;
+        let x2 = x * x / SERIES_ONE;
+        let mut term = x;
+        let mut sum = 0;
+        let mut n = 1;
+        while term != 0 {
+            sum += term;
+            term = -term * x2 / SERIES_ONE / ((2 * n) * (2 * n + 1));
+            n += 1;
+        }
+        table[i] = ((sum + (1 << 13)) >> 14) as i32;
+        i += 1;
+    }
+    table
+}
+
+/// atan(i / TABLE_SIZE) in binary angle units, with an extra entry for 1.
+static ATAN_TABLE: [u16; TABLE_SIZE + 1] = atan_table();
+
+const fn atan_table() -> [u16; TABLE_SIZE + 1] {
+    // 65536 / 2pi in 16.16.
+    const TURNS_PER_RADIAN: i64 = 683_565_276;
+    let mut table = [0; TABLE_SIZE + 1];
+    let mut i = 0;
+    while i <= TABLE_SIZE {
+        // Euler's series, which converges quickly over [0, 1]:
+        // atan(x) = sum (2n)!! / (2n + 1)!! * x^(2n + 1) / (1 + x^2)^(n + 1)
+        let x = SERIES_ONE * i as i64 / TABLE_SIZE as i64;
+        let x2 = x * x / SERIES_ONE;
+        let y = x2 * SERIES_ONE / (SERIES_ONE + x2);
+        let mut term = x * SERIES_ONE / (SERIES_ONE + x2);
+        let mut sum = 0;
+        let mut n = 1;
+        while term != 0 {
+            sum += term;
+            term = term * y / SERIES_ONE * (2 * n) / (2 * n + 1);
+            n += 1;
+        }
+        table[i] = ((sum * TURNS_PER_RADIAN + (1 << 45)) >> 46) as u16;
+        i += 1;
+    }
+    table
+}
+
+/// Looks up `table` at `index` plus `frac` parts in 2^`frac_bits`.
+fn interpolate(table: &[i32], index: usize, frac: i32, frac_bits: u32) -> i32 {
+    let a = table[index];
+    let b = table[index + 1];
+    a + (((b - a) * frac) >> frac_bits)
+}
+
+pub fn sin(angle: Angle) -> I16F16 {
+    const FRAC_BITS: u32 = 14 - TABLE_BITS;
+    let quadrant = angle.0 >> 14;
+    let mut offset = (angle.0 & 0x3fff) as i32;
+    if quadrant & 1 != 0 {
+        offset = 0x4000 - offset;
+    }
+    let index = (offset >> FRAC_BITS) as usize;
+    let value = if index == TABLE_SIZE {
+        SIN_TABLE[TABLE_SIZE]
+    } else {
+        let frac = offset & ((1 << FRAC_BITS) - 1);
+        interpolate(&SIN_TABLE, index, frac, FRAC_BITS)
+    };
+    Fixed(if quadrant & 2 != 0 { -value } else { value })
+}
+
+pub fn cos(angle: Angle) -> I16F16 {
+    sin(angle + Angle::QUARTER)
+}
+
+/// The angle of the vector from the origin to (x, y), with y pointing down
+/// the screen so that positive angles turn clockwise like `sin` and `cos`.
+pub fn atan2(y: I16F16, x: I16F16) -> Angle {
+    if x == I16F16::ZERO && y == I16F16::ZERO {
+        return Angle::ZERO;
+    }
+    let (ax, ay) = (x.0.unsigned_abs(), y.0.unsigned_abs());
+    // atan of the smaller over the larger stays in the table's range.
+    let (num, den) = if ay <= ax { (ay, ax) } else { (ax, ay) };
+    let ratio = ((num as u64) << 16) / den as u64;
+    const FRAC_BITS: u32 = 16 - TABLE_BITS;
+    let index = (ratio >> FRAC_BITS) as usize;
+    let frac = (ratio & ((1 << FRAC_BITS) - 1)) as i32;
+    let mut angle = if index == TABLE_SIZE {
+        ATAN_TABLE[TABLE_SIZE] as i32
+    } else {
+        let a = ATAN_TABLE[index] as i32;
+        let b = ATAN_TABLE[index + 1] as i32;
+        a + (((b - a) * frac) >> FRAC_BITS)
+    };
+    if ay > ax {
+        angle = 0x4000 - angle;
+    }
+    if x.0 < 0 {
+        angle = 0x8000 - angle;
+    }
+    if y.0 < 0 {
+        angle = -angle;
+    }
+    Angle(angle as u16)
+}
+
+/// A point or vector in 16.16 fixed point, for sub-pixel positions and
+/// velocities.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Vector2 {
+    pub x: I16F16,
+    pub y: I16F16,
+}
+
+impl Vector2 {
+    pub const ZERO: Vector2 = Vector2::new(I16F16::ZERO, I16F16::ZERO);
+
+    pub const fn new(x: I16F16, y: I16F16) -> Self {
+        Vector2 { x, y }
+    }
+
+    /// A vector of `length` pointing at `angle`.
+    pub fn from_angle(angle: Angle, length: I16F16) -> Self {
+        Vector2::new(cos(angle) * length, sin(angle) * length)
+    }
+
+    /// The nearest pixel.
+    pub fn to_point(self) -> Point {
+        Point::new(self.x.round(), self.y.round())
+    }
+
+    /// The pixel containing the point.
+    pub fn floor(self) -> Point {
+        Point::new(self.x.floor(), self.y.floor())
+    }
+
+    pub fn dot(self, other: Vector2) -> I16F16 {
+        self.x * other.x + self.y * other.y
+    }
+
+    pub fn length_squared(self) -> I16F16 {
+        self.dot(self)
+    }
+
+    pub fn length(self) -> I16F16 {
+        // Squaring in 16.16 would overflow past 181 pixels.
+        let squared = self.x.0 as i64 * self.x.0 as i64 + self.y.0 as i64 * self.y.0 as i64;
+        Fixed(isqrt(squared as u64) as i32)
+    }
+
+    /// Scales the vector to a length of one, or leaves it if it is zero.
+    pub fn normalize(self) -> Self {
+        let length = self.length();
+        if length == I16F16::ZERO {
+            return self;
+        }
+        Vector2::new(self.x / length, self.y / length)
+    }
+
+    pub fn angle(self) -> Angle {
+        atan2(self.y, self.x)
+    }
+
+    /// Rotates the vector clockwise on the screen.
+    pub fn rotate(self, angle: Angle) -> Self {
+        let (s, c) = (sin(angle), cos(angle));
+        Vector2::new(self.x * c - self.y * s, self.x * s + self.y * c)
+    }
+}
+
+impl From<Point> for Vector2 {
+    fn from(p: Point) -> Self {
+        Vector2::new(Fixed::from_int(p.x), Fixed::from_int(p.y))
+    }
+}
+
+impl From<Vector2> for Point {
+    fn from(v: Vector2) -> Self {
+        v.to_point()
+    }
+}
+
+impl Add for Vector2 {
+    type Output = Vector2;
+
+    fn add(self, other: Vector2) -> Vector2 {
+        Vector2::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Vector2 {
+    type Output = Vector2;
+
+    fn sub(self, other: Vector2) -> Vector2 {
+        Vector2::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Neg for Vector2 {
+    type Output = Vector2;
+
+    fn neg(self) -> Vector2 {
+        Vector2::new(-self.x, -self.y)
+    }
+}
+
+impl Mul<I16F16> for Vector2 {
+    type Output = Vector2;
+
+    fn mul(self, scale: I16F16) -> Vector2 {
+        Vector2::new(self.x * scale, self.y * scale)
+    }
+}
+
+impl Div<I16F16> for Vector2 {
+    type Output = Vector2;
+
+    fn div(self, scale: I16F16) -> Vector2 {
+        Vector2::new(self.x / scale, self.y / scale)
+    }
+}
+
+impl AddAssign for Vector2 {
+    fn add_assign(&mut self, other: Vector2) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Vector2 {
+    fn sub_assign(&mut self, other: Vector2) {
+        *self = *self - other;
+    }
+}
+
+/// A point or direction in 3D in 16.16 fixed point.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Vector3 {
+    pub x: I16F16,
+    pub y: I16F16,
+    pub z: I16F16,
+}
+
+impl Vector3 {
+    pub const ZERO: Vector3 = Vector3::new(I16F16::ZERO, I16F16::ZERO, I16F16::ZERO);
+
+    pub const fn new(x: I16F16, y: I16F16, z: I16F16) -> Self {
+        Vector3 { x, y, z }
+    }
+
+    pub const fn from_ints(x: i32, y: i32, z: i32) -> Self {
+        Vector3::new(Fixed::from_int(x), Fixed::from_int(y), Fixed::from_int(z))
+    }
+
+    pub fn dot(self, other: Vector3) -> I16F16 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vector3) -> Vector3 {
+        Vector3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn length(self) -> I16F16 {
+        let squared = [self.x, self.y, self.z]
+            .iter()
+            .map(|c| c.0 as i64 * c.0 as i64)
+            .sum::<i64>();
+        Fixed(isqrt(squared as u64) as i32)
+    }
+
+    /// Scales the vector to a length of one, or leaves it if it is zero.
+    pub fn normalize(self) -> Self {
+        let length = self.length();
+        if length == I16F16::ZERO {
+            return self;
+        }
+        Vector3::new(self.x / length, self.y / length, self.z / length)
+    }
+}
+
+impl Add for Vector3 {
+    type Output = Vector3;
+
+    fn add(self, other: Vector3) -> Vector3 {
+        Vector3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for Vector3 {
+    type Output = Vector3;
+
+    fn sub(self, other: Vector3) -> Vector3 {
+        Vector3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Neg for Vector3 {
+    type Output = Vector3;
+
+    fn neg(self) -> Vector3 {
+        Vector3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Mul<I16F16> for Vector3 {
+    type Output = Vector3;
+
+    fn mul(self, scale: I16F16) -> Vector3 {
+        Vector3::new(self.x * scale, self.y * scale, self.z * scale)
+    }
+}
+
+impl AddAssign for Vector3 {
+    fn add_assign(&mut self, other: Vector3) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Vector3 {
+    fn sub_assign(&mut self, other: Vector3) {
+        *self = *self - other;
+    }
+}
+
+/// A 3x3 matrix in 16.16 fixed point for rotating and scaling `Vector3`s,
+/// stored as rows. Multiplying matrices applies the right one first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Matrix3 {
+    pub rows: [Vector3; 3],
+}
+
+impl Matrix3 {
+    pub const IDENTITY: Matrix3 = Matrix3::scale(I16F16::ONE);
+
+    pub const fn scale(scale: I16F16) -> Self {
+        let zero = I16F16::ZERO;
+        Matrix3 {
+            rows: [
+                Vector3::new(scale, zero, zero),
+                Vector3::new(zero, scale, zero),
+                Vector3::new(zero, zero, scale),
+            ],
+        }
+    }
+
+    /// Turns y towards z by `angle`.
+    pub fn rotation_x(angle: Angle) -> Self {
+        let (s, c) = (sin(angle), cos(angle));
+        let (zero, one) = (I16F16::ZERO, I16F16::ONE);
+        Matrix3 {
+            rows: [
+                Vector3::new(one, zero, zero),
+                Vector3::new(zero, c, -s),
+                Vector3::new(zero, s, c),
+            ],
+        }
+    }
+
+    /// Turns z towards x by `angle`.
+    pub fn rotation_y(angle: Angle) -> Self {
+        let (s, c) = (sin(angle), cos(angle));
+        let (zero, one) = (I16F16::ZERO, I16F16::ONE);
+        Matrix3 {
+            rows: [
+                Vector3::new(c, zero, s),
+                Vector3::new(zero, one, zero),
+                Vector3::new(-s, zero, c),
+            ],
+        }
+    }
+
+    /// Turns x towards y by `angle`, clockwise on the screen like
+    /// `Vector2::rotate`.
+    pub fn rotation_z(angle: Angle) -> Self {
+        let (s, c) = (sin(angle), cos(angle));
+        let (zero, one) = (I16F16::ZERO, I16F16::ONE);
+        Matrix3 {
+            rows: [
+                Vector3::new(c, -s, zero),
+                Vector3::new(s, c, zero),
+                Vector3::new(zero, zero, one),
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let [a, b, c] = self.rows;
+        Matrix3 {
+            rows: [
+                Vector3::new(a.x, b.x, c.x),
+                Vector3::new(a.y, b.y, c.y),
+                Vector3::new(a.z, b.z, c.z),
+            ],
+        }
+    }
+}
+
+impl Mul<Vector3> for Matrix3 {
+    type Output = Vector3;
+
+    fn mul(self, v: Vector3) -> Vector3 {
+        let [a, b, c] = self.rows;
+        Vector3::new(a.dot(v), b.dot(v), c.dot(v))
+    }
+}
+
+impl Mul for Matrix3 {
+    type Output = Matrix3;
+
+    fn mul(self, other: Matrix3) -> Matrix3 {
+        let columns = other.transpose().rows;
+        Matrix3 {
+            rows: self.rows.map(|row| {
+                Vector3::new(
+                    row.dot(columns[0]),
+                    row.dot(columns[1]),
+                    row.dot(columns[2]),
+                )
+            }),
+        }
+    }
+}
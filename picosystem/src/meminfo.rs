@@ -0,0 +1,139 @@
+//! How much of the RAM and flash the program uses.
+//!
+//! Statics, including the framebuffer, take the start of RAM, and the stack
+//! grows down from its end. `Hardware::new` paints the free RAM between
+//! them with a pattern, and `stack_high_water` finds how much of it the
+//! stack has overwritten since, so the deepest the stack has been shows
+//! rather than how deep it happens to be now.
+//!
+//! Fixed-capacity pools and arenas live in statics, so their size counts in
+//! `static_ram` whether they are full or empty. To see how close they come
+//! to filling up, their owners report how many of their slots are used with
+//! `report_pool`, which keeps the peak. The debug overlay shows all of it.
+
+use crate::logging;
+use core::cell::RefCell;
+use critical_section::Mutex;
+
+/// RAM the program can use, without the two 4K scratch banks.
+pub const RAM_SIZE: usize = 256 * 1024;
+/// The most pools `report_pool` keeps track of.
+pub const MAX_POOLS: usize = 8;
+
+const RAM_START: usize = 0x2000_0000;
+const FLASH_START: usize = 0x1000_0000;
+// Written over the free RAM below the stack at startup. The first word
+// that is different shows how deep the stack has been.
+const STACK_PAINT: u32 = 0x5a5a_a5a5;
+// Left alone below the stack pointer while painting.
+const STACK_MARGIN: usize = 256;
+
+extern "C" {
+    static __sheap: u32;
+    static _stack_start: u32;
+    static __sdata: u32;
+    static __edata: u32;
+    static __sidata: u32;
+}
+
+/// Usage of a pool, in whatever units its owner counts, such as objects
+/// or bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub name: &'static str,
+    pub used: usize,
+    /// The most used at once since startup.
+    pub peak: usize,
+    pub capacity: usize,
+}
+
+static POOLS: Mutex<RefCell<heapless::Vec<PoolStats, MAX_POOLS>>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+/// Fills the free RAM below the stack with a pattern for
+/// `stack_high_water` to find. Called once at startup.
+#[inline(never)]
+pub(crate) fn paint_stack() {
+    let start = core::ptr::addr_of!(__sheap) as usize;
+    let end = cortex_m::register::msp::read() as usize - STACK_MARGIN;
+    for address in (start..end).step_by(4) {
+        unsafe { core::ptr::write_volatile(address as *mut u32, STACK_PAINT) };
+    }
+}
+
+/// The most stack used since startup, in bytes.
+pub fn stack_high_water() -> usize {
+    let start = core::ptr::addr_of!(__sheap) as usize;
+    let end = core::ptr::addr_of!(_stack_start) as usize;
+    let untouched = (start..end)
+        .step_by(4)
+        .take_while(|&address| unsafe {
+            core::ptr::read_volatile(address as *const u32) == STACK_PAINT
+        })
+        .count();
+    end - start - untouched * 4
+}
+
+/// RAM taken by statics, including the framebuffer.
+pub fn static_ram() -> usize {
+    core::ptr::addr_of!(__sheap) as usize - RAM_START
+}
+
+/// RAM neither statics nor the stack have used so far.
+pub fn free_ram() -> usize {
+    RAM_SIZE.saturating_sub(static_ram() + stack_high_water())
+}
+
+/// Flash taken by the program and the initial values of statics.
+pub fn program_flash() -> usize {
+    let data_len = core::ptr::addr_of!(__edata) as usize - core::ptr::addr_of!(__sdata) as usize;
+    core::ptr::addr_of!(__sidata) as usize + data_len - FLASH_START
+}
+
+/// Records that `used` of the `capacity` slots of the pool called `name`
+/// are in use. Pools past the first `MAX_POOLS` are ignored.
+pub fn report_pool(name: &'static str, used: usize, capacity: usize) {
+    critical_section::with(|cs| {
+        let mut pools = POOLS.borrow_ref_mut(cs);
+        match pools.iter_mut().find(|pool| pool.name == name) {
+            Some(pool) => {
+                pool.used = used;
+                pool.peak = pool.peak.max(used);
+                pool.capacity = capacity;
+            }
+            None => {
+                let _ = pools.push(PoolStats {
+                    name,
+                    used,
+                    peak: used,
+                    capacity,
+                });
+            }
+        }
+    });
+}
+
+/// The pools reported so far, in the order they were first reported.
+pub fn pools() -> heapless::Vec<PoolStats, MAX_POOLS> {
+    critical_section::with(|cs| POOLS.borrow_ref(cs).clone())
+}
+
+/// Logs the memory used so far.
+pub fn log_usage() {
+    logging::info!(
+        "RAM: {} statics, {} stack, {} free; flash: {}",
+        static_ram(),
+        stack_high_water(),
+        free_ram(),
+        program_flash()
+    );
+    for pool in pools() {
+        logging::info!(
+            "Pool {}: {}/{}, peak {}",
+            pool.name,
+            pool.used,
+            pool.capacity,
+            pool.peak
+        );
+    }
+}
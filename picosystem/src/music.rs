@@ -0,0 +1,136 @@
+pub use crate::audio::Instrument;
+use crate::audio::{Channel, NUM_CHANNELS, SAMPLE_RATE};
+
+pub const NOTE_NONE: u8 = 0;
+pub const NOTE_OFF: u8 = 255;
+
+pub const EFFECT_NONE: u8 = 0;
+/// Sets the tempo to `param` beats per minute.
+pub const EFFECT_TEMPO: u8 = 1;
+/// Sets the channel volume to `param`.
+pub const EFFECT_VOLUME: u8 = 2;
+/// Continues playback at order position `param` after this row.
+pub const EFFECT_JUMP: u8 = 3;
+
+const ROWS_PER_BEAT: u32 = 4;
+
+/// Millihertz of MIDI notes 0 to 11 (C-1 to B-1). Higher octaves double these.
+const NOTE_FREQS_MHZ: [u32; 12] = [
+    8176, 8662, 9177, 9723, 10301, 10913, 11562, 12250, 12978, 13750, 14568, 15434,
+];
+
+/// A song compiled into flash, usually generated by the `music!` macro.
+pub struct Song {
+    pub tempo: u8,
+    pub channels: usize,
+    pub instruments: &'static [Instrument],
+    pub patterns: &'static [Pattern],
+    /// Indices into `patterns`, in playback order.
+    pub order: &'static [u8],
+}
+
+/// Rows of `Song::channels` cells each.
+pub struct Pattern {
+    pub cells: &'static [Cell],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    /// MIDI note number, `NOTE_NONE` or `NOTE_OFF`.
+    pub note: u8,
+    pub instrument: u8,
+    pub effect: u8,
+    pub param: u8,
+}
+
+pub fn note_freq(note: u8) -> u32 {
+    (NOTE_FREQS_MHZ[note as usize % 12] << (note / 12)) / 1000
+}
+
+pub(crate) struct MusicPlayer {
+    song: &'static Song,
+    looping: bool,
+    order_index: usize,
+    row: usize,
+    samples_per_row: u32,
+    samples_until_row: u32,
+}
+
+impl MusicPlayer {
+    pub(crate) fn new(song: &'static Song, looping: bool) -> Self {
+        MusicPlayer {
+            song,
+            looping,
+            order_index: 0,
+            row: 0,
+            samples_per_row: samples_per_row(song.tempo),
+            samples_until_row: 0,
+        }
+    }
+
+    pub(crate) fn channels(&self) -> usize {
+        self.song.channels
+    }
+
+    pub(crate) fn set_tempo(&mut self, tempo: u8) {
+        self.samples_per_row = samples_per_row(tempo);
+    }
+
+    /// Advances playback by one sample. Returns false once the song has ended.
+    pub(crate) fn tick(&mut self, channels: &mut [Channel; NUM_CHANNELS]) -> bool {
+        if self.samples_until_row == 0 {
+            if !self.play_row(channels) {
+                return false;
+            }
+            self.samples_until_row = self.samples_per_row;
+        }
+        self.samples_until_row -= 1;
+        true
+    }
+
+    fn play_row(&mut self, channels: &mut [Channel; NUM_CHANNELS]) -> bool {
+        if self.order_index >= self.song.order.len() {
+            if !self.looping || self.song.order.is_empty() {
+                return false;
+            }
+            self.order_index = 0;
+        }
+        let song = self.song;
+        let pattern = &song.patterns[song.order[self.order_index] as usize];
+        let row_start = self.row * song.channels;
+        let mut jump = None;
+        for (channel, cell) in channels
+            .iter_mut()
+            .zip(&pattern.cells[row_start..row_start + song.channels])
+        {
+            match cell.note {
+                NOTE_NONE => {}
+                NOTE_OFF => channel.release(),
+                note => {
+                    let instrument = &song.instruments[cell.instrument as usize];
+                    channel.start_instrument(instrument, note_freq(note));
+                }
+            }
+            match cell.effect {
+                EFFECT_TEMPO => self.set_tempo(cell.param),
+                EFFECT_VOLUME => channel.set_volume(cell.param),
+                EFFECT_JUMP => jump = Some(cell.param as usize),
+                _ => {}
+            }
+        }
+
+        self.row += 1;
+        if let Some(order_index) = jump {
+            self.order_index = order_index;
+            self.row = 0;
+        } else if self.row * song.channels >= pattern.cells.len() {
+            self.order_index += 1;
+            self.row = 0;
+        }
+        true
+    }
+}
+
+fn samples_per_row(tempo: u8) -> u32 {
+    SAMPLE_RATE * 60 / (tempo.max(1) as u32 * ROWS_PER_BEAT)
+}
@@ -1,3 +1,5 @@
+use crate::logging;
+use crate::watchdog::{self, CrashReason};
 use core::panic::PanicInfo;
 use cortex_m_rt::{exception, ExceptionFrame};
 use rp_pico::hal::pac;
@@ -7,8 +9,12 @@ fn panic(info: &PanicInfo) -> ! {
     unsafe {
         turn_on_leds();
     }
+    // The watchdog, if it was started, reboots into the crash screen.
+    let line = info.location().map_or(0, |location| location.line());
+    watchdog::stash_crash_reason(CrashReason::Panic, line);
     //cortex_m::interrupt::disable();
-    log::error!("{}", info);
+    logging::error!("{}", logging::Display2Format(info));
+    crate::usb_logger::drain();
     unsafe {
         pac::NVIC::unmask(pac::Interrupt::USBCTRL_IRQ);
     }
@@ -17,8 +23,9 @@ fn panic(info: &PanicInfo) -> ! {
 
 // Turn on LEDs during a hard fault.
 #[exception]
-unsafe fn HardFault(_: &ExceptionFrame) -> ! {
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
     turn_on_leds();
+    watchdog::stash_crash_reason(CrashReason::HardFault, frame.pc());
     #[allow(clippy::empty_loop)]
     loop {}
 }
@@ -0,0 +1,311 @@
+//! A fixed-capacity particle pool with emitter presets.
+//!
+//! Positions and velocities are fixed point with `FRACTION_BITS` fractional
+//! bits, and advance once per `update`. Particles are drawn straight into the
+//! framebuffer as single pixels or small squares, whose rows are filled by DMA
+//! once they are wide enough.
+
+use crate::display::{self, framebuffer, Display, WIDTH};
+use crate::dma::{self, DmaChannel};
+use crate::rom_math;
+use crate::time;
+use embedded_graphics::pixelcolor::{raw::RawU16, Rgb565};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+pub const FRACTION_BITS: u32 = 8;
+pub const ONE: i32 = 1 << FRACTION_BITS;
+
+// Narrower rows are quicker to fill by the CPU than to set up DMA for.
+const MIN_DMA_FILL: u32 = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Particle {
+    pub x: i32,
+    pub y: i32,
+    pub vx: i32,
+    pub vy: i32,
+    /// Added to `vy` every update. Negative values make particles rise.
+    pub gravity: i32,
+    /// Updates the particle has lived for.
+    pub age: u16,
+    pub lifetime: u16,
+    pub size: u8,
+    /// Shrinks the particle from `size` to one pixel over its lifetime.
+    pub shrink: bool,
+    /// Colors from birth to death, spread evenly over the lifetime.
+    pub ramp: &'static [Rgb565],
+}
+
+impl Particle {
+    pub fn position(&self) -> Point {
+        Point::new(self.x >> FRACTION_BITS, self.y >> FRACTION_BITS)
+    }
+
+    pub fn color(&self) -> Rgb565 {
+        let index = self.age as usize * self.ramp.len() / self.lifetime.max(1) as usize;
+        self.ramp[index.min(self.ramp.len() - 1)]
+    }
+
+    fn current_size(&self) -> u32 {
+        if self.shrink {
+            let left = (self.lifetime - self.age) as u32;
+            (self.size as u32 * left)
+                .div_ceil(self.lifetime.max(1) as u32)
+                .max(1)
+        } else {
+            self.size as u32
+        }
+    }
+
+    fn area(&self) -> Rectangle {
+        let size = self.current_size();
+        Rectangle::new(
+            self.position() - Point::new(size as i32 / 2, size as i32 / 2),
+            Size::new(size, size),
+        )
+    }
+}
+
+/// How an emitter launches a burst of particles.
+#[derive(Debug, Clone, Copy)]
+pub struct Emitter {
+    pub count: u32,
+    /// Direction of travel in radians, with 0 to the right and positive
+    /// angles turning down the screen.
+    pub angle: f32,
+    /// Particles leave up to half of `spread` to either side of `angle`.
+    pub spread: f32,
+    /// Speed range in fixed point pixels per update.
+    pub min_speed: i32,
+    pub max_speed: i32,
+    pub min_lifetime: u16,
+    pub max_lifetime: u16,
+    pub size: u8,
+    pub shrink: bool,
+    pub gravity: i32,
+    pub ramp: &'static [Rgb565],
+}
+
+static EXPLOSION_RAMP: [Rgb565; 5] = [
+    Rgb565::WHITE,
+    Rgb565::YELLOW,
+    Rgb565::CSS_ORANGE,
+    Rgb565::RED,
+    Rgb565::CSS_DARK_RED,
+];
+
+static SMOKE_RAMP: [Rgb565; 4] = [
+    Rgb565::CSS_LIGHT_GRAY,
+    Rgb565::CSS_DARK_GRAY,
+    Rgb565::CSS_GRAY,
+    Rgb565::CSS_DIM_GRAY,
+];
+
+static SPARKLE_RAMP: [Rgb565; 4] = [
+    Rgb565::WHITE,
+    Rgb565::CSS_LIGHT_CYAN,
+    Rgb565::CYAN,
+    Rgb565::CSS_DEEP_SKY_BLUE,
+];
+
+impl Emitter {
+    /// A fast burst in every direction, fading from white hot to red.
+    pub const fn explosion() -> Self {
+        Emitter {
+            count: 24,
+            angle: 0.0,
+            spread: core::f32::consts::TAU,
+            min_speed: ONE / 2,
+            max_speed: 3 * ONE,
+            min_lifetime: 10,
+            max_lifetime: 30,
+            size: 4,
+            shrink: true,
+            gravity: ONE / 16,
+            ramp: &EXPLOSION_RAMP,
+        }
+    }
+
+    /// Slow grey puffs drifting upwards.
+    pub const fn smoke() -> Self {
+        Emitter {
+            count: 8,
+            angle: -core::f32::consts::FRAC_PI_2,
+            spread: core::f32::consts::FRAC_PI_2,
+            min_speed: ONE / 8,
+            max_speed: ONE / 2,
+            min_lifetime: 30,
+            max_lifetime: 60,
+            size: 3,
+            shrink: false,
+            gravity: -ONE / 64,
+            ramp: &SMOKE_RAMP,
+        }
+    }
+
+    /// A few short-lived single pixel glints.
+    pub const fn sparkle() -> Self {
+        Emitter {
+            count: 6,
+            angle: 0.0,
+            spread: core::f32::consts::TAU,
+            min_speed: ONE / 4,
+            max_speed: ONE,
+            min_lifetime: 8,
+            max_lifetime: 20,
+            size: 1,
+            shrink: false,
+            gravity: 0,
+            ramp: &SPARKLE_RAMP,
+        }
+    }
+}
+
+/// A pool of up to `N` particles. Particles spawned while it is full are
+/// dropped.
+pub struct ParticleSystem<const N: usize> {
+    particles: heapless::Vec<Particle, N>,
+    rng: oorandom::Rand32,
+}
+
+impl<const N: usize> ParticleSystem<N> {
+    pub fn new() -> Self {
+        ParticleSystem {
+            particles: heapless::Vec::new(),
+            rng: oorandom::Rand32::new(time::time_us() as u64),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Particle> {
+        self.particles.iter()
+    }
+
+    /// Adds a particle, returning false if the pool is full.
+    pub fn spawn(&mut self, particle: Particle) -> bool {
+        self.particles.push(particle).is_ok()
+    }
+
+    /// Launches a burst of particles from `position`.
+    pub fn emit(&mut self, emitter: &Emitter, position: Point) {
+        for _ in 0..emitter.count {
+            let angle = emitter.angle + (self.rng.rand_float() - 0.5) * emitter.spread;
+            let speed = self.rand_range(emitter.min_speed, emitter.max_speed) as f32;
+            let (sin, cos) = rom_math::sin_cos(angle);
+            let lifetime =
+                self.rand_range(emitter.min_lifetime as i32, emitter.max_lifetime as i32) as u16;
+            let spawned = self.spawn(Particle {
+                x: position.x << FRACTION_BITS,
+                y: position.y << FRACTION_BITS,
+                vx: (cos * speed) as i32,
+                vy: (sin * speed) as i32,
+                gravity: emitter.gravity,
+                age: 0,
+                lifetime,
+                size: emitter.size,
+                shrink: emitter.shrink,
+                ramp: emitter.ramp,
+            });
+            if !spawned {
+                break;
+            }
+        }
+    }
+
+    fn rand_range(&mut self, min: i32, max: i32) -> i32 {
+        if max <= min {
+            return min;
+        }
+        self.rng.rand_range(0..(max - min + 1) as u32) as i32 + min
+    }
+
+    /// Moves every particle one step, removing those that have died or left
+    /// the screen.
+    pub fn update(&mut self) {
+        let mut i = 0;
+        while i < self.particles.len() {
+            let p = &mut self.particles[i];
+            p.vy += p.gravity;
+            p.x += p.vx;
+            p.y += p.vy;
+            p.age += 1;
+            let position = p.position();
+            let margin = p.size as i32;
+            let on_screen = (-margin..WIDTH as i32 + margin).contains(&position.x)
+                && (-margin..display::screen_height() as i32 + margin).contains(&position.y);
+            if p.age >= p.lifetime || !on_screen {
+                self.particles.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Draws the particles into the framebuffer, marking the area they cover
+    /// dirty.
+    pub fn draw(&self, display: &mut Display) {
+        if self.particles.is_empty() {
+            return;
+        }
+        let screen = display::screen();
+        let dma_channel = display.draw_dma_channel();
+        let fb = framebuffer();
+        let mut top_left = Point::new(WIDTH as i32, screen.size.height as i32);
+        let mut bottom_right = Point::zero();
+        for particle in self.particles.iter() {
+            let area = particle.area().intersection(&screen);
+            let Some(corner) = area.bottom_right() else {
+                continue;
+            };
+            top_left = top_left.component_min(area.top_left);
+            bottom_right = bottom_right.component_max(corner);
+            let color = RawU16::from(particle.color()).into_inner().to_be();
+            fill(dma_channel, fb, &area, color);
+        }
+        if top_left.x <= bottom_right.x {
+            display.mark_dirty(Rectangle::with_corners(top_left, bottom_right));
+        }
+    }
+}
+
+impl<const N: usize> Default for ParticleSystem<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn fill(dma_channel: &mut DmaChannel, fb: &mut [u16], area: &Rectangle, color: u16) {
+    let width = area.size.width;
+    for y in area.rows() {
+        let start = y as usize * WIDTH + area.top_left.x as usize;
+        if width >= MIN_DMA_FILL {
+            unsafe {
+                dma::set_mem(
+                    dma_channel,
+                    &color as *const u16 as u32,
+                    fb.as_mut_ptr().add(start) as u32,
+                    2,
+                    width,
+                );
+            }
+        } else {
+            fb[start..start + width as usize].fill(color);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Finding the way around the map, for enemies and other characters.
+//!
+//! Both searches work on a grid of cells through the `Walkable` trait,
+//! which `Map` implements with a cell per tile, blocked where the tile has
+//! a collision shape.
+//!
+//! A `PathFinder` runs A* from one cell to another. `step` looks at no more
+//! than a budget of cells per call, so a long search can be spread over
+//! several frames while the game keeps running.
+//!
+//! A `FlowField` holds the distance from every cell to a goal, so any
+//! number of enemies chasing the player can look up their next step in it.
+//! It is built a budget of cells at a time too.
+//!
+//! The searches keep all their state in themselves, sized by `CELLS`, the
+//! most cells a grid may have, so they can live in statics. The grid must
+//! not change while a search is spread over several frames.
+
+use crate::map::Map;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use heapless::binary_heap::{BinaryHeap, Min};
+
+pub trait Walkable {
+    /// Width and height in cells.
+    fn size(&self) -> Size;
+    fn is_walkable(&self, cell: Point) -> bool;
+}
+
+impl Walkable for Map {
+    fn size(&self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+
+    fn is_walkable(&self, cell: Point) -> bool {
+        let area = Rectangle::new(
+            cell * self.tile_size,
+            Size::new_equal(self.tile_size as u32),
+        );
+        !self.is_blocked(&area)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Moves {
+    /// Up, down, left and right.
+    Four,
+    /// Diagonally as well, but not past the corners of blocked cells.
+    Eight,
+}
+
+impl Moves {
+    fn directions(self) -> &'static [Point] {
+        match self {
+            Moves::Four => &DIRECTIONS[..4],
+            Moves::Eight => &DIRECTIONS,
+        }
+    }
+}
+
+// The straight moves come first, so they win ties.
+const DIRECTIONS: [Point; 8] = [
+    Point::new(1, 0),
+    Point::new(0, 1),
+    Point::new(-1, 0),
+    Point::new(0, -1),
+    Point::new(1, 1),
+    Point::new(-1, 1),
+    Point::new(-1, -1),
+    Point::new(1, -1),
+];
+
+// Costs of a move, with diagonals about the square root of two longer.
+const STRAIGHT: u16 = 10;
+const DIAGONAL: u16 = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Search {
+    /// Still looking, call `step` again.
+    Searching,
+    Found,
+    /// The goal can't be reached, or the start or goal is off the grid.
+    NoPath,
+}
+
+// Set in `PathFinder::from` once a cell's cost is final.
+const CLOSED: u8 = 0x80;
+
+/// A* over grids of up to `CELLS` cells, at most 65536, with room for
+/// `OPEN` cells waiting to be looked at. Cells that don't fit in the open
+/// set are dropped, which can make a search fail or find a longer path.
+pub struct PathFinder<const CELLS: usize, const OPEN: usize> {
+    pub moves: Moves,
+    // Cells reached by the current search have its number here, so the
+    // other arrays don't need clearing between searches.
+    visited: [u16; CELLS],
+    // Cost from the start.
+    cost: [u16; CELLS],
+    // Index into `DIRECTIONS` of the move that reached the cell.
+    from: [u8; CELLS],
+    // Estimated total cost in the high half, cell index in the low half.
+    open: BinaryHeap<u32, Min, OPEN>,
+    search: u16,
+    width: usize,
+    start: usize,
+    goal: usize,
+    state: Search,
+}
+
+impl<const CELLS: usize, const OPEN: usize> PathFinder<CELLS, OPEN> {
+    pub const fn new(moves: Moves) -> Self {
+        assert!(CELLS <= 1 << 16);
+        PathFinder {
+            moves,
+            visited: [0; CELLS],
+            cost: [0; CELLS],
+            from: [0; CELLS],
+            open: BinaryHeap::new(),
+            search: 0,
+            width: 0,
+            start: 0,
+            goal: 0,
+            state: Search::NoPath,
+        }
+    }
+
+    /// Starts looking for a path from cell `from` to cell `to`, dropping
+    /// any search in progress.
+    pub fn start(&mut self, grid: &impl Walkable, from: Point, to: Point) {
+        self.open.clear();
+        self.search = self.search.wrapping_add(1);
+        if self.search == 0 {
+            self.visited = [0; CELLS];
+            self.search = 1;
+        }
+        let size = grid.size();
+        self.width = size.width as usize;
+        let fits = size.width as usize * size.height as usize <= CELLS;
+        let (Some(start), Some(goal), true) = (index(size, from), index(size, to), fits) else {
+            self.state = Search::NoPath;
+            return;
+        };
+        self.start = start;
+        self.goal = goal;
+        self.visited[start] = self.search;
+        self.cost[start] = 0;
+        self.from[start] = 0;
+        let _ = self.open.push(key(self.heuristic(from, to), start));
+        self.state = Search::Searching;
+    }
+
+    /// Continues the search, looking at up to `budget` cells. Returns
+    /// `Search::Searching` if it isn't done yet.
+    pub fn step(&mut self, grid: &impl Walkable, budget: usize) -> Search {
+        let size = grid.size();
+        let goal = self.cell(self.goal);
+        for _ in 0..budget {
+            if self.state != Search::Searching {
+                break;
+            }
+            let Some(entry) = self.open.pop() else {
+                self.state = Search::NoPath;
+                break;
+            };
+            let current = (entry & 0xffff) as usize;
+            if self.from[current] & CLOSED != 0 {
+                // Reached again more cheaply after this entry was added.
+                continue;
+            }
+            self.from[current] |= CLOSED;
+            if current == self.goal {
+                self.state = Search::Found;
+                break;
+            }
+            let cell = self.cell(current);
+            for (direction, &offset) in self.moves.directions().iter().enumerate() {
+                let next = cell + offset;
+                if !passable(grid, size, cell, offset) {
+                    continue;
+                }
+                let Some(next_index) = index(size, next) else {
+                    continue;
+                };
+                let step = if direction < 4 { STRAIGHT } else { DIAGONAL };
+                let cost = self.cost[current].saturating_add(step);
+                if self.visited[next_index] == self.search
+                    && (self.from[next_index] & CLOSED != 0 || self.cost[next_index] <= cost)
+                {
+                    continue;
+                }
+                self.visited[next_index] = self.search;
+                self.cost[next_index] = cost;
+                self.from[next_index] = direction as u8;
+                let estimate = cost.saturating_add(self.heuristic(next, goal));
+                let _ = self.open.push(key(estimate, next_index));
+            }
+        }
+        self.state
+    }
+
+    /// Runs a whole search at once.
+    pub fn find(&mut self, grid: &impl Walkable, from: Point, to: Point) -> Search {
+        self.start(grid, from, to);
+        self.step(grid, usize::MAX)
+    }
+
+    pub fn state(&self) -> Search {
+        self.state
+    }
+
+    /// The cells of the path found, from the goal back to the cell after
+    /// the start. Empty until a path is found.
+    pub fn path(&self) -> impl Iterator<Item = Point> + '_ {
+        let mut current = (self.state == Search::Found).then_some(self.goal);
+        core::iter::from_fn(move || {
+            let index = current.filter(|&index| index != self.start)?;
+            let cell = self.cell(index);
+            let previous = cell - DIRECTIONS[(self.from[index] & !CLOSED) as usize];
+            current = Some(previous.y as usize * self.width + previous.x as usize);
+            Some(cell)
+        })
+    }
+
+    /// The cell to move to from the start to follow the path found.
+    pub fn first_step(&self) -> Option<Point> {
+        self.path().last()
+    }
+
+    fn cell(&self, index: usize) -> Point {
+        Point::new((index % self.width) as i32, (index / self.width) as i32)
+    }
+
+    // A lower bound of the cost from `from` to `to`.
+    fn heuristic(&self, from: Point, to: Point) -> u16 {
+        let dx = from.x.abs_diff(to.x);
+        let dy = from.y.abs_diff(to.y);
+        let cost = match self.moves {
+            Moves::Four => STRAIGHT as u32 * (dx + dy),
+            Moves::Eight => {
+                STRAIGHT as u32 * dx.max(dy) + (DIAGONAL - STRAIGHT) as u32 * dx.min(dy)
+            }
+        };
+        cost.min(u16::MAX as u32) as u16
+    }
+}
+
+// Distance of a cell not reached by a `FlowField`.
+const UNREACHED: u16 = u16::MAX;
+
+/// Distances to a goal over grids of up to `CELLS` cells, at most 65536.
+pub struct FlowField<const CELLS: usize> {
+    pub moves: Moves,
+    // Moves from each cell to the goal, going up, down, left and right.
+    distance: [u16; CELLS],
+    // Cells reached whose neighbours are still to be looked at.
+    queue: heapless::Deque<u16, CELLS>,
+    size: Size,
+}
+
+impl<const CELLS: usize> FlowField<CELLS> {
+    pub const fn new(moves: Moves) -> Self {
+        assert!(CELLS <= 1 << 16);
+        FlowField {
+            moves,
+            distance: [UNREACHED; CELLS],
+            queue: heapless::Deque::new(),
+            size: Size::zero(),
+        }
+    }
+
+    /// Starts filling in the distances to `goal`, forgetting the old ones.
+    pub fn start(&mut self, grid: &impl Walkable, goal: Point) {
+        self.distance = [UNREACHED; CELLS];
+        self.queue.clear();
+        let size = grid.size();
+        self.size = size;
+        if size.width as usize * size.height as usize > CELLS {
+            self.size = Size::zero();
+            return;
+        }
+        if let Some(goal) = index(size, goal) {
+            self.distance[goal] = 0;
+            let _ = self.queue.push_back(goal as u16);
+        }
+    }
+
+    /// Continues filling in distances, looking at up to `budget` cells.
+    /// Returns true once every reachable cell has its distance.
+    pub fn step(&mut self, grid: &impl Walkable, budget: usize) -> bool {
+        for _ in 0..budget {
+            let Some(current) = self.queue.pop_front() else {
+                break;
+            };
+            let current = current as usize;
+            let cell = self.cell(current);
+            let distance = self.distance[current] + 1;
+            for &offset in &DIRECTIONS[..4] {
+                let next = cell + offset;
+                let Some(next_index) = index(self.size, next) else {
+                    continue;
+                };
+                if self.distance[next_index] == UNREACHED && grid.is_walkable(next) {
+                    self.distance[next_index] = distance;
+                    // Every cell is queued once, so there is room.
+                    let _ = self.queue.push_back(next_index as u16);
+                }
+            }
+        }
+        self.queue.is_empty()
+    }
+
+    /// Fills in all the distances to `goal` at once.
+    pub fn build(&mut self, grid: &impl Walkable, goal: Point) {
+        self.start(grid, goal);
+        self.step(grid, usize::MAX);
+    }
+
+    /// Straight moves from `cell` to the goal, or `None` if it hasn't been
+    /// reached.
+    pub fn distance(&self, cell: Point) -> Option<u16> {
+        let distance = self.distance[index(self.size, cell)?];
+        (distance != UNREACHED).then_some(distance)
+    }
+
+    /// The offset of the neighbouring cell to move to from `cell` towards
+    /// the goal, or `None` at the goal and where it can't be reached.
+    pub fn direction(&self, cell: Point) -> Option<Point> {
+        let mut best = (self.distance(cell)?, None);
+        for (direction, &offset) in self.moves.directions().iter().enumerate() {
+            // Cells next to reached ones are reached unless blocked, so
+            // this also keeps diagonal moves away from corners.
+            if direction >= 4
+                && (self.distance(cell + Point::new(offset.x, 0)).is_none()
+                    || self.distance(cell + Point::new(0, offset.y)).is_none())
+            {
+                continue;
+            }
+            if let Some(distance) = self.distance(cell + offset) {
+                if distance < best.0 {
+                    best = (distance, Some(offset));
+                }
+            }
+        }
+        best.1
+    }
+
+    fn cell(&self, index: usize) -> Point {
+        let width = self.size.width as usize;
+        Point::new((index % width) as i32, (index / width) as i32)
+    }
+}
+
+fn index(size: Size, cell: Point) -> Option<usize> {
+    if !(0..size.width as i32).contains(&cell.x) || !(0..size.height as i32).contains(&cell.y) {
+        return None;
+    }
+    Some(cell.y as usize * size.width as usize + cell.x as usize)
+}
+
+// Returns true if the move by `offset` from `cell` is allowed.
+fn passable(grid: &impl Walkable, size: Size, cell: Point, offset: Point) -> bool {
+    let walkable = |cell: Point| index(size, cell).is_some() && grid.is_walkable(cell);
+    walkable(cell + offset)
+        && (offset.x == 0
+            || offset.y == 0
+            || walkable(cell + Point::new(offset.x, 0)) && walkable(cell + Point::new(0, offset.y)))
+}
+
+fn key(estimate: u16, index: usize) -> u32 {
+    (estimate as u32) << 16 | index as u32
+}
@@ -0,0 +1,124 @@
+//! The pins `Hardware` leaves free, for sensors, extra displays and other
+//! add-ons.
+//!
+//! `Hardware::expansion` holds them together with I2C1 and SPI1, so a game
+//! can set up a bus without stealing peripherals from the crate:
+//!
+//! - I2C1 with SDA on GPIO2 and SCL on GPIO3, from `Expansion::i2c`.
+//! - SPI1 with SCK on GPIO10, TX on GPIO27 and RX on GPIO28, from
+//!   `Expansion::spi`. Chip select is any spare pin driven as an output.
+//!
+//! Pins not taken by a bus can be taken out of their fields and used as
+//! plain GPIO. GPIO0 and GPIO1 belong to `link`.
+
+use embedded_hal::spi::Mode;
+use fugit::HertzU32;
+use rp2040_hal::gpio::pin::bank0::{Gpio10, Gpio2, Gpio27, Gpio28, Gpio3};
+use rp2040_hal::gpio::pin::{FunctionI2C, FunctionSpi, Pin, PinId, PullDownDisabled};
+use rp2040_hal::i2c::I2C;
+use rp2040_hal::spi::{Enabled, Spi};
+use rp_pico::hal::pac;
+
+pub type I2cPins = (Pin<Gpio2, FunctionI2C>, Pin<Gpio3, FunctionI2C>);
+pub type ExpansionI2c = I2C<pac::I2C1, I2cPins>;
+/// SCK, TX and RX.
+pub type SpiPins = (
+    Pin<Gpio10, FunctionSpi>,
+    Pin<Gpio27, FunctionSpi>,
+    Pin<Gpio28, FunctionSpi>,
+);
+pub type ExpansionSpi = Spi<Enabled, pac::SPI1, 8>;
+
+pub struct Expansion {
+    pub gpio2: Option<Pin<Gpio2, PullDownDisabled>>,
+    pub gpio3: Option<Pin<Gpio3, PullDownDisabled>>,
+    pub gpio10: Option<Pin<Gpio10, PullDownDisabled>>,
+    pub gpio27: Option<Pin<Gpio27, PullDownDisabled>>,
+    pub gpio28: Option<Pin<Gpio28, PullDownDisabled>>,
+    i2c: Option<pac::I2C1>,
+    spi: Option<pac::SPI1>,
+    resets: pac::RESETS,
+    system_clock: HertzU32,
+    peripheral_clock: HertzU32,
+}
+
+impl Expansion {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        gpio2: Pin<Gpio2, PullDownDisabled>,
+        gpio3: Pin<Gpio3, PullDownDisabled>,
+        gpio10: Pin<Gpio10, PullDownDisabled>,
+        gpio27: Pin<Gpio27, PullDownDisabled>,
+        gpio28: Pin<Gpio28, PullDownDisabled>,
+        i2c: pac::I2C1,
+        spi: pac::SPI1,
+        resets: pac::RESETS,
+        system_clock: HertzU32,
+        peripheral_clock: HertzU32,
+    ) -> Self {
+        Expansion {
+            gpio2: Some(gpio2),
+            gpio3: Some(gpio3),
+            gpio10: Some(gpio10),
+            gpio27: Some(gpio27),
+            gpio28: Some(gpio28),
+            i2c: Some(i2c),
+            spi: Some(spi),
+            resets,
+            system_clock,
+            peripheral_clock,
+        }
+    }
+
+    /// I2C1 as a controller at `frequency`, usually 100 or 400 kHz. The
+    /// internal pull-ups are enabled, which is enough for short wires.
+    /// Returns `None` if it or its pins were already taken.
+    pub fn i2c(&mut self, frequency: HertzU32) -> Option<ExpansionI2c> {
+        if self.i2c.is_none() || self.gpio2.is_none() || self.gpio3.is_none() {
+            return None;
+        }
+        let sda = self.gpio2.take()?.into_mode::<FunctionI2C>();
+        let scl = self.gpio3.take()?.into_mode::<FunctionI2C>();
+        pull_up(Gpio2::DYN.num);
+        pull_up(Gpio3::DYN.num);
+        Some(I2C::i2c1(
+            self.i2c.take()?,
+            sda,
+            scl,
+            frequency,
+            &mut self.resets,
+            self.system_clock,
+        ))
+    }
+
+    /// SPI1 as a controller at `baud_rate`. Returns `None` if it or its
+    /// pins were already taken.
+    pub fn spi(&mut self, baud_rate: HertzU32, mode: Mode) -> Option<(ExpansionSpi, SpiPins)> {
+        if self.spi.is_none()
+            || self.gpio10.is_none()
+            || self.gpio27.is_none()
+            || self.gpio28.is_none()
+        {
+            return None;
+        }
+        let pins = (
+            self.gpio10.take()?.into_mode::<FunctionSpi>(),
+            self.gpio27.take()?.into_mode::<FunctionSpi>(),
+            self.gpio28.take()?.into_mode::<FunctionSpi>(),
+        );
+        let spi = Spi::<_, _, 8>::new(self.spi.take()?).init(
+            &mut self.resets,
+            self.peripheral_clock,
+            baud_rate,
+            &mode,
+        );
+        Some((spi, pins))
+    }
+}
+
+fn pull_up(pin: u8) {
+    unsafe {
+        (*pac::PADS_BANK0::PTR).gpio[pin as usize]
+            .modify(|_, w| w.pue().set_bit().pde().clear_bit());
+    }
+}
@@ -0,0 +1,142 @@
+//! Saving power while nothing happens on screen.
+//!
+//! `sleep_until_button` turns the screen off and stops the core until a
+//! button is pressed, with `SleepDepth` choosing how much else is stopped.
+//! `Idle` dims the backlight after a while without input and calls it a
+//! while later, and games can call it from a pause screen. The backlight
+//! can also be dimmed with `Display::set_backlight_level`.
+
+use crate::display::Display;
+use crate::hardware::Delay;
+use crate::interrupts::{self, GpioEvent};
+use rp_pico::hal::pac;
+
+const BUTTON_GPIOS: core::ops::Range<usize> = 16..24;
+
+// Written to XOSC_DORMANT to stop the crystal oscillator.
+const XOSC_DORMANT: u32 = 0x636f_6d61;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepDepth {
+    /// Turns the backlight off and waits for interrupts. Audio, LED effects
+    /// and USB keep running.
+    Light,
+    /// Also puts the LCD to sleep and runs the system clock from the 12 MHz
+    /// crystal with the system PLL off. Timer interrupts still wake the core
+    /// for a moment, so audio and LED effects carry on slowly.
+    Deep,
+    /// Also stops the crystal, which stops every clock, so nothing runs and
+    /// the timer stands still until a button is pressed. USB stops
+    /// responding.
+    Dormant,
+}
+
+fn buttons_released() -> bool {
+    let mask = BUTTON_GPIOS.fold(0, |mask, gpio| mask | (1 << gpio));
+    let gpio_in = unsafe { (*pac::SIO::PTR).gpio_in.read().bits() };
+    gpio_in & mask == mask
+}
+
+/// Turns the screen off and sleeps until a button is pressed, then turns
+/// it back on. Returns at once if a button is held.
+pub fn sleep_until_button(display: &mut Display, delay: &mut Delay, depth: SleepDepth) {
+    if !buttons_released() {
+        return;
+    }
+    // The TE interrupt would wake the core every frame, and its GPIO
+    // interrupt is masked again below.
+    let te_sync = display.te_sync();
+    display.set_te_sync(false);
+    display.disable_backlight(delay);
+    if depth != SleepDepth::Light {
+        display.sleep(delay);
+    }
+    unsafe {
+        for gpio in BUTTON_GPIOS {
+            interrupts::enable_gpio_interrupt(gpio, GpioEvent::EdgeLow);
+        }
+        interrupts::acknowledge_gpio_interrupt();
+        interrupts::unmask_gpio_interrupt();
+        match depth {
+            SleepDepth::Light => wait_for_button(),
+            SleepDepth::Deep => {
+                run_from_crystal();
+                wait_for_button();
+                run_from_pll();
+            }
+            SleepDepth::Dormant => {
+                run_from_crystal();
+                dormant_until_button();
+                run_from_pll();
+            }
+        }
+        interrupts::mask_gpio_interrupt();
+        for gpio in BUTTON_GPIOS {
+            interrupts::disable_gpio_interrupt(gpio, GpioEvent::EdgeLow);
+        }
+    }
+    if depth != SleepDepth::Light {
+        display.wake(delay);
+    }
+    display.set_te_sync(te_sync);
+    display.enable_backlight(delay);
+}
+
+// Timer interrupts from audio, LED effects and input events also wake the
+// core, so this sleeps again until a button is actually pressed.
+fn wait_for_button() {
+    while buttons_released() {
+        cortex_m::asm::wfi();
+    }
+}
+
+unsafe fn dormant_until_button() {
+    for gpio in BUTTON_GPIOS {
+        interrupts::enable_dormant_wake(gpio, GpioEvent::EdgeLow);
+    }
+    let xosc = &*pac::XOSC::PTR;
+    xosc.dormant.write(|w| w.bits(XOSC_DORMANT));
+    while xosc.status.read().stable().bit_is_clear() {}
+    for gpio in BUTTON_GPIOS {
+        interrupts::disable_dormant_wake(gpio, GpioEvent::EdgeLow);
+    }
+    interrupts::acknowledge_gpio_interrupt();
+    // The USB PLL lost its reference while the crystal was stopped.
+    while (*pac::PLL_USB::PTR).cs.read().lock().bit_is_clear() {}
+}
+
+/// Switches the system clock to the crystal, through `clk_ref`, and powers
+/// the system PLL down. Peripherals clocked from `clk_sys`, such as SPI and
+/// PWM, slow down with it, while the timer keeps counting microseconds.
+///
+/// # Safety
+///
+/// `cortex_m::delay::Delay` and anything else calibrated for the full
+/// system clock is wrong until `run_from_pll`.
+pub unsafe fn run_from_crystal() {
+    let clocks = &*pac::CLOCKS::PTR;
+    clocks.clk_sys_ctrl.modify(|_, w| w.src().clk_ref());
+    while clocks.clk_sys_selected.read().bits() & 1 == 0 {}
+    (*pac::PLL_SYS::PTR)
+        .pwr
+        .modify(|_, w| w.pd().set_bit().vcopd().set_bit().postdivpd().set_bit());
+}
+
+/// Powers the system PLL back up with its previous settings and switches
+/// the system clock back to it.
+///
+/// # Safety
+///
+/// Must only follow `run_from_crystal`.
+pub unsafe fn run_from_pll() {
+    let pll = &*pac::PLL_SYS::PTR;
+    pll.pwr
+        .modify(|_, w| w.pd().clear_bit().vcopd().clear_bit());
+    while pll.cs.read().lock().bit_is_clear() {}
+    pll.pwr.modify(|_, w| w.postdivpd().clear_bit());
+    let clocks = &*pac::CLOCKS::PTR;
+    clocks
+        .clk_sys_ctrl
+        .modify(|_, w| w.src().clksrc_clk_sys_aux());
+    while clocks.clk_sys_selected.read().bits() & 2 == 0 {}
+}
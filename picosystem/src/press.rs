@@ -0,0 +1,93 @@
+// Long and double presses of a button, worked out from whether it is held
+// each time it is looked at. Shared by the buttons of the device and the
+// simulator.
+
+use crate::time::Duration;
+
+const DEFAULT_LONG_PRESS_US: u64 = 500_000;
+const DEFAULT_DOUBLE_PRESS_US: u64 = 300_000;
+
+// A release shorter than this is the switch bouncing, not a second press.
+const RELEASE_DEBOUNCE_US: u64 = 5_000;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PressTracker {
+    long_press_us: u64,
+    double_press_us: u64,
+    // When the current press started, and when it was let go, until the
+    // release has lasted long enough to count.
+    pressed_at: Option<u64>,
+    released_at: Option<u64>,
+    // When the press before started, unless it ended a double press.
+    previous_press: Option<u64>,
+    long_press_taken: bool,
+    double_press: bool,
+}
+
+impl PressTracker {
+    pub(crate) const fn new() -> Self {
+        PressTracker {
+            long_press_us: DEFAULT_LONG_PRESS_US,
+            double_press_us: DEFAULT_DOUBLE_PRESS_US,
+            pressed_at: None,
+            released_at: None,
+            previous_press: None,
+            long_press_taken: false,
+            double_press: false,
+        }
+    }
+
+    pub(crate) fn set_long_press(&mut self, duration: Duration) {
+        self.long_press_us = duration.ticks();
+    }
+
+    pub(crate) fn set_double_press_window(&mut self, window: Duration) {
+        self.double_press_us = window.ticks();
+    }
+
+    pub(crate) fn update(&mut self, held: bool, now_us: u64) {
+        match (held, self.pressed_at) {
+            (true, None) => {
+                self.double_press = self
+                    .previous_press
+                    .is_some_and(|previous| now_us - previous <= self.double_press_us);
+                self.previous_press = if self.double_press {
+                    None
+                } else {
+                    Some(now_us)
+                };
+                self.pressed_at = Some(now_us);
+                self.long_press_taken = false;
+            }
+            (true, Some(_)) => self.released_at = None,
+            (false, Some(_)) => {
+                let released_at = *self.released_at.get_or_insert(now_us);
+                if now_us - released_at >= RELEASE_DEBOUNCE_US {
+                    self.pressed_at = None;
+                    self.released_at = None;
+                    self.double_press = false;
+                }
+            }
+            (false, None) => {}
+        }
+    }
+
+    pub(crate) fn held_for(&self, duration: Duration, now_us: u64) -> bool {
+        match (self.pressed_at, self.released_at) {
+            (Some(pressed_at), None) => now_us - pressed_at >= duration.ticks(),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn take_long_press(&mut self, now_us: u64) -> bool {
+        if self.long_press_taken || !self.held_for(Duration::micros(self.long_press_us), now_us) {
+            return false;
+        }
+        self.long_press_taken = true;
+        true
+    }
+
+    pub(crate) fn take_double_press(&mut self) -> bool {
+        core::mem::take(&mut self.double_press)
+    }
+}
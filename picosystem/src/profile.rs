@@ -0,0 +1,219 @@
+//! Timing of labelled scopes, counted in CPU cycles.
+//!
+//! `profile_scope!("blit")` times the rest of the enclosing block. The
+//! cycles spent in each label are added up over a frame, and once a second
+//! the smallest, average and largest of those frame totals are kept for
+//! `reports`, which the debug overlay shows, and optionally logged.
+//!
+//! The Cortex-M0+ has no DWT cycle counter, so SysTick runs freely for
+//! this and `Hardware::delay` waits on the microsecond timer instead.
+//! SysTick only has 24 bits and wraps after about 130 ms, so longer scopes
+//! are timed with the microsecond timer.
+
+use crate::logging;
+use crate::time;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SYST;
+use critical_section::Mutex;
+
+pub const MAX_LABELS: usize = 16;
+
+const REPORT_INTERVAL_US: u32 = 1_000_000;
+const SYST_MASK: u32 = 0x00ff_ffff;
+// Scopes longer than this might have wrapped SysTick.
+const MAX_SYST_SCOPE_US: u32 = 100_000;
+
+/// Times the rest of the enclosing block under `label`.
+#[macro_export]
+macro_rules! profile_scope {
+    ($label:expr) => {
+        let _profile_scope = $crate::profile::scope($label);
+    };
+}
+
+/// Per frame totals of one label over the last report interval.
+#[derive(Debug, Clone, Copy)]
+pub struct Report {
+    pub label: &'static str,
+    pub min_us: u32,
+    pub avg_us: u32,
+    pub max_us: u32,
+    /// Frames the label was timed in.
+    pub frames: u32,
+    pub calls: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    label: &'static str,
+    frame_cycles: u32,
+    frame_calls: u32,
+    min_cycles: u32,
+    max_cycles: u32,
+    total_cycles: u64,
+    frames: u32,
+    calls: u32,
+}
+
+impl Entry {
+    fn new(label: &'static str) -> Self {
+        Entry {
+            label,
+            frame_cycles: 0,
+            frame_calls: 0,
+            min_cycles: u32::MAX,
+            max_cycles: 0,
+            total_cycles: 0,
+            frames: 0,
+            calls: 0,
+        }
+    }
+}
+
+struct Profiler {
+    entries: heapless::Vec<Entry, MAX_LABELS>,
+    reports: heapless::Vec<Report, MAX_LABELS>,
+    last_report_us: u32,
+}
+
+static PROFILER: Mutex<RefCell<Profiler>> = Mutex::new(RefCell::new(Profiler {
+    entries: heapless::Vec::new(),
+    reports: heapless::Vec::new(),
+    last_report_us: 0,
+}));
+
+static CYCLES_PER_US: AtomicU32 = AtomicU32::new(125);
+static LOG_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Starts SysTick counting every cycle. Called by `Hardware::new`.
+pub(crate) fn init(mut syst: SYST, system_clock_hz: u32) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(SYST_MASK);
+    syst.clear_current();
+    syst.enable_counter();
+    CYCLES_PER_US.store(system_clock_hz / 1_000_000, Ordering::Relaxed);
+}
+
+/// Logs the reports every second.
+pub fn set_log_enabled(enabled: bool) {
+    LOG_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Starts timing `label` until the returned guard is dropped.
+pub fn scope(label: &'static str) -> Scope {
+    Scope {
+        label,
+        start_cycles: SYST::get_current(),
+        start_us: time::time_us(),
+    }
+}
+
+pub struct Scope {
+    label: &'static str,
+    start_cycles: u32,
+    start_us: u32,
+}
+
+impl Scope {
+    /// Stops timing and returns the time taken in microseconds.
+    pub fn finish(self) -> u32 {
+        let cycles = self.elapsed_cycles();
+        record(self.label, cycles);
+        core::mem::forget(self);
+        cycles / CYCLES_PER_US.load(Ordering::Relaxed)
+    }
+
+    fn elapsed_cycles(&self) -> u32 {
+        let elapsed_us = time::time_us().wrapping_sub(self.start_us);
+        if elapsed_us > MAX_SYST_SCOPE_US {
+            return elapsed_us.saturating_mul(CYCLES_PER_US.load(Ordering::Relaxed));
+        }
+        // SysTick counts down.
+        self.start_cycles.wrapping_sub(SYST::get_current()) & SYST_MASK
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        record(self.label, self.elapsed_cycles());
+    }
+}
+
+fn record(label: &'static str, cycles: u32) {
+    critical_section::with(|cs| {
+        let mut profiler = PROFILER.borrow_ref_mut(cs);
+        let entries = &mut profiler.entries;
+        let index = match entries.iter().position(|entry| entry.label == label) {
+            Some(index) => index,
+            None => {
+                if entries.push(Entry::new(label)).is_err() {
+                    return;
+                }
+                entries.len() - 1
+            }
+        };
+        let entry = &mut entries[index];
+        entry.frame_cycles = entry.frame_cycles.saturating_add(cycles);
+        entry.frame_calls += 1;
+    });
+}
+
+/// Adds this frame's totals to the statistics. Called by `Hardware::draw`.
+pub(crate) fn end_frame() {
+    let now = time::time_us();
+    let cycles_per_us = CYCLES_PER_US.load(Ordering::Relaxed);
+    let reported = critical_section::with(|cs| {
+        let mut profiler = PROFILER.borrow_ref_mut(cs);
+        for entry in profiler
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.frame_calls > 0)
+        {
+            entry.min_cycles = entry.min_cycles.min(entry.frame_cycles);
+            entry.max_cycles = entry.max_cycles.max(entry.frame_cycles);
+            entry.total_cycles += entry.frame_cycles as u64;
+            entry.frames += 1;
+            entry.calls += entry.frame_calls;
+            entry.frame_cycles = 0;
+            entry.frame_calls = 0;
+        }
+        if now.wrapping_sub(profiler.last_report_us) < REPORT_INTERVAL_US {
+            return false;
+        }
+        profiler.last_report_us = now;
+        let profiler = &mut *profiler;
+        profiler.reports.clear();
+        for entry in profiler.entries.iter_mut().filter(|entry| entry.frames > 0) {
+            let _ = profiler.reports.push(Report {
+                label: entry.label,
+                min_us: entry.min_cycles / cycles_per_us,
+                avg_us: (entry.total_cycles / entry.frames as u64 / cycles_per_us as u64) as u32,
+                max_us: entry.max_cycles / cycles_per_us,
+                frames: entry.frames,
+                calls: entry.calls,
+            });
+            *entry = Entry::new(entry.label);
+        }
+        true
+    });
+    if reported && LOG_ENABLED.load(Ordering::Relaxed) {
+        for report in reports() {
+            logging::info!(
+                "Profile {}: min {} avg {} max {} us/frame, {} calls in {} frames",
+                report.label,
+                report.min_us,
+                report.avg_us,
+                report.max_us,
+                report.calls,
+                report.frames
+            );
+        }
+    }
+}
+
+/// The statistics of the last full report interval.
+pub fn reports() -> heapless::Vec<Report, MAX_LABELS> {
+    critical_section::with(|cs| PROFILER.borrow_ref(cs).reports.clone())
+}
@@ -0,0 +1,372 @@
+//! A first person view of a grid of walls, drawn a screen column at a time
+//! like Wolfenstein 3D.
+//!
+//! Each column casts a ray from the camera through the grid until it hits a
+//! wall and draws a column of the wall's texture, scaled by the distance.
+//! Textures are tiles, so a `Map` made in Tiled is a level through
+//! `MapGrid`, with the tiles of one layer as walls. `Billboard`s are images
+//! that always face the camera, for items and enemies, hidden behind the
+//! walls column by column.
+//!
+//! Positions are in cells, and a wall is as tall as a cell is wide. The
+//! math is 16.16 fixed point, and DMA fills the ceiling and floor while the
+//! rays are cast.
+
+use crate::blit::Image;
+use crate::display::{framebuffer, screen_height, Display, WIDTH};
+use crate::dma::{self, DmaError};
+use crate::map::{Map, INVALID_TILE};
+use crate::math::{Angle, Fixed, Vector2, I16F16};
+use crate::tile::{load_tile, tile_id, LoadedTile, Tile, TileDma, TileId};
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+/// Wall textures kept decompressed. More in view at once are loaded again
+/// for every column that shows them.
+const TEXTURE_CACHE: usize = 4;
+/// Cells a ray crosses before it gives up and shows the horizon.
+const MAX_STEPS: u32 = 64;
+// Smallest ray direction divided by, to keep its inverse in range.
+const MIN_RAY: i32 = 1 << 6;
+// Closest a wall or billboard is drawn, so the scale stays in range.
+const NEAR: I16F16 = Fixed::from_bits(1 << 10);
+
+/// Where the walls are.
+pub trait Grid {
+    /// The texture of the wall filling cell (`x`, `y`), or `None` for open
+    /// space.
+    fn wall(&self, x: i32, y: i32) -> Option<&'static Tile>;
+}
+
+/// The tiles of one layer of `map` as walls, one per map tile.
+pub struct MapGrid<'a> {
+    pub map: &'a Map,
+    pub layer: usize,
+}
+
+impl Grid for MapGrid<'_> {
+    fn wall(&self, x: i32, y: i32) -> Option<&'static Tile> {
+        let tile = self.map.tile(x, y)?.layers[self.layer];
+        if tile == INVALID_TILE {
+            return None;
+        }
+        Some((self.map.tile_functions[tile as usize])())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Camera {
+    pub position: Vector2,
+    /// Direction of view, clockwise from the x axis.
+    pub angle: Angle,
+    /// Horizontal field of view, less than half a turn.
+    pub fov: Angle,
+}
+
+impl Camera {
+    pub fn new(position: Vector2, angle: Angle) -> Self {
+        Camera {
+            position,
+            angle,
+            fov: Angle::from_degrees(66),
+        }
+    }
+
+    fn direction(&self) -> Vector2 {
+        Vector2::new(self.angle.cos(), self.angle.sin())
+    }
+
+    // Points to the right of the screen, as long as half the screen is
+    // wide at a distance of one.
+    fn plane(&self) -> Vector2 {
+        let half_fov = Angle(self.fov.0.min(0x7fff) / 2);
+        let tan = half_fov.sin() / half_fov.cos();
+        Vector2::new(-self.angle.sin() * tan, self.angle.cos() * tan)
+    }
+}
+
+/// An image standing in the world, facing the camera.
+pub struct Billboard<'a> {
+    pub position: Vector2,
+    /// Drawn as tall as a wall and centered on the horizon.
+    pub image: Image<'a>,
+}
+
+#[derive(Clone, Copy)]
+struct Hit {
+    wall: Option<&'static Tile>,
+    // Distance along the view direction, which keeps walls straight.
+    distance: I16F16,
+    // Column of the texture, from 0 to 1.
+    wall_x: I16F16,
+    // The wall faces north or south.
+    shaded: bool,
+}
+
+pub struct Raycaster {
+    pub ceiling: Rgb565,
+    pub floor: Rgb565,
+    /// Draws walls facing north and south at half brightness, which makes
+    /// corners stand out.
+    pub shade_sides: bool,
+    textures: heapless::Vec<(TileId, LoadedTile), TEXTURE_CACHE>,
+    next_evicted: usize,
+    tile_dma: TileDma,
+    // Distance to the wall in each column, for hiding billboards.
+    depth: [I16F16; WIDTH],
+    camera: Camera,
+}
+
+impl Raycaster {
+    /// Claims two DMA channels for loading textures, and fails if they
+    /// aren't free. The ceiling and floor are filled by the display's.
+    pub fn new() -> Result<Self, DmaError> {
+        Ok(Raycaster {
+            ceiling: Rgb565::new(8, 16, 8),
+            floor: Rgb565::new(12, 24, 12),
+            shade_sides: true,
+            textures: heapless::Vec::new(),
+            next_evicted: 0,
+            tile_dma: TileDma::claim_any()?,
+            depth: [I16F16::MAX; WIDTH],
+            camera: Camera::new(Vector2::ZERO, Angle::ZERO),
+        })
+    }
+
+    /// Draws the walls of `grid` seen from `camera` over the whole screen.
+    pub fn render(&mut self, display: &mut Display, grid: &impl Grid, camera: &Camera) {
+        self.camera = *camera;
+        let fb = framebuffer();
+        let fill_word = |color: Rgb565| {
+            let color = RawU16::from(color).into_inner().to_be() as u32;
+            color | color << 16
+        };
+        let (ceiling, floor) = (fill_word(self.ceiling), fill_word(self.floor));
+        let half = (WIDTH * screen_height() / 2) as u32;
+        let fill_dma = display.draw_dma_channel();
+        unsafe {
+            dma::start_set_mem(
+                fill_dma,
+                &ceiling as *const u32 as u32,
+                fb.as_ptr() as u32,
+                4,
+                half / 2,
+            );
+        }
+
+        let direction = camera.direction();
+        let plane = camera.plane();
+        let mut hits = [Hit {
+            wall: None,
+            distance: I16F16::MAX,
+            wall_x: I16F16::ZERO,
+            shaded: false,
+        }; WIDTH];
+        for (x, hit) in hits.iter_mut().enumerate() {
+            // From -1 at the left edge to 1 at the right, at pixel centers.
+            let camera_x =
+                Fixed::from_bits(((2 * x as i32 + 1 - WIDTH as i32) << 16) / WIDTH as i32);
+            *hit = cast(grid, camera.position, direction + plane * camera_x);
+            self.depth[x] = hit.distance;
+        }
+
+        unsafe {
+            fill_dma.wait();
+            dma::start_set_mem(
+                fill_dma,
+                &floor as *const u32 as u32,
+                fb.as_ptr().add(WIDTH * screen_height() / 2) as u32,
+                4,
+                half / 2,
+            );
+        }
+        fill_dma.wait();
+
+        let focal = focal_length(&plane);
+        for (x, hit) in hits.iter().enumerate() {
+            if let Some(wall) = hit.wall {
+                let texture = self.texture(wall);
+                self.draw_column(x, hit, focal, texture);
+            }
+        }
+        display.mark_dirty(display.bounding_box());
+    }
+
+    /// Draws `billboards` seen from the camera of the last `render`, hidden
+    /// where walls are closer. Sorts them from far to near.
+    pub fn draw_billboards(&self, display: &mut Display, billboards: &mut [Billboard]) {
+        let camera = &self.camera;
+        let direction = camera.direction();
+        let plane = camera.plane();
+        let focal = focal_length(&plane);
+        // Unit vector to the right of the view direction.
+        let right = Vector2::new(-direction.y, direction.x);
+        let depth = |billboard: &Billboard| (billboard.position - camera.position).dot(direction);
+        billboards.sort_unstable_by_key(|billboard| core::cmp::Reverse(depth(billboard)));
+
+        let fb = framebuffer();
+        for billboard in billboards.iter() {
+            let relative = billboard.position - camera.position;
+            let distance = relative.dot(direction);
+            if distance < NEAR {
+                continue;
+            }
+            let image = &billboard.image;
+            let height = (focal / distance).floor();
+            let width = height * image.width as i32 / image.height().max(1) as i32;
+            if width <= 0 || height <= 0 {
+                continue;
+            }
+            let center_x = WIDTH as i32 / 2 + (relative.dot(right) * focal / distance).floor();
+            let area = Rectangle::new(
+                Point::new(center_x - width / 2, (screen_height() as i32 - height) / 2),
+                Size::new(width as u32, height as u32),
+            );
+            let clipped = area.intersection(&display.bounding_box());
+            if clipped.is_zero_sized() {
+                continue;
+            }
+            let offset = clipped.top_left - area.top_left;
+            // Image pixels per screen pixel in 16.16.
+            let step_x = (image.width << 16) / width as u32;
+            let step_y = (image.height() << 16) / height as u32;
+            for x in 0..clipped.size.width {
+                let screen_x = (clipped.top_left.x as u32 + x) as usize;
+                if distance >= self.depth[screen_x] {
+                    continue;
+                }
+                let src_x = ((offset.x as u32 + x) * step_x) >> 16;
+                let mut src_y = offset.y as u32 * step_y;
+                for y in 0..clipped.size.height as usize {
+                    let row = (src_y >> 16) * image.width;
+                    let dst = &mut fb[(clipped.top_left.y as usize + y) * WIDTH + screen_x];
+                    image.draw_pixel(src_x, src_y >> 16, (row + src_x) as usize, dst);
+                    src_y += step_y;
+                }
+            }
+            display.mark_dirty(clipped);
+        }
+    }
+
+    // Index of `wall` in the texture cache, loading it if needed.
+    fn texture(&mut self, wall: &'static Tile) -> usize {
+        let id = tile_id(wall);
+        if let Some(index) = self.textures.iter().position(|(cached, _)| *cached == id) {
+            return index;
+        }
+        let index = if self.textures.is_full() {
+            let index = self.next_evicted;
+            self.next_evicted = (index + 1) % TEXTURE_CACHE;
+            index
+        } else {
+            let _ = self.textures.push((id, LoadedTile::new()));
+            self.textures.len() - 1
+        };
+        let (cached, loaded) = &mut self.textures[index];
+        load_tile(&mut self.tile_dma, wall, loaded, false);
+        *cached = id;
+        index
+    }
+
+    fn draw_column(&self, x: usize, hit: &Hit, focal: I16F16, texture: usize) {
+        let texture = &self.textures[texture].1;
+        let size = texture.size;
+        let height = (focal / hit.distance.max(NEAR)).floor().max(1);
+        let screen_height = screen_height() as i32;
+        let top = (screen_height - height) / 2;
+        let texture_x = ((hit.wall_x.to_bits() * size) >> 16).clamp(0, size - 1);
+        // Texture rows per screen row in 16.16.
+        let step = (size << 16) / height;
+        let first = top.max(0);
+        let mut texture_y = (first - top) * step;
+        let fb = framebuffer();
+        for y in first..(top + height).min(screen_height) {
+            let texel = texture.data[((texture_y >> 16) * size + texture_x) as usize];
+            fb[y as usize * WIDTH + x] = if hit.shaded && self.shade_sides {
+                ((u16::from_be(texel) >> 1) & 0x7bef).to_be()
+            } else {
+                texel
+            };
+            texture_y += step;
+        }
+    }
+}
+
+// Distance to the screen in pixels, for a screen as wide as `plane` at a
+// distance of one.
+fn focal_length(plane: &Vector2) -> I16F16 {
+    I16F16::from_int(WIDTH as i32 / 2) / plane.length()
+}
+
+// Steps along `ray` from `position` one grid line at a time until it
+// enters a wall.
+fn cast(grid: &impl Grid, position: Vector2, ray: Vector2) -> Hit {
+    // Distance along the ray between crossings of vertical and horizontal
+    // grid lines.
+    let inverse = |d: I16F16| {
+        let d = d.to_bits().abs().max(MIN_RAY);
+        I16F16::ONE / Fixed::from_bits(d)
+    };
+    let (delta_x, delta_y) = (inverse(ray.x), inverse(ray.y));
+    let (mut cell_x, mut cell_y) = (position.x.floor(), position.y.floor());
+    let (step_x, mut side_x) = if ray.x.is_negative() {
+        (-1, position.x.frac() * delta_x)
+    } else {
+        (1, (I16F16::ONE - position.x.frac()) * delta_x)
+    };
+    let (step_y, mut side_y) = if ray.y.is_negative() {
+        (-1, position.y.frac() * delta_y)
+    } else {
+        (1, (I16F16::ONE - position.y.frac()) * delta_y)
+    };
+
+    for _ in 0..MAX_STEPS {
+        let crossed_x = side_x < side_y;
+        if crossed_x {
+            side_x += delta_x;
+            cell_x += step_x;
+        } else {
+            side_y += delta_y;
+            cell_y += step_y;
+        }
+        let Some(wall) = grid.wall(cell_x, cell_y) else {
+            continue;
+        };
+        // The ray is as long as the view direction is along it, so this is
+        // the distance along the view direction.
+        let (distance, wall_x) = if crossed_x {
+            let distance = side_x - delta_x;
+            let wall_x = (position.y + ray.y * distance).frac();
+            // Mirrored so textures read left to right from outside.
+            let wall_x = if ray.x.is_negative() {
+                wall_x
+            } else {
+                I16F16::ONE - wall_x
+            };
+            (distance, wall_x)
+        } else {
+            let distance = side_y - delta_y;
+            let wall_x = (position.x + ray.x * distance).frac();
+            let wall_x = if ray.y.is_negative() {
+                I16F16::ONE - wall_x
+            } else {
+                wall_x
+            };
+            (distance, wall_x)
+        };
+        return Hit {
+            wall: Some(wall),
+            distance,
+            wall_x,
+            shaded: !crossed_x,
+        };
+    }
+    Hit {
+        wall: None,
+        distance: I16F16::MAX,
+        wall_x: I16F16::ZERO,
+        shaded: false,
+    }
+}
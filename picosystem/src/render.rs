@@ -0,0 +1,478 @@
+//! A render server on core 1.
+//!
+//! Core 0 queues draw commands and hands over finished frames, and core 1
+//! draws them into the framebuffer and flushes it to the LCD, including the
+//! wait for vsync. Meanwhile core 0 is free to run the next frame's logic.
+//! The cores signal each other through the SIO FIFO.
+//!
+//! Core 1 also runs jobs between frames: a function and an argument, such
+//! as a pointer to its data, queued with `spawn`, whose result comes back
+//! through the FIFO, for work like pathfinding or decompression that would
+//! hold up core 0.
+
+use crate::blit::{blit_dma, Flip, Image};
+use crate::dirty_rects::DirtyRects;
+use crate::display::{self, framebuffer, Display, WIDTH};
+use crate::frame_arena;
+use crate::tile::{self, LoadedTile, Tile, TileDma};
+use core::cell::RefCell;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use critical_section::Mutex;
+use embedded_graphics::pixelcolor::{raw::RawU16, Rgb565};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use rp_pico::hal;
+use rp_pico::hal::multicore::{Multicore, Stack};
+use rp_pico::hal::pac;
+use rp_pico::hal::sio::{SioFifo, Spinlock0};
+
+// Smaller with `double-buffer`, with the stack of core 1 below, to fit next
+// to the second framebuffer.
+#[cfg(not(feature = "double-buffer"))]
+const QUEUE_SIZE: usize = 64;
+#[cfg(feature = "double-buffer")]
+const QUEUE_SIZE: usize = 32;
+
+// FIFO messages from core 0.
+const MSG_KICK: u32 = 1;
+const MSG_SYNC: u32 = 2;
+// Followed by a pointer to the display.
+const MSG_PRESENT: u32 = 3;
+// Replies from core 1.
+const MSG_DONE: u32 = 4;
+// Or'ed with the slot of a job, and followed by its result.
+const MSG_JOB_DONE: u32 = 0x100;
+
+pub const MAX_JOBS: usize = 8;
+
+/// A job for core 1, given its argument and returning its result.
+pub type JobFn = fn(u32) -> u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobError {
+    /// `MAX_JOBS` jobs are queued or waiting to be joined.
+    TooManyJobs,
+}
+
+#[derive(Clone, Copy)]
+struct Job {
+    slot: u8,
+    func: JobFn,
+    arg: u32,
+}
+
+// Jobs waiting for core 1, oldest first. The hardware spinlock guards it
+// rather than a critical section, which would take spinlock 31 that every
+// interrupt of either core shares.
+type JobLock = Spinlock0;
+static mut JOB_QUEUE: heapless::Deque<Job, MAX_JOBS> = heapless::Deque::new();
+
+fn with_job_queue<R>(f: impl FnOnce(&mut heapless::Deque<Job, MAX_JOBS>) -> R) -> R {
+    // An interrupt taking the lock while this core holds it would spin
+    // forever, so they wait until it is released.
+    cortex_m::interrupt::free(|_| {
+        let _lock = JobLock::claim();
+        f(unsafe { &mut *addr_of_mut!(JOB_QUEUE) })
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum JobState {
+    Free,
+    Running,
+    Done(u32),
+}
+
+#[cfg(not(feature = "double-buffer"))]
+static mut CORE1_STACK: Stack<4096> = Stack::new();
+#[cfg(feature = "double-buffer")]
+static mut CORE1_STACK: Stack<1024> = Stack::new();
+
+static CORE1_STARTED: AtomicBool = AtomicBool::new(false);
+
+// Core 1 runs from flash, so it waits in RAM while flash is written.
+const LOCKOUT_NONE: u8 = 0;
+const LOCKOUT_REQUESTED: u8 = 1;
+const LOCKOUT_PARKED: u8 = 2;
+static LOCKOUT: AtomicU8 = AtomicU8::new(LOCKOUT_NONE);
+
+#[derive(Clone, Copy)]
+pub enum Command {
+    Blit {
+        image: Image<'static>,
+        src: Rectangle,
+        dst: Point,
+        flip: Flip,
+    },
+    Fill {
+        area: Rectangle,
+        color: Rgb565,
+    },
+    /// Decompresses and draws a tile, using its mask if `transparent`.
+    Tile {
+        tile: &'static Tile,
+        dst: Point,
+        transparent: bool,
+    },
+}
+
+static QUEUE: Mutex<RefCell<heapless::Deque<Command, QUEUE_SIZE>>> =
+    Mutex::new(RefCell::new(heapless::Deque::new()));
+
+pub struct RenderServer {
+    fifo: SioFifo,
+    // `MSG_DONE` replies received and not yet waited for.
+    done: u32,
+    jobs: [JobState; MAX_JOBS],
+}
+
+/// A job spawned on core 1, to be joined for its result.
+#[must_use]
+pub struct JobHandle {
+    slot: usize,
+}
+
+impl RenderServer {
+    /// Starts the server on core 1.
+    pub fn start(psm: &mut pac::PSM, ppb: &mut pac::PPB, mut fifo: SioFifo) -> Self {
+        // Claimed on core 0 at boot, before the game can hold any channels.
+        let tile_dma = TileDma::claim_any().unwrap();
+        let mut multicore = Multicore::new(psm, ppb, &mut fifo);
+        let core1 = &mut multicore.cores()[1];
+        core1
+            .spawn(
+                unsafe { &mut (*core::ptr::addr_of_mut!(CORE1_STACK)).mem },
+                move || core1_main(tile_dma),
+            )
+            .unwrap();
+        CORE1_STARTED.store(true, Ordering::SeqCst);
+        RenderServer {
+            fifo,
+            done: 0,
+            jobs: [JobState::Free; MAX_JOBS],
+        }
+    }
+
+    /// Queues a command, waiting for room if the queue is full.
+    pub fn submit(&mut self, command: Command) {
+        loop {
+            let queued = critical_section::with(|cs| QUEUE.borrow_ref_mut(cs).push_back(command));
+            if queued.is_ok() {
+                break;
+            }
+            // Core 1 may be waiting for room to reply before it takes
+            // another.
+            while self.receive(false) {}
+            // Core 1 signals an event whenever it takes a command or
+            // replies.
+            cortex_m::asm::wfe();
+        }
+        self.kick();
+    }
+
+    // Wakes core 1 to look at its queues.
+    fn kick(&mut self) {
+        // A full FIFO means core 1 has messages to wake it already.
+        if self.fifo.is_write_ready() {
+            self.fifo.write_blocking(MSG_KICK);
+        }
+    }
+
+    pub fn blit(&mut self, image: Image<'static>, src: Rectangle, dst: Point, flip: Flip) {
+        self.submit(Command::Blit {
+            image,
+            src,
+            dst,
+            flip,
+        });
+    }
+
+    pub fn fill(&mut self, area: Rectangle, color: Rgb565) {
+        self.submit(Command::Fill { area, color });
+    }
+
+    pub fn draw_tile(&mut self, tile: &'static Tile, dst: Point, transparent: bool) {
+        self.submit(Command::Tile {
+            tile,
+            dst,
+            transparent,
+        });
+    }
+
+    /// Waits until every queued command has been drawn.
+    pub fn sync(&mut self) {
+        self.send(MSG_SYNC);
+        self.wait_done();
+    }
+
+    /// Has core 1 draw the queued commands and flush the frame. The display
+    /// stays borrowed until the returned frame is waited for or dropped.
+    pub fn present<'a>(&'a mut self, display: &'a mut Display) -> PendingFrame<'a> {
+        self.send(MSG_PRESENT);
+        self.send(display as *mut Display as u32);
+        PendingFrame {
+            server: self,
+            _display: display,
+            done: false,
+        }
+    }
+
+    /// Queues `func(arg)` to run on core 1 once the draw commands queued
+    /// before it are done. Jobs run one at a time, in order, and hold up
+    /// drawing while they run.
+    pub fn spawn(&mut self, func: JobFn, arg: u32) -> Result<JobHandle, JobError> {
+        let slot = self
+            .jobs
+            .iter()
+            .position(|job| *job == JobState::Free)
+            .ok_or(JobError::TooManyJobs)?;
+        self.jobs[slot] = JobState::Running;
+        // Jobs take slots first, so there is always room.
+        let job = Job {
+            slot: slot as u8,
+            func,
+            arg,
+        };
+        with_job_queue(|queue| queue.push_back(job).ok().unwrap());
+        self.kick();
+        Ok(JobHandle { slot })
+    }
+
+    /// Whether `job` has finished, so `join` won't wait.
+    pub fn is_job_done(&mut self, job: &JobHandle) -> bool {
+        while self.receive(false) {}
+        matches!(self.jobs[job.slot], JobState::Done(_))
+    }
+
+    /// Waits for `job` to finish and returns its result.
+    pub fn join(&mut self, job: JobHandle) -> u32 {
+        loop {
+            if let JobState::Done(result) = self.jobs[job.slot] {
+                self.jobs[job.slot] = JobState::Free;
+                return result;
+            }
+            self.receive(true);
+        }
+    }
+
+    // Writes to core 1, taking in its replies while the FIFO is full, as
+    // it may be waiting for room to reply before it reads again.
+    fn send(&mut self, message: u32) {
+        while !self.fifo.is_write_ready() {
+            self.receive(false);
+        }
+        self.fifo.write_blocking(message);
+    }
+
+    // Takes in one reply from core 1, waiting for it if `wait`. Returns
+    // whether there was one.
+    fn receive(&mut self, wait: bool) -> bool {
+        let message = if wait {
+            self.fifo.read_blocking()
+        } else {
+            match self.fifo.read() {
+                Some(message) => message,
+                None => return false,
+            }
+        };
+        if message == MSG_DONE {
+            self.done += 1;
+        } else if message & MSG_JOB_DONE != 0 {
+            let result = self.fifo.read_blocking();
+            self.jobs[(message & !MSG_JOB_DONE) as usize] = JobState::Done(result);
+        }
+        true
+    }
+
+    fn wait_done(&mut self) {
+        while self.done == 0 {
+            self.receive(true);
+        }
+        self.done -= 1;
+    }
+}
+
+/// A frame being drawn and flushed by core 1.
+pub struct PendingFrame<'a> {
+    server: &'a mut RenderServer,
+    _display: &'a mut Display,
+    done: bool,
+}
+
+impl<'a> PendingFrame<'a> {
+    /// Returns true once the frame is on the LCD.
+    pub fn is_done(&mut self) -> bool {
+        if !self.done {
+            while self.server.receive(false) {}
+            if self.server.done > 0 {
+                self.server.done -= 1;
+                self.done = true;
+            }
+        }
+        self.done
+    }
+
+    pub fn wait(mut self) {
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        if !self.done {
+            self.server.wait_done();
+            self.done = true;
+        }
+    }
+}
+
+impl<'a> Drop for PendingFrame<'a> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Runs `f` with core 1 parked in RAM, so `f` can write flash. Must not be
+/// called from a critical section, which core 1 may be waiting for.
+pub(crate) fn with_core1_parked<R>(f: impl FnOnce() -> R) -> R {
+    if !CORE1_STARTED.load(Ordering::SeqCst) {
+        return f();
+    }
+    LOCKOUT.store(LOCKOUT_REQUESTED, Ordering::SeqCst);
+    // Wakes core 1 if it is waiting for a message.
+    unsafe {
+        let sio = &*pac::SIO::PTR;
+        if sio.fifo_st.read().rdy().bit_is_set() {
+            sio.fifo_wr.write(|w| w.bits(MSG_KICK));
+        }
+    }
+    cortex_m::asm::sev();
+    while LOCKOUT.load(Ordering::SeqCst) != LOCKOUT_PARKED {}
+    let result = f();
+    LOCKOUT.store(LOCKOUT_NONE, Ordering::SeqCst);
+    result
+}
+
+fn check_lockout() {
+    if LOCKOUT.load(Ordering::SeqCst) == LOCKOUT_REQUESTED {
+        park();
+    }
+}
+
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+fn park() {
+    LOCKOUT.store(LOCKOUT_PARKED, Ordering::SeqCst);
+    while LOCKOUT.load(Ordering::SeqCst) != LOCKOUT_NONE {}
+}
+
+struct Core1 {
+    fifo: SioFifo,
+    tile_dma: TileDma,
+    dirty_rects: DirtyRects,
+}
+
+impl Core1 {
+    fn run_queue(&mut self) {
+        while let Some(command) = critical_section::with(|cs| QUEUE.borrow_ref_mut(cs).pop_front())
+        {
+            // Lets core 0 retry a submit that found the queue full.
+            cortex_m::asm::sev();
+            check_lockout();
+            self.run(&command);
+        }
+    }
+
+    // Runs the queued jobs and sends back their results.
+    fn run_jobs(&mut self) {
+        while let Some(job) = with_job_queue(|queue| queue.pop_front()) {
+            check_lockout();
+            let result = (job.func)(job.arg);
+            self.reply(MSG_JOB_DONE | job.slot as u32);
+            self.reply(result);
+        }
+    }
+
+    // Writes a reply. Core 0 may be writing flash rather than reading
+    // replies, so core 1 parks for it while the FIFO is full.
+    fn reply(&mut self, message: u32) {
+        while !self.fifo.is_write_ready() {
+            check_lockout();
+        }
+        self.fifo.write(message);
+    }
+
+    fn run(&mut self, command: &Command) {
+        let screen = display::screen();
+        match *command {
+            Command::Blit {
+                image,
+                src,
+                dst,
+                flip,
+            } => {
+                if let Some(area) = blit_dma(&mut self.tile_dma.channel0, &image, &src, dst, flip) {
+                    self.dirty_rects.add(area);
+                }
+            }
+            Command::Fill { area, color } => {
+                let area = area.intersection(&screen);
+                let color = RawU16::from(color).into_inner().to_be();
+                let fb = framebuffer();
+                for y in area.rows() {
+                    let start = y as usize * WIDTH + area.top_left.x as usize;
+                    fb[start..start + area.size.width as usize].fill(color);
+                }
+                self.dirty_rects.add(area);
+            }
+            Command::Tile {
+                tile,
+                dst,
+                transparent,
+            } => {
+                let mut loaded = LoadedTile::new();
+                tile::load_tile(&mut self.tile_dma, tile, &mut loaded, transparent);
+                let size = Size::new(loaded.size as u32, loaded.size as u32);
+                let channel = &mut self.tile_dma.channel0;
+                if transparent {
+                    tile::draw_transparent_tile(channel, &loaded, dst, size);
+                } else {
+                    tile::draw_opaque_tile(channel, &loaded, dst, size);
+                }
+                self.dirty_rects
+                    .add(Rectangle::new(dst, size).intersection(&screen));
+            }
+        }
+    }
+}
+
+fn core1_main(tile_dma: TileDma) -> ! {
+    let pac = unsafe { pac::Peripherals::steal() };
+    let sio = hal::sio::Sio::new(pac.SIO);
+    let mut core1 = Core1 {
+        fifo: sio.fifo,
+        tile_dma,
+        dirty_rects: DirtyRects::new(),
+    };
+
+    loop {
+        let message = core1.fifo.read_blocking();
+        check_lockout();
+        core1.run_queue();
+        match message {
+            MSG_SYNC => core1.reply(MSG_DONE),
+            MSG_PRESENT => {
+                let display = unsafe { &mut *(core1.fifo.read_blocking() as *mut Display) };
+                core1.run_queue();
+                for rect in core1.dirty_rects.iter() {
+                    display.mark_dirty(*rect);
+                }
+                core1.dirty_rects.clear();
+                display.flush();
+                frame_arena::end_frame();
+                core1.reply(MSG_DONE);
+            }
+            _ => {}
+        }
+        core1.run_jobs();
+    }
+}
@@ -0,0 +1,108 @@
+//! Recording the buttons held on each frame and playing them back.
+//!
+//! A game that takes its randomness from `Recording::seed` and only looks at
+//! the buttons through `Input` plays out the same way again when the
+//! recording is played back, on the device or in the simulator. That makes
+//! attract mode demos, bug reports that can be replayed and tests of game
+//! logic that don't need anyone to press buttons.
+//!
+//! Each frame, while recording, call `Input::record_frame` before reading
+//! the buttons, and while playing back, call `Input::replay_frame` instead.
+//! Recordings are a few bytes long and can be kept in flash with
+//! `storage::fs().write(name, recording.as_bytes())` and read back with
+//! `Recording::from_bytes`.
+
+/// Buttons held, one bit each in the order of `input::ButtonId`.
+pub type ButtonMask = u8;
+
+const SEED_SIZE: usize = 4;
+
+/// Button masks stored as runs of identical frames, after a four byte seed.
+/// `N` is the capacity in bytes, and each run takes two.
+#[derive(Clone)]
+pub struct Recording<const N: usize> {
+    bytes: heapless::Vec<u8, N>,
+}
+
+impl<const N: usize> Recording<N> {
+    pub fn new(seed: u32) -> Self {
+        let mut bytes = heapless::Vec::new();
+        bytes
+            .extend_from_slice(&seed.to_le_bytes())
+            .expect("recording too small for its seed");
+        Recording { bytes }
+    }
+
+    /// Returns `None` if `bytes` isn't a recording or doesn't fit.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < SEED_SIZE || (bytes.len() - SEED_SIZE) & 1 != 0 {
+            return None;
+        }
+        if bytes[SEED_SIZE..].chunks(2).any(|run| run[1] == 0) {
+            return None;
+        }
+        Some(Recording {
+            bytes: heapless::Vec::from_slice(bytes).ok()?,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The seed to start the game's random numbers from.
+    pub fn seed(&self) -> u32 {
+        u32::from_le_bytes(self.bytes[..SEED_SIZE].try_into().unwrap())
+    }
+
+    /// Adds a frame. Returns false if the recording is full.
+    pub fn push(&mut self, buttons: ButtonMask) -> bool {
+        let len = self.bytes.len();
+        if len > SEED_SIZE && self.bytes[len - 2] == buttons && self.bytes[len - 1] < u8::MAX {
+            self.bytes[len - 1] += 1;
+            return true;
+        }
+        self.bytes.extend_from_slice(&[buttons, 1]).is_ok()
+    }
+
+    pub fn frames(&self) -> usize {
+        self.runs().map(|(_, frames)| frames as usize).sum()
+    }
+
+    pub fn player(&self) -> Player<'_> {
+        Player {
+            runs: &self.bytes[SEED_SIZE..],
+            played: 0,
+        }
+    }
+
+    fn runs(&self) -> impl Iterator<Item = (ButtonMask, u8)> + '_ {
+        self.bytes[SEED_SIZE..]
+            .chunks(2)
+            .map(|run| (run[0], run[1]))
+    }
+}
+
+/// Plays a recording back one frame at a time.
+pub struct Player<'a> {
+    runs: &'a [u8],
+    // Frames played from the first run.
+    played: u8,
+}
+
+impl<'a> Player<'a> {
+    /// Returns the buttons held on the next frame, or `None` at the end.
+    pub fn next_frame(&mut self) -> Option<ButtonMask> {
+        let (&buttons, &frames) = (self.runs.first()?, self.runs.get(1)?);
+        self.played += 1;
+        if self.played == frames {
+            self.runs = &self.runs[2..];
+            self.played = 0;
+        }
+        Some(buttons)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.runs.is_empty()
+    }
+}
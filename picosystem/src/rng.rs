@@ -0,0 +1,190 @@
+//! Random numbers for games.
+//!
+//! `Rng` is a xoshiro128** generator, which only needs 32 bit operations
+//! and so is fast on the Cortex-M0+. On the device, `Rng::from_hardware`
+//! seeds it from the jitter of the ring oscillator, the unique ID of the
+//! flash chip and the timer, so every boot and every console plays
+//! differently. Games that need to play the same way again, for example
+//! when a recording is replayed, seed it with `Rng::new` instead.
+
+use core::ops::Range;
+
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: [u32; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // splitmix64 spreads similar seeds apart and never gives the all
+        // zero state xoshiro can't leave.
+        let mut seed = seed;
+        let mut next = || {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        let (a, b) = (next(), next());
+        Rng {
+            state: [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32],
+        }
+    }
+
+    /// Seeded from `hardware_seed`.
+    #[cfg(any(
+        all(target_arch = "arm", target_os = "none"),
+        all(feature = "simulator", not(target_os = "none"))
+    ))]
+    pub fn from_hardware() -> Self {
+        Self::new(hardware_seed())
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 9;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(11);
+        result
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) << 32 | self.next_u32() as u64
+    }
+
+    /// A number in `0..bound`, or 0 if `bound` is 0.
+    pub fn below(&mut self, bound: u32) -> u32 {
+        // Lemire's method: scale to the bound and reject the few values
+        // that would make the low results more likely.
+        let mut m = self.next_u32() as u64 * bound as u64;
+        if (m as u32) < bound {
+            let threshold = bound.wrapping_neg() % bound;
+            while (m as u32) < threshold {
+                m = self.next_u32() as u64 * bound as u64;
+            }
+        }
+        (m >> 32) as u32
+    }
+
+    /// A number in `range`, which must not be empty.
+    pub fn gen_range<T: RangeInt>(&mut self, range: Range<T>) -> T {
+        let (start, end) = (range.start.to_offset(), range.end.to_offset());
+        assert!(start < end, "empty range");
+        T::from_offset(start.wrapping_add(self.below(end.wrapping_sub(start))))
+    }
+
+    /// True with a probability of `numerator / denominator`.
+    pub fn chance(&mut self, numerator: u32, denominator: u32) -> bool {
+        self.below(denominator) < numerator
+    }
+
+    /// A number in `0.0..1.0`.
+    pub fn gen_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 * (1.0 / (1 << 24) as f32)
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            return None;
+        }
+        Some(&items[self.below(items.len() as u32) as usize])
+    }
+
+    /// Puts `items` in a random order.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i as u32 + 1) as usize);
+        }
+    }
+
+    /// Picks an index with a probability proportional to its weight.
+    /// Returns `None` if the weights add up to 0.
+    pub fn weighted_index(&mut self, weights: &[u32]) -> Option<usize> {
+        self.pick_weighted(weights.iter().copied())
+    }
+
+    /// Picks one of `items`, each paired with its weight.
+    pub fn choose_weighted<'a, T>(&mut self, items: &'a [(T, u32)]) -> Option<&'a T> {
+        let index = self.pick_weighted(items.iter().map(|(_, weight)| *weight))?;
+        Some(&items[index].0)
+    }
+
+    fn pick_weighted(&mut self, weights: impl Iterator<Item = u32> + Clone) -> Option<usize> {
+        let total = weights.clone().fold(0u32, u32::saturating_add);
+        if total == 0 {
+            return None;
+        }
+        let mut target = self.below(total);
+        for (i, weight) in weights.enumerate() {
+            if target < weight {
+                return Some(i);
+            }
+            target -= weight;
+        }
+        None
+    }
+}
+
+/// Integers `Rng::gen_range` works with. They are mapped to `u32` so that
+/// the order is kept, with signed ranges shifted up.
+pub trait RangeInt: Copy {
+    fn to_offset(self) -> u32;
+    fn from_offset(offset: u32) -> Self;
+}
+
+macro_rules! range_int {
+    ($($unsigned:ty),*; $($signed:ty),*) => {
+        $(impl RangeInt for $unsigned {
+            fn to_offset(self) -> u32 {
+                self as u32
+            }
+
+            fn from_offset(offset: u32) -> Self {
+                offset as Self
+            }
+        })*
+        $(impl RangeInt for $signed {
+            fn to_offset(self) -> u32 {
+                (self as i32 as u32) ^ 0x8000_0000
+            }
+
+            fn from_offset(offset: u32) -> Self {
+                (offset ^ 0x8000_0000) as i32 as Self
+            }
+        })*
+    };
+}
+
+range_int!(u8, u16, u32, usize; i8, i16, i32);
+
+/// Entropy from the hardware: 64 samples of the ring oscillator's random
+/// bit, which wanders with temperature and voltage, mixed with the flash
+/// ID and the time since boot.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub fn hardware_seed() -> u64 {
+    use rp_pico::hal::pac;
+
+    let rosc = unsafe { &*pac::ROSC::PTR };
+    let mut bits = 0u64;
+    for _ in 0..64 {
+        // A few cycles between samples let the bits decorrelate.
+        cortex_m::asm::delay(32);
+        bits = bits << 1 | rosc.randombit.read().randombit().bit() as u64;
+    }
+    bits ^ crate::storage::flash_unique_id().rotate_left(17) ^ crate::time::time_us64()
+}
+
+/// Entropy from the system clock and the process ID.
+#[cfg(all(feature = "simulator", not(target_os = "none")))]
+pub fn hardware_seed() -> u64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_nanos() as u64 ^ (std::process::id() as u64).rotate_left(32)
+}
@@ -0,0 +1,85 @@
+//! Division and float math through the RP2040 hardware divider and the boot
+//! ROM's float library.
+//!
+//! 32-bit `/` and `%` and the basic f32 operators already go to the
+//! hardware divider and the ROM through the HAL's compiler intrinsics. The
+//! divider routines save and restore its state when they interrupt another
+//! division, so dividing in interrupt handlers is safe. This module adds
+//! what the intrinsics leave out: a quotient and remainder from one
+//! division, and the ROM's transcendental functions, which are faster and
+//! more accurate than the `micromath` approximations.
+
+use core::f32::consts::TAU;
+use rp_pico::hal::rom_data::{self, float_funcs};
+
+extern "aapcs" {
+    // Both return the quotient in the low word and the remainder in the high
+    // word.
+    fn __aeabi_uidivmod(n: u32, d: u32) -> u64;
+    fn __aeabi_idivmod(n: i32, d: i32) -> u64;
+}
+
+/// Returns `(n / d, n % d)` from a single hardware division.
+pub fn div_rem_u32(n: u32, d: u32) -> (u32, u32) {
+    assert!(d != 0, "division by zero");
+    let packed = unsafe { __aeabi_uidivmod(n, d) };
+    (packed as u32, (packed >> 32) as u32)
+}
+
+/// Returns `(n / d, n % d)` from a single hardware division.
+pub fn div_rem_i32(n: i32, d: i32) -> (i32, i32) {
+    assert!(d != 0, "division by zero");
+    let packed = unsafe { __aeabi_idivmod(n, d) };
+    (packed as u32 as i32, (packed >> 32) as u32 as i32)
+}
+
+// The ROM's trig functions take angles from -1024 to 1024 radians, but lose
+// precision well before that, so larger angles are reduced first.
+fn to_trig_range(angle: f32) -> f32 {
+    if angle.abs() < 128.0 {
+        angle
+    } else {
+        angle % TAU
+    }
+}
+
+pub fn sin(angle: f32) -> f32 {
+    float_funcs::fsin(to_trig_range(angle))
+}
+
+pub fn cos(angle: f32) -> f32 {
+    float_funcs::fcos(to_trig_range(angle))
+}
+
+/// Returns `(sin(angle), cos(angle))`.
+pub fn sin_cos(angle: f32) -> (f32, f32) {
+    let angle = to_trig_range(angle);
+    (float_funcs::fsin(angle), float_funcs::fcos(angle))
+}
+
+pub fn tan(angle: f32) -> f32 {
+    float_funcs::ftan(to_trig_range(angle))
+}
+
+/// The angle of (x, y) in radians. The ROM's atan2 needs the second boot ROM
+/// version, so older chips fall back to `micromath`.
+pub fn atan2(y: f32, x: f32) -> f32 {
+    if rom_data::rom_version_number() >= 2 {
+        float_funcs::fatan2(y, x)
+    } else {
+        micromath::F32Ext::atan2(y, x)
+    }
+}
+
+pub fn sqrt(v: f32) -> f32 {
+    float_funcs::fsqrt(v)
+}
+
+pub fn exp(v: f32) -> f32 {
+    float_funcs::fexp(v)
+}
+
+/// Natural logarithm, or -infinity for `v <= 0`.
+pub fn ln(v: f32) -> f32 {
+    float_funcs::fln(v)
+}
@@ -0,0 +1,145 @@
+//! The defmt logger of the `defmt` feature. Messages are written to an RTT
+//! channel, a ring buffer in RAM that a debug probe reads, for example with
+//! `probe-rs run`, while the game goes on.
+//!
+//! Without a probe reading it, the buffer fills and further messages are
+//! cut short. A probe can ask for writes to wait for it instead.
+
+use crate::time;
+use core::cell::UnsafeCell;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use critical_section::RestoreState;
+
+const BUFFER_SIZE: usize = 1024;
+
+// What the probe sets in the flags of the channel.
+const MODE_MASK: usize = 0b11;
+const MODE_BLOCK_IF_FULL: usize = 0b10;
+const MODE_NO_BLOCK_TRIM: usize = 0b01;
+
+/// An up channel, as probes expect to find it.
+#[repr(C)]
+struct Channel {
+    name: *const u8,
+    buffer: *mut u8,
+    size: usize,
+    write: AtomicUsize,
+    read: AtomicUsize,
+    flags: AtomicUsize,
+}
+
+/// The control block, which probes find by its id.
+#[repr(C)]
+struct ControlBlock {
+    id: [u8; 16],
+    max_up_buffers: usize,
+    max_down_buffers: usize,
+    up: Channel,
+}
+
+unsafe impl Sync for ControlBlock {}
+
+static mut BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+#[no_mangle]
+static _SEGGER_RTT: ControlBlock = ControlBlock {
+    id: *b"SEGGER RTT\0\0\0\0\0\0",
+    max_up_buffers: 1,
+    max_down_buffers: 0,
+    up: Channel {
+        name: c"defmt".as_ptr(),
+        buffer: addr_of_mut!(BUFFER) as *mut u8,
+        size: BUFFER_SIZE,
+        write: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+        flags: AtomicUsize::new(MODE_NO_BLOCK_TRIM),
+    },
+};
+
+impl Channel {
+    fn write_all(&self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let written = self.write(bytes);
+            if written == 0 && self.flags.load(Ordering::Relaxed) & MODE_MASK != MODE_BLOCK_IF_FULL
+            {
+                return;
+            }
+            bytes = &bytes[written..];
+        }
+    }
+
+    // Writes what fits before the end of the buffer, returning how much.
+    fn write(&self, bytes: &[u8]) -> usize {
+        let read = self.read.load(Ordering::Acquire);
+        let write = self.write.load(Ordering::Relaxed);
+        // A byte is left free, to tell a full buffer from an empty one.
+        let free = if read > write {
+            read - write - 1
+        } else if read == 0 {
+            BUFFER_SIZE - write - 1
+        } else {
+            BUFFER_SIZE - write
+        };
+        let len = bytes.len().min(free);
+        unsafe {
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.buffer.add(write), len);
+        }
+        self.write
+            .store((write + len) % BUFFER_SIZE, Ordering::Release);
+        len
+    }
+}
+
+#[defmt::global_logger]
+struct RttLogger;
+
+// Only used between `acquire` and `release`, inside the critical section.
+struct State {
+    restore: RestoreState,
+    encoder: defmt::Encoder,
+}
+
+struct Shared(UnsafeCell<State>);
+
+unsafe impl Sync for Shared {}
+
+static STATE: Shared = Shared(UnsafeCell::new(State {
+    restore: RestoreState::invalid(),
+    encoder: defmt::Encoder::new(),
+}));
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+fn write_frame(bytes: &[u8]) {
+    _SEGGER_RTT.up.write_all(bytes);
+}
+
+unsafe impl defmt::Logger for RttLogger {
+    fn acquire() {
+        // Shared by both cores and interrupts, so a message is never split.
+        let restore = unsafe { critical_section::acquire() };
+        if TAKEN.load(Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly");
+        }
+        TAKEN.store(true, Ordering::Relaxed);
+        let state = unsafe { &mut *STATE.0.get() };
+        state.restore = restore;
+        state.encoder.start_frame(write_frame);
+    }
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        let state = &mut *STATE.0.get();
+        state.encoder.end_frame(write_frame);
+        TAKEN.store(false, Ordering::Relaxed);
+        critical_section::release(state.restore);
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        (*STATE.0.get()).encoder.write(bytes, write_frame);
+    }
+}
+
+defmt::timestamp!("{=u64:us}", time::time_us64());
@@ -0,0 +1,238 @@
+//! A stack of scenes, such as the title screen, the game and a pause menu
+//! over it.
+//!
+//! A game defines its scenes as one type, usually an enum, implementing
+//! `Scene`. Only the top scene of a `SceneStack` is updated, unless it lets
+//! the ones below carry on, and scenes that don't cover the whole screen
+//! are drawn over the ones below. Updates return a `Transition` to push a
+//! scene, such as the pause menu, or to replace or remove the scene.
+//!
+//! Scenes read the buttons from the `SceneInput` they are given, top scene
+//! first, so an overlay that uses the buttons can `consume` them and the
+//! game below doesn't move while its menu is open.
+//!
+//! `run_scenes` runs the stack with a `Scheduler`, like `hardware::run`.
+
+use crate::display::Display;
+use crate::hardware::Hardware;
+use crate::input::ButtonId;
+use crate::replay::ButtonMask;
+use crate::scheduler::{FrameContext, FrameRate, Scheduler};
+
+/// What to do with the scene that returned it after its update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition<T> {
+    Stay,
+    /// Puts a scene on top of the stack.
+    Push(T),
+    /// Removes the scene and any above it.
+    Pop,
+    /// Swaps the scene for another, removing any above it.
+    Replace(T),
+}
+
+/// The buttons as a scene sees them.
+#[derive(Debug, Clone, Copy)]
+pub struct SceneInput {
+    held: ButtonMask,
+    pressed: ButtonMask,
+}
+
+impl SceneInput {
+    pub fn held(&self) -> ButtonMask {
+        self.held
+    }
+
+    /// The buttons held now that weren't on the previous update.
+    pub fn pressed(&self) -> ButtonMask {
+        self.pressed
+    }
+
+    pub fn is_held(&self, button: ButtonId) -> bool {
+        self.held & button.mask() != 0
+    }
+
+    pub fn is_pressed(&self, button: ButtonId) -> bool {
+        self.pressed & button.mask() != 0
+    }
+
+    /// Hides the buttons from the scenes below.
+    pub fn consume(&mut self) {
+        self.held = 0;
+        self.pressed = 0;
+    }
+}
+
+/// A scene of a game with state `S`, which all scenes share.
+pub trait Scene<S>: Sized {
+    fn on_enter(&mut self, _state: &mut S) {}
+
+    fn on_exit(&mut self, _state: &mut S) {}
+
+    fn update(
+        &mut self,
+        state: &mut S,
+        hw: &mut Hardware,
+        input: &mut SceneInput,
+        context: &FrameContext,
+    ) -> Transition<Self>;
+
+    fn draw(&self, state: &S, display: &mut Display, context: &FrameContext);
+
+    /// Whether the scene covers the whole screen, so the scenes below
+    /// needn't be drawn.
+    fn is_opaque(&self) -> bool {
+        true
+    }
+
+    /// Whether the scenes below are paused while this one is above them.
+    fn pauses_below(&self) -> bool {
+        true
+    }
+}
+
+/// Up to `N` scenes, the last one on top.
+pub struct SceneStack<T, const N: usize> {
+    scenes: heapless::Vec<T, N>,
+    previous_held: ButtonMask,
+}
+
+impl<T, const N: usize> SceneStack<T, N> {
+    pub const fn new() -> Self {
+        SceneStack {
+            scenes: heapless::Vec::new(),
+            previous_held: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scenes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scenes.is_empty()
+    }
+
+    pub fn top(&self) -> Option<&T> {
+        self.scenes.last()
+    }
+
+    pub fn top_mut(&mut self) -> Option<&mut T> {
+        self.scenes.last_mut()
+    }
+
+    /// Enters `scene` on top of the others. Returns false, leaving it out,
+    /// if the stack is full.
+    pub fn push<S>(&mut self, mut scene: T, state: &mut S) -> bool
+    where
+        T: Scene<S>,
+    {
+        if self.scenes.is_full() {
+            return false;
+        }
+        scene.on_enter(state);
+        let _ = self.scenes.push(scene);
+        true
+    }
+
+    /// Exits the top scene and returns it.
+    pub fn pop<S>(&mut self, state: &mut S) -> Option<T>
+    where
+        T: Scene<S>,
+    {
+        let mut scene = self.scenes.pop()?;
+        scene.on_exit(state);
+        Some(scene)
+    }
+
+    /// Exits the scenes above `len`.
+    pub fn truncate<S>(&mut self, len: usize, state: &mut S)
+    where
+        T: Scene<S>,
+    {
+        while self.scenes.len() > len {
+            self.pop(state);
+        }
+    }
+
+    /// Updates the scenes from the top down to the first one that pauses
+    /// the rest, making the transition of each as it goes.
+    pub fn update<S>(&mut self, state: &mut S, hw: &mut Hardware, context: &FrameContext)
+    where
+        T: Scene<S>,
+    {
+        let held = hw.input.held_buttons();
+        let mut input = SceneInput {
+            held,
+            pressed: held & !self.previous_held,
+        };
+        self.previous_held = held;
+
+        let mut index = self.scenes.len();
+        while index > 0 {
+            index -= 1;
+            let transition = self.scenes[index].update(state, hw, &mut input, context);
+            let pauses_below = self.scenes[index].pauses_below();
+            let stay = matches!(transition, Transition::Stay);
+            match transition {
+                Transition::Stay => {}
+                Transition::Push(scene) => {
+                    self.push(scene, state);
+                }
+                Transition::Pop => {
+                    self.truncate(index, state);
+                }
+                Transition::Replace(scene) => {
+                    self.truncate(index, state);
+                    self.push(scene, state);
+                }
+            }
+            // The scenes changed, so the ones below wait for the next
+            // update.
+            if pauses_below || !stay {
+                break;
+            }
+        }
+    }
+
+    /// Draws the scenes from the highest opaque one up.
+    pub fn draw<S>(&self, state: &S, display: &mut Display, context: &FrameContext)
+    where
+        T: Scene<S>,
+    {
+        let first = self
+            .scenes
+            .iter()
+            .rposition(|scene| scene.is_opaque())
+            .unwrap_or(0);
+        for scene in &self.scenes[first..] {
+            scene.draw(state, display, context);
+        }
+    }
+}
+
+impl<T, const N: usize> Default for SceneStack<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs the game loop forever with `scenes`, which should have a scene
+/// pushed. Updates run at the fixed `rate`.
+pub fn run_scenes<T: Scene<S>, S, const N: usize>(
+    hw: &mut Hardware,
+    rate: FrameRate,
+    scenes: SceneStack<T, N>,
+    state: S,
+) -> ! {
+    let mut scheduler = Scheduler::new(rate);
+    let mut game = (scenes, state);
+    loop {
+        scheduler.run_frame(
+            hw,
+            &mut game,
+            &mut |(scenes, state), hw, context| scenes.update(state, hw, context),
+            &mut |(scenes, state), display, context| scenes.draw(state, display, context),
+        );
+    }
+}
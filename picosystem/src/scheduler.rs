@@ -0,0 +1,141 @@
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::debug_overlay;
+use crate::display::Display;
+use crate::fps_monitor::FpsMonitor;
+use crate::hardware::Hardware;
+use crate::time;
+
+// Updates run per frame at most, so a long stall doesn't snowball.
+const MAX_UPDATES_PER_FRAME: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameRate {
+    Fps30,
+    Fps40,
+    Fps50,
+    Fps60,
+}
+
+impl FrameRate {
+    pub fn fps(self) -> u32 {
+        match self {
+            FrameRate::Fps30 => 30,
+            FrameRate::Fps40 => 40,
+            FrameRate::Fps50 => 50,
+            FrameRate::Fps60 => 60,
+        }
+    }
+
+    pub fn frame_time_us(self) -> u32 {
+        1_000_000 / self.fps()
+    }
+}
+
+/// Timing of the current frame, passed to update and render.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameContext {
+    /// Rendered frames so far.
+    pub frame: u32,
+    /// Updates run so far.
+    pub update: u32,
+    /// Time between the starts of this frame and the previous one.
+    pub delta_us: u32,
+    /// The fixed time step of every update.
+    pub fixed_delta_us: u32,
+    /// How far render is between the last update and the next, from 0 to 1,
+    /// for interpolating movement.
+    pub alpha: f32,
+    pub time_us: u64,
+    /// Frames rendered over the last second.
+    pub fps: u32,
+}
+
+/// Runs update at a fixed rate and render once per frame, waiting for the
+/// target frame time before each render.
+pub struct Scheduler {
+    rate: FrameRate,
+    fps_monitor: FpsMonitor,
+    frame: u32,
+    update: u32,
+    last_frame_us: u32,
+    next_frame_us: u32,
+    accumulator_us: u32,
+}
+
+impl Scheduler {
+    pub fn new(rate: FrameRate) -> Self {
+        let now = time::time_us();
+        Scheduler {
+            rate,
+            fps_monitor: FpsMonitor::new(),
+            frame: 0,
+            update: 0,
+            last_frame_us: now,
+            next_frame_us: now,
+            // The first frame gets one update.
+            accumulator_us: rate.frame_time_us(),
+        }
+    }
+
+    pub fn rate(&self) -> FrameRate {
+        self.rate
+    }
+
+    pub fn set_rate(&mut self, rate: FrameRate) {
+        self.rate = rate;
+    }
+
+    /// Runs the updates due since the last frame, then renders one frame.
+    pub fn run_frame<S>(
+        &mut self,
+        hw: &mut Hardware,
+        state: &mut S,
+        update: &mut impl FnMut(&mut S, &mut Hardware, &FrameContext),
+        render: &mut impl FnMut(&S, &mut Display, &FrameContext),
+    ) {
+        let frame_time_us = self.rate.frame_time_us();
+        while (time::time_us().wrapping_sub(self.next_frame_us) as i32) < 0 {}
+
+        let now = time::time_us();
+        let delta_us = now.wrapping_sub(self.last_frame_us);
+        self.last_frame_us = now;
+        // Missed deadlines are dropped rather than made up for.
+        self.next_frame_us = if (now.wrapping_sub(self.next_frame_us) as i32) > frame_time_us as i32
+        {
+            now.wrapping_add(frame_time_us)
+        } else {
+            self.next_frame_us.wrapping_add(frame_time_us)
+        };
+
+        let mut context = FrameContext {
+            frame: self.frame,
+            update: self.update,
+            delta_us,
+            fixed_delta_us: frame_time_us,
+            alpha: 0.0,
+            time_us: time::time_us64(),
+            fps: self.fps_monitor.fps(),
+        };
+
+        self.accumulator_us =
+            (self.accumulator_us + delta_us).min(frame_time_us * MAX_UPDATES_PER_FRAME);
+        #[cfg(all(target_arch = "arm", target_os = "none"))]
+        let update_start = time::time_us();
+        while self.accumulator_us >= frame_time_us {
+            update(state, hw, &context);
+            self.accumulator_us -= frame_time_us;
+            self.update += 1;
+            context.update = self.update;
+        }
+        #[cfg(all(target_arch = "arm", target_os = "none"))]
+        debug_overlay::record_phase(
+            debug_overlay::Phase::Update,
+            time::time_us().wrapping_sub(update_start),
+        );
+
+        context.alpha = self.accumulator_us as f32 / frame_time_us as f32;
+        hw.draw(|display| render(state, display, &context));
+        self.fps_monitor.update();
+        self.frame += 1;
+    }
+}
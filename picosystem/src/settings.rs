@@ -0,0 +1,163 @@
+//! Options that belong to the console rather than to a game.
+//!
+//! `Hardware::new` loads the settings from flash and applies them, and
+//! `Hardware::settings` holds them for games to read, for example to greet
+//! the player by name, and sets the buttons and the way up of the screen
+//! from them. A settings screen changes them, applies them with
+//! `Settings::apply` and keeps them with `Settings::save`. The simulator
+//! always starts from the defaults.
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::audio::{Audio, Bus};
+use crate::button_map::{ButtonMap, Handedness, NUM_ACTIONS};
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::display::{Display, Orientation};
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::input::Input;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::logging;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::storage::{self, FsError};
+
+pub const MAX_PLAYER_NAME_LEN: usize = 16;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+const FILE_NAME: &str = "settings";
+const VERSION: u8 = 3;
+// Version, brightness, the master, sound effect and music volumes, the
+// handedness, the button of each action and the length of the name.
+// Version 2 had no handedness or buttons, and version 1 only the master
+// volume.
+const HEADER_LEN: usize = 7 + NUM_ACTIONS;
+const HEADER_LEN_V2: usize = 6;
+const HEADER_LEN_V1: usize = 4;
+const MAX_LEN: usize = HEADER_LEN + MAX_PLAYER_NAME_LEN;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// Backlight level, from 0 for off to 255.
+    pub brightness: u8,
+    /// Master volume, from 0 for silence to 255.
+    pub volume: u8,
+    pub sfx_volume: u8,
+    pub music_volume: u8,
+    pub player_name: heapless::String<MAX_PLAYER_NAME_LEN>,
+    pub handedness: Handedness,
+    /// The buttons chosen for the console held upright. `button_map` turns
+    /// them for the handedness.
+    pub buttons: ButtonMap,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            brightness: 255,
+            volume: 255,
+            sfx_volume: 255,
+            music_volume: 255,
+            player_name: heapless::String::new(),
+            handedness: Handedness::Right,
+            buttons: ButtonMap::new(),
+        }
+    }
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+impl Settings {
+    /// Reads the settings from flash, or returns the defaults if they were
+    /// never saved or can't be read.
+    pub fn load() -> Self {
+        let mut bytes = [0; MAX_LEN];
+        match storage::fs().read(FILE_NAME, &mut bytes) {
+            Ok(len) => Self::from_bytes(&bytes[..len]).unwrap_or_else(|| {
+                logging::warn!("Ignoring unreadable settings");
+                Self::default()
+            }),
+            Err(FsError::NotFound) => Self::default(),
+            Err(e) => {
+                logging::warn!("Failed to read settings: {:?}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), FsError> {
+        storage::fs().write(FILE_NAME, &self.to_bytes())
+    }
+
+    /// Sets the backlight, the volumes, the way up of the screen and the
+    /// buttons.
+    pub fn apply(&self, display: &mut Display, audio: &mut Audio, input: &mut Input) {
+        display.set_backlight_level(self.brightness);
+        audio.set_volume(Bus::Master, self.volume);
+        audio.set_volume(Bus::Sfx, self.sfx_volume);
+        audio.set_volume(Bus::Music, self.music_volume);
+        display.set_orientation(match self.handedness {
+            Handedness::Right => Orientation::Portrait,
+            Handedness::Left => Orientation::Flipped,
+        });
+        input.set_button_map(self.button_map());
+    }
+}
+
+impl Settings {
+    /// The buttons for the way the console is held.
+    pub fn button_map(&self) -> ButtonMap {
+        self.buttons.rotated(self.handedness.quarter_turns())
+    }
+
+    pub fn to_bytes(&self) -> heapless::Vec<u8, MAX_LEN> {
+        let name = self.player_name.as_bytes();
+        let mut bytes = heapless::Vec::new();
+        let _ = bytes.extend_from_slice(&[
+            VERSION,
+            self.brightness,
+            self.volume,
+            self.sfx_volume,
+            self.music_volume,
+            self.handedness as u8,
+        ]);
+        let _ = bytes.extend_from_slice(&self.buttons.to_bytes());
+        let _ = bytes.push(name.len() as u8);
+        let _ = bytes.extend_from_slice(name);
+        bytes
+    }
+
+    /// Returns `None` if `bytes` weren't made by `to_bytes`. Settings saved
+    /// by older versions get the default for what they didn't have.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (header_len, brightness, volume, sfx_volume, music_volume) = match *bytes {
+            [1, brightness, volume, ..] => (HEADER_LEN_V1, brightness, volume, 255, 255),
+            [2, brightness, volume, sfx_volume, music_volume, ..] => {
+                (HEADER_LEN_V2, brightness, volume, sfx_volume, music_volume)
+            }
+            [VERSION, brightness, volume, sfx_volume, music_volume, ..] => {
+                (HEADER_LEN, brightness, volume, sfx_volume, music_volume)
+            }
+            _ => return None,
+        };
+        let (handedness, buttons) = match bytes[0] {
+            VERSION => {
+                let handedness = match *bytes.get(5)? {
+                    0 => Handedness::Right,
+                    1 => Handedness::Left,
+                    _ => return None,
+                };
+                let buttons = ButtonMap::from_bytes(bytes.get(6..6 + NUM_ACTIONS)?)?;
+                (handedness, buttons)
+            }
+            _ => (Handedness::Right, ButtonMap::new()),
+        };
+        let name_len = *bytes.get(header_len - 1)? as usize;
+        let name = bytes.get(header_len..header_len + name_len)?;
+        Some(Settings {
+            brightness,
+            volume,
+            sfx_volume,
+            music_volume,
+            player_name: core::str::from_utf8(name).ok()?.into(),
+            handedness,
+            buttons,
+        })
+    }
+}
@@ -0,0 +1,16 @@
+//! Running games on a desktop.
+//!
+//! With the `simulator` feature on a host target, `display`, `input`,
+//! `time`, `audio`, `led`, `watchdog` and `hardware` come from here instead
+//! of the device drivers. The screen is shown in a window at twice its size, and the
+//! buttons are read from the keyboard: the arrow keys are the d-pad, and
+//! Z, X, A and S are the A, B, X and Y buttons. Closing the window or
+//! pressing Escape exits.
+
+pub mod audio;
+pub mod display;
+pub mod hardware;
+pub mod input;
+pub mod led;
+pub mod time;
+pub mod watchdog;
@@ -0,0 +1,85 @@
+//! Keeps games that play tones building. Nothing is heard, a tone only
+//! keeps its channel playing until it is stopped.
+
+pub const NUM_CHANNELS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Master,
+    Sfx,
+    Music,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Noise,
+}
+
+pub struct Audio {
+    playing: [bool; NUM_CHANNELS],
+    master_volume: u8,
+    sfx_volume: u8,
+    music_volume: u8,
+}
+
+#[allow(clippy::new_without_default)]
+impl Audio {
+    pub fn new() -> Self {
+        Audio {
+            playing: [false; NUM_CHANNELS],
+            master_volume: 255,
+            sfx_volume: 255,
+            music_volume: 255,
+        }
+    }
+
+    pub fn play_tone(&mut self, channel: usize, waveform: Waveform, freq: u32, volume: u8) {
+        log::trace!("Tone {:?} {} Hz {} on {}", waveform, freq, volume, channel);
+        self.playing[channel] = true;
+    }
+
+    pub fn start_tone(&mut self, freq: u32) {
+        self.play_tone(0, Waveform::Square, freq, 255);
+    }
+
+    pub fn is_playing(&self, channel: usize) -> bool {
+        self.playing[channel]
+    }
+
+    pub fn stop_channel(&mut self, channel: usize) {
+        self.playing[channel] = false;
+    }
+
+    pub fn set_volume(&mut self, bus: Bus, volume: u8) {
+        match bus {
+            Bus::Master => self.master_volume = volume,
+            Bus::Sfx => self.sfx_volume = volume,
+            Bus::Music => self.music_volume = volume,
+        }
+    }
+
+    pub fn volume(&self, bus: Bus) -> u8 {
+        match bus {
+            Bus::Master => self.master_volume,
+            Bus::Sfx => self.sfx_volume,
+            Bus::Music => self.music_volume,
+        }
+    }
+
+    pub fn set_master_volume(&mut self, volume: u8) {
+        self.set_volume(Bus::Master, volume);
+    }
+
+    pub fn master_volume(&self) -> u8 {
+        self.volume(Bus::Master)
+    }
+
+    pub fn set_ducking(&mut self, _volume: u8) {}
+
+    pub fn stop(&mut self) {
+        self.playing = [false; NUM_CHANNELS];
+    }
+}
@@ -0,0 +1,210 @@
+use crate::dirty_rects::DirtyRects;
+use crate::simulator::input;
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use minifb::{Key, Scale, Window, WindowOptions};
+use std::ptr::addr_of_mut;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::vec;
+use std::vec::Vec;
+
+pub const WIDTH: usize = 240;
+pub const HEIGHT: usize = 240;
+
+const FRAME_RATE: usize = 60;
+
+static mut FRAMEBUFFER: [u16; WIDTH * HEIGHT] = [0; WIDTH * HEIGHT];
+
+static SCREEN_HEIGHT: AtomicUsize = AtomicUsize::new(HEIGHT);
+
+/// How many rows of the framebuffer are drawn and shown: `HEIGHT`, or the
+/// height of the letterbox set with `Display::set_letterbox`.
+pub fn screen_height() -> usize {
+    SCREEN_HEIGHT.load(Ordering::Relaxed)
+}
+
+/// The area of the framebuffer that is shown.
+pub fn screen() -> Rectangle {
+    Rectangle::new(
+        Point::zero(),
+        Size::new(WIDTH as u32, screen_height() as u32),
+    )
+}
+
+/// Pixels are stored big endian, as on the device, so code that writes
+/// to the framebuffer directly draws the same picture.
+pub fn framebuffer() -> &'static mut [u16; WIDTH * HEIGHT] {
+    unsafe { &mut *addr_of_mut!(FRAMEBUFFER) }
+}
+
+pub struct Display {
+    window: Window,
+    pixels: Vec<u32>,
+    dirty_rects: DirtyRects,
+}
+
+#[allow(clippy::new_without_default)]
+impl Display {
+    pub fn new() -> Self {
+        let options = WindowOptions {
+            scale: Scale::X2,
+            ..WindowOptions::default()
+        };
+        let mut window = Window::new("PicoSystem", WIDTH, HEIGHT, options)
+            .unwrap_or_else(|e| panic!("Failed to open the window: {}", e));
+        window.set_target_fps(FRAME_RATE);
+        Display {
+            window,
+            pixels: vec![0; WIDTH * HEIGHT],
+            dirty_rects: DirtyRects::new(),
+        }
+    }
+
+    /// Has no effect, the whole window is redrawn every frame.
+    pub fn set_partial_flush(&mut self, _enabled: bool) {}
+
+    /// Records a region as modified. Needed after writing to `framebuffer()` directly.
+    pub fn mark_dirty(&mut self, area: Rectangle) {
+        self.dirty_rects.add(area);
+    }
+
+    pub fn dirty_rects(&self) -> &DirtyRects {
+        &self.dirty_rects
+    }
+
+    /// The height of the letterbox, if there is one.
+    pub fn letterbox(&self) -> Option<usize> {
+        Some(screen_height()).filter(|&height| height < HEIGHT)
+    }
+
+    /// Shrinks the screen to `height` rows in the middle of the window,
+    /// with black bars above and below, or gives it back all of it with
+    /// `None`. The framebuffer is cleared.
+    pub fn set_letterbox(&mut self, height: Option<usize>) {
+        let height = height.unwrap_or(HEIGHT).clamp(1, HEIGHT);
+        SCREEN_HEIGHT.store(height, Ordering::Relaxed);
+        self.pixels.fill(0);
+        let _ = self.clear(Rgb565::BLACK);
+    }
+
+    /// Shows the framebuffer in the window and reads the keyboard, waiting
+    /// as long as needed to keep to 60 frames per second. Exits
+    /// the process when the window has been closed.
+    pub fn flush(&mut self) {
+        let height = screen_height();
+        let top = (HEIGHT - height) / 2 * WIDTH;
+        let shown = &mut self.pixels[top..top + height * WIDTH];
+        for (pixel, &raw) in shown.iter_mut().zip(framebuffer().iter()) {
+            let color = Rgb565::from(RawU16::new(u16::from_be(raw)));
+            let [r, g, b] = [
+                color.r() as u32 * 255 / Rgb565::MAX_R as u32,
+                color.g() as u32 * 255 / Rgb565::MAX_G as u32,
+                color.b() as u32 * 255 / Rgb565::MAX_B as u32,
+            ];
+            *pixel = r << 16 | g << 8 | b;
+        }
+        self.dirty_rects.clear();
+        if self
+            .window
+            .update_with_buffer(&self.pixels, WIDTH, HEIGHT)
+            .is_err()
+            || !self.window.is_open()
+            || self.window.is_key_down(Key::Escape)
+        {
+            std::process::exit(0);
+        }
+        input::update_keys(&self.window);
+    }
+
+    pub fn flush_blocking(&mut self) {
+        self.flush();
+    }
+
+    pub fn draw(&mut self, func: impl FnOnce(&mut Self)) {
+        func(self);
+        self.flush();
+    }
+
+    /// The window keeps to the frame rate in `flush` instead.
+    pub fn wait_for_vsync(&mut self) {}
+
+    /// Flushes complete before `flush` returns.
+    pub fn is_flush_done(&self) -> bool {
+        true
+    }
+
+    pub fn flush_progress(&self) -> usize {
+        WIDTH * HEIGHT
+    }
+
+    /// Fills the framebuffer with `color`, like `clear`.
+    pub fn clear_fast(&mut self, color: Rgb565) {
+        let _ = self.clear(color);
+    }
+
+    /// Fills `area` with `color`, like `fill_solid`.
+    pub fn fill_rect_fast(&mut self, area: Rectangle, color: Rgb565) {
+        let _ = self.fill_solid(&area, color);
+    }
+}
+
+impl DrawTarget for Display {
+    type Color = Rgb565;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        const M: u32 = WIDTH as u32 - 1;
+        let n = screen_height() as u32 - 1;
+        let fb = framebuffer();
+        let mut bounds: Option<(Point, Point)> = None;
+        for Pixel(coord, color) in pixels.into_iter() {
+            if let Ok((x @ 0..=M, y)) = coord.try_into() {
+                if y > n {
+                    continue;
+                }
+                let index: u32 = x + y * WIDTH as u32;
+                fb[index as usize] = RawU16::from(color).into_inner().to_be();
+                bounds = Some(match bounds {
+                    Some((min, max)) => (min.component_min(coord), max.component_max(coord)),
+                    None => (coord, coord),
+                });
+            }
+        }
+        if let Some((min, max)) = bounds {
+            self.mark_dirty(Rectangle::with_corners(min, max));
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        let Some(bottom_right) = area.bottom_right() else {
+            return Ok(());
+        };
+        self.mark_dirty(area);
+        let color = RawU16::from(color).into_inner().to_be();
+        let fb = framebuffer();
+        for y in area.top_left.y..=bottom_right.y {
+            let row = y as usize * WIDTH;
+            fb[row + area.top_left.x as usize..=row + bottom_right.x as usize].fill(color);
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.mark_dirty(self.bounding_box());
+        framebuffer().fill(RawU16::from(color).into_inner().to_be());
+        Ok(())
+    }
+}
+
+impl OriginDimensions for Display {
+    fn size(&self) -> Size {
+        screen().size
+    }
+}
@@ -0,0 +1,106 @@
+use crate::scheduler;
+use crate::settings::Settings;
+use crate::simulator::display::Display;
+use crate::simulator::{audio, input, led, watchdog};
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use std::time::Duration;
+
+/// Stands in for `cortex_m::delay::Delay`.
+pub struct Delay;
+
+impl Delay {
+    pub fn delay_ms(&mut self, ms: u32) {
+        std::thread::sleep(Duration::from_millis(ms as u64));
+    }
+
+    pub fn delay_us(&mut self, us: u32) {
+        std::thread::sleep(Duration::from_micros(us as u64));
+    }
+}
+
+impl DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        Delay::delay_ms(self, ms);
+    }
+}
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        Delay::delay_us(self, us);
+    }
+}
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        std::eprintln!("{} {}", record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+pub struct Hardware {
+    pub display: Display,
+    pub delay: Delay,
+    pub input: input::Input,
+    pub audio: audio::Audio,
+    pub led: led::Led,
+    pub watchdog: watchdog::Watchdog,
+    /// Always the defaults, the simulator doesn't keep settings.
+    pub settings: Settings,
+}
+
+/// A made up ID, the same for every run.
+pub fn device_id() -> u64 {
+    0x5349_4d55_4c41_544f
+}
+
+/// There is no bootloader to go to, so this quits.
+pub fn reboot_to_bootsel() -> ! {
+    log::info!("Reboot to the bootloader, quitting");
+    std::process::exit(0);
+}
+
+impl Hardware {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        if log::set_logger(&StderrLogger).is_ok() {
+            log::set_max_level(log::LevelFilter::Info);
+        }
+        log::info!("Logging initialized");
+        Hardware {
+            display: Display::new(),
+            delay: Delay,
+            input: input::Input::new(),
+            audio: audio::Audio::new(),
+            led: led::Led::new(),
+            watchdog: watchdog::Watchdog,
+            settings: Settings::default(),
+        }
+    }
+
+    pub fn draw(&mut self, func: impl FnOnce(&mut Display)) {
+        self.input.check_reset();
+        self.display.draw(func);
+    }
+}
+
+/// Runs the game loop forever. `update` runs at the fixed `rate` and may
+/// change `state`, `render` draws it once per frame.
+pub fn run<S>(
+    hw: &mut Hardware,
+    rate: scheduler::FrameRate,
+    mut state: S,
+    mut update: impl FnMut(&mut S, &mut Hardware, &scheduler::FrameContext),
+    mut render: impl FnMut(&S, &mut Display, &scheduler::FrameContext),
+) -> ! {
+    let mut scheduler = scheduler::Scheduler::new(rate);
+    loop {
+        scheduler.run_frame(hw, &mut state, &mut update, &mut render);
+    }
+}
@@ -0,0 +1,450 @@
+//! The buttons, read from the keyboard each time the display is flushed.
+
+pub use crate::button_map::{Action, ButtonMap};
+pub use crate::combo::{Combo, ComboDetector};
+use crate::press::PressTracker;
+use crate::replay::{ButtonMask, Player, Recording};
+use crate::simulator::time::{self, Duration};
+pub use crate::virtual_stick::VirtualStick;
+use minifb::{Key, Window};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU16, AtomicU8, Ordering};
+use std::sync::Mutex;
+
+const DEBOUNCE_US: u64 = 30_000;
+const REPEAT_US: u64 = 200_000;
+
+const NUM_BUTTONS: usize = 8;
+const EVENT_QUEUE_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonId {
+    DpadLeft,
+    DpadRight,
+    DpadUp,
+    DpadDown,
+    X,
+    Y,
+    A,
+    B,
+}
+
+impl ButtonId {
+    /// The bit of the button in a `ButtonMask`.
+    pub const fn mask(self) -> ButtonMask {
+        1 << self as u8
+    }
+}
+
+const BUTTON_IDS: [ButtonId; NUM_BUTTONS] = [
+    ButtonId::DpadLeft,
+    ButtonId::DpadRight,
+    ButtonId::DpadUp,
+    ButtonId::DpadDown,
+    ButtonId::X,
+    ButtonId::Y,
+    ButtonId::A,
+    ButtonId::B,
+];
+
+const BUTTON_KEYS: [Key; NUM_BUTTONS] = [
+    Key::Left,
+    Key::Right,
+    Key::Up,
+    Key::Down,
+    Key::A,
+    Key::S,
+    Key::Z,
+    Key::X,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEventKind {
+    Press,
+    Release,
+    /// Sent while a button is held, after the repeat delay.
+    Repeat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonEvent {
+    pub button: ButtonId,
+    pub kind: ButtonEventKind,
+    /// `time::time_us64` when the event happened.
+    pub timestamp: u64,
+}
+
+const RESET_BUTTONS: ButtonMask =
+    ButtonId::X.mask() | ButtonId::Y.mask() | ButtonId::A.mask() | ButtonId::B.mask();
+const RESET_HOLD_MS: u32 = 1000;
+const BOOTSEL_HOLD_MS: u32 = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetAction {
+    Launcher,
+    Bootloader,
+    Ignore,
+}
+
+pub type ResetHandler = fn() -> ResetAction;
+
+static RESET_HANDLER: Mutex<Option<ResetHandler>> = Mutex::new(None);
+
+/// There is no launcher or bootloader to go back to, so both quit.
+pub fn reset(action: ResetAction) {
+    if action != ResetAction::Ignore {
+        log::info!("Reset to {:?}, quitting", action);
+        std::process::exit(0);
+    }
+}
+
+// One bit per button, in the order of `BUTTON_IDS`.
+static HELD: AtomicU8 = AtomicU8::new(0);
+
+// Buttons replayed in place of the keyboard, with `OVERRIDE_ACTIVE` set.
+static OVERRIDE: AtomicU16 = AtomicU16::new(0);
+const OVERRIDE_ACTIVE: u16 = 0x100;
+
+fn held_buttons() -> ButtonMask {
+    let value = OVERRIDE.load(Ordering::Relaxed);
+    if value & OVERRIDE_ACTIVE != 0 {
+        value as ButtonMask
+    } else {
+        HELD.load(Ordering::Relaxed)
+    }
+}
+
+struct EventState {
+    // Buttons held when events were last queued.
+    held: ButtonMask,
+    events: VecDeque<ButtonEvent>,
+    next_repeat_us: [u64; NUM_BUTTONS],
+    // Zero disables repeat.
+    repeat_delay_us: u64,
+    repeat_interval_us: u64,
+    enabled: bool,
+    dropped_events: u32,
+}
+
+impl EventState {
+    fn push(&mut self, button: ButtonId, kind: ButtonEventKind, timestamp: u64) {
+        if self.events.len() == EVENT_QUEUE_SIZE {
+            self.dropped_events += 1;
+            return;
+        }
+        self.events.push_back(ButtonEvent {
+            button,
+            kind,
+            timestamp,
+        });
+    }
+}
+
+static EVENTS: Mutex<EventState> = Mutex::new(EventState {
+    held: 0,
+    events: VecDeque::new(),
+    next_repeat_us: [0; NUM_BUTTONS],
+    repeat_delay_us: 400_000,
+    repeat_interval_us: 100_000,
+    enabled: false,
+    dropped_events: 0,
+});
+
+/// Samples the keyboard, and queues events for the buttons that changed.
+pub(crate) fn update_keys(window: &Window) {
+    let now = time::time_us64();
+    let mut held = 0;
+    for (i, &key) in BUTTON_KEYS.iter().enumerate() {
+        if window.is_key_down(key) {
+            held |= 1 << i;
+        }
+    }
+    HELD.store(held, Ordering::Relaxed);
+    let held = held_buttons();
+    let mut events = EVENTS.lock().unwrap();
+    let previous = events.held;
+    events.held = held;
+    if !events.enabled {
+        return;
+    }
+    for (i, &id) in BUTTON_IDS.iter().enumerate() {
+        let is_held = held & (1 << i) != 0;
+        if is_held != (previous & (1 << i) != 0) {
+            let kind = if is_held {
+                ButtonEventKind::Press
+            } else {
+                ButtonEventKind::Release
+            };
+            events.push(id, kind, now);
+            events.next_repeat_us[i] = now + events.repeat_delay_us;
+        } else if is_held && events.repeat_delay_us > 0 && now >= events.next_repeat_us[i] {
+            events.push(id, ButtonEventKind::Repeat, now);
+            events.next_repeat_us[i] = now + events.repeat_interval_us.max(1);
+        }
+    }
+}
+
+pub struct Button {
+    index: usize,
+    press_inhibit: bool,
+    last_held_time: u64,
+    last_repeat_time: u64,
+    presses: PressTracker,
+}
+
+impl Button {
+    fn new(index: usize) -> Button {
+        Button {
+            index,
+            press_inhibit: false,
+            last_held_time: 0,
+            last_repeat_time: 0,
+            presses: PressTracker::new(),
+        }
+    }
+
+    pub fn is_held(&self) -> bool {
+        held_buttons() & (1 << self.index) != 0
+    }
+
+    pub fn is_pressed(&mut self) -> bool {
+        if self.is_held() {
+            let now = time::time_us64();
+            self.last_held_time = now;
+            if self.press_inhibit {
+                if now - self.last_repeat_time > REPEAT_US {
+                    self.last_repeat_time = now;
+                    true
+                } else {
+                    false
+                }
+            } else {
+                self.press_inhibit = true;
+                self.last_repeat_time = now;
+                true
+            }
+        } else if self.press_inhibit && time::time_us64() > self.last_held_time + DEBOUNCE_US {
+            self.press_inhibit = false;
+            false
+        } else {
+            false
+        }
+    }
+
+    /// Whether the button has been held for at least `duration`. Like
+    /// `is_pressed`, this and the long and double presses only see the
+    /// button when asked, so games ask every frame.
+    pub fn held_for(&mut self, duration: Duration) -> bool {
+        let now = time::time_us64();
+        self.presses.update(self.is_held(), now);
+        self.presses.held_for(duration, now)
+    }
+
+    /// Returns true once per press, when the button has been held for the
+    /// long press time, half a second unless set otherwise.
+    pub fn is_long_pressed(&mut self) -> bool {
+        let now = time::time_us64();
+        self.presses.update(self.is_held(), now);
+        self.presses.take_long_press(now)
+    }
+
+    /// Returns true once when the button is pressed a second time within
+    /// the double press window, 300 ms unless set otherwise. A third press
+    /// starts again.
+    pub fn is_double_pressed(&mut self) -> bool {
+        self.presses.update(self.is_held(), time::time_us64());
+        self.presses.take_double_press()
+    }
+
+    pub fn set_long_press(&mut self, duration: Duration) {
+        self.presses.set_long_press(duration);
+    }
+
+    pub fn set_double_press_window(&mut self, window: Duration) {
+        self.presses.set_double_press_window(window);
+    }
+}
+
+pub struct Input {
+    pub dpad_left: Button,
+    pub dpad_right: Button,
+    pub dpad_up: Button,
+    pub dpad_down: Button,
+    pub button_x: Button,
+    pub button_y: Button,
+    pub button_a: Button,
+    pub button_b: Button,
+    reset_combos: ComboDetector<ResetAction, 2>,
+    reset_requested: bool,
+    button_map: ButtonMap,
+}
+
+#[allow(clippy::new_without_default)]
+impl Input {
+    pub fn new() -> Self {
+        Input {
+            dpad_left: Button::new(0),
+            dpad_right: Button::new(1),
+            dpad_up: Button::new(2),
+            dpad_down: Button::new(3),
+            button_x: Button::new(4),
+            button_y: Button::new(5),
+            button_a: Button::new(6),
+            button_b: Button::new(7),
+            reset_combos: Self::reset_combos(None),
+            reset_requested: false,
+            button_map: ButtonMap::new(),
+        }
+    }
+
+    fn reset_combos(bootsel_buttons: Option<ButtonMask>) -> ComboDetector<ResetAction, 2> {
+        let mut combos = ComboDetector::new();
+        combos.add(
+            ResetAction::Launcher,
+            Combo::Chord {
+                buttons: RESET_BUTTONS,
+                hold_ms: RESET_HOLD_MS,
+            },
+        );
+        if let Some(buttons) = bootsel_buttons {
+            combos.add(
+                ResetAction::Bootloader,
+                Combo::Chord {
+                    buttons,
+                    hold_ms: BOOTSEL_HOLD_MS,
+                },
+            );
+        }
+        combos
+    }
+
+    pub fn set_reset_handler(&mut self, handler: Option<ResetHandler>) {
+        *RESET_HANDLER.lock().unwrap() = handler;
+    }
+
+    pub fn set_bootsel_chord(&mut self, buttons: Option<ButtonMask>) {
+        self.reset_combos = Self::reset_combos(buttons);
+    }
+
+    pub fn check_reset(&mut self) {
+        let held = self.physical_buttons();
+        let completed = self.reset_combos.update(held, time::time_us64());
+        if completed.contains(&ResetAction::Bootloader) {
+            reset(ResetAction::Bootloader);
+        }
+        if completed.is_empty() {
+            return;
+        }
+        let handler = *RESET_HANDLER.lock().unwrap();
+        let action = handler.map_or(ResetAction::Launcher, |handler| handler());
+        self.reset_requested = action == ResetAction::Ignore;
+        reset(action);
+    }
+
+    pub fn take_reset_request(&mut self) -> bool {
+        std::mem::take(&mut self.reset_requested)
+    }
+
+    /// Whether a button is actually held, even while a recording is played
+    /// back.
+    pub fn is_active(&self) -> bool {
+        self.physical_buttons() != 0
+    }
+
+    /// The buttons held, or the ones played back by `replay_frame`.
+    pub fn held_buttons(&self) -> ButtonMask {
+        held_buttons()
+    }
+
+    /// The buttons actually held, for example to end an attract mode demo.
+    pub fn physical_buttons(&self) -> ButtonMask {
+        HELD.load(Ordering::Relaxed)
+    }
+
+    /// Sets which button does each action.
+    pub fn set_button_map(&mut self, button_map: ButtonMap) {
+        self.button_map = button_map;
+    }
+
+    pub fn button_map(&self) -> &ButtonMap {
+        &self.button_map
+    }
+
+    /// The button of `action`, to check whether it is held or pressed.
+    pub fn action_button(&mut self, action: Action) -> &mut Button {
+        match self.button_map.button(action) {
+            ButtonId::DpadLeft => &mut self.dpad_left,
+            ButtonId::DpadRight => &mut self.dpad_right,
+            ButtonId::DpadUp => &mut self.dpad_up,
+            ButtonId::DpadDown => &mut self.dpad_down,
+            ButtonId::X => &mut self.button_x,
+            ButtonId::Y => &mut self.button_y,
+            ButtonId::A => &mut self.button_a,
+            ButtonId::B => &mut self.button_b,
+        }
+    }
+
+    pub fn is_action_held(&self, action: Action) -> bool {
+        self.held_buttons() & self.button_map.button(action).mask() != 0
+    }
+
+    /// Feeds the buttons held on this frame to `combos` and returns the
+    /// combos completed.
+    pub fn update_combos<Id: Copy, const N: usize>(
+        &self,
+        combos: &mut ComboDetector<Id, N>,
+    ) -> heapless::Vec<Id, N> {
+        combos.update(self.held_buttons(), time::time_us64())
+    }
+
+    /// Adds the buttons held on this frame to `recording`. Returns false
+    /// once the recording is full.
+    pub fn record_frame<const N: usize>(&self, recording: &mut Recording<N>) -> bool {
+        recording.push(self.held_buttons())
+    }
+
+    /// Makes the buttons, and their events, follow the next frame of
+    /// `player` instead of the keyboard. Returns false, and gives the
+    /// keyboard back, once the recording has been played.
+    pub fn replay_frame(&mut self, player: &mut Player) -> bool {
+        let buttons = player.next_frame();
+        self.set_override(buttons);
+        buttons.is_some()
+    }
+
+    /// Replaces the keyboard with `buttons`, or gives it back with `None`.
+    pub fn set_override(&mut self, buttons: Option<ButtonMask>) {
+        let value = buttons.map_or(0, |buttons| OVERRIDE_ACTIVE | buttons as u16);
+        OVERRIDE.store(value, Ordering::Relaxed);
+    }
+
+    /// Starts queueing button events read with `poll_event`. The keyboard
+    /// is only sampled once per frame, so timestamps are frame times.
+    pub fn enable_events(&mut self) {
+        let mut events = EVENTS.lock().unwrap();
+        events.enabled = true;
+        events.events.clear();
+    }
+
+    pub fn disable_events(&mut self) {
+        EVENTS.lock().unwrap().enabled = false;
+    }
+
+    /// Held buttons send `Repeat` events after `delay_ms` and then every
+    /// `interval_ms`. A delay of 0 disables repeat.
+    pub fn set_repeat(&mut self, delay_ms: u32, interval_ms: u32) {
+        let mut events = EVENTS.lock().unwrap();
+        events.repeat_delay_us = delay_ms as u64 * 1000;
+        events.repeat_interval_us = interval_ms as u64 * 1000;
+    }
+
+    /// Returns the oldest queued event.
+    pub fn poll_event(&mut self) -> Option<ButtonEvent> {
+        EVENTS.lock().unwrap().events.pop_front()
+    }
+
+    /// Number of events lost because the queue was full.
+    pub fn dropped_events(&self) -> u32 {
+        EVENTS.lock().unwrap().dropped_events
+    }
+}
@@ -0,0 +1,90 @@
+//! Keeps games that light the LED building. There is no LED in the window,
+//! so each change is logged instead.
+
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Solid,
+    /// On for the first half of each period and off for the second.
+    Blink {
+        period_ms: u32,
+    },
+    /// Fades in and out once per period.
+    Breathe {
+        period_ms: u32,
+    },
+}
+
+pub struct Led {
+    color: Rgb888,
+    brightness: u8,
+    effect: Effect,
+}
+
+#[allow(clippy::new_without_default)]
+impl Led {
+    pub fn new() -> Self {
+        Led {
+            color: Rgb888::BLACK,
+            brightness: 255,
+            effect: Effect::Solid,
+        }
+    }
+
+    pub fn set_color(&mut self, color: Rgb888) {
+        self.color = color;
+        self.log();
+    }
+
+    pub fn color(&self) -> Rgb888 {
+        self.color
+    }
+
+    /// Scales the color, from 0 (off) to 255 (full brightness).
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+        self.log();
+    }
+
+    pub fn set_effect(&mut self, effect: Effect) {
+        self.effect = effect;
+        self.log();
+    }
+
+    pub fn blink(&mut self, color: Rgb888, period_ms: u32) {
+        self.set_color(color);
+        self.set_effect(Effect::Blink { period_ms });
+    }
+
+    pub fn breathe(&mut self, color: Rgb888, period_ms: u32) {
+        self.set_color(color);
+        self.set_effect(Effect::Breathe { period_ms });
+    }
+
+    /// Blinks `color` `times` times over the color and effect set, which
+    /// carry on afterwards.
+    pub fn flash(&mut self, color: Rgb888, times: u32, period_ms: u32) {
+        log::info!(
+            "LED flashes {:?} {} times every {} ms",
+            color,
+            times,
+            period_ms
+        );
+    }
+
+    /// Turns the LED off and stops any effect.
+    pub fn off(&mut self) {
+        self.set_effect(Effect::Solid);
+        self.set_color(Rgb888::BLACK);
+    }
+
+    fn log(&self) {
+        log::info!(
+            "LED {:?} at {} {:?}",
+            self.color,
+            self.brightness,
+            self.effect
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use std::sync::OnceLock;
+
+static START: OnceLock<std::time::Instant> = OnceLock::new();
+
+fn elapsed_us() -> u64 {
+    START
+        .get_or_init(std::time::Instant::now)
+        .elapsed()
+        .as_micros() as u64
+}
+
+/// Microseconds since startup, wrapping like the device timer.
+pub fn time_us() -> u32 {
+    elapsed_us() as u32
+}
+
+pub fn time_us64() -> u64 {
+    elapsed_us()
+}
+
+/// A point in time, in microseconds since startup.
+pub type Instant = fugit::TimerInstantU64<1_000_000>;
+
+/// A span of time in microseconds.
+pub type Duration = fugit::MicrosDurationU64;
+
+pub fn now() -> Instant {
+    Instant::from_ticks(time_us64())
+}
+
+/// The time since `instant`, or zero if it's in the future.
+pub fn elapsed(instant: Instant) -> Duration {
+    now()
+        .checked_duration_since(instant)
+        .unwrap_or(Duration::from_ticks(0))
+}
@@ -0,0 +1,96 @@
+//! Splash screens: a picture, usually an `image!`, faded in from a color,
+//! held and faded out again, for logos and title cards.
+//!
+//! The fades go through the display's color filter, so the picture is
+//! decoded into the framebuffer once and each frame only changes the
+//! filter.
+
+use crate::color_filter::ColorFilter;
+use crate::hardware::Hardware;
+use crate::sprite::{self, CompressedSprite};
+use crate::time;
+use embedded_graphics::pixelcolor::{Rgb565, RgbColor};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Splash {
+    pub fade_in_ms: u32,
+    pub hold_ms: u32,
+    pub fade_out_ms: u32,
+    /// Fills the screen around the picture, and is what it fades from and
+    /// to.
+    pub background: Rgb565,
+    /// Whether pressing a button cuts the hold short and fades out.
+    pub skippable: bool,
+}
+
+impl Default for Splash {
+    fn default() -> Self {
+        Splash {
+            fade_in_ms: 500,
+            hold_ms: 2000,
+            fade_out_ms: 500,
+            background: Rgb565::BLACK,
+            skippable: true,
+        }
+    }
+}
+
+impl Splash {
+    /// Shows `image` in the middle of the screen and returns once it has
+    /// faded out, leaving the screen filled with the background and no
+    /// color filter.
+    pub fn show(&self, hw: &mut Hardware, image: &CompressedSprite) {
+        let position =
+            Rectangle::with_center(hw.display.bounding_box().center(), image.size).top_left;
+        // Buttons already held, such as the one that started the game,
+        // don't skip.
+        let mut held = hw.input.held_buttons();
+        let mut drawn = false;
+        let start = time::time_us();
+        // When the fade out started, once it has, and how faded it was.
+        let mut fade_out: Option<(u32, u32)> = None;
+        loop {
+            let now = time::time_us();
+            let elapsed_ms = now.wrapping_sub(start) / 1000;
+            let pressed = hw.input.held_buttons() & !held != 0;
+            held = hw.input.held_buttons();
+            // How far towards the background, out of 255.
+            let fade_in = 255 - progress(elapsed_ms, self.fade_in_ms);
+            if fade_out.is_none()
+                && ((self.skippable && pressed) || elapsed_ms >= self.fade_in_ms + self.hold_ms)
+            {
+                fade_out = Some((now, fade_in));
+            }
+            let amount = match fade_out {
+                Some((fade_out_start, from)) => {
+                    let elapsed_ms = now.wrapping_sub(fade_out_start) / 1000;
+                    if elapsed_ms >= self.fade_out_ms {
+                        break;
+                    }
+                    from + (255 - from) * progress(elapsed_ms, self.fade_out_ms) / 255
+                }
+                None => fade_in,
+            };
+            hw.display
+                .set_color_filter(Some(ColorFilter::fade(self.background, amount as u8)));
+            hw.draw(|display| {
+                if !drawn {
+                    display.clear(self.background).unwrap();
+                    sprite::draw_compressed(display, image, position);
+                    drawn = true;
+                }
+            });
+        }
+        hw.draw(|display| display.clear(self.background).unwrap());
+        hw.display.set_color_filter(None);
+    }
+}
+
+// Progress from 0 to 255 of `elapsed_ms` into `duration_ms`.
+fn progress(elapsed_ms: u32, duration_ms: u32) -> u32 {
+    (elapsed_ms.min(duration_ms) * 255)
+        .checked_div(duration_ms)
+        .unwrap_or(255)
+}
@@ -1,3 +1,4 @@
+use crate::compression::Reader;
 use embedded_graphics::pixelcolor::raw::RawU16;
 use embedded_graphics::pixelcolor::Rgb565;
 use embedded_graphics::prelude::*;
@@ -7,6 +8,10 @@ pub struct Sprite<'a> {
     pub size: Size,
     pub transparent_color: Option<u16>,
     pub data: &'a [u16],
+    /// Four bits of opacity per pixel, laid out like `blit::Transparency::Alpha`.
+    /// Only blits blend it, drawing the sprite as an `ImageDrawable` uses
+    /// `transparent_color`.
+    pub alpha: Option<&'a [u8]>,
 }
 
 impl ImageDrawable for Sprite<'_> {
@@ -81,3 +86,233 @@ impl OriginDimensions for Sprite<'_> {
         self.size
     }
 }
+
+/// A sprite stored as palette indices of `bits_per_pixel` (4 or 8) bits,
+/// usually generated by `sprite!` with a palette size. Each row starts on a
+/// byte boundary, with the first pixel in the low bits.
+pub struct IndexedSprite<'a> {
+    pub size: Size,
+    pub bits_per_pixel: u32,
+    pub palette: &'a [u16],
+    pub transparent_index: Option<u8>,
+    pub data: &'a [u8],
+}
+
+impl IndexedSprite<'_> {
+    fn row_stride(&self) -> usize {
+        (self.size.width * self.bits_per_pixel).div_ceil(8) as usize
+    }
+
+    pub fn index(&self, x: u32, y: u32) -> u8 {
+        let bit = x * self.bits_per_pixel;
+        let byte = self.data[y as usize * self.row_stride() + (bit / 8) as usize];
+        (byte >> (bit % 8)) & ((1 << self.bits_per_pixel) - 1) as u8
+    }
+
+    /// Color of the pixel at `x`, `y`, or `None` if it is transparent.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<u16> {
+        let index = self.index(x, y);
+        if Some(index) == self.transparent_index {
+            None
+        } else {
+            Some(self.palette[index as usize])
+        }
+    }
+}
+
+impl ImageDrawable for IndexedSprite<'_> {
+    type Color = Rgb565;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.draw_sub_image(target, &self.bounding_box())
+    }
+
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let area = area.intersection(&self.bounding_box());
+        let points = area.points();
+        if self.transparent_index.is_some() {
+            target.draw_iter(points.filter_map(|p| {
+                self.pixel(p.x as u32, p.y as u32)
+                    .map(|c| Pixel(p - area.top_left, RawU16::new(c).into()))
+            }))
+        } else {
+            target.fill_contiguous(
+                &Rectangle::new(Point::zero(), area.size),
+                points.map(|p| {
+                    RawU16::new(self.palette[self.index(p.x as u32, p.y as u32) as usize]).into()
+                }),
+            )
+        }
+    }
+}
+
+impl OriginDimensions for IndexedSprite<'_> {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+/// A sprite whose RGB565 pixels, big-endian like the framebuffer, are
+/// run-length compressed row after row as one stream. Skips in the stream
+/// are transparent. It is decoded as it is drawn, so even a full screen
+/// image needs no buffer for its pixels.
+pub struct CompressedSprite<'a> {
+    pub size: Size,
+    pub data: &'a [u16],
+}
+
+impl ImageDrawable for CompressedSprite<'_> {
+    type Color = Rgb565;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.draw_sub_image(target, &self.bounding_box())
+    }
+
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let area = area.intersection(&self.bounding_box());
+        let width = self.size.width as usize;
+        let left = area.top_left.x as usize;
+        let right = width - left - area.size.width as usize;
+        let mut reader = Reader::new(self.data);
+        reader.skip_words(area.top_left.y as usize * width);
+        for y in 0..area.size.height as i32 {
+            reader.skip_words(left);
+            target.draw_iter((0..area.size.width as i32).filter_map(|x| {
+                let color = reader.next().flatten()?;
+                Some(Pixel(
+                    Point::new(x, y),
+                    RawU16::new(u16::from_be(color)).into(),
+                ))
+            }))?;
+            reader.skip_words(right);
+        }
+        Ok(())
+    }
+}
+
+impl OriginDimensions for CompressedSprite<'_> {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+mod device {
+    use crate::compression::Reader;
+    use crate::display::{framebuffer, Display, WIDTH};
+    use crate::dma;
+    use crate::sprite::{CompressedSprite, IndexedSprite};
+    use embedded_graphics::prelude::*;
+    use embedded_graphics::primitives::Rectangle;
+
+    /// Draws `sprite` with its top left corner at `position`, faster than
+    /// going through `ImageDrawable`. Opaque sprites are expanded a row at a
+    /// time while the previous row is copied to the framebuffer by DMA.
+    pub fn draw_indexed(display: &mut Display, sprite: &IndexedSprite, position: Point) {
+        let clipped = Rectangle::new(position, sprite.size).intersection(&display.bounding_box());
+        if clipped.is_zero_sized() {
+            return;
+        }
+        display.mark_dirty(clipped);
+        let src = clipped.top_left - position;
+        let fb = framebuffer();
+        let width = clipped.size.width as usize;
+
+        if sprite.transparent_index.is_some() {
+            for y in 0..clipped.size.height as i32 {
+                let row = (clipped.top_left.y + y) as usize * WIDTH + clipped.top_left.x as usize;
+                for x in 0..width {
+                    if let Some(color) = sprite.pixel((src.x + x as i32) as u32, (src.y + y) as u32)
+                    {
+                        fb[row + x] = color.to_be();
+                    }
+                }
+            }
+            return;
+        }
+
+        let dma_channel = display.draw_dma_channel();
+        let mut row_buffers = [[0u16; WIDTH]; 2];
+        for y in 0..clipped.size.height as i32 {
+            let row_buffer = &mut row_buffers[y as usize % 2];
+            for (x, pixel) in row_buffer[..width].iter_mut().enumerate() {
+                let index = sprite.index((src.x + x as i32) as u32, (src.y + y) as u32);
+                *pixel = sprite.palette[index as usize].to_be();
+            }
+            let dst = (clipped.top_left.y + y) as usize * WIDTH + clipped.top_left.x as usize;
+            dma_channel.wait();
+            unsafe {
+                dma::start_copy_mem(
+                    dma_channel,
+                    row_buffer.as_ptr() as u32,
+                    fb.as_mut_ptr().add(dst) as u32,
+                    2,
+                    width as u32,
+                );
+            }
+        }
+        dma_channel.wait();
+    }
+
+    /// Draws `sprite` with its top left corner at `position`, decoding each
+    /// row straight into the framebuffer. Rows and columns off the screen
+    /// are passed over without being decoded.
+    pub fn draw_compressed(display: &mut Display, sprite: &CompressedSprite, position: Point) {
+        let clipped = Rectangle::new(position, sprite.size).intersection(&display.bounding_box());
+        if clipped.is_zero_sized() {
+            return;
+        }
+        display.mark_dirty(clipped);
+        let src = clipped.top_left - position;
+        let fb = framebuffer();
+        let width = clipped.size.width as usize;
+        let left = src.x as usize;
+        let right = sprite.size.width as usize - left - width;
+        let mut reader = Reader::new(sprite.data);
+        reader.skip_words(src.y as usize * sprite.size.width as usize);
+        for y in 0..clipped.size.height as i32 {
+            let row = (clipped.top_left.y + y) as usize * WIDTH + clipped.top_left.x as usize;
+            reader.skip_words(left);
+            reader.read(&mut fb[row..row + width]);
+            reader.skip_words(right);
+        }
+    }
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub use device::{draw_compressed, draw_indexed};
+
+#[cfg(all(feature = "simulator", not(target_os = "none")))]
+mod simulator {
+    use crate::display::Display;
+    use crate::sprite::{CompressedSprite, IndexedSprite};
+    use embedded_graphics::image::Image;
+    use embedded_graphics::prelude::*;
+
+    /// Draws `sprite` with its top left corner at `position`, through
+    /// `ImageDrawable`, as there is no DMA to speed it up.
+    pub fn draw_indexed(display: &mut Display, sprite: &IndexedSprite, position: Point) {
+        let _ = Image::new(sprite, position).draw(display);
+    }
+
+    /// Draws `sprite` with its top left corner at `position`, through
+    /// `ImageDrawable`.
+    pub fn draw_compressed(display: &mut Display, sprite: &CompressedSprite, position: Point) {
+        let _ = Image::new(sprite, position).draw(display);
+    }
+}
+
+#[cfg(all(feature = "simulator", not(target_os = "none")))]
+pub use simulator::{draw_compressed, draw_indexed};
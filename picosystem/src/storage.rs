@@ -0,0 +1,430 @@
+//! A file system in the last megabyte of flash, built on littlefs.
+//!
+//! littlefs survives a power loss at any point: a file's new contents only
+//! replace the old ones when it is closed, and its metadata is written
+//! copy-on-write. It also spreads erases over the flash. Files live in the
+//! root directory, at most `MAX_FILES` of them, and each ends in a CRC-32 of
+//! its contents, computed by the DMA sniffer and checked by `Fs::read`.
+//!
+//! The file system is mounted for each call, which only reads its metadata,
+//! so it keeps no RAM between calls. Flash that doesn't hold one yet has no
+//! files, and is formatted by the first `write`.
+//!
+//! Flash can't be read while it is written, so erasing and programming run
+//! from RAM with interrupts disabled, and the render server on core 1 is
+//! parked in RAM while a file is written or removed. Any other code started
+//! on core 1 must not run from flash meanwhile.
+
+use crate::dma;
+use crate::render::with_core1_parked;
+use littlefs2::consts::{U256, U4};
+use littlefs2::driver::Storage;
+use littlefs2::fs::Filesystem;
+use littlefs2::io::{self as lfs_io, SeekFrom, Write as _};
+use littlefs2::path;
+use littlefs2::path::{Path, PathBuf};
+use rp_pico::hal::rom_data;
+
+const XIP_BASE: u32 = 0x1000_0000;
+/// Offset of the file system from the start of flash. `memory.x` ends
+/// `STATIC_FLASH` here.
+pub const STORAGE_OFFSET: u32 = 15 * 1024 * 1024;
+pub const STORAGE_SIZE: u32 = 1024 * 1024;
+
+const SECTOR_SIZE: u32 = 4096;
+const PAGE_SIZE: usize = 256;
+const NUM_SECTORS: usize = (STORAGE_SIZE / SECTOR_SIZE) as usize;
+const BLOCK_SIZE: u32 = 1 << 16;
+const BLOCK_ERASE_CMD: u8 = 0xd8;
+
+// The CRC-32 after the contents of every file.
+const CRC_SIZE: usize = 4;
+
+pub const MAX_NAME_LEN: usize = 40;
+pub const MAX_FILES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FsError {
+    NameTooLong,
+    /// Names are printable ASCII, without `/`.
+    InvalidName,
+    NotFound,
+    NoSpace,
+    /// Writing a new file would make more than `MAX_FILES`.
+    TooManyFiles,
+    /// The stored checksum doesn't match the file contents, or littlefs
+    /// found its metadata damaged.
+    Corrupt,
+}
+
+impl From<lfs_io::Error> for FsError {
+    fn from(error: lfs_io::Error) -> Self {
+        match error {
+            lfs_io::Error::NoSuchEntry => FsError::NotFound,
+            lfs_io::Error::NoSpace => FsError::NoSpace,
+            lfs_io::Error::FilenameTooLong => FsError::NameTooLong,
+            _ => FsError::Corrupt,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: heapless::String<MAX_NAME_LEN>,
+    pub len: usize,
+}
+
+/// A file opened for reading. It refers to the file by name, so reads after
+/// the file is rewritten return the new contents, and fail once it is
+/// removed.
+pub struct File {
+    name: heapless::String<MAX_NAME_LEN>,
+    len: u32,
+    position: u32,
+}
+
+impl File {
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads from the current position into `buf`, returning the number of
+    /// bytes read, which is 0 at the end of the file.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FsError> {
+        let n = self.read_at(self.position as usize, buf)?;
+        self.position += n as u32;
+        Ok(n)
+    }
+
+    pub fn seek(&mut self, position: usize) {
+        self.position = (position as u32).min(self.len);
+    }
+
+    /// Reads from `position` into `buf` without moving the current
+    /// position, returning the number of bytes read.
+    pub fn read_at(&self, position: usize, buf: &mut [u8]) -> Result<usize, FsError> {
+        let (n, _, _) = read_file(&PathBuf::from(self.name.as_str()), position, buf)?;
+        Ok(n)
+    }
+}
+
+pub struct Fs {
+    _private: (),
+}
+
+pub fn fs() -> Fs {
+    Fs { _private: () }
+}
+
+impl Fs {
+    /// Opens the file `name`. Its checksum isn't checked, as that would
+    /// read it all.
+    pub fn open(&self, name: &str) -> Result<File, FsError> {
+        let path = file_path(name)?;
+        let metadata = mount(false, |fs| Ok(fs.metadata(&path)?))?;
+        if !metadata.is_file() {
+            return Err(FsError::NotFound);
+        }
+        let mut file_name = heapless::String::new();
+        // `file_path` checked the length.
+        let _ = file_name.push_str(name);
+        Ok(File {
+            name: file_name,
+            len: metadata.len().saturating_sub(CRC_SIZE) as u32,
+            position: 0,
+        })
+    }
+
+    /// Reads the whole file into `buf`, returning its length. Longer files
+    /// are truncated, and then not checked against their checksum.
+    pub fn read(&self, name: &str, buf: &mut [u8]) -> Result<usize, FsError> {
+        let (n, len, crc) = read_file(&file_path(name)?, 0, buf)?;
+        if n == len && dma::crc32(&buf[..n]) != crc {
+            return Err(FsError::Corrupt);
+        }
+        Ok(n)
+    }
+
+    /// Creates or replaces the file `name` with `data`.
+    pub fn write(&self, name: &str, data: &[u8]) -> Result<(), FsError> {
+        let path = file_path(name)?;
+        let crc = dma::crc32(data);
+        with_core1_parked(|| {
+            mount(true, |fs| {
+                if fs.metadata(&path).is_err() && count_files(fs)? >= MAX_FILES {
+                    return Err(FsError::TooManyFiles);
+                }
+                fs.create_file_and_then(&path, |file| {
+                    file.write_all(data)?;
+                    file.write_all(&crc.to_le_bytes())
+                })?;
+                Ok(())
+            })
+        })
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), FsError> {
+        let path = file_path(name)?;
+        with_core1_parked(|| mount(false, |fs| Ok(fs.remove(&path)?)))
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        file_path(name)
+            .and_then(|path| mount(false, |fs| Ok(fs.metadata(&path)?.is_file())))
+            .unwrap_or(false)
+    }
+
+    pub fn list(&self) -> heapless::Vec<DirEntry, MAX_FILES> {
+        let mut list = heapless::Vec::new();
+        let _ = mount(false, |fs| {
+            fs.read_dir_and_then(path!("/"), |dir| {
+                for entry in dir {
+                    let entry = entry?;
+                    let mut name = heapless::String::new();
+                    // Longer names were written some other way.
+                    if !entry.file_type().is_file()
+                        || name.push_str(entry.file_name().as_ref()).is_err()
+                    {
+                        continue;
+                    }
+                    let len = entry.metadata().len().saturating_sub(CRC_SIZE);
+                    let _ = list.push(DirEntry { name, len });
+                }
+                Ok(())
+            })?;
+            Ok(())
+        });
+        list
+    }
+
+    /// Bytes available for new files. More may fit, as littlefs keeps
+    /// small files with its metadata.
+    pub fn free_space(&self) -> usize {
+        mount(false, |fs| Ok(fs.available_space()?)).unwrap_or(STORAGE_SIZE as usize)
+    }
+}
+
+// The storage region as a littlefs block device, with a block per sector.
+struct Flash;
+
+impl Storage for Flash {
+    const READ_SIZE: usize = 1;
+    const WRITE_SIZE: usize = PAGE_SIZE;
+    const BLOCK_SIZE: usize = SECTOR_SIZE as usize;
+    const BLOCK_COUNT: usize = NUM_SECTORS;
+    // Erases a metadata block this many times before moving it.
+    const BLOCK_CYCLES: isize = 500;
+    type CACHE_SIZE = U256;
+    // In 64 bit words of one bit per block, so all of them.
+    type LOOKAHEAD_SIZE = U4;
+
+    fn read(&mut self, off: usize, buf: &mut [u8]) -> lfs_io::Result<usize> {
+        buf.copy_from_slice(flash_slice(STORAGE_OFFSET + off as u32, buf.len() as u32));
+        Ok(buf.len())
+    }
+
+    // littlefs programs whole pages from its cache, which is in RAM.
+    fn write(&mut self, off: usize, data: &[u8]) -> lfs_io::Result<usize> {
+        for (i, page) in data.chunks_exact(PAGE_SIZE).enumerate() {
+            let address = STORAGE_OFFSET + (off + i * PAGE_SIZE) as u32;
+            unsafe { flash::program(address, page.try_into().unwrap()) };
+        }
+        Ok(data.len())
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> lfs_io::Result<usize> {
+        unsafe { flash::erase(STORAGE_OFFSET + off as u32, len as u32) };
+        Ok(len)
+    }
+}
+
+// Mounts the file system for `f`, with interrupts disabled since a write
+// can't be interrupted by code running from flash. With `format`, flash
+// that doesn't hold a file system is formatted first.
+fn mount<R>(
+    format: bool,
+    f: impl FnOnce(&Filesystem<'_, Flash>) -> Result<R, FsError>,
+) -> Result<R, FsError> {
+    critical_section::with(|_| {
+        if format && !Filesystem::is_mountable(&mut Flash) {
+            Filesystem::format(&mut Flash)?;
+        }
+        let mut alloc = Filesystem::allocate();
+        let mut flash = Flash;
+        // Flash that was never formatted has no files.
+        let fs = Filesystem::mount(&mut alloc, &mut flash).map_err(|_| FsError::NotFound)?;
+        f(&fs)
+    })
+}
+
+fn file_path(name: &str) -> Result<PathBuf, FsError> {
+    if name.len() > MAX_NAME_LEN {
+        return Err(FsError::NameTooLong);
+    }
+    if name.is_empty()
+        || !name.bytes().all(|b| b.is_ascii_graphic() || b == b' ')
+        || name.contains('/')
+    {
+        return Err(FsError::InvalidName);
+    }
+    Ok(PathBuf::from(name))
+}
+
+// Reads the contents of `path` from `position` into `buf`, returning the
+// number of bytes read, the length of the contents and their checksum.
+fn read_file(path: &Path, position: usize, buf: &mut [u8]) -> Result<(usize, usize, u32), FsError> {
+    mount(false, |fs| {
+        Ok(fs.open_file_and_then(path, |file| {
+            let len = file
+                .len()?
+                .checked_sub(CRC_SIZE)
+                .ok_or(lfs_io::Error::Corruption)?;
+            let mut crc = [0; CRC_SIZE];
+            file.seek(SeekFrom::Start(len as u32))?;
+            file.read(&mut crc)?;
+            let n = buf.len().min(len.saturating_sub(position));
+            file.seek(SeekFrom::Start(position as u32))?;
+            // littlefs reads until `buf` is full or the file ends.
+            file.read(&mut buf[..n])?;
+            Ok((n, len, u32::from_le_bytes(crc)))
+        })?)
+    })
+}
+
+fn count_files(fs: &Filesystem<'_, Flash>) -> Result<usize, FsError> {
+    Ok(fs.read_dir_and_then(path!("/"), |dir| {
+        let mut count = 0;
+        for entry in dir {
+            if entry?.file_type().is_file() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    })?)
+}
+
+fn flash_slice(address: u32, len: u32) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts((XIP_BASE + address) as *const u8, len as usize) }
+}
+
+/// The 64 bit unique ID of the flash chip, which tells consoles apart.
+pub fn flash_unique_id() -> u64 {
+    let mut id = [0; 8];
+    with_core1_parked(|| critical_section::with(|_| unsafe { flash::read_unique_id(&mut id) }));
+    u64::from_be_bytes(id)
+}
+
+mod flash {
+    use super::*;
+
+    struct FlashFunctions {
+        connect_internal_flash: unsafe extern "C" fn(),
+        flash_exit_xip: unsafe extern "C" fn(),
+        flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+        flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+        flash_flush_cache: unsafe extern "C" fn(),
+        enter_xip: unsafe extern "C" fn(),
+    }
+
+    // boot2 sets up fast XIP again after a write. It can't be run from flash.
+    static mut BOOT2_RAM: [u32; 64] = [0; 64];
+
+    unsafe fn functions() -> FlashFunctions {
+        let boot2 = &mut *core::ptr::addr_of_mut!(BOOT2_RAM);
+        if boot2[0] == 0 {
+            core::ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2.as_mut_ptr(), 64);
+        }
+        FlashFunctions {
+            connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+            flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+            flash_range_erase: rom_data::flash_range_erase::ptr(),
+            flash_range_program: rom_data::flash_range_program::ptr(),
+            flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+            // Thumb code, so set the low bit.
+            enter_xip: core::mem::transmute::<usize, unsafe extern "C" fn()>(
+                boot2.as_ptr() as usize + 1,
+            ),
+        }
+    }
+
+    /// Erases `len` bytes at `address`, both multiples of the sector size.
+    /// Must be called with interrupts disabled.
+    pub(super) unsafe fn erase(address: u32, len: u32) {
+        run(&functions(), address, core::ptr::null(), len as usize, true);
+    }
+
+    /// Programs a page at `address`, which must be page aligned. Must be
+    /// called with interrupts disabled.
+    pub(super) unsafe fn program(address: u32, page: &[u8; PAGE_SIZE]) {
+        run(&functions(), address, page.as_ptr(), PAGE_SIZE, false);
+    }
+
+    const SSI_SR: *mut u32 = 0x1800_0028 as *mut u32;
+    const SSI_DR0: *mut u32 = 0x1800_0060 as *mut u32;
+    const SSI_SR_TFNF: u32 = 1 << 1;
+    const SSI_SR_RFNE: u32 = 1 << 3;
+    const QSPI_SS_CTRL: *mut u32 = 0x4001_800c as *mut u32;
+    const QSPI_SS_OUTOVER: u32 = 3 << 8;
+    const QSPI_SS_LOW: u32 = 2 << 8;
+    const QSPI_SS_HIGH: u32 = 3 << 8;
+    const READ_UNIQUE_ID_CMD: u8 = 0x4b;
+    // The command, four dummy bytes and the ID.
+    const UNIQUE_ID_CMD_LEN: usize = 13;
+
+    /// Reads the flash chip's unique ID. Must be called with interrupts
+    /// disabled.
+    pub(super) unsafe fn read_unique_id(id: &mut [u8; 8]) {
+        let mut buf = [0; UNIQUE_ID_CMD_LEN];
+        buf[0] = READ_UNIQUE_ID_CMD;
+        do_cmd(&functions(), &mut buf);
+        id.copy_from_slice(&buf[5..]);
+    }
+
+    // Sends `buf` to the flash chip with chip select held low, replacing it
+    // with the bytes received, as the SDK's `flash_do_cmd` does. Only uses
+    // volatile accesses and function pointers, so nothing runs from flash.
+    #[inline(never)]
+    #[link_section = ".data.ram_func"]
+    unsafe fn do_cmd(f: &FlashFunctions, buf: &mut [u8]) {
+        (f.connect_internal_flash)();
+        (f.flash_exit_xip)();
+        let ss_ctrl = core::ptr::read_volatile(QSPI_SS_CTRL) & !QSPI_SS_OUTOVER;
+        core::ptr::write_volatile(QSPI_SS_CTRL, ss_ctrl | QSPI_SS_LOW);
+        // The FIFOs hold 16 entries, so don't get further ahead than that.
+        let (mut sent, mut received) = (0, 0);
+        while received < buf.len() {
+            let status = core::ptr::read_volatile(SSI_SR);
+            if status & SSI_SR_TFNF != 0 && sent < buf.len() && sent - received < 14 {
+                core::ptr::write_volatile(SSI_DR0, buf[sent] as u32);
+                sent += 1;
+            }
+            if status & SSI_SR_RFNE != 0 && received < sent {
+                buf[received] = core::ptr::read_volatile(SSI_DR0) as u8;
+                received += 1;
+            }
+        }
+        core::ptr::write_volatile(QSPI_SS_CTRL, ss_ctrl | QSPI_SS_HIGH);
+        (f.flash_flush_cache)();
+        (f.enter_xip)();
+    }
+
+    // Only calls through function pointers, so nothing runs from flash.
+    #[inline(never)]
+    #[link_section = ".data.ram_func"]
+    unsafe fn run(f: &FlashFunctions, address: u32, data: *const u8, len: usize, erase: bool) {
+        (f.connect_internal_flash)();
+        (f.flash_exit_xip)();
+        if erase {
+            (f.flash_range_erase)(address, len, BLOCK_SIZE, BLOCK_ERASE_CMD);
+        } else {
+            (f.flash_range_program)(address, data, len);
+        }
+        (f.flash_flush_cache)();
+        (f.enter_xip)();
+    }
+}
@@ -0,0 +1,171 @@
+//! Test patterns for checking the colors and geometry of a screen.
+//!
+//! `TestPattern::draw` fills any RGB565 target with color bars, gradients
+//! or a grid. On the device, `calibrate` shows them with the gamma curve
+//! and color order of the display, so that a unit with a different panel
+//! can be set up by eye: the bars should read white, yellow, cyan, green,
+//! magenta, red and blue, the gradients should brighten evenly with no
+//! level lost in the dark end, and the grid should be square with its
+//! border on the edges of the screen.
+
+use embedded_graphics::pixelcolor::{Rgb565, Rgb888};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::display::{ColorOrder, Gamma};
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use crate::hardware::Hardware;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use core::fmt::Write;
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use embedded_graphics::mono_font::{ascii::FONT_6X10, MonoTextStyle};
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use embedded_graphics::text::{Baseline, Text};
+
+// In the order of the usual color bars, from the brightest.
+const BARS: [Rgb565; 8] = [
+    Rgb565::WHITE,
+    Rgb565::YELLOW,
+    Rgb565::CYAN,
+    Rgb565::GREEN,
+    Rgb565::MAGENTA,
+    Rgb565::RED,
+    Rgb565::BLUE,
+    Rgb565::BLACK,
+];
+
+const RAMPS: [Rgb888; 4] = [Rgb888::RED, Rgb888::GREEN, Rgb888::BLUE, Rgb888::WHITE];
+
+const GRID_SPACING: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPattern {
+    /// Eight vertical bars of the primary and secondary colors, white and
+    /// black, for the color order.
+    ColorBars,
+    /// Red, green, blue and gray rising from black to full across the
+    /// screen, without dithering, for the gamma curve.
+    Gradients,
+    /// White lines every 20 pixels inside a red border, for the geometry.
+    Grid,
+}
+
+impl TestPattern {
+    pub const ALL: [TestPattern; 3] = [
+        TestPattern::ColorBars,
+        TestPattern::Gradients,
+        TestPattern::Grid,
+    ];
+
+    pub fn next(self) -> Self {
+        match self {
+            TestPattern::ColorBars => TestPattern::Gradients,
+            TestPattern::Gradients => TestPattern::Grid,
+            TestPattern::Grid => TestPattern::ColorBars,
+        }
+    }
+
+    /// Fills the whole of `target` with the pattern.
+    pub fn draw<D>(self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let area = target.bounding_box();
+        match self {
+            TestPattern::ColorBars => {
+                for (i, &color) in BARS.iter().enumerate() {
+                    let left = band(area.size.width, i, BARS.len());
+                    let right = band(area.size.width, i + 1, BARS.len());
+                    let bar = Rectangle::new(
+                        area.top_left + Point::new(left as i32, 0),
+                        Size::new(right - left, area.size.height),
+                    );
+                    target.fill_solid(&bar, color)?;
+                }
+            }
+            TestPattern::Gradients => {
+                let last = area.size.width.max(2) - 1;
+                for (i, &ramp) in RAMPS.iter().enumerate() {
+                    let top = band(area.size.height, i, RAMPS.len());
+                    let bottom = band(area.size.height, i + 1, RAMPS.len());
+                    let colors = (top..bottom).flat_map(|_| {
+                        (0..area.size.width).map(move |x| {
+                            let scale = |level: u8| (level as u32 * x / last) as u8;
+                            Rgb888::new(scale(ramp.r()), scale(ramp.g()), scale(ramp.b())).into()
+                        })
+                    });
+                    let rows = Rectangle::new(
+                        area.top_left + Point::new(0, top as i32),
+                        Size::new(area.size.width, bottom - top),
+                    );
+                    target.fill_contiguous(&rows, colors)?;
+                }
+            }
+            TestPattern::Grid => {
+                target.fill_solid(&area, Rgb565::BLACK)?;
+                let line =
+                    |top_left: Point, size: Size| Rectangle::new(area.top_left + top_left, size);
+                for x in (0..area.size.width).step_by(GRID_SPACING as usize) {
+                    let column = line(Point::new(x as i32, 0), Size::new(1, area.size.height));
+                    target.fill_solid(&column, Rgb565::WHITE)?;
+                }
+                for y in (0..area.size.height).step_by(GRID_SPACING as usize) {
+                    let row = line(Point::new(0, y as i32), Size::new(area.size.width, 1));
+                    target.fill_solid(&row, Rgb565::WHITE)?;
+                }
+                area.into_styled(PrimitiveStyle::with_stroke(Rgb565::RED, 1))
+                    .draw(target)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Where the `i`th of `count` equal bands across `length` starts.
+fn band(length: u32, i: usize, count: usize) -> u32 {
+    length * i as u32 / count as u32
+}
+
+/// Shows the test patterns until B is pressed. A steps through the
+/// patterns, X through the gamma curves and Y swaps the color order. The
+/// display keeps the last gamma curve and color order, for the game to
+/// save if it wants them kept.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub fn calibrate(hw: &mut Hardware) {
+    let mut pattern = TestPattern::ColorBars;
+    loop {
+        if hw.input.button_b.is_pressed() {
+            return;
+        }
+        if hw.input.button_a.is_pressed() {
+            pattern = pattern.next();
+        }
+        if hw.input.button_x.is_pressed() {
+            let gamma = hw.display.gamma();
+            let at = Gamma::ALL.iter().position(|&g| g == gamma).unwrap_or(0);
+            hw.display
+                .set_gamma(Gamma::ALL[(at + 1) % Gamma::ALL.len()]);
+        }
+        if hw.input.button_y.is_pressed() {
+            hw.display.set_color_order(match hw.display.color_order() {
+                ColorOrder::Rgb => ColorOrder::Bgr,
+                ColorOrder::Bgr => ColorOrder::Rgb,
+            });
+        }
+        let mut label = heapless::String::<32>::new();
+        let _ = write!(
+            label,
+            "{:?} {:?}",
+            hw.display.gamma(),
+            hw.display.color_order()
+        );
+        hw.draw(|display| {
+            let _ = pattern.draw(display);
+            let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+            let mut text = Text::with_baseline(&label, Point::new(2, 2), style, Baseline::Top);
+            text.character_style.background_color = Some(Rgb565::BLACK);
+            let _ = text.draw(display);
+        });
+    }
+}
@@ -0,0 +1,287 @@
+//! Meshes of colored triangles, for spinning logos, title screens and small
+//! 3D scenes.
+//!
+//! A `Scene` collects the triangles of a frame. `Scene::add` places a
+//! `Mesh` in the world, turns it into the camera's view and projects it,
+//! leaving out triangles that face away. `Scene::draw` then fills the
+//! triangles from the farthest to the nearest, the painter's algorithm,
+//! which is exact for a single convex mesh and close enough for most
+//! scenes without the RAM for a depth buffer.
+//!
+//! The camera looks along z with x to the right and y down the screen, and
+//! the front of a triangle is the side its vertices go clockwise around.
+//! There is no clipping, so triangles partly behind the camera or reaching
+//! far off the screen are left out too.
+
+use crate::display::{framebuffer, screen_height, Display, WIDTH};
+use crate::math::{Angle, Fixed, Matrix3, Vector3, I16F16};
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::{Rgb565, RgbColor};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+pub const MAX_TRIANGLES: usize = 256;
+
+// Fraction bits of projected screen positions.
+const SUBPIXEL_BITS: u32 = 4;
+// How far past the screen edges projected vertices may be. This keeps the
+// edge functions within 32 bits.
+const GUARD_BAND: i32 = 512;
+// Closest distance in front of the camera that is drawn.
+const NEAR: I16F16 = Fixed::from_bits(1 << 12);
+
+/// Triangles sharing vertices.
+pub struct Mesh<'a> {
+    pub vertices: &'a [Vector3],
+    /// Indices of the corners of each triangle in `vertices`.
+    pub triangles: &'a [[u16; 3]],
+    /// One color per triangle.
+    pub colors: &'a [Rgb565],
+    /// One color per vertex, blended across each triangle, used instead of
+    /// `colors` when set.
+    pub vertex_colors: Option<&'a [Rgb565]>,
+}
+
+/// A rotation followed by a translation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transform {
+    pub rotation: Matrix3,
+    pub translation: Vector3,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        rotation: Matrix3::IDENTITY,
+        translation: Vector3::ZERO,
+    };
+
+    pub fn new(rotation: Matrix3, translation: Vector3) -> Self {
+        Transform {
+            rotation,
+            translation,
+        }
+    }
+
+    /// The view of a camera at `position` turned by `rotation`, which must
+    /// be a pure rotation.
+    pub fn camera(position: Vector3, rotation: Matrix3) -> Self {
+        let inverse = rotation.transpose();
+        Transform::new(inverse, -(inverse * position))
+    }
+
+    pub fn apply(&self, v: Vector3) -> Vector3 {
+        self.rotation * v + self.translation
+    }
+
+    /// This transform followed by `next`.
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform::new(next.rotation * self.rotation, next.apply(self.translation))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Projected {
+    // Screen positions with `SUBPIXEL_BITS` fraction bits.
+    points: [(i32, i32); 3],
+    colors: [Rgb565; 3],
+    blend: bool,
+    // Sum of the vertex depths.
+    depth: I16F16,
+}
+
+pub struct Scene {
+    /// From world to camera space.
+    pub view: Transform,
+    /// Horizontal field of view, less than half a turn.
+    pub fov: Angle,
+    /// Direction towards the light in world space. Without a light the
+    /// triangles keep their colors.
+    pub light: Option<Vector3>,
+    /// Brightness of faces turned away from the light, out of 256.
+    pub ambient: u32,
+    triangles: heapless::Vec<Projected, MAX_TRIANGLES>,
+}
+
+#[allow(clippy::new_without_default)]
+impl Scene {
+    pub fn new() -> Self {
+        Scene {
+            view: Transform::IDENTITY,
+            fov: Angle::from_degrees(60),
+            light: None,
+            ambient: 64,
+            triangles: heapless::Vec::new(),
+        }
+    }
+
+    /// Adds the triangles of `mesh` placed in the world by `model`. Returns
+    /// false if some didn't fit in the `MAX_TRIANGLES` of a frame.
+    pub fn add(&mut self, mesh: &Mesh, model: &Transform) -> bool {
+        let transform = model.then(&self.view);
+        let half_fov = Angle(self.fov.0.min(0x7fff) / 2);
+        let focal = I16F16::from_int(WIDTH as i32 / 2) * half_fov.cos() / half_fov.sin();
+        let light = self
+            .light
+            .map(|light| (self.view.rotation * light).normalize());
+
+        for (i, triangle) in mesh.triangles.iter().enumerate() {
+            let v = triangle.map(|index| transform.apply(mesh.vertices[index as usize]));
+            if v.iter().any(|v| v.z < NEAR) {
+                continue;
+            }
+            let [Some(p0), Some(p1), Some(p2)] = v.map(|v| project(v, focal)) else {
+                continue;
+            };
+            if edge(p0, p1, p2) <= 0 {
+                // Facing away, or seen edge on.
+                continue;
+            }
+            let brightness = match light {
+                Some(light) => {
+                    let normal = (v[2] - v[0]).cross(v[1] - v[0]).normalize();
+                    let lit = normal.dot(light).to_bits().clamp(0, 1 << 16) as u32;
+                    self.ambient + (((256 - self.ambient.min(256)) * lit) >> 16)
+                }
+                None => 256,
+            };
+            let colors = match mesh.vertex_colors {
+                Some(colors) => triangle.map(|index| colors[index as usize]),
+                None => [mesh.colors[i]; 3],
+            }
+            .map(|color| scale(color, brightness));
+            let projected = Projected {
+                points: [p0, p1, p2],
+                colors,
+                blend: mesh.vertex_colors.is_some(),
+                depth: v[0].z + v[1].z + v[2].z,
+            };
+            if self.triangles.push(projected).is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Draws the triangles added since the last `draw`, far to near.
+    pub fn draw(&mut self, display: &mut Display) {
+        self.triangles
+            .sort_unstable_by_key(|triangle| core::cmp::Reverse(triangle.depth));
+        for triangle in &self.triangles {
+            if let Some(area) = rasterize(triangle) {
+                display.mark_dirty(area);
+            }
+        }
+        self.triangles.clear();
+    }
+}
+
+// Screen position of `v` in camera space, or `None` past the guard band.
+fn project(v: Vector3, focal: I16F16) -> Option<(i32, i32)> {
+    let axis = |c: I16F16, center: usize| {
+        // Pixels from the center in 16.16, in 64 bits as points near the
+        // camera project far off the screen.
+        let offset = c.to_bits() as i64 * focal.to_bits() as i64 / v.z.to_bits() as i64;
+        let position = ((center as i64) << 16) + offset;
+        if position < -((GUARD_BAND as i64) << 16)
+            || position > (((center * 2) as i64 + GUARD_BAND as i64) << 16)
+        {
+            return None;
+        }
+        Some((position >> (16 - SUBPIXEL_BITS)) as i32)
+    };
+    Some((axis(v.x, WIDTH / 2)?, axis(v.y, screen_height() / 2)?))
+}
+
+// Twice the signed area of the triangle `a`, `b`, `p`, positive when it
+// goes clockwise on the screen.
+fn edge(a: (i32, i32), b: (i32, i32), p: (i32, i32)) -> i32 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+fn scale(color: Rgb565, brightness: u32) -> Rgb565 {
+    let channel = |c: u8| ((c as u32 * brightness) >> 8) as u8;
+    Rgb565::new(channel(color.r()), channel(color.g()), channel(color.b()))
+}
+
+// Fills the pixels whose centers are inside `triangle` and returns the
+// screen area it covers.
+fn rasterize(triangle: &Projected) -> Option<Rectangle> {
+    let [p0, p1, p2] = triangle.points;
+    let one = 1 << SUBPIXEL_BITS;
+    let xs = [p0.0, p1.0, p2.0];
+    let ys = [p0.1, p1.1, p2.1];
+    let left = (xs.iter().min().unwrap() >> SUBPIXEL_BITS).max(0);
+    let right = (xs.iter().max().unwrap() >> SUBPIXEL_BITS).min(WIDTH as i32 - 1);
+    let top = (ys.iter().min().unwrap() >> SUBPIXEL_BITS).max(0);
+    let bottom = (ys.iter().max().unwrap() >> SUBPIXEL_BITS).min(screen_height() as i32 - 1);
+    if left > right || top > bottom {
+        return None;
+    }
+
+    // Each corner's weight is the edge function of the opposite side. They
+    // change by a constant for each pixel across and down.
+    let start = (
+        (left << SUBPIXEL_BITS) + one / 2,
+        (top << SUBPIXEL_BITS) + one / 2,
+    );
+    let sides = [(p1, p2), (p2, p0), (p0, p1)];
+    let mut row_weights = sides.map(|(a, b)| edge(a, b, start));
+    let step_x = sides.map(|(a, b)| -(b.1 - a.1) << SUBPIXEL_BITS);
+    let step_y = sides.map(|(a, b)| (b.0 - a.0) << SUBPIXEL_BITS);
+
+    // Red, green and blue in 16.16, with their steps across and down. Far
+    // outside thin triangles they may overflow, but wrapping arithmetic
+    // still gets the pixels inside right.
+    let area = edge(p0, p1, p2) as i64;
+    let channels = triangle.colors.map(|c| [c.r(), c.g(), c.b()]);
+    let gradient = |weights: [i32; 3], c: usize| -> i32 {
+        let sum: i64 = (0..3)
+            .map(|corner| weights[corner] as i64 * channels[corner][c] as i64)
+            .sum();
+        ((sum << 16) / area) as i32
+    };
+    let (mut row_color, color_x, color_y) = if triangle.blend {
+        (
+            [0, 1, 2].map(|c| gradient(row_weights, c)),
+            [0, 1, 2].map(|c| gradient(step_x, c)),
+            [0, 1, 2].map(|c| gradient(step_y, c)),
+        )
+    } else {
+        Default::default()
+    };
+    let flat = RawU16::from(triangle.colors[0]).into_inner().to_be();
+
+    let fb = framebuffer();
+    for y in top..=bottom {
+        let row = y as usize * WIDTH;
+        let mut weights = row_weights;
+        let mut color = row_color;
+        for x in left..=right {
+            if weights[0] | weights[1] | weights[2] >= 0 {
+                fb[row + x as usize] = if triangle.blend {
+                    let channel = |c: usize, max: u8| (color[c] >> 16).clamp(0, max as i32) as u8;
+                    let color = Rgb565::new(
+                        channel(0, Rgb565::MAX_R),
+                        channel(1, Rgb565::MAX_G),
+                        channel(2, Rgb565::MAX_B),
+                    );
+                    RawU16::from(color).into_inner().to_be()
+                } else {
+                    flat
+                };
+            }
+            for i in 0..3 {
+                weights[i] += step_x[i];
+                color[i] = color[i].wrapping_add(color_x[i]);
+            }
+        }
+        for i in 0..3 {
+            row_weights[i] += step_y[i];
+            row_color[i] = row_color[i].wrapping_add(color_y[i]);
+        }
+    }
+    Some(Rectangle::with_corners(
+        Point::new(left, top),
+        Point::new(right, bottom),
+    ))
+}
@@ -1,10 +1,79 @@
+use crate::compression::Codec;
 use crate::map::NUM_LAYERS;
 
+/// Default tile size, and the largest supported one. Tiles may also be 8 or
+/// 16 pixels square.
 pub const TILE_SIZE: i32 = 32;
 
+/// Returns true for the tile sizes the renderer supports.
+pub const fn is_valid_tile_size(size: i32) -> bool {
+    matches!(size, 8 | 16 | 32)
+}
+
+/// The most frame arena loading a tile takes: its compressed data copied
+/// from flash, and for a paletted tile the indices decompressed from it.
+pub const MAX_TILE_LOAD_SIZE: usize = {
+    let pixels = (TILE_SIZE * TILE_SIZE) as usize;
+    let colors = max_tile_data_size(pixels);
+    // Indices are packed at least two to a word.
+    let indexed = max_tile_data_size(pixels / 2) + pixels;
+    if colors > indexed {
+        colors
+    } else {
+        indexed
+    }
+};
+
+/// The most bytes of compressed data for `words` words, with any codec,
+/// padded to the whole number of flash words tile data is copied in.
+const fn max_tile_data_size(words: usize) -> usize {
+    let mut max = 0;
+    let mut i = 0;
+    while i < Codec::ALL.len() {
+        let len = Codec::ALL[i].max_compressed_len(words);
+        if len > max {
+            max = len;
+        }
+        i += 1;
+    }
+    max.next_multiple_of(2) * 2
+}
+
 pub struct Tile {
     pub data: &'static [u16],
+    /// How `data` is compressed.
+    pub codec: Codec,
+    /// One word per row, so also the tile's width and height.
     pub mask: &'static [u32],
+    /// Set when `data` holds packed palette indices rather than colors.
+    pub palette: Option<&'static Palette>,
+    /// The CRC-32 of `data`, checked as it is loaded.
+    pub crc: u32,
+    /// Set for mirror images of another tile sharing its data and mask,
+    /// which are flipped as they are loaded.
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+/// Colors of a palettized atlas, big-endian like tile data.
+pub struct Palette {
+    pub bits_per_pixel: u32,
+    pub colors: &'static [u16],
+}
+
+impl Palette {
+    /// Expands `indices`, packed with the first pixel in the low bits, into `output`.
+    pub fn expand(&self, indices: &[u16], output: &mut [u16]) {
+        let pixels_per_word = 16 / self.bits_per_pixel as usize;
+        let mask = (1 << self.bits_per_pixel) - 1;
+        for (word, output) in indices.iter().zip(output.chunks_mut(pixels_per_word)) {
+            let mut word = *word;
+            for pixel in output.iter_mut() {
+                *pixel = self.colors[(word & mask) as usize];
+                word >>= self.bits_per_pixel;
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -14,13 +83,22 @@ pub fn tile_id(tile: &Tile) -> TileId {
     TileId(tile as *const Tile as u32)
 }
 
+impl Tile {
+    pub fn size(&self) -> i32 {
+        self.mask.len() as i32
+    }
+}
+
 pub struct GenMapTile {
     pub layers: heapless::Vec<&'static Tile, NUM_LAYERS>,
 }
 
+/// A decompressed tile. The buffers fit the largest tiles; smaller ones
+/// use the start of them with rows `size` pixels apart.
 pub struct LoadedTile {
     pub data: [u16; (TILE_SIZE * TILE_SIZE) as usize],
     pub mask: [u32; TILE_SIZE as usize],
+    pub size: i32,
 }
 
 #[allow(clippy::new_without_default)]
@@ -29,369 +107,482 @@ impl LoadedTile {
         LoadedTile {
             data: [0; (TILE_SIZE * TILE_SIZE) as usize],
             mask: [0; TILE_SIZE as usize],
+            size: TILE_SIZE,
         }
     }
 }
 
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 mod device {
-    use crate::display::{framebuffer, Display, HEIGHT, WIDTH};
-    use crate::dma;
+    use crate::blit::{blit_dma, Flip, Image, Transparency};
+    use crate::compression;
+    use crate::dma::{self, ChainedDma, ControlBlock, DmaChannel, DmaError, DmaManager};
+    use crate::frame_arena::with_frame_arena;
+    use crate::logging;
     use crate::tile::*;
-    use crate::time;
+    use core::cell::{Cell, UnsafeCell};
+    use critical_section::Mutex;
     use embedded_graphics::prelude::*;
     use embedded_graphics::primitives::Rectangle;
 
-    fn load_tile(src: &Tile, dst: &mut LoadedTile, masked: bool) {
-        let mut buf = [0u16; (2 * TILE_SIZE * TILE_SIZE + 1) as usize];
+    // Magenta, big-endian like tile data, drawn in place of a corrupt tile.
+    const CORRUPT_COLOR: u16 = 0xf81f_u16.swap_bytes();
+
+    pub(crate) struct TileDma {
+        pub(crate) channel0: DmaChannel,
+        pub(crate) channel1: DmaChannel,
+    }
+
+    impl TileDma {
+        pub(crate) fn claim() -> Self {
+            TileDma {
+                channel0: DmaManager::claim(dma::CHANNEL_TILE0).unwrap(),
+                channel1: DmaManager::claim(dma::CHANNEL_TILE1).unwrap(),
+            }
+        }
+
+        /// Claims any two free channels, for drawing tiles outside the tile renderer.
+        pub(crate) fn claim_any() -> Result<Self, DmaError> {
+            Ok(TileDma {
+                channel0: DmaManager::claim_any()?,
+                channel1: DmaManager::claim_any()?,
+            })
+        }
+    }
+
+    pub(crate) fn load_tile(
+        tile_dma: &mut TileDma,
+        src: &Tile,
+        dst: &mut LoadedTile,
+        masked: bool,
+    ) {
+        load_tile_prefetched(tile_dma, src, None, dst, masked);
+    }
+
+    /// Like `load_tile`, but decompresses `prefetched`, a copy of the data
+    /// of `src` already in RAM, if there is one.
+    pub(crate) fn load_tile_prefetched(
+        tile_dma: &mut TileDma,
+        src: &Tile,
+        prefetched: Option<&[u16]>,
+        dst: &mut LoadedTile,
+        masked: bool,
+    ) {
+        assert!(is_valid_tile_size(src.size()));
         assert_eq!(src.data.len() % 2, 0);
-        assert!(src.data.len() < buf.len());
-        unsafe {
-            let mut dma_channel = dma::DmaChannel::new(dma::CHANNEL_TILE0);
-            dma::copy_flash_to_mem(
-                &mut dma_channel,
-                src.data.as_ptr() as u32,
-                buf.as_mut_ptr() as u32,
-                src.data.len() as u32 / 2,
-            );
-            decompress_dma(&buf[0..src.data.len()], &mut dst.data);
+        with_frame_arena(|arena| unsafe {
+            let src_address = src.data.as_ptr() as u32;
+            let (buf, crc) = match prefetched {
+                Some(data) => {
+                    let bytes =
+                        core::slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * 2);
+                    (data, Some(dma::crc32(bytes)))
+                }
+                None => 'copy: {
+                    // Copied from flash a word at a time, so allocated as words.
+                    let Some(words) = arena.alloc_slice(src.data.len() / 2, 0u32) else {
+                        // Decompressed from flash instead, which is slower.
+                        let bytes = core::slice::from_raw_parts(
+                            src.data.as_ptr() as *const u8,
+                            src.data.len() * 2,
+                        );
+                        break 'copy (src.data, Some(dma::crc32(bytes)));
+                    };
+                    let dst_address = words.as_mut_ptr() as u32;
+                    let count = words.len() as u32;
+                    // The data is checked when the sniffer is free, which it
+                    // is unless a checksum is being computed elsewhere.
+                    let crc = match dma::Sniffer::claim() {
+                        Some(mut sniffer) => Some(dma::copy_flash_to_mem_crc32(
+                            &mut tile_dma.channel0,
+                            &mut sniffer,
+                            src_address,
+                            dst_address,
+                            count,
+                        )),
+                        None => {
+                            dma::copy_flash_to_mem(
+                                &mut tile_dma.channel0,
+                                src_address,
+                                dst_address,
+                                count,
+                            );
+                            None
+                        }
+                    };
+                    let buf =
+                        core::slice::from_raw_parts(words.as_ptr() as *const u16, src.data.len());
+                    (buf, crc)
+                }
+            };
+            if crc.is_some_and(|crc| crc != src.crc) {
+                logging::error!("Corrupt tile data at {:#x}", src_address);
+                dst.data.fill(CORRUPT_COLOR);
+            } else if let Some(palette) = src.palette {
+                match arena.alloc_slice((TILE_SIZE * TILE_SIZE / 2) as usize, 0u16) {
+                    Some(indices) => {
+                        compression::decompress_with(
+                            src.codec,
+                            &mut tile_dma.channel0,
+                            &mut tile_dma.channel1,
+                            buf,
+                            indices,
+                        );
+                        palette.expand(indices, &mut dst.data);
+                    }
+                    None => {
+                        logging::error!("Frame arena full loading tile at {:#x}", src_address);
+                        dst.data.fill(CORRUPT_COLOR);
+                    }
+                }
+            } else {
+                compression::decompress_with(
+                    src.codec,
+                    &mut tile_dma.channel0,
+                    &mut tile_dma.channel1,
+                    buf,
+                    &mut dst.data,
+                );
+            }
             if masked {
                 dma::copy_flash_to_mem(
-                    &mut dma_channel,
+                    &mut tile_dma.channel0,
                     src.mask.as_ptr() as u32,
                     dst.mask.as_ptr() as u32,
-                    TILE_SIZE as u32,
+                    src.mask.len() as u32,
                 );
             }
-        }
+            dst.size = src.size();
+            dst.flip(src.flip_horizontal, src.flip_vertical);
+        })
     }
 
-    pub fn decompress_dma(input: &[u16], output: &mut [u16]) {
-        unsafe {
-            let mut dma_channel0 = dma::DmaChannel::new(dma::CHANNEL_TILE0);
-            let mut dma_channel1 = dma::DmaChannel::new(dma::CHANNEL_TILE1);
-            let mut src_ptr: *const u16 = input.as_ptr().add(1);
-            let end_ptr = input.as_ptr().add(input.len());
-            let mut dst_ptr: *mut u16 = output.as_mut_ptr();
-
-            while src_ptr < end_ptr {
-                let ctrl = *src_ptr;
-                src_ptr = src_ptr.add(1);
-                let data_length = ctrl & 0xff;
-                let run_length = ctrl >> 8;
-
-                if data_length == 0 {
-                    dst_ptr = dst_ptr.add(run_length as usize);
-                    continue;
-                }
+    /// The most tiles `TilePrefetcher` copies for one frame.
+    pub(crate) const MAX_PREFETCH_TILES: usize = 24;
 
-                dma_channel0.wait();
-                dma::start_copy_mem(
-                    &mut dma_channel0,
-                    src_ptr as u32,
-                    dst_ptr as u32,
-                    2,
-                    data_length as u32,
-                );
-                src_ptr = src_ptr.add(data_length as usize);
-                dst_ptr = dst_ptr.add(data_length as usize);
-
-                if run_length > 0 {
-                    dma_channel1.wait();
-                    dma::start_set_mem(
-                        &mut dma_channel1,
-                        src_ptr.offset(-1) as u32,
-                        dst_ptr as u32,
-                        2,
-                        run_length as u32,
-                    );
-                    dst_ptr = dst_ptr.add(run_length as usize);
-                }
-            }
+    #[derive(Clone, Copy)]
+    struct PrefetchEntry {
+        id: TileId,
+        // Where the data is in the buffer, in words.
+        offset: usize,
+        len: usize,
+    }
 
-            dma_channel0.wait();
-            dma_channel1.wait();
-        }
+    /// Copies the compressed data of tiles about to come into view from
+    /// flash to RAM by DMA while the current frame is drawn, so that the
+    /// next frame decompresses them without waiting for flash.
+    ///
+    /// Each of the two buffers holds `WORDS` words. One is read by `get`
+    /// while the other is filled, and `finish` swaps them at the end of the
+    /// frame once the copies are done.
+    pub(crate) struct TilePrefetcher<const WORDS: usize> {
+        buffers: [[u16; WORDS]; 2],
+        entries: [heapless::Vec<PrefetchEntry, MAX_PREFETCH_TILES>; 2],
+        blocks: [ControlBlock; MAX_PREFETCH_TILES + 1],
+        // The buffer read this frame.
+        current: usize,
+        // Claimed on first use.
+        dma: Option<ChainedDma>,
     }
 
-    fn draw_opaque_tile(display: &mut Display, tile: &LoadedTile, dst: Point, size: Size) -> bool {
-        let clipped_dst = Rectangle::new(dst, size).intersection(&display.bounding_box());
-        let mut dma_channel = unsafe { dma::DmaChannel::new(dma::CHANNEL_TILE0) };
-
-        let src = clipped_dst.top_left - dst;
-        let dst = clipped_dst.top_left;
-
-        let src_data = &tile.data;
-        let dst_data = framebuffer();
-        let src_index = src.x + src.y * TILE_SIZE;
-        let dst_index = dst.x + dst.y * WIDTH as i32;
-        unsafe {
-            let mut src_ptr = src_data.as_ptr().add(src_index as usize);
-            let mut dst_ptr = dst_data.as_mut_ptr().add(dst_index as usize);
-            for _ in 0..clipped_dst.size.height {
-                dma_channel.wait();
-                dma::start_copy_mem(
-                    &mut dma_channel,
-                    src_ptr as u32,
-                    dst_ptr as u32,
-                    4,
-                    clipped_dst.size.width / 2,
-                );
-                src_ptr = src_ptr.add(TILE_SIZE as usize);
-                dst_ptr = dst_ptr.add(WIDTH);
+    impl<const WORDS: usize> TilePrefetcher<WORDS> {
+        pub(crate) fn new() -> Self {
+            TilePrefetcher {
+                buffers: [[0; WORDS]; 2],
+                entries: [heapless::Vec::new(), heapless::Vec::new()],
+                blocks: [ControlBlock::END; MAX_PREFETCH_TILES + 1],
+                current: 0,
+                dma: None,
             }
         }
 
-        dma_channel.wait();
-        clipped_dst.size == size
-    }
+        /// The data of `tile`, if it was copied during the last frame.
+        pub(crate) fn get(&self, tile: &Tile) -> Option<&[u16]> {
+            let id = tile_id(tile);
+            let entry = self.entries[self.current]
+                .iter()
+                .find(|entry| entry.id == id)?;
+            Some(&self.buffers[self.current][entry.offset..entry.offset + entry.len])
+        }
 
-    fn draw_transparent_tile(
-        display: &mut Display,
-        tile: &LoadedTile,
-        dst: Point,
-        size: Size,
-    ) -> bool {
-        let clipped_dst = Rectangle::new(dst, size).intersection(&display.bounding_box());
-        let src = clipped_dst.top_left - dst;
-        let dst = clipped_dst.top_left;
-
-        unsafe {
-            let mut dma_channel = dma::DmaChannel::new(dma::CHANNEL_TILE0);
-            let mut src_ptr: *const u16 = tile.data.as_ptr();
-            let mut dst_ptr: *mut u16 = framebuffer().as_mut_ptr();
-            let mut mask_ptr: *const u32 = tile.mask.as_ptr().add(src.y as usize);
-            src_ptr = src_ptr.add((src.x + src.y * TILE_SIZE) as usize);
-            dst_ptr = dst_ptr.add((dst.x + dst.y * WIDTH as i32) as usize);
-            for _ in 0..clipped_dst.size.height {
-                let w = clipped_dst.size.width;
-                let mut mask = *mask_ptr;
-                mask >>= src.x;
-                if w < 32 {
-                    mask &= (1 << w) - 1;
-                }
-                let mut x = 0;
-                while mask != 0 {
-                    const LOOKAHEAD: u32 = 0x7;
-                    let n = if mask & LOOKAHEAD == LOOKAHEAD {
-                        let n = mask.trailing_ones();
-                        dma_channel.wait();
-                        dma::start_copy_mem(&mut dma_channel, src_ptr as u32, dst_ptr as u32, 2, n);
-                        n
-                    } else if mask & LOOKAHEAD == 0x0 {
-                        mask.trailing_zeros()
-                    } else {
-                        let color = *src_ptr;
-                        if mask & 1 != 0 {
-                            *dst_ptr = color;
-                        }
-                        1
-                    };
-                    src_ptr = src_ptr.add(n as usize);
-                    dst_ptr = dst_ptr.add(n as usize);
-                    if n == 32 {
-                        mask = 0;
-                    } else {
-                        mask >>= n;
-                    }
-                    x += n;
+        /// Starts copying the data of `tiles`, as many as fit, for the next
+        /// frame. Returns how many are copied.
+        pub(crate) fn start(&mut self, tiles: impl Iterator<Item = &'static Tile>) -> usize {
+            let next = 1 - self.current;
+            let entries = &mut self.entries[next];
+            entries.clear();
+            if WORDS == 0 {
+                return 0;
+            }
+            let mut used = 0;
+            for tile in tiles {
+                let id = tile_id(tile);
+                let len = tile.data.len();
+                if entries.is_full() {
+                    break;
+                } else if used + len > WORDS || entries.iter().any(|entry| entry.id == id) {
+                    continue;
                 }
-                src_ptr = src_ptr.add(TILE_SIZE as usize - x as usize);
-                dst_ptr = dst_ptr.add(WIDTH - x as usize);
-                mask_ptr = mask_ptr.add(1);
+                let dst = self.buffers[next][used..].as_mut_ptr() as u32;
+                // Tile data is only aligned to half words.
+                self.blocks[entries.len()] =
+                    ControlBlock::copy_mem(tile.data.as_ptr() as u32, dst, 2, len as u32);
+                let _ = entries.push(PrefetchEntry {
+                    id,
+                    offset: used,
+                    len,
+                });
+                used += len;
+            }
+            let count = entries.len();
+            if count == 0 {
+                return 0;
             }
-            dma_channel.wait();
+            if self.dma.is_none() {
+                self.dma = ChainedDma::claim_any().ok();
+            }
+            let Some(dma) = &mut self.dma else {
+                entries.clear();
+                return 0;
+            };
+            self.blocks[count] = ControlBlock::END;
+            // `finish` waits for the copies, and `self` is borrowed until
+            // then by the renderer drawing the frame.
+            unsafe { dma.start(&mut self.blocks[..=count]) };
+            count
         }
 
-        clipped_dst.size == size
-    }
-
-    fn copy_tile(display: &mut Display, src: Point, dst: Point, size: Size) {
-        let clipped_dst = Rectangle::new(dst, size).intersection(&display.bounding_box());
-        let mut dma_channel = unsafe { dma::DmaChannel::new(dma::CHANNEL_TILE1) };
-        let fb_data = framebuffer();
-
-        let src = src + clipped_dst.top_left - dst;
-        let dst = clipped_dst.top_left;
-        let size = clipped_dst.size;
-
-        let mut src_index = src.x + src.y * WIDTH as i32;
-        let mut dst_index = dst.x + dst.y * WIDTH as i32;
-        for _ in 0..size.height {
-            unsafe {
-                let src_addr = fb_data.as_ptr().add(src_index as usize) as u32;
-                let dst_addr = fb_data.as_mut_ptr().add(dst_index as usize) as u32;
-                dma_channel.wait();
-                dma::start_copy_mem(&mut dma_channel, src_addr, dst_addr, 2, size.width);
+        /// Waits for the copies started this frame and makes them available
+        /// to `get` for the next one.
+        pub(crate) fn finish(&mut self) {
+            if let Some(dma) = &self.dma {
+                dma.wait();
             }
-            src_index += WIDTH as i32;
-            dst_index += WIDTH as i32;
+            self.current = 1 - self.current;
         }
-        dma_channel.wait();
     }
 
-    pub fn draw<F>(display: &mut Display, position: Point, map_generator: &F, verbose: bool)
-    where
-        F: Fn(Point) -> GenMapTile,
-    {
-        let subtile_mask = 32 - 1;
-        let enable_tile_cache = true;
-
-        let mut drawn_y: i32 = 0;
-        let mut world_y = position.y;
-        let subtile_y = position.y & subtile_mask;
-
-        let mut tile_cache = heapless::LinearMap::<TileId, Point, 64>::new();
-        let mut base_tile_cache_misses = 0;
-        let mut base_tile_cache_lookups = 0;
-        let mut base_tile_cache_insert_failures = 0;
-
-        let mut overlay_tile_cache = heapless::LinearMap::<TileId, LoadedTile, 4>::new();
-        let mut overlay_tile_cache_misses = 0;
-        let mut overlay_tile_cache_lookups = 0;
-        let mut overlay_tile_cache_insert_failures = 0;
-
-        let mut missing_transparent_tiles = heapless::Vec::<(Point, GenMapTile), 64>::new();
-
-        let mut slow_draw = false;
-        let mut draw_time = 0;
-        let mut load_time = 0;
-        loop {
-            let progress = display.flush_progress();
-            let safe_y = (progress as i32 - WIDTH as i32 + 1) / WIDTH as i32;
-            if safe_y - drawn_y < 32 && progress < (WIDTH * HEIGHT) {
-                continue;
-            } else if safe_y - drawn_y > 64 {
-                slow_draw = true;
-            }
-            let draw_start_time = time::time_us();
-
-            let screen_y = drawn_y - subtile_y;
-
-            let subtile_x = position.x & subtile_mask;
-
-            for screen_x in (-subtile_x..(WIDTH as i32)).step_by(32) {
-                let world_x = position.x + screen_x;
-                let map_coord = Point::new(world_x & !subtile_mask, world_y & !subtile_mask);
-                let screen_coord = Point::new(screen_x, screen_y);
-                let map_tile = map_generator(map_coord);
-                let base_tile = map_tile.layers[0];
-                base_tile_cache_lookups += 1;
-                if let Some(cached_src) = tile_cache.get(&tile_id(base_tile)) {
-                    copy_tile(display, *cached_src, screen_coord, Size::new(32, 32));
-                    for overlay_tile in map_tile.layers[1..].iter() {
-                        overlay_tile_cache_lookups += 1;
-                        if let Some(cached_overlay_tile) =
-                            overlay_tile_cache.get(&tile_id(overlay_tile))
-                        {
-                            draw_transparent_tile(
-                                display,
-                                cached_overlay_tile,
-                                screen_coord,
-                                Size::new(32, 32),
-                            );
-                        } else {
-                            overlay_tile_cache_misses += 1;
-                            let mut loaded_tile = LoadedTile::new();
-                            let start_time = time::time_us();
-                            load_tile(overlay_tile, &mut loaded_tile, true);
-                            load_time += time::time_us() - start_time;
-                            draw_transparent_tile(
-                                display,
-                                &loaded_tile,
-                                screen_coord,
-                                Size::new(32, 32),
-                            );
-                            if overlay_tile_cache
-                                .insert(tile_id(overlay_tile), loaded_tile)
-                                .is_err()
-                            {
-                                overlay_tile_cache_insert_failures += 1;
-                            }
-                        }
-                    }
-                } else {
-                    base_tile_cache_misses += 1;
-                    let mut loaded_tile = LoadedTile::new();
-                    let start_time = time::time_us();
-                    load_tile(base_tile, &mut loaded_tile, false);
-                    load_time += time::time_us() - start_time;
-                    if (draw_opaque_tile(display, &loaded_tile, screen_coord, Size::new(32, 32))
-                        || (screen_x >= 0 && screen_y < 0))
-                        && enable_tile_cache
-                        && tile_cache.insert(tile_id(base_tile), screen_coord).is_err()
-                    {
-                        base_tile_cache_insert_failures += 1;
-                    }
-                    if map_tile.layers.len() > 1 {
-                        let _ = missing_transparent_tiles.push((screen_coord, map_tile));
-                    }
+    /// RAM set aside for `TileCache`, in bytes. With `double-buffer` only
+    /// one tile fits next to the second framebuffer.
+    #[cfg(not(feature = "double-buffer"))]
+    pub const TILE_CACHE_SIZE: usize = 32 * 1024;
+    #[cfg(feature = "double-buffer")]
+    pub const TILE_CACHE_SIZE: usize = 4 * 1024;
+    const TILE_CACHE_SLOTS: usize = TILE_CACHE_SIZE / core::mem::size_of::<LoadedTile>();
+
+    struct CacheRegion(UnsafeCell<[LoadedTile; TILE_CACHE_SLOTS]>);
+
+    // Only the holder of the claim touches the region.
+    unsafe impl Sync for CacheRegion {}
+
+    // Zeroed, so that it takes no flash.
+    static CACHE_REGION: CacheRegion =
+        CacheRegion(UnsafeCell::new([LoadedTile::EMPTY; TILE_CACHE_SLOTS]));
+    static CACHE_CLAIMED: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+    #[derive(Clone, Copy)]
+    struct CacheEntry {
+        id: TileId,
+        last_used: u32,
+    }
+
+    /// How `TileCache::get_or_load` found a tile.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) enum CacheLookup {
+        Hit,
+        /// Loaded into a free slot.
+        Loaded,
+        /// Loaded in place of the least recently used tile.
+        Replaced,
+    }
+
+    /// Decompressed tiles kept across frames in a region of RAM of its own,
+    /// so that tiles staying on screen are decompressed once rather than
+    /// every frame. The cache uses as many slots of the region as fit in its
+    /// budget, and once they are all taken the least recently used tile
+    /// makes room for the next one. Tiles are loaded with their masks, so an
+    /// entry serves both opaque and transparent drawing.
+    pub(crate) struct TileCache {
+        slots: &'static mut [LoadedTile; TILE_CACHE_SLOTS],
+        // The tile in each slot, in slot order.
+        entries: heapless::Vec<CacheEntry, TILE_CACHE_SLOTS>,
+        capacity: usize,
+        // Counts lookups, for the age of entries.
+        clock: u32,
+    }
+
+    impl TileCache {
+        /// Claims the region, using `budget` bytes of it. Returns `None` if
+        /// another cache has it.
+        pub(crate) fn claim(budget: usize) -> Option<Self> {
+            critical_section::with(|cs| {
+                let claimed = CACHE_CLAIMED.borrow(cs);
+                if claimed.get() {
+                    return None;
                 }
-            }
+                claimed.set(true);
+                let mut cache = TileCache {
+                    slots: unsafe { &mut *CACHE_REGION.0.get() },
+                    entries: heapless::Vec::new(),
+                    capacity: 0,
+                    clock: 0,
+                };
+                cache.set_budget(budget);
+                Some(cache)
+            })
+        }
+
+        /// Uses `budget` bytes of the region, at least one tile and at most
+        /// `TILE_CACHE_SIZE`, dropping tiles that no longer fit.
+        pub(crate) fn set_budget(&mut self, budget: usize) {
+            self.capacity =
+                (budget / core::mem::size_of::<LoadedTile>()).clamp(1, TILE_CACHE_SLOTS);
+            self.entries.truncate(self.capacity);
+        }
 
-            draw_time += time::time_us() - draw_start_time;
+        /// The most tiles the cache holds.
+        pub(crate) fn capacity(&self) -> usize {
+            self.capacity
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.entries.len()
+        }
 
-            drawn_y += 32;
-            world_y += 32;
-            if screen_y < 0 {
-                tile_cache.clear();
-            } else if screen_y + 32 >= HEIGHT as i32 {
-                break;
+        /// The decompressed `tile`, which `load` fills in first unless it is
+        /// already cached.
+        pub(crate) fn get_or_load(
+            &mut self,
+            tile: &Tile,
+            load: impl FnOnce(&mut LoadedTile),
+        ) -> (&LoadedTile, CacheLookup) {
+            let id = tile_id(tile);
+            self.clock = self.clock.wrapping_add(1);
+            let clock = self.clock;
+            if let Some(index) = self.entries.iter().position(|entry| entry.id == id) {
+                self.entries[index].last_used = clock;
+                return (&self.slots[index], CacheLookup::Hit);
             }
+            let entry = CacheEntry {
+                id,
+                last_used: clock,
+            };
+            let (index, lookup) = if self.entries.len() < self.capacity {
+                let _ = self.entries.push(entry);
+                (self.entries.len() - 1, CacheLookup::Loaded)
+            } else {
+                let (index, _) = self
+                    .entries
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(_, entry)| clock.wrapping_sub(entry.last_used))
+                    .unwrap();
+                self.entries[index] = entry;
+                (index, CacheLookup::Replaced)
+            };
+            load(&mut self.slots[index]);
+            (&self.slots[index], lookup)
         }
+    }
 
-        let draw_start_time = time::time_us();
-        for (screen_coord, map_tile) in missing_transparent_tiles {
-            for overlay_tile in map_tile.layers[1..].iter() {
-                overlay_tile_cache_lookups += 1;
-                if let Some(cached_overlay_tile) = overlay_tile_cache.get(&tile_id(overlay_tile)) {
-                    draw_transparent_tile(
-                        display,
-                        cached_overlay_tile,
-                        screen_coord,
-                        Size::new(32, 32),
-                    );
-                } else {
-                    overlay_tile_cache_misses += 1;
-                    let mut loaded_tile = LoadedTile::new();
-                    let start_time = time::time_us();
-                    load_tile(overlay_tile, &mut loaded_tile, true);
-                    load_time += time::time_us() - start_time;
-                    draw_transparent_tile(display, &loaded_tile, screen_coord, Size::new(32, 32));
-                    if overlay_tile_cache
-                        .insert(tile_id(overlay_tile), loaded_tile)
-                        .is_err()
-                    {
-                        overlay_tile_cache_insert_failures += 1;
-                    }
+    impl Drop for TileCache {
+        fn drop(&mut self) {
+            critical_section::with(|cs| CACHE_CLAIMED.borrow(cs).set(false));
+        }
+    }
+
+    impl LoadedTile {
+        const EMPTY: LoadedTile = LoadedTile {
+            data: [0; (TILE_SIZE * TILE_SIZE) as usize],
+            mask: [0; TILE_SIZE as usize],
+            size: 0,
+        };
+
+        fn flip(&mut self, horizontal: bool, vertical: bool) {
+            let size = self.size as usize;
+            let data = &mut self.data[..size * size];
+            let mask = &mut self.mask[..size];
+            if horizontal {
+                data.chunks_exact_mut(size).for_each(|row| row.reverse());
+                for m in mask.iter_mut() {
+                    *m = m.reverse_bits() >> (32 - size);
                 }
             }
+            if vertical {
+                for y in 0..size / 2 {
+                    let (top, bottom) = data.split_at_mut((size - 1 - y) * size);
+                    top[y * size..(y + 1) * size].swap_with_slice(&mut bottom[..size]);
+                }
+                mask.reverse();
+            }
         }
-        draw_time += time::time_us() - draw_start_time;
-
-        if verbose {
-            log::info!("draw_time={}us load_time={}us", draw_time, load_time);
-            log::info!("position: {:?}", position);
-            log::info!(
-                "Base tile cache: misses={} lookups={} insert_failures={} miss_rate={:.2}%",
-                base_tile_cache_misses,
-                base_tile_cache_lookups,
-                base_tile_cache_insert_failures,
-                base_tile_cache_misses as f32 / base_tile_cache_lookups as f32 * 100.0
-            );
-            log::info!(
-                "Overlay tile cache: misses={} lookups={} insert_failures={} miss_rate={:.2}%",
-                overlay_tile_cache_misses,
-                overlay_tile_cache_lookups,
-                overlay_tile_cache_insert_failures,
-                overlay_tile_cache_misses as f32 / overlay_tile_cache_lookups as f32 * 100.0
-            );
-            if slow_draw {
-                log::info!("Slow draw detected");
+
+        fn image<'a>(&'a self, transparency: Transparency<'a>) -> Image<'a> {
+            Image {
+                data: &self.data[..(self.size * self.size) as usize],
+                width: self.size as u32,
+                big_endian: true,
+                transparency,
             }
         }
     }
+
+    pub(crate) fn draw_opaque_tile(
+        dma_channel: &mut DmaChannel,
+        tile: &LoadedTile,
+        dst: Point,
+        size: Size,
+    ) -> bool {
+        let image = tile.image(Transparency::Opaque);
+        let src = Rectangle::new(Point::zero(), size);
+        blit_dma(dma_channel, &image, &src, dst, Flip::NONE).map(|area| area.size) == Some(size)
+    }
+
+    pub(crate) fn draw_transparent_tile(
+        dma_channel: &mut DmaChannel,
+        tile: &LoadedTile,
+        dst: Point,
+        size: Size,
+    ) -> bool {
+        let image = tile.image(Transparency::Mask(&tile.mask));
+        let src = Rectangle::new(Point::zero(), size);
+        blit_dma(dma_channel, &image, &src, dst, Flip::NONE).map(|area| area.size) == Some(size)
+    }
+
+    /// Draws the part of `tile` at `dst` that falls inside `clip`.
+    pub(crate) fn draw_tile_clipped(
+        dma_channel: &mut DmaChannel,
+        tile: &LoadedTile,
+        dst: Point,
+        clip: &Rectangle,
+        transparent: bool,
+    ) {
+        let size = Size::new(tile.size as u32, tile.size as u32);
+        let area = Rectangle::new(dst, size).intersection(clip);
+        if area.is_zero_sized() {
+            return;
+        }
+        let transparency = if transparent {
+            Transparency::Mask(&tile.mask)
+        } else {
+            Transparency::Opaque
+        };
+        let src = Rectangle::new(area.top_left - dst, area.size);
+        blit_dma(
+            dma_channel,
+            &tile.image(transparency),
+            &src,
+            area.top_left,
+            Flip::NONE,
+        );
+    }
 }
 
 #[cfg(all(target_arch = "arm", target_os = "none"))]
-pub use device::draw;
+pub(crate) use device::{
+    draw_opaque_tile, draw_tile_clipped, draw_transparent_tile, load_tile, load_tile_prefetched,
+    CacheLookup, TileCache, TileDma, TilePrefetcher,
+};
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub use device::TILE_CACHE_SIZE;
@@ -1,10 +1,518 @@
+//! The microsecond timer, and its four alarms, which call functions from
+//! their interrupts at a given time or rate.
+//!
+//! Alarms are claimed like DMA channels. The audio mixer, the LED effects
+//! and the button sampling each have one of their own, and games get the
+//! rest from `Alarm::claim_any`. The audio alarm's interrupt is handled in
+//! `audio`, which sets it with `arm_at` rather than going through the
+//! critical sections of the shared alarm table.
+
+use crate::math::I16F16;
+use core::cell::{Cell, RefCell};
+use critical_section::Mutex;
+use rp_pico::hal::pac;
+use rp_pico::hal::pac::interrupt;
+
+/// Set by the audio mixer with `Alarm::arm_at`. Callbacks scheduled on it
+/// are never called, as its interrupt is handled in `audio`.
+pub const ALARM_AUDIO: usize = 0;
+pub const ALARM_LED: usize = 1;
+pub const ALARM_INPUT: usize = 2;
+
+pub const NUM_ALARMS: usize = 4;
+
+/// Alarms with a fixed role in the library, skipped by `Alarm::claim_any`.
+const RESERVED_ALARMS: u8 = (1 << ALARM_AUDIO) | (1 << ALARM_LED) | (1 << ALARM_INPUT);
+
+static CLAIMED_ALARMS: Mutex<Cell<u8>> = Mutex::new(Cell::new(0));
+
+const INTERRUPTS: [pac::Interrupt; NUM_ALARMS] = [
+    pac::Interrupt::TIMER_IRQ_0,
+    pac::Interrupt::TIMER_IRQ_1,
+    pac::Interrupt::TIMER_IRQ_2,
+    pac::Interrupt::TIMER_IRQ_3,
+];
+
+/// A point in time, in microseconds since startup. It takes 584 thousand
+/// years to wrap, so unlike `time_us` instants can be compared and
+/// subtracted directly.
+pub type Instant = fugit::TimerInstantU64<1_000_000>;
+
+/// A span of time in microseconds, made with `fugit::ExtU32` or
+/// `fugit::ExtU64`, e.g. `250.millis()`.
+pub type Duration = fugit::MicrosDurationU64;
+
+/// The low word of the timer: microseconds since startup, wrapping every
+/// 71 minutes. Compare with `wrapping_sub`, or use `now`.
 pub fn time_us() -> u32 {
     unsafe { (*rp2040_pac::TIMER::PTR).timerawl.read().bits() }
 }
 
+/// Microseconds since startup.
 pub fn time_us64() -> u64 {
-    unsafe {
-        (*rp2040_pac::TIMER::PTR).timelr.read().bits() as u64
-            | (((*rp2040_pac::TIMER::PTR).timehr.read().bits() as u64) << 32)
+    // The latched TIMELR and TIMEHR pair is shared by both cores and every
+    // interrupt, so a read between the two would pair the wrong words. The
+    // raw ones are read again instead if the high word moved on.
+    let timer = timer();
+    loop {
+        let high = timer.timerawh.read().bits();
+        let low = timer.timerawl.read().bits();
+        if timer.timerawh.read().bits() == high {
+            return (high as u64) << 32 | low as u64;
+        }
+    }
+}
+
+pub fn now() -> Instant {
+    Instant::from_ticks(time_us64())
+}
+
+/// The time since `instant`, or zero if it's in the future.
+pub fn elapsed(instant: Instant) -> Duration {
+    now()
+        .checked_duration_since(instant)
+        .unwrap_or(Duration::from_ticks(0))
+}
+
+/// Waits for `duration` in an async task, letting the others run.
+#[cfg(feature = "async")]
+pub async fn sleep(duration: Duration) {
+    sleep_until(now() + duration).await
+}
+
+#[cfg(feature = "async")]
+pub async fn sleep_until(instant: Instant) {
+    core::future::poll_fn(|cx| {
+        if now() >= instant {
+            return core::task::Poll::Ready(());
+        }
+        crate::executor::wake_at(instant.ticks(), cx.waker());
+        core::task::Poll::Pending
+    })
+    .await
+}
+
+// Frames that `FrameClock::stats` covers.
+const STATS_FRAMES: usize = 64;
+
+/// Frame times over the last 64 frames, in microseconds, as measured, before
+/// any clamping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub min_us: u32,
+    pub max_us: u32,
+    pub mean_us: u32,
+    /// How far frame times are from the mean on average.
+    pub jitter_us: u32,
+}
+
+/// Measures the time between frames, for games moving things by the time
+/// passed rather than by the frame. `tick` is called once per frame.
+///
+/// Deltas longer than the maximum, 100 ms unless set, are clamped to it,
+/// so that a stall such as a flash write doesn't make physics take one huge
+/// step.
+pub struct FrameClock {
+    last: Instant,
+    max_delta: Duration,
+    delta: Duration,
+    // Sum of the clamped deltas, which whole milliseconds are counted from.
+    total_us: u64,
+    delta_ms: u32,
+    frame: u32,
+    // The last frame times as measured, by frame number modulo their count.
+    history: [u32; STATS_FRAMES],
+}
+
+impl FrameClock {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        FrameClock {
+            last: now(),
+            max_delta: Duration::millis(100),
+            delta: Duration::from_ticks(0),
+            total_us: 0,
+            delta_ms: 0,
+            frame: 0,
+            history: [0; STATS_FRAMES],
+        }
+    }
+
+    pub fn set_max_delta(&mut self, max_delta: Duration) {
+        self.max_delta = max_delta;
+    }
+
+    /// Starts a new frame and returns the time since the last one, or since
+    /// the clock was made for the first.
+    pub fn tick(&mut self) -> Duration {
+        let now = now();
+        let measured = now
+            .checked_duration_since(self.last)
+            .unwrap_or(Duration::from_ticks(0));
+        self.last = now;
+        self.history[self.frame as usize % STATS_FRAMES] =
+            measured.ticks().min(u32::MAX as u64) as u32;
+        self.frame = self.frame.wrapping_add(1);
+        self.delta = measured.min(self.max_delta);
+        let total_us = self.total_us + self.delta.ticks();
+        self.delta_ms = (total_us / 1000 - self.total_us / 1000) as u32;
+        self.total_us = total_us;
+        self.delta
+    }
+
+    /// The time between the last two ticks, clamped to the maximum.
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_us(&self) -> u32 {
+        self.delta.ticks() as u32
+    }
+
+    /// Whole milliseconds of the delta. The rest carries over to later
+    /// frames, so these add up to the time passed.
+    pub fn delta_ms(&self) -> u32 {
+        self.delta_ms
+    }
+
+    /// The delta in seconds.
+    pub fn delta_seconds(&self) -> I16F16 {
+        I16F16::from_bits(((self.delta.ticks() << 16) / 1_000_000) as i32)
+    }
+
+    /// Ticks so far.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let count = (self.frame as usize).min(STATS_FRAMES);
+        if count == 0 {
+            return FrameStats::default();
+        }
+        let times = &self.history[..count];
+        let mean_us = (times.iter().map(|&t| t as u64).sum::<u64>() / count as u64) as u32;
+        FrameStats {
+            min_us: *times.iter().min().unwrap(),
+            max_us: *times.iter().max().unwrap(),
+            mean_us,
+            jitter_us: (times
+                .iter()
+                .map(|&t| t.abs_diff(mean_us) as u64)
+                .sum::<u64>()
+                / count as u64) as u32,
+        }
+    }
+}
+
+/// Called from the interrupt of an alarm. Returning false stops a
+/// periodic alarm.
+pub type AlarmCallback = fn() -> bool;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmError {
+    InvalidAlarm(usize),
+    AlreadyClaimed(usize),
+    NoFreeAlarm,
+}
+
+/// The priority of the interrupt of an alarm. A higher one interrupts the
+/// callbacks of lower ones, and equal ones wait for each other. All start
+/// at `Highest`, like the other interrupts, which the callbacks taking
+/// critical sections also hold off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmPriority {
+    Highest,
+    High,
+    Low,
+    Lowest,
+}
+
+#[derive(Clone, Copy)]
+struct AlarmState {
+    callback: Option<AlarmCallback>,
+    // Time between calls: whole microseconds and a fraction of
+    // `period_frac / divisor` more, which adds up in `frac`. Zero for a
+    // single call.
+    period_us: u32,
+    period_frac: u32,
+    divisor: u32,
+    frac: u32,
+    next_us: u32,
+    // Changed whenever the alarm is set or cancelled, so that the interrupt
+    // can tell whether its callback did either.
+    generation: u32,
+}
+
+impl AlarmState {
+    const IDLE: AlarmState = AlarmState {
+        callback: None,
+        period_us: 0,
+        period_frac: 0,
+        divisor: 1,
+        frac: 0,
+        next_us: 0,
+        generation: 0,
+    };
+
+    fn period_step(&mut self) -> u32 {
+        self.frac += self.period_frac;
+        if self.frac >= self.divisor {
+            self.frac -= self.divisor;
+            self.period_us + 1
+        } else {
+            self.period_us
+        }
+    }
+}
+
+static ALARMS: Mutex<RefCell<[AlarmState; NUM_ALARMS]>> =
+    Mutex::new(RefCell::new([AlarmState::IDLE; NUM_ALARMS]));
+
+fn timer() -> &'static pac::timer::RegisterBlock {
+    unsafe { &*pac::TIMER::PTR }
+}
+
+// Sets the hardware alarm `index` to fire at `time_us`.
+unsafe fn arm(index: usize, time_us: u32) {
+    let timer = timer();
+    timer.inte.modify(|r, w| w.bits(r.bits() | (1 << index)));
+    match index {
+        0 => timer.alarm0.write(|w| w.bits(time_us)),
+        1 => timer.alarm1.write(|w| w.bits(time_us)),
+        2 => timer.alarm2.write(|w| w.bits(time_us)),
+        _ => timer.alarm3.write(|w| w.bits(time_us)),
+    }
+    // An alarm only fires when the low word of the timer matches, so one
+    // already past is forced rather than left until the timer wraps.
+    if (time_us.wrapping_sub(self::time_us()) as i32) <= 0 {
+        timer.intf.modify(|r, w| w.bits(r.bits() | (1 << index)));
+    }
+}
+
+// Stops the hardware alarm `index` and clears its interrupt.
+unsafe fn disarm(index: usize) {
+    let timer = timer();
+    timer.armed.write(|w| w.bits(1 << index));
+    timer.intf.modify(|r, w| w.bits(r.bits() & !(1 << index)));
+    timer.intr.write(|w| w.bits(1 << index));
+}
+
+/// One of the four alarms of the timer. A claimed alarm is cancelled and
+/// released when dropped.
+pub struct Alarm {
+    index: usize,
+    claimed: bool,
+}
+
+impl Alarm {
+    pub fn claim(index: usize) -> Result<Alarm, AlarmError> {
+        if index >= NUM_ALARMS {
+            return Err(AlarmError::InvalidAlarm(index));
+        }
+        critical_section::with(|cs| {
+            let claimed = CLAIMED_ALARMS.borrow(cs);
+            if claimed.get() & (1 << index) != 0 {
+                return Err(AlarmError::AlreadyClaimed(index));
+            }
+            claimed.set(claimed.get() | (1 << index));
+            Ok(())
+        })?;
+        Ok(Alarm {
+            index,
+            claimed: true,
+        })
+    }
+
+    pub fn claim_any() -> Result<Alarm, AlarmError> {
+        let index = critical_section::with(|cs| {
+            let claimed = CLAIMED_ALARMS.borrow(cs);
+            let index = (!(claimed.get() | RESERVED_ALARMS)).trailing_zeros() as usize;
+            if index >= NUM_ALARMS {
+                return Err(AlarmError::NoFreeAlarm);
+            }
+            claimed.set(claimed.get() | (1 << index));
+            Ok(index)
+        })?;
+        Ok(Alarm {
+            index,
+            claimed: true,
+        })
     }
+
+    /// A handle to alarm `index` without claiming it, for the code that
+    /// claimed it to use from where it can't keep the claimed one.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may use the alarm.
+    pub unsafe fn new(index: usize) -> Alarm {
+        Alarm {
+            index,
+            claimed: false,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Calls `callback` once, `delay_us` from now, replacing whatever the
+    /// alarm was set to.
+    pub fn schedule_once(&mut self, delay_us: u32, callback: AlarmCallback) {
+        self.schedule(delay_us, 0, 0, 1, callback);
+    }
+
+    /// Calls `callback` every `period_us` from now on, until it returns
+    /// false or the alarm is cancelled. After a late call the next one is
+    /// a period later, rather than the missed ones being made up.
+    pub fn schedule_periodic(&mut self, period_us: u32, callback: AlarmCallback) {
+        self.schedule(period_us, period_us, 0, 1, callback);
+    }
+
+    /// Like `schedule_periodic`, with `rate_hz` calls a second on average,
+    /// for rates such as 48 kHz whose periods aren't whole microseconds.
+    pub fn schedule_rate(&mut self, rate_hz: u32, callback: AlarmCallback) {
+        assert!(rate_hz > 0 && rate_hz <= 1_000_000, "rate out of range");
+        let period_us = 1_000_000 / rate_hz;
+        self.schedule(period_us, period_us, 1_000_000 % rate_hz, rate_hz, callback);
+    }
+
+    fn schedule(
+        &mut self,
+        delay_us: u32,
+        period_us: u32,
+        period_frac: u32,
+        divisor: u32,
+        callback: AlarmCallback,
+    ) {
+        critical_section::with(|cs| {
+            let mut alarms = ALARMS.borrow_ref_mut(cs);
+            let alarm = &mut alarms[self.index];
+            *alarm = AlarmState {
+                callback: Some(callback),
+                period_us,
+                period_frac,
+                divisor,
+                frac: 0,
+                next_us: time_us().wrapping_add(delay_us),
+                generation: alarm.generation.wrapping_add(1),
+            };
+            unsafe {
+                disarm(self.index);
+                arm(self.index, alarm.next_us);
+                pac::NVIC::unmask(INTERRUPTS[self.index]);
+            }
+        });
+    }
+
+    /// Stops the alarm. A callback already running finishes.
+    pub fn cancel(&mut self) {
+        critical_section::with(|cs| {
+            let mut alarms = ALARMS.borrow_ref_mut(cs);
+            let alarm = &mut alarms[self.index];
+            alarm.callback = None;
+            alarm.generation = alarm.generation.wrapping_add(1);
+            unsafe { disarm(self.index) };
+        });
+    }
+
+    /// Whether the alarm will call its callback again.
+    pub fn is_scheduled(&self) -> bool {
+        critical_section::with(|cs| ALARMS.borrow_ref(cs)[self.index].callback.is_some())
+    }
+
+    /// Sets the alarm to go off at `time_us`, or at once if that has
+    /// passed, without a callback. For the audio alarm, whose interrupt
+    /// handler is outside this module.
+    pub(crate) fn arm_at(&mut self, time_us: u32) {
+        unsafe {
+            disarm(self.index);
+            arm(self.index, time_us);
+            pac::NVIC::unmask(INTERRUPTS[self.index]);
+        }
+    }
+
+    /// Stops an alarm set with `arm_at` and clears its interrupt.
+    pub(crate) fn disarm(&mut self) {
+        unsafe { disarm(self.index) };
+    }
+
+    pub fn set_priority(&mut self, priority: AlarmPriority) {
+        // The RP2040 has two bits of priority, the top ones of the byte.
+        let value = match priority {
+            AlarmPriority::Highest => 0x00,
+            AlarmPriority::High => 0x40,
+            AlarmPriority::Low => 0x80,
+            AlarmPriority::Lowest => 0xc0,
+        };
+        unsafe {
+            let mut peripherals = cortex_m::Peripherals::steal();
+            peripherals.NVIC.set_priority(INTERRUPTS[self.index], value);
+        }
+    }
+}
+
+impl Drop for Alarm {
+    fn drop(&mut self) {
+        if self.claimed {
+            self.cancel();
+            critical_section::with(|cs| {
+                let claimed = CLAIMED_ALARMS.borrow(cs);
+                claimed.set(claimed.get() & !(1 << self.index));
+            });
+        }
+    }
+}
+
+// Runs the callback of alarm `index` and sets the alarm for the next call.
+// The callback runs outside the critical section, so that higher priority
+// interrupts and the other core aren't held up by it unless it takes one.
+fn fire(index: usize) {
+    unsafe { disarm(index) };
+    let Some((callback, generation)) = critical_section::with(|cs| {
+        let alarm = ALARMS.borrow_ref(cs)[index];
+        alarm.callback.map(|callback| (callback, alarm.generation))
+    }) else {
+        return;
+    };
+    let again = callback();
+    critical_section::with(|cs| {
+        let mut alarms = ALARMS.borrow_ref_mut(cs);
+        let alarm = &mut alarms[index];
+        if alarm.generation != generation {
+            return;
+        }
+        if !again || alarm.period_us == 0 {
+            alarm.callback = None;
+            return;
+        }
+        let step = alarm.period_step();
+        alarm.next_us = alarm.next_us.wrapping_add(step);
+        let now = time_us();
+        if (alarm.next_us.wrapping_sub(now) as i32) <= 0 {
+            alarm.next_us = now.wrapping_add(step);
+        }
+        unsafe { arm(index, alarm.next_us) };
+    });
+}
+
+// TIMER_IRQ_0 belongs to `ALARM_AUDIO` and is handled in `audio`.
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn TIMER_IRQ_1() {
+    fire(1);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn TIMER_IRQ_2() {
+    fire(2);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn TIMER_IRQ_3() {
+    fire(3);
 }
@@ -1,9 +1,9 @@
-use crate::time;
-use log::info;
+use crate::logging::info;
+use crate::time::{self, Instant};
 
 pub struct TimeTracker {
     name: &'static str,
-    last_display_us: u32,
+    last_display: Instant,
     accumulated_us: u32,
     count: u32,
 }
@@ -12,36 +12,37 @@ impl TimeTracker {
     pub fn new(name: &'static str) -> Self {
         Self {
             name,
-            last_display_us: 0,
+            last_display: Instant::from_ticks(0),
             accumulated_us: 0,
             count: 0,
         }
     }
 
     pub fn run<F: FnOnce()>(&mut self, func: F) {
-        let start = time::time_us();
+        let start = time::now();
         func();
-        self.update(time::time_us() - start);
+        self.update(time::elapsed(start).ticks() as u32);
     }
 
     pub fn start(&mut self) -> Tracked {
         Tracked {
             tracker: self,
-            start: time::time_us(),
+            start: time::now(),
         }
     }
 
     pub fn update(&mut self, elapsed: u32) {
         self.accumulated_us += elapsed;
         self.count += 1;
-        if time::time_us() - self.last_display_us >= 1_000_000 {
+        let since_display_us = time::elapsed(self.last_display).ticks();
+        if since_display_us >= 1_000_000 {
             info!(
                 "TimeTracker {}: {} us/call {} us/sec",
                 &self.name,
                 self.accumulated_us / self.count,
-                (self.accumulated_us * 1000) / ((time::time_us() - self.last_display_us) / 1000)
+                (self.accumulated_us as u64 * 1000) / (since_display_us / 1000)
             );
-            self.last_display_us = time::time_us();
+            self.last_display = time::now();
             self.count = 0;
             self.accumulated_us = 0;
         }
@@ -50,11 +51,12 @@ impl TimeTracker {
 
 pub struct Tracked<'a> {
     tracker: &'a mut TimeTracker,
-    start: u32,
+    start: Instant,
 }
 
 impl Drop for Tracked<'_> {
     fn drop(&mut self) {
-        self.tracker.update(time::time_us() - self.start);
+        self.tracker
+            .update(time::elapsed(self.start).ticks() as u32);
     }
 }
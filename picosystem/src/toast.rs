@@ -0,0 +1,260 @@
+//! Short messages shown for a moment over the game, such as "Game saved"
+//! or "Low battery".
+//!
+//! `Toasts` queues them and shows up to `MAX_VISIBLE` at once as banners
+//! stacked down from the top of the screen, each with an optional icon,
+//! starting the next one waiting when a banner has been shown for its
+//! duration.
+//!
+//! On the device there is also a global queue filled with `notify`, which
+//! `Hardware::draw` draws over every frame, so messages show up whatever
+//! scene is running.
+
+use crate::sprite::Sprite;
+use embedded_graphics::image::Image;
+use embedded_graphics::mono_font::{ascii::FONT_6X10, MonoTextStyle};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyleBuilder, Rectangle};
+use embedded_graphics::text::{Baseline, Text};
+
+pub const MAX_TEXT: usize = 32;
+pub const MAX_VISIBLE: usize = 3;
+pub const DEFAULT_DURATION_MS: u32 = 2000;
+
+const CHAR_WIDTH: u32 = 6;
+const TEXT_HEIGHT: u32 = 10;
+const PADDING: u32 = 3;
+// Between the banners and from the top of the screen.
+const GAP: i32 = 2;
+const BACKGROUND: Rgb565 = Rgb565::new(4, 8, 6);
+const BORDER: Rgb565 = Rgb565::new(16, 32, 16);
+const TEXT: Rgb565 = Rgb565::WHITE;
+
+#[derive(Clone)]
+pub struct Toast {
+    pub text: heapless::String<MAX_TEXT>,
+    pub icon: Option<&'static Sprite<'static>>,
+    pub duration_ms: u32,
+}
+
+impl Toast {
+    /// A toast showing `text`, cut to `MAX_TEXT` bytes, for the default
+    /// duration.
+    pub fn new(text: &str) -> Self {
+        let mut end = text.len().min(MAX_TEXT);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        Toast {
+            text: text[..end].into(),
+            icon: None,
+            duration_ms: DEFAULT_DURATION_MS,
+        }
+    }
+
+    /// Shows `icon` left of the text.
+    pub fn with_icon(self, icon: &'static Sprite<'static>) -> Self {
+        Toast {
+            icon: Some(icon),
+            ..self
+        }
+    }
+
+    pub fn with_duration_ms(self, duration_ms: u32) -> Self {
+        Toast {
+            duration_ms,
+            ..self
+        }
+    }
+
+    fn size(&self) -> Size {
+        let icon = self.icon.map_or(Size::zero(), |icon| icon.size);
+        let icon_gap = if self.icon.is_some() { PADDING } else { 0 };
+        let text_width = self.text.chars().count() as u32 * CHAR_WIDTH;
+        Size::new(
+            icon.width + icon_gap + text_width + 2 * PADDING,
+            icon.height.max(TEXT_HEIGHT) + 2 * PADDING,
+        )
+    }
+
+    /// Draws the banner with its top edge at `top`, centered across
+    /// `target`, and returns its height.
+    pub fn draw<D>(&self, target: &mut D, top: i32) -> Result<u32, D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let size = self.size();
+        let left = (target.bounding_box().size.width as i32 - size.width as i32) / 2;
+        let banner = Rectangle::new(Point::new(left, top), size);
+        let style = PrimitiveStyleBuilder::new()
+            .fill_color(BACKGROUND)
+            .stroke_color(BORDER)
+            .stroke_width(1)
+            .build();
+        banner.into_styled(style).draw(target)?;
+
+        let mut x = left + PADDING as i32;
+        if let Some(icon) = self.icon {
+            let y = top + (size.height - icon.size.height) as i32 / 2;
+            Image::new(icon, Point::new(x, y)).draw(target)?;
+            x += (icon.size.width + PADDING) as i32;
+        }
+        let y = top + (size.height - TEXT_HEIGHT) as i32 / 2;
+        Text::with_baseline(
+            &self.text,
+            Point::new(x, y),
+            MonoTextStyle::new(&FONT_6X10, TEXT),
+            Baseline::Top,
+        )
+        .draw(target)?;
+        Ok(size.height)
+    }
+}
+
+struct Entry {
+    toast: Toast,
+    // When the banner was first shown, `None` while it waits.
+    shown_us: Option<u64>,
+}
+
+/// Up to `N` toasts, shown and waiting. Toasts added while it is full are
+/// dropped.
+pub struct Toasts<const N: usize> {
+    entries: heapless::Vec<Entry, N>,
+}
+
+impl<const N: usize> Toasts<N> {
+    pub const fn new() -> Self {
+        Toasts {
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds `toast` after the others, returning false if the queue is full.
+    pub fn push(&mut self, toast: Toast) -> bool {
+        self.entries
+            .push(Entry {
+                toast,
+                shown_us: None,
+            })
+            .is_ok()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Removes the banners shown for their whole duration by `now_us` and
+    /// starts showing the next ones.
+    pub fn update(&mut self, now_us: u64) {
+        let mut index = 0;
+        while let Some(entry) = self.entries.get(index) {
+            match entry.shown_us {
+                Some(shown_us) if now_us >= shown_us + entry.toast.duration_ms as u64 * 1000 => {
+                    // heapless 0.7 has no `remove`, so the rest are rotated down to
+                    // keep their order.
+                    self.entries[index..].rotate_left(1);
+                    self.entries.pop();
+                }
+                _ => index += 1,
+            }
+        }
+        for entry in self.entries.iter_mut().take(MAX_VISIBLE) {
+            entry.shown_us.get_or_insert(now_us);
+        }
+    }
+
+    /// The banners being shown, top first.
+    pub fn visible(&self) -> impl Iterator<Item = &Toast> {
+        self.entries
+            .iter()
+            .take_while(|entry| entry.shown_us.is_some())
+            .map(|entry| &entry.toast)
+    }
+
+    /// Draws the banners being shown.
+    pub fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        draw_stack(target, self.visible())
+    }
+}
+
+impl<const N: usize> Default for Toasts<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn draw_stack<'a, D>(
+    target: &mut D,
+    toasts: impl Iterator<Item = &'a Toast>,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let mut top = GAP;
+    for toast in toasts {
+        top += toast.draw(target, top)? as i32 + GAP;
+    }
+    Ok(())
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub use global::*;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+mod global {
+    use super::*;
+    use crate::display::Display;
+    use crate::time;
+    use core::cell::RefCell;
+    use critical_section::Mutex;
+
+    const QUEUE: usize = 8;
+
+    static TOASTS: Mutex<RefCell<Toasts<QUEUE>>> = Mutex::new(RefCell::new(Toasts::new()));
+
+    /// Queues `text` for the default duration. Returns false if the queue
+    /// is full.
+    pub fn notify(text: &str) -> bool {
+        notify_toast(Toast::new(text))
+    }
+
+    /// Queues `toast`. Returns false if the queue is full.
+    pub fn notify_toast(toast: Toast) -> bool {
+        critical_section::with(|cs| TOASTS.borrow_ref_mut(cs).push(toast))
+    }
+
+    /// Removes every toast, shown or waiting.
+    pub fn clear() {
+        critical_section::with(|cs| TOASTS.borrow_ref_mut(cs).clear());
+    }
+
+    /// Draws the global toasts over `display`, called by `Hardware::draw`.
+    pub(crate) fn draw_overlay(display: &mut Display) {
+        // Copied out so drawing doesn't hold a critical section.
+        let visible = critical_section::with(|cs| {
+            let mut toasts = TOASTS.borrow_ref_mut(cs);
+            if toasts.is_empty() {
+                return None;
+            }
+            toasts.update(time::time_us64());
+            Some(
+                toasts
+                    .visible()
+                    .cloned()
+                    .collect::<heapless::Vec<Toast, MAX_VISIBLE>>(),
+            )
+        });
+        if let Some(visible) = visible {
+            let _ = draw_stack(display, visible.iter());
+        }
+    }
+}
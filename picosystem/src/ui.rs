@@ -0,0 +1,246 @@
+use crate::hardware::Hardware;
+use crate::input::Input;
+use embedded_graphics::mono_font::{ascii::FONT_10X20, MonoTextStyle};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+pub const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+pub const ALPHANUMERIC: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789 -_.";
+
+const COLUMNS: usize = 10;
+const KEY_WIDTH: i32 = 22;
+const KEY_HEIGHT: i32 = 20;
+const KEYBOARD_TOP: i32 = 68;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextInputResult {
+    Editing,
+    Done,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Delete,
+    Done,
+}
+
+/// An on-screen keyboard editing a string of up to `N` bytes.
+///
+/// The D-pad moves around a grid of the charset's characters, with delete
+/// and done keys on the last row. A presses the selected key, B deletes the
+/// last character and Y cancels.
+pub struct TextInput<const N: usize> {
+    title: &'static str,
+    charset: &'static str,
+    text: heapless::String<N>,
+    max_len: usize,
+    row: usize,
+    column: usize,
+}
+
+impl<const N: usize> TextInput<N> {
+    pub fn new(title: &'static str, charset: &'static str) -> Self {
+        TextInput {
+            title,
+            charset,
+            text: heapless::String::new(),
+            max_len: N,
+            row: 0,
+            column: 0,
+        }
+    }
+
+    /// Limits the text to `max_len` characters, at most `N` bytes.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+        while self.text.chars().count() > max_len {
+            self.text.pop();
+        }
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.text.clear();
+        for c in text.chars().take(self.max_len) {
+            if self.text.push(c).is_err() {
+                break;
+            }
+        }
+    }
+
+    pub fn text(&self) -> &heapless::String<N> {
+        &self.text
+    }
+
+    fn char_rows(&self) -> usize {
+        self.charset.chars().count().div_ceil(COLUMNS)
+    }
+
+    fn row_len(&self, row: usize) -> usize {
+        if row == self.char_rows() {
+            2
+        } else {
+            (self.charset.chars().count() - row * COLUMNS).min(COLUMNS)
+        }
+    }
+
+    fn key(&self, row: usize, column: usize) -> Key {
+        if row == self.char_rows() {
+            if column == 0 {
+                Key::Delete
+            } else {
+                Key::Done
+            }
+        } else {
+            Key::Char(self.charset.chars().nth(row * COLUMNS + column).unwrap())
+        }
+    }
+
+    fn key_area(&self, row: usize, column: usize) -> Rectangle {
+        let left = (crate::display::WIDTH as i32 - COLUMNS as i32 * KEY_WIDTH) / 2;
+        let top = KEYBOARD_TOP + row as i32 * KEY_HEIGHT;
+        if row == self.char_rows() {
+            let width = COLUMNS as i32 / 2 * KEY_WIDTH;
+            Rectangle::new(
+                Point::new(left + column as i32 * width, top + 4),
+                Size::new(width as u32, KEY_HEIGHT as u32),
+            )
+        } else {
+            Rectangle::new(
+                Point::new(left + column as i32 * KEY_WIDTH, top),
+                Size::new(KEY_WIDTH as u32, KEY_HEIGHT as u32),
+            )
+        }
+    }
+
+    fn move_vertically(&mut self, down: bool) {
+        let rows = self.char_rows() + 1;
+        let special_row = self.char_rows();
+        let old_row = self.row;
+        self.row = if down {
+            (self.row + 1) % rows
+        } else {
+            (self.row + rows - 1) % rows
+        };
+        // The last row has two wide keys, each above or below half the grid.
+        if old_row == special_row {
+            self.column *= COLUMNS / 2;
+        } else if self.row == special_row {
+            self.column /= COLUMNS / 2;
+        }
+        self.column = self.column.min(self.row_len(self.row) - 1);
+    }
+
+    fn move_horizontally(&mut self, right: bool) {
+        let len = self.row_len(self.row);
+        self.column = if right {
+            (self.column + 1) % len
+        } else {
+            (self.column + len - 1) % len
+        };
+    }
+
+    fn press(&mut self, key: Key) -> TextInputResult {
+        match key {
+            Key::Char(c) => {
+                if self.text.chars().count() < self.max_len {
+                    let _ = self.text.push(c);
+                }
+            }
+            Key::Delete => {
+                self.text.pop();
+            }
+            Key::Done => return TextInputResult::Done,
+        }
+        TextInputResult::Editing
+    }
+
+    /// Handles button presses, call once per frame.
+    pub fn update(&mut self, input: &mut Input) -> TextInputResult {
+        if input.dpad_up.is_pressed() {
+            self.move_vertically(false);
+        }
+        if input.dpad_down.is_pressed() {
+            self.move_vertically(true);
+        }
+        if input.dpad_left.is_pressed() {
+            self.move_horizontally(false);
+        }
+        if input.dpad_right.is_pressed() {
+            self.move_horizontally(true);
+        }
+        if input.button_y.is_pressed() {
+            return TextInputResult::Cancelled;
+        }
+        if input.button_b.is_pressed() {
+            self.text.pop();
+        }
+        if input.button_a.is_pressed() {
+            return self.press(self.key(self.row, self.column));
+        }
+        TextInputResult::Editing
+    }
+
+    pub fn draw<D>(&self, display: &mut D)
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let _ = display.clear(Rgb565::BLACK);
+        let center_x = crate::display::WIDTH as i32 / 2;
+        let centered = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+        let white = MonoTextStyle::new(&FONT_10X20, Rgb565::WHITE);
+        let _ = Text::with_text_style(self.title, Point::new(center_x, 16), white, centered)
+            .draw(display);
+
+        let field = Rectangle::new(Point::new(10, 34), Size::new(220, 28));
+        let _ = field
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::CSS_LIGHT_SLATE_GRAY, 1))
+            .draw(display);
+        let mut shown: heapless::String<N> = self.text.clone();
+        if self.text.chars().count() < self.max_len {
+            let _ = shown.push('_');
+        }
+        let _ = Text::with_text_style(&shown, field.center(), white, centered).draw(display);
+
+        for row in 0..=self.char_rows() {
+            for column in 0..self.row_len(row) {
+                let area = self.key_area(row, column);
+                let selected = (row, column) == (self.row, self.column);
+                let color = if selected {
+                    let _ = area
+                        .into_styled(PrimitiveStyle::with_fill(Rgb565::CSS_DARK_GREEN))
+                        .draw(display);
+                    Rgb565::WHITE
+                } else {
+                    Rgb565::CSS_LIGHT_GRAY
+                };
+                let style = MonoTextStyle::new(&FONT_10X20, color);
+                let mut label = [0u8; 4];
+                let label = match self.key(row, column) {
+                    Key::Char(c) => &*c.encode_utf8(&mut label),
+                    Key::Delete => "DEL",
+                    Key::Done => "OK",
+                };
+                let _ = Text::with_text_style(label, area.center(), style, centered).draw(display);
+            }
+        }
+    }
+
+    /// Runs the keyboard until done or cancelled, returning the text if done.
+    pub fn run(mut self, hw: &mut Hardware) -> Option<heapless::String<N>> {
+        loop {
+            match self.update(&mut hw.input) {
+                TextInputResult::Editing => {}
+                TextInputResult::Done => return Some(self.text),
+                TextInputResult::Cancelled => return None,
+            }
+            hw.draw(|display| self.draw(display));
+        }
+    }
+}
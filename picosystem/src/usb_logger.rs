@@ -1,7 +1,16 @@
 // Based on https://github.com/rp-rs/rp-hal/blob/c8bb2e43c792dd3975a255d7eba479547411aec6/boards/pico/examples/pico_usb_serial_interrupt.rs
+//
+// Logging works from interrupts and core 1 as well: messages go into a ring
+// of the core logging them, and `drain` moves them on to the serial port and
+// the console from the main loop.
+use crate::logging;
 use crate::time;
+use core::cell::{Cell, RefCell, UnsafeCell};
 use core::fmt;
 use core::fmt::Write;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use critical_section::Mutex;
 use log::LevelFilter;
 use log::{Level, Metadata, Record};
 use rp_pico::hal;
@@ -21,6 +30,117 @@ static mut USB_SERIAL: Option<SerialPort<hal::usb::UsbBus>> = None;
 
 static LOGGER: UsbSerialLogger = UsbSerialLogger;
 
+// The buffers are smaller with `double-buffer`, to fit next to the second
+// framebuffer, so fewer messages are kept until a host connects.
+#[cfg(not(feature = "double-buffer"))]
+const TX_BUFFER_SIZE: usize = 4096;
+#[cfg(feature = "double-buffer")]
+const TX_BUFFER_SIZE: usize = 1024;
+
+/// Log output waiting to be sent. It is filled by the logger and drained by
+/// the interrupt, so logging never waits for the host. Messages that don't
+/// fit are dropped, which keeps startup logs around until a host connects.
+struct TxBuffer {
+    data: heapless::Deque<u8, TX_BUFFER_SIZE>,
+    dropped: usize,
+}
+
+static TX_BUFFER: Mutex<RefCell<TxBuffer>> = Mutex::new(RefCell::new(TxBuffer {
+    data: heapless::Deque::new(),
+    dropped: 0,
+}));
+
+#[cfg(not(feature = "double-buffer"))]
+const RING_SIZE: usize = 2048;
+#[cfg(feature = "double-buffer")]
+const RING_SIZE: usize = 512;
+
+// Each message in a ring is its level and length, then its text.
+const HEADER_LEN: usize = 3;
+
+/// Messages logged on one core, waiting for `drain`. Only that core writes
+/// `head`, with its interrupts masked while it copies a whole message in,
+/// and only `drain` writes `tail`, so neither core ever waits for the other
+/// and messages can't interleave.
+struct LogRing {
+    data: UnsafeCell<[u8; RING_SIZE]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+unsafe impl Sync for LogRing {}
+
+impl LogRing {
+    const fn new() -> Self {
+        LogRing {
+            data: UnsafeCell::new([0; RING_SIZE]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    // Called on the core owning the ring.
+    fn push(&self, level: Level, message: &str) {
+        let len = message.len();
+        let header = [level as u8, len as u8, (len >> 8) as u8];
+        cortex_m::interrupt::free(|_| {
+            let head = self.head.load(Ordering::Relaxed);
+            let tail = self.tail.load(Ordering::Acquire);
+            if RING_SIZE - head.wrapping_sub(tail) < HEADER_LEN + len {
+                let dropped = self.dropped.load(Ordering::Relaxed);
+                self.dropped.store(dropped + 1, Ordering::Relaxed);
+                return;
+            }
+            let data = unsafe { &mut *self.data.get() };
+            for (i, &b) in header.iter().chain(message.as_bytes()).enumerate() {
+                data[head.wrapping_add(i) % RING_SIZE] = b;
+            }
+            self.head
+                .store(head.wrapping_add(HEADER_LEN + len), Ordering::Release);
+        });
+    }
+
+    // Called by `drain` only. Copies the oldest message into `buf`.
+    fn pop<'a>(&self, buf: &'a mut [u8; MAX_MESSAGE_LEN]) -> Option<(Level, &'a str)> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let data = unsafe { &*self.data.get() };
+        let at = |i: usize| data[tail.wrapping_add(i) % RING_SIZE];
+        let level = level_from_u8(at(0));
+        let len = at(1) as usize | (at(2) as usize) << 8;
+        for (i, b) in buf[..len].iter_mut().enumerate() {
+            *b = at(HEADER_LEN + i);
+        }
+        self.tail
+            .store(tail.wrapping_add(HEADER_LEN + len), Ordering::Release);
+        Some((level, core::str::from_utf8(&buf[..len]).unwrap_or("")))
+    }
+}
+
+static RINGS: [LogRing; 2] = [LogRing::new(), LogRing::new()];
+
+// Set while `drain` runs, so that logging from an interrupt during it
+// doesn't drain again.
+static DRAINING: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+fn level_from_u8(level: u8) -> Level {
+    match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+fn current_core() -> usize {
+    unsafe { (*pac::SIO::PTR).cpuid.read().bits() as usize }
+}
+
 pub fn init(
     regs: pac::USBCTRL_REGS,
     dpram: pac::USBCTRL_DPRAM,
@@ -31,7 +151,7 @@ pub fn init(
     unsafe {
         USB_BUS = Some(usb_bus);
     }
-    let usb_bus_ref = unsafe { USB_BUS.as_ref().unwrap() };
+    let usb_bus_ref = unsafe { (*addr_of!(USB_BUS)).as_ref().unwrap() };
 
     let serial = SerialPort::new(usb_bus_ref);
 
@@ -62,7 +182,7 @@ pub fn init(
 
 pub fn connected() -> bool {
     unsafe {
-        USB_DEVICE
+        (*addr_of!(USB_DEVICE))
             .as_ref()
             .map(|d| {
                 d.state() == UsbDeviceState::Addressed || d.state() == UsbDeviceState::Configured
@@ -71,11 +191,75 @@ pub fn connected() -> bool {
     }
 }
 
+/// Number of log messages dropped because a ring or the TX buffer was full.
+pub fn dropped_messages() -> usize {
+    let dropped: usize = RINGS
+        .iter()
+        .map(|ring| ring.dropped.load(Ordering::Relaxed))
+        .sum();
+    dropped + critical_section::with(|cs| TX_BUFFER.borrow_ref(cs).dropped)
+}
+
+/// Moves logged messages on to the console and the TX buffer. Called from
+/// the main loop on core 0 each frame, and while waiting in `Delay`; does
+/// nothing elsewhere.
+pub fn drain() {
+    if current_core() != 0 || critical_section::with(|cs| DRAINING.borrow(cs).replace(true)) {
+        return;
+    }
+    let mut buf = [0; MAX_MESSAGE_LEN];
+    let mut queued = false;
+    for ring in &RINGS {
+        while let Some((level, message)) = ring.pop(&mut buf) {
+            crate::console::log(level, message);
+            critical_section::with(|cs| {
+                let mut tx = TX_BUFFER.borrow_ref_mut(cs);
+                let len = message.len() + 2;
+                if tx.data.capacity() - tx.data.len() < len {
+                    tx.dropped += 1;
+                    return;
+                }
+                for &b in message.as_bytes().iter().chain(b"\r\n") {
+                    let _ = tx.data.push_back(b);
+                }
+            });
+            queued = true;
+        }
+    }
+    critical_section::with(|cs| DRAINING.borrow(cs).set(false));
+    if queued {
+        // Let the interrupt start sending.
+        pac::NVIC::pend(hal::pac::Interrupt::USBCTRL_IRQ);
+    }
+}
+
+// Sends as much buffered output as the serial port accepts.
+fn drain_tx_buffer(serial: &mut SerialPort<hal::usb::UsbBus>) {
+    critical_section::with(|cs| {
+        let mut tx = TX_BUFFER.borrow_ref_mut(cs);
+        while !tx.data.is_empty() {
+            let (front, _) = tx.data.as_slices();
+            let written = match serial.write(front) {
+                Ok(written) => written,
+                Err(_) => break,
+            };
+            for _ in 0..written {
+                tx.data.pop_front();
+            }
+        }
+    });
+}
+
 #[allow(non_snake_case)]
 #[interrupt]
 unsafe fn USBCTRL_IRQ() {
-    let usb_dev = USB_DEVICE.as_mut().unwrap();
-    let serial = USB_SERIAL.as_mut().unwrap();
+    // USB belongs to usb_storage in mass storage mode.
+    let (Some(usb_dev), Some(serial)) = (
+        (*addr_of_mut!(USB_DEVICE)).as_mut(),
+        (*addr_of_mut!(USB_SERIAL)).as_mut(),
+    ) else {
+        return;
+    };
 
     if usb_dev.poll(&mut [serial]) {
         let mut buf = [0u8; 64];
@@ -84,14 +268,17 @@ unsafe fn USBCTRL_IRQ() {
             Ok(count) => {
                 buf.iter_mut().take(count).for_each(|b| {
                     if *b == 0 {
-                        log::info!("Entering flash mode");
-                        hal::rom_data::reset_to_usb_boot(0, 0);
+                        logging::info!("Entering flash mode");
+                        crate::hardware::reboot_to_bootsel();
                     }
                 });
             }
             Err(_) => {}
         }
     }
+    if connected() {
+        drain_tx_buffer(serial);
+    }
 }
 
 struct UsbSerialLogger;
@@ -103,32 +290,32 @@ impl log::Log for UsbSerialLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let mut writer = UsbSerialWriter;
-            pac::NVIC::mask(hal::pac::Interrupt::USBCTRL_IRQ);
-            write!(
-                &mut writer,
-                "{:.3} {} - {}\r\n",
+            let mut message = LogMessage(heapless::String::new());
+            // Overlong messages are truncated.
+            let _ = write!(
+                &mut message,
+                "{:.3} {} - {}",
                 time::time_us() as f32 / 1000.0,
                 record.level(),
                 record.args()
-            )
-            .unwrap();
-            unsafe {
-                pac::NVIC::unmask(hal::pac::Interrupt::USBCTRL_IRQ);
-            }
+            );
+            // Formatted before the ring is written, so other logging
+            // isn't held up by it.
+            RINGS[current_core()].push(record.level(), &message.0);
         }
     }
 
     fn flush(&self) {}
 }
 
-struct UsbSerialWriter;
+const MAX_MESSAGE_LEN: usize = 256;
+
+struct LogMessage(heapless::String<MAX_MESSAGE_LEN>);
 
-impl fmt::Write for UsbSerialWriter {
+impl fmt::Write for LogMessage {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        unsafe {
-            let serial = USB_SERIAL.as_mut().unwrap();
-            let _ = serial.write(s.as_bytes());
+        for c in s.chars() {
+            self.0.push(c).map_err(|_| fmt::Error)?;
         }
         Ok(())
     }
@@ -0,0 +1,425 @@
+//! USB mass storage mode, for copying saves and screenshots to a computer.
+//!
+//! The files in [`storage`](crate::storage) are shown to the host as a small
+//! read-only FAT12 drive, which is generated on the fly from the file list.
+//! The game doesn't run in this mode: it is entered by rebooting, either
+//! with [`enter`] or by holding X and Y at power on, and left by pressing A.
+
+use crate::display::Display;
+use crate::input::Input;
+use crate::logging;
+use crate::storage::{self, File};
+use embedded_graphics::mono_font::{ascii::FONT_10X20, MonoTextStyle};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Alignment, Text};
+use rp_pico::hal;
+use rp_pico::hal::pac;
+use usb_device::{class_prelude::*, device::UsbDeviceState, prelude::*};
+use usbd_storage::subclass::scsi::{Scsi, ScsiCommand};
+use usbd_storage::subclass::Command;
+use usbd_storage::transport::bbb::{BulkOnly, BulkOnlyError};
+use usbd_storage::transport::TransportError;
+
+// Kept in a watchdog scratch register across the reboot.
+const MODE_MAGIC: u32 = 0x5553_4244; // "USBD"
+
+const BLOCK_SIZE: usize = 512;
+const SECTORS_PER_CLUSTER: usize = 8;
+const CLUSTER_SIZE: usize = BLOCK_SIZE * SECTORS_PER_CLUSTER;
+// Enough for every flash sector plus a partial cluster for each file.
+const CLUSTERS: usize = 512;
+const FAT_SECTORS: usize = 2;
+const FATS: usize = 2;
+const ROOT_ENTRIES: usize = 512;
+const FAT_START: usize = 1;
+const ROOT_START: usize = FAT_START + FATS * FAT_SECTORS;
+const DATA_START: usize = ROOT_START + ROOT_ENTRIES * 32 / BLOCK_SIZE;
+const TOTAL_SECTORS: usize = DATA_START + CLUSTERS * SECTORS_PER_CLUSTER;
+const FIRST_CLUSTER: usize = 2;
+
+const DIR_ENTRY_SIZE: usize = 32;
+const LFN_CHARS: usize = 13;
+// 2022-01-01, files have no timestamps.
+const FAT_DATE: u16 = ((2022 - 1980) << 9) | (1 << 5) | 1;
+
+const USB_PACKET_SIZE: u16 = 64;
+
+/// Returns whether mass storage mode was requested for this boot, and
+/// clears the request.
+pub fn requested() -> bool {
+    let watchdog = unsafe { &*pac::WATCHDOG::PTR };
+    let requested = watchdog.scratch0.read().bits() == MODE_MAGIC;
+    watchdog.scratch0.write(|w| unsafe { w.bits(0) });
+    requested
+}
+
+/// Reboots into mass storage mode.
+pub fn enter() -> ! {
+    let watchdog = unsafe { &*pac::WATCHDOG::PTR };
+    watchdog.scratch0.write(|w| unsafe { w.bits(MODE_MAGIC) });
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+struct VolumeFile {
+    name: heapless::String<{ storage::MAX_NAME_LEN }>,
+    file: File,
+    first_cluster: usize,
+}
+
+impl VolumeFile {
+    fn clusters(&self) -> usize {
+        self.file.len().div_ceil(CLUSTER_SIZE)
+    }
+
+    fn lfn_entries(&self) -> usize {
+        self.name.chars().count().div_ceil(LFN_CHARS)
+    }
+
+    // An 8.3 name made unique with the file's index.
+    fn short_name(&self, index: usize) -> [u8; 11] {
+        let mut short_name = [b' '; 11];
+        let (base, extension) = match self.name.rsplit_once('.') {
+            Some((base, extension)) => (base, extension),
+            None => (self.name.as_str(), ""),
+        };
+        let valid = |c: &u8| c.is_ascii_alphanumeric() || b"_-".contains(c);
+        for (dst, c) in short_name[..4].iter_mut().zip(base.bytes().filter(valid)) {
+            *dst = c.to_ascii_uppercase();
+        }
+        short_name[4] = b'~';
+        for (i, digit) in short_name[5..8].iter_mut().enumerate() {
+            *digit = b'0' + (index / 10usize.pow(2 - i as u32) % 10) as u8;
+        }
+        for (dst, c) in short_name[8..]
+            .iter_mut()
+            .zip(extension.bytes().filter(valid))
+        {
+            *dst = c.to_ascii_uppercase();
+        }
+        short_name
+    }
+}
+
+/// A read-only FAT12 view of the file system.
+struct Volume {
+    files: heapless::Vec<VolumeFile, { storage::MAX_FILES }>,
+}
+
+impl Volume {
+    fn new() -> Self {
+        let fs = storage::fs();
+        let mut files = heapless::Vec::new();
+        let mut next_cluster = FIRST_CLUSTER;
+        for entry in fs.list() {
+            let Ok(file) = fs.open(&entry.name) else {
+                logging::warn!("Skipping unreadable file {}", entry.name.as_str());
+                continue;
+            };
+            let file = VolumeFile {
+                name: entry.name,
+                file,
+                first_cluster: next_cluster,
+            };
+            next_cluster += file.clusters();
+            let _ = files.push(file);
+        }
+        Volume { files }
+    }
+
+    fn read_sector(&self, lba: usize, buf: &mut [u8; BLOCK_SIZE]) {
+        buf.fill(0);
+        if lba == 0 {
+            self.boot_sector(buf);
+        } else if lba < ROOT_START {
+            self.fat_sector((lba - FAT_START) % FAT_SECTORS, buf);
+        } else if lba < DATA_START {
+            self.root_sector(lba - ROOT_START, buf);
+        } else if lba < TOTAL_SECTORS {
+            self.data_sector(lba - DATA_START, buf);
+        }
+    }
+
+    fn boot_sector(&self, buf: &mut [u8; BLOCK_SIZE]) {
+        buf[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        buf[3..11].copy_from_slice(b"PICOSYS ");
+        buf[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+        buf[13] = SECTORS_PER_CLUSTER as u8;
+        buf[14..16].copy_from_slice(&(FAT_START as u16).to_le_bytes());
+        buf[16] = FATS as u8;
+        buf[17..19].copy_from_slice(&(ROOT_ENTRIES as u16).to_le_bytes());
+        buf[19..21].copy_from_slice(&(TOTAL_SECTORS as u16).to_le_bytes());
+        buf[21] = 0xf8;
+        buf[22..24].copy_from_slice(&(FAT_SECTORS as u16).to_le_bytes());
+        buf[24..26].copy_from_slice(&1u16.to_le_bytes());
+        buf[26..28].copy_from_slice(&1u16.to_le_bytes());
+        buf[36] = 0x80;
+        buf[38] = 0x29;
+        buf[39..43].copy_from_slice(&0x5049_434fu32.to_le_bytes());
+        buf[43..54].copy_from_slice(b"PICOSYSTEM ");
+        buf[54..62].copy_from_slice(b"FAT12   ");
+        buf[510] = 0x55;
+        buf[511] = 0xaa;
+    }
+
+    fn fat_entry(&self, cluster: usize) -> u16 {
+        match cluster {
+            0 => 0xff8,
+            1 => 0xfff,
+            _ => self
+                .files
+                .iter()
+                .find(|f| (f.first_cluster..f.first_cluster + f.clusters()).contains(&cluster))
+                .map(|f| {
+                    if cluster + 1 == f.first_cluster + f.clusters() {
+                        0xfff
+                    } else {
+                        cluster as u16 + 1
+                    }
+                })
+                .unwrap_or(0),
+        }
+    }
+
+    // FAT12 packs two 12-bit entries into three bytes.
+    fn fat_sector(&self, sector: usize, buf: &mut [u8; BLOCK_SIZE]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let offset = sector * BLOCK_SIZE + i;
+            let pair = offset / 3 * 2;
+            let (first, second) = (self.fat_entry(pair), self.fat_entry(pair + 1));
+            *byte = match offset % 3 {
+                0 => first as u8,
+                1 => ((first >> 8) | ((second & 0xf) << 4)) as u8,
+                _ => (second >> 4) as u8,
+            };
+        }
+    }
+
+    fn root_sector(&self, sector: usize, buf: &mut [u8; BLOCK_SIZE]) {
+        let first = sector * BLOCK_SIZE / DIR_ENTRY_SIZE;
+        let entries = first..first + BLOCK_SIZE / DIR_ENTRY_SIZE;
+        let mut write_entry = |index: usize, entry: &[u8; DIR_ENTRY_SIZE]| {
+            if entries.contains(&index) {
+                let offset = (index - first) * DIR_ENTRY_SIZE;
+                buf[offset..offset + DIR_ENTRY_SIZE].copy_from_slice(entry);
+            }
+        };
+
+        let mut label = [0u8; DIR_ENTRY_SIZE];
+        label[..11].copy_from_slice(b"PICOSYSTEM ");
+        label[11] = 0x08;
+        write_entry(0, &label);
+
+        let mut index = 1;
+        for (file_index, file) in self.files.iter().enumerate() {
+            if index >= entries.end {
+                break;
+            }
+            let short_name = file.short_name(file_index);
+            let checksum = short_name
+                .iter()
+                .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c));
+
+            let name: heapless::Vec<u16, { storage::MAX_NAME_LEN }> =
+                file.name.chars().map(|c| c as u16).collect();
+            let lfn_entries = file.lfn_entries();
+            for ordinal in (1..=lfn_entries).rev() {
+                let mut entry = [0u8; DIR_ENTRY_SIZE];
+                entry[0] = ordinal as u8 | if ordinal == lfn_entries { 0x40 } else { 0 };
+                entry[11] = 0x0f;
+                entry[13] = checksum;
+                let chars = (0..5).map(|i| 1 + 2 * i).chain((0..6).map(|i| 14 + 2 * i));
+                let chars = chars.chain([28, 30]);
+                for (i, offset) in chars.enumerate() {
+                    let char_index = (ordinal - 1) * LFN_CHARS + i;
+                    let c = match char_index.cmp(&name.len()) {
+                        core::cmp::Ordering::Less => name[char_index],
+                        core::cmp::Ordering::Equal => 0,
+                        core::cmp::Ordering::Greater => 0xffff,
+                    };
+                    entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+                }
+                write_entry(index, &entry);
+                index += 1;
+            }
+
+            let mut entry = [0u8; DIR_ENTRY_SIZE];
+            entry[..11].copy_from_slice(&short_name);
+            // Read-only, archive.
+            entry[11] = 0x21;
+            for offset in [16, 18, 24] {
+                entry[offset..offset + 2].copy_from_slice(&FAT_DATE.to_le_bytes());
+            }
+            if !file.file.is_empty() {
+                entry[26..28].copy_from_slice(&(file.first_cluster as u16).to_le_bytes());
+            }
+            entry[28..32].copy_from_slice(&(file.file.len() as u32).to_le_bytes());
+            write_entry(index, &entry);
+            index += 1;
+        }
+    }
+
+    fn data_sector(&self, sector: usize, buf: &mut [u8; BLOCK_SIZE]) {
+        let cluster = FIRST_CLUSTER + sector / SECTORS_PER_CLUSTER;
+        let Some(file) = self
+            .files
+            .iter()
+            .find(|f| (f.first_cluster..f.first_cluster + f.clusters()).contains(&cluster))
+        else {
+            return;
+        };
+        let start = ((cluster - file.first_cluster) * SECTORS_PER_CLUSTER
+            + sector % SECTORS_PER_CLUSTER)
+            * BLOCK_SIZE;
+        if let Err(e) = file.file.read_at(start, buf) {
+            logging::warn!("Failed to read {}: {:?}", file.name.as_str(), e);
+        }
+    }
+}
+
+#[derive(Default)]
+struct ScsiState {
+    // Bytes of the current read that were already sent.
+    transferred: usize,
+    sense: Option<(u8, u8)>,
+}
+
+type UsbScsi<'a> = Scsi<BulkOnly<'a, hal::usb::UsbBus, &'a mut [u8]>>;
+
+fn process_command(
+    volume: &Volume,
+    state: &mut ScsiState,
+    mut command: Command<ScsiCommand, UsbScsi>,
+) -> Result<(), TransportError<BulkOnlyError>> {
+    match command.kind {
+        ScsiCommand::TestUnitReady => command.pass(),
+        ScsiCommand::Inquiry { .. } => {
+            let mut data = [b' '; 36];
+            data[..8].copy_from_slice(&[0x00, 0x80, 0x04, 0x02, 0x1f, 0x00, 0x00, 0x00]);
+            data[8..16].copy_from_slice(b"Pimoroni");
+            data[16..26].copy_from_slice(b"PicoSystem");
+            data[32..36].copy_from_slice(b"1.0 ");
+            command.try_write_data_all(&data)?;
+            command.pass();
+        }
+        ScsiCommand::RequestSense { .. } => {
+            let (key, code) = state.sense.take().unwrap_or((0, 0));
+            let mut data = [0u8; 18];
+            data[0] = 0x70;
+            data[2] = key;
+            data[7] = 10;
+            data[12] = code;
+            command.try_write_data_all(&data)?;
+            command.pass();
+        }
+        ScsiCommand::ReadCapacity10 => {
+            let mut data = [0u8; 8];
+            data[..4].copy_from_slice(&(TOTAL_SECTORS as u32 - 1).to_be_bytes());
+            data[4..].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+            command.try_write_data_all(&data)?;
+            command.pass();
+        }
+        ScsiCommand::ReadCapacity16 { .. } => {
+            let mut data = [0u8; 32];
+            data[..8].copy_from_slice(&(TOTAL_SECTORS as u64 - 1).to_be_bytes());
+            data[8..12].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+            command.try_write_data_all(&data)?;
+            command.pass();
+        }
+        ScsiCommand::ReadFormatCapacities { .. } => {
+            let mut data = [0u8; 12];
+            data[3] = 8;
+            data[4..8].copy_from_slice(&(TOTAL_SECTORS as u32).to_be_bytes());
+            // Formatted media.
+            data[8] = 0x02;
+            data[9..12].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes()[1..]);
+            command.try_write_data_all(&data)?;
+            command.pass();
+        }
+        // The device-specific parameter marks the drive write-protected.
+        ScsiCommand::ModeSense6 { .. } => {
+            command.try_write_data_all(&[0x03, 0x00, 0x80, 0x00])?;
+            command.pass();
+        }
+        ScsiCommand::ModeSense10 { .. } => {
+            command.try_write_data_all(&[0x00, 0x06, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00])?;
+            command.pass();
+        }
+        ScsiCommand::Read { lba, len } => {
+            let total = len as usize * BLOCK_SIZE;
+            if state.transferred == total {
+                state.transferred = 0;
+                command.pass();
+                return Ok(());
+            }
+            let mut sector = [0u8; BLOCK_SIZE];
+            volume.read_sector(lba as usize + state.transferred / BLOCK_SIZE, &mut sector);
+            let offset = state.transferred % BLOCK_SIZE;
+            state.transferred += command.write_data(&sector[offset..])?;
+        }
+        ScsiCommand::Write { .. } => {
+            // Data protect, write protected.
+            state.sense = Some((0x07, 0x27));
+            command.fail();
+        }
+        ScsiCommand::Unknown => {
+            // Illegal request, invalid command operation code.
+            state.sense = Some((0x05, 0x20));
+            command.fail();
+        }
+    }
+    Ok(())
+}
+
+/// Runs mass storage mode until A is pressed, then reboots.
+pub fn run(
+    regs: pac::USBCTRL_REGS,
+    dpram: pac::USBCTRL_DPRAM,
+    resets: &mut pac::RESETS,
+    clock: hal::clocks::UsbClock,
+    display: &mut Display,
+    input: &Input,
+) -> ! {
+    let volume = Volume::new();
+
+    display.draw(|display| {
+        display.clear(Rgb565::BLACK).unwrap();
+        let style = MonoTextStyle::new(&FONT_10X20, Rgb565::WHITE);
+        let center = display.bounding_box().center();
+        Text::with_alignment(
+            "USB drive\n\nPress A to exit",
+            center - Point::new(0, 30),
+            style,
+            Alignment::Center,
+        )
+        .draw(display)
+        .unwrap();
+    });
+
+    let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(regs, dpram, clock, true, resets));
+    let mut transport_buf = [0u8; BLOCK_SIZE];
+    let mut scsi = Scsi::new(&usb_bus, USB_PACKET_SIZE, 0, &mut transport_buf[..]).unwrap();
+    let mut usb_device = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        .manufacturer("Fake company")
+        .product("PicoSystem storage")
+        .serial_number("TEST")
+        .build();
+
+    let mut state = ScsiState::default();
+    loop {
+        if input.button_a.is_held() {
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+        if !usb_device.poll(&mut [&mut scsi]) {
+            continue;
+        }
+        if usb_device.state() == UsbDeviceState::Default {
+            state = ScsiState::default();
+        }
+        let _ = scsi.poll(|command| {
+            if let Err(err) = process_command(&volume, &mut state, command) {
+                logging::warn!("USB storage error: {:?}", logging::Debug2Format(&err));
+            }
+        });
+    }
+}
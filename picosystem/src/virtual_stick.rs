@@ -0,0 +1,124 @@
+//! An analog stick made from the d-pad, for movement that speeds up and
+//! slows down instead of starting and stopping at full speed.
+//!
+//! Call `VirtualStick::update` once per frame and move by `value` times the
+//! top speed. Diagonals are as fast as straight lines, and the stick eases
+//! from one direction to the next rather than jumping.
+
+use crate::input::{Action, Input};
+use crate::math::{Vector2, I16F16};
+
+/// How the speed builds up as the stick is pushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    /// Evenly.
+    Linear,
+    /// Slowly at first, for fine control while tapping.
+    Quadratic,
+    /// Slowly at first and last.
+    Smooth,
+}
+
+impl Curve {
+    fn apply(self, t: I16F16) -> I16F16 {
+        match self {
+            Curve::Linear => t,
+            Curve::Quadratic => t * t,
+            Curve::Smooth => t * t * (I16F16::from_int(3) - t.mul_int(2)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VirtualStick {
+    // Eased towards the direction held, before the curve.
+    raw: Vector2,
+    ramp_up: I16F16,
+    ramp_down: I16F16,
+    curve: Curve,
+}
+
+impl Default for VirtualStick {
+    fn default() -> Self {
+        VirtualStick::new()
+    }
+}
+
+impl VirtualStick {
+    /// Takes 8 frames to reach full speed and 6 to stop, along
+    /// `Curve::Smooth`.
+    pub fn new() -> Self {
+        VirtualStick {
+            raw: Vector2::ZERO,
+            ramp_up: I16F16::ONE.div_int(8),
+            ramp_down: I16F16::ONE.div_int(6),
+            curve: Curve::Smooth,
+        }
+    }
+
+    /// Sets how many frames the stick takes to go from rest to full speed,
+    /// and back. 1 answers straight away.
+    pub fn with_ramp(mut self, up_frames: u32, down_frames: u32) -> Self {
+        self.ramp_up = I16F16::ONE.div_int(up_frames.max(1) as i32);
+        self.ramp_down = I16F16::ONE.div_int(down_frames.max(1) as i32);
+        self
+    }
+
+    pub fn with_curve(mut self, curve: Curve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    /// Moves the stick towards the directions held, through the button
+    /// map of `input`.
+    pub fn update(&mut self, input: &Input) {
+        let axis = |negative, positive| match (
+            input.is_action_held(negative),
+            input.is_action_held(positive),
+        ) {
+            (true, false) => -I16F16::ONE,
+            (false, true) => I16F16::ONE,
+            _ => I16F16::ZERO,
+        };
+        let held = Vector2::new(
+            axis(Action::Left, Action::Right),
+            axis(Action::Up, Action::Down),
+        );
+        self.push(held);
+    }
+
+    /// Moves the stick towards `direction`, which is scaled to a length of
+    /// one. The zero vector lets go of it.
+    pub fn push(&mut self, direction: Vector2) {
+        let target = direction.normalize();
+        let step = if target == Vector2::ZERO {
+            self.ramp_down
+        } else {
+            self.ramp_up
+        };
+        let towards = target - self.raw;
+        if towards.length() <= step {
+            self.raw = target;
+        } else {
+            self.raw += towards.normalize() * step;
+        }
+    }
+
+    /// Where the stick is, at most one long.
+    pub fn value(&self) -> Vector2 {
+        let length = self.raw.length();
+        if length == I16F16::ZERO {
+            return Vector2::ZERO;
+        }
+        self.raw * (self.curve.apply(length.min(I16F16::ONE)) / length)
+    }
+
+    pub fn is_centered(&self) -> bool {
+        self.raw == Vector2::ZERO
+    }
+
+    /// Lets go at once, for example when the character hits a wall.
+    pub fn reset(&mut self) {
+        self.raw = Vector2::ZERO;
+    }
+}
@@ -0,0 +1,205 @@
+//! Rebooting when a game hangs.
+//!
+//! `Hardware` arms the watchdog at startup and feeds it from
+//! `Hardware::draw`, which the scheduler calls every frame. A game that
+//! stops drawing for longer than the timeout is rebooted, and the next boot
+//! shows what happened before going back to the menu. Loops that don't
+//! draw, like the `leds` example, call `Watchdog::feed` themselves or
+//! `pause` it. Panics and hard faults record themselves in the watchdog
+//! scratch registers and wait for the reboot, and games can record their
+//! own reason with `stash_crash_reason`.
+
+use crate::display::Display;
+use crate::input::Input;
+use crate::logging;
+use core::fmt::Write;
+use embedded_graphics::mono_font::{ascii::FONT_10X20, MonoTextStyle};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Alignment, Text};
+use embedded_hal::watchdog::{Watchdog as _, WatchdogDisable, WatchdogEnable};
+use fugit::ExtU32;
+use rp_pico::hal;
+use rp_pico::hal::pac;
+
+/// Long enough for the two second pauses some games make between rounds.
+pub const DEFAULT_TIMEOUT_MS: u32 = 5000;
+
+// The hardware counter is 24 bits and counts down twice per microsecond.
+const MAX_TIMEOUT_MS: u32 = 0xff_ffff / 2 / 1000;
+
+// Scratch1 holds this in the upper half and the reason in the lower half,
+// scratch2 the detail. Scratch0 belongs to usb_storage, scratch3 to the
+// launcher, and the boot ROM uses scratch4 to scratch7.
+const CRASH_MAGIC: u32 = 0xc4a5_0000;
+const GAME_REASON: u32 = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CrashReason {
+    /// The watchdog wasn't fed in time.
+    Hang,
+    /// A panic. The detail is the line it happened on.
+    Panic,
+    /// A hard fault. The detail is the program counter.
+    HardFault,
+    /// Recorded by the game, with a code below 0x8000.
+    Game(u16),
+}
+
+impl CrashReason {
+    fn code(self) -> u32 {
+        match self {
+            CrashReason::Hang => 1,
+            CrashReason::Panic => 2,
+            CrashReason::HardFault => 3,
+            CrashReason::Game(code) => GAME_REASON | (code as u32 & 0x7fff),
+        }
+    }
+
+    fn from_code(code: u32) -> Option<Self> {
+        match code {
+            1 => Some(CrashReason::Hang),
+            2 => Some(CrashReason::Panic),
+            3 => Some(CrashReason::HardFault),
+            code if code & GAME_REASON != 0 => Some(CrashReason::Game((code & 0x7fff) as u16)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Crash {
+    pub reason: CrashReason,
+    pub detail: u32,
+}
+
+/// Records why the device is about to be reset by the watchdog. Kept
+/// across the reset, and read back with `take_crash`.
+pub fn stash_crash_reason(reason: CrashReason, detail: u32) {
+    let watchdog = unsafe { &*pac::WATCHDOG::PTR };
+    watchdog
+        .scratch1
+        .write(|w| unsafe { w.bits(CRASH_MAGIC | reason.code()) });
+    watchdog.scratch2.write(|w| unsafe { w.bits(detail) });
+}
+
+/// Returns the crash that caused the last reset, if the watchdog reset
+/// the device, and forgets it.
+pub fn take_crash() -> Option<Crash> {
+    let watchdog = unsafe { &*pac::WATCHDOG::PTR };
+    let stashed = watchdog.scratch1.read().bits();
+    let detail = watchdog.scratch2.read().bits();
+    watchdog.scratch1.write(|w| unsafe { w.bits(0) });
+    watchdog.scratch2.write(|w| unsafe { w.bits(0) });
+    let reason = watchdog.reason.read();
+    if reason.timer().bit_is_clear() && reason.force().bit_is_clear() {
+        return None;
+    }
+    if stashed & 0xffff_0000 == CRASH_MAGIC {
+        if let Some(reason) = CrashReason::from_code(stashed & 0xffff) {
+            return Some(Crash { reason, detail });
+        }
+    }
+    Some(Crash {
+        reason: CrashReason::Hang,
+        detail: 0,
+    })
+}
+
+/// Records `reason` and resets the device through the watchdog at once.
+pub fn crash(reason: CrashReason, detail: u32) -> ! {
+    stash_crash_reason(reason, detail);
+    let watchdog = unsafe { &*pac::WATCHDOG::PTR };
+    watchdog.ctrl.write(|w| w.trigger().set_bit());
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
+
+/// Shows `crash` until A is pressed.
+pub fn show_crash(crash: &Crash, display: &mut Display, input: &Input) {
+    logging::error!("Rebooted after a crash: {:?}", crash);
+    let mut message = heapless::String::<64>::new();
+    let _ = match crash.reason {
+        CrashReason::Hang => write!(message, "The game\nstopped responding"),
+        CrashReason::Panic => write!(message, "The game crashed\nat line {}", crash.detail),
+        CrashReason::HardFault => write!(message, "The game crashed\nat {:#010x}", crash.detail),
+        CrashReason::Game(code) => write!(
+            message,
+            "The game crashed\nwith code {} ({})",
+            code, crash.detail
+        ),
+    };
+    let _ = write!(message, "\n\nPress A");
+    display.draw(|display| {
+        display.clear(Rgb565::BLACK).unwrap();
+        let style = MonoTextStyle::new(&FONT_10X20, Rgb565::WHITE);
+        let center = display.bounding_box().center();
+        Text::with_alignment(
+            &message,
+            center - Point::new(0, 40),
+            style,
+            Alignment::Center,
+        )
+        .draw(display)
+        .unwrap();
+    });
+    while !input.button_a.is_held() {}
+    while input.button_a.is_held() {}
+}
+
+pub struct Watchdog {
+    watchdog: hal::watchdog::Watchdog,
+    timeout_ms: u32,
+    running: bool,
+    paused: bool,
+}
+
+impl Watchdog {
+    pub(crate) fn new(mut watchdog: hal::watchdog::Watchdog) -> Self {
+        watchdog.pause_on_debug(true);
+        Watchdog {
+            watchdog,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            running: false,
+            paused: false,
+        }
+    }
+
+    /// Starts the watchdog, which resets the device unless `feed` is called
+    /// at least every `timeout_ms`, at most about 8 seconds.
+    pub fn start(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms.min(MAX_TIMEOUT_MS);
+        self.watchdog.start((self.timeout_ms * 1000).micros());
+        self.running = true;
+        self.paused = false;
+    }
+
+    pub fn feed(&mut self) {
+        if self.running {
+            self.watchdog.feed();
+        }
+    }
+
+    /// Stops the watchdog for a while, for example while sleeping until a
+    /// button is pressed or loading something slow.
+    pub fn pause(&mut self) {
+        if self.running {
+            self.watchdog.disable();
+            self.running = false;
+            self.paused = true;
+        }
+    }
+
+    /// Restarts the watchdog after `pause`.
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.start(self.timeout_ms);
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+}
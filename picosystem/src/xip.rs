@@ -0,0 +1,99 @@
+//! Counters of the XIP cache, which holds recently read flash.
+//!
+//! Code and assets are read from flash through a 16K cache, and a miss
+//! stalls whoever reads it, core or DMA, while the flash chip is read. The
+//! cache counts every access and every hit, so the misses show how much of
+//! a frame waits for flash: a high miss count while drawing suggests
+//! keeping the assets drawn most raw in RAM, or moving hot code there with
+//! `#[ram_code]`.
+//!
+//! `stats` reads the counters and `measure` counts the accesses of one
+//! piece of code. With `set_sampling` the counters are read and cleared at
+//! the end of every frame by `Hardware::draw`, and `last_frame` gives the
+//! counts of the frame before, which the debug overlay then shows. Both
+//! cores and DMA share the counters.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use critical_section::Mutex;
+use rp_pico::hal::pac;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XipStats {
+    /// Reads served by the cache.
+    pub hits: u32,
+    /// All reads through XIP, including uncached ones.
+    pub accesses: u32,
+}
+
+impl XipStats {
+    /// Reads that went to the flash chip.
+    pub fn misses(&self) -> u32 {
+        self.accesses.saturating_sub(self.hits)
+    }
+
+    /// Hits as a percentage of accesses, 100 without any.
+    pub fn hit_rate(&self) -> u32 {
+        match self.accesses {
+            0 => 100,
+            accesses => (self.hits.min(accesses) as u64 * 100 / accesses as u64) as u32,
+        }
+    }
+}
+
+static SAMPLING: AtomicBool = AtomicBool::new(false);
+static LAST_FRAME: Mutex<Cell<Option<XipStats>>> = Mutex::new(Cell::new(None));
+
+fn xip_ctrl() -> &'static pac::xip_ctrl::RegisterBlock {
+    unsafe { &*pac::XIP_CTRL::PTR }
+}
+
+/// The counts since they were last cleared. They saturate rather than
+/// wrap.
+pub fn stats() -> XipStats {
+    let xip_ctrl = xip_ctrl();
+    XipStats {
+        hits: xip_ctrl.ctr_hit.read().bits(),
+        accesses: xip_ctrl.ctr_acc.read().bits(),
+    }
+}
+
+pub fn clear() {
+    let xip_ctrl = xip_ctrl();
+    xip_ctrl.ctr_hit.write(|w| unsafe { w.bits(0) });
+    xip_ctrl.ctr_acc.write(|w| unsafe { w.bits(0) });
+}
+
+/// Runs `f` and returns the accesses made while it ran, by anything. Clears
+/// the counters, so it spoils the sample of the current frame.
+pub fn measure<R>(f: impl FnOnce() -> R) -> (R, XipStats) {
+    clear();
+    let result = f();
+    (result, stats())
+}
+
+/// Reads and clears the counters at the end of every frame.
+pub fn set_sampling(enabled: bool) {
+    SAMPLING.store(enabled, Ordering::Relaxed);
+    clear();
+    critical_section::with(|cs| LAST_FRAME.borrow(cs).set(None));
+}
+
+pub fn is_sampling() -> bool {
+    SAMPLING.load(Ordering::Relaxed)
+}
+
+/// The counts of the last full frame while sampling.
+pub fn last_frame() -> Option<XipStats> {
+    critical_section::with(|cs| LAST_FRAME.borrow(cs).get())
+}
+
+/// Takes the sample of the frame. Called by `Hardware::draw`.
+pub(crate) fn end_frame() {
+    if !is_sampling() {
+        return;
+    }
+    let frame = stats();
+    clear();
+    critical_section::with(|cs| LAST_FRAME.borrow(cs).set(Some(frame)));
+}
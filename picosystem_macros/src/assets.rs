@@ -0,0 +1,168 @@
+// All of a game's assets declared in one block, with their flash use.
+
+use proc_macro::TokenStream;
+use syn::parse::{Parse, ParseStream, Result};
+use syn::{parenthesized, parse_macro_input, Ident, LitInt, Token};
+
+use crate::{atlas, background, font, map, sound, Sprite};
+
+/// The code generated for an asset and the flash its data takes.
+pub struct Asset {
+    pub code: String,
+    pub flash: usize,
+    /// The method of the assets struct returning the asset.
+    pub accessor: String,
+}
+
+impl Asset {
+    /// An asset whose function `name` returns `return_type`.
+    pub fn new(name: &Ident, return_type: &str, code: String, flash: usize) -> Self {
+        Asset {
+            code,
+            flash,
+            accessor: format!("pub fn {0}(&self) -> {1} {{ {0}() }}\n", name, return_type),
+        }
+    }
+}
+
+enum Entry {
+    Sprite(Sprite),
+    Atlas(atlas::Atlas),
+    Image(background::ImageArgs),
+    Map(map::MapArgs),
+    Sound(sound::SoundArgs),
+    Font(font::FontInput),
+}
+
+struct Assets {
+    public: bool,
+    struct_name: Ident,
+    flash_budget: Option<LitInt>,
+    entries: Vec<(Ident, Entry)>,
+}
+
+impl Parse for Assets {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let public = input.parse::<Option<Token![pub]>>()?.is_some();
+        input.parse::<Token![struct]>()?;
+        let struct_name = input.parse()?;
+        input.parse::<Token![;]>()?;
+        let mut flash_budget = None;
+        if input.peek(Ident) && input.peek2(Token![=]) {
+            let name: Ident = input.parse()?;
+            if name != "flash_budget" {
+                return Err(syn::Error::new(name.span(), "expected `flash_budget`"));
+            }
+            input.parse::<Token![=]>()?;
+            flash_budget = Some(input.parse()?);
+            input.parse::<Token![;]>()?;
+        }
+        let mut entries = Vec::new();
+        while !input.is_empty() {
+            let kind: Ident = input.parse()?;
+            input.parse::<Token![!]>()?;
+            let content;
+            parenthesized!(content in input);
+            let entry = match kind.to_string().as_str() {
+                "sprite" => Entry::Sprite(content.parse()?),
+                "atlas" => Entry::Atlas(content.parse()?),
+                "image" => Entry::Image(content.parse()?),
+                "map" => Entry::Map(content.parse()?),
+                "sound" => Entry::Sound(content.parse()?),
+                "font" => Entry::Font(content.parse()?),
+                _ => {
+                    return Err(syn::Error::new(
+                        kind.span(),
+                        "expected `sprite!`, `atlas!`, `image!`, `map!`, `sound!` or `font!`",
+                    ))
+                }
+            };
+            input.parse::<Token![;]>()?;
+            entries.push((kind, entry));
+        }
+        Ok(Assets {
+            public,
+            struct_name,
+            flash_budget,
+            entries,
+        })
+    }
+}
+
+pub fn assets(input: TokenStream) -> TokenStream {
+    let Assets {
+        public,
+        struct_name,
+        flash_budget,
+        entries,
+    } = parse_macro_input!(input as Assets);
+
+    let mut code = String::new();
+    let mut accessors = String::new();
+    let mut report = Vec::new();
+    let mut total = 0;
+    for (kind, entry) in entries {
+        let (name, asset) = match entry {
+            Entry::Sprite(args) => (args.function_name.clone(), crate::sprite_asset(args)),
+            Entry::Atlas(args) => (args.function_name.clone(), atlas::atlas_asset(args)),
+            Entry::Image(args) => (args.function_name.clone(), background::image_asset(args)),
+            Entry::Map(args) => (args.function_name.clone(), map::map_asset(args)),
+            Entry::Sound(args) => (args.function_name.clone(), sound::sound_asset(args)),
+            Entry::Font(args) => (args.function_name.clone(), font::font_asset(args)),
+        };
+        code.push_str(&asset.code);
+        accessors.push_str(&asset.accessor);
+        report.push(format!("{} {}: {} bytes", kind, name, asset.flash));
+        total += asset.flash;
+    }
+
+    eprintln!("Assets of {}:", struct_name);
+    for line in report.iter() {
+        eprintln!("  {}", line);
+    }
+    eprintln!("  total: {} bytes", total);
+    if let Some(budget) = flash_budget {
+        let limit = match budget.base10_parse::<usize>() {
+            Ok(limit) => limit,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        if total > limit {
+            return syn::Error::new(
+                budget.span(),
+                format!(
+                    "the assets of {} take {} bytes of flash, over the budget of {}",
+                    struct_name, total, limit
+                ),
+            )
+            .to_compile_error()
+            .into();
+        }
+    }
+
+    let docs: String = report
+        .iter()
+        .map(|line| format!("/// - {}\n", line))
+        .collect();
+    code.push_str(&format!(
+        r#"
+        /// Assets declared with `assets!`, and the flash their data takes:
+        ///
+        {}
+        #[derive(Debug, Clone, Copy, Default)]
+        {}struct {};
+
+        impl {} {{
+            /// Flash taken by the data of all the assets, in bytes.
+            pub const FLASH_BYTES: usize = {};
+
+            {}
+        }}"#,
+        docs,
+        if public { "pub " } else { "" },
+        struct_name,
+        struct_name,
+        total,
+        accessors
+    ));
+    code.parse().unwrap()
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
 use image::io::Reader as ImageReader;
@@ -6,12 +7,18 @@ use proc_macro::TokenStream;
 use syn::parse::{Parse, ParseStream, Result};
 use syn::{parse_macro_input, Ident, LitInt, LitStr, Token};
 
-const TILE_SIZE: usize = 32;
+use crate::assets::Asset;
+use crate::color::{ColorOptions, Dither};
+use crate::compression::Compression;
+use crate::palette;
 
-struct Atlas {
-    function_name: Ident,
+pub struct Atlas {
+    pub function_name: Ident,
     path: LitStr,
     tile_size: LitInt,
+    colors: Option<LitInt>,
+    color_options: ColorOptions,
+    compression: Compression,
 }
 
 impl Parse for Atlas {
@@ -21,61 +28,169 @@ impl Parse for Atlas {
         let path = input.parse()?;
         input.parse::<Token![,]>()?;
         let tile_size = input.parse()?;
+        let mut colors = None;
+        let mut color_options = ColorOptions::default();
+        let mut compression = Compression::default();
+        while input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            if input.peek(LitInt) {
+                colors = Some(input.parse()?);
+                continue;
+            }
+            let option: Ident = input.parse()?;
+            if !color_options.parse_option(&option, input)?
+                && !compression.parse_option(&option, input)?
+            {
+                return Err(syn::Error::new(
+                    option.span(),
+                    "expected a number of colors, `dither`, `gamma` or `compression`",
+                ));
+            }
+        }
         Ok(Atlas {
             function_name,
             path,
             tile_size,
+            colors,
+            color_options,
+            compression,
         })
     }
 }
 
+// Mirrorings tried when looking for a copy of a tile, unflipped first.
+const FLIPS: [(bool, bool); 4] = [(false, false), (true, false), (false, true), (true, true)];
+
+/// The pixels and mask of a `size` pixel tile, mirrored.
+fn flip_tile(
+    pixels: &[u16],
+    mask: &[u32],
+    size: usize,
+    horizontal: bool,
+    vertical: bool,
+) -> (Vec<u16>, Vec<u32>) {
+    let mut rows: Vec<&[u16]> = pixels.chunks(size).collect();
+    let mut mask = mask.to_vec();
+    if vertical {
+        rows.reverse();
+        mask.reverse();
+    }
+    let pixels = rows
+        .iter()
+        .flat_map(|row| {
+            let row = row.iter().copied();
+            if horizontal {
+                row.rev().collect::<Vec<_>>()
+            } else {
+                row.collect()
+            }
+        })
+        .collect();
+    if horizontal {
+        for m in mask.iter_mut() {
+            *m = m.reverse_bits() >> (32 - size);
+        }
+    }
+    (pixels, mask)
+}
+
 pub fn atlas(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as Atlas);
+    atlas_asset(args).code.parse().unwrap()
+}
+
+pub fn atlas_asset(args: Atlas) -> Asset {
     let Atlas {
         function_name,
         path,
         tile_size,
-    } = parse_macro_input!(input as Atlas);
+        colors,
+        color_options,
+        compression,
+    } = args;
     let tile_size = tile_size.base10_parse::<u32>().unwrap();
-    assert_eq!(tile_size as usize, TILE_SIZE);
+    assert!(
+        matches!(tile_size, 8 | 16 | 32),
+        "tiles must be 8, 16 or 32 pixels"
+    );
+    // Masks use a bit per pixel of a u32 row.
+    let size = tile_size as usize;
     let mut fullpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     fullpath.pop();
     fullpath.push(path.value());
     let pathstr = fullpath.to_str().unwrap();
-    let img = ImageReader::open(&fullpath)
+    let mut img = ImageReader::open(&fullpath)
         .expect(&format!("Could not load image {:?}", &pathstr))
         .decode()
         .expect(&format!("Could not decode image {:?}", &pathstr))
         .into_rgba8();
 
-    let mut tile_index = 0;
     let mut code = String::new();
+
+    // Palettized atlases share one palette, stored big-endian like the tile data.
+    let palette_name = format!("{}_PALETTE", function_name.to_string().to_uppercase());
+    // The whole image is reduced at once, so dithering carries across tiles.
+    let rgb565 = color_options.rgb565(&img, |p| p[3] == 255);
+    let indexed = colors.map(|colors| {
+        let colors = colors.base10_parse::<usize>().unwrap();
+        assert_eq!(
+            color_options.dither,
+            Dither::Truncate,
+            "palettized atlases can't be dithered"
+        );
+        color_options.apply_gamma(&mut img);
+        let indexed = palette::index_colors(img.pixels().map(|p| &p.0[..]), colors);
+        let bits_per_pixel = palette::bits_per_pixel(colors);
+        let palette: Vec<u16> = indexed
+            .palette
+            .iter()
+            .map(|&c| palette::rgb565(c).to_be())
+            .collect();
+        code.push_str(&format!(
+            r#"
+        static {}: picosystem::tile::Palette = picosystem::tile::Palette {{
+            bits_per_pixel: {},
+            colors: &{:?},
+        }};"#,
+            &palette_name, bits_per_pixel, &palette
+        ));
+        (indexed, bits_per_pixel)
+    });
+
+    let palette_code = match &indexed {
+        Some(_) => format!("Some(&{})", &palette_name),
+        None => "None".to_string(),
+    };
+    // Data and masks are shared by mirror images, so they live outside the
+    // tile functions.
+    let statics_prefix = function_name.to_string().to_uppercase();
+    // The first index of each distinct tile, by its pixels and mask.
+    let mut tiles: HashMap<(Vec<u16>, Vec<u32>), usize> = HashMap::new();
+    // The codec and checksum of the data of each distinct tile.
+    let mut crcs: HashMap<usize, (picosystem_compressor::Codec, u32)> = HashMap::new();
+    let mut flash = indexed
+        .as_ref()
+        .map_or(0, |(indexed, _)| indexed.palette.len() * 2);
+    let mut tile_index = 0;
     for y in 0..img.height() / tile_size {
         for x in 0..img.width() / tile_size {
             let tile = img.view(x * tile_size, y * tile_size, tile_size, tile_size);
 
-            let transparent_color = 0;
-            let mut found_transparent_color = false;
+            // Transparent pixels are 0.
             let data: Vec<u16> = tile
                 .pixels()
-                .map(|(_, _, p)| {
-                    let r = p[0] as u16;
-                    let g = p[1] as u16;
-                    let b = p[2] as u16;
-                    let a = p[3] as u16;
-                    if a != 255 {
-                        found_transparent_color = true;
-                        transparent_color
-                    } else {
-                        (((r >> 3) << 11) | ((g >> 2) << 5) | ((b >> 3) << 0)).to_be()
-                    }
+                .map(|(tx, ty, _)| {
+                    let px = (x * tile_size + tx) as usize;
+                    let py = (y * tile_size + ty) as usize;
+                    rgb565[py * img.width() as usize + px].to_be()
                 })
                 .collect();
 
-            let mut mask = [0u32; TILE_SIZE];
-            for y in 0..TILE_SIZE {
+            let mut mask = vec![0u32; size];
+            for y in 0..size {
                 let mut m: u32 = 0;
-                for x in 0..TILE_SIZE {
-                    let color = data[(y * TILE_SIZE + x) as usize];
+                for x in 0..size {
+                    let color = data[(y * size + x) as usize];
                     if color != 0 {
                         m |= 1 << x;
                     }
@@ -83,37 +198,156 @@ pub fn atlas(input: TokenStream) -> TokenStream {
                 mask[y as usize] = m;
             }
 
-            let mut compressed_data = [0u16; 2 * TILE_SIZE * TILE_SIZE + 1];
-            let mut compressed_length =
-                picosystem_compressor::compress(&data, &mut compressed_data);
-            if compressed_length % 2 != 0 {
-                compressed_length += 1;
-            }
+            // Palettized tiles compress the packed indices instead of colors.
+            let pixels: Vec<u16> = match &indexed {
+                Some((indexed, _)) => {
+                    let mut indices = Vec::with_capacity(size * size);
+                    let img_width = img.width() as usize;
+                    for ty in 0..size {
+                        let start = (y as usize * size + ty) * img_width + x as usize * size;
+                        indices.extend(
+                            indexed.indices[start..start + size]
+                                .iter()
+                                .map(|&i| i as u16),
+                        );
+                    }
+                    if indexed.transparent {
+                        for (ty, m) in mask.iter_mut().enumerate() {
+                            *m = (0..size)
+                                .filter(|tx| indices[ty * size + tx] != 0)
+                                .fold(0, |m, tx| m | (1 << tx));
+                        }
+                    } else {
+                        mask = vec![((1u64 << size) - 1) as u32; size];
+                    }
+                    indices
+                }
+                None => data,
+            };
+
+            // A tile that is a copy or a mirror image of an earlier one shares
+            // its data, and mirrored ones are flipped back as they are loaded.
+            let duplicate = FLIPS.iter().find_map(|&(horizontal, vertical)| {
+                let key = flip_tile(&pixels, &mask, size, horizontal, vertical);
+                tiles.get(&key).map(|&index| (index, horizontal, vertical))
+            });
+            match duplicate {
+                Some((index, false, false)) => {
+                    code.push_str(&format!(
+                        r#"
+        pub fn {0}{1}() -> &'static picosystem::tile::Tile {{
+            {0}{2}()
+        }}"#,
+                        &function_name, tile_index, index
+                    ));
+                }
+                Some((index, horizontal, vertical)) => {
+                    code.push_str(&format!(
+                        r#"
+        pub fn {}{}() -> &'static picosystem::tile::Tile {{
+            static TILE: picosystem::tile::Tile = picosystem::tile::Tile {{
+                data: &{}_DATA_{},
+                codec: picosystem::compression::Codec::{:?},
+                mask: &{}_MASK_{},
+                palette: {},
+                crc: {:#x},
+                flip_horizontal: {},
+                flip_vertical: {},
+            }};
+            &TILE
+        }}"#,
+                        &function_name,
+                        tile_index,
+                        &statics_prefix,
+                        index,
+                        crcs[&index].0,
+                        &statics_prefix,
+                        index,
+                        palette_code,
+                        crcs[&index].1,
+                        horizontal,
+                        vertical
+                    ));
+                }
+                None => {
+                    let data = match &indexed {
+                        Some((_, bits_per_pixel)) => {
+                            let indices: Vec<u8> = pixels.iter().map(|&i| i as u8).collect();
+                            palette::pack::<u16>(&indices, *bits_per_pixel)
+                        }
+                        None => pixels.clone(),
+                    };
+                    let (codec, compressed_data) = compression.compress(&data);
+                    let compressed_length = compressed_data.len();
+                    let crc = picosystem_compressor::crc32(
+                        &compressed_data
+                            .iter()
+                            .flat_map(|word| word.to_le_bytes())
+                            .collect::<Vec<u8>>(),
+                    );
 
-            code.push_str(&format!(
-                r#"
+                    code.push_str(&format!(
+                        r#"
+        static {}_DATA_{}: [u16; {}] = {:?};
+        static {}_MASK_{}: [u32; {}] = {:?};
         pub fn {}{}() -> &'static picosystem::tile::Tile {{
             static COMPRESSION_RATIO: u32 = {};
-            static DATA: [u16; {}] = {:?};
-            static MASK: [u32; {}] = {:?};
             static TILE: picosystem::tile::Tile = picosystem::tile::Tile {{
-                data: &DATA,
-                mask: &MASK,
+                data: &{}_DATA_{},
+                codec: picosystem::compression::Codec::{:?},
+                mask: &{}_MASK_{},
+                palette: {},
+                crc: {:#x},
+                flip_horizontal: false,
+                flip_vertical: false,
             }};
             &TILE
         }}"#,
-                &function_name,
-                tile_index,
-                (100.0 * compressed_length as f64 / data.len() as f64) as u32,
-                compressed_length,
-                &compressed_data[0..compressed_length],
-                mask.len(),
-                &mask
-            ));
+                        &statics_prefix,
+                        tile_index,
+                        compressed_length,
+                        &compressed_data,
+                        &statics_prefix,
+                        tile_index,
+                        mask.len(),
+                        &mask,
+                        &function_name,
+                        tile_index,
+                        (100.0 * compressed_length as f64 / data.len() as f64) as u32,
+                        &statics_prefix,
+                        tile_index,
+                        codec,
+                        &statics_prefix,
+                        tile_index,
+                        palette_code,
+                        crc
+                    ));
+                    flash += compressed_length * 2 + mask.len() * 4;
+                    tiles.insert((pixels, mask), tile_index);
+                    crcs.insert(tile_index, (codec, crc));
+                }
+            }
 
             tile_index += 1;
         }
     }
 
-    code.parse().unwrap()
+    let tile_functions: Vec<String> = (0..tile_index)
+        .map(|i| format!("{}{}", &function_name, i))
+        .collect();
+    let accessor = format!(
+        r#"
+        pub fn {}(&self, index: usize) -> &'static picosystem::tile::Tile {{
+            static TILES: [fn() -> &'static picosystem::tile::Tile; {}] = [{}];
+            TILES[index]()
+        }}"#,
+        &function_name,
+        tile_functions.len(),
+        tile_functions.join(", ")
+    );
+    Asset {
+        code,
+        flash,
+        accessor,
+    }
 }
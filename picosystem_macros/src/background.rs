@@ -0,0 +1,144 @@
+// `image!`: screen-sized pictures, run-length coded and drawn a row at a
+// time.
+
+use image::ImageReader;
+use picosystem_compressor::{compress, ctrl_word, max_compressed_len};
+use proc_macro::TokenStream;
+use std::env;
+use std::path::PathBuf;
+use syn::parse::{Parse, ParseStream, Result};
+use syn::{parse_macro_input, Ident, LitInt, LitStr, Token};
+
+use crate::assets::Asset;
+use crate::color::ColorOptions;
+
+// The width of the screen, which images are scaled to by default.
+const SCREEN_WIDTH: u32 = 240;
+
+pub struct ImageArgs {
+    pub function_name: Ident,
+    path: LitStr,
+    width: Option<LitInt>,
+    color_options: ColorOptions,
+}
+
+impl Parse for ImageArgs {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let function_name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let path = input.parse()?;
+        let mut width = None;
+        let mut color_options = ColorOptions::default();
+        while input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            if input.peek(LitInt) {
+                width = Some(input.parse()?);
+                continue;
+            }
+            let option: Ident = input.parse()?;
+            if !color_options.parse_option(&option, input)? {
+                return Err(syn::Error::new(
+                    option.span(),
+                    "expected a width, `dither` or `gamma`",
+                ));
+            }
+        }
+        Ok(ImageArgs {
+            function_name,
+            path,
+            width,
+            color_options,
+        })
+    }
+}
+
+/// Run-length codes `pixels` as one stream, with skips for the pixels that
+/// aren't `opaque`.
+fn encode(pixels: &[u16], opaque: &[bool]) -> Vec<u16> {
+    let mut stream = vec![pixels.len() as u16];
+    let mut start = 0;
+    while start < pixels.len() {
+        let visible = opaque[start];
+        let end = (start..pixels.len())
+            .find(|&i| opaque[i] != visible)
+            .unwrap_or(pixels.len());
+        if visible {
+            // Blocks stand alone, so the stream of the span goes on after
+            // its length word.
+            let mut compressed = vec![0u16; max_compressed_len(end - start)];
+            let length = compress(&pixels[start..end], &mut compressed);
+            stream.extend_from_slice(&compressed[1..length]);
+        } else {
+            let mut count = end - start;
+            while count > 0 {
+                let skip = count.min(255);
+                stream.push(ctrl_word(0, skip as u8));
+                count -= skip;
+            }
+        }
+        start = end;
+    }
+    stream
+}
+
+pub fn image(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as ImageArgs);
+    image_asset(args).code.parse().unwrap()
+}
+
+pub fn image_asset(args: ImageArgs) -> Asset {
+    let ImageArgs {
+        function_name,
+        path,
+        width,
+        color_options,
+    } = args;
+    let width = width.map_or(SCREEN_WIDTH, |width| width.base10_parse::<u32>().unwrap());
+    let mut fullpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    fullpath.pop();
+    fullpath.push(path.value());
+    let pathstr = fullpath.to_str().unwrap();
+    let img = ImageReader::open(&fullpath)
+        .expect(&format!("Could not load {:?}", &pathstr))
+        .decode()
+        .expect(&format!("Could not decode image {:?}", &pathstr))
+        .resize(width, 16384, image::imageops::FilterType::Triangle)
+        .into_rgba8();
+    assert!(
+        img.width() * img.height() <= u16::MAX as u32,
+        "images can't have more than 65535 pixels"
+    );
+
+    // Pixels less than half opaque are left out.
+    let opaque = |p: [u8; 4]| p[3] >= 128;
+    let pixels: Vec<u16> = color_options
+        .rgb565(&img, opaque)
+        .iter()
+        .map(|c| c.to_be())
+        .collect();
+    let visible: Vec<bool> = img.pixels().map(|p| opaque(p.0)).collect();
+    let data = encode(&pixels, &visible);
+
+    let code = format!(
+        r#"
+        pub fn {}() -> &'static picosystem::sprite::CompressedSprite<'static> {{
+            static DATA: [u16; {}] = {:?};
+            static IMAGE: picosystem::sprite::CompressedSprite<'static> = picosystem::sprite::CompressedSprite {{
+                size: embedded_graphics::geometry::Size::new({}, {}),
+                data: &DATA,
+            }};
+            &IMAGE
+        }}"#,
+        &function_name,
+        data.len(),
+        &data,
+        img.width(),
+        img.height()
+    );
+    Asset::new(
+        &function_name,
+        "&'static picosystem::sprite::CompressedSprite<'static>",
+        code,
+        data.len() * 2,
+    )
+}
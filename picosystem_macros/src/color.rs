@@ -0,0 +1,139 @@
+// Build-time reduction of 8-bit colors to RGB565 for sprites and tiles.
+
+use image::RgbaImage;
+use syn::parse::{ParseStream, Result};
+use syn::{Ident, LitFloat, Token};
+
+// 4x4 Bayer matrix, thresholds in 1/16ths of a quantization step.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+// Bits of red, green and blue.
+const BITS: [u32; 3] = [5, 6, 5];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// Drops the low bits, which bands smooth gradients.
+    Truncate,
+    /// Rounds to the closest color.
+    Nearest,
+    /// Adds a 4x4 Bayer pattern before rounding.
+    Ordered,
+    /// Floyd-Steinberg error diffusion.
+    Diffusion,
+}
+
+/// How colors are reduced, set with `dither = nearest | ordered | diffusion`
+/// and `gamma = <factor>` options of `sprite!` and `atlas!`.
+#[derive(Debug, Clone, Copy)]
+pub struct ColorOptions {
+    pub dither: Dither,
+    /// Channels are raised to the power of `1 / gamma` first, so factors
+    /// above 1 brighten mid tones.
+    pub gamma: f32,
+}
+
+impl Default for ColorOptions {
+    fn default() -> Self {
+        ColorOptions {
+            dither: Dither::Truncate,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl ColorOptions {
+    /// Parses the value of the option `name`, which `input` is at the `=`
+    /// of. Returns false if `name` is not a color option.
+    pub fn parse_option(&mut self, name: &Ident, input: ParseStream) -> Result<bool> {
+        if name == "dither" {
+            input.parse::<Token![=]>()?;
+            let mode: Ident = input.parse()?;
+            self.dither = match mode.to_string().as_str() {
+                "nearest" => Dither::Nearest,
+                "ordered" => Dither::Ordered,
+                "diffusion" => Dither::Diffusion,
+                _ => {
+                    return Err(syn::Error::new(
+                        mode.span(),
+                        "expected `nearest`, `ordered` or `diffusion`",
+                    ))
+                }
+            };
+        } else if name == "gamma" {
+            input.parse::<Token![=]>()?;
+            let gamma: LitFloat = input.parse()?;
+            self.gamma = gamma.base10_parse()?;
+            if self.gamma <= 0.0 {
+                return Err(syn::Error::new(gamma.span(), "gamma must be above 0"));
+            }
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Applies the gamma to the color channels of `img`, for images that
+    /// are then quantized to a palette.
+    pub fn apply_gamma(&self, img: &mut RgbaImage) {
+        if self.gamma == 1.0 {
+            return;
+        }
+        for pixel in img.pixels_mut() {
+            for c in pixel.0[..3].iter_mut() {
+                *c = self.gamma_correct(*c).round() as u8;
+            }
+        }
+    }
+
+    /// The RGB565 colors of `img`, row by row, with 0 for the pixels
+    /// `opaque` rejects. Dithering skips those.
+    pub fn rgb565(&self, img: &RgbaImage, opaque: impl Fn([u8; 4]) -> bool) -> Vec<u16> {
+        let width = img.width() as usize;
+        // Errors carried to the current and next rows by diffusion.
+        let mut errors = vec![[0f32; 3]; width + 2];
+        let mut next_errors = vec![[0f32; 3]; width + 2];
+        let mut colors = Vec::with_capacity(width * img.height() as usize);
+        for (y, row) in img.rows().enumerate() {
+            for (x, pixel) in row.enumerate() {
+                if !opaque(pixel.0) {
+                    colors.push(0);
+                    continue;
+                }
+                let mut color = 0;
+                for (channel, bits) in BITS.iter().enumerate() {
+                    let max = ((1 << bits) - 1) as f32;
+                    let value = self.gamma_correct(pixel.0[channel]);
+                    let level = match self.dither {
+                        Dither::Truncate => (value as u32 >> (8 - bits)) as f32,
+                        Dither::Nearest => (value * max / 255.0).round(),
+                        Dither::Ordered => {
+                            let threshold = (BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5;
+                            (value * max / 255.0 + threshold).round()
+                        }
+                        Dither::Diffusion => {
+                            let value = value + errors[x + 1][channel];
+                            let level = (value * max / 255.0).round().clamp(0.0, max);
+                            let error = value - level * 255.0 / max;
+                            errors[x + 2][channel] += error * 7.0 / 16.0;
+                            next_errors[x][channel] += error * 3.0 / 16.0;
+                            next_errors[x + 1][channel] += error * 5.0 / 16.0;
+                            next_errors[x + 2][channel] += error / 16.0;
+                            level
+                        }
+                    };
+                    color = (color << bits) | level.clamp(0.0, max) as u16;
+                }
+                colors.push(color);
+            }
+            errors = std::mem::replace(&mut next_errors, vec![[0f32; 3]; width + 2]);
+        }
+        colors
+    }
+
+    fn gamma_correct(&self, c: u8) -> f32 {
+        if self.gamma == 1.0 {
+            c as f32
+        } else {
+            255.0 * (c as f32 / 255.0).powf(1.0 / self.gamma)
+        }
+    }
+}
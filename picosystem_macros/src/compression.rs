@@ -0,0 +1,69 @@
+// The codec compressing an asset's data, chosen per asset.
+
+use picosystem_compressor::Codec;
+use syn::parse::{ParseStream, Result};
+use syn::{Ident, Token};
+
+/// Set with the `compression = rle | lz4 | heatshrink | smallest` option of
+/// `atlas!`. Run-length coding is the default, as it is the fastest to
+/// decode.
+#[derive(Debug, Clone, Copy)]
+pub enum Compression {
+    Codec(Codec),
+    /// The codec giving the shortest stream for each piece of data, the
+    /// faster to decode of equal ones.
+    Smallest,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Codec(Codec::Rle)
+    }
+}
+
+impl Compression {
+    /// Parses the value of the option `name`, which `input` is at the `=`
+    /// of. Returns false if `name` is not `compression`.
+    pub fn parse_option(&mut self, name: &Ident, input: ParseStream) -> Result<bool> {
+        if name != "compression" {
+            return Ok(false);
+        }
+        input.parse::<Token![=]>()?;
+        let codec: Ident = input.parse()?;
+        *self = match codec.to_string().as_str() {
+            "rle" => Compression::Codec(Codec::Rle),
+            "lz4" => Compression::Codec(Codec::Lz4),
+            "heatshrink" => Compression::Codec(Codec::Heatshrink),
+            "smallest" => Compression::Smallest,
+            _ => {
+                return Err(syn::Error::new(
+                    codec.span(),
+                    "expected `rle`, `lz4`, `heatshrink` or `smallest`",
+                ))
+            }
+        };
+        Ok(true)
+    }
+
+    /// `data` compressed and padded to whole 32-bit words, which the device
+    /// copies it in, and the codec used.
+    pub fn compress(&self, data: &[u16]) -> (Codec, Vec<u16>) {
+        let codecs = match self {
+            Compression::Codec(codec) => vec![*codec],
+            Compression::Smallest => Codec::ALL.to_vec(),
+        };
+        codecs
+            .into_iter()
+            .map(|codec| {
+                let mut compressed = vec![0u16; codec.max_compressed_len(data.len()) + 1];
+                let mut length = codec.compress(data, &mut compressed);
+                if length % 2 != 0 {
+                    length += 1;
+                }
+                compressed.truncate(length);
+                (codec, compressed)
+            })
+            .min_by_key(|(_, compressed)| compressed.len())
+            .unwrap()
+    }
+}
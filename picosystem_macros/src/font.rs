@@ -0,0 +1,289 @@
+use image::ImageReader;
+use proc_macro::TokenStream;
+use std::env;
+use std::path::{Path, PathBuf};
+use syn::parse::{Parse, ParseStream, Result};
+use syn::{parse_macro_input, Ident, LitInt, LitStr, Token};
+
+use crate::assets::Asset;
+
+// Fonts keep ASCII and Latin-1 to stay small.
+const FIRST_CHAR: u32 = 0x20;
+const LAST_CHAR: u32 = 0xff;
+// PNG sheets have 16 glyphs per row.
+const SHEET_COLUMNS: u32 = 16;
+// Size of picosystem::font::Glyph.
+const GLYPH_SIZE: usize = 16;
+
+pub struct FontInput {
+    pub function_name: Ident,
+    path: LitStr,
+    size: LitInt,
+}
+
+impl Parse for FontInput {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let function_name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let path = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let size = input.parse()?;
+        Ok(FontInput {
+            function_name,
+            path,
+            size,
+        })
+    }
+}
+
+struct Glyph {
+    code: u32,
+    x_offset: i32,
+    y_offset: i32,
+    advance: u32,
+    // Rows of the glyph's cell, with its top left at the offset.
+    rows: Vec<Vec<bool>>,
+}
+
+impl Glyph {
+    // Removes empty rows and columns around the set pixels.
+    fn trim(&mut self) {
+        let set = |row: &Vec<bool>| row.iter().any(|&p| p);
+        let top = self.rows.iter().position(set).unwrap_or(self.rows.len());
+        let bottom = self.rows.iter().rposition(set).map_or(top, |i| i + 1);
+        self.rows = self.rows[top..bottom].to_vec();
+        let width = self.rows.first().map_or(0, |row| row.len());
+        let column_set = |x: &usize| self.rows.iter().any(|row| row[*x]);
+        let left = (0..width).find(column_set).unwrap_or(0);
+        let right = (0..width).rev().find(column_set).map_or(left, |x| x + 1);
+        for row in self.rows.iter_mut() {
+            *row = row[left..right].to_vec();
+        }
+        self.x_offset += left as i32;
+        self.y_offset += top as i32;
+    }
+
+    fn width(&self) -> usize {
+        self.rows.first().map_or(0, |row| row.len())
+    }
+}
+
+struct Font {
+    line_height: u32,
+    ascent: u32,
+    spacing: &'static str,
+    glyphs: Vec<Glyph>,
+}
+
+// See `font!` for the layout.
+fn load_png(path: &Path, size: u32) -> Font {
+    let img = ImageReader::open(path)
+        .unwrap_or_else(|_| panic!("Could not load {:?}", path))
+        .decode()
+        .unwrap_or_else(|_| panic!("Could not decode image {:?}", path))
+        .into_rgba8();
+    let cell_width = img.width() / SHEET_COLUMNS;
+    let cells = SHEET_COLUMNS * (img.height() / size);
+    let glyphs = (0..cells)
+        .map(|cell| {
+            let code = if cell < 96 {
+                0x20 + cell
+            } else {
+                0xa0 + cell - 96
+            };
+            let (cell_x, cell_y) = (
+                cell % SHEET_COLUMNS * cell_width,
+                cell / SHEET_COLUMNS * size,
+            );
+            let rows = (0..size)
+                .map(|y| {
+                    (0..cell_width)
+                        .map(|x| {
+                            let p = img.get_pixel(cell_x + x, cell_y + y);
+                            p[3] >= 128 && (p[0] as u32 + p[1] as u32 + p[2] as u32) >= 3 * 128
+                        })
+                        .collect()
+                })
+                .collect();
+            Glyph {
+                code,
+                x_offset: 0,
+                y_offset: 0,
+                advance: cell_width,
+                rows,
+            }
+        })
+        .filter(|g| g.code <= LAST_CHAR)
+        .collect();
+    Font {
+        line_height: size,
+        ascent: size,
+        spacing: "Monospace",
+        glyphs,
+    }
+}
+
+fn load_bdf(path: &Path, size: u32) -> Font {
+    let text =
+        std::fs::read_to_string(path).unwrap_or_else(|_| panic!("Could not load {:?}", path));
+    let numbers = |line: &str| -> Vec<i32> {
+        line.split_whitespace()
+            .skip(1)
+            .map(|n| n.parse().unwrap())
+            .collect()
+    };
+
+    let mut ascent = None;
+    let mut descent = None;
+    let mut bounding_box = vec![0, size as i32, 0, 0];
+    let mut spacing = "Monospace";
+    let mut glyphs = Vec::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let keyword = line.split_whitespace().next().unwrap_or("");
+        match keyword {
+            "FONTBOUNDINGBOX" => bounding_box = numbers(line),
+            "FONT_ASCENT" => ascent = Some(numbers(line)[0]),
+            "FONT_DESCENT" => descent = Some(numbers(line)[0]),
+            "SPACING" if line.contains("\"P\"") => spacing = "Proportional",
+            "STARTCHAR" => {
+                let mut code = 0;
+                let mut advance = 0;
+                let mut bbx = vec![0; 4];
+                for line in lines.by_ref() {
+                    match line.split_whitespace().next().unwrap_or("") {
+                        "ENCODING" => code = numbers(line)[0],
+                        "DWIDTH" => advance = numbers(line)[0],
+                        "BBX" => bbx = numbers(line),
+                        "BITMAP" => break,
+                        _ => {}
+                    }
+                }
+                let rows: Vec<Vec<bool>> = lines
+                    .by_ref()
+                    .take_while(|line| !line.starts_with("ENDCHAR"))
+                    .map(|line| {
+                        let bits = u64::from_str_radix(line.trim(), 16).unwrap();
+                        let bit_count = line.trim().len() * 4;
+                        (0..bbx[0] as usize)
+                            .map(|x| bits & (1 << (bit_count - 1 - x)) != 0)
+                            .collect()
+                    })
+                    .collect();
+                if (FIRST_CHAR as i32..=LAST_CHAR as i32).contains(&code) {
+                    glyphs.push((code as u32, advance, bbx, rows));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let ascent = ascent.unwrap_or(bounding_box[1] + bounding_box[3]);
+    let descent = descent.unwrap_or(-bounding_box[3]);
+    let line_height = (ascent + descent) as u32;
+    assert_eq!(
+        line_height, size,
+        "{:?} is {} pixels high, not {}",
+        path, line_height, size
+    );
+    let glyphs = glyphs
+        .into_iter()
+        .map(|(code, advance, bbx, rows)| Glyph {
+            code,
+            x_offset: bbx[2],
+            // BBX offsets are from the baseline, up.
+            y_offset: ascent - bbx[3] - bbx[1],
+            advance: advance as u32,
+            rows,
+        })
+        .collect();
+    Font {
+        line_height,
+        ascent: ascent as u32,
+        spacing,
+        glyphs,
+    }
+}
+
+pub fn font(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as FontInput);
+    font_asset(args).code.parse().unwrap()
+}
+
+pub fn font_asset(args: FontInput) -> Asset {
+    let FontInput {
+        function_name,
+        path,
+        size,
+    } = args;
+    let size = size.base10_parse::<u32>().unwrap();
+    let mut fullpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    fullpath.pop();
+    fullpath.push(path.value());
+
+    let mut font = if fullpath.extension().is_some_and(|e| e == "bdf") {
+        load_bdf(&fullpath, size)
+    } else {
+        load_png(&fullpath, size)
+    };
+    font.glyphs.sort_by_key(|g| g.code);
+    font.glyphs.dedup_by_key(|g| g.code);
+    let advance = font.glyphs.iter().map(|g| g.advance).max().unwrap_or(0);
+
+    let mut bits: Vec<bool> = Vec::new();
+    let mut glyph_code = Vec::new();
+    for glyph in font.glyphs.iter_mut() {
+        glyph.trim();
+        glyph_code.push(format!(
+            "picosystem::font::Glyph {{ code: {}, x_offset: {}, y_offset: {}, width: {}, height: {}, advance: {}, bit_offset: {} }}",
+            glyph.code,
+            glyph.x_offset,
+            glyph.y_offset,
+            glyph.width(),
+            glyph.rows.len(),
+            glyph.advance,
+            bits.len(),
+        ));
+        bits.extend(glyph.rows.iter().flatten());
+    }
+    let bitmap: Vec<u8> = bits
+        .chunks(8)
+        .map(|byte| {
+            byte.iter()
+                .enumerate()
+                .fold(0u8, |b, (i, &set)| b | ((set as u8) << (7 - i)))
+        })
+        .collect();
+
+    let code = format!(
+        r#"
+        pub fn {}() -> &'static picosystem::font::Font {{
+            static GLYPHS: [picosystem::font::Glyph; {}] = [{}];
+            static BITMAP: [u8; {}] = {:?};
+            static FONT: picosystem::font::Font = picosystem::font::Font {{
+                line_height: {},
+                ascent: {},
+                advance: {},
+                spacing: picosystem::font::Spacing::{},
+                glyphs: &GLYPHS,
+                bitmap: &BITMAP,
+            }};
+            &FONT
+        }}"#,
+        function_name,
+        glyph_code.len(),
+        glyph_code.join(", "),
+        bitmap.len(),
+        bitmap,
+        font.line_height,
+        font.ascent,
+        advance,
+        font.spacing,
+    );
+    Asset::new(
+        &function_name,
+        "&'static picosystem::font::Font",
+        code,
+        glyph_code.len() * GLYPH_SIZE + bitmap.len(),
+    )
+}
@@ -0,0 +1,127 @@
+use image::ImageReader;
+use proc_macro::TokenStream;
+use std::env;
+use std::path::PathBuf;
+use syn::parse::{Parse, ParseStream, Result};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Ident, LitStr, Token};
+
+// Matches `picosystem::launcher::ICON_SIZE`.
+const ICON_SIZE: u32 = 32;
+
+struct Field {
+    name: Ident,
+    value: LitStr,
+}
+
+impl Parse for Field {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let value = input.parse()?;
+        Ok(Field { name, value })
+    }
+}
+
+#[derive(Default)]
+struct GameMeta {
+    title: Option<LitStr>,
+    version: Option<LitStr>,
+    author: Option<LitStr>,
+    icon: Option<LitStr>,
+}
+
+impl Parse for GameMeta {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let fields = Punctuated::<Field, Token![,]>::parse_terminated(input)?;
+        let mut meta = GameMeta::default();
+        for Field { name, value } in fields {
+            let slot = match name.to_string().as_str() {
+                "title" => &mut meta.title,
+                "version" => &mut meta.version,
+                "author" => &mut meta.author,
+                "icon" => &mut meta.icon,
+                _ => {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        "expected `title`, `version`, `author` or `icon`",
+                    ))
+                }
+            };
+            if slot.replace(value).is_some() {
+                return Err(syn::Error::new(name.span(), "given twice"));
+            }
+        }
+        if meta.title.is_none() {
+            return Err(input.error("missing `title`"));
+        }
+        Ok(meta)
+    }
+}
+
+// The pixels of the icon at `path`, scaled to `ICON_SIZE` pixels square,
+// and the transparent color if it has transparent pixels.
+fn load_icon(path: &str) -> (Vec<u16>, Option<u16>) {
+    let mut fullpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    fullpath.pop();
+    fullpath.push(path);
+    let img = ImageReader::open(&fullpath)
+        .unwrap_or_else(|_| panic!("Could not load {:?}", fullpath))
+        .decode()
+        .unwrap_or_else(|_| panic!("Could not decode image {:?}", fullpath))
+        .resize_exact(ICON_SIZE, ICON_SIZE, image::imageops::FilterType::Triangle)
+        .into_rgba8();
+
+    let transparent_color = 0;
+    let mut found_transparent_color = false;
+    let data = img
+        .pixels()
+        .map(|p| {
+            if p[3] != 255 {
+                found_transparent_color = true;
+                transparent_color
+            } else {
+                let [r, g, b] = [p[0] as u16, p[1] as u16, p[2] as u16];
+                ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3)
+            }
+        })
+        .collect();
+    (data, found_transparent_color.then_some(transparent_color))
+}
+
+pub fn game_meta(input: TokenStream) -> TokenStream {
+    let GameMeta {
+        title,
+        version,
+        author,
+        icon,
+    } = parse_macro_input!(input as GameMeta);
+
+    let mut header = format!(
+        "picosystem::launcher::GameHeader::new({:?})",
+        title.unwrap().value()
+    );
+    if let Some(version) = version {
+        header.push_str(&format!(".with_version({:?})", version.value()));
+    }
+    if let Some(author) = author {
+        header.push_str(&format!(".with_author({:?})", author.value()));
+    }
+    if let Some(icon) = icon {
+        let (data, transparent_color) = load_icon(&icon.value());
+        header.push_str(&format!(".with_icon(&{:?})", data));
+        if let Some(color) = transparent_color {
+            header.push_str(&format!(".with_transparent_color({})", color));
+        }
+    }
+
+    format!(
+        r#"
+        #[link_section = ".game_header"]
+        #[used]
+        pub static GAME_HEADER: picosystem::launcher::GameHeader = {};"#,
+        header
+    )
+    .parse()
+    .unwrap()
+}
@@ -1,5 +1,15 @@
+mod assets;
 mod atlas;
+mod background;
+mod color;
+mod compression;
+mod font;
+mod game_meta;
 mod map;
+mod music;
+mod palette;
+mod ram_code;
+mod sound;
 use image::io::Reader as ImageReader;
 use proc_macro::TokenStream;
 use std::env;
@@ -7,10 +17,16 @@ use std::path::PathBuf;
 use syn::parse::{Parse, ParseStream, Result};
 use syn::{parse_macro_input, Ident, LitInt, LitStr, Token};
 
+use crate::assets::Asset;
+use crate::color::{ColorOptions, Dither};
+
 struct Sprite {
     function_name: Ident,
     path: LitStr,
     width: LitInt,
+    colors: Option<LitInt>,
+    alpha: bool,
+    color_options: ColorOptions,
 }
 
 impl Parse for Sprite {
@@ -20,21 +36,67 @@ impl Parse for Sprite {
         let path = input.parse()?;
         input.parse::<Token![,]>()?;
         let width = input.parse()?;
+        let mut colors = None;
+        let mut alpha = false;
+        let mut color_options = ColorOptions::default();
+        while input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            if input.peek(LitInt) {
+                colors = Some(input.parse()?);
+                continue;
+            }
+            let option: Ident = input.parse()?;
+            if option == "alpha" {
+                alpha = true;
+            } else if !color_options.parse_option(&option, input)? {
+                return Err(syn::Error::new(
+                    option.span(),
+                    "expected a number of colors, `alpha`, `dither` or `gamma`",
+                ));
+            }
+        }
+        if colors.is_some() && alpha {
+            return Err(input.error("palettized sprites can't keep the alpha channel"));
+        }
         Ok(Sprite {
             function_name,
             path,
             width,
+            colors,
+            alpha,
+            color_options,
         })
     }
 }
 
+/// `sprite!(name, "path.png", width)` generates `name()` returning a
+/// `Sprite` scaled to `width`. With a fourth argument of 16 or 256 the image
+/// is quantized to that many colors and `name()` returns an `IndexedSprite`.
+/// With `alpha` as the fourth argument the sprite keeps the image's alpha
+/// channel in four bits per pixel, which blits blend with the screen.
+///
+/// Colors are truncated to RGB565 unless `dither = nearest` rounds them,
+/// `dither = ordered` adds a Bayer pattern first or `dither = diffusion`
+/// spreads the error over the neighboring pixels, which hides the banding
+/// of gradients. `gamma = 1.2` raises channels to the power of 1 / 1.2
+/// first, brightening mid tones. Both follow the other arguments, e.g.
+/// `sprite!(sky, "sky.png", 240, dither = ordered)`. Palettized sprites only
+/// take `gamma`.
 #[proc_macro]
 pub fn sprite(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as Sprite);
+    sprite_asset(args).code.parse().unwrap()
+}
+
+fn sprite_asset(args: Sprite) -> Asset {
     let Sprite {
         function_name,
         path,
         width,
-    } = parse_macro_input!(input as Sprite);
+        colors,
+        alpha,
+        color_options,
+    } = args;
     let width = width.base10_parse::<u32>().unwrap();
     let mut fullpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     fullpath.pop();
@@ -46,48 +108,174 @@ pub fn sprite(input: TokenStream) -> TokenStream {
         .expect(&format!("Could not decode image {:?}", &pathstr))
         .resize(width, 16384, image::imageops::FilterType::Triangle)
         .into_rgba8();
+
+    if let Some(colors) = colors {
+        let colors = colors.base10_parse::<usize>().unwrap();
+        assert_eq!(
+            color_options.dither,
+            Dither::Truncate,
+            "palettized sprites can't be dithered"
+        );
+        let mut img = img;
+        color_options.apply_gamma(&mut img);
+        return indexed_sprite(&function_name, &img, colors);
+    }
+
+    // Only fully transparent pixels are left out when the alpha channel is
+    // kept.
+    let opaque = |p: [u8; 4]| p[3] == 255 || (alpha && p[3] != 0);
     let transparent_color = 0;
-    let mut found_transparent_color = false;
-    let data: Vec<u16> = img
-        .pixels()
-        .map(|p| {
-            let r = p[0] as u16;
-            let g = p[1] as u16;
-            let b = p[2] as u16;
-            let a = p[3] as u16;
-            if a != 255 {
-                found_transparent_color = true;
-                transparent_color
-            } else {
-                ((r >> 3) << 11) | ((g >> 2) << 5) | ((b >> 3) << 0)
-            }
-        })
-        .collect();
+    let found_transparent_color = img.pixels().any(|p| !opaque(p.0));
+    let data = color_options.rgb565(&img, opaque);
+
+    // Four bits per pixel, rows starting on a byte boundary with the first
+    // pixel in the low bits.
+    let alpha_data: Option<Vec<u8>> = alpha.then(|| {
+        img.rows()
+            .flat_map(|row| {
+                let alpha: Vec<u8> = row.map(|p| p[3] >> 4).collect();
+                alpha
+                    .chunks(2)
+                    .map(|pair| pair[0] | pair.get(1).map_or(0, |a| a << 4))
+                    .collect::<Vec<u8>>()
+            })
+            .collect()
+    });
+    let (alpha_static, alpha_field) = match &alpha_data {
+        Some(alpha_data) => (
+            format!(
+                "static ALPHA: [u8; {}] = {:?};",
+                alpha_data.len(),
+                alpha_data
+            ),
+            "Some(&ALPHA)",
+        ),
+        None => (String::new(), "None"),
+    };
 
+    let flash = data.len() * 2 + alpha_data.as_ref().map_or(0, |alpha| alpha.len());
     let mut code = String::new();
     code.push_str(&format!(
         r#"
         pub fn {}() -> &'static picosystem::sprite::Sprite<'static> {{
             static DATA: [u16; {}] = {:?};
+            {}
             static SPRITE: picosystem::sprite::Sprite<'static> = picosystem::sprite::Sprite {{
                 size: embedded_graphics::geometry::Size::new({}, {}),
                 transparent_color: {:?},
-                data: &DATA
+                data: &DATA,
+                alpha: {}
             }};
             &SPRITE
         }}"#,
         &function_name,
         data.len(),
         &data,
+        alpha_static,
         img.width(),
         img.height(),
         if found_transparent_color {
             Some(transparent_color)
         } else {
             None
-        }
+        },
+        alpha_field
     ));
-    code.parse().unwrap()
+    Asset::new(
+        &function_name,
+        "&'static picosystem::sprite::Sprite<'static>",
+        code,
+        flash,
+    )
+}
+
+fn indexed_sprite(function_name: &Ident, img: &image::RgbaImage, colors: usize) -> Asset {
+    let bits_per_pixel = palette::bits_per_pixel(colors);
+    let indexed = palette::index_colors(img.pixels().map(|p| &p.0[..]), colors);
+    let palette: Vec<u16> = indexed
+        .palette
+        .iter()
+        .map(|&c| palette::rgb565(c))
+        .collect();
+    // Rows start on a byte boundary.
+    let data: Vec<u8> = indexed
+        .indices
+        .chunks(img.width() as usize)
+        .flat_map(|row| palette::pack::<u8>(row, bits_per_pixel))
+        .collect();
+
+    let code = format!(
+        r#"
+        pub fn {}() -> &'static picosystem::sprite::IndexedSprite<'static> {{
+            static PALETTE: [u16; {}] = {:?};
+            static DATA: [u8; {}] = {:?};
+            static SPRITE: picosystem::sprite::IndexedSprite<'static> = picosystem::sprite::IndexedSprite {{
+                size: embedded_graphics::geometry::Size::new({}, {}),
+                bits_per_pixel: {},
+                palette: &PALETTE,
+                transparent_index: {:?},
+                data: &DATA
+            }};
+            &SPRITE
+        }}"#,
+        function_name,
+        palette.len(),
+        &palette,
+        data.len(),
+        &data,
+        img.width(),
+        img.height(),
+        bits_per_pixel,
+        if indexed.transparent { Some(0u8) } else { None },
+    );
+    Asset::new(
+        function_name,
+        "&'static picosystem::sprite::IndexedSprite<'static>",
+        code,
+        palette.len() * 2 + data.len(),
+    )
+}
+
+/// `atlas!(name, "path.png", size)` cuts the image into `size` pixel tiles
+/// and generates `name0()`, `name1()` and so on, left to right and top to
+/// bottom, each returning a compressed `picosystem::tile::Tile`. With a color
+/// count as the fourth argument the tiles share a palette of that many
+/// colors. A tile identical to an earlier one returns that tile, and one
+/// that mirrors an earlier tile shares its data with flip flags set, so
+/// repeats in large atlases store no extra data.
+/// The `dither` and `gamma` options of `sprite!` may follow, and dithering
+/// runs over the whole image rather than tile by tile.
+///
+/// Tiles are run-length coded, which the DMA decompresses fastest. With
+/// `compression = lz4` or `compression = heatshrink` they take less flash
+/// and the CPU decompresses them, heatshrink being the densest and the
+/// slowest. `compression = smallest` codes each tile with whichever is
+/// shortest for it, and prints how many tiles use each.
+/// `assets! { ... }` declares all of a game's assets in one block and
+/// generates a struct with a method returning each of them, by the name of
+/// its function. The block starts with the struct and optionally a flash
+/// budget in bytes, followed by invocations of the asset macros:
+///
+/// ```text
+/// assets! {
+///     pub struct Assets;
+///     flash_budget = 600000;
+///     sprite!(ship, "games/assets/ship.png", 56);
+///     atlas!(terrain, "games/assets/terrain.png", 32);
+///     image!(title, "games/assets/title.png");
+///     map!(world, "games/assets/world.tmx", terrain);
+///     sound!(laser, "games/assets/laser.wav");
+///     font!(small, "games/assets/small.bdf", 8);
+/// }
+/// ```
+///
+/// The functions the macros generate are still there, and the method of an
+/// atlas takes the index of the tile. The flash each asset's data takes is
+/// printed while building and listed in the struct's documentation, and
+/// the build fails if they add up to more than the budget.
+#[proc_macro]
+pub fn assets(input: TokenStream) -> TokenStream {
+    assets::assets(input)
 }
 
 #[proc_macro]
@@ -95,7 +283,93 @@ pub fn atlas(input: TokenStream) -> TokenStream {
     atlas::atlas(input)
 }
 
+/// `font!(name, "path.bdf", size)` generates `name()` returning a
+/// `picosystem::font::Font` with the ASCII and Latin-1 glyphs of a BDF font,
+/// whose line height must be `size`. A PNG can be used instead: a sheet of
+/// `size` pixel high cells, 16 per row, starting at ' ' and laid out like the
+/// embedded-graphics font images. Light opaque pixels are set.
+#[proc_macro]
+pub fn font(input: TokenStream) -> TokenStream {
+    font::font(input)
+}
+
+/// `game_meta!(title = "Invaders", version = "1.0", author = "...", icon =
+/// "path.png")` declares the `picosystem::launcher::GameHeader` of the game,
+/// which the linker script puts at `picosystem::launcher::HEADER_OFFSET` in
+/// the image for the launcher and host tools to find. Only the title is
+/// required. The icon is scaled to 32 by 32 pixels.
+#[proc_macro]
+pub fn game_meta(input: TokenStream) -> TokenStream {
+    game_meta::game_meta(input)
+}
+
+/// `image!(name, "path.png")` generates `name()` returning a
+/// `picosystem::sprite::CompressedSprite` of the image scaled to the width
+/// of the screen, for backgrounds and title screens. The pixels are
+/// run-length coded as one stream, which `sprite::draw_compressed` decodes
+/// straight into the framebuffer a row at a time. A width may follow the
+/// path for smaller pictures such as logos, and the `dither` and `gamma`
+/// options of `sprite!` may follow that. Pixels less than half opaque are
+/// transparent. Images can't have more than 65535 pixels, a little more
+/// than the screen.
+#[proc_macro]
+pub fn image(input: TokenStream) -> TokenStream {
+    background::image(input)
+}
+
+/// `map!(name, "path.tmx")` generates `name()` returning the
+/// `picosystem::map::Map` of a Tiled map, whose tiles come from the
+/// functions of `atlas!(atlas, ...)`. A map with several tilesets, embedded
+/// or in external TSX files, names the atlas of each in the map's order, as
+/// in `map!(name, "path.tmx", terrain, buildings)`. Tiles of all tilesets
+/// are numbered together, each tileset after the ones before it, up to 2048.
 #[proc_macro]
 pub fn map(input: TokenStream) -> TokenStream {
     map::map(input)
 }
+
+/// Compiles a song in a text tracker format into a `picosystem::music::Song`.
+///
+/// ```text
+/// # Comments start with '#'.
+/// tempo 140                 # beats per minute, 4 rows per beat
+/// instrument square 160     # instrument 0: waveform and volume
+/// instrument noise 80       # instrument 1
+/// pattern                   # starts pattern 0, one row per line
+/// C-4 0 T150 | C-2 1        # note instrument effect, channels separated by '|'
+/// ---        | ---          # no change
+/// ===        | C#2 1 V40    # note off
+/// order 0 0                 # pattern order, defaults to each pattern once
+/// ```
+///
+/// Effects are `T<bpm>` (tempo), `V<volume>` and `J<order position>` (jump).
+///
+/// Instruments can follow the volume with `adsr <attack ms> <decay ms>
+/// <sustain 0-255> <release ms>`, `vibrato <depth in 1/16 semitones> <rate
+/// Hz>` and `slide <Hz per second>`, e.g. `instrument triangle 200 adsr 5 80
+/// 160 120 vibrato 4 6`. Note off starts the release.
+#[proc_macro]
+pub fn music(input: TokenStream) -> TokenStream {
+    music::music(input)
+}
+
+/// `#[ram_code]` places a function in RAM, so that it runs without waiting
+/// for flash when the XIP cache misses, which matters for code that runs
+/// while DMA and the CPU are busy moving pixels. The function is never
+/// inlined, which would put its code back in flash, but the functions it
+/// calls stay in flash unless they are inlined into it or marked too. With
+/// the `double-buffer` feature of the crate using it, the function stays
+/// in flash.
+#[proc_macro_attribute]
+pub fn ram_code(args: TokenStream, item: TokenStream) -> TokenStream {
+    ram_code::ram_code(args, item)
+}
+
+/// `sound!(name, "path.wav")` generates `name()` returning a
+/// `picosystem::audio::Sample`. The WAV may be 8 to 32-bit integer or 32-bit
+/// float PCM at any rate; it is mixed down to mono and stored as unsigned
+/// 8-bit samples at 22050 Hz.
+#[proc_macro]
+pub fn sound(input: TokenStream) -> TokenStream {
+    sound::sound(input)
+}
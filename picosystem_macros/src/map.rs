@@ -6,22 +6,32 @@ use std::collections::HashSet;
 use syn::parse::{Parse, ParseStream, Result};
 use syn::{parse_macro_input, Ident, LitStr, Token};
 
+use crate::assets::Asset;
+
 // local copy of constants from picosystem::map and picosystem::tile to avoid circular references.
 // If you change them there update them here as well.
 // Don't want to go to the trouble of introducing a common constants module for 3 numbers
 const INVALID_TILE: u16 = !0;
 const NUM_LAYERS: usize = 4;
-const TILE_SIZE: i32 = 32;
+const CHUNK_TILES: usize = 16;
 
 // local copy of MapTile struct. same reason as above
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct MapTile {
     pub layers: [u16; NUM_LAYERS],
 }
 
-struct MapArgs {
-    function_name: Ident,
+// Tile functions of maps with one tileset, unless named.
+const DEFAULT_ATLAS: &str = "atlas";
+const MAX_TILES: usize = 2048;
+// Function pointers and references on the target.
+const POINTER_SIZE: usize = 4;
+
+pub struct MapArgs {
+    pub function_name: Ident,
     path: LitStr,
+    // The atlas! function names of the tilesets, in the map's order.
+    atlases: Vec<Ident>,
 }
 
 impl Parse for MapArgs {
@@ -29,18 +39,211 @@ impl Parse for MapArgs {
         let function_name = input.parse()?;
         input.parse::<Token![,]>()?;
         let path = input.parse()?;
+        let mut atlases = Vec::new();
+        while input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            atlases.push(input.parse()?);
+        }
         Ok(MapArgs {
             function_name,
             path,
+            atlases,
+        })
+    }
+}
+
+// Bounding box of an object as x, y, width, height. Rotation is ignored.
+fn object_bounds(object: &tiled::ObjectData) -> Option<(i32, i32, u32, u32)> {
+    let (left, top, right, bottom) = match &object.shape {
+        tiled::ObjectShape::Rect { width, height }
+        | tiled::ObjectShape::Ellipse { width, height } => (0.0, 0.0, *width, *height),
+        tiled::ObjectShape::Polygon { points } | tiled::ObjectShape::Polyline { points } => {
+            points.iter().fold(
+                (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+                |(left, top, right, bottom), &(x, y)| {
+                    (left.min(x), top.min(y), right.max(x), bottom.max(y))
+                },
+            )
+        }
+        _ => return None,
+    };
+    let x = (object.x + left).floor() as i32;
+    let y = (object.y + top).floor() as i32;
+    let width = ((object.x + right).ceil() as i32 - x).max(1) as u32;
+    let height = ((object.y + bottom).ceil() as i32 - y).max(1) as u32;
+    Some((x, y, width, height))
+}
+
+// Splits a row major grid into chunks, padding the right and bottom edges
+// with `empty`. Returns the distinct non-empty chunks and, for each chunk
+// position, the index of its chunk or None if it is all `empty`.
+fn split_chunks<T: Clone + PartialEq>(
+    tiles: &[T],
+    width: usize,
+    height: usize,
+    empty: &T,
+) -> (Vec<Vec<T>>, Vec<Option<usize>>) {
+    let mut unique_chunks: Vec<Vec<T>> = Vec::new();
+    let mut table = Vec::new();
+    for chunk_y in 0..height.div_ceil(CHUNK_TILES) {
+        for chunk_x in 0..width.div_ceil(CHUNK_TILES) {
+            let mut chunk = Vec::with_capacity(CHUNK_TILES * CHUNK_TILES);
+            for y in chunk_y * CHUNK_TILES..(chunk_y + 1) * CHUNK_TILES {
+                for x in chunk_x * CHUNK_TILES..(chunk_x + 1) * CHUNK_TILES {
+                    if x < width && y < height {
+                        chunk.push(tiles[x + y * width].clone());
+                    } else {
+                        chunk.push(empty.clone());
+                    }
+                }
+            }
+            if chunk.iter().all(|tile| tile == empty) {
+                table.push(None);
+                continue;
+            }
+            let index = match unique_chunks.iter().position(|c| *c == chunk) {
+                Some(index) => index,
+                None => {
+                    unique_chunks.push(chunk);
+                    unique_chunks.len() - 1
+                }
+            };
+            table.push(Some(index));
+        }
+    }
+    (unique_chunks, table)
+}
+
+fn chunk_table_code(table: &[Option<usize>], prefix: &str) -> String {
+    table
+        .iter()
+        .map(|index| match index {
+            Some(index) => format!("Some(&{}{}),\n", prefix, index),
+            None => "None,\n".to_string(),
         })
+        .collect()
+}
+
+fn color_code(color: &tiled::Color) -> String {
+    format!(
+        "embedded_graphics::pixelcolor::Rgb565::new({}, {}, {})",
+        color.red >> 3,
+        color.green >> 2,
+        color.blue >> 3
+    )
+}
+
+fn color_property(layer: &tiled::Layer, name: &str) -> Option<tiled::Color> {
+    match layer.properties.get(name)? {
+        tiled::PropertyValue::ColorValue(color) => Some(*color),
+        _ => panic!("layer {:?}: {} must be a color", layer.name, name),
+    }
+}
+
+// Tiled's parallax factors in 1/256ths.
+fn parallax_code(factor: f32) -> i32 {
+    (factor * 256.0).round() as i32
+}
+
+// Tiles of all tilesets are numbered together, each tileset's after the
+// ones before it, so `offsets` holds the index of the first tile of each.
+fn tile_indices(tile_layer: &tiled::FiniteTileLayer, offsets: &[usize]) -> Vec<u16> {
+    let mut indices = Vec::new();
+    for y in 0..tile_layer.height() {
+        for x in 0..tile_layer.width() {
+            indices.push(match tile_layer.get_tile(x as i32, y as i32) {
+                Some(tile) => (offsets[tile.tileset_index()] + tile.id() as usize) as u16,
+                None => INVALID_TILE,
+            });
+        }
     }
+    indices
+}
+
+fn rectangle_code((x, y, width, height): (i32, i32, u32, u32)) -> String {
+    format!(
+        "embedded_graphics::primitives::Rectangle::new(embedded_graphics::prelude::Point::new({}, {}), embedded_graphics::prelude::Size::new({}, {}))",
+        x, y, width, height
+    )
+}
+
+fn property_code(value: &tiled::PropertyValue) -> Option<String> {
+    Some(match value {
+        tiled::PropertyValue::BoolValue(v) => {
+            format!("picosystem::map::PropertyValue::Bool({})", v)
+        }
+        tiled::PropertyValue::IntValue(v) => format!("picosystem::map::PropertyValue::Int({})", v),
+        tiled::PropertyValue::FloatValue(v) => {
+            format!("picosystem::map::PropertyValue::Float({:?}f32)", v)
+        }
+        tiled::PropertyValue::StringValue(v) | tiled::PropertyValue::FileValue(v) => {
+            format!("picosystem::map::PropertyValue::String({:?})", v)
+        }
+        tiled::PropertyValue::ColorValue(c) => {
+            format!("picosystem::map::PropertyValue::Color({})", color_code(c))
+        }
+        tiled::PropertyValue::ObjectValue(v) => {
+            format!("picosystem::map::PropertyValue::Object({})", v)
+        }
+        // Nested class properties have no flat representation.
+        tiled::PropertyValue::ClassValue { .. } => return None,
+    })
+}
+
+fn object_code(object: &tiled::ObjectData) -> String {
+    let (x, y, width, height) = match &object.shape {
+        tiled::ObjectShape::Point(..) => (object.x.floor() as i32, object.y.floor() as i32, 0, 0),
+        tiled::ObjectShape::Text { width, height, .. } => (
+            object.x.floor() as i32,
+            object.y.floor() as i32,
+            width.ceil() as u32,
+            height.ceil() as u32,
+        ),
+        _ => object_bounds(object).unwrap(),
+    };
+    // Tile objects are anchored at their bottom left corner.
+    let y = if object.tile_data().is_some() {
+        y - height as i32
+    } else {
+        y
+    };
+    let mut properties: Vec<_> = object.properties.iter().collect();
+    properties.sort_by_key(|(name, _)| name.as_str());
+    let mut properties_code = String::new();
+    for (name, value) in properties {
+        if let Some(value) = property_code(value) {
+            properties_code.push_str(&format!(
+                "picosystem::map::Property {{ name: {:?}, value: {} }},\n",
+                name, value
+            ));
+        }
+    }
+    format!(
+        "picosystem::map::MapObject {{ id: {}, name: {:?}, kind: {:?}, \
+         position: embedded_graphics::prelude::Point::new({}, {}), \
+         size: embedded_graphics::prelude::Size::new({}, {}), properties: &[{}] }},\n",
+        object.id(),
+        object.name,
+        object.user_type,
+        x,
+        y,
+        width,
+        height,
+        properties_code
+    )
 }
 
 pub fn map(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as MapArgs);
+    map_asset(args).code.parse().expect("Failed to parse code")
+}
+
+pub fn map_asset(args: MapArgs) -> Asset {
     let MapArgs {
         function_name,
         path,
-    } = parse_macro_input!(input as MapArgs);
+        mut atlases,
+    } = args;
 
     let mut loader = Loader::new();
     let mut fullpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -48,32 +251,109 @@ pub fn map(input: TokenStream) -> TokenStream {
     fullpath.push(path.value());
     let map = loader.load_tmx_map(&fullpath).expect("Failed to parse map");
 
-    assert_eq!(map.tile_width, TILE_SIZE as u32);
-    assert_eq!(map.tile_height, TILE_SIZE as u32);
-    assert_eq!(map.tilesets().len(), 1);
-    assert_eq!(map.layers().len() <= NUM_LAYERS, true);
+    assert!(
+        matches!(map.tile_width, 8 | 16 | 32) && map.tile_height == map.tile_width,
+        "tiles must be 8, 16 or 32 pixels square"
+    );
     assert_eq!(map.infinite(), false);
 
+    if atlases.is_empty() {
+        atlases.push(Ident::new(DEFAULT_ATLAS, function_name.span()));
+    }
+    assert_eq!(
+        atlases.len(),
+        map.tilesets().len(),
+        "name the atlas of each of the {} tilesets",
+        map.tilesets().len()
+    );
+    let mut offsets = Vec::new();
+    let mut num_tiles = 0;
+    for tileset in map.tilesets() {
+        assert_eq!(
+            (tileset.tile_width, tileset.tile_height),
+            (map.tile_width, map.tile_height),
+            "tileset {:?} has tiles of another size than the map",
+            tileset.name
+        );
+        offsets.push(num_tiles);
+        num_tiles += tileset.tilecount as usize;
+    }
+    assert!(
+        num_tiles <= MAX_TILES,
+        "maps can use at most {} tiles, the tilesets have {}",
+        MAX_TILES,
+        num_tiles
+    );
+
+    // Tile layers that scroll with the camera make up the map itself. Layers
+    // with other parallax factors, and layers with a "sky_top" color, are
+    // drawn behind them, or in front if they come after one of them.
     let mut tile_index_layers = Vec::<Vec<u16>>::new();
     let mut used_tile_functions: HashSet<u16> = HashSet::new();
+    let mut parallax_chunks_code = String::new();
+    let mut parallax_layers_code = String::new();
+    let mut num_parallax_layers = 0;
+    // Chunks, their tables and the tile functions, leaving out objects and
+    // colliders.
+    let mut flash = MAX_TILES * POINTER_SIZE;
     for layer in map.layers() {
-        let mut tile_index_layer = Vec::<u16>::new();
-        if let tiled::LayerType::Tiles(tiled::TileLayer::Finite(tile_layer)) =
-            &layer.layer_type()
-        {
-            for y in 0..tile_layer.height() {
-                for x in 0..tile_layer.width() {
-                    let tile_index = match tile_layer.get_tile(x as i32, y as i32) {
-                        Some(tile) => tile.id() as u16,
-                        None => INVALID_TILE,
-                    };
-                    tile_index_layer.push(tile_index);
-                    used_tile_functions.insert(tile_index);
-                }
-            }
-            tile_index_layers.push(tile_index_layer);
+        let foreground = !tile_index_layers.is_empty();
+        if let Some(top) = color_property(&layer, "sky_top") {
+            let bottom = color_property(&layer, "sky_bottom").unwrap_or(top);
+            parallax_layers_code.push_str(&format!(
+                "picosystem::map::MapLayer {{ name: {:?}, parallax_x: 0, parallax_y: 0, foreground: false, \
+                 kind: picosystem::map::LayerKind::Sky {{ top: {}, bottom: {} }} }},\n",
+                layer.name,
+                color_code(&top),
+                color_code(&bottom)
+            ));
+            num_parallax_layers += 1;
+            continue;
+        }
+        let tile_layer = match layer.layer_type() {
+            tiled::LayerType::Tiles(tiled::TileLayer::Finite(tile_layer)) => tile_layer,
+            _ => continue,
+        };
+        let indices = tile_indices(&tile_layer, &offsets);
+        used_tile_functions.extend(indices.iter().copied());
+        if layer.parallax_x == 1.0 && layer.parallax_y == 1.0 {
+            tile_index_layers.push(indices);
+            continue;
         }
+
+        let layer_index = num_parallax_layers;
+        num_parallax_layers += 1;
+        let (width, height) = (tile_layer.width() as usize, tile_layer.height() as usize);
+        let (chunks, table) = split_chunks(&indices, width, height, &INVALID_TILE);
+        flash += chunks.len() * CHUNK_TILES * CHUNK_TILES * 2 + table.len() * POINTER_SIZE;
+        for (i, chunk) in chunks.iter().enumerate() {
+            parallax_chunks_code.push_str(&format!(
+                "static LAYER{}_CHUNK{}: picosystem::map::LayerChunk = picosystem::map::LayerChunk {{ tiles: {:?} }};\n",
+                layer_index, i, chunk
+            ));
+        }
+        let repeat = matches!(
+            layer.properties.get("repeat"),
+            Some(tiled::PropertyValue::BoolValue(true))
+        );
+        parallax_layers_code.push_str(&format!(
+            "picosystem::map::MapLayer {{ name: {:?}, parallax_x: {}, parallax_y: {}, foreground: {}, \
+             kind: picosystem::map::LayerKind::Tiles {{ width: {}, height: {}, repeat: {}, chunks: &[{}] }} }},\n",
+            layer.name,
+            parallax_code(layer.parallax_x),
+            parallax_code(layer.parallax_y),
+            foreground,
+            width,
+            height,
+            repeat,
+            chunk_table_code(&table, &format!("LAYER{}_CHUNK", layer_index))
+        ));
     }
+    assert!(
+        !tile_index_layers.is_empty() && tile_index_layers.len() <= NUM_LAYERS,
+        "maps need 1 to {} tile layers without parallax",
+        NUM_LAYERS
+    );
 
     let mut tiles = Vec::<MapTile>::new();
     for i in 0..(tile_index_layers[0].len()) {
@@ -86,12 +366,102 @@ pub fn map(input: TokenStream) -> TokenStream {
         tiles.push(tile);
     }
 
+    // Empty chunks are left out and identical ones are shared.
+    let empty_tile = MapTile {
+        layers: [INVALID_TILE; NUM_LAYERS],
+    };
+    let (chunks, table) =
+        split_chunks(&tiles, map.width as usize, map.height as usize, &empty_tile);
+    flash += chunks.len() * CHUNK_TILES * CHUNK_TILES * NUM_LAYERS * 2 + table.len() * POINTER_SIZE;
+    let mut chunks_code = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        chunks_code.push_str(&format!(
+            "static CHUNK{}: picosystem::map::MapChunk = picosystem::map::MapChunk {{ tiles: {:?} }};\n",
+            i, chunk
+        ));
+    }
+    chunks_code.push_str(&parallax_chunks_code);
+    let chunk_table_code = chunk_table_code(&table, "CHUNK");
+
     let mut tile_functions_code = String::new();
-    for i in 0..2048 {
-        if used_tile_functions.contains(&i) {
-            tile_functions_code.push_str(&format!("atlas{},\n", i));
+    for i in 0..MAX_TILES {
+        let tileset = offsets.iter().rposition(|&offset| offset <= i).unwrap();
+        if used_tile_functions.contains(&(i as u16)) {
+            tile_functions_code.push_str(&format!(
+                "{}{},\n",
+                &atlases[tileset],
+                i - offsets[tileset]
+            ));
         } else {
-            tile_functions_code.push_str(&format!("atlas{},\n", 0));
+            tile_functions_code.push_str(&format!("{}{},\n", &atlases[0], 0));
+        }
+    }
+
+    // Animations and collision shapes of tiles, by their tileset and id in it.
+    let tileset_tiles = || {
+        map.tilesets()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, tileset)| tileset.tiles().map(move |(id, tile)| (index, id, tile)))
+    };
+
+    let mut animations_code = String::new();
+    let mut animated_tiles: Vec<_> = tileset_tiles()
+        .filter_map(|(tileset, id, tile)| Some((tileset, id, tile.animation.clone()?)))
+        .collect();
+    animated_tiles.sort_by_key(|(tileset, id, _)| (*tileset, *id));
+    for (tileset, id, frames) in animated_tiles {
+        let atlas = &atlases[tileset];
+        let mut frames_code = String::new();
+        for frame in frames.iter() {
+            frames_code.push_str(&format!(
+                "picosystem::map::AnimationFrame {{ tile: {}{}, duration_ms: {} }},\n",
+                atlas, frame.tile_id, frame.duration
+            ));
+        }
+        animations_code.push_str(&format!(
+            "picosystem::map::TileAnimation {{ tile: {}{}, frames: &[{}], duration_ms: {} }},\n",
+            atlas,
+            id,
+            frames_code,
+            frames.iter().map(|frame| frame.duration).sum::<u32>()
+        ));
+    }
+
+    let mut tile_colliders_code = String::new();
+    let mut collision_tiles: Vec<_> = tileset_tiles()
+        .filter_map(|(tileset, id, tile)| {
+            Some((offsets[tileset] + id as usize, tile.collision.clone()?))
+        })
+        .collect();
+    collision_tiles.sort_by_key(|(id, _)| *id);
+    for (id, collision) in collision_tiles {
+        for object in collision.object_data() {
+            if let Some(bounds) = object_bounds(object) {
+                tile_colliders_code.push_str(&format!(
+                    "picosystem::map::TileCollider {{ tile: {}, area: {} }},\n",
+                    id,
+                    rectangle_code(bounds)
+                ));
+            }
+        }
+    }
+
+    let mut colliders_code = String::new();
+    let mut objects_code = String::new();
+    for layer in map.layers() {
+        if let tiled::LayerType::Objects(object_layer) = layer.layer_type() {
+            if !layer.name.eq_ignore_ascii_case("collision") {
+                for object in object_layer.object_data() {
+                    objects_code.push_str(&object_code(object));
+                }
+                continue;
+            }
+            for object in object_layer.object_data() {
+                if let Some(bounds) = object_bounds(object) {
+                    colliders_code.push_str(&format!("{},\n", rectangle_code(bounds)));
+                }
+            }
         }
     }
 
@@ -99,15 +469,33 @@ pub fn map(input: TokenStream) -> TokenStream {
     code.push_str(&format!(
         r"
         pub fn {}() -> &'static Map {{
+            {}
             static MAP: Map = Map {{
                 width: {},
                 height: {},
-                tiles: &{:?},
+                tile_size: {},
+                chunks: &[{}],
                 tile_functions: [{}],
+                animations: &[{}],
+                tile_colliders: &[{}],
+                colliders: &[{}],
+                objects: &[{}],
+                parallax_layers: &[{}],
             }};
             &MAP
         }}",
-        &function_name, map.width, map.height, &tiles, &tile_functions_code
+        &function_name,
+        &chunks_code,
+        map.width,
+        map.height,
+        map.tile_width,
+        &chunk_table_code,
+        &tile_functions_code,
+        &animations_code,
+        &tile_colliders_code,
+        &colliders_code,
+        &objects_code,
+        &parallax_layers_code
     ));
-    code.parse().expect("Failed to parse code")
+    Asset::new(&function_name, "&'static picosystem::map::Map", code, flash)
 }
\ No newline at end of file
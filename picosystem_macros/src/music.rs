@@ -0,0 +1,343 @@
+use proc_macro::TokenStream;
+use std::env;
+use std::path::PathBuf;
+use syn::parse::{Parse, ParseStream, Result};
+use syn::{parse_macro_input, Ident, LitStr, Token};
+
+// local copy of constants from picosystem::music, see map.rs for why.
+const NOTE_NONE: u8 = 0;
+const NOTE_OFF: u8 = 255;
+const EFFECT_NONE: u8 = 0;
+const EFFECT_TEMPO: u8 = 1;
+const EFFECT_VOLUME: u8 = 2;
+const EFFECT_JUMP: u8 = 3;
+
+struct MusicArgs {
+    function_name: Ident,
+    path: LitStr,
+}
+
+impl Parse for MusicArgs {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let function_name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let path = input.parse()?;
+        Ok(MusicArgs {
+            function_name,
+            path,
+        })
+    }
+}
+
+struct Cell {
+    note: u8,
+    instrument: u8,
+    effect: u8,
+    param: u8,
+}
+
+struct Instrument {
+    waveform: &'static str,
+    volume: u8,
+    // Attack, decay and release in milliseconds, sustain level.
+    adsr: (u16, u16, u8, u16),
+    vibrato: (u8, u8),
+    slide: i16,
+}
+
+struct Song {
+    tempo: u8,
+    channels: usize,
+    instruments: Vec<Instrument>,
+    patterns: Vec<Vec<Cell>>,
+    order: Vec<u8>,
+}
+
+fn parse_note(s: &str) -> Option<u8> {
+    match s {
+        "---" => return Some(NOTE_NONE),
+        "===" => return Some(NOTE_OFF),
+        _ => {}
+    }
+    let bytes = s.as_bytes();
+    if bytes.len() != 3 {
+        return None;
+    }
+    let semitone = match bytes[0] {
+        b'C' => 0,
+        b'D' => 2,
+        b'E' => 4,
+        b'F' => 5,
+        b'G' => 7,
+        b'A' => 9,
+        b'B' => 11,
+        _ => return None,
+    };
+    let sharp = match bytes[1] {
+        b'-' => 0,
+        b'#' => 1,
+        _ => return None,
+    };
+    let octave = (bytes[2] as char).to_digit(10)? as u8;
+    // MIDI numbering: C-4 is 60.
+    Some((octave + 1) * 12 + semitone + sharp)
+}
+
+fn parse_effect(s: &str) -> Option<(u8, u8)> {
+    let effect = match s.chars().next()? {
+        'T' => EFFECT_TEMPO,
+        'V' => EFFECT_VOLUME,
+        'J' => EFFECT_JUMP,
+        _ => return None,
+    };
+    Some((effect, s[1..].parse().ok()?))
+}
+
+fn parse_cell(s: &str) -> std::result::Result<Cell, String> {
+    let mut fields = s.split_whitespace();
+    let note = fields.next().ok_or("empty cell")?;
+    let note = parse_note(note).ok_or(format!("invalid note {:?}", note))?;
+    let mut cell = Cell {
+        note,
+        instrument: 0,
+        effect: EFFECT_NONE,
+        param: 0,
+    };
+    for field in fields {
+        if field == "." {
+            continue;
+        } else if let Ok(instrument) = field.parse() {
+            cell.instrument = instrument;
+        } else {
+            let (effect, param) =
+                parse_effect(field).ok_or(format!("invalid effect {:?}", field))?;
+            cell.effect = effect;
+            cell.param = param;
+        }
+    }
+    Ok(cell)
+}
+
+fn parse_song(text: &str) -> std::result::Result<Song, String> {
+    let mut song = Song {
+        tempo: 120,
+        channels: 0,
+        instruments: Vec::new(),
+        patterns: Vec::new(),
+        order: Vec::new(),
+    };
+    for (line_index, line) in text.lines().enumerate() {
+        let error = |msg: String| format!("line {}: {}", line_index + 1, msg);
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match keyword {
+            "tempo" => {
+                song.tempo = rest
+                    .trim()
+                    .parse()
+                    .map_err(|_| error(format!("invalid tempo {:?}", rest)))?;
+            }
+            "instrument" => {
+                let mut fields = rest.split_whitespace();
+                let waveform = match fields.next() {
+                    Some("square") => "Square",
+                    Some("triangle") => "Triangle",
+                    Some("sawtooth") => "Sawtooth",
+                    Some("noise") => "Noise",
+                    other => return Err(error(format!("invalid waveform {:?}", other))),
+                };
+                let volume = fields
+                    .next()
+                    .unwrap_or("255")
+                    .parse()
+                    .map_err(|_| error("invalid volume".to_string()))?;
+                let mut instrument = Instrument {
+                    waveform,
+                    volume,
+                    adsr: (0, 0, 255, 0),
+                    vibrato: (0, 0),
+                    slide: 0,
+                };
+                while let Some(effect) = fields.next() {
+                    let mut number = |name: &str| {
+                        let field = fields.next().unwrap_or("");
+                        field
+                            .parse::<i32>()
+                            .map_err(|_| error(format!("invalid {} {:?}", name, field)))
+                    };
+                    let invalid = |name: &str| error(format!("{} out of range", name));
+                    match effect {
+                        "adsr" => {
+                            let attack = number("attack")?;
+                            let decay = number("decay")?;
+                            let sustain = number("sustain")?;
+                            let release = number("release")?;
+                            instrument.adsr = (
+                                attack.try_into().map_err(|_| invalid("attack"))?,
+                                decay.try_into().map_err(|_| invalid("decay"))?,
+                                sustain.try_into().map_err(|_| invalid("sustain"))?,
+                                release.try_into().map_err(|_| invalid("release"))?,
+                            );
+                        }
+                        "vibrato" => {
+                            let depth = number("vibrato depth")?;
+                            let rate = number("vibrato rate")?;
+                            instrument.vibrato = (
+                                depth.try_into().map_err(|_| invalid("vibrato depth"))?,
+                                rate.try_into().map_err(|_| invalid("vibrato rate"))?,
+                            );
+                        }
+                        "slide" => {
+                            instrument.slide =
+                                number("slide")?.try_into().map_err(|_| invalid("slide"))?;
+                        }
+                        other => {
+                            return Err(error(format!("invalid instrument effect {:?}", other)))
+                        }
+                    }
+                }
+                song.instruments.push(instrument);
+            }
+            "pattern" => song.patterns.push(Vec::new()),
+            "order" => {
+                for index in rest.split_whitespace() {
+                    let index = index
+                        .parse()
+                        .map_err(|_| error(format!("invalid pattern index {:?}", index)))?;
+                    song.order.push(index);
+                }
+            }
+            _ => {
+                let pattern = song
+                    .patterns
+                    .last_mut()
+                    .ok_or_else(|| error("row outside of a pattern".to_string()))?;
+                let cells = line
+                    .split('|')
+                    .map(|cell| parse_cell(cell).map_err(error))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                if song.channels == 0 {
+                    song.channels = cells.len();
+                } else if cells.len() != song.channels {
+                    return Err(error(format!(
+                        "expected {} channels, got {}",
+                        song.channels,
+                        cells.len()
+                    )));
+                }
+                pattern.extend(cells);
+            }
+        }
+    }
+
+    if song.order.is_empty() {
+        song.order = (0..song.patterns.len() as u8).collect();
+    }
+    for &index in song.order.iter() {
+        if index as usize >= song.patterns.len() {
+            return Err(format!("order refers to missing pattern {}", index));
+        }
+    }
+    for cell in song.patterns.iter().flatten() {
+        if cell.note != NOTE_NONE
+            && cell.note != NOTE_OFF
+            && cell.instrument as usize >= song.instruments.len()
+        {
+            return Err(format!("missing instrument {}", cell.instrument));
+        }
+    }
+    Ok(song)
+}
+
+pub fn music(input: TokenStream) -> TokenStream {
+    let MusicArgs {
+        function_name,
+        path,
+    } = parse_macro_input!(input as MusicArgs);
+
+    let mut fullpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    fullpath.pop();
+    fullpath.push(path.value());
+    let pathstr = fullpath.to_str().unwrap();
+    let text = std::fs::read_to_string(&fullpath)
+        .unwrap_or_else(|_| panic!("Could not load song {:?}", &pathstr));
+    let song = parse_song(&text).unwrap_or_else(|e| panic!("{}: {}", &pathstr, e));
+
+    let mut code = String::new();
+    code.push_str(&format!(
+        "pub fn {}() -> &'static picosystem::music::Song {{\n",
+        &function_name
+    ));
+
+    code.push_str(&format!(
+        "static INSTRUMENTS: [picosystem::music::Instrument; {}] = [\n",
+        song.instruments.len()
+    ));
+    for instrument in song.instruments.iter() {
+        let (attack, decay, sustain, release) = instrument.adsr;
+        code.push_str(&format!(
+            "picosystem::music::Instrument {{ waveform: picosystem::audio::Waveform::{}, volume: {}, \
+             envelope: picosystem::audio::Envelope {{ attack_ms: {}, decay_ms: {}, sustain: {}, release_ms: {} }}, \
+             vibrato: picosystem::audio::Vibrato {{ depth: {}, rate_hz: {} }}, slide: {} }},\n",
+            instrument.waveform,
+            instrument.volume,
+            attack,
+            decay,
+            sustain,
+            release,
+            instrument.vibrato.0,
+            instrument.vibrato.1,
+            instrument.slide
+        ));
+    }
+    code.push_str("];\n");
+
+    for (i, pattern) in song.patterns.iter().enumerate() {
+        code.push_str(&format!(
+            "static PATTERN{}: [picosystem::music::Cell; {}] = [\n",
+            i,
+            pattern.len()
+        ));
+        for cell in pattern.iter() {
+            code.push_str(&format!(
+                "picosystem::music::Cell {{ note: {}, instrument: {}, effect: {}, param: {} }},\n",
+                cell.note, cell.instrument, cell.effect, cell.param
+            ));
+        }
+        code.push_str("];\n");
+    }
+
+    code.push_str(&format!(
+        "static PATTERNS: [picosystem::music::Pattern; {}] = [\n",
+        song.patterns.len()
+    ));
+    for i in 0..song.patterns.len() {
+        code.push_str(&format!(
+            "picosystem::music::Pattern {{ cells: &PATTERN{} }},\n",
+            i
+        ));
+    }
+    code.push_str("];\n");
+
+    code.push_str(&format!(
+        r"
+            static ORDER: [u8; {}] = {:?};
+            static SONG: picosystem::music::Song = picosystem::music::Song {{
+                tempo: {},
+                channels: {},
+                instruments: &INSTRUMENTS,
+                patterns: &PATTERNS,
+                order: &ORDER,
+            }};
+            &SONG
+        }}",
+        song.order.len(),
+        &song.order,
+        song.tempo,
+        song.channels
+    ));
+    code.parse().expect("Failed to parse code")
+}
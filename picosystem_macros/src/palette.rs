@@ -0,0 +1,144 @@
+// Build-time color quantization for palettized sprites and tiles.
+
+pub type Rgb = [u8; 3];
+
+pub fn rgb565(c: Rgb) -> u16 {
+    ((c[0] as u16 >> 3) << 11) | ((c[1] as u16 >> 2) << 5) | (c[2] as u16 >> 3)
+}
+
+/// Reduces `pixels` to at most `max_colors` colors with median cut. Images
+/// that already have few enough colors keep them exactly.
+pub fn quantize(pixels: &[Rgb], max_colors: usize) -> Vec<Rgb> {
+    let mut unique: Vec<Rgb> = pixels.to_vec();
+    unique.sort_unstable();
+    unique.dedup();
+    if unique.len() <= max_colors {
+        return unique;
+    }
+
+    let mut boxes: Vec<Vec<Rgb>> = vec![pixels.to_vec()];
+    while boxes.len() < max_colors {
+        // Split the box with the widest channel range.
+        let (index, channel, range) = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let (channel, range) = widest_channel(b);
+                (i, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range)
+            .unwrap();
+        if range == 0 {
+            break;
+        }
+        let mut b = boxes.swap_remove(index);
+        b.sort_unstable_by_key(|c| c[channel]);
+        let upper = b.split_off(b.len() / 2);
+        boxes.push(b);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|b| {
+            let mut sum = [0u64; 3];
+            for c in b.iter() {
+                for i in 0..3 {
+                    sum[i] += c[i] as u64;
+                }
+            }
+            let n = b.len() as u64;
+            [(sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8]
+        })
+        .collect()
+}
+
+fn widest_channel(colors: &[Rgb]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = colors.iter().map(|c| c[channel]).min().unwrap_or(0);
+            let max = colors.iter().map(|c| c[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+pub fn nearest(palette: &[Rgb], color: Rgb) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| {
+            (0..3)
+                .map(|i| (p[i] as i32 - color[i] as i32).pow(2))
+                .sum::<i32>()
+        })
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+/// Palette and per-pixel indices for an RGBA image. Index 0 is reserved for
+/// transparent pixels if there are any.
+pub struct Indexed {
+    pub palette: Vec<Rgb>,
+    pub indices: Vec<u8>,
+    pub transparent: bool,
+}
+
+pub fn index_colors<'a>(
+    pixels: impl Iterator<Item = &'a [u8]> + Clone,
+    max_colors: usize,
+) -> Indexed {
+    let transparent = pixels.clone().any(|p| p[3] != 255);
+    let opaque: Vec<Rgb> = pixels
+        .clone()
+        .filter(|p| p[3] == 255)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+    let reserved = transparent as usize;
+    let mut palette = quantize(&opaque, max_colors - reserved);
+    let indices = pixels
+        .map(|p| {
+            if p[3] != 255 {
+                0
+            } else {
+                (reserved + nearest(&palette, [p[0], p[1], p[2]])) as u8
+            }
+        })
+        .collect();
+    if transparent {
+        palette.insert(0, [0, 0, 0]);
+    }
+    Indexed {
+        palette,
+        indices,
+        transparent,
+    }
+}
+
+/// Packs indices with `bits_per_pixel` of 4 or 8, first pixel in the low bits.
+pub fn pack<T>(indices: &[u8], bits_per_pixel: u32) -> Vec<T>
+where
+    T: Copy + Default + From<u8> + std::ops::Shl<u32, Output = T> + std::ops::BitOr<Output = T>,
+{
+    let per_word = (std::mem::size_of::<T>() as u32 * 8 / bits_per_pixel) as usize;
+    indices
+        .chunks(per_word)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(T::default(), |word, (i, &index)| {
+                    word | (T::from(index) << (i as u32 * bits_per_pixel))
+                })
+        })
+        .collect()
+}
+
+pub fn bits_per_pixel(colors: usize) -> u32 {
+    match colors {
+        16 => 4,
+        256 => 8,
+        _ => panic!("Palette size must be 16 or 256, got {}", colors),
+    }
+}
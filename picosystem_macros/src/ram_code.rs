@@ -0,0 +1,38 @@
+use proc_macro::{TokenStream, TokenTree};
+
+// The section `.data` is gathered into, which the runtime copies to RAM
+// before `main` along with the initial values of statics.
+const SECTION: &str = ".data.ram_func";
+
+pub fn ram_code(args: TokenStream, item: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return r#"compile_error!("`ram_code` takes no arguments");"#
+            .parse()
+            .unwrap();
+    }
+    let is_fn = item
+        .clone()
+        .into_iter()
+        .any(|token| matches!(token, TokenTree::Ident(ident) if ident.to_string() == "fn"));
+    if !is_fn {
+        return r#"compile_error!("`ram_code` only applies to functions");"#
+            .parse()
+            .unwrap();
+    }
+    // Only on the device, as the host doesn't run code from data sections,
+    // and not with `double-buffer`, whose second framebuffer leaves no RAM
+    // for code. The feature is the one of the crate using the attribute.
+    let mut code: TokenStream = format!(
+        r#"
+        #[inline(never)]
+        #[cfg_attr(
+            all(target_arch = "arm", target_os = "none", not(feature = "double-buffer")),
+            link_section = "{}"
+        )]"#,
+        SECTION
+    )
+    .parse()
+    .unwrap();
+    code.extend(item);
+    code
+}
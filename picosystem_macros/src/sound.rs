@@ -0,0 +1,159 @@
+use proc_macro::TokenStream;
+use std::env;
+use std::path::{Path, PathBuf};
+use syn::parse::{Parse, ParseStream, Result};
+use syn::{parse_macro_input, Ident, LitStr, Token};
+
+use crate::assets::Asset;
+
+// Samples are stored at this rate whatever the rate of the WAV file.
+const TARGET_RATE: u32 = 22050;
+
+pub struct SoundArgs {
+    pub function_name: Ident,
+    path: LitStr,
+}
+
+impl Parse for SoundArgs {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let function_name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let path = input.parse()?;
+        Ok(SoundArgs {
+            function_name,
+            path,
+        })
+    }
+}
+
+struct Wav {
+    rate: u32,
+    // Mono, from -1 to 1.
+    samples: Vec<f32>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn load_wav(path: &Path) -> Wav {
+    let data = std::fs::read(path).unwrap_or_else(|_| panic!("Could not load {:?}", path));
+    assert!(
+        data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WAVE",
+        "{:?} is not a WAV file",
+        path
+    );
+
+    let mut format = None;
+    let mut samples = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let len = read_u32(&data, offset + 4) as usize;
+        let body = &data[offset + 8..(offset + 8 + len).min(data.len())];
+        match id {
+            b"fmt " => {
+                let tag = read_u16(body, 0);
+                let channels = read_u16(body, 2) as usize;
+                let rate = read_u32(body, 4);
+                let bits = read_u16(body, 14);
+                format = Some((tag, channels, rate, bits));
+            }
+            b"data" => samples = Some(body),
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        offset += 8 + len + (len & 1);
+    }
+    let (tag, channels, rate, bits) =
+        format.unwrap_or_else(|| panic!("{:?} has no format chunk", path));
+    let body = samples.unwrap_or_else(|| panic!("{:?} has no data chunk", path));
+
+    let decode = |frame: &[u8]| -> f32 {
+        match (tag, bits) {
+            (1, 8) => (frame[0] as f32 - 128.0) / 128.0,
+            (1, 16) => i16::from_le_bytes([frame[0], frame[1]]) as f32 / 32768.0,
+            (1, 24) => i32::from_le_bytes([0, frame[0], frame[1], frame[2]]) as f32 / 2147483648.0,
+            (1, 32) => i32::from_le_bytes(frame[0..4].try_into().unwrap()) as f32 / 2147483648.0,
+            (3, 32) => f32::from_le_bytes(frame[0..4].try_into().unwrap()),
+            _ => panic!(
+                "{:?}: unsupported format {} with {} bits per sample",
+                path, tag, bits
+            ),
+        }
+    };
+    let sample_size = bits as usize / 8;
+    let samples = body
+        .chunks_exact(sample_size * channels)
+        .map(|frame| {
+            let sum: f32 = frame.chunks_exact(sample_size).map(decode).sum();
+            sum / channels as f32
+        })
+        .collect();
+    Wav { rate, samples }
+}
+
+// Linear interpolation is enough for a piezo.
+fn resample(wav: &Wav, rate: u32) -> Vec<f32> {
+    if wav.rate == rate || wav.samples.is_empty() {
+        return wav.samples.clone();
+    }
+    let len = (wav.samples.len() as u64 * rate as u64 / wav.rate as u64) as usize;
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * wav.rate as f64 / rate as f64;
+            let index = position as usize;
+            let frac = (position - index as f64) as f32;
+            let a = wav.samples[index];
+            let b = *wav.samples.get(index + 1).unwrap_or(&a);
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+pub fn sound(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as SoundArgs);
+    sound_asset(args).code.parse().unwrap()
+}
+
+pub fn sound_asset(args: SoundArgs) -> Asset {
+    let SoundArgs {
+        function_name,
+        path,
+    } = args;
+    let mut fullpath = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    fullpath.pop();
+    fullpath.push(path.value());
+
+    let wav = load_wav(&fullpath);
+    let data: Vec<u8> = resample(&wav, TARGET_RATE)
+        .iter()
+        .map(|s| (s * 128.0 + 128.0).round().clamp(0.0, 255.0) as u8)
+        .collect();
+
+    let code = format!(
+        r#"
+        pub fn {}() -> &'static picosystem::audio::Sample {{
+            static DATA: [u8; {}] = {:?};
+            static SAMPLE: picosystem::audio::Sample = picosystem::audio::Sample {{
+                data: &DATA,
+                rate: {},
+            }};
+            &SAMPLE
+        }}"#,
+        function_name,
+        data.len(),
+        data,
+        TARGET_RATE,
+    );
+    Asset::new(
+        &function_name,
+        "&'static picosystem::audio::Sample",
+        code,
+        data.len(),
+    )
+}
@@ -0,0 +1,25 @@
+/* Memory layout of a game run by picosystem::launcher from slot 0. Use it as
+   the game's memory.x, adding the slot number times 0x100000 to the ORIGIN
+   of BOOT2 and FLASH for another slot. The game must fit in the slot and
+   can't use STATIC_FLASH. */
+MEMORY {
+    BOOT2 : ORIGIN = 0x10400000, LENGTH = 0x100
+    FLASH : ORIGIN = 0x10400100, LENGTH = 1024K - 0x100
+    RAM   : ORIGIN = 0x20000000, LENGTH = 256K
+}
+
+/* Leaves room for the game header after the vector table. */
+_stext = ORIGIN(FLASH) + 0xf00;
+
+SECTIONS {
+    /* Not run, the launcher's boot2 has already set up flash. */
+    .boot2 ORIGIN(BOOT2) :
+    {
+        KEEP(*(.boot2));
+    } > BOOT2
+
+    .game_header ORIGIN(FLASH) + 0x100 :
+    {
+        KEEP(*(.game_header));
+    } > FLASH
+} INSERT BEFORE .text;